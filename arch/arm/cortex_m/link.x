@@ -186,6 +186,15 @@ SECTIONS
     __bss_end = .;
   } > RAM AT > RAM
 
+  /* Kept across resets: neither copied nor zeroed at startup. */
+  .noinit (NOLOAD) :
+  {
+    . = ALIGN(4);
+    *(.noinit)
+    *(.noinit.*)
+    . = ALIGN(4);
+  } > RAM
+
   .heap (COPY) :
   {
     . = ALIGN(8);
@@ -221,7 +230,6 @@ SECTIONS
     *(.ARM.exidx.*);
     *(.ARM.extab.*);
     *(.ARM.extab);
-    *(.noinit);
   }
   ASSERT(__sys_stack_guard_start >= __heap_end, "Stack and heap overlap each other!")
 }
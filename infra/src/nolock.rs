@@ -74,6 +74,11 @@ impl<T> NoLock<T> {
 }
 
 impl<T: ?Sized> NoLock<T> {
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
     pub fn try_write(&self) -> Option<NoLockWriteGuard<'_, T>> {
         Some(NoLockWriteGuard {
             inner: self.data.get(),
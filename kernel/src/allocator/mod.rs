@@ -50,11 +50,15 @@ static_arc! {
 
 unsafe impl GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(all(virtualization, debug))]
+        crate::arch::virt::assert_heap_allowed();
         HEAP.alloc(layout)
             .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(all(virtualization, debug))]
+        crate::arch::virt::assert_heap_allowed();
         HEAP.dealloc(ptr, layout);
     }
 }
@@ -0,0 +1,127 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Identification of each core. MIDR_EL1 and the ID registers describe
+//! only the core reading them, so every core records its own at boot and
+//! readers such as /proc/cpuinfo look them up afterwards.
+
+use super::{registers::midr_el1::MIDR_EL1, sysreg::read_sysreg};
+use spin::Once;
+use tock_registers::interfaces::Readable;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuId {
+    midr: u64,
+    pfr0: u64,
+    mmfr1: u64,
+    isar1: u64,
+}
+
+fn field(reg: u64, shift: u32) -> u64 {
+    (reg >> shift) & 0xf
+}
+
+impl CpuId {
+    fn read() -> Self {
+        Self {
+            midr: MIDR_EL1.get(),
+            pfr0: read_sysreg!("id_aa64pfr0_el1"),
+            mmfr1: read_sysreg!("id_aa64mmfr1_el1"),
+            isar1: read_sysreg!("id_aa64isar1_el1"),
+        }
+    }
+
+    pub fn midr(&self) -> u64 {
+        self.midr
+    }
+
+    pub fn implementer(&self) -> u8 {
+        (self.midr >> 24) as u8
+    }
+
+    pub fn implementer_name(&self) -> &'static str {
+        match self.implementer() {
+            0x00 => "software",
+            0x41 => "Arm",
+            0x42 => "Broadcom",
+            0x43 => "Cavium",
+            0x46 => "Fujitsu",
+            0x48 => "HiSilicon",
+            0x4e => "NVIDIA",
+            0x51 => "Qualcomm",
+            0x61 => "Apple",
+            0xc0 => "Ampere",
+            _ => "unknown",
+        }
+    }
+
+    pub fn variant(&self) -> u8 {
+        field(self.midr, 20) as u8
+    }
+
+    pub fn part(&self) -> u16 {
+        ((self.midr >> 4) & 0xfff) as u16
+    }
+
+    pub fn revision(&self) -> u8 {
+        field(self.midr, 0) as u8
+    }
+
+    /// EL2 implemented, in AArch64 at least.
+    pub fn has_el2(&self) -> bool {
+        field(self.pfr0, 8) != 0
+    }
+
+    /// Virtualization Host Extensions.
+    pub fn has_vhe(&self) -> bool {
+        field(self.mmfr1, 8) != 0
+    }
+
+    /// GIC CPU interface reachable through system registers, GICv3 on.
+    pub fn has_gic_sysregs(&self) -> bool {
+        field(self.pfr0, 24) != 0
+    }
+
+    /// Address authentication with the architected or an IMPDEF algorithm.
+    pub fn has_pauth(&self) -> bool {
+        field(self.isar1, 4) != 0 || field(self.isar1, 8) != 0
+    }
+
+    /// Names of the features above the core has, in a fixed order.
+    pub fn flags(&self) -> impl Iterator<Item = &'static str> {
+        [
+            ("el2", self.has_el2()),
+            ("vhe", self.has_vhe()),
+            ("gicv3", self.has_gic_sysregs()),
+            ("pauth", self.has_pauth()),
+        ]
+        .into_iter()
+        .filter_map(|(name, has)| has.then_some(name))
+    }
+}
+
+static CPUS: [Once<CpuId>; NUM_CORES] = [const { Once::new() }; NUM_CORES];
+
+/// Record the calling core's identification. Called by each core once at
+/// boot; later calls keep the first record.
+pub fn record() {
+    CPUS[super::current_cpu_id()].call_once(CpuId::read);
+}
+
+/// Identification of `cpu`, if it came up.
+pub fn get(cpu: usize) -> Option<CpuId> {
+    CPUS.get(cpu)?.get().copied()
+}
@@ -184,6 +184,8 @@ extern "C" fn trap_exception(context: &mut Context) -> usize {
 
 extern "C" fn trap_irq(context: &mut Context) -> usize {
     let sp = context as *const _ as usize;
+    #[cfg(soft_watchdog)]
+    crate::watchdog::irq_taken(context.elr, context.lr, context.fp);
     let irq = irq::get_interrupt();
     irq::trigger_irq(irq);
     irq::end_interrupt(irq);
@@ -18,6 +18,7 @@ use crate::{arch::current_cpu_id, sync::SpinLock};
 use alloc::boxed::Box;
 pub use arm_gic::Trigger as IrqTrigger;
 use arm_gic::{gicv3::*, IntId};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Once;
 use tock_registers::interfaces::Readable;
 
@@ -37,7 +38,17 @@ const SPI_START: u32 = 32;
 const SPECIAL_START: u32 = 1020;
 const SPECIAL_END: u32 = 1024;
 
+// GICD_IROUTER<n>: SPI n is delivered to the core whose affinity it holds.
+const GICD_IROUTER: usize = 0x6100;
+const IROUTER_AFF0_MASK: u64 = 0xff;
+// Interrupt_Routing_Mode: any participating core instead of a fixed one.
+const IROUTER_ANY: u64 = 1 << 31;
+
 static GIC: Once<SpinLock<GicV3>> = Once::new();
+static GICD_BASE: AtomicUsize = AtomicUsize::new(0);
+static NUM_CORES: AtomicUsize = AtomicUsize::new(0);
+// Cores host SPIs are kept off, see `set_isolated_cores`.
+static ISOLATED_CORES: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
 #[repr(transparent)]
@@ -52,6 +63,10 @@ impl IrqNumber {
         };
         Self(id)
     }
+
+    pub fn is_spi(self) -> bool {
+        (SPI_START..SPECIAL_START).contains(&u32::from(self))
+    }
 }
 // IrqNumber to u32
 impl From<IrqNumber> for u32 {
@@ -69,6 +84,8 @@ impl From<IrqNumber> for usize {
 
 // Initialize the GIC for the system
 pub unsafe fn init(gicd: u64, gicr: u64, num_cores: usize, is_v4: bool) {
+    GICD_BASE.store(gicd as usize, Ordering::Relaxed);
+    NUM_CORES.store(num_cores, Ordering::Relaxed);
     GIC.call_once(|| {
         // Safety: gicd and gicr must need to be valid pointers.
         let mut gic = unsafe {
@@ -153,7 +170,10 @@ impl IrqManager {
 
 // Register interrupt handler
 pub fn register_handler(irq: IrqNumber, handler: Box<dyn IrqHandler>) -> Result<(), &'static str> {
-    IRQ_MANAGER.lock().register_handler(irq, handler)
+    // `trigger_irq` takes the lock from IRQ context.
+    IRQ_MANAGER.irqsave_lock().register_handler(irq, handler)?;
+    steer_off_isolated(irq);
+    Ok(())
 }
 
 // Trigger interrupt
@@ -188,6 +208,77 @@ pub fn set_irq_priority(irq: IrqNumber, cpu_id: usize, priority: u8) {
         .set_interrupt_priority(irq.0, Some(cpu_id), priority);
 }
 
+fn irouter(irq: IrqNumber) -> *mut u64 {
+    (GICD_BASE.load(Ordering::Relaxed) + GICD_IROUTER + 8 * usize::from(irq)) as *mut u64
+}
+
+// Route an SPI to a single core. SGIs and PPIs are banked per core and have
+// no affinity to set.
+pub fn set_irq_affinity(irq: IrqNumber, cpu_id: usize) -> Result<(), &'static str> {
+    if !irq.is_spi() {
+        return Err("only SPIs can be routed");
+    }
+    if cpu_id >= NUM_CORES.load(Ordering::Relaxed) {
+        return Err("no such core");
+    }
+    // The GIC lock keeps this from racing with the distributor setup.
+    let _gic = get_gic().irqsave_lock();
+    // Safety: IROUTER of an SPI lies inside the distributor frame.
+    unsafe { irouter(irq).write_volatile(cpu_id as u64) };
+    Ok(())
+}
+
+// Core an SPI is routed to, or None if it isn't an SPI or may go to any core.
+pub fn irq_affinity(irq: IrqNumber) -> Option<usize> {
+    if !irq.is_spi() || GICD_BASE.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    // Safety: see set_irq_affinity.
+    let route = unsafe { irouter(irq).read_volatile() };
+    if route & IROUTER_ANY != 0 {
+        return None;
+    }
+    Some((route & IROUTER_AFF0_MASK) as usize)
+}
+
+pub fn isolated_cores() -> usize {
+    ISOLATED_CORES.load(Ordering::Relaxed)
+}
+
+// Keep host SPIs off the cores in `mask`, one bit per core. Registered SPIs
+// routed to one of them move to the lowest core outside it, and so do ones
+// registered later. At least one core has to stay with the host.
+pub fn set_isolated_cores(mask: usize) -> Result<(), &'static str> {
+    let all = (1usize << NUM_CORES.load(Ordering::Relaxed)) - 1;
+    if mask & all == all {
+        return Err("no core left for host interrupts");
+    }
+    ISOLATED_CORES.store(mask, Ordering::Relaxed);
+    let irqs: alloc::vec::Vec<IrqNumber> = IRQ_MANAGER
+        .irqsave_lock()
+        .contexts
+        .iter()
+        .flatten()
+        .map(|context| context.irq)
+        .collect();
+    for irq in irqs {
+        steer_off_isolated(irq);
+    }
+    Ok(())
+}
+
+fn steer_off_isolated(irq: IrqNumber) {
+    let mask = isolated_cores();
+    let Some(cpu_id) = irq_affinity(irq) else {
+        return;
+    };
+    if mask & (1 << cpu_id) == 0 {
+        return;
+    }
+    let target = (!mask).trailing_zeros() as usize;
+    let _ = set_irq_affinity(irq, target);
+}
+
 // Set priority mask for current CPU
 pub fn set_priority_mask(priority: u8) {
     GicV3::set_priority_mask(priority);
@@ -16,6 +16,7 @@ use crate::arch::aarch64::{
     asm,
     asm::DsbOptions,
     registers::{mair_el1::*, sctlr_el1::*, tcr_el1::*, ttbr0_el1::TTBR0_EL1},
+    sysreg::{read_sysreg, write_sysreg},
 };
 use core::sync::atomic::{AtomicBool, Ordering};
 use tock_registers::{interfaces::*, register_bitfields, registers::InMemoryRegister};
@@ -149,6 +150,25 @@ impl PageEntry {
         InMemoryRegister::<u64, PAGE_DESCRIPTOR::Register>::new(self.0)
             .is_set(PAGE_DESCRIPTOR::VALID)
     }
+
+    // The block `set` makes, as the EL2 translation regime takes it: with a
+    // single privilege level AP[1] is RES1, and without ASIDs nG is RES0.
+    // UXN is XN there.
+    #[cfg(virtualization)]
+    const fn el2_block(output_addr: u64, attributes: MemAttributes) -> Self {
+        let attrs = match attributes {
+            MemAttributes::Device => PAGE_DESCRIPTOR::UXN::True.value,
+            MemAttributes::Normal => PAGE_DESCRIPTOR::SH::InnerShareable.value,
+        };
+        Self(
+            PAGE_DESCRIPTOR::VALID::Valid.value
+                | PAGE_DESCRIPTOR::AF::True.value
+                | PAGE_DESCRIPTOR::AP::EL0_RW.value
+                | PAGE_DESCRIPTOR::ATTRINDX.val(attributes as u64).value
+                | attrs
+                | output_addr,
+        )
+    }
 }
 
 // This page table must be available before `init_runtime()` clears `.bss`,
@@ -157,6 +177,11 @@ impl PageEntry {
 #[link_section = ".data"]
 static mut TABLE_MANAGER: PageTableManager = PageTableManager::new();
 
+// The same identity map for EL2, built at compile time since EL2 turns its
+// MMU on before anything else runs.
+#[cfg(virtualization)]
+static EL2_TABLE: PageTableManager = PageTableManager::el2();
+
 #[repr(C, align(4096))]
 pub struct PageTableManager([PageEntry; 512]);
 
@@ -176,6 +201,31 @@ impl PageTableManager {
             let _ = table.0[index].set(base, MemAttributes::Normal);
         }
     }
+
+    // What `init` builds, in EL2 blocks. A base given twice keeps its first
+    // entry, as `set` does.
+    #[cfg(virtualization)]
+    const fn el2() -> Self {
+        let mut table = Self::new();
+        let bases = [
+            (crate::boards::MMU_L1_DEVICE_BASES, MemAttributes::Device),
+            (crate::boards::MMU_L1_NORMAL_BASES, MemAttributes::Normal),
+        ];
+        let mut k = 0;
+        while k < bases.len() {
+            let (list, attributes) = bases[k];
+            let mut i = 0;
+            while i < list.len() {
+                let index = (list[i] >> 30) as usize;
+                if table.0[index].0 == 0 {
+                    table.0[index] = PageEntry::el2_block(list[i], attributes);
+                }
+                i += 1;
+            }
+            k += 1;
+        }
+        table
+    }
 }
 
 // Indicate whether the page table initialization is done.
@@ -200,15 +250,7 @@ pub fn enable_mmu() {
         }
     }
     // Set physical table base addr.
-    unsafe {
-        core::arch::asm!(
-            "adrp {temp}, {tbl}",
-            "msr ttbr0_el1, {temp}",
-            temp = out(reg) _,
-            tbl = sym TABLE_MANAGER,
-            options(nostack, nomem)
-        )
-    };
+    write_sysreg!("ttbr0_el1", core::ptr::addr_of!(TABLE_MANAGER) as u64);
     // Set memory type.
     MAIR_EL1.write(
         MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
@@ -240,3 +282,42 @@ pub fn enable_mmu() {
     );
     asm::isb_sy();
 }
+
+/// Turn the EL2 MMU on over the host's identity map, with the memory types
+/// EL1 uses, so that locks and pages EL2 shares with the host and guests
+/// are Normal write-back memory on both sides rather than Device memory
+/// to EL2. Runs at EL2, on each core and again after it lost its state.
+#[cfg(virtualization)]
+pub fn enable_el2_mmu() {
+    let mair = InMemoryRegister::<u64, MAIR_EL1::Register>::new(0);
+    mair.write(
+        MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
+            + MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc
+            + MAIR_EL1::Attr0_Device::NonGathering_NonReordering_EarlyWriteAck,
+    );
+    write_sysreg!("mair_el2", mair.get());
+    // Below bit 16 TCR_EL2 is laid out as TCR_EL1; PS = 0 is the 32-bit
+    // output size EL1 uses, and bits 31 and 23 are RES1.
+    let tcr = InMemoryRegister::<u64, TCR_EL1::Register>::new(0);
+    tcr.write(
+        TCR_EL1::TG0::KiB_4
+            + TCR_EL1::SH0::InnerShareable
+            + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::T0SZ.val(25),
+    );
+    write_sysreg!("tcr_el2", tcr.get() | (1 << 31) | (1 << 23));
+    write_sysreg!("ttbr0_el2", core::ptr::addr_of!(EL2_TABLE) as u64);
+    unsafe {
+        core::arch::asm!("tlbi alle2", options(nostack));
+    }
+    asm::dsb(DsbOptions::Sys);
+    asm::isb_sy();
+    // M, C and I on; A and WXN, which reset to unknown values, off.
+    let sctlr: u64 = read_sysreg!("sctlr_el2");
+    write_sysreg!(
+        "sctlr_el2",
+        (sctlr | (1 << 0) | (1 << 2) | (1 << 12)) & !((1 << 1) | (1 << 19))
+    );
+    asm::isb_sy();
+}
@@ -13,11 +13,13 @@
 // limitations under the License.
 
 pub(crate) mod asm;
+pub(crate) mod cpuinfo;
 mod exception;
 pub mod irq;
 pub(crate) mod mmu;
 pub(crate) mod psci;
 pub(crate) mod registers;
+pub(crate) mod sysreg;
 pub(crate) mod vector;
 pub(crate) mod virt;
 
@@ -31,6 +33,7 @@ use core::{
     },
 };
 use scheduler::ContextSwitchHookHolder;
+use sysreg::{read_sysreg, write_sysreg};
 use tock_registers::interfaces::Readable;
 
 pub(crate) const NR_SWITCH: usize = !0;
@@ -64,9 +67,12 @@ macro_rules! enter_el1 {
         orr x0, x0, #(0x3 << 20)
         msr cpacr_el1, x0
         isb
-        // Enable CNTP to EL1 for systick.
+        // Enable CNTP to EL1 for systick. Clear the FEAT_ECV controls
+        // (ECV, EL1TVT, EL1TVCT, EL1NVPCT, EL1NVVCT), which reset to
+        // unknown values, so guest counter reads never trap to EL2.
         mrs     x0, cnthctl_el2
         orr     x0, x0, #3
+        bic     x0, x0, #(0x1f << 12)
         msr     cnthctl_el2, x0
         msr     cntvoff_el2, xzr
         // Enable AArch64 in EL1.
@@ -88,6 +94,13 @@ macro_rules! enter_el1 {
         add x4, x4, #0x1000
         mov sp, x4
         bl {enable_mmu}
+        // Back to this core's EL2 stack for exceptions taken later.
+        ldr x1, ={stack_end}
+        mrs x9, mpidr_el1
+        and x9, x9, #0xff
+        lsl x9, x9, #14
+        sub x1, x1, x9
+        mov sp, x1
         // Set EL1 entry and enter.
         ldr x0, ={stack_start}
         ldr x1, ={stack_end} 
@@ -479,12 +492,12 @@ pub(crate) extern "C" fn start_schedule(cont: extern "C" fn() -> !) {
 
 #[inline]
 pub extern "C" fn disable_local_irq() {
-    unsafe { core::arch::asm!("msr daifset, #3", options(nostack, nomem)) }
+    unsafe { core::arch::asm!("msr daifset, #3", options(nostack)) }
 }
 
 #[inline]
 pub extern "C" fn enable_local_irq() {
-    unsafe { core::arch::asm!("msr daifclr, #3", options(nostack, nomem)) }
+    unsafe { core::arch::asm!("msr daifclr, #3", options(nostack)) }
 }
 
 #[inline]
@@ -523,19 +536,12 @@ pub extern "C" fn disable_local_irq_save() -> usize {
 #[inline]
 pub extern "C" fn enable_local_irq_restore(old: usize) {
     atomic::compiler_fence(Ordering::SeqCst);
-    unsafe { core::arch::asm!("msr daif, {}", in(reg) old, options(nostack)) }
+    write_sysreg!("daif", old as u64);
 }
 
 #[inline]
 pub extern "C" fn local_irq_enabled() -> bool {
-    let x: usize;
-    unsafe {
-        core::arch::asm!(
-            "mrs {}, daif",
-            out(reg) x, options(nostack)
-        );
-    };
-    (x & (1 << 7)) == 0
+    (read_sysreg!("daif") & (1 << 7)) == 0
 }
 
 #[inline]
@@ -548,6 +554,32 @@ pub fn secondary_cpu_setup(psci_base: u32) {
     }
 }
 
+/// Suspend the calling core in PSCI `power_state`. With virtualization the
+/// VMs are frozen around it and a core back from a powerdown state goes on
+/// here, see `virt::host_pm::suspend_core`.
+pub fn cpu_suspend(psci_base: u32, power_state: usize) {
+    #[cfg(virtualization)]
+    if let Err(e) = virt::host_pm::suspend_core(psci_base, power_state) {
+        log::warn!("[pm] core not suspended: {:?}", e);
+    }
+    #[cfg(not(virtualization))]
+    psci::cpu_suspend(psci_base, power_state, 0, 0);
+}
+
+/// Stop all running guests so they can't interfere with the panic dump.
+pub fn stop_guests_on_panic() {
+    #[cfg(virtualization)]
+    virt::panic::stop_all_guests();
+}
+
+/// End a selftest run under QEMU with its result as the exit status. Does
+/// nothing elsewhere.
+#[cfg_attr(not(virtualization), allow(unused_variables))]
+pub fn test_exit(passed: bool) {
+    #[cfg(virtualization)]
+    virt::qemu::test_exit(passed);
+}
+
 #[naked]
 pub(crate) extern "C" fn switch_stack(
     to_sp: usize,
@@ -573,12 +605,6 @@ pub(crate) extern "C" fn send_ipi(target_core_id: usize) {
     let int_id = 1u64;
 
     let sgi_val: u64 = (aff3 << 48) | (aff2 << 32) | (int_id << 24) | (aff1 << 16) | (target_list);
-    unsafe {
-        core::arch::asm!(
-            "msr ICC_SGI1R_EL1, {x}",
-            "isb",
-            x = in(reg) sgi_val,
-            options(nomem, nostack)
-        );
-    }
+    write_sysreg!("icc_sgi1r_el1", sgi_val);
+    asm::isb();
 }
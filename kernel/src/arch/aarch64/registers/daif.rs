@@ -77,7 +77,7 @@ impl Writeable for Daif {
             core::arch::asm!(
                 "msr daif, {}",
                 in(reg) value,
-                options(nomem, nostack)
+                options(nostack)
             );
         }
     }
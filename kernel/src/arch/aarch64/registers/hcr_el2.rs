@@ -87,26 +87,27 @@ register_bitfields! {u64,
             Trap = 1
         ],
 
-        /// ST, bit [17] - Store Team Register
-        ST OFFSET(17) NUMBITS(1) [
+        /// TID2, bit [17] - Trap ID Group 2: CTR_EL0, CCSIDR_EL1, CLIDR_EL1
+        /// and CSSELR_EL1
+        TID2 OFFSET(17) NUMBITS(1) [
             NoTrap = 0,
             Trap = 1
         ],
 
-        /// VSE, bit [18] - Virtual System Error
-        VSE OFFSET(18) NUMBITS(1) [
+        /// VSE, bit [8] - Virtual System Error
+        VSE OFFSET(8) NUMBITS(1) [
             Disable = 0,
             Enable = 1
         ],
 
-        /// VI, bit [19] - Virtual IRQ
-        VI OFFSET(19) NUMBITS(1) [
+        /// VI, bit [7] - Virtual IRQ
+        VI OFFSET(7) NUMBITS(1) [
             Disable = 0,
             Enable = 1
         ],
 
-        /// VF, bit [20] - Virtual FIQ
-        VF OFFSET(20) NUMBITS(1) [
+        /// VF, bit [6] - Virtual FIQ
+        VF OFFSET(6) NUMBITS(1) [
             Disable = 0,
             Enable = 1
         ],
@@ -143,14 +144,22 @@ register_bitfields! {u64,
             Disable = 1
         ],
 
-        /// TDZ, bit [25] - TRAP DC ZVA
-        TDZ OFFSET(25) NUMBITS(1) [
+        /// TVM, bit [26] - Trap Virtual Memory controls: writes to SCTLR_EL1,
+        /// TTBRn_EL1, TCR_EL1, ESR_EL1, FAR_EL1, AFSRn_EL1, MAIR_EL1,
+        /// AMAIR_EL1 and CONTEXTIDR_EL1
+        TVM OFFSET(26) NUMBITS(1) [
             NoTrap = 0,
             Trap = 1
         ],
 
-        /// TSC, bit [31] - TRAP SC
-        TSC OFFSET(31) NUMBITS(1) [
+        /// TDZ, bit [28] - Trap DC ZVA; DCZID_EL0 then reads as prohibited
+        TDZ OFFSET(28) NUMBITS(1) [
+            NoTrap = 0,
+            Trap = 1
+        ],
+
+        /// TSC, bit [19] - Trap SMC
+        TSC OFFSET(19) NUMBITS(1) [
             NoTrap = 0,
             Trap = 1
         ],
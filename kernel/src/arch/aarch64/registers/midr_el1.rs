@@ -0,0 +1,58 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+// See: https://developer.arm.com/documentation/ddi0601/2024-12/AArch64-Registers/MIDR-EL1--Main-ID-Register
+register_bitfields! [u64,
+    /// Main ID Register
+    MIDR_EL1 [
+        /// Implementer code, assigned by Arm.
+        Implementer OFFSET(24) NUMBITS(8) [],
+
+        /// Major revision, the rN of an rNpM product revision.
+        Variant OFFSET(20) NUMBITS(4) [],
+
+        /// Architecture, 0b1111 for features given by the ID registers.
+        Architecture OFFSET(16) NUMBITS(4) [],
+
+        /// Part number, defined by the implementer.
+        PartNum OFFSET(4) NUMBITS(12) [],
+
+        /// Minor revision, the pM of an rNpM product revision.
+        Revision OFFSET(0) NUMBITS(4) []
+    ]
+];
+
+pub struct MidrEl1;
+
+impl Readable for MidrEl1 {
+    type T = u64;
+    type R = MIDR_EL1::Register;
+
+    #[inline]
+    fn get(&self) -> Self::T {
+        let midr;
+        unsafe {
+            core::arch::asm!(
+                "mrs {}, midr_el1",
+                out(reg) midr,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        midr
+    }
+}
+
+pub const MIDR_EL1: MidrEl1 = MidrEl1 {};
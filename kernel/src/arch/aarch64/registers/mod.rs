@@ -21,6 +21,7 @@ pub mod daif;
 pub mod esr_el1;
 pub mod hcr_el2;
 pub mod mair_el1;
+pub mod midr_el1;
 pub mod mpidr_el1;
 pub mod sctlr_el1;
 pub mod spsel;
@@ -28,3 +29,4 @@ pub mod tcr_el1;
 pub mod ttbr0_el1;
 pub mod ttbr1_el1;
 pub mod vbar_el1;
+pub mod vtcr_el2;
@@ -0,0 +1,149 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+// See: https://developer.arm.com/documentation/ddi0601/2024-12/AArch64-Registers/VTCR-EL2--Virtualization-Translation-Control-Register
+register_bitfields! {u64,
+    pub VTCR_EL2 [
+        /// NSA, bit [30] - Non-secure stage 2 output address space, when
+        /// NSW is 1
+        NSA OFFSET(30) NUMBITS(1) [
+            Secure = 0,
+            NonSecure = 1
+        ],
+
+        /// NSW, bit [29] - Non-secure stage 2 translation table walk
+        /// address space
+        NSW OFFSET(29) NUMBITS(1) [
+            Secure = 0,
+            NonSecure = 1
+        ],
+
+        /// HD, bit [22] - Hardware management of the dirty state, when
+        /// FEAT_HAFDBS is implemented
+        HD OFFSET(22) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ],
+
+        /// HA, bit [21] - Hardware Access flag update, when FEAT_HAFDBS is
+        /// implemented
+        HA OFFSET(21) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ],
+
+        /// VS, bit [19] - VMID size, when FEAT_VMID16 is implemented
+        VS OFFSET(19) NUMBITS(1) [
+            Bits8 = 0,
+            Bits16 = 1
+        ],
+
+        /// PS, bits [18:16] - Physical address size of the stage 2 output
+        PS OFFSET(16) NUMBITS(3) [
+            Bits_32 = 0b000,
+            Bits_36 = 0b001,
+            Bits_40 = 0b010,
+            Bits_42 = 0b011,
+            Bits_44 = 0b100,
+            Bits_48 = 0b101,
+            Bits_52 = 0b110
+        ],
+
+        /// TG0, bits [15:14] - Granule size of the stage 2 translation
+        TG0 OFFSET(14) NUMBITS(2) [
+            KiB_4 = 0b00,
+            KiB_64 = 0b01,
+            KiB_16 = 0b10
+        ],
+
+        /// SH0, bits [13:12] - Shareability of the stage 2 table walks
+        SH0 OFFSET(12) NUMBITS(2) [
+            NonShareable = 0b00,
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// ORGN0, bits [11:10] - Outer cacheability of the stage 2 table
+        /// walks
+        ORGN0 OFFSET(10) NUMBITS(2) [
+            NonCacheable = 0b00,
+            WriteBack_ReadAlloc_WriteAlloc_Cacheable = 0b01,
+            WriteThrough_ReadAlloc_NoWriteAlloc_Cacheable = 0b10,
+            WriteBack_ReadAlloc_NoWriteAlloc_Cacheable = 0b11
+        ],
+
+        /// IRGN0, bits [9:8] - Inner cacheability of the stage 2 table
+        /// walks
+        IRGN0 OFFSET(8) NUMBITS(2) [
+            NonCacheable = 0b00,
+            WriteBack_ReadAlloc_WriteAlloc_Cacheable = 0b01,
+            WriteThrough_ReadAlloc_NoWriteAlloc_Cacheable = 0b10,
+            WriteBack_ReadAlloc_NoWriteAlloc_Cacheable = 0b11
+        ],
+
+        /// SL0, bits [7:6] - Starting level of the stage 2 table walk. With
+        /// the 4KB granule, 0 starts at level 2, 1 at level 1 and 2 at
+        /// level 0.
+        SL0 OFFSET(6) NUMBITS(2) [],
+
+        /// T0SZ, bits [5:0] - The input (IPA) region is 2^(64-T0SZ) bytes
+        T0SZ OFFSET(0) NUMBITS(6) []
+    ]
+}
+
+/// Bit 31 of VTCR_EL2 is RES1.
+pub const VTCR_EL2_RES1: u64 = 1 << 31;
+
+pub struct VtcrEl2;
+
+impl Readable for VtcrEl2 {
+    type T = u64;
+    type R = VTCR_EL2::Register;
+
+    #[inline]
+    fn get(&self) -> Self::T {
+        let value;
+        unsafe {
+            core::arch::asm!(
+                "mrs {}, vtcr_el2",
+                out(reg) value,
+                options(nomem, nostack)
+            );
+        }
+        value
+    }
+}
+
+impl Writeable for VtcrEl2 {
+    type T = u64;
+    type R = VTCR_EL2::Register;
+
+    #[inline]
+    fn set(&self, value: Self::T) {
+        unsafe {
+            core::arch::asm!(
+                "msr vtcr_el2, {}",
+                in(reg) value,
+                options(nomem, nostack)
+            );
+        }
+    }
+}
+
+pub const VTCR_EL2: VtcrEl2 = VtcrEl2 {};
@@ -0,0 +1,65 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! System register access. Plain `mrs`/`msr` of a register named at
+//! compile time goes through `read_sysreg!` and `write_sysreg!`, so there
+//! is one place to audit them and one set of asm options:
+//!
+//! - a read touches no memory (`nomem`), but isn't `pure`: registers such
+//!   as ESR_EL2 or CNTPCT_EL0 change under the compiler's feet, so every
+//!   read is issued where it is written;
+//! - a write keeps the memory clobber, since it may change how memory is
+//!   accessed (translation, exception masks, traps), and loads and stores
+//!   must not move across it;
+//! - neither uses the stack nor changes the flags.
+//!
+//! Barriers a write needs stay with the caller. Accessors patched at boot
+//! (`virt::hal`'s EL12 aliases) and register moves inside naked context
+//! switch code can't be expanded from here and keep their own asm.
+
+/// Value of system register `$reg`, e.g. `read_sysreg!("esr_el2")`.
+macro_rules! read_sysreg {
+    ($reg:literal) => {{
+        let v: u64;
+        // SAFETY: an `mrs` only reads the register; one the current EL
+        // can't access traps rather than touching memory.
+        unsafe {
+            core::arch::asm!(
+                concat!("mrs {}, ", $reg),
+                out(reg) v,
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+        v
+    }};
+}
+pub(crate) use read_sysreg;
+
+/// Write `$v` to system register `$reg`, e.g.
+/// `write_sysreg!("vbar_el2", base)`.
+macro_rules! write_sysreg {
+    ($reg:literal, $v:expr) => {{
+        let v: u64 = $v;
+        // SAFETY: callers own the register's effect on the system; the
+        // memory clobber keeps accesses on the side they were written.
+        unsafe {
+            core::arch::asm!(
+                concat!("msr ", $reg, ", {}"),
+                in(reg) v,
+                options(nostack, preserves_flags)
+            )
+        }
+    }};
+}
+pub(crate) use write_sysreg;
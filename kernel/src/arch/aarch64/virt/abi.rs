@@ -0,0 +1,185 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Everything a guest sees of the hypervisor by number: hypercall
+//! immediates and their return values, the flags and layouts of shared
+//! structures, and where the canned board layouts put devices. Guest-side
+//! support libraries are developed out of tree against these values, so
+//! they are defined here and nowhere else; the modules implementing them
+//! re-export what they use.
+//!
+//! The ABI carries a version the guest reads with `GUEST_HVC_ABI_VERSION`.
+//! Additions, e.g. a new hypercall or flag, bump the minor version; any
+//! change to a value below or to what a call does bumps the major version
+//! and resets the minor. A guest built against major M, minor m runs on
+//! any hypervisor with major M and a minor of at least m, see
+//! `compatible`.
+
+pub const VERSION_MAJOR: u16 = 1;
+pub const VERSION_MINOR: u16 = 3;
+/// What `GUEST_HVC_ABI_VERSION` returns: the major version in bits
+/// [31:16], the minor one in bits [15:0].
+pub const VERSION: u64 = (VERSION_MAJOR as u64) << 16 | VERSION_MINOR as u64;
+
+/// Whether a guest library built against `major`.`minor` works with this
+/// hypervisor.
+pub const fn compatible(major: u16, minor: u16) -> bool {
+    major == VERSION_MAJOR && minor <= VERSION_MINOR
+}
+
+// Guest HVC immediates.
+/// Write the character in x0 to the host console.
+pub const GUEST_HVC_PUTC: u16 = 0;
+pub const GUEST_HVC_SHUTDOWN: u16 = 1;
+/// Returns the guest's own cycle count in x0, which excludes time the vCPU
+/// was switched out when the vCPU uses `VirtualCounter::GuestTime`.
+pub const GUEST_HVC_GUEST_CYCLES: u16 = 2;
+/// Share the page at IPA x0 with peer x1 (a VM id or `PEER_HOST`) under
+/// the `GRANT_*` flags in x2. Returns the grant reference in x0.
+pub const GUEST_HVC_GRANT: u16 = 3;
+/// Revoke the grant referenced by x0. Returns 0 in x0.
+pub const GUEST_HVC_REVOKE: u16 = 4;
+/// Take the oldest unread device hotplug event: `HOTPLUG_ADDED` or
+/// `HOTPLUG_REMOVED` in x0, or 0 if there is none, then base, size, SPI
+/// and type id in x1-x4.
+pub const GUEST_HVC_HOTPLUG_EVENT: u16 = 5;
+/// Report a virtual timer sample for `timer_cal`: the deadline the guest
+/// armed in x0 and the virtual count it read on taking the PPI in x1.
+/// Returns the calibrated offset in x0.
+pub const GUEST_HVC_TIMER_SAMPLE: u16 = 6;
+/// Ring doorbell x0 of the VM, waking the host set it is bound to. Returns
+/// 0 in x0, or `NOT_SUPPORTED` if there is no such doorbell.
+pub const GUEST_HVC_DOORBELL: u16 = 7;
+/// Emit trace event x0 with argument x1, taken at virtual count x2 or at
+/// the call if x2 is 0. Returns 0 in x0, or `NOT_SUPPORTED` while tracing
+/// is off.
+pub const GUEST_HVC_TRACE: u16 = 8;
+/// Returns the bitmap of `SERVICE_*` bits the VM may call in x0.
+pub const GUEST_HVC_SERVICES: u16 = 9;
+/// Queue the copy listed on the page of grant x0 between the guest and
+/// target x1 (a VM id or `TARGET_HOST`) under the `COPY_*` flags in x2,
+/// raising SPI x3 once done. Returns 0 in x0.
+pub const GUEST_HVC_COPY: u16 = 10;
+/// Shut the VM down reporting the status in w0, `STATUS_SUCCESS` or a
+/// failure code of the guest's own.
+pub const GUEST_HVC_EXIT: u16 = 11;
+/// Take the next byte of host console input. Returns it in x0, or
+/// `NO_INPUT` if there is none or the VM isn't attached.
+pub const GUEST_HVC_GETC: u16 = 12;
+/// Returns `VERSION` in x0. Always available, like `GUEST_HVC_SERVICES`.
+pub const GUEST_HVC_ABI_VERSION: u16 = 13;
+/// Raise INTID x0 on the calling vCPU as a virtual FIQ, e.g. to test the
+/// guest's FIQ path. Returns 0 in x0, or `NOT_SUPPORTED` if x0 isn't an
+/// SGI, PPI or SPI.
+pub const GUEST_HVC_FIQ: u16 = 14;
+/// Map grant x1 of VM x0, made for the calling VM, at IPA x2 of the
+/// caller. Returns 0 in x0.
+pub const GUEST_HVC_GRANT_MAP: u16 = 15;
+/// Unmap grant x1 of VM x0 from the caller, so its owner can revoke it.
+/// Returns 0 in x0.
+pub const GUEST_HVC_GRANT_UNMAP: u16 = 16;
+
+/// SMCCC NOT_SUPPORTED, for calls the VM may not make or that don't exist.
+pub const NOT_SUPPORTED: u64 = u64::MAX;
+pub const NO_INPUT: u64 = u64::MAX;
+pub const STATUS_SUCCESS: u32 = 0;
+
+// Negative error codes the grant and copy calls return in x0.
+pub const ERR_INVALID: i64 = -1;
+pub const ERR_NOT_OWNED: i64 = -2;
+pub const ERR_NO_SLOT: i64 = -3;
+pub const ERR_NOT_FOUND: i64 = -4;
+pub const ERR_BUSY: i64 = -5;
+pub const ERR_DENIED: i64 = -6;
+
+// Bits of the `GUEST_HVC_SERVICES` bitmap.
+pub const SERVICE_CONSOLE: u64 = 1 << 0;
+pub const SERVICE_TIME: u64 = 1 << 1;
+pub const SERVICE_SHMEM: u64 = 1 << 2;
+pub const SERVICE_FS: u64 = 1 << 3;
+pub const SERVICE_TEST_AGENT: u64 = 1 << 4;
+pub const SERVICE_HOTPLUG: u64 = 1 << 5;
+pub const SERVICE_COPY: u64 = 1 << 6;
+
+/// x1 of a grant hypercall naming the host as the peer.
+pub const PEER_HOST: u64 = u64::MAX;
+/// Grant flags in x2.
+pub const GRANT_READ: u64 = 1 << 0;
+pub const GRANT_WRITE: u64 = 1 << 1;
+
+/// x1 of a copy hypercall naming the VM's host buffer as the target.
+pub const TARGET_HOST: u64 = u64::MAX;
+/// Copy flag in x2: the bytes go from the target to the guest.
+pub const COPY_TO_GUEST: u64 = 1 << 0;
+// Copy list page: a u32 segment count at offset 0, the u32 status at
+// `COPY_STATUS_OFFSET`, then segments from `COPY_HEADER_SIZE`, see `copy`.
+pub const COPY_HEADER_SIZE: usize = 32;
+pub const COPY_SEGMENT_SIZE: usize = 32;
+pub const COPY_STATUS_OFFSET: usize = 4;
+pub const COPY_STATUS_DONE: u32 = 1;
+/// Or'ed with the index of the segment that failed.
+pub const COPY_STATUS_FAILED: u32 = 1 << 31;
+
+// Heartbeat page, read-only to the guest, see `heartbeat`: u64 fields at
+// these offsets. `HEARTBEAT_SEQ` is odd while the hypervisor updates the
+// page; a reader retries until it reads the same even value before and
+// after the other fields.
+pub const HEARTBEAT_SEQ: usize = 0x00;
+/// Host physical counter at the last guest entry.
+pub const HEARTBEAT_HOST_COUNT: usize = 0x08;
+/// Host counter ticks since the VM was built, at the last guest entry.
+pub const HEARTBEAT_UPTIME: usize = 0x10;
+/// Frequency of both counts in Hz.
+pub const HEARTBEAT_FREQ: usize = 0x18;
+/// Index of the vCPU that entered last.
+pub const HEARTBEAT_VCPU: usize = 0x20;
+
+// `x0` of a hotplug event.
+pub const HOTPLUG_ADDED: u64 = 1;
+pub const HOTPLUG_REMOVED: u64 = 2;
+/// SPIs from here on are handed to hotplugged devices.
+pub const FIRST_HOTPLUG_SPI: u32 = 96;
+
+// Guest physical layout of `qemu::vm_builder`, QEMU's `virt` board. The
+// GIC is the host's own through the system register interface; the rest
+// are the addresses guests find in QEMU's device tree.
+pub const QEMU_GICD_BASE: u64 = 0x0800_0000;
+pub const QEMU_GICR_BASE: u64 = 0x080a_0000;
+pub const QEMU_UART_BASE: u64 = 0x0900_0000;
+pub const QEMU_RTC_BASE: u64 = 0x0901_0000;
+pub const QEMU_FW_CFG_BASE: u64 = 0x0902_0000;
+pub const QEMU_GPIO_BASE: u64 = 0x0903_0000;
+pub const QEMU_VIRTIO_MMIO_BASE: u64 = 0x0a00_0000;
+pub const QEMU_VIRTIO_MMIO_STRIDE: u64 = 0x200;
+pub const QEMU_RAM_BASE: u64 = 0x4000_0000;
+pub const QEMU_UART_INTID: u32 = 33;
+pub const QEMU_RTC_INTID: u32 = 34;
+pub const QEMU_GPIO_INTID: u32 = 39;
+/// INTID of the first virtio-mmio transport; each next one adds one.
+pub const QEMU_VIRTIO_MMIO_INTID: u32 = 48;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_abi_compatible() {
+        assert_eq!(VERSION >> 16, VERSION_MAJOR as u64);
+        assert!(compatible(VERSION_MAJOR, 0));
+        assert!(compatible(VERSION_MAJOR, VERSION_MINOR));
+        assert!(!compatible(VERSION_MAJOR, VERSION_MINOR + 1));
+        assert!(!compatible(VERSION_MAJOR + 1, 0));
+    }
+}
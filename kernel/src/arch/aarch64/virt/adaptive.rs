@@ -0,0 +1,244 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exit-rate heuristics. Every vCPU keeps a small window of exit statistics;
+//! at the end of each window it is classified as idle (mostly WFI exits),
+//! busy (few exits over a long window) or normal, and the world switch picks
+//! cheaper VGIC handling accordingly. The window can be resized at runtime,
+//! see `set_window`; it applies from the next window on.
+
+use super::{
+    exit::{ExitCode, GuestEl},
+    policy::ExitClass,
+};
+use crate::time;
+use core::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// A window is idle once at least this share (in 1/8) of its exits are WFI.
+const IDLE_WFI_EIGHTHS: u32 = 6;
+/// Largest `Window::exits`.
+pub const MAX_WINDOW_EXITS: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// Exits per classification window.
+    pub exits: u32,
+    /// A window is busy if it took at least this long to fill up.
+    pub busy: Duration,
+}
+
+pub const DEFAULT_WINDOW: Window = Window {
+    exits: 32,
+    busy: Duration::from_millis(10),
+};
+
+static WINDOW_EXITS: AtomicU32 = AtomicU32::new(DEFAULT_WINDOW.exits);
+static BUSY_US: AtomicU64 = AtomicU64::new(DEFAULT_WINDOW.busy.as_micros() as u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadWindow;
+
+pub fn window() -> Window {
+    Window {
+        exits: WINDOW_EXITS.load(Ordering::Relaxed),
+        busy: Duration::from_micros(BUSY_US.load(Ordering::Relaxed)),
+    }
+}
+
+/// Classify every vCPU's exits over `window` from its next window on.
+pub fn set_window(window: Window) -> Result<(), BadWindow> {
+    if !(1..=MAX_WINDOW_EXITS).contains(&window.exits) {
+        return Err(BadWindow);
+    }
+    WINDOW_EXITS.store(window.exits, Ordering::Relaxed);
+    BUSY_US.store(window.busy.as_micros() as u64, Ordering::Relaxed);
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExitProfile {
+    #[default]
+    Normal,
+    /// Frequent WFI exits: batch timer wakeups and skip VGIC flushes that
+    /// would load nothing.
+    Idle,
+    /// Rare exits: skip VGIC syncs when no list register was in use.
+    Busy,
+}
+
+pub fn classify(exits: u32, wfi_exits: u32, elapsed: Duration, busy: Duration) -> ExitProfile {
+    if exits == 0 {
+        return ExitProfile::Normal;
+    }
+    if wfi_exits * 8 >= exits * IDLE_WFI_EIGHTHS {
+        ExitProfile::Idle
+    } else if elapsed >= busy {
+        ExitProfile::Busy
+    } else {
+        ExitProfile::Normal
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExitStats {
+    pub exits: u64,
+    pub wfi_exits: u64,
+    pub profile_changes: u64,
+    /// Guest traps by the exception level they came from, then by class.
+    /// Unlike `exits`, these include traps EL2 handles without the host.
+    pub traps: [[u64; ExitClass::COUNT]; GuestEl::ALL.len()],
+    profile: ExitProfile,
+    window_start: u64,
+    window_exits: u32,
+    window_wfi: u32,
+}
+
+impl ExitStats {
+    pub const fn new() -> Self {
+        Self {
+            exits: 0,
+            wfi_exits: 0,
+            profile_changes: 0,
+            traps: [[0; ExitClass::COUNT]; GuestEl::ALL.len()],
+            profile: ExitProfile::Normal,
+            window_start: 0,
+            window_exits: 0,
+            window_wfi: 0,
+        }
+    }
+
+    #[inline]
+    pub fn profile(&self) -> ExitProfile {
+        self.profile
+    }
+
+    /// Start the counters over, along with the current window. The profile
+    /// stays until a window classifies differently.
+    pub fn reset(&mut self) {
+        *self = Self {
+            profile: self.profile,
+            ..Self::new()
+        };
+    }
+
+    /// Account one guest trap. Runs at EL2.
+    #[inline]
+    pub fn record_trap(&mut self, el: GuestEl, class: ExitClass) {
+        self.traps[el as usize][class as usize] += 1;
+    }
+
+    /// Traps taken from `el`, of any class.
+    pub fn traps_from(&self, el: GuestEl) -> u64 {
+        self.traps[el as usize].iter().sum()
+    }
+
+    /// Account one exit. Returns the new profile when a window closes with
+    /// a different classification.
+    pub fn record_exit(&mut self, code: ExitCode) -> Option<ExitProfile> {
+        let now = time::current_clock_cycles();
+        if self.window_exits == 0 {
+            self.window_start = now;
+        }
+        self.exits += 1;
+        self.window_exits += 1;
+        if code == ExitCode::Wfi {
+            self.wfi_exits += 1;
+            self.window_wfi += 1;
+        }
+        let window = window();
+        if self.window_exits < window.exits {
+            return None;
+        }
+
+        let elapsed = time::from_clock_cycles(now.saturating_sub(self.window_start));
+        let profile = classify(self.window_exits, self.window_wfi, elapsed, window.busy);
+        self.window_exits = 0;
+        self.window_wfi = 0;
+        if profile == self.profile {
+            return None;
+        }
+        self.profile = profile;
+        self.profile_changes += 1;
+        Some(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_classify_idle() {
+        let busy = DEFAULT_WINDOW.busy;
+        assert_eq!(classify(32, 30, Duration::ZERO, busy), ExitProfile::Idle);
+        assert_eq!(classify(32, 24, busy, busy), ExitProfile::Idle);
+    }
+
+    #[test]
+    fn test_classify_busy() {
+        let busy = DEFAULT_WINDOW.busy;
+        assert_eq!(classify(32, 2, busy, busy), ExitProfile::Busy);
+        assert_eq!(
+            classify(32, 2, Duration::from_millis(1), busy),
+            ExitProfile::Normal
+        );
+    }
+
+    #[test]
+    fn test_exit_stats_by_el() {
+        let mut stats = ExitStats::new();
+        stats.record_trap(GuestEl::El0, ExitClass::Hvc);
+        stats.record_trap(GuestEl::El0, ExitClass::DataAbort);
+        stats.record_trap(GuestEl::El1, ExitClass::Hvc);
+        assert_eq!(stats.traps_from(GuestEl::El0), 2);
+        assert_eq!(stats.traps_from(GuestEl::El1), 1);
+        assert_eq!(
+            stats.traps[GuestEl::El0 as usize][ExitClass::DataAbort as usize],
+            1
+        );
+    }
+
+    #[test]
+    fn test_classify_empty() {
+        assert_eq!(
+            classify(0, 0, DEFAULT_WINDOW.busy, DEFAULT_WINDOW.busy),
+            ExitProfile::Normal
+        );
+    }
+
+    #[test]
+    fn test_exit_stats_reset() {
+        let mut stats = ExitStats::new();
+        stats.record_trap(GuestEl::El1, ExitClass::Hvc);
+        stats.record_exit(ExitCode::Wfi);
+        stats.reset();
+        assert_eq!(stats.exits, 0);
+        assert_eq!(stats.wfi_exits, 0);
+        assert_eq!(stats.traps_from(GuestEl::El1), 0);
+    }
+
+    #[test]
+    fn test_set_window() {
+        let bad = Window {
+            exits: 0,
+            ..DEFAULT_WINDOW
+        };
+        assert_eq!(set_window(bad), Err(BadWindow));
+        assert_eq!(window(), DEFAULT_WINDOW);
+    }
+}
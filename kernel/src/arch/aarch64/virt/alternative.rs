@@ -0,0 +1,356 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Boot-time code patching. Instructions whose form depends on a CPU
+//! feature fixed at boot are assembled for the case without it and listed
+//! by `alternative!` next to a replacement of the same length. `init`
+//! rewrites every site whose feature the boot core has before any guest
+//! runs, so the world switch never tests a feature flag.
+//!
+//! Replacements are copied, not relocated: they must not contain branches
+//! or other PC-relative instructions.
+
+#![cfg_attr(not(virtualization), allow(dead_code))]
+
+use super::hal::{sysregs, SysReg, SysRegBackend};
+use crate::arch::aarch64::sysreg::read_sysreg;
+#[cfg(virtualization)]
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::{arch::asm, ptr::addr_of};
+
+const HCR_EL2_E2H: u64 = 1 << 34;
+
+fn field(reg: u64, shift: u32) -> u64 {
+    (reg >> shift) & 0xf
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Feature {
+    /// EL2 runs with HCR_EL2.E2H, so EL1 registers are reached through
+    /// their `*_EL12` aliases. `hyper::hyp_init` keeps the host nVHE, so
+    /// this is clear for now.
+    Vhe = 0,
+    /// GIC system register interface, GICv3 or later. A GICv2 has its
+    /// virtual interface in MMIO, which the vGIC doesn't drive.
+    GicV3 = 1,
+    /// Pointer authentication, with keys guests may use.
+    PAuth = 2,
+}
+
+impl Feature {
+    const ALL: [Feature; 3] = [Feature::Vhe, Feature::GicV3, Feature::PAuth];
+
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Vhe => "vhe",
+            Self::GicV3 => "gicv3",
+            Self::PAuth => "pauth",
+        }
+    }
+}
+
+/// Set of `Feature`s.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn with(self, feature: Feature) -> Self {
+        Self(self.0 | feature.bit())
+    }
+
+    pub const fn has(self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Feature> {
+        Feature::ALL.into_iter().filter(move |&f| self.has(f))
+    }
+
+    /// Features of the calling core. Runs at EL2.
+    pub fn probe() -> Self {
+        let pfr0 = read_sysreg!("id_aa64pfr0_el1");
+        let isar1 = read_sysreg!("id_aa64isar1_el1");
+        // ID_AA64ISAR2_EL1, by encoding for assemblers before Armv8.7.
+        let isar2 = read_sysreg!("s3_0_c0_c6_2");
+        let mut features = Self::empty();
+        if sysregs().read(SysReg::HcrEl2) & HCR_EL2_E2H != 0 {
+            features = features.with(Feature::Vhe);
+        }
+        if field(pfr0, 24) != 0 {
+            features = features.with(Feature::GicV3);
+        }
+        // APA, API or APA3: address authentication with some algorithm.
+        if field(isar1, 4) != 0 || field(isar1, 8) != 0 || field(isar2, 12) != 0 {
+            features = features.with(Feature::PAuth);
+        }
+        features
+    }
+}
+
+/// Assembler number of a `Feature`, for `alternative!`.
+macro_rules! alt_feature {
+    (Vhe) => {
+        "0"
+    };
+    (GicV3) => {
+        "1"
+    };
+    (PAuth) => {
+        "2"
+    };
+}
+pub(crate) use alt_feature;
+
+/// Asm template running `$orig`, replaced with `$repl` at boot when the
+/// CPU has `$feature`. Both must assemble to the same length. Operands
+/// are shared, so refer to them by position or name, never as `{}`.
+macro_rules! alternative {
+    ($orig:expr, $repl:expr, $feature:ident) => {
+        concat!(
+            "661:\n",
+            $orig,
+            "\n662:\n",
+            ".pushsection .hyp.alternatives, \"a\"\n",
+            ".balign 4\n",
+            ".word 661b - .\n",
+            ".word 663f - .\n",
+            ".hword ",
+            $crate::arch::virt::alternative::alt_feature!($feature),
+            "\n",
+            ".byte 662b - 661b\n",
+            ".byte 664f - 663f\n",
+            ".popsection\n",
+            ".pushsection .hyp.text.alt, \"ax\"\n",
+            "663:\n",
+            $repl,
+            "\n664:\n",
+            ".if (664b - 663b) != (662b - 661b)\n",
+            ".error \"alternative replacement length differs\"\n",
+            ".endif\n",
+            ".popsection\n",
+        )
+    };
+}
+pub(crate) use alternative;
+
+/// One patch site, as `alternative!` emits it. Addresses are stored
+/// relative to the field holding them.
+#[repr(C)]
+#[derive(Debug)]
+struct AltEntry {
+    orig: i32,
+    repl: i32,
+    feature: u16,
+    orig_len: u8,
+    repl_len: u8,
+}
+
+impl AltEntry {
+    fn orig_addr(&self) -> usize {
+        (addr_of!(self.orig) as isize + self.orig as isize) as usize
+    }
+
+    fn repl_addr(&self) -> usize {
+        (addr_of!(self.repl) as isize + self.repl as isize) as usize
+    }
+
+    /// Whether the site takes its replacement on a CPU with `features`.
+    /// Unknown feature numbers never match.
+    fn selected(&self, features: Features) -> bool {
+        Feature::ALL
+            .into_iter()
+            .any(|f| f as u16 == self.feature && features.has(f))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AltStats {
+    pub sites: usize,
+    pub patched: usize,
+}
+
+/// Copy the replacement of every site `features` selects over the
+/// original and make the instruction stream see it.
+///
+/// # Safety
+/// The sites must be writable and no core may be executing them.
+unsafe fn patch(entries: &[AltEntry], features: Features) -> Result<AltStats, &'static str> {
+    let mut stats = AltStats {
+        sites: entries.len(),
+        patched: 0,
+    };
+    for entry in entries.iter().filter(|e| e.selected(features)) {
+        if entry.orig_len != entry.repl_len || entry.orig_len % 4 != 0 {
+            return Err("alternative length mismatch");
+        }
+        let orig = entry.orig_addr() as *mut u32;
+        let repl = entry.repl_addr() as *const u32;
+        for i in 0..entry.orig_len as usize / 4 {
+            core::ptr::write_volatile(orig.add(i), core::ptr::read(repl.add(i)));
+            // Clean the new word to the point of unification, then drop any
+            // stale copy from the instruction caches.
+            asm!(
+                "dc cvau, {0}",
+                "dsb ish",
+                "ic ivau, {0}",
+                in(reg) orig.add(i),
+                options(nostack)
+            );
+        }
+        stats.patched += 1;
+    }
+    asm!("dsb ish", "isb", options(nostack));
+    Ok(stats)
+}
+
+#[cfg(virtualization)]
+extern "C" {
+    static __hyp_alt_start: u8;
+    static __hyp_alt_end: u8;
+}
+
+// Written at EL2 before `.bss` is cleared.
+#[cfg(virtualization)]
+#[link_section = ".data"]
+static APPLIED: AtomicBool = AtomicBool::new(false);
+#[cfg(virtualization)]
+#[link_section = ".data"]
+static FEATURES: AtomicU32 = AtomicU32::new(0);
+#[cfg(virtualization)]
+#[link_section = ".data"]
+static PATCHED: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(virtualization)]
+fn entries() -> &'static [AltEntry] {
+    unsafe {
+        let start = addr_of!(__hyp_alt_start) as *const AltEntry;
+        let end = addr_of!(__hyp_alt_end) as *const AltEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Patch the hypervisor for the features of the boot core. Runs at EL2,
+/// whose identity map leaves `.hyp.text` writable. Later cores
+/// only check they have the same features, since the patched code is
+/// shared.
+#[cfg(virtualization)]
+pub fn init() -> Result<(), &'static str> {
+    let features = Features::probe();
+    if APPLIED.load(Ordering::Acquire) {
+        if FEATURES.load(Ordering::Relaxed) != features.0 {
+            return Err("cpu features differ from the boot core");
+        }
+        unsafe { asm!("ic iallu", "dsb nsh", "isb", options(nostack)) };
+        return Ok(());
+    }
+    let stats = unsafe { patch(entries(), features)? };
+    FEATURES.store(features.0, Ordering::Relaxed);
+    PATCHED.store(stats.patched as u32, Ordering::Relaxed);
+    APPLIED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Features the hypervisor was patched for, empty before `init`.
+#[cfg(virtualization)]
+pub fn features() -> Features {
+    Features(FEATURES.load(Ordering::Relaxed))
+}
+
+#[cfg(virtualization)]
+pub fn has(feature: Feature) -> bool {
+    features().has(feature)
+}
+
+#[cfg(virtualization)]
+pub fn stats() -> AltStats {
+    AltStats {
+        sites: entries().len(),
+        patched: PATCHED.load(Ordering::Relaxed) as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    const NOP: u32 = 0xd503_201f;
+
+    #[test]
+    fn test_alternative_feature_numbers() {
+        for feature in Feature::ALL {
+            let number = match feature {
+                Feature::Vhe => alt_feature!(Vhe),
+                Feature::GicV3 => alt_feature!(GicV3),
+                Feature::PAuth => alt_feature!(PAuth),
+            };
+            assert_eq!(number.parse::<u16>(), Ok(feature as u16));
+        }
+        let features = Features::empty().with(Feature::PAuth);
+        assert!(features.has(Feature::PAuth));
+        assert!(!features.has(Feature::Vhe));
+        assert_eq!(features.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_alternative_patch() {
+        static mut CODE: [u32; 4] = [NOP; 4];
+        static REPL: [u32; 4] = [1, 2, 3, 4];
+        let mut entries = [
+            AltEntry {
+                orig: 0,
+                repl: 0,
+                feature: Feature::PAuth as u16,
+                orig_len: 8,
+                repl_len: 8,
+            },
+            AltEntry {
+                orig: 0,
+                repl: 0,
+                feature: Feature::Vhe as u16,
+                orig_len: 8,
+                repl_len: 8,
+            },
+        ];
+        for (n, entry) in entries.iter_mut().enumerate() {
+            let orig = unsafe { addr_of!(CODE[n * 2]) } as isize;
+            let repl = addr_of!(REPL[n * 2]) as isize;
+            entry.orig = (orig - addr_of!(entry.orig) as isize) as i32;
+            entry.repl = (repl - addr_of!(entry.repl) as isize) as i32;
+        }
+        let features = Features::empty().with(Feature::PAuth);
+        let stats = unsafe { patch(&entries, features) };
+        assert_eq!(
+            stats,
+            Ok(AltStats {
+                sites: 2,
+                patched: 1
+            })
+        );
+        assert_eq!(unsafe { *addr_of!(CODE) }, [1, 2, NOP, NOP]);
+
+        entries[1].repl_len = 4;
+        let features = features.with(Feature::Vhe);
+        assert!(unsafe { patch(&entries[1..], features) }.is_err());
+    }
+}
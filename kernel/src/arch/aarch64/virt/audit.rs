@@ -0,0 +1,295 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audit log of operations that widen what a VM can reach: host memory or
+//! devices mapped into it, pages it shares, secure calls it attempts. Each
+//! record carries the VM's security label and who asked, so a review of a
+//! multi-VM setup can tell which domain touched what. Records are kept in
+//! a fixed ring across VM lifetimes; reading doesn't consume them, and
+//! sequence numbers show how many were overwritten.
+
+use super::{
+    grant::PEER_HOST,
+    hyper,
+    identity::{self, Uuid},
+    ring::Ring,
+    vcpu::vcpu_manager,
+};
+use crate::{scheduler, sync::SpinLock};
+use core::fmt;
+
+/// Records kept before the oldest is overwritten.
+pub const AUDIT_ENTRIES: usize = 128;
+
+/// Security label of a VM, such as the trust domain it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityLabel(pub &'static str);
+
+impl SecurityLabel {
+    pub const UNLABELED: Self = Self("unlabeled");
+}
+
+/// Label of VM `vm_id`, from the config of its vCPUs.
+pub fn label_of(vm_id: usize) -> SecurityLabel {
+    vcpu_manager()
+        .ids_of(vm_id)
+        .find_map(|id| vcpu_manager().with_vcpu(id, |v| v.config.label))
+        .unwrap_or(SecurityLabel::UNLABELED)
+}
+
+/// Who asked for an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Initiator {
+    /// A host thread, by id.
+    Host(usize),
+    /// A trap or hypercall of a guest, by vCPU id.
+    Vcpu(usize),
+}
+
+impl Initiator {
+    /// The calling host thread.
+    pub fn host() -> Self {
+        Initiator::Host(scheduler::current_thread_id())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Host device memory mapped into the VM when it was built.
+    Passthrough {
+        ipa: u64,
+        pa: u64,
+        size: u64,
+    },
+    /// Host ROM, flash or a kernel payload mapped read-only into the VM
+    /// when it was built.
+    Rom {
+        ipa: u64,
+        pa: u64,
+        size: u64,
+    },
+    /// Host memory added to the running VM.
+    SharedMemory {
+        ipa: u64,
+        pa: u64,
+        size: u64,
+        writable: bool,
+    },
+    /// Emulated device added to the running VM.
+    DevicePlug {
+        ipa: u64,
+        size: u64,
+        intid: u32,
+    },
+    /// A page of the VM granted to `peer`, a VM id or `PEER_HOST`.
+    Grant {
+        gref: u64,
+        ipa: u64,
+        peer: u64,
+        write: bool,
+    },
+    Revoke {
+        gref: u64,
+    },
+    /// A grant made to the VM mapped into it.
+    GrantMap {
+        owner: usize,
+        gref: u64,
+        ipa: u64,
+    },
+    /// A secure call EL2 refused to forward; `function` is its x0.
+    SmcRefused {
+        function: u64,
+    },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operation::Passthrough { ipa, pa, size } => {
+                write!(f, "passthrough ipa {ipa:#x} pa {pa:#x} size {size:#x}")
+            }
+            Operation::Rom { ipa, pa, size } => {
+                write!(f, "rom ipa {ipa:#x} pa {pa:#x} size {size:#x}")
+            }
+            Operation::SharedMemory {
+                ipa,
+                pa,
+                size,
+                writable,
+            } => write!(
+                f,
+                "shared-memory ipa {ipa:#x} pa {pa:#x} size {size:#x} {}",
+                if writable { "rw" } else { "ro" }
+            ),
+            Operation::DevicePlug { ipa, size, intid } => {
+                write!(f, "device-plug ipa {ipa:#x} size {size:#x} intid {intid}")
+            }
+            Operation::Grant {
+                gref,
+                ipa,
+                peer,
+                write,
+            } => {
+                write!(f, "grant ref {gref:#x} ipa {ipa:#x} to ")?;
+                match peer {
+                    PEER_HOST => write!(f, "host")?,
+                    vm_id => write!(f, "vm{vm_id}")?,
+                }
+                write!(f, " {}", if write { "rw" } else { "ro" })
+            }
+            Operation::Revoke { gref } => write!(f, "revoke ref {gref:#x}"),
+            Operation::GrantMap { owner, gref, ipa } => {
+                write!(f, "grant-map vm{owner} ref {gref:#x} at ipa {ipa:#x}")
+            }
+            Operation::SmcRefused { function } => write!(f, "smc-refused fn {function:#x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub seq: u64,
+    /// Physical counter when the operation completed.
+    pub time: u64,
+    pub vm_id: usize,
+    /// UUID of the VM, which outlives reuse of its id.
+    pub uuid: Option<Uuid>,
+    pub label: SecurityLabel,
+    pub initiator: Initiator,
+    pub op: Operation,
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} vm{} ", self.seq, self.time, self.vm_id)?;
+        if let Some(uuid) = self.uuid {
+            write!(f, "{} ", uuid)?;
+        }
+        write!(f, "[{}] ", self.label.0)?;
+        match self.initiator {
+            Initiator::Host(thread) => write!(f, "host:{thread}")?,
+            Initiator::Vcpu(id) => write!(f, "vcpu:{id}")?,
+        }
+        write!(f, " {}", self.op)
+    }
+}
+
+struct AuditLog {
+    records: Ring<AuditRecord, AUDIT_ENTRIES>,
+    next_seq: u64,
+}
+
+impl AuditLog {
+    const fn new() -> Self {
+        Self {
+            records: Ring::new(),
+            next_seq: 0,
+        }
+    }
+
+    fn push(&mut self, mut record: AuditRecord) {
+        record.seq = self.next_seq;
+        self.next_seq += 1;
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let _ = self.records.push_back(record);
+    }
+}
+
+// Also taken at EL2 by guest hypercalls, so nothing is allocated under it.
+static LOG: SpinLock<AuditLog> = SpinLock::new(AuditLog::new());
+
+/// Log `op` on VM `vm_id`, labeled `label`.
+pub fn record(vm_id: usize, label: SecurityLabel, initiator: Initiator, op: Operation) {
+    let uuid = identity::uuid_of(vm_id);
+    LOG.irqsave_lock().push(AuditRecord {
+        seq: 0,
+        time: hyper::read_cntpct(),
+        vm_id,
+        uuid,
+        label,
+        initiator,
+        op,
+    });
+}
+
+/// Call `f` on every record kept, oldest first.
+pub fn for_each(mut f: impl FnMut(&AuditRecord)) {
+    LOG.irqsave_lock().records.iter().for_each(|r| f(r));
+}
+
+/// Records logged since boot, including overwritten ones.
+pub fn total() -> u64 {
+    LOG.irqsave_lock().next_seq
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    fn refused(function: u64) -> AuditRecord {
+        AuditRecord {
+            seq: 0,
+            time: 0,
+            vm_id: 3,
+            uuid: None,
+            label: SecurityLabel("untrusted"),
+            initiator: Initiator::Vcpu(1),
+            op: Operation::SmcRefused { function },
+        }
+    }
+
+    #[test]
+    fn test_audit_ring_keeps_latest() {
+        let mut log = AuditLog::new();
+        for n in 0..AUDIT_ENTRIES as u64 + 2 {
+            log.push(refused(n));
+        }
+        assert_eq!(log.next_seq, AUDIT_ENTRIES as u64 + 2);
+        assert_eq!(log.records.len(), AUDIT_ENTRIES);
+        let first = log.records.iter().next().unwrap();
+        assert_eq!(first.seq, 2);
+        assert_eq!(first.op, Operation::SmcRefused { function: 2 });
+    }
+
+    #[test]
+    fn test_audit_record_format() {
+        let mut record = refused(0xc200_0000);
+        record.seq = 7;
+        record.time = 42;
+        assert_eq!(
+            record.to_string(),
+            "7 42 vm3 [untrusted] vcpu:1 smc-refused fn 0xc2000000"
+        );
+        record.initiator = Initiator::Host(9);
+        record.op = Operation::Grant {
+            gref: 0x101,
+            ipa: 0x4000_0000,
+            peer: PEER_HOST,
+            write: false,
+        };
+        assert_eq!(
+            record.to_string(),
+            "7 42 vm3 [untrusted] host:9 grant ref 0x101 ipa 0x40000000 to host ro"
+        );
+        record.uuid = Uuid::parse("6f1c2a3b-0d4e-4f5a-8b6c-7d8e9fa0b1c2");
+        assert!(record
+            .to_string()
+            .starts_with("7 42 vm3 6f1c2a3b-0d4e-4f5a-8b6c-7d8e9fa0b1c2 [untrusted] host:9"));
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! VMs started at boot. Board code registers each with a setup closure
+//! that loads the image and describes the VM, before the `Services` init
+//! level, whose last init call runs `boot_all`. That gives every VM an init
+//! thread of its own: it runs the setup, builds the VM and starts its boot
+//! vCPU, so images of different VMs load concurrently.
+//!
+//! A VM may be started after another one is ready. Ready means its boot
+//! vCPU entered the guest, or, with `ReadyOn::Doorbell`, that the guest
+//! rang the doorbell, e.g. once its services are up. A VM that fails to
+//! build, whose boot vCPU stops first, or that isn't ready within
+//! `READY_TIMEOUT_TICKS` has failed, and a VM whose dependency failed
+//! isn't started.
+
+use super::{
+    doorbell::{self, DoorbellSet},
+    hyper, identity, vcpu,
+    vm::{BuildError, VmBuilder},
+};
+use crate::{
+    arch::aarch64::registers::cntfrq_el0::CNTFRQ_EL0,
+    config,
+    scheduler::InsertToEnd,
+    sync::{
+        event_flags::{EventFlags, EventFlagsMode},
+        SpinLock,
+    },
+    thread::{self, Entry},
+    time::Tick,
+    virt::run_state::{self, RunState},
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use tock_registers::interfaces::Readable;
+
+/// VMs that can be registered, one event bit each.
+pub const MAX_AUTOSTART: usize = 32;
+/// How long a started VM has to become ready.
+pub const READY_TIMEOUT_TICKS: usize = 30 * blueos_kconfig::CONFIG_TICKS_PER_SECOND as usize;
+
+// Tokens of the set a VM's init thread waits on. The ready doorbell is
+// bound to `READY`.
+const READY: u32 = 1 << 0;
+const STOPPED: u32 = 1 << 1;
+
+/// Loads the image of a VM and describes it.
+pub type Setup = Box<dyn FnOnce() -> Result<VmBuilder, &'static str> + Send>;
+
+/// When VMs started after this one may go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyOn {
+    /// Its boot vCPU is running.
+    Started,
+    /// The guest rang this doorbell.
+    Doorbell(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoStartError {
+    TooMany,
+    /// The VM is registered already.
+    Duplicate(usize),
+    /// The VM waits for one that isn't registered.
+    UnknownDependency {
+        vm_id: usize,
+        after: usize,
+    },
+    /// The VM waits for itself, possibly through others.
+    Cycle(usize),
+    /// `boot_all` ran already.
+    Booted,
+    /// No run state hook was free to tell when boot vCPUs start.
+    NoHook,
+}
+
+struct AutoStart {
+    vm_id: usize,
+    after: Option<usize>,
+    ready_on: ReadyOn,
+    setup: Setup,
+}
+
+static VMS: SpinLock<Vec<AutoStart>> = SpinLock::new(Vec::new());
+static BOOTED: AtomicBool = AtomicBool::new(false);
+// Bit n is set once the VM in slot n is ready or has failed.
+static DONE: EventFlags = EventFlags::new();
+static FAILED: AtomicU32 = AtomicU32::new(0);
+
+// Boot vCPU of each VM being started, and the set its init thread waits on.
+struct Starting {
+    vcpu: usize,
+    ready_on: ReadyOn,
+    set: Arc<DoorbellSet>,
+}
+
+static STARTING: SpinLock<BTreeMap<usize, Starting>> = SpinLock::new(BTreeMap::new());
+
+/// Start VM `vm_id` from `boot_all`, after VM `after` if given.
+pub fn register(
+    vm_id: usize,
+    after: Option<usize>,
+    ready_on: ReadyOn,
+    setup: impl FnOnce() -> Result<VmBuilder, &'static str> + Send + 'static,
+) -> Result<(), AutoStartError> {
+    if BOOTED.load(Ordering::Acquire) {
+        return Err(AutoStartError::Booted);
+    }
+    let mut vms = VMS.irqsave_lock();
+    if vms.len() >= MAX_AUTOSTART {
+        return Err(AutoStartError::TooMany);
+    }
+    if vms.iter().any(|v| v.vm_id == vm_id) {
+        return Err(AutoStartError::Duplicate(vm_id));
+    }
+    vms.push(AutoStart {
+        vm_id,
+        after,
+        ready_on,
+        setup: Box::new(setup),
+    });
+    Ok(())
+}
+
+/// Slot each VM of `vms`, given as (id, dependency), waits for.
+fn resolve(vms: &[(usize, Option<usize>)]) -> Result<Vec<Option<usize>>, AutoStartError> {
+    let deps = vms
+        .iter()
+        .map(|&(vm_id, after)| {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            vms.iter()
+                .position(|&(id, _)| id == after)
+                .map(Some)
+                .ok_or(AutoStartError::UnknownDependency { vm_id, after })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // A chain longer than the number of VMs runs in a circle.
+    for (slot, &(vm_id, _)) in vms.iter().enumerate() {
+        let mut next = deps[slot];
+        for _ in 0..vms.len() {
+            match next {
+                Some(dep) => next = deps[dep],
+                None => break,
+            }
+        }
+        if next.is_some() {
+            return Err(AutoStartError::Cycle(vm_id));
+        }
+    }
+    Ok(deps)
+}
+
+/// Give every registered VM its init thread. Checks the dependencies
+/// first and starts nothing if they don't resolve. Runs once, from the
+/// `Services` init level.
+pub fn boot_all() -> Result<(), AutoStartError> {
+    let vms = {
+        let mut vms = VMS.irqsave_lock();
+        let ids: Vec<_> = vms.iter().map(|v| (v.vm_id, v.after)).collect();
+        let deps = resolve(&ids)?;
+        if BOOTED.swap(true, Ordering::AcqRel) {
+            return Err(AutoStartError::Booted);
+        }
+        if vms.is_empty() {
+            return Ok(());
+        }
+        core::mem::take(&mut *vms).into_iter().zip(deps)
+    };
+    run_state::register_hook(on_run_state).map_err(|_| AutoStartError::NoHook)?;
+    DONE.init(0);
+    for (slot, (vm, dep)) in vms.enumerate() {
+        thread::Builder::new(Entry::Closure(Box::new(move || init_vm(slot, dep, vm))))
+            .set_priority(config::VIRT_INIT_THREAD_PRIORITY)
+            .start();
+    }
+    Ok(())
+}
+
+/// The `Services` init call: start the VMs board code registered.
+pub(crate) fn init() -> Result<(), &'static str> {
+    boot_all().map_err(|e| {
+        log::error!("[virt] autostart: {:?}", e);
+        "vms not started"
+    })
+}
+
+// Wakes the init thread of a VM whose boot vCPU entered the guest for the
+// first time, or stopped.
+fn on_run_state(vm_id: usize, vcpu_id: usize, _from: RunState, to: RunState) {
+    let (set, token) = {
+        let starting = STARTING.irqsave_lock();
+        let Some(s) = starting.get(&vm_id).filter(|s| s.vcpu == vcpu_id) else {
+            return;
+        };
+        let token = match to {
+            RunState::Running if s.ready_on == ReadyOn::Started => READY,
+            RunState::Stopped => STOPPED,
+            _ => return,
+        };
+        (s.set.clone(), token)
+    };
+    set.notify(token);
+}
+
+fn init_vm(slot: usize, dep: Option<usize>, vm: AutoStart) {
+    let vm_id = vm.vm_id;
+    let start = hyper::read_cntpct();
+    let ok = dep.map_or(true, wait_for) && {
+        match boot(vm) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("[virt] vm {} not started: {}", vm_id, e);
+                false
+            }
+        }
+    };
+    if ok {
+        let us = hyper::read_cntpct().wrapping_sub(start) * 1_000_000 / CNTFRQ_EL0.get();
+        log::info!("[virt] {} ready after {} us", identity::tag(vm_id), us);
+    } else {
+        FAILED.fetch_or(1 << slot, Ordering::AcqRel);
+    }
+    let _ = DONE.set(1 << slot);
+}
+
+// Whether the VM in `slot` came up.
+fn wait_for(slot: usize) -> bool {
+    let _ = DONE.wait::<InsertToEnd>(
+        1 << slot,
+        EventFlagsMode::ALL | EventFlagsMode::NO_CLEAR,
+        Tick::MAX,
+    );
+    FAILED.load(Ordering::Acquire) & (1 << slot) == 0
+}
+
+fn boot(vm: AutoStart) -> Result<(), &'static str> {
+    let vm_id = vm.vm_id;
+    let vcpus = (vm.setup)()?.build().map_err(|e| match e {
+        BuildError::Invalid(_) => "invalid description",
+        BuildError::Failed(e) => e,
+    })?;
+    let &boot_vcpu = vcpus.first().ok_or("no boot vcpu")?;
+    // Bound before the guest runs so an early ring isn't missed.
+    let set = DoorbellSet::new();
+    if let ReadyOn::Doorbell(n) = vm.ready_on {
+        set.bind(vm_id, n, READY.trailing_zeros())
+            .map_err(|_| "ready doorbell taken")?;
+    }
+    STARTING.irqsave_lock().insert(
+        vm_id,
+        Starting {
+            vcpu: boot_vcpu,
+            ready_on: vm.ready_on,
+            set: set.clone(),
+        },
+    );
+    let stopped = set.clone();
+    thread::Builder::new(Entry::Closure(Box::new(move || {
+        if let Err(e) = vcpu::run_vcpu(boot_vcpu) {
+            log::warn!("[virt] {} boot vcpu failed: {:?}", identity::tag(vm_id), e);
+            stopped.notify(STOPPED);
+        }
+    })))
+    .start();
+    let tokens = set.wait(Tick(READY_TIMEOUT_TICKS));
+    STARTING.irqsave_lock().remove(&vm_id);
+    if let ReadyOn::Doorbell(n) = vm.ready_on {
+        let _ = doorbell::unbind(vm_id, n);
+    }
+    match tokens {
+        Ok(tokens) if tokens & READY != 0 => Ok(()),
+        Ok(_) => Err("boot vcpu stopped before it was ready"),
+        Err(_) => Err("not ready in time"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_autostart_resolve() {
+        let vms = [(1, None), (2, Some(1)), (3, Some(2)), (4, Some(1))];
+        assert_eq!(
+            resolve(&vms).as_deref(),
+            Ok(&[None, Some(0), Some(1), Some(0)][..])
+        );
+        assert_eq!(
+            resolve(&[(1, None), (2, Some(5))]),
+            Err(AutoStartError::UnknownDependency { vm_id: 2, after: 5 })
+        );
+        assert_eq!(resolve(&[(1, Some(1))]), Err(AutoStartError::Cycle(1)));
+        assert_eq!(
+            resolve(&[(1, None), (2, Some(3)), (3, Some(2))]),
+            Err(AutoStartError::Cycle(2))
+        );
+    }
+
+    #[test]
+    fn test_autostart_ready_on_started() {
+        let (vm_id, boot_vcpu) = (test_vm_id(), 7);
+        let set = DoorbellSet::new();
+        STARTING.irqsave_lock().insert(
+            vm_id,
+            Starting {
+                vcpu: boot_vcpu,
+                ready_on: ReadyOn::Started,
+                set: set.clone(),
+            },
+        );
+        // Only the boot vCPU counts, and only once it enters the guest.
+        on_run_state(vm_id, boot_vcpu + 1, RunState::Stopped, RunState::Running);
+        on_run_state(vm_id, boot_vcpu, RunState::Running, RunState::Blocked);
+        assert_eq!(set.poll(), 0);
+        on_run_state(vm_id, boot_vcpu, RunState::Stopped, RunState::Running);
+        assert_eq!(set.poll(), READY);
+        on_run_state(vm_id, boot_vcpu, RunState::Running, RunState::Stopped);
+        assert_eq!(set.poll(), STOPPED);
+
+        STARTING.irqsave_lock().get_mut(&vm_id).unwrap().ready_on = ReadyOn::Doorbell(3);
+        on_run_state(vm_id, boot_vcpu, RunState::Stopped, RunState::Running);
+        assert_eq!(set.poll(), 0);
+        STARTING.irqsave_lock().remove(&vm_id);
+    }
+}
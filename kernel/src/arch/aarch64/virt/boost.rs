@@ -0,0 +1,194 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Priority boost on interrupt injection. A vCPU that gets an interrupt
+//! while out of guest mode, asleep after a WFI or waiting for a core, has
+//! its host thread raised to the boost priority, so how long the interrupt
+//! takes to reach the guest doesn't depend on host load. The boost decays
+//! on the first exit `decay` ticks later, or when the run loop ends.
+//! Boosting changes the thread's origin priority, so a priority inherited
+//! through a mutex the thread holds is neither lost nor left behind.
+
+use crate::{scheduler, sync::SpinLock, thread::ThreadNode, time::Tick, types::ThreadPriority};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+static PRIORITY: AtomicU32 = AtomicU32::new(blueos_kconfig::CONFIG_VIRT_IRQ_BOOST_PRIORITY as u32);
+static DECAY: AtomicUsize = AtomicUsize::new(blueos_kconfig::CONFIG_VIRT_IRQ_BOOST_DECAY as usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoostConfig {
+    /// Host thread priority while boosted; lower is more urgent.
+    pub priority: ThreadPriority,
+    /// Ticks a boost lasts at least.
+    pub decay: Tick,
+}
+
+pub fn config() -> BoostConfig {
+    BoostConfig {
+        priority: PRIORITY.load(Ordering::Relaxed) as ThreadPriority,
+        decay: Tick(DECAY.load(Ordering::Relaxed)),
+    }
+}
+
+/// Applies to boosts from now on; running ones keep their priority.
+pub fn set_config(config: BoostConfig) {
+    PRIORITY.store(config.priority as u32, Ordering::Relaxed);
+    DECAY.store(config.decay.0, Ordering::Relaxed);
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BoostStats {
+    /// Injections that raised the host thread.
+    pub boosts: u64,
+    /// Injections finding the thread boosted already or more urgent.
+    pub skipped: u64,
+    /// Ticks spent boosted, counting finished boosts only.
+    pub boosted_ticks: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Active {
+    since: Tick,
+    decay: Tick,
+    // Origin priority to go back to.
+    saved: ThreadPriority,
+}
+
+impl Active {
+    fn expired(&self, now: Tick) -> bool {
+        now.since(self.since) >= self.decay
+    }
+}
+
+struct Inner {
+    thread: Option<ThreadNode>,
+    active: Option<Active>,
+    stats: BoostStats,
+}
+
+/// Boost state of one vCPU's host thread.
+pub struct Boost {
+    inner: SpinLock<Inner>,
+}
+
+impl Boost {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(Inner {
+                thread: None,
+                active: None,
+                stats: BoostStats {
+                    boosts: 0,
+                    skipped: 0,
+                    boosted_ticks: 0,
+                },
+            }),
+        }
+    }
+
+    /// `thread` runs the vCPU from now on.
+    pub(crate) fn attach(&self, thread: ThreadNode) {
+        self.inner.irqsave_lock().thread = Some(thread);
+    }
+
+    /// The run loop ended; drop any boost and forget the thread.
+    pub(crate) fn detach(&self) {
+        let mut inner = self.inner.irqsave_lock();
+        Self::end(&mut inner, Tick::now());
+        inner.thread = None;
+    }
+
+    /// An interrupt was injected while the vCPU was out of guest mode.
+    pub(crate) fn raise(&self) {
+        let mut inner = self.inner.irqsave_lock();
+        let Some(thread) = inner.thread.clone() else {
+            return;
+        };
+        let config = config();
+        let saved = thread.origin_priority();
+        if inner.active.is_some() || saved <= config.priority {
+            inner.stats.skipped += 1;
+            return;
+        }
+        scheduler::set_thread_origin_priority(&thread, config.priority);
+        inner.active = Some(Active {
+            since: Tick::now(),
+            decay: config.decay,
+            saved,
+        });
+        inner.stats.boosts += 1;
+    }
+
+    /// Called by the host thread on each exit; ends an expired boost.
+    pub(crate) fn decay(&self) {
+        let mut inner = self.inner.irqsave_lock();
+        let now = Tick::now();
+        if inner.active.is_some_and(|active| active.expired(now)) {
+            Self::end(&mut inner, now);
+        }
+    }
+
+    fn end(inner: &mut Inner, now: Tick) {
+        let Some(active) = inner.active.take() else {
+            return;
+        };
+        inner.stats.boosted_ticks += now.since(active.since).0 as u64;
+        if let Some(thread) = &inner.thread {
+            scheduler::set_thread_origin_priority(thread, active.saved);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.inner.irqsave_lock().active.is_some()
+    }
+
+    pub fn stats(&self) -> BoostStats {
+        self.inner.irqsave_lock().stats
+    }
+
+    /// Start the counters over. A running boost still counts its ticks
+    /// when it ends.
+    pub fn reset_stats(&self) {
+        self.inner.irqsave_lock().stats = BoostStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_boost_expiry() {
+        let active = Active {
+            since: Tick(100),
+            decay: Tick(2),
+            saved: 10,
+        };
+        assert!(!active.expired(Tick(100)));
+        assert!(!active.expired(Tick(101)));
+        assert!(active.expired(Tick(102)));
+        // A tick counter behind `since` never expires it early.
+        assert!(!active.expired(Tick(99)));
+    }
+
+    #[test]
+    fn test_boost_without_thread() {
+        let boost = Boost::new();
+        boost.raise();
+        boost.decay();
+        assert!(!boost.is_active());
+        assert_eq!(boost.stats(), BoostStats::default());
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exit latency budgets, for users checking the hypervisor against the
+//! latency budget of a real-time guest. Each exit class may be given a
+//! worst case in counter cycles; every synchronous exit is timed from the
+//! start of its handler until the handler decided what to do, and one
+//! over the budget of its class is counted and kept with the vCPU, ESR
+//! and pc that caused it. Strict VMs fail on it instead.
+//!
+//! Frame save and restore around the handler are what `latency` measures.
+//! Violation times are physical counts like those of `trace`, so both
+//! can be lined up.
+
+use super::{
+    exit::{ExitAction, ExitCode, ExitReason},
+    hyper,
+    policy::ExitClass,
+    ring::Ring,
+    strict::Anomaly,
+    vcpu::Vcpu,
+    vlog::vlog_limited,
+};
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Violations kept before the oldest is overwritten.
+pub const VIOLATION_ENTRIES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    /// Physical count when the handler finished.
+    pub time: u64,
+    pub vm_id: usize,
+    pub vcpu_id: usize,
+    pub class: ExitClass,
+    pub esr: u64,
+    pub pc: u64,
+    pub cycles: u64,
+    pub budget: u64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} vm{} vcpu:{} {} {}/{} esr {:#x} pc {:#x}",
+            self.time,
+            self.vm_id,
+            self.vcpu_id,
+            self.class.name(),
+            self.cycles,
+            self.budget,
+            self.esr,
+            self.pc
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// Budget in counter cycles, 0 if there is none.
+    pub budget: u64,
+    pub exits: u64,
+    pub violations: u64,
+    /// Longest handler seen, in counter cycles.
+    pub worst: u64,
+}
+
+const ZERO: [AtomicU64; ExitClass::COUNT] = [const { AtomicU64::new(0) }; ExitClass::COUNT];
+static BUDGETS: [AtomicU64; ExitClass::COUNT] = ZERO;
+static EXITS: [AtomicU64; ExitClass::COUNT] = ZERO;
+static VIOLATIONS: [AtomicU64; ExitClass::COUNT] = ZERO;
+static WORST: [AtomicU64; ExitClass::COUNT] = ZERO;
+// Taken at EL2, so nothing is allocated under it.
+static LOG: SpinLock<Ring<Violation, VIOLATION_ENTRIES>> = SpinLock::new(Ring::new());
+
+/// Allow exits of `class` `cycles` counter cycles, or any time with 0.
+pub fn set_budget(class: ExitClass, cycles: u64) {
+    BUDGETS[class as usize].store(cycles, Ordering::Relaxed);
+}
+
+pub fn stats(class: ExitClass) -> ClassStats {
+    let n = class as usize;
+    ClassStats {
+        budget: BUDGETS[n].load(Ordering::Relaxed),
+        exits: EXITS[n].load(Ordering::Relaxed),
+        violations: VIOLATIONS[n].load(Ordering::Relaxed),
+        worst: WORST[n].load(Ordering::Relaxed),
+    }
+}
+
+/// Violations kept, oldest first.
+pub fn violations() -> Vec<Violation> {
+    LOG.irqsave_lock().iter().copied().collect()
+}
+
+/// Forget counts and violations; budgets stay.
+pub fn reset() {
+    for n in 0..ExitClass::COUNT {
+        EXITS[n].store(0, Ordering::Relaxed);
+        VIOLATIONS[n].store(0, Ordering::Relaxed);
+        WORST[n].store(0, Ordering::Relaxed);
+    }
+    let mut log = LOG.irqsave_lock();
+    while log.pop_front().is_some() {}
+}
+
+fn over_budget(cycles: u64, budget: u64) -> bool {
+    budget != 0 && cycles > budget
+}
+
+/// Account the exit of `vcpu` for `reason` whose handler started at
+/// physical count `start` and decided on `action`. Returns the action to
+/// take. Runs at EL2.
+pub fn check(vcpu: &Vcpu, reason: ExitReason, start: u64, action: ExitAction) -> ExitAction {
+    let now = hyper::read_cntpct();
+    let cycles = now.wrapping_sub(start);
+    let class = ExitClass::of(reason);
+    let n = class as usize;
+    EXITS[n].fetch_add(1, Ordering::Relaxed);
+    WORST[n].fetch_max(cycles, Ordering::Relaxed);
+    let budget = BUDGETS[n].load(Ordering::Relaxed);
+    if !over_budget(cycles, budget) {
+        return action;
+    }
+    VIOLATIONS[n].fetch_add(1, Ordering::Relaxed);
+    {
+        let mut log = LOG.irqsave_lock();
+        if log.is_full() {
+            log.pop_front();
+        }
+        let _ = log.push_back(Violation {
+            time: now,
+            vm_id: vcpu.vm_id,
+            vcpu_id: vcpu.id,
+            class,
+            esr: vcpu.exit_esr,
+            pc: vcpu.regs.elr,
+            cycles,
+            budget,
+        });
+    }
+    vlog_limited!(
+        Exit,
+        Warn,
+        "[EL2] vcpu {} {} exit took {} cycles, budget {}",
+        vcpu.id,
+        class.name(),
+        cycles,
+        budget
+    );
+    if vcpu.config.strict {
+        ExitAction::Exit(ExitCode::Anomaly(Anomaly::ExitBudget as u32))
+    } else {
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_exit_budget() {
+        assert!(!over_budget(u64::MAX, 0));
+        assert!(!over_budget(100, 100));
+        assert!(over_budget(101, 100));
+
+        let violation = Violation {
+            time: 42,
+            vm_id: 2,
+            vcpu_id: 1,
+            class: ExitClass::DataAbort,
+            esr: 0x9200_0046,
+            pc: 0x4008_0000,
+            cycles: 900,
+            budget: 500,
+        };
+        assert_eq!(
+            violation.to_string(),
+            "42 vm2 vcpu:1 data_abort 900/500 esr 0x92000046 pc 0x40080000"
+        );
+    }
+}
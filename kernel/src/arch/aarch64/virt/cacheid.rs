@@ -0,0 +1,257 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache geometry as guests see it. Cores of a big.LITTLE system may
+//! disagree on the line sizes in CTR_EL0 or the DC ZVA block size in
+//! DCZID_EL0, and a guest reads them once: after its vCPU moves, its memset
+//! zeroes the wrong amount and its cache maintenance skips lines. Each core
+//! folds its registers into values that hold on all of them. VMs trapping
+//! the cache ID registers, those with `Traps::cache_id` and all of them
+//! once the cores disagree, read the folded CTR_EL0.
+//!
+//! Trapping DC ZVA makes DCZID_EL0 report it as prohibited, so guests
+//! that check it zero with plain stores. EL2 zeroes the smallest block of all
+//! cores for those that use DC ZVA regardless.
+//!
+//! CLIDR_EL1, CCSIDR_EL1 and CSSELR_EL1 trap along with CTR_EL0 and pass
+//! through: set/way geometry is the core's own either way.
+
+use super::{
+    exit::{ExitAction, GuestEl, SysRegAccess},
+    fault,
+    guest_mem::{GuestMemError, GuestMemory},
+    hal::{sysregs, SysReg, SysRegBackend},
+    hyper, stage2,
+    vcpu::Vcpu,
+};
+use crate::sync::SpinLock;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// (op0, op1, CRn, CRm, op2) of the trapped accesses.
+type Encoding = (u8, u8, u8, u8, u8);
+const CTR_EL0: Encoding = (3, 3, 0, 0, 1);
+const CLIDR_EL1: Encoding = (3, 1, 0, 0, 1);
+const CCSIDR_EL1: Encoding = (3, 1, 0, 0, 0);
+const CSSELR_EL1: Encoding = (3, 2, 0, 0, 0);
+const DC_ZVA: Encoding = (1, 3, 7, 4, 1);
+
+const CTR_RES1: u64 = 1 << 31;
+const CTR_IMINLINE: u32 = 0;
+const CTR_L1IP_MASK: u64 = 0b11 << 14;
+const CTR_DMINLINE: u32 = 16;
+const CTR_ERG: u32 = 20;
+const CTR_CWG: u32 = 24;
+const CTR_IDC: u64 = 1 << 28;
+const CTR_DIC: u64 = 1 << 29;
+const DCZID_BS_MASK: u64 = 0xf;
+const DCZID_DZP: u64 = 1 << 4;
+
+const PAR_F: u64 = 1;
+// PAR_EL1.S: the fault was on the Stage-2 walk of the Stage-1 tables.
+const PAR_S: u64 = 1 << 9;
+const PAR_PA_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+const ZEROS: [u8; 64] = [0; 64];
+
+/// CTR_EL0 as guests read it, and DCZID_EL0 as EL2 emulates DC ZVA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheIds {
+    pub ctr: u64,
+    pub dczid: u64,
+}
+
+fn field(val: u64, shift: u32) -> u64 {
+    (val >> shift) & 0xf
+}
+
+impl CacheIds {
+    /// Values that hold on the cores of both `self` and `other`: the
+    /// smallest lines and zeroing block, the largest granules, coherence
+    /// only where both have it. L1Ip stays that of `self`.
+    pub fn merge(self, other: Self) -> Self {
+        let (a, b) = (self.ctr, other.ctr);
+        let min = |shift| field(a, shift).min(field(b, shift)) << shift;
+        let max = |shift| field(a, shift).max(field(b, shift)) << shift;
+        let ctr = CTR_RES1
+            | (a & CTR_L1IP_MASK)
+            | min(CTR_IMINLINE)
+            | min(CTR_DMINLINE)
+            | max(CTR_ERG)
+            | max(CTR_CWG)
+            | (a & b & (CTR_IDC | CTR_DIC));
+        let bs = (self.dczid & DCZID_BS_MASK).min(other.dczid & DCZID_BS_MASK);
+        let dczid = bs | ((self.dczid | other.dczid) & DCZID_DZP);
+        Self { ctr, dczid }
+    }
+
+    /// Bytes DC ZVA zeroes, or `None` if it's prohibited.
+    pub fn zva_block(&self) -> Option<u64> {
+        (self.dczid & DCZID_DZP == 0).then_some(4 << (self.dczid & DCZID_BS_MASK))
+    }
+
+    fn live() -> Self {
+        Self {
+            ctr: hyper::read_ctr_el0(),
+            dczid: hyper::read_dczid_el0(),
+        }
+    }
+}
+
+// Folded values, written by `cpu_init` only; read at EL2.
+static CTR: AtomicU64 = AtomicU64::new(0);
+static DCZID: AtomicU64 = AtomicU64::new(0);
+static MISMATCHED: AtomicBool = AtomicBool::new(false);
+static INIT: SpinLock<Option<CacheIds>> = SpinLock::new(None);
+
+/// Fold the registers of the calling core in.
+pub(crate) fn cpu_init() -> Result<(), &'static str> {
+    let own = CacheIds::live();
+    let mut init = INIT.irqsave_lock();
+    let ids = match *init {
+        Some(ids) => {
+            if ids != own {
+                MISMATCHED.store(true, Ordering::Release);
+            }
+            ids.merge(own)
+        }
+        None => own,
+    };
+    *init = Some(ids);
+    CTR.store(ids.ctr, Ordering::Release);
+    DCZID.store(ids.dczid, Ordering::Release);
+    Ok(())
+}
+
+/// Whether the cores initialized so far disagree on their cache geometry.
+pub fn mismatched() -> bool {
+    MISMATCHED.load(Ordering::Acquire)
+}
+
+/// What guests read; the calling core's own values before any core folded
+/// its registers in.
+pub fn ids() -> CacheIds {
+    match CTR.load(Ordering::Acquire) {
+        0 => CacheIds::live(),
+        ctr => CacheIds {
+            ctr,
+            dczid: DCZID.load(Ordering::Acquire),
+        },
+    }
+}
+
+/// Emulate a trapped cache ID register access or DC ZVA, or `None` if
+/// `access` is neither. Runs at EL2.
+#[link_section = ".hyp.text"]
+pub fn emulate(vcpu: &mut Vcpu, access: SysRegAccess) -> Option<ExitAction> {
+    let encoding = (access.op0, access.op1, access.crn, access.crm, access.op2);
+    let rt = access.rt as usize;
+    let val = match (encoding, access.read) {
+        (CTR_EL0, true) => ids().ctr,
+        (CLIDR_EL1, true) => sysregs().read(SysReg::ClidrEl1),
+        (CCSIDR_EL1, true) => sysregs().read(SysReg::CcsidrEl1),
+        (CSSELR_EL1, true) => sysregs().read(SysReg::CsselrEl1),
+        (CSSELR_EL1, false) => {
+            sysregs().write(SysReg::CsselrEl1, read_x(vcpu, rt));
+            vcpu.advance_pc();
+            return Some(ExitAction::Resume);
+        }
+        (DC_ZVA, false) => return zero_block(vcpu, read_x(vcpu, rt)),
+        _ => return None,
+    };
+    if rt != 31 {
+        vcpu.regs.x[rt] = val;
+    }
+    vcpu.advance_pc();
+    Some(ExitAction::Resume)
+}
+
+fn read_x(vcpu: &Vcpu, rt: usize) -> u64 {
+    match rt {
+        31 => 0,
+        rt => vcpu.regs.x[rt],
+    }
+}
+
+// DC ZVA of the guest at `va`, with the permissions of the level it ran at.
+#[link_section = ".hyp.text"]
+fn zero_block(vcpu: &mut Vcpu, va: u64) -> Option<ExitAction> {
+    // Prohibited zeroing is UNDEFINED, which nothing here injects.
+    let block = ids().zva_block()?;
+    let va = va & !(block - 1);
+    let el0 = GuestEl::of(vcpu.regs.spsr) == GuestEl::El0;
+    let par = sysregs().translate_el1_write(va, el0);
+    if par & PAR_F != 0 {
+        if par & PAR_S != 0 {
+            fault::inject_data_abort(vcpu, va, true);
+        } else {
+            fault::inject_stage1_fault(vcpu, va, true, par >> 1);
+        }
+        return Some(ExitAction::Resume);
+    }
+    let ipa = (par & PAR_PA_MASK) | (va & 0xfff);
+    let mut done = 0;
+    while done < block {
+        let len = (block - done).min(ZEROS.len() as u64);
+        let res = stage2::with_vm(vcpu.vm_id, |s2| {
+            s2.write(ipa + done, &ZEROS[..len as usize])
+        })
+        .unwrap_or(Err(GuestMemError::NoSuchVm));
+        match res {
+            // A block never straddles a page, and lazy RAM reads as zero.
+            Ok(()) | Err(GuestMemError::NotPopulated(_)) => {}
+            Err(_) => {
+                fault::inject_data_abort(vcpu, va, true);
+                return Some(ExitAction::Resume);
+            }
+        }
+        done += len;
+    }
+    vcpu.advance_pc();
+    Some(ExitAction::Resume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_cache_ids_merge() {
+        // 64-byte lines and ZVA blocks; the other core has 32-byte I-lines
+        // and ZVA blocks, a larger exclusives granule and no IDC.
+        let big = CacheIds {
+            ctr: 0x9444_c004 | CTR_IDC,
+            dczid: 0x4,
+        };
+        let little = CacheIds {
+            ctr: 0x8454_c003,
+            dczid: 0x3,
+        };
+        let ids = big.merge(little);
+        assert_eq!(field(ids.ctr, CTR_IMINLINE), 3);
+        assert_eq!(field(ids.ctr, CTR_DMINLINE), 4);
+        assert_eq!(field(ids.ctr, CTR_ERG), 5);
+        assert_eq!(field(ids.ctr, CTR_CWG), 4);
+        assert_eq!(ids.ctr & CTR_IDC, 0);
+        assert_eq!(ids.ctr & CTR_L1IP_MASK, big.ctr & CTR_L1IP_MASK);
+        assert_eq!(ids.zva_block(), Some(32));
+        assert_eq!(big.merge(big), big);
+
+        let prohibited = CacheIds {
+            dczid: DCZID_DZP | 0x4,
+            ..big
+        };
+        assert_eq!(big.merge(prohibited).zva_block(), None);
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Regression check for counter reads trapping to EL2. Guests read
+//! CNTVCT_EL0 and CNTFRQ_EL0 for every timestamp, so a configuration that
+//! traps them costs an exit per clock read. The probe runs a guest of one
+//! page of hypervisor code that reads both registers in a loop, timing the
+//! loop with the virtual counter, then shuts down with the elapsed count
+//! in x0. The run passes when the guest took no trap besides the shutdown
+//! and the loop stayed under a bound.
+//!
+//! CNTKCTL_EL1 only decides whether guest userspace may read the counters
+//! and traps to the guest kernel, never to EL2, so it stays the guest's.
+
+use super::{
+    abi::GUEST_HVC_SHUTDOWN,
+    exit::{ExitCode, GuestEl},
+    policy::ExitClass,
+    profile::GuestProfile,
+    stage2::PAGE_SIZE,
+    status,
+    vcpu::{self, vcpu_manager, VcpuError},
+    vm::{vm_manager, BuildError, VmBuilder},
+};
+use crate::sync::SpinLock;
+use core::ptr::addr_of;
+
+/// Iterations of the probe loop, each reading CNTVCT_EL0 and CNTFRQ_EL0.
+pub const ITERATIONS: u64 = 1024;
+/// Counter reads one run makes.
+pub const READS: u64 = ITERATIONS * 2;
+/// IPA the probe page is mapped and entered at.
+const PROBE_IPA: u64 = 0x4000_0000;
+
+core::arch::global_asm!(
+    "
+.pushsection .rodata.guest_clock_probe, \"a\"
+.balign 4096
+.global __guest_clock_probe
+__guest_clock_probe:
+    mov x1, #{iterations}
+    isb
+    mrs x2, cntvct_el0
+1:
+    mrs x3, cntvct_el0
+    mrs x3, cntfrq_el0
+    subs x1, x1, #1
+    b.ne 1b
+    isb
+    mrs x0, cntvct_el0
+    sub x0, x0, x2
+    hvc #{shutdown}
+    b .
+.balign 4096
+.global __guest_clock_probe_end
+__guest_clock_probe_end:
+.popsection
+",
+    iterations = const ITERATIONS,
+    shutdown = const GUEST_HVC_SHUTDOWN,
+);
+
+// Cost measured by the last run that got as far as measuring.
+static LAST: SpinLock<Option<ClockCost>> = SpinLock::new(None);
+
+extern "C" {
+    static __guest_clock_probe: u8;
+    static __guest_clock_probe_end: u8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeError {
+    Build(&'static str),
+    Run(VcpuError),
+    /// The guest didn't shut down, e.g. a trapped read faulted it.
+    Ended(ExitCode),
+    /// The guest took this many traps besides its shutdown call.
+    Trapped(u64),
+    /// The loop took `cycles`, more than the bound.
+    OverBound {
+        cycles: u64,
+        bound: u64,
+    },
+}
+
+/// What one probe run measured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClockCost {
+    /// Virtual counter cycles the `READS` reads took.
+    pub cycles: u64,
+    /// Traps besides the shutdown call.
+    pub traps: u64,
+}
+
+impl ClockCost {
+    /// Whether counter reads stayed in the guest and the loop took no more
+    /// than `bound` cycles.
+    pub fn check(self, bound: u64) -> Result<Self, ProbeError> {
+        if self.traps != 0 {
+            return Err(ProbeError::Trapped(self.traps));
+        }
+        if self.cycles > bound {
+            return Err(ProbeError::OverBound {
+                cycles: self.cycles,
+                bound,
+            });
+        }
+        Ok(self)
+    }
+}
+
+/// Host physical address of the probe page.
+pub fn pa() -> u64 {
+    addr_of!(__guest_clock_probe) as u64
+}
+
+/// Run the probe as VM `vm_id`, which must not exist, on the calling
+/// thread and check it against `bound` cycles. The VM is destroyed like
+/// any other afterwards, its status forgotten.
+pub fn run(vm_id: usize, bound: u64) -> Result<ClockCost, ProbeError> {
+    let vcpus = VmBuilder::new(vm_id, GuestProfile::BareMetalTest.config())
+        .rom(PROBE_IPA, pa(), PAGE_SIZE, true)
+        .boot_vcpu(PROBE_IPA, 0)
+        .build()
+        .map_err(|e| match e {
+            BuildError::Invalid(_) => ProbeError::Build("invalid description"),
+            BuildError::Failed(e) => ProbeError::Build(e),
+        })?;
+    let id = vcpus[0];
+    let ret = vcpu::run_vcpu(id);
+    let cost = vcpu_manager().with_vcpu(id, |v| {
+        let traps = &v.stats.traps[GuestEl::El1 as usize];
+        ClockCost {
+            cycles: v.regs.x[0],
+            traps: v.stats.traps_from(GuestEl::El1) - traps[ExitClass::Hvc as usize],
+        }
+    });
+    vm_manager().destroy(vm_id).map_err(ProbeError::Run)?;
+    status::clear(vm_id);
+    match ret.map_err(ProbeError::Run)? {
+        ExitCode::Shutdown(status::SUCCESS) => {}
+        code => return Err(ProbeError::Ended(code)),
+    }
+    let cost = cost.ok_or(ProbeError::Run(VcpuError::InvalidId))?;
+    *LAST.irqsave_lock() = Some(cost);
+    log::info!(
+        "[virt] clock probe: {} reads in {} cycles, {} traps",
+        READS,
+        cost.cycles,
+        cost.traps
+    );
+    cost.check(bound)
+}
+
+/// What the last run measured.
+pub fn last() -> Option<ClockCost> {
+    *LAST.irqsave_read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_probe_page() {
+        let end = addr_of!(__guest_clock_probe_end) as u64;
+        assert_eq!(pa() % PAGE_SIZE, 0);
+        assert_eq!(end - pa(), PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_probe_check() {
+        let cost = ClockCost {
+            cycles: 40,
+            traps: 0,
+        };
+        assert_eq!(cost.check(100), Ok(cost));
+        assert_eq!(
+            cost.check(10),
+            Err(ProbeError::OverBound {
+                cycles: 40,
+                bound: 10
+            })
+        );
+        let trapped = ClockCost {
+            cycles: 40,
+            traps: READS,
+        };
+        assert_eq!(trapped.check(100), Err(ProbeError::Trapped(READS)));
+    }
+
+    #[test]
+    fn test_probe_run() {
+        let vm_id = test_vm_id();
+        let cost = run(vm_id, u64::MAX).unwrap();
+        assert_eq!(cost.traps, 0);
+        assert_eq!(last(), Some(cost));
+        assert!(!vm_manager().contains(vm_id));
+        assert_eq!(vcpu_manager().ids_of(vm_id).count(), 0);
+        assert_eq!(status::of(vm_id), None);
+        // The id is free again.
+        assert!(run(vm_id, u64::MAX).is_ok());
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest console output in files. Each VM logged this way gets
+//! `DIR`/vmN.console on the host VFS, so the output of long soak runs can
+//! be read per VM instead of interleaved in the host log. Its console is
+//! captured, see `vconsole::capture`, and a writer thread appends what was
+//! kept every `FLUSH_TICKS`; with `LogConfig::tee` the output goes to the
+//! host console as well. With no VM logged the writer sleeps until one is.
+//!
+//! A file that reached `LogConfig::max_size` is rotated: vmN.console
+//! becomes vmN.console.1, the older ones move up by one and the oldest
+//! beyond `LogConfig::keep` is deleted. A file that can't be moved stops
+//! the rotation and vmN.console is kept as it is, so a failing VFS costs
+//! new output rather than the old. Output a full capture buffer drops
+//! between two flushes is lost.
+
+use super::vconsole;
+use crate::{
+    config,
+    scheduler::InsertToEnd,
+    sync::{
+        event_flags::{EventFlags, EventFlagsMode},
+        SpinLock,
+    },
+    thread::{self, Entry},
+    time::Tick,
+    vfs::syscalls,
+};
+use alloc::{boxed::Box, collections::BTreeMap, ffi::CString, format, string::String, vec::Vec};
+
+/// Directory the logs are written to.
+pub const DIR: &str = "/var/log";
+/// Smallest file size a log rotates at.
+pub const MIN_SIZE: usize = 4096;
+const FLUSH_TICKS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Rotate once the file holds this many bytes.
+    pub max_size: usize,
+    /// Rotated files kept, vmN.console.1 the newest.
+    pub keep: usize,
+    /// Also write the output to the host console.
+    pub tee: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 1 << 20,
+            keep: 3,
+            tee: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    /// `max_size` is under `MIN_SIZE`.
+    TooSmall,
+}
+
+/// How a VM's log fares.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LogStatus {
+    pub config: Option<LogConfig>,
+    /// Bytes in the current file.
+    pub size: usize,
+    pub rotations: u64,
+    /// Error the last flush failed with, as a negative errno. Cleared by
+    /// the next one that succeeds.
+    pub error: Option<i32>,
+}
+
+// What the host asked for, read by the writer on every flush.
+static CONFIGS: SpinLock<BTreeMap<usize, LogConfig>> = SpinLock::new(BTreeMap::new());
+// What the writer published.
+static STATUS: SpinLock<BTreeMap<usize, LogStatus>> = SpinLock::new(BTreeMap::new());
+// Set when CONFIGS changes, to wake an idle writer.
+static CHANGED: EventFlags = EventFlags::new();
+
+fn wake_writer() {
+    let _ = CHANGED.set(1);
+}
+
+/// Log the console of VM `vm_id` under `config`, or change the config of
+/// its log.
+pub fn enable(vm_id: usize, config: LogConfig) -> Result<(), LogError> {
+    if config.max_size < MIN_SIZE {
+        return Err(LogError::TooSmall);
+    }
+    vconsole::capture(vm_id, config.tee);
+    CONFIGS.irqsave_lock().insert(vm_id, config);
+    wake_writer();
+    Ok(())
+}
+
+/// Capture the console of the new VM `vm_id` again if it is logged; the
+/// builder dropped what the previous VM with its id left.
+pub(crate) fn vm_created(vm_id: usize) {
+    if let Some(config) = CONFIGS.irqsave_read().get(&vm_id) {
+        vconsole::capture(vm_id, config.tee);
+    }
+}
+
+/// Stop logging the console of VM `vm_id`. The writer flushes what is
+/// left and gives the console back to the host.
+pub fn disable(vm_id: usize) {
+    CONFIGS.irqsave_lock().remove(&vm_id);
+    wake_writer();
+}
+
+/// The logs, by VM.
+pub fn status() -> Vec<(usize, LogStatus)> {
+    let configs = CONFIGS.irqsave_read().clone();
+    let status = STATUS.irqsave_read();
+    configs
+        .into_iter()
+        .map(|(vm_id, config)| {
+            let st = status.get(&vm_id).copied().unwrap_or_default();
+            (
+                vm_id,
+                LogStatus {
+                    config: Some(config),
+                    ..st
+                },
+            )
+        })
+        .collect()
+}
+
+/// File the console of VM `vm_id` is logged to, `n` rotations ago.
+pub fn path(vm_id: usize, n: usize) -> String {
+    match n {
+        0 => format!("{}/vm{}.console", DIR, vm_id),
+        n => format!("{}/vm{}.console.{}", DIR, vm_id, n),
+    }
+}
+
+fn c_path(path: &str) -> CString {
+    // Paths are built from numbers, no NUL in them.
+    CString::new(path).unwrap()
+}
+
+fn check(ret: isize) -> Result<usize, i32> {
+    if ret < 0 {
+        Err(ret as i32)
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+fn open(path: &str, flags: i32) -> Result<i32, i32> {
+    let path = c_path(path);
+    check(syscalls::open(path.as_ptr(), libc::O_WRONLY | libc::O_CREAT | flags, 0o644) as isize)
+        .map(|fd| fd as i32)
+}
+
+// The VFS has no rename, so this links `to` and unlinks `from`. A `from`
+// that doesn't exist is nothing to move; on any other failure both names
+// are left as they were.
+fn rename(from: &str, to: &str) -> Result<(), i32> {
+    let (from, to) = (c_path(from), c_path(to));
+    match syscalls::link(from.as_ptr(), to.as_ptr()) {
+        0 => {}
+        e if e == -libc::ENOENT => return Ok(()),
+        e => return Err(e),
+    }
+    match syscalls::unlink(from.as_ptr()) {
+        0 => Ok(()),
+        e => {
+            syscalls::unlink(to.as_ptr());
+            Err(e)
+        }
+    }
+}
+
+struct Log {
+    vm_id: usize,
+    config: LogConfig,
+    fd: i32,
+    size: usize,
+    rotations: u64,
+}
+
+impl Log {
+    fn open(vm_id: usize, config: LogConfig) -> Result<Self, i32> {
+        make_dirs();
+        let fd = open(&path(vm_id, 0), 0)?;
+        let size = check(syscalls::lseek(fd, 0, libc::SEEK_END) as isize)?;
+        Ok(Self {
+            vm_id,
+            config,
+            fd,
+            size,
+            rotations: 0,
+        })
+    }
+
+    fn append(&mut self, mut bytes: &[u8]) -> Result<(), i32> {
+        while !bytes.is_empty() {
+            if self.size >= self.config.max_size {
+                self.rotate()?;
+            }
+            let room = self.config.max_size - self.size;
+            let chunk = &bytes[..bytes.len().min(room)];
+            let n = check(syscalls::write(self.fd, chunk.as_ptr(), chunk.len()))?;
+            if n == 0 {
+                return Err(-libc::ENOSPC);
+            }
+            self.size += n;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), i32> {
+        let keep = self.config.keep;
+        if keep > 0 {
+            match syscalls::unlink(c_path(&path(self.vm_id, keep)).as_ptr()) {
+                e if e == 0 || e == -libc::ENOENT => {}
+                e => return Err(e),
+            }
+            for n in (0..keep).rev() {
+                rename(&path(self.vm_id, n), &path(self.vm_id, n + 1))?;
+            }
+        }
+        syscalls::close(self.fd);
+        self.fd = open(&path(self.vm_id, 0), libc::O_TRUNC)?;
+        self.size = 0;
+        self.rotations += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), i32> {
+        match vconsole::take_output(self.vm_id) {
+            Some(bytes) => self.append(&bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        syscalls::close(self.fd);
+    }
+}
+
+fn make_dirs() {
+    let mut dir = String::new();
+    for part in DIR.split('/').filter(|p| !p.is_empty()) {
+        dir.push('/');
+        dir.push_str(part);
+        // Exists already, or the open reports the problem.
+        syscalls::mkdir(c_path(&dir).as_ptr(), 0o755);
+    }
+}
+
+fn run_writer() {
+    let mut logs: BTreeMap<usize, Log> = BTreeMap::new();
+    loop {
+        let timeout = if logs.is_empty() {
+            Tick::MAX
+        } else {
+            Tick(FLUSH_TICKS)
+        };
+        let _ = CHANGED.wait::<InsertToEnd>(1, EventFlagsMode::ANY, timeout);
+        let configs = CONFIGS.irqsave_read().clone();
+        let gone: Vec<usize> = logs
+            .keys()
+            .filter(|vm_id| !configs.contains_key(vm_id))
+            .copied()
+            .collect();
+        for vm_id in gone {
+            if let Some(mut log) = logs.remove(&vm_id) {
+                let _ = log.flush();
+            }
+            vconsole::release(vm_id);
+            STATUS.irqsave_lock().remove(&vm_id);
+        }
+        for (vm_id, config) in configs {
+            let result = match logs.get_mut(&vm_id) {
+                Some(log) => {
+                    log.config = config;
+                    log.flush()
+                }
+                None => Log::open(vm_id, config).and_then(|mut log| {
+                    let result = log.flush();
+                    logs.insert(vm_id, log);
+                    result
+                }),
+            };
+            let mut status = STATUS.irqsave_lock();
+            let st = status.entry(vm_id).or_default();
+            if let Some(log) = logs.get(&vm_id) {
+                st.size = log.size;
+                st.rotations = log.rotations;
+            }
+            st.error = result.err();
+        }
+    }
+}
+
+pub(crate) fn init() {
+    CHANGED.init(0);
+    thread::Builder::new(Entry::Closure(Box::new(run_writer)))
+        .set_priority(config::VIRT_LOG_THREAD_PRIORITY)
+        .start();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_console_log_paths() {
+        assert_eq!(path(2, 0), "/var/log/vm2.console");
+        assert_eq!(path(2, 3), "/var/log/vm2.console.3");
+        let small = LogConfig {
+            max_size: MIN_SIZE - 1,
+            ..LogConfig::default()
+        };
+        assert_eq!(enable(usize::MAX, small), Err(LogError::TooSmall));
+        assert!(status().is_empty());
+    }
+
+    fn file_size(path: &str) -> Option<usize> {
+        let fd = syscalls::open(c_path(path).as_ptr(), libc::O_RDONLY, 0);
+        if fd < 0 {
+            return None;
+        }
+        let size = syscalls::lseek(fd, 0, libc::SEEK_END) as usize;
+        syscalls::close(fd);
+        Some(size)
+    }
+
+    #[test]
+    fn test_console_log_rotate() {
+        let vm_id = test_vm_id();
+        let config = LogConfig {
+            max_size: MIN_SIZE,
+            keep: 2,
+            tee: false,
+        };
+        let mut log = Log::open(vm_id, config).unwrap();
+        let bytes = [b'x'; 3 * MIN_SIZE + 10];
+        assert_eq!(log.append(&bytes), Ok(()));
+        assert_eq!(log.rotations, 3);
+        assert_eq!(log.size, 10);
+        drop(log);
+        assert_eq!(file_size(&path(vm_id, 0)), Some(10));
+        assert_eq!(file_size(&path(vm_id, 1)), Some(MIN_SIZE));
+        assert_eq!(file_size(&path(vm_id, 2)), Some(MIN_SIZE));
+        assert_eq!(file_size(&path(vm_id, 3)), None);
+
+        // Reopened, the log carries on where it was.
+        let mut log = Log::open(vm_id, config).unwrap();
+        assert_eq!(log.size, 10);
+        assert_eq!(log.append(&bytes[..MIN_SIZE]), Ok(()));
+        assert_eq!(log.rotations, 1);
+        drop(log);
+        assert_eq!(file_size(&path(vm_id, 1)), Some(MIN_SIZE));
+        for n in 0..=config.keep {
+            syscalls::unlink(c_path(&path(vm_id, n)).as_ptr());
+        }
+    }
+}
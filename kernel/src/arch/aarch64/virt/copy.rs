@@ -0,0 +1,349 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bulk copies between a guest and the host or another VM, for transfers
+//! too large to push through MMIO a word at a time. The guest describes
+//! the copy in a scatter-gather list on a page it granted to the host and
+//! submits it with `GUEST_HVC_COPY`; a worker thread does the copy and
+//! raises the completion interrupt the guest asked for.
+//!
+//! The list page starts with a header: the number of segments as a u32 at
+//! offset 0, and a u32 status at offset 4 the host publishes once the
+//! copy is over, `STATUS_DONE` or `STATUS_FAILED` with the index of the
+//! segment that failed. Segments of `SEGMENT_SIZE` bytes follow from
+//! `HEADER_SIZE`, each naming a guest page granted to the host, an offset
+//! in it and a length, and where the bytes go to or come from. That's a
+//! position in the VM's host buffer, which it gets with
+//! `VmBuilder::copy_buffer` and host code reaches with `host_buffer`, or a
+//! page another VM granted to this one.
+//!
+//! Only granted pages are touched, and they stay granted until the copy
+//! is over. EL2 doesn't look at the list: the hypercall exits to the
+//! vCPU's run loop, which checks and queues it.
+
+use super::{
+    abi::{self, ERR_BUSY, ERR_INVALID, ERR_NOT_FOUND},
+    grant::{self, GrantError, GrantMapping, GrantRef},
+    identity,
+    spi::FIRST_SPI,
+    stage2::PAGE_SIZE,
+    vcpu::Vcpu,
+    vgic::MAX_INTID,
+    vlog::vlog,
+    workers::{self, Completion},
+};
+use crate::sync::SpinLock;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec};
+
+pub use super::abi::{COPY_TO_GUEST, TARGET_HOST};
+
+pub const HEADER_SIZE: usize = abi::COPY_HEADER_SIZE;
+pub const SEGMENT_SIZE: usize = abi::COPY_SEGMENT_SIZE;
+/// Segments that fit on the list page.
+pub const MAX_SEGMENTS: usize = (PAGE_SIZE as usize - HEADER_SIZE) / SEGMENT_SIZE;
+const STATUS_OFFSET: usize = abi::COPY_STATUS_OFFSET;
+/// List status once every segment is copied.
+pub const STATUS_DONE: u32 = abi::COPY_STATUS_DONE;
+/// List status once a segment failed, or'ed with its index. The segments
+/// before it are copied.
+pub const STATUS_FAILED: u32 = abi::COPY_STATUS_FAILED;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyError {
+    /// Unknown flags, a segment count the page doesn't hold or a
+    /// completion interrupt that isn't an SPI.
+    Invalid,
+    /// The VM has no host buffer.
+    NoTarget,
+    /// Too many jobs queued already.
+    QueueFull,
+    Grant(GrantError),
+    /// The segment doesn't fit its page or the host buffer.
+    OutOfRange,
+}
+
+impl CopyError {
+    /// Value returned to the guest in x0.
+    pub fn code(self) -> u64 {
+        let code: i64 = match self {
+            CopyError::Invalid | CopyError::OutOfRange => ERR_INVALID,
+            CopyError::NoTarget => ERR_NOT_FOUND,
+            CopyError::QueueFull => ERR_BUSY,
+            CopyError::Grant(e) => return e.code(),
+        };
+        code as u64
+    }
+}
+
+impl From<GrantError> for CopyError {
+    fn from(e: GrantError) -> Self {
+        CopyError::Grant(e)
+    }
+}
+
+/// One entry of the list, as the guest lays it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// Guest page granted to the host.
+    pub gref: u64,
+    /// Position in the host buffer, or the grant of the target VM's page.
+    pub target: u64,
+    pub offset: u32,
+    /// Offset in the target VM's page; unused for the host buffer.
+    pub target_offset: u32,
+    pub len: u32,
+}
+
+impl Segment {
+    pub fn decode(raw: &[u8; SEGMENT_SIZE]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(raw[at..at + 8].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        Self {
+            gref: u64_at(0),
+            target: u64_at(8),
+            offset: u32_at(16),
+            target_offset: u32_at(20),
+            len: u32_at(24),
+        }
+    }
+}
+
+/// Memory host code exchanges with a VM through the copy service.
+pub struct HostBuffer(SpinLock<Box<[u8]>>);
+
+impl HostBuffer {
+    pub fn len(&self) -> usize {
+        self.0.irqsave_lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run `f` on the contents.
+    pub fn with<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        f(&mut self.0.irqsave_lock())
+    }
+}
+
+static HOST_BUFFERS: SpinLock<BTreeMap<usize, Arc<HostBuffer>>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` a zeroed host buffer of `len` bytes to copy to and
+/// from, replacing the one it had.
+pub fn set_host_buffer(vm_id: usize, len: usize) -> Arc<HostBuffer> {
+    let buf = Arc::new(HostBuffer(SpinLock::new(vec![0; len].into_boxed_slice())));
+    HOST_BUFFERS.irqsave_lock().insert(vm_id, buf.clone());
+    buf
+}
+
+/// The host buffer of VM `vm_id`, if it has one.
+pub fn host_buffer(vm_id: usize) -> Option<Arc<HostBuffer>> {
+    HOST_BUFFERS.irqsave_lock().get(&vm_id).cloned()
+}
+
+/// Drop the host buffer of VM `vm_id`. Copies already queued keep it.
+pub fn clear_host_buffer(vm_id: usize) {
+    HOST_BUFFERS.irqsave_lock().remove(&vm_id);
+}
+
+enum Target {
+    Host(Arc<HostBuffer>),
+    Vm(usize),
+}
+
+struct Job {
+    vm_id: usize,
+    list: GrantMapping,
+    count: usize,
+    target: Target,
+    to_guest: bool,
+}
+
+/// Take the copy hypercall `vcpu` exited with: list grant in x0, target
+/// in x1, flags in x2, completion interrupt in x3. Leaves 0 in x0 once the
+/// copy is queued. Called by the vCPU's run loop.
+pub fn submit(vcpu: &mut Vcpu) {
+    let [list, target, flags, intid] = [
+        vcpu.regs.x[0],
+        vcpu.regs.x[1],
+        vcpu.regs.x[2],
+        vcpu.regs.x[3],
+    ];
+    let done = Completion {
+        vcpu_id: vcpu.id,
+        intid: intid as u32,
+    };
+    let queued = if (FIRST_SPI as u64..MAX_INTID as u64).contains(&intid) {
+        queue(vcpu.vm_id, GrantRef(list), target, flags, done)
+    } else {
+        Err(CopyError::Invalid)
+    };
+    vcpu.regs.x[0] = match queued {
+        Ok(()) => 0,
+        Err(e) => {
+            vlog!(
+                Vcpu,
+                Debug,
+                "{} copy not queued: {:?}",
+                identity::tag(vcpu.vm_id),
+                e
+            );
+            e.code()
+        }
+    };
+}
+
+fn queue(
+    vm_id: usize,
+    list: GrantRef,
+    target: u64,
+    flags: u64,
+    done: Completion,
+) -> Result<(), CopyError> {
+    if flags & !COPY_TO_GUEST != 0 {
+        return Err(CopyError::Invalid);
+    }
+    let list = grant::map(vm_id, list)?;
+    if !list.is_writable() {
+        return Err(CopyError::Grant(GrantError::Denied));
+    }
+    let count = list.load_index(0)? as usize;
+    if count > MAX_SEGMENTS {
+        return Err(CopyError::Invalid);
+    }
+    let target = match target {
+        TARGET_HOST => Target::Host(host_buffer(vm_id).ok_or(CopyError::NoTarget)?),
+        // The segments' grants tell whether the VM exists.
+        peer => Target::Vm(peer as usize),
+    };
+    let job = Job {
+        vm_id,
+        list,
+        count,
+        target,
+        to_guest: flags & COPY_TO_GUEST != 0,
+    };
+    workers::submit(vm_id, move || {
+        let status = match run(&job) {
+            Ok(()) => STATUS_DONE,
+            Err((index, e)) => {
+                vlog!(
+                    Vcpu,
+                    Debug,
+                    "{} copy segment {} failed: {:?}",
+                    identity::tag(job.vm_id),
+                    index,
+                    e
+                );
+                STATUS_FAILED | index as u32
+            }
+        };
+        job.list.store_index(STATUS_OFFSET, status).ok()?;
+        Some(done)
+    })
+    .map_err(|_| CopyError::QueueFull)
+}
+
+// Copy the segments of `job` in order, up to the first that fails.
+fn run(job: &Job) -> Result<(), (usize, CopyError)> {
+    let mut bounce = vec![0; PAGE_SIZE as usize];
+    for index in 0..job.count {
+        let mut raw = [0; SEGMENT_SIZE];
+        job.list
+            .read(HEADER_SIZE + index * SEGMENT_SIZE, &mut raw)
+            .map_err(|e| (index, e.into()))?;
+        let seg = Segment::decode(&raw);
+        copy_segment(job, &seg, &mut bounce).map_err(|e| (index, e))?;
+    }
+    Ok(())
+}
+
+fn copy_segment(job: &Job, seg: &Segment, bounce: &mut [u8]) -> Result<(), CopyError> {
+    let len = seg.len as usize;
+    let bounce = bounce.get_mut(..len).ok_or(CopyError::OutOfRange)?;
+    let guest = grant::map(job.vm_id, GrantRef(seg.gref))?;
+    let offset = seg.offset as usize;
+    match &job.target {
+        Target::Host(buf) => {
+            let at = usize::try_from(seg.target).map_err(|_| CopyError::OutOfRange)?;
+            buf.with(|bytes| {
+                let range = at..at.checked_add(len).ok_or(CopyError::OutOfRange)?;
+                let host = bytes.get_mut(range).ok_or(CopyError::OutOfRange)?;
+                if job.to_guest {
+                    guest.write(offset, host)?;
+                } else {
+                    guest.read(offset, host)?;
+                }
+                Ok(())
+            })
+        }
+        &Target::Vm(peer) => {
+            let other = grant::map_for_vm(peer, GrantRef(seg.target), job.vm_id)?;
+            let target_offset = seg.target_offset as usize;
+            if job.to_guest {
+                other.read(target_offset, bounce)?;
+                guest.write(offset, bounce)?;
+            } else {
+                guest.read(offset, bounce)?;
+                other.write(target_offset, bounce)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_copy_segment_decode() {
+        let mut raw = [0; SEGMENT_SIZE];
+        raw[0..8].copy_from_slice(&0x1_0003u64.to_le_bytes());
+        raw[8..16].copy_from_slice(&0x2000u64.to_le_bytes());
+        raw[16..20].copy_from_slice(&0x80u32.to_le_bytes());
+        raw[20..24].copy_from_slice(&0x10u32.to_le_bytes());
+        raw[24..28].copy_from_slice(&0x400u32.to_le_bytes());
+        assert_eq!(
+            Segment::decode(&raw),
+            Segment {
+                gref: 0x1_0003,
+                target: 0x2000,
+                offset: 0x80,
+                target_offset: 0x10,
+                len: 0x400,
+            }
+        );
+        assert_eq!(MAX_SEGMENTS, 127);
+        assert_eq!(CopyError::QueueFull.code(), -5i64 as u64);
+        assert_eq!(
+            CopyError::Grant(GrantError::NoSuchGrant).code(),
+            GrantError::NoSuchGrant.code()
+        );
+    }
+
+    #[test]
+    fn test_copy_host_buffer() {
+        let vm_id = test_vm_id();
+        let buf = set_host_buffer(vm_id, 16);
+        assert_eq!(buf.len(), 16);
+        buf.with(|bytes| bytes[3] = 7);
+        let held = host_buffer(vm_id).unwrap();
+        assert_eq!(held.with(|bytes| bytes[3]), 7);
+        clear_host_buffer(vm_id);
+        assert!(host_buffer(vm_id).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Doorbells: VM events a host service thread blocks on. Each VM has
+//! `MAX_DOORBELLS` of them. A guest rings one with `GUEST_HVC_DOORBELL`,
+//! an emulated device by exiting with `ExitCode::Doorbell`, and host code
+//! with `ring`. A `DoorbellSet` collects doorbells of any number of VMs,
+//! each bound to a token bit, so one thread serves them all with a single
+//! blocking `wait` instead of polling every VM. Registered as a device,
+//! the set is also a file whose reads block like `wait` and return the
+//! token mask, in the manner of an eventfd.
+//!
+//! Guest and device rings reach the host through the vCPU's run loop:
+//! EL2 can't wake threads, so the set is signalled once the exit is back
+//! at EL1.
+
+use crate::{
+    devices::{Device, DeviceClass, DeviceId, DeviceManager},
+    error::{code, Error},
+    scheduler::InsertToEnd,
+    sync::{
+        event_flags::{EventFlags, EventFlagsMode},
+        SpinLock,
+    },
+    time::Tick,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use embedded_io::ErrorKind;
+
+/// Doorbells per VM, and tokens per set.
+pub const MAX_DOORBELLS: u32 = 32;
+// Device major of doorbell sets; the minor is chosen at registration.
+const DOORBELL_MAJOR: usize = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorbellError {
+    /// The doorbell or token number is `MAX_DOORBELLS` or more.
+    OutOfRange,
+    /// The doorbell is already bound to a set.
+    Busy,
+    /// The doorbell isn't bound.
+    NotBound,
+}
+
+/// Host-side waitable handle over doorbells of several VMs.
+#[derive(Debug)]
+pub struct DoorbellSet {
+    tokens: EventFlags,
+    minor: usize,
+}
+
+// SAFETY: `EventFlags` keeps all of its state behind its own lock; it is
+// only `!Send` to stay out of thread-local use.
+unsafe impl Send for DoorbellSet {}
+
+impl DoorbellSet {
+    pub fn new() -> Arc<Self> {
+        Self::with_minor(0)
+    }
+
+    fn with_minor(minor: usize) -> Arc<Self> {
+        let set = Arc::new(Self {
+            tokens: EventFlags::new(),
+            minor,
+        });
+        set.tokens.init(0);
+        set
+    }
+
+    /// A set that is also the char device `name`, numbered `minor`.
+    pub fn register(name: &str, minor: usize) -> Result<Arc<Self>, Error> {
+        let set = Self::with_minor(minor);
+        DeviceManager::get()
+            .register_device(String::from(name), set.clone())
+            .map_err(Error::from)?;
+        Ok(set)
+    }
+
+    /// Report doorbell `doorbell` of VM `vm_id` as bit `token` from `wait`.
+    /// Several doorbells may share a token.
+    pub fn bind(
+        self: &Arc<Self>,
+        vm_id: usize,
+        doorbell: u32,
+        token: u32,
+    ) -> Result<(), DoorbellError> {
+        if doorbell >= MAX_DOORBELLS || token >= MAX_DOORBELLS {
+            return Err(DoorbellError::OutOfRange);
+        }
+        let mut bindings = BINDINGS.irqsave_lock();
+        if bindings
+            .iter()
+            .any(|b| b.vm_id == vm_id && b.doorbell == doorbell)
+        {
+            return Err(DoorbellError::Busy);
+        }
+        bindings.push(Binding {
+            vm_id,
+            doorbell,
+            token,
+            set: self.clone(),
+        });
+        Ok(())
+    }
+
+    /// Block until a bound doorbell rings, at most `timeout`. Returns the
+    /// tokens of all doorbells rung since the last call and clears them.
+    pub fn wait(&self, timeout: Tick) -> Result<u32, Error> {
+        self.tokens
+            .wait::<InsertToEnd>(u32::MAX, EventFlagsMode::ANY, timeout)
+    }
+
+    /// Tokens rung since the last call, without blocking.
+    pub fn poll(&self) -> u32 {
+        self.wait(Tick(0)).unwrap_or(0)
+    }
+
+    /// Raise `tokens` directly, e.g. to make a serving thread look at a
+    /// new VM.
+    pub fn notify(&self, tokens: u32) {
+        if tokens != 0 {
+            let _ = self.tokens.set(tokens);
+        }
+    }
+}
+
+impl Device for DoorbellSet {
+    fn name(&self) -> String {
+        String::from("doorbell")
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::new(DOORBELL_MAJOR, self.minor)
+    }
+
+    /// Reads the rung tokens as a little-endian `u32`.
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, ErrorKind> {
+        let Some(out) = buf.get_mut(..4) else {
+            return Err(ErrorKind::InvalidInput);
+        };
+        let timeout = if is_nonblocking { Tick(0) } else { Tick::MAX };
+        let tokens = match self.wait(timeout) {
+            Ok(tokens) => tokens,
+            Err(e) if e == code::ETIMEDOUT => return Err(ErrorKind::WouldBlock),
+            Err(_) => return Err(ErrorKind::Interrupted),
+        };
+        out.copy_from_slice(&tokens.to_le_bytes());
+        Ok(4)
+    }
+
+    /// Writing a little-endian `u32` raises those tokens, see `notify`.
+    fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, ErrorKind> {
+        let bytes = buf
+            .get(..4)
+            .and_then(|b| <[u8; 4]>::try_from(b).ok())
+            .ok_or(ErrorKind::InvalidInput)?;
+        self.notify(u32::from_le_bytes(bytes));
+        Ok(4)
+    }
+}
+
+struct Binding {
+    vm_id: usize,
+    doorbell: u32,
+    token: u32,
+    set: Arc<DoorbellSet>,
+}
+
+static BINDINGS: SpinLock<Vec<Binding>> = SpinLock::new(Vec::new());
+// Rings of doorbells bound to no set.
+static UNCLAIMED: AtomicU64 = AtomicU64::new(0);
+
+/// Ring doorbell `doorbell` of VM `vm_id`. Returns whether a set took it.
+/// Runs in thread context.
+pub fn ring(vm_id: usize, doorbell: u32) -> bool {
+    let target = BINDINGS
+        .irqsave_lock()
+        .iter()
+        .find(|b| b.vm_id == vm_id && b.doorbell == doorbell)
+        .map(|b| (b.set.clone(), b.token));
+    match target {
+        Some((set, token)) => {
+            set.notify(1 << token);
+            true
+        }
+        None => {
+            UNCLAIMED.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Stop reporting doorbell `doorbell` of VM `vm_id`.
+pub fn unbind(vm_id: usize, doorbell: u32) -> Result<(), DoorbellError> {
+    let mut bindings = BINDINGS.irqsave_lock();
+    let n = bindings
+        .iter()
+        .position(|b| b.vm_id == vm_id && b.doorbell == doorbell)
+        .ok_or(DoorbellError::NotBound)?;
+    bindings.swap_remove(n);
+    Ok(())
+}
+
+/// Drop the bindings of a VM being torn down.
+pub fn release_vm(vm_id: usize) {
+    BINDINGS.irqsave_lock().retain(|b| b.vm_id != vm_id);
+}
+
+/// Rings no set was bound for, since boot.
+pub fn unclaimed() -> u64 {
+    UNCLAIMED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_doorbell_multiplex() {
+        let (vm_a, vm_b) = (test_vm_id(), test_vm_id());
+        let set = DoorbellSet::new();
+        set.bind(vm_a, 0, 1).unwrap();
+        set.bind(vm_b, 3, 2).unwrap();
+        assert_eq!(set.bind(vm_a, 0, 5), Err(DoorbellError::Busy));
+        assert_eq!(
+            set.bind(vm_a, MAX_DOORBELLS, 0),
+            Err(DoorbellError::OutOfRange)
+        );
+        assert_eq!(set.poll(), 0);
+
+        assert!(ring(vm_a, 0));
+        assert!(ring(vm_b, 3));
+        let unclaimed = unclaimed();
+        assert!(!ring(vm_b, 4));
+        assert_eq!(unclaimed(), unclaimed + 1);
+        assert_eq!(set.wait(Tick(1)), Ok(0b110));
+        assert_eq!(set.poll(), 0);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(set.write(0, &8u32.to_le_bytes(), false), Ok(4));
+        assert_eq!(set.read(0, &mut buf, true), Ok(4));
+        assert_eq!(u32::from_le_bytes(buf), 8);
+        assert_eq!(set.read(0, &mut buf, true), Err(ErrorKind::WouldBlock));
+
+        unbind(vm_a, 0).unwrap();
+        assert_eq!(unbind(vm_a, 0), Err(DoorbellError::NotBound));
+        release_vm(vm_b);
+        assert!(!ring(vm_b, 3));
+    }
+}
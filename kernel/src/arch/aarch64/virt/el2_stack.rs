@@ -0,0 +1,154 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-water marks of the EL2 stacks. Each core runs EL2 on the top
+//! `STACK_SIZE` bytes of its 16 KiB boot stack chunk, see `enter_el1`.
+//! While the core is still in EL2 during boot, `paint` fills the part below
+//! the stack pointer with a pattern; the lowest word that lost it is as deep
+//! as EL2 ever got.
+//!
+//! A vCPU coming back to its host thread checks the one word `WARN_PERCENT`
+//! deep, so a core getting close to overflow is reported once without
+//! scanning on every exit. `usage` scans for the exact mark, e.g. for
+//! /proc/hypervisor/el2_stack.
+
+use crate::arch::aarch64::current_cpu_id;
+use core::{
+    arch::asm,
+    ptr::{addr_of, read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Bytes of EL2 stack per core.
+pub const STACK_SIZE: usize = 0x1000;
+/// Usage, in percent of `STACK_SIZE`, that is reported.
+pub const WARN_PERCENT: usize = 75;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+// Boot stack per core, the shift in `enter_el1`.
+const CORE_STACK: usize = 1 << 14;
+const PATTERN: u64 = 0x57ac_57ac_57ac_57ac;
+// Left unpainted below the stack pointer for `paint`'s own frame.
+const PAINT_MARGIN: usize = 256;
+
+extern "C" {
+    static __sys_stack_end: u8;
+}
+
+// Cores whose stack got painted. Written at EL2 before `.bss` is cleared.
+#[link_section = ".data"]
+static PAINTED: AtomicU32 = AtomicU32::new(0);
+// Cores reported over `WARN_PERCENT`.
+static WARNED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// Deepest the stack got, in bytes.
+    pub used: usize,
+    pub size: usize,
+}
+
+impl Usage {
+    pub fn percent(&self) -> usize {
+        self.used * 100 / self.size
+    }
+}
+
+// [bottom, top) of `cpu`'s EL2 stack.
+fn bounds(cpu: usize) -> (usize, usize) {
+    let top = addr_of!(__sys_stack_end) as usize - cpu * CORE_STACK;
+    (top - STACK_SIZE, top)
+}
+
+/// Fill the calling core's EL2 stack below the stack pointer with the
+/// pattern. Runs at EL2 during boot, see `initcall`.
+pub fn paint() -> Result<(), &'static str> {
+    let cpu = current_cpu_id();
+    let (bottom, top) = bounds(cpu);
+    let sp: usize;
+    unsafe { asm!("mov {}, sp", out(reg) sp, options(nomem, nostack)) };
+    if !(bottom + PAINT_MARGIN..=top).contains(&sp) {
+        return Err("not on the el2 stack");
+    }
+    let mut p = bottom;
+    while p < sp - PAINT_MARGIN {
+        unsafe { write_volatile(p as *mut u64, PATTERN) };
+        p += 8;
+    }
+    PAINTED.fetch_or(1 << cpu, Ordering::Release);
+    Ok(())
+}
+
+/// How deep `cpu`'s EL2 stack got so far, `None` if it wasn't painted.
+pub fn usage(cpu: usize) -> Option<Usage> {
+    if cpu >= NUM_CORES || PAINTED.load(Ordering::Acquire) & (1 << cpu) == 0 {
+        return None;
+    }
+    let (bottom, top) = bounds(cpu);
+    let mut p = bottom;
+    while p < top && unsafe { read_volatile(p as *const u64) } == PATTERN {
+        p += 8;
+    }
+    Some(Usage {
+        used: top - p,
+        size: STACK_SIZE,
+    })
+}
+
+/// Report `cpu` once its EL2 stack got `WARN_PERCENT` deep. Cheap enough
+/// for every exit the host sees.
+pub fn check(cpu: usize) {
+    let bit = 1 << cpu;
+    if PAINTED.load(Ordering::Relaxed) & bit == 0 || WARNED.load(Ordering::Relaxed) & bit != 0 {
+        return;
+    }
+    let (_, top) = bounds(cpu);
+    let word = (top - STACK_SIZE * WARN_PERCENT / 100) & !7;
+    if unsafe { read_volatile(word as *const u64) } == PATTERN {
+        return;
+    }
+    if WARNED.fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+        if let Some(usage) = usage(cpu) {
+            log::warn!(
+                "[virt] cpu {} el2 stack {}/{} bytes deep",
+                cpu,
+                usage.used,
+                usage.size
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_el2_stack_bounds() {
+        let (bottom, top) = bounds(1);
+        assert_eq!(top - bottom, STACK_SIZE);
+        assert_eq!(bounds(0).1 - top, CORE_STACK);
+        assert_eq!(usage(NUM_CORES), None);
+    }
+
+    #[test]
+    fn test_usage_percent() {
+        let usage = Usage {
+            used: STACK_SIZE / 2,
+            size: STACK_SIZE,
+        };
+        assert_eq!(usage.percent(), 50);
+    }
+}
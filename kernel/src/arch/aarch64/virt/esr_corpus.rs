@@ -0,0 +1,158 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ESR_EL2 test vectors for the exit decoders. Every entry is a raw syndrome
+//! as taken on a guest exit together with what it must decode to; when a
+//! decoding bug turns up, its ESR goes here.
+
+use super::exit::*;
+use blueos_test_macro::test;
+
+const FAR: u64 = 0x0900_0000;
+
+// (esr, instruction, expected reason)
+const REASONS: &[(u64, &str, ExitReason)] = &[
+    (0x0600_0000, "wfi", ExitReason::Wfx),
+    (0x0600_0001, "wfe", ExitReason::Wfx),
+    (0x5a00_0000, "hvc #0", ExitReason::Hvc(0)),
+    (0x5a00_0002, "hvc #2", ExitReason::Hvc(2)),
+    (0x4a00_0001, "hvc #1 (a32)", ExitReason::Hvc32(1)),
+    (
+        0x5e00_0000,
+        "smc #0",
+        ExitReason::Smc {
+            imm: 0,
+            aarch32: false,
+        },
+    ),
+    (
+        0x4e00_0000,
+        "smc #0 (a32)",
+        ExitReason::Smc {
+            imm: 0,
+            aarch32: true,
+        },
+    ),
+    (
+        0x9381_0047,
+        "str w1, [x0]",
+        ExitReason::DataAbort { far: FAR },
+    ),
+    (0x622807e0, "msr oslar_el1, xzr", ExitReason::Unknown(0x18)),
+    (0x8200_0007, "instruction abort", ExitReason::Unknown(0x20)),
+    (0x0200_0000, "undefined", ExitReason::Unknown(0)),
+];
+
+// (esr, instruction, expected access)
+const MMIO: &[(u64, &str, Option<MmioAccess>)] = &[
+    (
+        0x9381_0047,
+        "str w1, [x0]",
+        Some(MmioAccess {
+            write: true,
+            size: 4,
+            reg: 1,
+            sign_extend: false,
+            sf: false,
+        }),
+    ),
+    (
+        0x93c2_8006,
+        "ldr x2, [x1]",
+        Some(MmioAccess {
+            write: false,
+            size: 8,
+            reg: 2,
+            sign_extend: false,
+            sf: true,
+        }),
+    ),
+    (
+        0x9323_0007,
+        "ldrsb w3, [x0]",
+        Some(MmioAccess {
+            write: false,
+            size: 1,
+            reg: 3,
+            sign_extend: true,
+            sf: false,
+        }),
+    ),
+    (0x9200_0047, "stp w1, w2, [x0]", None),
+];
+
+// (esr, instruction, expected access)
+const SYSREG: &[(u64, &str, SysRegAccess)] = &[
+    (
+        0x6232_c001,
+        "mrs x0, ctr_el0",
+        SysRegAccess {
+            op0: 3,
+            op1: 3,
+            crn: 0,
+            crm: 0,
+            op2: 1,
+            rt: 0,
+            read: true,
+        },
+    ),
+    (
+        0x6228_07e0,
+        "msr oslar_el1, xzr",
+        SysRegAccess {
+            op0: 2,
+            op1: 0,
+            crn: 1,
+            crm: 0,
+            op2: 4,
+            rt: 31,
+            read: false,
+        },
+    ),
+    (
+        0x6230_00a9,
+        "mrs x5, id_aa64pfr0_el1",
+        SysRegAccess {
+            op0: 3,
+            op1: 0,
+            crn: 0,
+            crm: 4,
+            op2: 0,
+            rt: 5,
+            read: true,
+        },
+    ),
+];
+
+#[test]
+fn test_corpus_exit_reasons() {
+    for &(esr, insn, reason) in REASONS {
+        assert_eq!(decode_exit_reason(esr, FAR), reason, "{esr:#x} {insn}");
+        assert_eq!(instr_len(esr), 4, "{esr:#x} {insn}");
+    }
+}
+
+#[test]
+fn test_corpus_mmio() {
+    for &(esr, insn, access) in MMIO {
+        assert_eq!(decode_mmio(esr), access, "{esr:#x} {insn}");
+    }
+}
+
+#[test]
+fn test_corpus_sysreg() {
+    for &(esr, insn, access) in SYSREG {
+        assert_eq!(decode_sysreg(esr), access, "{esr:#x} {insn}");
+    }
+}
@@ -0,0 +1,851 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    abi::{
+        self, GUEST_HVC_ABI_VERSION, GUEST_HVC_COPY, GUEST_HVC_DOORBELL, GUEST_HVC_EXIT,
+        GUEST_HVC_FIQ, GUEST_HVC_GETC, GUEST_HVC_GRANT, GUEST_HVC_GRANT_MAP, GUEST_HVC_GRANT_UNMAP,
+        GUEST_HVC_GUEST_CYCLES, GUEST_HVC_HOTPLUG_EVENT, GUEST_HVC_PUTC, GUEST_HVC_REVOKE,
+        GUEST_HVC_SERVICES, GUEST_HVC_SHUTDOWN, GUEST_HVC_TIMER_SAMPLE, GUEST_HVC_TRACE,
+        NOT_SUPPORTED, NO_INPUT,
+    },
+    audit::{self, Initiator, Operation},
+    cacheid, doorbell, fault,
+    grant::{self, GrantRef},
+    hotplug, hyper, kick, lazy_ram, mmio,
+    policy::{ExitClass, PolicyAction},
+    ptimer,
+    services::Services,
+    stage2, status,
+    strict::{self, Anomaly},
+    timer_cal, trace, tvm, vconsole,
+    vcpu::{self, Vcpu},
+    vector::TrapFrame,
+    vgic::MAX_INTID,
+    vlog::{vlog, vlog_limited},
+    vpsci, vsgi,
+};
+
+const EC_WFX: u64 = 0x01;
+const EC_HVC32: u64 = 0x12;
+const EC_SMC32: u64 = 0x13;
+const EC_HVC64: u64 = 0x16;
+const EC_SMC64: u64 = 0x17;
+pub(crate) const EC_SYSREG: u64 = 0x18;
+const EC_DABT_LOW: u64 = 0x24;
+
+// ESR_EL2.IL: the trapped instruction was 32 bits wide.
+const ESR_IL: u64 = 1 << 25;
+// ESR_EL2.ISS.CV/COND for AArch32 traps of conditional instructions.
+const ESR_CV: u64 = 1 << 24;
+const ESR_COND_SHIFT: u64 = 20;
+// ESR_EL2.ISS.WnR of a data abort: the access was a write.
+const ESR_WNR: u64 = 1 << 6;
+// ESR_EL2.ISS.DFSC of a data abort with the level masked out.
+const DFSC_TYPE_MASK: u64 = 0x3c;
+const DFSC_PERMISSION: u64 = 0x0c;
+// SPSR_EL2.M[4]: the exception was taken from AArch32.
+const SPSR_AARCH32: u64 = 1 << 4;
+// SPSR_EL2.M[3:0]: the mode, or exception level and stack, trapped from.
+const SPSR_MODE_MASK: u64 = 0xf;
+// SPSR_EL2.T of an AArch32 trap: the guest ran T32.
+const SPSR_T: u64 = 1 << 5;
+// SPSR_EL2.IT, the T32 ITSTATE: IT[1:0] at bits 26:25, IT[7:2] at 15:10.
+const SPSR_IT_LO_SHIFT: u64 = 25;
+const SPSR_IT_HI_SHIFT: u64 = 10;
+const SPSR_IT_MASK: u64 = (0b11 << SPSR_IT_LO_SHIFT) | (0x3f << SPSR_IT_HI_SHIFT);
+
+/// Why a vCPU left guest mode, as reported to the host run loop in x0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// A host interrupt arrived; it is still pending and taken at EL1.
+    HostIrq,
+    /// The guest executed WFI.
+    Wfi,
+    /// Another CPU kicked the vCPU; payload is the KickReason bitmask.
+    Kick(u32),
+    /// The guest asked to be shut down; payload is the status it reported,
+    /// see `status`.
+    Shutdown(u32),
+    /// The guest hit an exception the hypervisor cannot handle.
+    Fault,
+    /// The run request itself was invalid.
+    Invalid,
+    /// The VM's exit policy paused the vCPU for inspection.
+    Paused,
+    /// The guest turned the vCPU off with PSCI CPU_OFF.
+    PowerOff,
+    /// The guest called PSCI CPU_SUSPEND and waits for an interrupt.
+    Suspended,
+    /// The guest or one of its devices rang a doorbell; payload is its
+    /// number, see `doorbell`.
+    Doorbell(u32),
+    /// The guest wrote to lazy RAM still on the zero page; payload is the
+    /// IPA of the page, see `lazy_ram`.
+    Populate(u64),
+    /// The guest asked for its VM to be reset, see `vcpu::reset_vm`.
+    Reset,
+    /// Strict mode caught the guest misbehaving; payload is the
+    /// `strict::Anomaly`. The run loop panics the host.
+    Anomaly(u32),
+    /// The guest submitted a copy, see `copy::submit`.
+    Copy,
+    /// The guest called PSCI CPU_ON for a vCPU the host has to create or
+    /// start a thread for; payload is its index, see `vpsci`.
+    CpuOn(u32),
+    /// The guest asked to map or unmap a grant made for it; payload is the
+    /// hypercall, see `grant::handle_map`.
+    GrantMap(u16),
+    /// A trap of the guest failed at EL2 and `recovery` had no room to mark
+    /// its VM failed. The run loop stops the VM's other vCPUs.
+    VmFailed,
+}
+
+impl ExitCode {
+    pub const fn encode(self) -> u64 {
+        match self {
+            Self::HostIrq => 1,
+            Self::Wfi => 2,
+            Self::Kick(reasons) => 3 | ((reasons as u64) << 32),
+            Self::Shutdown(status) => 4 | ((status as u64) << 32),
+            Self::Fault => 5,
+            Self::Invalid => 6,
+            Self::Paused => 7,
+            Self::PowerOff => 8,
+            Self::Suspended => 9,
+            Self::Doorbell(n) => 10 | ((n as u64) << 32),
+            // The page offset bits are free for the code.
+            Self::Populate(ipa) => 11 | (ipa & !0xfff),
+            Self::Reset => 12,
+            Self::Anomaly(kind) => 13 | ((kind as u64) << 32),
+            Self::Copy => 14,
+            Self::CpuOn(index) => 15 | ((index as u64) << 32),
+            Self::GrantMap(imm) => 16 | ((imm as u64) << 32),
+            Self::VmFailed => 17,
+        }
+    }
+
+    pub const fn decode(raw: u64) -> Self {
+        match raw & 0xff {
+            1 => Self::HostIrq,
+            2 => Self::Wfi,
+            3 => Self::Kick((raw >> 32) as u32),
+            4 => Self::Shutdown((raw >> 32) as u32),
+            5 => Self::Fault,
+            7 => Self::Paused,
+            8 => Self::PowerOff,
+            9 => Self::Suspended,
+            10 => Self::Doorbell((raw >> 32) as u32),
+            11 => Self::Populate(raw & !0xfff),
+            12 => Self::Reset,
+            13 => Self::Anomaly((raw >> 32) as u32),
+            14 => Self::Copy,
+            15 => Self::CpuOn((raw >> 32) as u32),
+            16 => Self::GrantMap((raw >> 32) as u16),
+            17 => Self::VmFailed,
+            _ => Self::Invalid,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Wfx,
+    Hvc(u16),
+    Hvc32(u16),
+    Smc { imm: u16, aarch32: bool },
+    DataAbort { far: u64 },
+    Unknown(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAction {
+    Resume,
+    Exit(ExitCode),
+}
+
+pub fn parse_exit_reason(esr: u64) -> ExitReason {
+    let mut far = 0;
+    if (esr >> 26) & 0x3f == EC_DABT_LOW {
+        far = hyper::read_far_el2();
+    }
+    decode_exit_reason(esr, far)
+}
+
+/// `parse_exit_reason` with FAR_EL2 already read, so it runs anywhere.
+pub fn decode_exit_reason(esr: u64, far: u64) -> ExitReason {
+    match (esr >> 26) & 0x3f {
+        EC_WFX => ExitReason::Wfx,
+        EC_HVC64 => ExitReason::Hvc((esr & 0xffff) as u16),
+        EC_HVC32 => ExitReason::Hvc32((esr & 0xffff) as u16),
+        EC_SMC64 => ExitReason::Smc {
+            imm: (esr & 0xffff) as u16,
+            aarch32: false,
+        },
+        EC_SMC32 => ExitReason::Smc {
+            imm: 0,
+            aarch32: true,
+        },
+        EC_DABT_LOW => ExitReason::DataAbort { far },
+        ec => ExitReason::Unknown(ec),
+    }
+}
+
+/// A data abort the syndrome fully describes, i.e. one MMIO emulation can
+/// replay without decoding the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioAccess {
+    pub write: bool,
+    /// Access size in bytes.
+    pub size: u8,
+    /// Transfer register; 31 is xzr.
+    pub reg: u8,
+    pub sign_extend: bool,
+    /// The register is 64 bits wide.
+    pub sf: bool,
+}
+
+/// Decode the ISS of a data abort. `None` when ISS.ISV is clear, e.g. for
+/// load/store pair or writeback forms.
+pub fn decode_mmio(esr: u64) -> Option<MmioAccess> {
+    if esr & (1 << 24) == 0 {
+        return None;
+    }
+    Some(MmioAccess {
+        write: esr & (1 << 6) != 0,
+        size: 1 << ((esr >> 22) & 0x3),
+        reg: ((esr >> 16) & 0x1f) as u8,
+        sign_extend: esr & (1 << 21) != 0,
+        sf: esr & (1 << 15) != 0,
+    })
+}
+
+/// Whether a data abort is a write Stage-2 permissions denied.
+pub fn is_write_permission_fault(esr: u64) -> bool {
+    esr & ESR_WNR != 0 && esr & DFSC_TYPE_MASK == DFSC_PERMISSION
+}
+
+/// An MSR/MRS trapped with EC 0x18.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysRegAccess {
+    pub op0: u8,
+    pub op1: u8,
+    pub crn: u8,
+    pub crm: u8,
+    pub op2: u8,
+    pub rt: u8,
+    /// MRS; otherwise MSR.
+    pub read: bool,
+}
+
+pub fn decode_sysreg(esr: u64) -> SysRegAccess {
+    SysRegAccess {
+        op0: ((esr >> 20) & 0x3) as u8,
+        op1: ((esr >> 14) & 0x7) as u8,
+        crn: ((esr >> 10) & 0xf) as u8,
+        crm: ((esr >> 1) & 0xf) as u8,
+        op2: ((esr >> 17) & 0x7) as u8,
+        rt: ((esr >> 5) & 0x1f) as u8,
+        read: esr & 1 != 0,
+    }
+}
+
+/// IPA of the Stage-2 fault being handled: the page from HPFAR_EL2.FIPA and
+/// the offset within it from FAR_EL2.
+pub fn fault_ipa(far: u64) -> u64 {
+    let hpfar = hyper::read_hpfar_el2();
+    ((hpfar & ((1 << 44) - 1)) >> 4 << 12) | (far & 0xfff)
+}
+
+/// Size of the trapped instruction, for stepping over it.
+#[inline]
+pub fn instr_len(esr: u64) -> u64 {
+    if esr & ESR_IL != 0 {
+        4
+    } else {
+        2
+    }
+}
+
+#[inline]
+pub fn is_aarch32(spsr: u64) -> bool {
+    spsr & SPSR_AARCH32 != 0
+}
+
+/// Exception level a guest trapped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestEl {
+    /// Guest userspace.
+    El0 = 0,
+    /// The guest kernel.
+    El1 = 1,
+}
+
+impl GuestEl {
+    pub const ALL: [GuestEl; 2] = [GuestEl::El0, GuestEl::El1];
+
+    /// From the SPSR_EL2 of the trap. EL0t in AArch64 and User mode in
+    /// AArch32 are the only modes with M[3:0] clear.
+    pub fn of(spsr: u64) -> Self {
+        if spsr & SPSR_MODE_MASK == 0 {
+            GuestEl::El0
+        } else {
+            GuestEl::El1
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GuestEl::El0 => "el0",
+            GuestEl::El1 => "el1",
+        }
+    }
+}
+
+// ITSTATE of a T32 guest, 0 outside an IT block.
+fn itstate(spsr: u64) -> u64 {
+    if !is_aarch32(spsr) || spsr & SPSR_T == 0 {
+        return 0;
+    }
+    ((spsr >> SPSR_IT_LO_SHIFT) & 0b11) | (((spsr >> SPSR_IT_HI_SHIFT) & 0x3f) << 2)
+}
+
+/// SPSR of a guest past the instruction it trapped on: inside an IT block
+/// ITSTATE moves on to the next instruction, or ends with the last.
+pub fn advance_itstate(spsr: u64) -> u64 {
+    let it = itstate(spsr);
+    if it == 0 {
+        return spsr;
+    }
+    let it = if it & 0b111 == 0 {
+        0
+    } else {
+        (it & 0xe0) | ((it << 1) & 0x1f)
+    };
+    (spsr & !SPSR_IT_MASK) | ((it & 0b11) << SPSR_IT_LO_SHIFT) | ((it >> 2) << SPSR_IT_HI_SHIFT)
+}
+
+/// Whether a trapped AArch32 instruction would have executed. Some cores trap
+/// conditional instructions whose condition fails; those must be skipped
+/// instead of emulated. Without a valid COND, a T32 instruction in an IT
+/// block takes its condition from ITSTATE. AArch64 exits always pass.
+pub fn condition_passed(esr: u64, spsr: u64) -> bool {
+    if !is_aarch32(spsr) {
+        return true;
+    }
+    // Only the EC range 0x01..=0x0e and a trapped SMC report CV/COND.
+    let ec = (esr >> 26) & 0x3f;
+    if !(0x01..=0x0e).contains(&ec) && ec != EC_SMC32 {
+        return true;
+    }
+    let cond = if esr & ESR_CV != 0 {
+        (esr >> ESR_COND_SHIFT) & 0xf
+    } else {
+        match itstate(spsr) {
+            0 => return true,
+            it => it >> 4,
+        }
+    };
+    let n = spsr & (1 << 31) != 0;
+    let z = spsr & (1 << 30) != 0;
+    let c = spsr & (1 << 29) != 0;
+    let v = spsr & (1 << 28) != 0;
+    let result = match cond >> 1 {
+        0b000 => z,
+        0b001 => c,
+        0b010 => n,
+        0b011 => v,
+        0b100 => c && !z,
+        0b101 => n == v,
+        0b110 => n == v && !z,
+        _ => true,
+    };
+    // Odd conditions negate, except 0b1111 which is "always" like 0b1110.
+    if cond & 1 == 1 && cond != 0b1111 {
+        !result
+    } else {
+        result
+    }
+}
+
+// Service guest hypercall `imm` belongs to, if it isn't always available.
+fn hvc_service(imm: u16) -> Option<Services> {
+    match imm {
+        GUEST_HVC_PUTC | GUEST_HVC_GETC => Some(Services::CONSOLE),
+        GUEST_HVC_GUEST_CYCLES | GUEST_HVC_TIMER_SAMPLE => Some(Services::TIME),
+        GUEST_HVC_GRANT
+        | GUEST_HVC_REVOKE
+        | GUEST_HVC_GRANT_MAP
+        | GUEST_HVC_GRANT_UNMAP
+        | GUEST_HVC_DOORBELL => Some(Services::SHMEM),
+        GUEST_HVC_HOTPLUG_EVENT => Some(Services::HOTPLUG),
+        GUEST_HVC_TRACE | GUEST_HVC_FIQ => Some(Services::TEST_AGENT),
+        GUEST_HVC_COPY => Some(Services::COPY),
+        _ => None,
+    }
+}
+
+pub fn handle_hvc(vcpu: &mut Vcpu, imm: u16) -> ExitAction {
+    // PSCI function ids never collide with a putc character.
+    if imm == GUEST_HVC_PUTC && vpsci::is_psci_call(vcpu.regs.x[0]) {
+        return vpsci::handle(vcpu);
+    }
+    if hvc_service(imm).is_some_and(|s| !vcpu.config.services.contains(s)) {
+        vcpu.regs.x[0] = NOT_SUPPORTED;
+        return ExitAction::Resume;
+    }
+    match imm {
+        GUEST_HVC_PUTC => {
+            vconsole::putc(vcpu.vm_id, vcpu.regs.x[0] as u8);
+            vcpu.regs.x[0] = 0;
+            ExitAction::Resume
+        }
+        GUEST_HVC_GETC => {
+            vcpu.regs.x[0] = vconsole::getc(vcpu.vm_id).map_or(NO_INPUT, u64::from);
+            ExitAction::Resume
+        }
+        GUEST_HVC_SHUTDOWN => ExitAction::Exit(ExitCode::Shutdown(status::SUCCESS)),
+        GUEST_HVC_EXIT => ExitAction::Exit(ExitCode::Shutdown(vcpu.regs.x[0] as u32)),
+        GUEST_HVC_GUEST_CYCLES => {
+            vcpu.regs.x[0] = vcpu.guest_cycles(hyper::read_cntpct());
+            ExitAction::Resume
+        }
+        GUEST_HVC_GRANT => {
+            let [ipa, peer, flags] = [vcpu.regs.x[0], vcpu.regs.x[1], vcpu.regs.x[2]];
+            vcpu.regs.x[0] = match grant::create(vcpu.vm_id, ipa, peer, flags) {
+                Ok(gref) => {
+                    let op = Operation::Grant {
+                        gref: gref.0,
+                        ipa,
+                        peer,
+                        write: flags & grant::GRANT_WRITE != 0,
+                    };
+                    audit::record(vcpu.vm_id, vcpu.config.label, Initiator::Vcpu(vcpu.id), op);
+                    gref.0
+                }
+                Err(e) => e.code(),
+            };
+            ExitAction::Resume
+        }
+        GUEST_HVC_REVOKE => {
+            let gref = vcpu.regs.x[0];
+            vcpu.regs.x[0] = match grant::revoke(vcpu.vm_id, GrantRef(gref)) {
+                Ok(()) => {
+                    let op = Operation::Revoke { gref };
+                    audit::record(vcpu.vm_id, vcpu.config.label, Initiator::Vcpu(vcpu.id), op);
+                    0
+                }
+                Err(e) => e.code(),
+            };
+            ExitAction::Resume
+        }
+        GUEST_HVC_HOTPLUG_EVENT => {
+            match hotplug::next_event(vcpu.vm_id) {
+                Some(e) => {
+                    vcpu.regs.x[..5].copy_from_slice(&[
+                        e.kind as u64,
+                        e.base,
+                        e.size,
+                        e.intid as u64,
+                        e.type_id as u64,
+                    ]);
+                }
+                None => vcpu.regs.x[0] = 0,
+            }
+            ExitAction::Resume
+        }
+        GUEST_HVC_TIMER_SAMPLE => {
+            vcpu.regs.x[0] = timer_cal::record_sample(vcpu.regs.x[0], vcpu.regs.x[1]);
+            ExitAction::Resume
+        }
+        GUEST_HVC_DOORBELL if vcpu.regs.x[0] < doorbell::MAX_DOORBELLS as u64 => {
+            let n = vcpu.regs.x[0] as u32;
+            vcpu.regs.x[0] = 0;
+            ExitAction::Exit(ExitCode::Doorbell(n))
+        }
+        GUEST_HVC_TRACE => {
+            let [id, arg, stamp] = [vcpu.regs.x[0], vcpu.regs.x[1], vcpu.regs.x[2]];
+            vcpu.regs.x[0] = if trace::record_guest(vcpu, id, arg, stamp) {
+                0
+            } else {
+                NOT_SUPPORTED
+            };
+            ExitAction::Resume
+        }
+        GUEST_HVC_SERVICES => {
+            vcpu.regs.x[0] = vcpu.config.services.discover();
+            ExitAction::Resume
+        }
+        GUEST_HVC_ABI_VERSION => {
+            vcpu.regs.x[0] = abi::VERSION;
+            ExitAction::Resume
+        }
+        GUEST_HVC_FIQ => {
+            let intid = vcpu.regs.x[0];
+            vcpu.regs.x[0] = if intid < MAX_INTID as u64
+                && kick::inject_fiq(vcpu.vm_id, vcpu.id, intid as u32).is_ok()
+            {
+                0
+            } else {
+                NOT_SUPPORTED
+            };
+            ExitAction::Resume
+        }
+        // The run loop leaves the result in x0.
+        GUEST_HVC_COPY => ExitAction::Exit(ExitCode::Copy),
+        GUEST_HVC_GRANT_MAP | GUEST_HVC_GRANT_UNMAP => ExitAction::Exit(ExitCode::GrantMap(imm)),
+        _ if vcpu.config.strict => ExitAction::Exit(ExitCode::Anomaly(Anomaly::UnknownHvc as u32)),
+        _ => {
+            vcpu.regs.x[0] = NOT_SUPPORTED;
+            ExitAction::Resume
+        }
+    }
+}
+
+pub fn handle_vm_exit(vcpu: &mut Vcpu, reason: ExitReason) -> ExitAction {
+    let action = dispatch_exit(vcpu, reason);
+    if vcpu.config.strict {
+        strict::check_exit(reason, action)
+    } else {
+        action
+    }
+}
+
+fn dispatch_exit(vcpu: &mut Vcpu, reason: ExitReason) -> ExitAction {
+    let el = GuestEl::of(vcpu.regs.spsr);
+    let class = ExitClass::of(reason);
+    vcpu.stats.record_trap(el, class);
+    vlog!(
+        Exit,
+        Trace,
+        "[EL2] vcpu {} exit {:?} from {}, pc {:#x}",
+        vcpu.id,
+        reason,
+        el.name(),
+        vcpu.regs.elr
+    );
+    // Fatal whatever the policy says, but worth naming.
+    if let ExitReason::DataAbort { far } = reason {
+        let ipa = fault_ipa(far);
+        if stage2::with_vm(vcpu.vm_id, |s2| s2.is_guard(ipa)) == Some(true) {
+            vlog!(
+                Exit,
+                Error,
+                "[EL2] vcpu {} stack overflow: access to guard page at ipa {:#x}, pc {:#x}",
+                vcpu.id,
+                ipa,
+                vcpu.regs.elr
+            );
+            return ExitAction::Exit(ExitCode::Fault);
+        }
+        let write = is_write_permission_fault(vcpu.exit_esr);
+        if let Some(action) = lazy_ram::on_abort(vcpu.vm_id, ipa, write) {
+            return action;
+        }
+        // Device accesses are ordinary guest behavior; the policy only sees
+        // data aborts nothing claims.
+        if let Some(action) = mmio::handle(vcpu, far, ipa) {
+            return action;
+        }
+    }
+    // So are the registers a VM traps by its `Traps`, and the SGIs its
+    // vCPUs send each other.
+    if let ExitReason::Unknown(EC_SYSREG) = reason {
+        let access = decode_sysreg(vcpu.exit_esr);
+        if let Some(action) = cacheid::emulate(vcpu, access) {
+            return action;
+        }
+        if let Some(action) = tvm::emulate(vcpu, access) {
+            return action;
+        }
+        if let Some(action) = vsgi::emulate(vcpu, access) {
+            return action;
+        }
+        if let Some(action) = ptimer::emulate(vcpu, access) {
+            return action;
+        }
+    }
+    match vcpu.config.policy.action(class) {
+        PolicyAction::Handle => {}
+        PolicyAction::HandleLog => vlog_limited!(
+            Exit,
+            Info,
+            "[EL2] vcpu {} exit {:?} from {}, pc {:#x}",
+            vcpu.id,
+            reason,
+            el.name(),
+            vcpu.regs.elr
+        ),
+        PolicyAction::Terminate => {
+            vlog!(
+                Exit,
+                Warn,
+                "[EL2] vcpu {} terminated by policy on {:?} from {}, pc {:#x}",
+                vcpu.id,
+                reason,
+                el.name(),
+                vcpu.regs.elr
+            );
+            return ExitAction::Exit(ExitCode::Fault);
+        }
+        // Stays on the trapping instruction; resuming runs it again unless
+        // the debugger asks to skip it.
+        PolicyAction::Pause => return ExitAction::Exit(ExitCode::Paused),
+    }
+    match reason {
+        // ELR_EL2 already points past the HVC.
+        ExitReason::Hvc(imm) | ExitReason::Hvc32(imm) => handle_hvc(vcpu, imm),
+        // A trapped SMC returns to the SMC itself. PSCI is the only secure
+        // service provided to guests.
+        ExitReason::Smc { imm: 0, .. } if vpsci::is_psci_call(vcpu.regs.x[0]) => {
+            vcpu.advance_pc();
+            vpsci::handle(vcpu)
+        }
+        ExitReason::Smc { .. } => {
+            let op = Operation::SmcRefused {
+                function: vcpu.regs.x[0],
+            };
+            audit::record(vcpu.vm_id, vcpu.config.label, Initiator::Vcpu(vcpu.id), op);
+            vcpu.regs.x[0] = NOT_SUPPORTED;
+            vcpu.advance_pc();
+            ExitAction::Resume
+        }
+        ExitReason::Wfx => {
+            vcpu.advance_pc();
+            ExitAction::Exit(ExitCode::Wfi)
+        }
+        // Neither RAM nor a device: the access is the guest's bug, so the
+        // guest gets the abort, as on real hardware. A strict VM stops.
+        ExitReason::DataAbort { far } => {
+            let ipa = fault_ipa(far);
+            let mapped = stage2::with_vm(vcpu.vm_id, |s2| s2.attrs(ipa).is_some());
+            vlog_limited!(
+                Exit,
+                Warn,
+                "[EL2] vcpu {} data abort at {:#x}, ipa {:#x} {} ({:?}), pc {:#x}",
+                vcpu.id,
+                far,
+                ipa,
+                if mapped == Some(true) {
+                    "denied"
+                } else {
+                    "unmapped"
+                },
+                decode_mmio(vcpu.exit_esr),
+                vcpu.regs.elr
+            );
+            if vcpu.config.strict {
+                return ExitAction::Exit(ExitCode::Fault);
+            }
+            fault::inject_data_abort(vcpu, far, vcpu.exit_esr & ESR_WNR != 0);
+            ExitAction::Resume
+        }
+        ExitReason::Unknown(EC_SYSREG) => {
+            vlog_limited!(
+                Exit,
+                Warn,
+                "[EL2] vcpu {} unhandled {:?}, pc {:#x}",
+                vcpu.id,
+                decode_sysreg(vcpu.exit_esr),
+                vcpu.regs.elr
+            );
+            ExitAction::Exit(ExitCode::Fault)
+        }
+        ExitReason::Unknown(ec) => {
+            vlog_limited!(
+                Exit,
+                Warn,
+                "[EL2] vcpu {} unhandled exit ec {:#x}, pc {:#x}",
+                vcpu.id,
+                ec,
+                vcpu.regs.elr
+            );
+            ExitAction::Exit(ExitCode::Fault)
+        }
+    }
+}
+
+/// Handle a synchronous exception taken from a running guest. Returns the
+/// value `sync_from_lower_el1` expects: non-zero to eret with the frame.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of the exception.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn handle_guest_sync(frame: *mut TrapFrame, vcpu: &mut Vcpu) -> u64 {
+    #[cfg(virt_exit_budget)]
+    let start = hyper::read_cntpct();
+    let esr = hyper::read_esr_el2();
+    vcpu.regs.save_from_frame(frame);
+    vcpu.shadow.record(&vcpu.regs);
+    vcpu.exit_esr = esr;
+    #[cfg(debug)]
+    if let Err(e) = super::sanity::check_exit(vcpu) {
+        vlog!(
+            Exit,
+            Error,
+            "[EL2] vcpu {} failed exit check: {}",
+            vcpu.id,
+            e
+        );
+        let code = if vcpu.config.strict {
+            ExitCode::Anomaly(Anomaly::ExitCheck as u32)
+        } else {
+            ExitCode::Fault
+        };
+        vcpu::leave_guest(frame, vcpu, code);
+        return 1;
+    }
+    if !condition_passed(esr, vcpu.regs.spsr) {
+        vcpu.advance_pc();
+        vcpu.regs.restore_to_frame(frame);
+        return 1;
+    }
+    let reason = parse_exit_reason(esr);
+    let action = handle_vm_exit(vcpu, reason);
+    #[cfg(virt_exit_budget)]
+    let action = super::budget::check(vcpu, reason, start, action);
+    match action {
+        ExitAction::Resume => vcpu.regs.restore_to_frame(frame),
+        ExitAction::Exit(code) => vcpu::leave_guest(frame, vcpu, code),
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_exit_code_round_trip() {
+        for code in [
+            ExitCode::HostIrq,
+            ExitCode::Wfi,
+            ExitCode::Kick(0x9),
+            ExitCode::Shutdown(0),
+            ExitCode::Shutdown(0xdead_beef),
+            ExitCode::Fault,
+            ExitCode::Invalid,
+            ExitCode::Paused,
+            ExitCode::PowerOff,
+            ExitCode::Suspended,
+            ExitCode::Doorbell(31),
+            ExitCode::Populate(0xff_ffff_f000),
+            ExitCode::Reset,
+            ExitCode::Anomaly(4),
+            ExitCode::Copy,
+            ExitCode::CpuOn(3),
+            ExitCode::GrantMap(GUEST_HVC_GRANT_UNMAP),
+            ExitCode::VmFailed,
+        ] {
+            assert_eq!(ExitCode::decode(code.encode()), code);
+        }
+    }
+
+    #[test]
+    fn test_write_permission_fault() {
+        // WnR, permission fault at level 3.
+        assert!(is_write_permission_fault(0x9200_004f));
+        // Read.
+        assert!(!is_write_permission_fault(0x9200_000f));
+        // Write, translation fault at level 3.
+        assert!(!is_write_permission_fault(0x9200_0047));
+    }
+
+    #[test]
+    fn test_hvc_service() {
+        assert_eq!(hvc_service(GUEST_HVC_PUTC), Some(Services::CONSOLE));
+        assert_eq!(hvc_service(GUEST_HVC_GETC), Some(Services::CONSOLE));
+        assert_eq!(hvc_service(GUEST_HVC_DOORBELL), Some(Services::SHMEM));
+        assert_eq!(hvc_service(GUEST_HVC_GRANT_MAP), Some(Services::SHMEM));
+        assert_eq!(hvc_service(GUEST_HVC_COPY), Some(Services::COPY));
+        assert_eq!(hvc_service(GUEST_HVC_FIQ), Some(Services::TEST_AGENT));
+        assert_eq!(hvc_service(GUEST_HVC_SHUTDOWN), None);
+        assert_eq!(hvc_service(GUEST_HVC_EXIT), None);
+        assert_eq!(hvc_service(GUEST_HVC_SERVICES), None);
+    }
+
+    #[test]
+    fn test_instr_len() {
+        assert_eq!(instr_len(ESR_IL), 4);
+        assert_eq!(instr_len(0), 2);
+    }
+
+    #[test]
+    fn test_condition_passed() {
+        let wfi = (EC_WFX << 26) | ESR_IL | ESR_CV;
+        let z_set = SPSR_AARCH32 | (1 << 30);
+        // EQ / NE
+        assert!(condition_passed(wfi, z_set));
+        assert!(!condition_passed(wfi | (0b0001 << ESR_COND_SHIFT), z_set));
+        // GE with N != V
+        let n_set = SPSR_AARCH32 | (1 << 31);
+        assert!(!condition_passed(wfi | (0b1010 << ESR_COND_SHIFT), n_set));
+        assert!(condition_passed(wfi | (0b1011 << ESR_COND_SHIFT), n_set));
+        // AL
+        assert!(condition_passed(wfi | (0b1110 << ESR_COND_SHIFT), 0));
+        // COND not valid, or AArch64 guest
+        assert!(condition_passed(
+            wfi & !ESR_CV | (0b0001 << ESR_COND_SHIFT),
+            z_set
+        ));
+        assert!(condition_passed(wfi | (0b0001 << ESR_COND_SHIFT), 1 << 30));
+        // SMCNE that failed its condition but trapped anyway
+        let smc = (EC_SMC32 << 26) | ESR_IL | ESR_CV;
+        assert!(!condition_passed(smc | (0b0001 << ESR_COND_SHIFT), z_set));
+        assert!(condition_passed(smc, z_set));
+    }
+
+    #[test]
+    fn test_it_block() {
+        let wfi = (EC_WFX << 26) | ESR_IL;
+        // ITTE NE: NE, NE, then EQ, with Z set.
+        let it = |it: u64| ((it & 0b11) << SPSR_IT_LO_SHIFT) | ((it >> 2) << SPSR_IT_HI_SHIFT);
+        let spsr = SPSR_AARCH32 | SPSR_T | (1 << 30) | it(0b0001_1010);
+        assert!(!condition_passed(wfi, spsr));
+        let spsr = advance_itstate(spsr);
+        assert_eq!(itstate(spsr), 0b0001_0100);
+        assert!(!condition_passed(wfi, spsr));
+        let spsr = advance_itstate(spsr);
+        assert_eq!(itstate(spsr), 0b0000_1000);
+        assert!(condition_passed(wfi, spsr));
+        // The block ends with its last instruction.
+        let spsr = advance_itstate(spsr);
+        assert_eq!(spsr & SPSR_IT_MASK, 0);
+        assert!(condition_passed(wfi, spsr));
+        // A32 has no ITSTATE.
+        assert_eq!(
+            advance_itstate(SPSR_AARCH32 | it(0x16)),
+            SPSR_AARCH32 | it(0x16)
+        );
+    }
+
+    #[test]
+    fn test_guest_el() {
+        // EL0t, EL1t and EL1h in AArch64.
+        assert_eq!(GuestEl::of(0x3c0), GuestEl::El0);
+        assert_eq!(GuestEl::of(0x3c4), GuestEl::El1);
+        assert_eq!(GuestEl::of(0x3c5), GuestEl::El1);
+        // User, SVC and System in AArch32.
+        assert_eq!(GuestEl::of(SPSR_AARCH32), GuestEl::El0);
+        assert_eq!(GuestEl::of(SPSR_AARCH32 | 0x3), GuestEl::El1);
+        assert_eq!(GuestEl::of(SPSR_AARCH32 | 0xf), GuestEl::El1);
+    }
+
+    #[test]
+    fn test_exit_code_decode_garbage() {
+        assert_eq!(ExitCode::decode(0), ExitCode::Invalid);
+        assert_eq!(ExitCode::decode(u64::MAX), ExitCode::Invalid);
+    }
+}
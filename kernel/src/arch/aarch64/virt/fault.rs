@@ -0,0 +1,116 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exceptions injected into guests. The guest's EL1 registers are live in
+//! hardware while its exit is handled at EL2, so injecting means writing
+//! them as the exception entry would and pointing the vCPU at its vector.
+
+use super::{
+    exit,
+    hal::{sysregs, SysReg, SysRegBackend},
+    hyper,
+    vcpu::Vcpu,
+};
+
+const EC_DABT_LOWER: u64 = 0x24;
+const EC_DABT_CURRENT: u64 = 0x25;
+const ESR_IL: u64 = 1 << 25;
+const ESR_WNR: u64 = 1 << 6;
+// Synchronous external abort, not on a translation table walk.
+const DFSC_EXT_ABORT: u64 = 0x10;
+const DFSC_MASK: u64 = 0x3f;
+
+const SPSR_MODE_MASK: u64 = 0xf;
+const SPSR_EL0T: u64 = 0x0;
+const SPSR_EL1T: u64 = 0x4;
+// EL1h with DAIF masked, as on exception entry.
+const SPSR_EL1H_MASKED: u64 = 0x3c5;
+
+const VECTOR_CURRENT_SP0: u64 = 0x000;
+const VECTOR_CURRENT_SPX: u64 = 0x200;
+const VECTOR_LOWER_A64: u64 = 0x400;
+const VECTOR_LOWER_A32: u64 = 0x600;
+
+/// ESR_EL1 and vector offset of a synchronous external abort taken from
+/// guest mode `spsr`.
+pub fn data_abort_syndrome(spsr: u64, write: bool) -> (u64, u64) {
+    syndrome(spsr, write, DFSC_EXT_ABORT)
+}
+
+fn syndrome(spsr: u64, write: bool, dfsc: u64) -> (u64, u64) {
+    let (ec, vector) = match spsr & SPSR_MODE_MASK {
+        // EL1 is always AArch64 (HCR_EL2.RW), so this is AArch32 EL0.
+        _ if exit::is_aarch32(spsr) => (EC_DABT_LOWER, VECTOR_LOWER_A32),
+        SPSR_EL0T => (EC_DABT_LOWER, VECTOR_LOWER_A64),
+        SPSR_EL1T => (EC_DABT_CURRENT, VECTOR_CURRENT_SP0),
+        _ => (EC_DABT_CURRENT, VECTOR_CURRENT_SPX),
+    };
+    let wnr = if write { ESR_WNR } else { 0 };
+    ((ec << 26) | ESR_IL | wnr | (dfsc & DFSC_MASK), vector)
+}
+
+/// Make the guest take a synchronous external abort on the access at
+/// `far` instead of completing it. Must run on the vCPU's core during its
+/// exit, before the PC is advanced.
+#[link_section = ".hyp.text"]
+pub fn inject_data_abort(vcpu: &mut Vcpu, far: u64, write: bool) {
+    inject(vcpu, far, write, DFSC_EXT_ABORT);
+}
+
+/// Make the guest take the Stage-1 fault an access EL2 emulates for it
+/// would have raised: `fsc` is the fault status of the translation, as in
+/// PAR_EL1.FST. Same constraints as `inject_data_abort`.
+#[link_section = ".hyp.text"]
+pub fn inject_stage1_fault(vcpu: &mut Vcpu, far: u64, write: bool, fsc: u64) {
+    inject(vcpu, far, write, fsc);
+}
+
+#[link_section = ".hyp.text"]
+fn inject(vcpu: &mut Vcpu, far: u64, write: bool, dfsc: u64) {
+    let (esr, vector) = syndrome(vcpu.regs.spsr, write, dfsc);
+    let regs = sysregs();
+    regs.write(SysReg::EsrEl1, esr);
+    regs.write(SysReg::FarEl1, far);
+    regs.write(SysReg::ElrEl1, vcpu.regs.elr);
+    regs.write(SysReg::SpsrEl1, vcpu.regs.spsr);
+    vcpu.regs.elr = hyper::read_vbar_el1() + vector;
+    vcpu.regs.spsr = SPSR_EL1H_MASKED;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_data_abort_syndrome() {
+        assert_eq!(
+            data_abort_syndrome(0x3c5, false),
+            (0x9600_0010, VECTOR_CURRENT_SPX)
+        );
+        assert_eq!(
+            data_abort_syndrome(0x3c4, true),
+            (0x9600_0050, VECTOR_CURRENT_SP0)
+        );
+        assert_eq!(
+            data_abort_syndrome(0x0, false),
+            (0x9200_0010, VECTOR_LOWER_A64)
+        );
+        // AArch32 user mode.
+        assert_eq!(
+            data_abort_syndrome(0x10, true),
+            (0x9200_0050, VECTOR_LOWER_A32)
+        );
+    }
+}
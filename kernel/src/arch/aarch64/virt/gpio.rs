@@ -0,0 +1,253 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated 8-pin GPIO controller with the PL061 register layout, so guest
+//! drivers can be tested against signals driven from the host. The guest
+//! owns output pins; input pins are set by the host, and their edges or
+//! levels raise the controller's interrupt through the VGIC.
+
+use super::{
+    irq_line::{IrqLine, Trigger},
+    mmio::{self, BackendError, MmioDevice, MmioError},
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc};
+
+pub const NUM_PINS: usize = 8;
+pub const REGION_SIZE: u64 = 0x1000;
+
+const GPIODATA_END: u64 = 0x400;
+const GPIODIR: u64 = 0x400;
+const GPIOIS: u64 = 0x404;
+const GPIOIBE: u64 = 0x408;
+const GPIOIEV: u64 = 0x40c;
+const GPIOIE: u64 = 0x410;
+const GPIORIS: u64 = 0x414;
+const GPIOMIS: u64 = 0x418;
+const GPIOIC: u64 = 0x41c;
+const GPIOAFSEL: u64 = 0x420;
+const ID_START: u64 = 0xfe0;
+// GPIOPeriphID0-3 then GPIOPCellID0-3.
+const ID: [u8; 8] = [0x61, 0x10, 0x04, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpioError {
+    NoSuchPin,
+    /// The guest drives the pin.
+    NotInput,
+}
+
+#[derive(Default)]
+struct Regs {
+    // Output latch written by the guest.
+    data: u8,
+    // Levels the host drives onto the pins.
+    input: u8,
+    dir: u8,
+    is: u8,
+    ibe: u8,
+    iev: u8,
+    ie: u8,
+    ris: u8,
+    afsel: u8,
+}
+
+impl Regs {
+    fn levels(&self) -> u8 {
+        (self.data & self.dir) | (self.input & !self.dir)
+    }
+
+    // Level-sensitive pins follow their level; edge status stays latched
+    // until GPIOIC clears it.
+    fn update_level_status(&mut self) {
+        let active = !(self.levels() ^ self.iev);
+        self.ris = (self.ris & !self.is) | (active & self.is);
+    }
+
+    fn mis(&self) -> u8 {
+        self.ris & self.ie
+    }
+}
+
+pub struct Gpio {
+    line: IrqLine,
+    regs: SpinLock<Regs>,
+}
+
+impl Gpio {
+    pub fn new(vm_id: usize, intid: u32) -> Self {
+        Self {
+            line: IrqLine::new(vm_id, intid, Trigger::Level),
+            regs: SpinLock::new(Regs::default()),
+        }
+    }
+
+    /// Pin levels, one bit per pin: outputs as the guest set them, inputs as
+    /// the host drives them.
+    pub fn levels(&self) -> u8 {
+        self.regs.irqsave_lock().levels()
+    }
+
+    /// Pins the guest configured as outputs.
+    pub fn outputs(&self) -> u8 {
+        self.regs.irqsave_lock().dir
+    }
+
+    /// Interrupt status before masking, GPIORIS.
+    pub fn raw_status(&self) -> u8 {
+        self.regs.irqsave_lock().ris
+    }
+
+    /// Drive input `pin` from the host.
+    pub fn set_input(&self, pin: usize, high: bool) -> Result<(), GpioError> {
+        if pin >= NUM_PINS {
+            return Err(GpioError::NoSuchPin);
+        }
+        let bit = 1 << pin;
+        self.update(|regs| {
+            if regs.dir & bit != 0 {
+                return Err(GpioError::NotInput);
+            }
+            let old = regs.input;
+            if high {
+                regs.input |= bit;
+            } else {
+                regs.input &= !bit;
+            }
+            let changed = (old ^ regs.input) & !regs.is;
+            let rising = changed & regs.input;
+            let wanted = regs.ibe | !(rising ^ regs.iev);
+            regs.ris |= changed & wanted;
+            Ok(())
+        })
+    }
+
+    // Apply `f` and follow the change on the interrupt line. Like the
+    // PL061's combined line, it is high while GPIOMIS is non-zero. The line
+    // is driven under the lock so concurrent updates can't reorder levels.
+    fn update<R>(&self, f: impl FnOnce(&mut Regs) -> R) -> R {
+        let mut regs = self.regs.irqsave_lock();
+        let was_pending = regs.mis() != 0;
+        let ret = f(&mut regs);
+        regs.update_level_status();
+        let pending = regs.mis() != 0;
+        if pending != was_pending {
+            // An interrupt raised by the guest's own access is taken on its
+            // next entry.
+            let _ = self.line.set(pending);
+        }
+        ret
+    }
+}
+
+impl MmioDevice for Gpio {
+    fn read(&self, offset: u64, _size: u8) -> Result<u64, BackendError> {
+        let regs = self.regs.irqsave_lock();
+        let val = match offset {
+            // Address bits [9:2] mask the pins a GPIODATA access covers.
+            0..GPIODATA_END => regs.levels() & (offset >> 2) as u8,
+            GPIODIR => regs.dir,
+            GPIOIS => regs.is,
+            GPIOIBE => regs.ibe,
+            GPIOIEV => regs.iev,
+            GPIOIE => regs.ie,
+            GPIORIS => regs.ris,
+            GPIOMIS => regs.mis(),
+            GPIOAFSEL => regs.afsel,
+            ID_START.. if offset < REGION_SIZE && offset % 4 == 0 => {
+                ID[((offset - ID_START) / 4) as usize]
+            }
+            _ => 0,
+        };
+        Ok(val as u64)
+    }
+
+    fn write(&self, offset: u64, _size: u8, value: u64) -> Result<(), BackendError> {
+        let val = value as u8;
+        self.update(|regs| match offset {
+            0..GPIODATA_END => {
+                let mask = (offset >> 2) as u8;
+                regs.data = (regs.data & !mask) | (val & mask);
+            }
+            GPIODIR => regs.dir = val,
+            GPIOIS => regs.is = val,
+            GPIOIBE => regs.ibe = val,
+            GPIOIEV => regs.iev = val,
+            GPIOIE => regs.ie = val,
+            GPIOIC => regs.ris &= !val,
+            GPIOAFSEL => regs.afsel = val,
+            _ => {}
+        });
+        Ok(())
+    }
+}
+
+// Controller of each VM with the IPA it sits at.
+static GPIOS: SpinLock<BTreeMap<usize, (u64, Arc<Gpio>)>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` a GPIO controller at IPA `base` raising `intid`.
+pub fn create(vm_id: usize, base: u64, intid: u32) -> Result<Arc<Gpio>, MmioError> {
+    let gpio = Arc::new(Gpio::new(vm_id, intid));
+    mmio::register(vm_id, base, REGION_SIZE, gpio.clone())?;
+    GPIOS.irqsave_lock().insert(vm_id, (base, gpio.clone()));
+    Ok(gpio)
+}
+
+/// Remove the controller of VM `vm_id` from the guest's address space.
+pub fn destroy(vm_id: usize) {
+    if let Some((base, _)) = GPIOS.irqsave_lock().remove(&vm_id) {
+        mmio::unregister(vm_id, base);
+    }
+}
+
+pub fn get(vm_id: usize) -> Option<Arc<Gpio>> {
+    GPIOS
+        .irqsave_lock()
+        .get(&vm_id)
+        .map(|(_, gpio)| gpio.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_gpio_edges() {
+        let gpio = Gpio::new(usize::MAX, 40);
+        // Pin 0 output, both edges on pin 1, rising edges on pin 2.
+        gpio.write(GPIODIR, 4, 0x01).unwrap();
+        gpio.write(GPIOIBE, 4, 0x02).unwrap();
+        gpio.write(GPIOIEV, 4, 0x04).unwrap();
+        gpio.write(GPIOIE, 4, 0x06).unwrap();
+
+        gpio.write(0x3fc, 4, 0xff).unwrap();
+        assert_eq!(gpio.levels(), 0x01);
+        assert_eq!(gpio.set_input(0, true), Err(GpioError::NotInput));
+        assert_eq!(gpio.set_input(8, true), Err(GpioError::NoSuchPin));
+
+        gpio.set_input(2, true).unwrap();
+        gpio.set_input(1, true).unwrap();
+        assert_eq!(gpio.read(GPIOMIS, 4), Ok(0x06));
+        gpio.write(GPIOIC, 4, 0x06).unwrap();
+        gpio.set_input(2, false).unwrap();
+        gpio.set_input(1, false).unwrap();
+        assert_eq!(gpio.read(GPIORIS, 4), Ok(0x02));
+        // Only pin 2 is visible through this GPIODATA address.
+        assert_eq!(gpio.read(0x4 << 2, 4), Ok(0));
+        gpio.set_input(2, true).unwrap();
+        assert_eq!(gpio.read(0x4 << 2, 4), Ok(0x04));
+        assert_eq!(gpio.read(0xfe0, 4), Ok(0x61));
+    }
+}
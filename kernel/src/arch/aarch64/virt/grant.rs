@@ -0,0 +1,522 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pages a guest explicitly shares. With `GUEST_HVC_GRANT` a guest hands one
+//! of its pages to the host or to another VM, with the access it allows,
+//! and gets a reference to pass along; `GUEST_HVC_REVOKE` takes the page
+//! back once nobody has it mapped. Paravirt services go through grants
+//! instead of reading guest RAM wherever the guest points them.
+//!
+//! Grants are created and revoked at EL2, so the table has a fixed size.
+//! Mapping a grant happens in the host: `map` gives host code a checked
+//! window on the page, `map_for_vm` one on a page granted to another VM
+//! for copies on its behalf, and `map_into_vm` adds it to the Stage-2
+//! tables of the peer VM when the peer asks with `GUEST_HVC_GRANT_MAP`.
+//! All map the page as shared memory, see `shmem` for the memory model the
+//! two sides follow. The grants of a VM die with it, see `release_vm`.
+
+use super::{
+    abi::{
+        ERR_BUSY, ERR_DENIED, ERR_INVALID, ERR_NOT_FOUND, ERR_NOT_OWNED, ERR_NO_SLOT,
+        GUEST_HVC_GRANT_MAP,
+    },
+    audit::{self, Initiator, Operation},
+    identity,
+    shmem::{self, STAGE2_MEM},
+    stage2::{self, S2Perms, Stage2Error, PAGE_SIZE},
+    vcpu::Vcpu,
+    vlog::vlog,
+    vm::vm_manager,
+};
+use crate::sync::SpinLock;
+
+/// Live grants across all VMs.
+pub const MAX_GRANTS: usize = 64;
+
+pub use super::abi::{GRANT_READ, GRANT_WRITE, PEER_HOST};
+
+const SLOT_BITS: u32 = 8;
+const SLOT_MASK: u64 = (1 << SLOT_BITS) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantError {
+    /// Bad flags, an IPA that isn't page aligned or a peer VM that doesn't
+    /// exist.
+    Invalid,
+    /// The page isn't mapped in the owner, or with less access than asked.
+    NotOwned,
+    NoSlot,
+    /// The reference is stale, someone else's or for another peer.
+    NoSuchGrant,
+    /// Still mapped by its peer.
+    Busy,
+    /// The access isn't allowed by the grant.
+    Denied,
+    /// The host doesn't map the page as shared memory.
+    Incoherent,
+    Stage2(Stage2Error),
+}
+
+impl GrantError {
+    /// Value returned to the guest in x0.
+    pub fn code(self) -> u64 {
+        let code: i64 = match self {
+            GrantError::Invalid => ERR_INVALID,
+            GrantError::NotOwned => ERR_NOT_OWNED,
+            GrantError::NoSlot => ERR_NO_SLOT,
+            GrantError::NoSuchGrant => ERR_NOT_FOUND,
+            GrantError::Busy => ERR_BUSY,
+            GrantError::Denied | GrantError::Incoherent | GrantError::Stage2(_) => ERR_DENIED,
+        };
+        code as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Host,
+    Vm(usize),
+    /// The peer VM was torn down. Nobody can map the grant any more, not
+    /// even a new VM given the same id, and it waits for its owner to
+    /// revoke it.
+    Gone,
+}
+
+impl Peer {
+    // `None` for a VM that doesn't exist.
+    fn from_raw(raw: u64) -> Option<Self> {
+        match raw {
+            PEER_HOST => Some(Peer::Host),
+            vm_id => {
+                let vm_id = vm_id as usize;
+                vm_manager().contains(vm_id).then_some(Peer::Vm(vm_id))
+            }
+        }
+    }
+}
+
+/// What guests pass around to name a grant: a slot and the generation of
+/// the slot, so a reference dies with its grant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrantRef(pub u64);
+
+impl GrantRef {
+    fn new(slot: usize, generation: u32) -> Self {
+        Self(((generation as u64) << SLOT_BITS) | slot as u64)
+    }
+
+    fn slot(self) -> usize {
+        (self.0 & SLOT_MASK) as usize
+    }
+
+    fn generation(self) -> u32 {
+        (self.0 >> SLOT_BITS) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Grant {
+    owner: usize,
+    pa: u64,
+    peer: Peer,
+    write: bool,
+    // Host windows currently open on the page.
+    host_maps: usize,
+    // IPA the page is mapped at in the peer VM.
+    peer_ipa: Option<u64>,
+}
+
+impl Grant {
+    fn is_mapped(&self) -> bool {
+        self.host_maps != 0 || self.peer_ipa.is_some()
+    }
+}
+
+struct GrantTable {
+    slots: [Option<Grant>; MAX_GRANTS],
+    generations: [u32; MAX_GRANTS],
+}
+
+impl GrantTable {
+    const fn new() -> Self {
+        Self {
+            slots: [None; MAX_GRANTS],
+            generations: [0; MAX_GRANTS],
+        }
+    }
+
+    fn insert(&mut self, grant: Grant) -> Result<GrantRef, GrantError> {
+        let slot = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(GrantError::NoSlot)?;
+        self.slots[slot] = Some(grant);
+        self.generations[slot] = self.generations[slot].wrapping_add(1);
+        Ok(GrantRef::new(slot, self.generations[slot]))
+    }
+
+    fn get(&mut self, gref: GrantRef, owner: usize) -> Result<&mut Grant, GrantError> {
+        let slot = gref.slot();
+        if slot >= MAX_GRANTS || self.generations[slot] != gref.generation() {
+            return Err(GrantError::NoSuchGrant);
+        }
+        self.slots[slot]
+            .as_mut()
+            .filter(|g| g.owner == owner)
+            .ok_or(GrantError::NoSuchGrant)
+    }
+
+    fn remove(&mut self, gref: GrantRef, owner: usize) -> Result<(), GrantError> {
+        if self.get(gref, owner)?.is_mapped() {
+            return Err(GrantError::Busy);
+        }
+        self.slots[gref.slot()] = None;
+        Ok(())
+    }
+}
+
+static GRANTS: SpinLock<GrantTable> = SpinLock::new(GrantTable::new());
+
+/// Share the page at `ipa` of VM `owner` with `peer`. Runs at EL2 on the
+/// owner's hypercall.
+pub fn create(owner: usize, ipa: u64, peer: u64, flags: u64) -> Result<GrantRef, GrantError> {
+    if ipa % PAGE_SIZE != 0 || flags & !(GRANT_READ | GRANT_WRITE) != 0 || flags == 0 {
+        return Err(GrantError::Invalid);
+    }
+    let write = flags & GRANT_WRITE != 0;
+    let peer = Peer::from_raw(peer).ok_or(GrantError::Invalid)?;
+    if peer == Peer::Vm(owner) {
+        return Err(GrantError::Invalid);
+    }
+    // Only shared memory the owner itself may access that way.
+    let pa = stage2::with_vm(owner, |s2| match s2.attrs(ipa) {
+        Some((STAGE2_MEM, perms)) if perms.read && (perms.write || !write) => {
+            s2.lookup(ipa).map(|(pa, _)| pa)
+        }
+        _ => None,
+    })
+    .flatten()
+    .ok_or(GrantError::NotOwned)?;
+    let gref = GRANTS.irqsave_lock().insert(Grant {
+        owner,
+        pa,
+        peer,
+        write,
+        host_maps: 0,
+        peer_ipa: None,
+    })?;
+    vlog!(
+        Stage2,
+        Debug,
+        "[grant] {} shares ipa {:#x} with {:?}, ref {:#x}",
+        identity::tag(owner),
+        ipa,
+        peer,
+        gref.0
+    );
+    Ok(gref)
+}
+
+/// Take a page back. Fails with `Busy` while the peer has it mapped.
+pub fn revoke(owner: usize, gref: GrantRef) -> Result<(), GrantError> {
+    GRANTS.irqsave_lock().remove(gref, owner)
+}
+
+/// Drop every grant VM `vm_id` made, unmapping them from peer VMs, and
+/// cut it off from those made for it, which are left `Peer::Gone` for
+/// their owners to revoke. Host
+/// windows still open keep pointing at the page, so a VM must only go away
+/// once its device models are gone. Called as the VM is torn down.
+pub fn release_vm(vm_id: usize) {
+    let mut grants = GRANTS.irqsave_lock();
+    for slot in grants.slots.iter_mut() {
+        let Some(grant) = slot.as_mut() else {
+            continue;
+        };
+        if grant.owner == vm_id {
+            if let (Peer::Vm(peer), Some(ipa)) = (grant.peer, grant.peer_ipa) {
+                let _ = stage2::with_vm(peer, |s2| s2.unmap(ipa, PAGE_SIZE));
+            }
+            *slot = None;
+        } else if grant.peer == Peer::Vm(vm_id) {
+            grant.peer = Peer::Gone;
+            grant.peer_ipa = None;
+        }
+    }
+}
+
+/// Host window on a page granted to the host. The page stays granted
+/// until the window is dropped.
+pub struct GrantMapping {
+    owner: usize,
+    gref: GrantRef,
+    pa: u64,
+    write: bool,
+}
+
+impl GrantMapping {
+    pub fn is_writable(&self) -> bool {
+        self.write
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), GrantError> {
+        match offset.checked_add(len) {
+            Some(end) if end as u64 <= PAGE_SIZE => Ok(()),
+            _ => Err(GrantError::Denied),
+        }
+    }
+
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), GrantError> {
+        self.check(offset, buf.len())?;
+        // Host memory is identity mapped.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                (self.pa as usize + offset) as *const u8,
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, offset: usize, buf: &[u8]) -> Result<(), GrantError> {
+        if !self.write {
+            return Err(GrantError::Denied);
+        }
+        self.check(offset, buf.len())?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                buf.as_ptr(),
+                (self.pa as usize + offset) as *mut u8,
+                buf.len(),
+            );
+        }
+        Ok(())
+    }
+
+    // Host address of the ring index at `offset`.
+    fn index(&self, offset: usize) -> Result<usize, GrantError> {
+        if offset % 4 != 0 {
+            return Err(GrantError::Denied);
+        }
+        self.check(offset, 4)?;
+        Ok(self.pa as usize + offset)
+    }
+
+    /// Read the ring index at `offset` the guest publishes, see
+    /// `shmem::load_acquire`.
+    pub fn load_index(&self, offset: usize) -> Result<u32, GrantError> {
+        let addr = self.index(offset)?;
+        Ok(unsafe { shmem::load_acquire(addr as *const u32) })
+    }
+
+    /// Publish the ring index at `offset`, see `shmem::store_release`.
+    pub fn store_index(&self, offset: usize, value: u32) -> Result<(), GrantError> {
+        if !self.write {
+            return Err(GrantError::Denied);
+        }
+        let addr = self.index(offset)?;
+        unsafe { shmem::store_release(addr as *mut u32, value) };
+        Ok(())
+    }
+
+    /// Make the page coherent with a guest that maps it non-cacheable:
+    /// what the host wrote is cleaned to memory and what the guest wrote
+    /// is read from it next.
+    pub fn sync_uncached(&self) {
+        unsafe { shmem::clean_invalidate_dcache(self.pa as usize, PAGE_SIZE as usize) };
+    }
+}
+
+impl Drop for GrantMapping {
+    fn drop(&mut self) {
+        if let Ok(grant) = GRANTS.irqsave_lock().get(self.gref, self.owner) {
+            grant.host_maps -= 1;
+        }
+    }
+}
+
+/// Open a host window on grant `gref` of VM `owner`.
+pub fn map(owner: usize, gref: GrantRef) -> Result<GrantMapping, GrantError> {
+    open(owner, gref, Peer::Host)
+}
+
+/// Open a host window on grant `gref` of VM `owner`, made for VM `peer`,
+/// to copy on the peer's behalf. The page isn't mapped in the peer.
+pub fn map_for_vm(owner: usize, gref: GrantRef, peer: usize) -> Result<GrantMapping, GrantError> {
+    open(owner, gref, Peer::Vm(peer))
+}
+
+fn open(owner: usize, gref: GrantRef, peer: Peer) -> Result<GrantMapping, GrantError> {
+    let mut grants = GRANTS.irqsave_lock();
+    let grant = grants.get(gref, owner)?;
+    if grant.peer != peer {
+        return Err(GrantError::NoSuchGrant);
+    }
+    if !shmem::host_coherent(grant.pa, PAGE_SIZE) {
+        return Err(GrantError::Incoherent);
+    }
+    grant.host_maps += 1;
+    Ok(GrantMapping {
+        owner,
+        gref,
+        pa: grant.pa,
+        write: grant.write,
+    })
+}
+
+/// Take the hypercall `vcpu` exited with, `GUEST_HVC_GRANT_MAP` or
+/// `GUEST_HVC_GRANT_UNMAP` as `imm`: owner in x0, grant in x1 and, to map,
+/// the IPA in x2. Leaves the result in x0. Called by the vCPU's run loop.
+pub fn handle_map(vcpu: &mut Vcpu, imm: u16) {
+    let [owner, gref, ipa] = [vcpu.regs.x[0], vcpu.regs.x[1], vcpu.regs.x[2]];
+    let (owner, gref) = (owner as usize, GrantRef(gref));
+    let result = if imm == GUEST_HVC_GRANT_MAP {
+        map_into_vm(owner, gref, vcpu.vm_id, ipa)
+    } else {
+        unmap_from_vm(owner, gref, vcpu.vm_id)
+    };
+    vcpu.regs.x[0] = match result {
+        Ok(()) => 0,
+        Err(e) => e.code(),
+    };
+}
+
+/// Map grant `gref` of VM `owner` at `ipa` of the peer VM it was made
+/// for, where nothing is mapped yet. Must be called from thread context,
+/// since Stage-2 tables may have to grow.
+pub fn map_into_vm(owner: usize, gref: GrantRef, peer: usize, ipa: u64) -> Result<(), GrantError> {
+    if ipa % PAGE_SIZE != 0 {
+        return Err(GrantError::Invalid);
+    }
+    let mut grants = GRANTS.irqsave_lock();
+    let grant = grants.get(gref, owner)?;
+    if grant.peer != Peer::Vm(peer) {
+        return Err(GrantError::NoSuchGrant);
+    }
+    if grant.peer_ipa.is_some() {
+        return Err(GrantError::Busy);
+    }
+    let perms = S2Perms::new(true, grant.write, false);
+    stage2::with_vm(peer, |s2| {
+        if s2.lookup(ipa).is_some() {
+            return Err(GrantError::Invalid);
+        }
+        s2.map(ipa, grant.pa, PAGE_SIZE, STAGE2_MEM, perms)
+            .map_err(GrantError::Stage2)
+    })
+    .ok_or(GrantError::NoSuchGrant)??;
+    grant.peer_ipa = Some(ipa);
+    let op = Operation::GrantMap {
+        owner,
+        gref: gref.0,
+        ipa,
+    };
+    audit::record(peer, audit::label_of(peer), Initiator::host(), op);
+    Ok(())
+}
+
+/// Undo `map_into_vm` in `peer`, letting the owner revoke the grant.
+pub fn unmap_from_vm(owner: usize, gref: GrantRef, peer: usize) -> Result<(), GrantError> {
+    let mut grants = GRANTS.irqsave_lock();
+    let grant = grants.get(gref, owner)?;
+    let Some(ipa) = grant.peer_ipa.filter(|_| grant.peer == Peer::Vm(peer)) else {
+        return Err(GrantError::NoSuchGrant);
+    };
+    if let Some(Err(e)) = stage2::with_vm(peer, |s2| s2.unmap(ipa, PAGE_SIZE)) {
+        return Err(GrantError::Stage2(e));
+    }
+    grant.peer_ipa = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    fn grant(owner: usize) -> Grant {
+        Grant {
+            owner,
+            pa: 0x8000_0000,
+            peer: Peer::Host,
+            write: false,
+            host_maps: 0,
+            peer_ipa: None,
+        }
+    }
+
+    #[test]
+    fn test_grant_refs() {
+        let mut table = GrantTable::new();
+        let a = table.insert(grant(1)).unwrap();
+        assert_eq!(table.get(a, 2).err(), Some(GrantError::NoSuchGrant));
+
+        table.get(a, 1).unwrap().host_maps = 1;
+        assert_eq!(table.remove(a, 1), Err(GrantError::Busy));
+        table.get(a, 1).unwrap().host_maps = 0;
+        assert_eq!(table.remove(a, 1), Ok(()));
+
+        // The slot is reused, the old reference isn't.
+        let b = table.insert(grant(1)).unwrap();
+        assert_eq!(a.slot(), b.slot());
+        assert_eq!(table.remove(a, 1), Err(GrantError::NoSuchGrant));
+        assert!(table.get(b, 1).is_ok());
+    }
+
+    #[test]
+    fn test_grant_table_full() {
+        let mut table = GrantTable::new();
+        for _ in 0..MAX_GRANTS {
+            table.insert(grant(1)).unwrap();
+        }
+        assert_eq!(table.insert(grant(1)), Err(GrantError::NoSlot));
+    }
+
+    #[test]
+    fn test_release_vm() {
+        let (dying, other) = (test_vm_id(), test_vm_id());
+        let mapped = |owner, peer| Grant {
+            peer: Peer::Vm(peer),
+            peer_ipa: Some(0x4000_0000),
+            ..grant(owner)
+        };
+        let own = GRANTS.irqsave_lock().insert(mapped(dying, other)).unwrap();
+        let received = GRANTS.irqsave_lock().insert(mapped(other, dying)).unwrap();
+
+        release_vm(dying);
+        assert_eq!(
+            GRANTS.irqsave_lock().get(own, dying).err(),
+            Some(GrantError::NoSuchGrant)
+        );
+        // A VM reusing the id gets nothing from its predecessor.
+        assert_eq!(
+            GRANTS.irqsave_lock().get(received, other).unwrap().peer,
+            Peer::Gone
+        );
+        assert_eq!(
+            map_for_vm(other, received, dying).err(),
+            Some(GrantError::NoSuchGrant)
+        );
+        assert_eq!(revoke(other, received), Ok(()));
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        assert_eq!(Peer::from_raw(PEER_HOST), Some(Peer::Host));
+        assert_eq!(Peer::from_raw(test_vm_id() as u64), None);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host access to guest memory by IPA. Every access is translated through
+//! the VM's Stage-2 tables, so a guest can't make the host touch memory that
+//! isn't mapped into it.
+
+use super::{
+    lazy_ram,
+    stage2::{self, Stage2, PAGE_SIZE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestMemError {
+    /// The IPA isn't mapped into the guest.
+    Unmapped(u64),
+    /// The VM has no Stage-2 tables.
+    NoSuchVm,
+    /// The IPA is lazy RAM still on the zero page. `VmMemory` populates it
+    /// before writing.
+    NotPopulated(u64),
+    /// Lazy RAM couldn't be populated.
+    NoMemory,
+}
+
+/// Byte-level access to guest memory. Multi-byte helpers are little endian,
+/// as used by virtio and most guest/host protocols.
+pub trait GuestMemory {
+    fn read(&self, ipa: u64, buf: &mut [u8]) -> Result<(), GuestMemError>;
+    fn write(&self, ipa: u64, buf: &[u8]) -> Result<(), GuestMemError>;
+
+    fn read_u16(&self, ipa: u64) -> Result<u16, GuestMemError> {
+        let mut b = [0; 2];
+        self.read(ipa, &mut b)?;
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn read_u32(&self, ipa: u64) -> Result<u32, GuestMemError> {
+        let mut b = [0; 4];
+        self.read(ipa, &mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_u64(&self, ipa: u64) -> Result<u64, GuestMemError> {
+        let mut b = [0; 8];
+        self.read(ipa, &mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn write_u16(&self, ipa: u64, val: u16) -> Result<(), GuestMemError> {
+        self.write(ipa, &val.to_le_bytes())
+    }
+
+    fn write_u32(&self, ipa: u64, val: u32) -> Result<(), GuestMemError> {
+        self.write(ipa, &val.to_le_bytes())
+    }
+
+    fn write_u64(&self, ipa: u64, val: u64) -> Result<(), GuestMemError> {
+        self.write(ipa, &val.to_le_bytes())
+    }
+}
+
+impl Stage2 {
+    // Call `f(host_addr, offset, len)` for every page sized piece of
+    // `[ipa, ipa + len)`, after checking the whole range is mapped.
+    fn for_each_chunk(
+        &self,
+        ipa: u64,
+        len: usize,
+        mut f: impl FnMut(u64, usize, usize),
+    ) -> Result<(), GuestMemError> {
+        let end = ipa
+            .checked_add(len as u64)
+            .ok_or(GuestMemError::Unmapped(ipa))?;
+        let mut cur = ipa;
+        while cur < end {
+            self.lookup(cur).ok_or(GuestMemError::Unmapped(cur))?;
+            cur = (cur & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+        }
+        let mut cur = ipa;
+        while cur < end {
+            let next = ((cur & !(PAGE_SIZE - 1)) + PAGE_SIZE).min(end);
+            let (pa, _) = self.lookup(cur).ok_or(GuestMemError::Unmapped(cur))?;
+            f(pa, (cur - ipa) as usize, (next - cur) as usize);
+            cur = next;
+        }
+        Ok(())
+    }
+}
+
+// Host memory is identity mapped, so output addresses are directly usable.
+impl GuestMemory for Stage2 {
+    fn read(&self, ipa: u64, buf: &mut [u8]) -> Result<(), GuestMemError> {
+        let dst = buf.as_mut_ptr();
+        self.for_each_chunk(ipa, buf.len(), |pa, off, len| unsafe {
+            core::ptr::copy_nonoverlapping(pa as *const u8, dst.add(off), len);
+        })
+    }
+
+    fn write(&self, ipa: u64, buf: &[u8]) -> Result<(), GuestMemError> {
+        let mut shared = None;
+        self.for_each_chunk(ipa, buf.len(), |pa, off, _| {
+            if pa & !(PAGE_SIZE - 1) == lazy_ram::zero_pa() && shared.is_none() {
+                shared = Some(ipa + off as u64);
+            }
+        })?;
+        if let Some(ipa) = shared {
+            return Err(GuestMemError::NotPopulated(ipa));
+        }
+        self.for_each_chunk(ipa, buf.len(), |pa, off, len| unsafe {
+            core::ptr::copy_nonoverlapping(buf.as_ptr().add(off), pa as *mut u8, len);
+        })
+    }
+}
+
+/// Memory of a VM looked up by id on every access.
+#[derive(Debug, Clone, Copy)]
+pub struct VmMemory(pub usize);
+
+impl GuestMemory for VmMemory {
+    fn read(&self, ipa: u64, buf: &mut [u8]) -> Result<(), GuestMemError> {
+        stage2::with_vm(self.0, |s2| s2.read(ipa, buf)).unwrap_or(Err(GuestMemError::NoSuchVm))
+    }
+
+    fn write(&self, ipa: u64, buf: &[u8]) -> Result<(), GuestMemError> {
+        lazy_ram::populate_range(self.0, ipa, buf.len()).map_err(|_| GuestMemError::NoMemory)?;
+        stage2::with_vm(self.0, |s2| s2.write(ipa, buf)).unwrap_or(Err(GuestMemError::NoSuchVm))
+    }
+}
@@ -0,0 +1,621 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hardware the hypervisor touches: EL2 system registers, the guest EL1
+//! registers EL2 edits, and the GIC CPU interface. Virt code reaches them
+//! through `sysregs()` and `gic()` only. Normal builds get `Native`, which
+//! issues the instructions; `Mock` keeps the registers in memory.
+//! `cfg(test)` builds still use `Native` unless a test
+//! opts into `Mock` with `with_mock`, so vGIC, exit and vCPU logic can run
+//! without EL2 while every other test keeps the real hardware.
+
+use super::alternative::alternative;
+use crate::arch::aarch64::sysreg::{read_sysreg, write_sysreg};
+#[cfg(test)]
+use crate::scheduler;
+use core::arch::asm;
+#[cfg(test)]
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysReg {
+    HcrEl2,
+    VbarEl2,
+    VmpidrEl2,
+    VpidrEl2,
+    VtcrEl2,
+    VttbrEl2,
+    CntvoffEl2,
+    CnthctlEl2,
+    EsrEl2,
+    ElrEl2,
+    FarEl2,
+    HpfarEl2,
+    CntpctEl0,
+    /// Read only.
+    IdAa64mmfr0El1,
+    /// Read only.
+    CtrEl0,
+    VbarEl1,
+    EsrEl1,
+    FarEl1,
+    ElrEl1,
+    SpsrEl1,
+    SctlrEl1,
+    CpacrEl1,
+    Ttbr0El1,
+    Ttbr1El1,
+    TcrEl1,
+    Afsr0El1,
+    Afsr1El1,
+    MairEl1,
+    AmairEl1,
+    ContextidrEl1,
+    CntkctlEl1,
+    CntvCvalEl0,
+    CntvCtlEl0,
+    ParEl1,
+    TpidrEl1,
+    SpEl0,
+    TpidrEl0,
+    TpidrroEl0,
+    CsselrEl1,
+    /// Read only.
+    DczidEl0,
+    /// Read only.
+    ClidrEl1,
+    /// Read only.
+    CcsidrEl1,
+}
+
+impl SysReg {
+    pub const COUNT: usize = 42;
+}
+
+pub trait SysRegBackend: Sync {
+    fn read(&self, reg: SysReg) -> u64;
+    fn write(&self, reg: SysReg, val: u64);
+    /// Make earlier register writes take effect.
+    fn isb(&self);
+    /// PAR_EL1 of a Stage-1 EL1 read translation of `va`, leaving PAR_EL1
+    /// itself untouched.
+    fn translate_el1_read(&self, va: u64) -> u64;
+    /// PAR_EL1 of a Stage-1 write translation of `va` with the permissions
+    /// of EL0 or EL1, leaving PAR_EL1 itself untouched.
+    fn translate_el1_write(&self, va: u64, el0: bool) -> u64;
+}
+
+pub trait GicBackend: Sync {
+    fn read_lr(&self, n: usize) -> u64;
+    fn write_lr(&self, n: usize, val: u64);
+    /// ICH_ELRSR_EL2: one bit per list register holding no interrupt.
+    fn elrsr(&self) -> u64;
+    fn vtr(&self) -> u64;
+    fn read_vmcr(&self) -> u64;
+    fn write_vmcr(&self, val: u64);
+    /// ICH_AP0R<n>_EL2 and ICH_AP1R<n>_EL2, the active priorities of the
+    /// guest's Group 0 and Group 1 interrupts.
+    fn read_apr(&self, group1: bool, n: usize) -> u64;
+    fn write_apr(&self, group1: bool, n: usize, val: u64);
+    fn write_hcr(&self, val: u64);
+    /// Highest priority pending group 1 interrupt, left pending.
+    fn highest_pending(&self) -> u32;
+    fn ack(&self) -> u32;
+    fn eoi(&self, intid: u32);
+}
+
+pub struct Native;
+
+// Guest EL1 registers as seen from EL2: the register itself under nVHE,
+// its `*_EL12` alias (given by encoding) under VHE, patched at boot. The
+// asm options are those of `read_sysreg!` and `write_sysreg!`.
+macro_rules! mrs_el1 {
+    ($reg:literal, $el12:literal) => {{
+        let v: u64;
+        unsafe {
+            asm!(
+                alternative!(
+                    concat!("mrs {0}, ", $reg),
+                    concat!("mrs {0}, ", $el12),
+                    Vhe
+                ),
+                out(reg) v,
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+        v
+    }};
+}
+
+macro_rules! msr_el1 {
+    ($reg:literal, $el12:literal, $v:expr) => {
+        unsafe {
+            asm!(
+                alternative!(
+                    concat!("msr ", $reg, ", {0}"),
+                    concat!("msr ", $el12, ", {0}"),
+                    Vhe
+                ),
+                in(reg) $v,
+                options(nostack, preserves_flags)
+            )
+        }
+    };
+}
+
+// PAR_EL1 of an address translation, with the register itself preserved.
+macro_rules! at {
+    ($op:literal, $va:expr) => {{
+        let par: u64;
+        unsafe {
+            asm!(
+                "mrs {saved}, par_el1",
+                concat!("at ", $op, ", {va}"),
+                "isb",
+                "mrs {par}, par_el1",
+                "msr par_el1, {saved}",
+                va = in(reg) $va,
+                saved = out(reg) _,
+                par = out(reg) par,
+                options(nostack)
+            );
+        }
+        par
+    }};
+}
+
+impl SysRegBackend for Native {
+    #[inline]
+    fn read(&self, reg: SysReg) -> u64 {
+        match reg {
+            SysReg::HcrEl2 => read_sysreg!("hcr_el2"),
+            SysReg::VbarEl2 => read_sysreg!("vbar_el2"),
+            SysReg::VmpidrEl2 => read_sysreg!("vmpidr_el2"),
+            SysReg::VpidrEl2 => read_sysreg!("vpidr_el2"),
+            SysReg::VtcrEl2 => read_sysreg!("vtcr_el2"),
+            SysReg::VttbrEl2 => read_sysreg!("vttbr_el2"),
+            SysReg::CntvoffEl2 => read_sysreg!("cntvoff_el2"),
+            SysReg::CnthctlEl2 => read_sysreg!("cnthctl_el2"),
+            SysReg::EsrEl2 => read_sysreg!("esr_el2"),
+            SysReg::ElrEl2 => read_sysreg!("elr_el2"),
+            SysReg::FarEl2 => read_sysreg!("far_el2"),
+            SysReg::HpfarEl2 => read_sysreg!("hpfar_el2"),
+            SysReg::CntpctEl0 => {
+                self.isb();
+                read_sysreg!("cntpct_el0")
+            }
+            SysReg::IdAa64mmfr0El1 => read_sysreg!("id_aa64mmfr0_el1"),
+            SysReg::CtrEl0 => read_sysreg!("ctr_el0"),
+            SysReg::VbarEl1 => mrs_el1!("vbar_el1", "s3_5_c12_c0_0"),
+            SysReg::EsrEl1 => mrs_el1!("esr_el1", "s3_5_c5_c2_0"),
+            SysReg::FarEl1 => mrs_el1!("far_el1", "s3_5_c6_c0_0"),
+            SysReg::ElrEl1 => mrs_el1!("elr_el1", "s3_5_c4_c0_1"),
+            SysReg::SpsrEl1 => mrs_el1!("spsr_el1", "s3_5_c4_c0_0"),
+            SysReg::SctlrEl1 => mrs_el1!("sctlr_el1", "s3_5_c1_c0_0"),
+            SysReg::CpacrEl1 => mrs_el1!("cpacr_el1", "s3_5_c1_c0_2"),
+            SysReg::Ttbr0El1 => mrs_el1!("ttbr0_el1", "s3_5_c2_c0_0"),
+            SysReg::Ttbr1El1 => mrs_el1!("ttbr1_el1", "s3_5_c2_c0_1"),
+            SysReg::TcrEl1 => mrs_el1!("tcr_el1", "s3_5_c2_c0_2"),
+            SysReg::Afsr0El1 => mrs_el1!("afsr0_el1", "s3_5_c5_c1_0"),
+            SysReg::Afsr1El1 => mrs_el1!("afsr1_el1", "s3_5_c5_c1_1"),
+            SysReg::MairEl1 => mrs_el1!("mair_el1", "s3_5_c10_c2_0"),
+            SysReg::AmairEl1 => mrs_el1!("amair_el1", "s3_5_c10_c3_0"),
+            SysReg::ContextidrEl1 => mrs_el1!("contextidr_el1", "s3_5_c13_c0_1"),
+            SysReg::CntkctlEl1 => mrs_el1!("cntkctl_el1", "s3_5_c14_c1_0"),
+            SysReg::CntvCvalEl0 => mrs_el1!("cntv_cval_el0", "s3_5_c14_c3_2"),
+            SysReg::CntvCtlEl0 => mrs_el1!("cntv_ctl_el0", "s3_5_c14_c3_1"),
+            // No EL12 aliases: under VHE these are the guest's anyway.
+            SysReg::ParEl1 => read_sysreg!("par_el1"),
+            SysReg::TpidrEl1 => read_sysreg!("tpidr_el1"),
+            SysReg::SpEl0 => read_sysreg!("sp_el0"),
+            SysReg::TpidrEl0 => read_sysreg!("tpidr_el0"),
+            SysReg::TpidrroEl0 => read_sysreg!("tpidrro_el0"),
+            SysReg::CsselrEl1 => read_sysreg!("csselr_el1"),
+            SysReg::DczidEl0 => read_sysreg!("dczid_el0"),
+            SysReg::ClidrEl1 => read_sysreg!("clidr_el1"),
+            SysReg::CcsidrEl1 => read_sysreg!("ccsidr_el1"),
+        }
+    }
+
+    #[inline]
+    fn write(&self, reg: SysReg, val: u64) {
+        match reg {
+            SysReg::HcrEl2 => write_sysreg!("hcr_el2", val),
+            SysReg::VbarEl2 => write_sysreg!("vbar_el2", val),
+            SysReg::VmpidrEl2 => write_sysreg!("vmpidr_el2", val),
+            SysReg::VpidrEl2 => write_sysreg!("vpidr_el2", val),
+            SysReg::VtcrEl2 => write_sysreg!("vtcr_el2", val),
+            SysReg::VttbrEl2 => write_sysreg!("vttbr_el2", val),
+            SysReg::CntvoffEl2 => write_sysreg!("cntvoff_el2", val),
+            SysReg::CnthctlEl2 => write_sysreg!("cnthctl_el2", val),
+            SysReg::EsrEl2 => write_sysreg!("esr_el2", val),
+            SysReg::ElrEl2 => write_sysreg!("elr_el2", val),
+            SysReg::FarEl2 => write_sysreg!("far_el2", val),
+            SysReg::HpfarEl2 => write_sysreg!("hpfar_el2", val),
+            SysReg::CntpctEl0
+            | SysReg::IdAa64mmfr0El1
+            | SysReg::CtrEl0
+            | SysReg::DczidEl0
+            | SysReg::ClidrEl1
+            | SysReg::CcsidrEl1 => {}
+            SysReg::VbarEl1 => msr_el1!("vbar_el1", "s3_5_c12_c0_0", val),
+            SysReg::EsrEl1 => msr_el1!("esr_el1", "s3_5_c5_c2_0", val),
+            SysReg::FarEl1 => msr_el1!("far_el1", "s3_5_c6_c0_0", val),
+            SysReg::ElrEl1 => msr_el1!("elr_el1", "s3_5_c4_c0_1", val),
+            SysReg::SpsrEl1 => msr_el1!("spsr_el1", "s3_5_c4_c0_0", val),
+            SysReg::SctlrEl1 => msr_el1!("sctlr_el1", "s3_5_c1_c0_0", val),
+            SysReg::CpacrEl1 => msr_el1!("cpacr_el1", "s3_5_c1_c0_2", val),
+            SysReg::Ttbr0El1 => msr_el1!("ttbr0_el1", "s3_5_c2_c0_0", val),
+            SysReg::Ttbr1El1 => msr_el1!("ttbr1_el1", "s3_5_c2_c0_1", val),
+            SysReg::TcrEl1 => msr_el1!("tcr_el1", "s3_5_c2_c0_2", val),
+            SysReg::Afsr0El1 => msr_el1!("afsr0_el1", "s3_5_c5_c1_0", val),
+            SysReg::Afsr1El1 => msr_el1!("afsr1_el1", "s3_5_c5_c1_1", val),
+            SysReg::MairEl1 => msr_el1!("mair_el1", "s3_5_c10_c2_0", val),
+            SysReg::AmairEl1 => msr_el1!("amair_el1", "s3_5_c10_c3_0", val),
+            SysReg::ContextidrEl1 => msr_el1!("contextidr_el1", "s3_5_c13_c0_1", val),
+            SysReg::CntkctlEl1 => msr_el1!("cntkctl_el1", "s3_5_c14_c1_0", val),
+            SysReg::CntvCvalEl0 => msr_el1!("cntv_cval_el0", "s3_5_c14_c3_2", val),
+            SysReg::CntvCtlEl0 => msr_el1!("cntv_ctl_el0", "s3_5_c14_c3_1", val),
+            SysReg::ParEl1 => write_sysreg!("par_el1", val),
+            SysReg::TpidrEl1 => write_sysreg!("tpidr_el1", val),
+            SysReg::SpEl0 => write_sysreg!("sp_el0", val),
+            SysReg::TpidrEl0 => write_sysreg!("tpidr_el0", val),
+            SysReg::TpidrroEl0 => write_sysreg!("tpidrro_el0", val),
+            SysReg::CsselrEl1 => write_sysreg!("csselr_el1", val),
+        }
+    }
+
+    #[inline]
+    fn isb(&self) {
+        unsafe { asm!("isb", options(nostack)) };
+    }
+
+    #[inline]
+    fn translate_el1_read(&self, va: u64) -> u64 {
+        at!("s1e1r", va)
+    }
+
+    #[inline]
+    fn translate_el1_write(&self, va: u64, el0: bool) -> u64 {
+        if el0 {
+            at!("s1e0w", va)
+        } else {
+            at!("s1e1w", va)
+        }
+    }
+}
+
+impl GicBackend for Native {
+    #[inline]
+    fn read_lr(&self, n: usize) -> u64 {
+        match n {
+            0 => read_sysreg!("ich_lr0_el2"),
+            1 => read_sysreg!("ich_lr1_el2"),
+            2 => read_sysreg!("ich_lr2_el2"),
+            _ => read_sysreg!("ich_lr3_el2"),
+        }
+    }
+
+    #[inline]
+    fn write_lr(&self, n: usize, val: u64) {
+        match n {
+            0 => write_sysreg!("ich_lr0_el2", val),
+            1 => write_sysreg!("ich_lr1_el2", val),
+            2 => write_sysreg!("ich_lr2_el2", val),
+            _ => write_sysreg!("ich_lr3_el2", val),
+        }
+    }
+
+    #[inline]
+    fn elrsr(&self) -> u64 {
+        read_sysreg!("ich_elrsr_el2")
+    }
+
+    #[inline]
+    fn vtr(&self) -> u64 {
+        read_sysreg!("ich_vtr_el2")
+    }
+
+    #[inline]
+    fn read_vmcr(&self) -> u64 {
+        read_sysreg!("ich_vmcr_el2")
+    }
+
+    #[inline]
+    fn write_vmcr(&self, val: u64) {
+        write_sysreg!("ich_vmcr_el2", val)
+    }
+
+    #[inline]
+    fn read_apr(&self, group1: bool, n: usize) -> u64 {
+        match (group1, n) {
+            (false, 0) => read_sysreg!("ich_ap0r0_el2"),
+            (false, 1) => read_sysreg!("ich_ap0r1_el2"),
+            (false, 2) => read_sysreg!("ich_ap0r2_el2"),
+            (false, _) => read_sysreg!("ich_ap0r3_el2"),
+            (true, 0) => read_sysreg!("ich_ap1r0_el2"),
+            (true, 1) => read_sysreg!("ich_ap1r1_el2"),
+            (true, 2) => read_sysreg!("ich_ap1r2_el2"),
+            (true, _) => read_sysreg!("ich_ap1r3_el2"),
+        }
+    }
+
+    #[inline]
+    fn write_apr(&self, group1: bool, n: usize, val: u64) {
+        match (group1, n) {
+            (false, 0) => write_sysreg!("ich_ap0r0_el2", val),
+            (false, 1) => write_sysreg!("ich_ap0r1_el2", val),
+            (false, 2) => write_sysreg!("ich_ap0r2_el2", val),
+            (false, _) => write_sysreg!("ich_ap0r3_el2", val),
+            (true, 0) => write_sysreg!("ich_ap1r0_el2", val),
+            (true, 1) => write_sysreg!("ich_ap1r1_el2", val),
+            (true, 2) => write_sysreg!("ich_ap1r2_el2", val),
+            (true, _) => write_sysreg!("ich_ap1r3_el2", val),
+        }
+    }
+
+    #[inline]
+    fn write_hcr(&self, val: u64) {
+        write_sysreg!("ich_hcr_el2", val)
+    }
+
+    #[inline]
+    fn highest_pending(&self) -> u32 {
+        read_sysreg!("icc_hppir1_el1") as u32
+    }
+
+    #[inline]
+    fn ack(&self) -> u32 {
+        read_sysreg!("icc_iar1_el1") as u32
+    }
+
+    #[inline]
+    fn eoi(&self, intid: u32) {
+        write_sysreg!("icc_eoir1_el1", intid as u64)
+    }
+}
+
+/// In-memory registers for tests. Translations are identity mappings and
+/// the GIC has one settable pending interrupt.
+#[cfg(test)]
+pub struct Mock {
+    regs: [AtomicU64; SysReg::COUNT],
+    lrs: [AtomicU64; Mock::NUM_LRS],
+    vmcr: AtomicU64,
+    aprs: [[AtomicU64; 4]; 2],
+    hcr: AtomicU64,
+    pending: AtomicU32,
+    last_eoi: AtomicU32,
+}
+
+#[cfg(test)]
+impl Mock {
+    pub const NUM_LRS: usize = 4;
+    /// INTID the GIC reports when nothing is pending.
+    pub const SPURIOUS: u32 = 1023;
+    const LR_STATE_MASK: u64 = 0b11 << 62;
+
+    const fn new() -> Self {
+        Self {
+            regs: [const { AtomicU64::new(0) }; SysReg::COUNT],
+            lrs: [const { AtomicU64::new(0) }; Mock::NUM_LRS],
+            vmcr: AtomicU64::new(0),
+            aprs: [const { [const { AtomicU64::new(0) }; 4] }; 2],
+            hcr: AtomicU64::new(0),
+            pending: AtomicU32::new(Self::SPURIOUS),
+            last_eoi: AtomicU32::new(Self::SPURIOUS),
+        }
+    }
+
+    /// Make `intid` the interrupt the next `ack` returns.
+    pub fn set_pending(&self, intid: u32) {
+        self.pending.store(intid, Ordering::Relaxed);
+    }
+
+    pub fn last_eoi(&self) -> u32 {
+        self.last_eoi.load(Ordering::Relaxed)
+    }
+
+    /// ICH_VMCR_EL2 and ICH_HCR_EL2 as last written.
+    pub fn vgic_ctrl(&self) -> (u64, u64) {
+        (
+            self.vmcr.load(Ordering::Relaxed),
+            self.hcr.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+impl SysRegBackend for Mock {
+    fn read(&self, reg: SysReg) -> u64 {
+        self.regs[reg as usize].load(Ordering::Relaxed)
+    }
+
+    fn write(&self, reg: SysReg, val: u64) {
+        self.regs[reg as usize].store(val, Ordering::Relaxed);
+    }
+
+    fn isb(&self) {}
+
+    fn translate_el1_read(&self, va: u64) -> u64 {
+        va & 0x0000_ffff_ffff_f000
+    }
+
+    fn translate_el1_write(&self, va: u64, _el0: bool) -> u64 {
+        va & 0x0000_ffff_ffff_f000
+    }
+}
+
+#[cfg(test)]
+impl GicBackend for Mock {
+    fn read_lr(&self, n: usize) -> u64 {
+        self.lrs[n].load(Ordering::Relaxed)
+    }
+
+    fn write_lr(&self, n: usize, val: u64) {
+        self.lrs[n].store(val, Ordering::Relaxed);
+    }
+
+    fn elrsr(&self) -> u64 {
+        (0..Self::NUM_LRS)
+            .filter(|&n| self.read_lr(n) & Self::LR_STATE_MASK == 0)
+            .fold(0, |mask, n| mask | (1 << n))
+    }
+
+    fn vtr(&self) -> u64 {
+        // Five preemption bits, so one active priority register per group.
+        (4 << 26) | (Self::NUM_LRS as u64 - 1)
+    }
+
+    fn read_vmcr(&self) -> u64 {
+        self.vmcr.load(Ordering::Relaxed)
+    }
+
+    fn write_vmcr(&self, val: u64) {
+        self.vmcr.store(val, Ordering::Relaxed);
+    }
+
+    fn read_apr(&self, group1: bool, n: usize) -> u64 {
+        self.aprs[group1 as usize][n].load(Ordering::Relaxed)
+    }
+
+    fn write_apr(&self, group1: bool, n: usize, val: u64) {
+        self.aprs[group1 as usize][n].store(val, Ordering::Relaxed);
+    }
+
+    fn write_hcr(&self, val: u64) {
+        self.hcr.store(val, Ordering::Relaxed);
+    }
+
+    fn highest_pending(&self) -> u32 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    fn ack(&self) -> u32 {
+        self.pending.swap(Self::SPURIOUS, Ordering::Relaxed)
+    }
+
+    fn eoi(&self, intid: u32) {
+        self.last_eoi.store(intid, Ordering::Relaxed);
+    }
+}
+
+static NATIVE: Native = Native;
+#[cfg(test)]
+static MOCK: Mock = Mock::new();
+// Thread that has `Mock` installed, see `with_mock`.
+#[cfg(test)]
+static MOCK_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+#[cfg(test)]
+const NO_OWNER: usize = usize::MAX;
+
+#[cfg(test)]
+fn mocked() -> bool {
+    MOCK_OWNER.load(Ordering::Acquire) == scheduler::current_thread_id()
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+pub fn sysregs() -> &'static impl SysRegBackend {
+    &NATIVE
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+pub fn gic() -> &'static impl GicBackend {
+    &NATIVE
+}
+
+#[cfg(test)]
+pub fn sysregs() -> &'static dyn SysRegBackend {
+    if mocked() {
+        &MOCK
+    } else {
+        &NATIVE
+    }
+}
+
+#[cfg(test)]
+pub fn gic() -> &'static dyn GicBackend {
+    if mocked() {
+        &MOCK
+    } else {
+        &NATIVE
+    }
+}
+
+/// Runs `f` with `sysregs()` and `gic()` backed by `Mock` on the calling
+/// thread; tests on other threads keep `Native`. One thread has the mock
+/// at a time, others wait for it, and it's given back even if `f` panics.
+#[cfg(test)]
+pub fn with_mock<R>(f: impl FnOnce() -> R) -> R {
+    let me = scheduler::current_thread_id();
+    if MOCK_OWNER.load(Ordering::Acquire) == me {
+        return f();
+    }
+    while MOCK_OWNER
+        .compare_exchange(NO_OWNER, me, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        scheduler::yield_me();
+    }
+    let _owner = MockOwner;
+    f()
+}
+
+// Gives the mock back as `with_mock` returns or unwinds.
+#[cfg(test)]
+struct MockOwner;
+
+#[cfg(test)]
+impl Drop for MockOwner {
+    fn drop(&mut self) {
+        MOCK_OWNER.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+/// The mock `with_mock` installs, for setting up and checking registers.
+#[cfg(test)]
+pub fn mock() -> &'static Mock {
+    &MOCK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_mock_gic() {
+        with_mock(check_mock_gic);
+    }
+
+    fn check_mock_gic() {
+        let gic = gic();
+        for n in 0..Mock::NUM_LRS {
+            gic.write_lr(n, 0);
+        }
+        assert_eq!(gic.elrsr(), 0b1111);
+        gic.write_lr(2, (1 << 62) | 40);
+        assert_eq!(gic.elrsr(), 0b1011);
+        gic.write_lr(2, 0);
+
+        mock().set_pending(8);
+        assert_eq!(gic.highest_pending(), 8);
+        let intid = gic.ack();
+        gic.eoi(intid);
+        assert_eq!(mock().last_eoi(), 8);
+        assert_eq!(gic.ack(), Mock::SPURIOUS);
+    }
+}
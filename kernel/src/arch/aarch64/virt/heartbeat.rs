@@ -0,0 +1,201 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Heartbeat page. A VM built with `VmBuilder::heartbeat` gets a page,
+//! mapped read-only, that EL2 updates on every guest entry with the host
+//! counter, the VM's uptime and a sequence number, laid out as the
+//! `HEARTBEAT_*` offsets of `abi` say. A guest watchdog compares it with
+//! its own clock to notice that the host stalled or didn't schedule it,
+//! without taking an exit. EL2 writes the page as Normal write-back
+//! memory, as the guest maps it, see `mmu::enable_el2_mmu`.
+//!
+//! The sequence number works as a seqlock: odd while an update is under
+//! way. vCPUs entering at the same time don't wait for each other; one
+//! that finds an update under way leaves it to that one.
+
+use super::{abi, stage2::PAGE_SIZE, vcpu::vcpu_manager};
+use crate::{arch::aarch64::registers::cntfrq_el0::CNTFRQ_EL0, sync::SpinLock};
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::{
+    mem::offset_of,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tock_registers::interfaces::Readable;
+
+#[repr(C, align(4096))]
+pub struct HeartbeatPage {
+    seq: AtomicU64,
+    host_count: AtomicU64,
+    uptime: AtomicU64,
+    freq: AtomicU64,
+    vcpu: AtomicU64,
+    // Host counter when the VM was built, moved back by what a counter
+    // that started over lost. Not part of the ABI.
+    built: AtomicU64,
+}
+
+const _: () = {
+    assert!(offset_of!(HeartbeatPage, seq) == abi::HEARTBEAT_SEQ);
+    assert!(offset_of!(HeartbeatPage, host_count) == abi::HEARTBEAT_HOST_COUNT);
+    assert!(offset_of!(HeartbeatPage, uptime) == abi::HEARTBEAT_UPTIME);
+    assert!(offset_of!(HeartbeatPage, freq) == abi::HEARTBEAT_FREQ);
+    assert!(offset_of!(HeartbeatPage, vcpu) == abi::HEARTBEAT_VCPU);
+    assert!(core::mem::size_of::<HeartbeatPage>() as u64 == PAGE_SIZE);
+};
+
+impl HeartbeatPage {
+    /// A page for a VM built at host counter `now`.
+    pub fn new(now: u64) -> Box<Self> {
+        Box::new(Self {
+            seq: AtomicU64::new(0),
+            host_count: AtomicU64::new(now),
+            uptime: AtomicU64::new(0),
+            freq: AtomicU64::new(CNTFRQ_EL0.get()),
+            vcpu: AtomicU64::new(0),
+            built: AtomicU64::new(now),
+        })
+    }
+
+    /// Host physical address of the page, identity mapped.
+    pub fn pa(&self) -> u64 {
+        self as *const Self as u64
+    }
+
+    /// Publish an entry of the vCPU with index `vcpu` at host counter
+    /// `now`. Runs at EL2.
+    #[link_section = ".hyp.text"]
+    pub fn beat(&self, now: u64, vcpu: usize) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 != 0
+            || self
+                .seq
+                .compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        self.host_count.store(now, Ordering::Relaxed);
+        self.uptime.store(
+            now.wrapping_sub(self.built.load(Ordering::Relaxed)),
+            Ordering::Relaxed,
+        );
+        self.vcpu.store(vcpu as u64, Ordering::Relaxed);
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    /// Keep the uptime going after the host counter started over `lost`
+    /// counts behind where it stood.
+    fn rebase(&self, lost: u64) {
+        self.built.fetch_sub(lost, Ordering::Relaxed);
+    }
+
+    /// Sequence number, host counter and uptime as a guest reads them.
+    pub fn read(&self) -> (u64, u64, u64) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let host_count = self.host_count.load(Ordering::Relaxed);
+            let uptime = self.uptime.load(Ordering::Relaxed);
+            core::sync::atomic::fence(Ordering::Acquire);
+            if seq & 1 == 0 && self.seq.load(Ordering::Relaxed) == seq {
+                return (seq, host_count, uptime);
+            }
+        }
+    }
+}
+
+/// The heartbeat page of a vCPU's VM, which the vCPU beats on entry.
+#[derive(Clone, Copy)]
+pub struct Heartbeat(NonNull<HeartbeatPage>);
+
+// SAFETY: The page is only written through atomics, and stays allocated
+// while the VM has vCPUs, see `release_vm`.
+unsafe impl Send for Heartbeat {}
+unsafe impl Sync for Heartbeat {}
+
+impl Heartbeat {
+    #[link_section = ".hyp.text"]
+    pub fn beat(&self, now: u64, vcpu: usize) {
+        // SAFETY: See the `Send` impl.
+        unsafe { self.0.as_ref() }.beat(now, vcpu);
+    }
+}
+
+static PAGES: SpinLock<BTreeMap<usize, Box<HeartbeatPage>>> = SpinLock::new(BTreeMap::new());
+
+/// Make `page`, already mapped into it, the heartbeat page of VM `vm_id`.
+pub fn register(vm_id: usize, page: Box<HeartbeatPage>) {
+    PAGES.irqsave_lock().insert(vm_id, page);
+}
+
+/// Have vCPU `vcpu_id` beat the heartbeat page of VM `vm_id`, if it has
+/// one.
+pub fn attach(vm_id: usize, vcpu_id: usize) {
+    let Some(page) = PAGES
+        .irqsave_lock()
+        .get(&vm_id)
+        .map(|page| NonNull::from(&**page))
+    else {
+        return;
+    };
+    vcpu_manager().with_vcpu_mut(vcpu_id, |vcpu| vcpu.heartbeat = Some(Heartbeat(page)));
+}
+
+/// Sequence number, host counter and uptime of VM `vm_id`'s page.
+pub fn read(vm_id: usize) -> Option<(u64, u64, u64)> {
+    PAGES.irqsave_lock().get(&vm_id).map(|page| page.read())
+}
+
+/// Move every VM's uptime base back by `lost` counts, for a host counter
+/// that started over across a suspend. See `host_pm::thaw`.
+pub(crate) fn rebase_all(lost: u64) {
+    for page in PAGES.irqsave_lock().values() {
+        page.rebase(lost);
+    }
+}
+
+/// Free the page of VM `vm_id`, once it has no vCPUs left.
+pub(crate) fn release_vm(vm_id: usize) {
+    let page = PAGES.irqsave_lock().remove(&vm_id);
+    drop(page);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_heartbeat_seqlock() {
+        let page = HeartbeatPage::new(1000);
+        assert_eq!(page.pa() % PAGE_SIZE, 0);
+        assert_eq!(page.read(), (0, 1000, 0));
+        page.beat(1500, 1);
+        assert_eq!(page.read(), (2, 1500, 500));
+        assert_eq!(page.vcpu.load(Ordering::Relaxed), 1);
+
+        // An entry racing with an update leaves it alone.
+        page.seq.store(3, Ordering::Relaxed);
+        page.beat(2000, 0);
+        assert_eq!(page.host_count.load(Ordering::Relaxed), 1500);
+        page.seq.store(4, Ordering::Relaxed);
+        page.beat(2000, 0);
+        assert_eq!(page.read(), (6, 2000, 1000));
+
+        // The counter started over at 100, 2500 behind: uptime goes on.
+        page.rebase(2500);
+        page.beat(150, 0);
+        assert_eq!(page.read(), (8, 150, 1150));
+    }
+}
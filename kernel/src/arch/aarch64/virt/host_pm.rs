@@ -0,0 +1,323 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host suspend. `suspend` wraps the platform's suspend to RAM: before
+//! it, `freeze` takes every vCPU out of guest mode and keeps it parked in
+//! its run loop, so all guest state is in memory, along with each vCPU's
+//! list registers and counter offset. A core back from suspend lost its
+//! EL2 registers; the platform resumes it at `resume_entry`, which sets
+//! them and the GIC virtual CPU interface up again as boot did before it
+//! drops to EL1. `thaw` then lets the vCPUs go on.
+//!
+//! `suspend_core` is the host's PSCI CPU_SUSPEND: it saves the host's EL1
+//! registers and callee-saved state, so a core back from a powerdown state
+//! goes on in it as if CPU_SUSPEND had returned.
+//!
+//! Guests with `VirtualCounter::GuestTime` don't see the suspend: their
+//! counter stands still while they are out of guest mode anyway. Those
+//! counting host time see the time pass, as the host does. If the
+//! physical counter didn't survive the suspend and started over, every
+//! guest's counter, and the uptime on its heartbeat page, goes on from
+//! where it stood at `freeze`.
+
+use super::{
+    hal::{sysregs, SysReg, SysRegBackend},
+    heartbeat, hyper,
+    kick::{self, KickReason},
+    vcpu::{vcpu_manager, El1Context},
+    vgic,
+    vlog::vlog,
+};
+use crate::{
+    arch::aarch64::{
+        current_cpu_id, disable_local_irq_save, enable_local_irq_restore, psci::PsciFuncName,
+    },
+    scheduler::InsertToEnd,
+    sync::{
+        event_flags::{EventFlags, EventFlagsMode},
+        SpinLock,
+    },
+    time::Tick,
+};
+use core::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+// How long `freeze` waits for the vCPUs to leave guest mode.
+const FREEZE_TIMEOUT_TICKS: usize = 100;
+// CNTHCTL_EL2 as `enter_el1` leaves it: EL1 has the physical counter and
+// timer, the FEAT_ECV controls are off.
+const CNTHCTL_EL1_ACCESS: u64 = 0b11;
+const CNTHCTL_ECV_MASK: u64 = 0x1f << 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmError {
+    AlreadyFrozen,
+    NotFrozen,
+    /// A vCPU didn't leave guest mode in time; the VMs run on.
+    Timeout,
+}
+
+static FROZEN: AtomicBool = AtomicBool::new(false);
+// Physical count once every vCPU was out of guest mode.
+static FROZEN_AT: AtomicU64 = AtomicU64::new(0);
+// Set by a vCPU's run loop each time it parks for a freeze.
+static LEFT: EventFlags = EventFlags::new();
+const LEFT_GUEST: u32 = 1;
+// Cores `resume_entry` couldn't set up again, reported by `thaw`.
+static RESUME_FAILED: AtomicU32 = AtomicU32::new(0);
+// EL1 registers of the host on each core in `suspend_core`, loaded again
+// by `resume_entry`.
+#[link_section = ".hyp.data"]
+static HOST_EL1: [SpinLock<El1Context>; NUM_CORES] =
+    [const { SpinLock::new(El1Context::new()) }; NUM_CORES];
+// Stack pointer of each core in `suspend_core`, with its callee-saved
+// registers on top.
+static HOST_SP: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+
+/// The `Services` init call.
+pub(crate) fn init() -> Result<(), &'static str> {
+    LEFT.init(0);
+    Ok(())
+}
+
+/// Whether vCPUs have to stay out of guest mode.
+pub fn is_frozen() -> bool {
+    FROZEN.load(Ordering::Acquire)
+}
+
+/// Tell `freeze` that a vCPU left guest mode for it. Called from the run
+/// loop before the vCPU waits out the freeze.
+pub(crate) fn left_guest() {
+    let _ = LEFT.set(LEFT_GUEST);
+}
+
+// Whether no vCPU is in guest mode.
+fn quiesced() -> bool {
+    let mut running = false;
+    vcpu_manager().for_each(|vcpu| running |= vcpu.running_on().is_some());
+    !running
+}
+
+/// Stop every VM for a host suspend. Returns once no vCPU is in guest
+/// mode; vCPUs that are out already, or get created meanwhile, stay out
+/// until `thaw`.
+pub fn freeze() -> Result<(), PmError> {
+    if FROZEN.swap(true, Ordering::AcqRel) {
+        return Err(PmError::AlreadyFrozen);
+    }
+    // Pairs with the fence in `vcpu::enter`: a vCPU entering now either
+    // sees the freeze or is seen running below.
+    fence(Ordering::SeqCst);
+    for id in vcpu_manager().ids() {
+        if let Some(vm_id) = vcpu_manager().vm_of(id) {
+            let _ = kick::vcpu_kick(vm_id, id, KickReason::Freeze);
+        }
+    }
+    let start = Tick::now();
+    while !quiesced() {
+        let waited = Tick::now().since(start).0;
+        if waited >= FREEZE_TIMEOUT_TICKS {
+            FROZEN.store(false, Ordering::Release);
+            wake_all();
+            vlog!(Vcpu, Warn, "[pm] vcpus still in guest mode, not frozen");
+            return Err(PmError::Timeout);
+        }
+        let _ = LEFT.wait::<InsertToEnd>(
+            LEFT_GUEST,
+            EventFlagsMode::ANY,
+            Tick(FREEZE_TIMEOUT_TICKS - waited),
+        );
+    }
+    FROZEN_AT.store(hyper::read_cntpct(), Ordering::Release);
+    vlog!(Vcpu, Info, "[pm] vms frozen");
+    Ok(())
+}
+
+// Let the vCPUs parked by a freeze look at it again.
+fn wake_all() {
+    for id in vcpu_manager().ids() {
+        vcpu_manager().wake(id);
+    }
+}
+
+/// Freeze the VMs, run `enter` to suspend the host and thaw them once it
+/// returned, after the host resumed.
+pub fn suspend<R>(enter: impl FnOnce() -> R) -> Result<R, PmError> {
+    freeze()?;
+    let r = enter();
+    thaw()?;
+    Ok(r)
+}
+
+/// Suspend the calling core in PSCI `power_state`, with the VMs frozen
+/// around it. From a powerdown state the core comes back through
+/// `resume_entry` and returns here with the host's EL1 state as it left
+/// it. Returns what CPU_SUSPEND returned, 0 once resumed.
+pub fn suspend_core(psci_base: u32, power_state: usize) -> Result<i64, PmError> {
+    suspend(|| {
+        let daif = disable_local_irq_save();
+        HOST_EL1[current_cpu_id()].irqsave_lock().save();
+        let func_id = psci_base + PsciFuncName::CpuSuspend as u32;
+        let entry = resume_entry as usize as u64;
+        let r = unsafe { host_suspend(func_id as u64, power_state as u64, entry) };
+        enable_local_irq_restore(daif);
+        r as i64
+    })
+}
+
+// Make the CPU_SUSPEND call `func_id` with `power_state` and `entry`, the
+// resume address at EL2. The context id is where `resume_entry` drops the
+// core back at EL1: there the stack pointer comes from HOST_SP and the
+// callee-saved registers from the stack, and 0 is returned as if from the
+// call. Runs at EL1 with interrupts masked.
+#[naked]
+unsafe extern "C" fn host_suspend(func_id: u64, power_state: u64, entry: u64) -> u64 {
+    core::arch::naked_asm!(
+        "stp x29, x30, [sp, #-96]!\n",
+        "stp x19, x20, [sp, #16]\n",
+        "stp x21, x22, [sp, #32]\n",
+        "stp x23, x24, [sp, #48]\n",
+        "stp x25, x26, [sp, #64]\n",
+        "stp x27, x28, [sp, #80]\n",
+        "mrs x9, mpidr_el1\n",
+        "and x9, x9, #0xff\n",
+        "ldr x10, ={host_sp}\n",
+        "mov x11, sp\n",
+        "str x11, [x10, x9, lsl #3]\n",
+        "adr x3, 1f\n",
+        "smc #0\n",
+        "b 2f\n",
+        "1:\n",
+        "mrs x9, mpidr_el1\n",
+        "and x9, x9, #0xff\n",
+        "ldr x10, ={host_sp}\n",
+        "ldr x11, [x10, x9, lsl #3]\n",
+        "mov sp, x11\n",
+        "mov x0, #0\n",
+        "2:\n",
+        "ldp x19, x20, [sp, #16]\n",
+        "ldp x21, x22, [sp, #32]\n",
+        "ldp x23, x24, [sp, #48]\n",
+        "ldp x25, x26, [sp, #64]\n",
+        "ldp x27, x28, [sp, #80]\n",
+        "ldp x29, x30, [sp], #96\n",
+        "ret\n",
+        host_sp = sym HOST_SP,
+    );
+}
+
+/// Set EL2 and the GIC virtual CPU interface of the calling core up again
+/// after it came back from suspend to RAM. Runs at EL2, from
+/// `resume_entry`, before the core drops to EL1.
+#[link_section = ".hyp.text"]
+pub fn resume_cpu() -> Result<(), &'static str> {
+    let cnthctl = sysregs().read(SysReg::CnthctlEl2);
+    sysregs().write(
+        SysReg::CnthctlEl2,
+        (cnthctl | CNTHCTL_EL1_ACCESS) & !CNTHCTL_ECV_MASK,
+    );
+    sysregs().write(SysReg::CntvoffEl2, 0);
+    hyper::hyp_init();
+    vgic::init()
+}
+
+// Also gives the host back the EL1 registers `suspend_core` saved.
+#[link_section = ".hyp.text"]
+extern "C" fn resume_cpu_entry() {
+    if resume_cpu().is_err() {
+        RESUME_FAILED.fetch_or(1 << current_cpu_id(), Ordering::Release);
+    }
+    HOST_EL1[current_cpu_id()].irqsave_lock().restore();
+}
+
+/// Where the platform resumes a core from suspend to RAM, the entry
+/// `suspend_core` gives PSCI CPU_SUSPEND. Runs at EL2 with the MMU off and
+/// `x0` holding the EL1 address to go on at, the PSCI context id: it takes
+/// the core's EL2 stack as `enter_el1` does, calls `resume_cpu` and drops
+/// to that address at EL1h with DAIF masked.
+///
+/// # Safety
+/// Only the platform's resume path may branch here.
+#[naked]
+#[link_section = ".hyp.text"]
+pub unsafe extern "C" fn resume_entry() {
+    core::arch::naked_asm!(
+        "mov x19, x0\n",
+        "ldr x1, ={stack_end}\n",
+        "mrs x9, mpidr_el1\n",
+        "and x9, x9, #0xff\n",
+        "lsl x9, x9, #14\n",
+        "sub x1, x1, x9\n",
+        "mov sp, x1\n",
+        "bl {resume}\n",
+        "mov x0, #0x3C5\n",
+        "msr spsr_el2, x0\n",
+        "msr elr_el2, x19\n",
+        "eret\n",
+        stack_end = sym __sys_stack_end,
+        resume = sym resume_cpu_entry,
+    );
+}
+
+extern "C" {
+    static __sys_stack_end: u8;
+}
+
+/// Let the VMs stopped by `freeze` go on, once the host resumed.
+pub fn thaw() -> Result<(), PmError> {
+    if !is_frozen() {
+        return Err(PmError::NotFrozen);
+    }
+    let (at, now) = (FROZEN_AT.load(Ordering::Acquire), hyper::read_cntpct());
+    if now < at {
+        for id in vcpu_manager().ids() {
+            vcpu_manager().with_vcpu_mut(id, |vcpu| vcpu.rebase_counter(at - now));
+        }
+        heartbeat::rebase_all(at - now);
+        vlog!(Vcpu, Info, "[pm] vms thawed, counter started over");
+    } else {
+        vlog!(Vcpu, Info, "[pm] vms thawed after {} counts", now - at);
+    }
+    let failed = RESUME_FAILED.swap(0, Ordering::AcqRel);
+    if failed != 0 {
+        vlog!(
+            Vcpu,
+            Warn,
+            "[pm] cores {:#x} not set up again on resume",
+            failed
+        );
+    }
+    FROZEN.store(false, Ordering::Release);
+    wake_all();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_host_pm_suspend() {
+        assert_eq!(thaw(), Err(PmError::NotFrozen));
+        let r = suspend(|| {
+            assert!(is_frozen());
+            assert_eq!(freeze(), Err(PmError::AlreadyFrozen));
+            7
+        });
+        assert_eq!(r, Ok(7));
+        assert!(!is_frozen());
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Devices added to or removed from a running VM. An emulated device goes
+//! in and out of the MMIO dispatch table under the lock exits hold while
+//! they access a device, so an in-flight exit sees the whole device or
+//! none of it. Hotplugged devices take their SPI from a range built VMs
+//! may not use. Guests describing their devices in a device tree learn of
+//! changes from an event queue they drain with an HVC, announced by the
+//! VM's notify interrupt.
+
+use super::{
+    abi,
+    audit::{self, Initiator, Operation},
+    kick,
+    mmio::{self, MmioDevice},
+    ring::Ring,
+    stage2::{self, MemType, S2Perms, Stage2Error, PAGE_SIZE},
+    vcpu::vcpu_manager,
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+pub use super::abi::FIRST_HOTPLUG_SPI;
+/// Devices a VM can have hotplugged at once, and SPIs reserved for them.
+pub const MAX_HOTPLUG: usize = 16;
+// Changes a guest can leave unread before the oldest is dropped.
+const MAX_EVENTS: usize = 16;
+
+/// Whether `intid` is kept for hotplugged devices.
+pub fn is_reserved(intid: u32) -> bool {
+    (FIRST_HOTPLUG_SPI..FIRST_HOTPLUG_SPI + MAX_HOTPLUG as u32).contains(&intid)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugError {
+    /// The VM has no Stage-2 tables.
+    NoVm,
+    Misaligned,
+    /// The range overlaps memory or another device of the VM.
+    Overlap,
+    /// All hotplug SPIs of the VM are taken.
+    NoIntid,
+    /// No hotplugged device at the address.
+    NoDevice,
+    Stage2(Stage2Error),
+}
+
+/// What to plug in.
+pub enum HotplugDevice {
+    /// Emulated through `mmio`.
+    Mmio { dev: Arc<dyn MmioDevice>, size: u64 },
+    /// Host memory at `pa` mapped into the guest, e.g. a buffer shared with
+    /// a host service.
+    SharedMemory { pa: u64, size: u64, writable: bool },
+}
+
+impl HotplugDevice {
+    fn size(&self) -> u64 {
+        match *self {
+            Self::Mmio { size, .. } | Self::SharedMemory { size, .. } => size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum EventKind {
+    Added = abi::HOTPLUG_ADDED,
+    Removed = abi::HOTPLUG_REMOVED,
+}
+
+/// A change as the guest reads it back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub base: u64,
+    pub size: u64,
+    pub intid: u32,
+    /// What the guest should bind a driver by, as given to `plug`.
+    pub type_id: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    base: u64,
+    size: u64,
+    intid: u32,
+    type_id: u32,
+    shared_memory: bool,
+}
+
+struct VmHotplug {
+    slots: Vec<Slot>,
+    events: Ring<Event, MAX_EVENTS>,
+    notify: Option<u32>,
+}
+
+impl VmHotplug {
+    fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            events: Ring::new(),
+            notify: None,
+        }
+    }
+
+    fn free_intid(&self) -> Option<u32> {
+        (FIRST_HOTPLUG_SPI..FIRST_HOTPLUG_SPI + MAX_HOTPLUG as u32)
+            .find(|&intid| self.slots.iter().all(|s| s.intid != intid))
+    }
+
+    // The guest would rather miss its oldest change than the latest one.
+    fn push_event(&mut self, event: Event) {
+        if let Err(event) = self.events.push_back(event) {
+            self.events.pop_front();
+            let _ = self.events.push_back(event);
+        }
+    }
+}
+
+static VMS: SpinLock<BTreeMap<usize, VmHotplug>> = SpinLock::new(BTreeMap::new());
+
+/// Raise SPI `intid` on the VM's first vCPU whenever a device comes or
+/// goes; `None` leaves the guest to poll.
+pub fn set_notify(vm_id: usize, intid: Option<u32>) {
+    VMS.irqsave_lock()
+        .entry(vm_id)
+        .or_insert_with(VmHotplug::new)
+        .notify = intid;
+}
+
+/// Add `dev` at IPA `base` of running VM `vm_id`, announced to the guest
+/// as `type_id`. Returns the SPI allocated to the device.
+pub fn plug(
+    vm_id: usize,
+    base: u64,
+    dev: HotplugDevice,
+    type_id: u32,
+) -> Result<u32, HotplugError> {
+    let size = dev.size();
+    if base % PAGE_SIZE != 0 || size == 0 || size % PAGE_SIZE != 0 {
+        return Err(HotplugError::Misaligned);
+    }
+    let mapped = stage2::with_vm(vm_id, |s2| {
+        (base..base + size)
+            .step_by(PAGE_SIZE as usize)
+            .any(|ipa| s2.lookup(ipa).is_some())
+    })
+    .ok_or(HotplugError::NoVm)?;
+    if mapped || mmio::overlaps(vm_id, base, size) {
+        return Err(HotplugError::Overlap);
+    }
+
+    let mut vms = VMS.irqsave_lock();
+    let vm = vms.entry(vm_id).or_insert_with(VmHotplug::new);
+    let intid = vm.free_intid().ok_or(HotplugError::NoIntid)?;
+    let (shared_memory, op) = match dev {
+        HotplugDevice::Mmio { dev, size } => {
+            mmio::register(vm_id, base, size, dev).map_err(|_| HotplugError::Overlap)?;
+            let op = Operation::DevicePlug {
+                ipa: base,
+                size,
+                intid,
+            };
+            (false, op)
+        }
+        HotplugDevice::SharedMemory { pa, size, writable } => {
+            let perms = S2Perms::new(true, writable, false);
+            stage2::with_vm(vm_id, |s2| s2.map(base, pa, size, MemType::Normal, perms))
+                .ok_or(HotplugError::NoVm)?
+                .map_err(HotplugError::Stage2)?;
+            let op = Operation::SharedMemory {
+                ipa: base,
+                pa,
+                size,
+                writable,
+            };
+            (true, op)
+        }
+    };
+    audit::record(vm_id, audit::label_of(vm_id), Initiator::host(), op);
+    let slot = Slot {
+        base,
+        size,
+        intid,
+        type_id,
+        shared_memory,
+    };
+    vm.slots.push(slot);
+    announce(vm_id, vm, slot, EventKind::Added);
+    Ok(intid)
+}
+
+/// Remove the hotplugged device at `base` of VM `vm_id`. Once this returns
+/// no exit is still accessing it, and its interrupt is no longer queued.
+pub fn unplug(vm_id: usize, base: u64) -> Result<(), HotplugError> {
+    let mut vms = VMS.irqsave_lock();
+    let vm = vms.get_mut(&vm_id).ok_or(HotplugError::NoDevice)?;
+    let n = vm
+        .slots
+        .iter()
+        .position(|s| s.base == base)
+        .ok_or(HotplugError::NoDevice)?;
+    let slot = vm.slots.swap_remove(n);
+    remove(vm_id, &slot);
+    announce(vm_id, vm, slot, EventKind::Removed);
+    Ok(())
+}
+
+fn remove(vm_id: usize, slot: &Slot) {
+    if slot.shared_memory {
+        let _ = stage2::with_vm(vm_id, |s2| s2.unmap(slot.base, slot.size));
+    } else {
+        mmio::unregister(vm_id, slot.base);
+    }
+    vcpu_manager().for_each_of(vm_id, |vcpu| vcpu.vgic.retract(slot.intid));
+}
+
+fn announce(vm_id: usize, vm: &mut VmHotplug, slot: Slot, kind: EventKind) {
+    vm.push_event(Event {
+        kind,
+        base: slot.base,
+        size: slot.size,
+        intid: slot.intid,
+        type_id: slot.type_id,
+    });
+    let Some(intid) = vm.notify else {
+        return;
+    };
+    if let Some(id) = vcpu_manager().find(vm_id, 0) {
+        let _ = kick::inject_irq(vm_id, id, intid);
+    }
+}
+
+/// Oldest change the guest of `vm_id` hasn't read yet. Runs at EL2 for the
+/// guest's HVC.
+pub fn next_event(vm_id: usize) -> Option<Event> {
+    VMS.irqsave_lock().get_mut(&vm_id)?.events.pop_front()
+}
+
+/// Remove every hotplugged device of a VM being torn down.
+pub fn release_vm(vm_id: usize) {
+    let Some(vm) = VMS.irqsave_lock().remove(&vm_id) else {
+        return;
+    };
+    for slot in &vm.slots {
+        remove(vm_id, slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::{mmio::BackendError, stage2::Stage2, test_vm_id};
+    use blueos_test_macro::test;
+
+    struct Zero;
+
+    impl MmioDevice for Zero {
+        fn read(&self, _offset: u64, _size: u8) -> Result<u64, BackendError> {
+            Ok(0)
+        }
+
+        fn write(&self, _offset: u64, _size: u8, _value: u64) -> Result<(), BackendError> {
+            Ok(())
+        }
+    }
+
+    fn zero() -> HotplugDevice {
+        HotplugDevice::Mmio {
+            dev: Arc::new(Zero),
+            size: PAGE_SIZE,
+        }
+    }
+
+    #[test]
+    fn test_hotplug_plug_unplug() {
+        let vm_id = test_vm_id();
+        assert_eq!(plug(vm_id, 0x1000, zero(), 3), Err(HotplugError::NoVm));
+
+        let mut s2 = Stage2::new();
+        s2.map(0, 0x4000_0000, PAGE_SIZE, MemType::Normal, S2Perms::RW)
+            .unwrap();
+        stage2::install(vm_id, s2);
+        assert_eq!(plug(vm_id, 0, zero(), 3), Err(HotplugError::Overlap));
+        assert_eq!(
+            plug(vm_id, 0x1800, zero(), 3),
+            Err(HotplugError::Misaligned)
+        );
+
+        assert_eq!(plug(vm_id, 0x1000, zero(), 3), Ok(FIRST_HOTPLUG_SPI));
+        assert_eq!(plug(vm_id, 0x1000, zero(), 3), Err(HotplugError::Overlap));
+        assert_eq!(plug(vm_id, 0x2000, zero(), 3), Ok(FIRST_HOTPLUG_SPI + 1));
+        unplug(vm_id, 0x1000).unwrap();
+        assert!(!mmio::overlaps(vm_id, 0x1000, PAGE_SIZE));
+        assert_eq!(unplug(vm_id, 0x1000), Err(HotplugError::NoDevice));
+        // The freed SPI is handed out again.
+        assert_eq!(plug(vm_id, 0x3000, zero(), 3), Ok(FIRST_HOTPLUG_SPI));
+
+        let kinds: Vec<_> = core::iter::from_fn(|| next_event(vm_id))
+            .map(|e| (e.kind, e.base))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (EventKind::Added, 0x1000),
+                (EventKind::Added, 0x2000),
+                (EventKind::Removed, 0x1000),
+                (EventKind::Added, 0x3000),
+            ]
+        );
+
+        release_vm(vm_id);
+        assert!(!mmio::overlaps(vm_id, 0, 0x4000));
+        stage2::remove(vm_id);
+    }
+
+    #[test]
+    fn test_hotplug_event_overflow() {
+        let mut vm = VmHotplug::new();
+        for n in 0..MAX_EVENTS as u64 + 1 {
+            vm.push_event(Event {
+                kind: EventKind::Added,
+                base: n,
+                size: PAGE_SIZE,
+                intid: FIRST_HOTPLUG_SPI,
+                type_id: 0,
+            });
+        }
+        assert_eq!(vm.events.len(), MAX_EVENTS);
+        assert_eq!(vm.events.pop_front().map(|e| e.base), Some(1));
+    }
+}
@@ -12,60 +12,120 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::arch::aarch64::{registers::hcr_el2::HCR_EL2, virt::vector};
-use tock_registers::interfaces::{Readable, Writeable};
+use super::hal::{sysregs, SysReg, SysRegBackend};
+use crate::arch::aarch64::{
+    mmu,
+    registers::hcr_el2::HCR_EL2,
+    sysreg::{read_sysreg, write_sysreg},
+    virt::vector,
+};
+use tock_registers::interfaces::Writeable;
 
 #[inline]
 pub fn get_current_el() -> u64 {
-    let current_el: u64;
-    unsafe {
-        core::arch::asm!("mrs {}, currentel", out(reg) current_el);
-    }
-    (current_el >> 2) & 0x3
+    (read_sysreg!("currentel") >> 2) & 0x3
 }
 
 #[inline]
 pub fn read_hcr_el2() -> u64 {
-    HCR_EL2.get()
+    sysregs().read(SysReg::HcrEl2)
 }
 
 #[inline]
 pub fn write_hcr_el2(val: u64) {
-    HCR_EL2.set(val);
+    sysregs().write(SysReg::HcrEl2, val);
 }
 
 #[inline]
 pub fn read_vbar_el2() -> u64 {
-    let vbar: u64;
-    unsafe {
-        core::arch::asm!("mrs {}, vbar_el2", out(reg) vbar);
-    }
-    vbar
+    sysregs().read(SysReg::VbarEl2)
 }
 
 #[inline]
 pub fn write_vbar_el2(val: u64) {
-    unsafe {
-        core::arch::asm!("msr vbar_el2, {}", in(reg) val);
-    }
+    sysregs().write(SysReg::VbarEl2, val);
+}
+
+#[inline]
+pub fn write_vmpidr_el2(val: u64) {
+    sysregs().write(SysReg::VmpidrEl2, val);
+}
+
+#[inline]
+pub fn write_vpidr_el2(val: u64) {
+    sysregs().write(SysReg::VpidrEl2, val);
+}
+
+#[inline]
+pub fn write_vtcr_el2(val: u64) {
+    sysregs().write(SysReg::VtcrEl2, val);
+}
+
+#[inline]
+pub fn write_vttbr_el2(val: u64) {
+    sysregs().write(SysReg::VttbrEl2, val);
+}
+
+#[inline]
+pub fn read_id_aa64mmfr0_el1() -> u64 {
+    sysregs().read(SysReg::IdAa64mmfr0El1)
+}
+
+#[inline]
+pub fn read_ctr_el0() -> u64 {
+    sysregs().read(SysReg::CtrEl0)
+}
+
+#[inline]
+pub fn read_dczid_el0() -> u64 {
+    sysregs().read(SysReg::DczidEl0)
+}
+
+#[inline]
+pub fn read_vbar_el1() -> u64 {
+    sysregs().read(SysReg::VbarEl1)
+}
+
+#[inline]
+pub fn read_cntpct() -> u64 {
+    sysregs().read(SysReg::CntpctEl0)
+}
+
+#[inline]
+pub fn write_cntvoff_el2(val: u64) {
+    sysregs().write(SysReg::CntvoffEl2, val);
 }
 
 #[inline]
 pub fn read_esr_el2() -> u64 {
-    let esr: u64;
-    unsafe {
-        core::arch::asm!("mrs {}, esr_el2", out(reg) esr);
-    }
-    esr
+    sysregs().read(SysReg::EsrEl2)
 }
 
 #[inline]
 pub fn read_elr_el2() -> u64 {
-    let elr: u64;
-    unsafe {
-        core::arch::asm!("mrs {}, elr_el2", out(reg) elr);
+    sysregs().read(SysReg::ElrEl2)
+}
+
+#[inline]
+pub fn read_far_el2() -> u64 {
+    sysregs().read(SysReg::FarEl2)
+}
+
+#[inline]
+pub fn read_hpfar_el2() -> u64 {
+    sysregs().read(SysReg::HpfarEl2)
+}
+
+/// Stage-1 translation of `va` in the EL1&0 regime currently loaded, i.e.
+/// the guest's while handling one of its exits. Returns the IPA, or `None`
+/// if the guest's tables don't map `va` for reading.
+#[inline]
+pub fn translate_el1_va(va: u64) -> Option<u64> {
+    let par = sysregs().translate_el1_read(va);
+    if par & 1 != 0 {
+        return None;
     }
-    elr
+    Some((par & 0x0000_ffff_ffff_f000) | (va & 0xfff))
 }
 
 #[inline]
@@ -75,18 +135,13 @@ fn configure_hcr_el2() {
 
 #[inline]
 fn configure_vector_table(vector_base: usize) {
-    unsafe {
-        core::arch::asm!(
-            "msr vbar_el2, {}",
-            in(reg) vector_base as u64,
-            options(nostack)
-        );
-    }
+    write_sysreg!("vbar_el2", vector_base as u64);
 }
 
 // Hypervisor initialization
 #[cfg(virtualization)]
 pub fn hyp_init() {
+    mmu::enable_el2_mmu();
     configure_hcr_el2();
     unsafe {
         core::arch::asm!("dsb sy", options(nostack));
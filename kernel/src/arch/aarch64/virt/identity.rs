@@ -0,0 +1,215 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! VM identities. VM ids are small integers that come back once a VM is
+//! destroyed, so logs, trace events and audit records of two VMs can carry
+//! the same one. Every VM built by `VmBuilder` also gets a UUID, given by
+//! its description or generated, and optionally a name and metadata; the
+//! registry maps the VM id to them while the VM exists.
+//!
+//! `tag` formats a VM for log messages. Lookups don't allocate, so EL2 may
+//! use them.
+
+use super::hyper;
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    pub const NIL: Self = Self([0; 16]);
+
+    // A version 4 UUID from 128 bits.
+    fn from_bits(hi: u64, lo: u64) -> Self {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&hi.to_be_bytes());
+        bytes[8..].copy_from_slice(&lo.to_be_bytes());
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Self(bytes)
+    }
+
+    /// A UUID no other VM of this boot has. It isn't random: it mixes the
+    /// physical counter with a creation count, which is enough to tell
+    /// VMs apart in logs of different boots too. VMs that must keep their
+    /// UUID across boots are given one.
+    pub fn generate() -> Self {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+        let n = CREATED.fetch_add(1, Ordering::Relaxed);
+        let now = hyper::read_cntpct();
+        Self::from_bits(mix(now ^ n.rotate_left(32)), mix(now.wrapping_add(n)))
+    }
+
+    /// Parse the 8-4-4-4-12 hex form.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut bytes = [0; 16];
+        let mut n = 0;
+        for (i, group) in s.split('-').enumerate() {
+            if i >= 5 || group.len() != [8, 4, 4, 4, 12][i] {
+                return None;
+            }
+            for pair in group.as_bytes().chunks(2) {
+                let pair = core::str::from_utf8(pair).ok()?;
+                bytes[n] = u8::from_str_radix(pair, 16).ok()?;
+                n += 1;
+            }
+        }
+        (n == 16).then_some(Self(bytes))
+    }
+}
+
+// splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub uuid: Uuid,
+    pub name: Option<String>,
+    /// Key and value pairs, in the order given.
+    pub metadata: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityError {
+    /// The VM id has an identity already.
+    VmExists,
+    /// Another VM has the UUID.
+    UuidTaken,
+}
+
+static VMS: SpinLock<BTreeMap<usize, Identity>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` its identity for as long as it exists.
+pub fn register(vm_id: usize, identity: Identity) -> Result<(), IdentityError> {
+    let mut vms = VMS.irqsave_lock();
+    if vms.contains_key(&vm_id) {
+        return Err(IdentityError::VmExists);
+    }
+    if vms.values().any(|i| i.uuid == identity.uuid) {
+        return Err(IdentityError::UuidTaken);
+    }
+    vms.insert(vm_id, identity);
+    Ok(())
+}
+
+/// Forget the identity of VM `vm_id`, which is gone.
+pub fn release(vm_id: usize) -> Option<Identity> {
+    VMS.irqsave_lock().remove(&vm_id)
+}
+
+pub fn uuid_of(vm_id: usize) -> Option<Uuid> {
+    VMS.irqsave_lock().get(&vm_id).map(|i| i.uuid)
+}
+
+pub fn identity_of(vm_id: usize) -> Option<Identity> {
+    VMS.irqsave_lock().get(&vm_id).cloned()
+}
+
+/// Id of the VM that has `uuid`.
+pub fn find(uuid: Uuid) -> Option<usize> {
+    VMS.irqsave_lock()
+        .iter()
+        .find(|(_, i)| i.uuid == uuid)
+        .map(|(&vm_id, _)| vm_id)
+}
+
+/// VM `vm_id` as log messages name it: "vm3 (name uuid)", or "vm3" for a
+/// VM without identity.
+pub fn tag(vm_id: usize) -> VmTag {
+    VmTag(vm_id)
+}
+
+pub struct VmTag(usize);
+
+impl fmt::Display for VmTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vm{}", self.0)?;
+        match VMS.irqsave_lock().get(&self.0) {
+            Some(Identity {
+                uuid,
+                name: Some(name),
+                ..
+            }) => write!(f, " ({} {})", name, uuid),
+            Some(identity) => write!(f, " ({})", identity.uuid),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_uuid_format() {
+        let uuid = Uuid::from_bits(0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210);
+        let s = uuid.to_string();
+        assert_eq!(s, "01234567-89ab-4def-bedc-ba9876543210");
+        assert_eq!(Uuid::parse(&s), Some(uuid));
+        assert_eq!(Uuid::parse("01234567-89ab-4def-bedc"), None);
+        assert_eq!(Uuid::parse("0123456789ab-4def-bedc-ba98-76543210"), None);
+        assert_eq!(Uuid::parse("0123456z-89ab-4def-bedc-ba9876543210"), None);
+        assert_ne!(Uuid::generate(), Uuid::generate());
+    }
+
+    #[test]
+    fn test_identity_registry() {
+        let vm_id = test_vm_id();
+        let identity = Identity {
+            uuid: Uuid::generate(),
+            name: Some("rtos".to_string()),
+            metadata: Vec::new(),
+        };
+        let uuid = identity.uuid;
+        assert_eq!(register(vm_id, identity.clone()), Ok(()));
+        assert_eq!(
+            register(vm_id, Identity::default()),
+            Err(IdentityError::VmExists)
+        );
+        assert_eq!(register(vm_id - 1, identity), Err(IdentityError::UuidTaken));
+        assert_eq!(find(uuid), Some(vm_id));
+        assert_eq!(
+            tag(vm_id).to_string(),
+            alloc::format!("vm{} (rtos {})", vm_id, uuid)
+        );
+        assert!(release(vm_id).is_some());
+        assert_eq!(uuid_of(vm_id), None);
+        assert_eq!(tag(vm_id).to_string(), alloc::format!("vm{}", vm_id));
+    }
+}
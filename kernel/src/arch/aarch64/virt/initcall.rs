@@ -0,0 +1,256 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ordered bring-up of the hypervisor. Every component registers in
+//! `INITCALLS` under a level; levels run in order and a level only runs on a
+//! core once all earlier levels succeeded there. The first two levels run at
+//! EL2 before `.bss` is cleared and before the logger exists, so the state
+//! lives in `.data` and failures are only logged once the last level runs.
+
+use super::{
+    alternative, autostart, cacheid, console_log, el2_stack, host_pm, hyper, kick, qemu, sections,
+    status, vgic, vtimer, workers,
+};
+use crate::{arch::aarch64::current_cpu_id, scheduler, time::Tick};
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+// How long the report waits for the other cores to get through their
+// levels before it calls them stuck.
+const REPORT_WAIT_TICKS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitLevel {
+    /// EL2 registers and vectors of each core. Runs at EL2 during boot.
+    Hyp,
+    /// GIC virtual CPU interface of each core. Runs at EL2 during boot.
+    Vgic,
+    /// Host side interrupts of each core, after the GIC is up.
+    CpuIrq,
+    /// Host threads, once the scheduler runs. Core 0 only.
+    Services,
+}
+
+impl InitLevel {
+    const fn per_cpu(self) -> bool {
+        !matches!(self, Self::Services)
+    }
+
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+struct InitCall {
+    name: &'static str,
+    level: InitLevel,
+    run: fn() -> Result<(), &'static str>,
+}
+
+// In bring-up order within each level.
+static INITCALLS: [InitCall; 15] = [
+    InitCall {
+        name: "hyp",
+        level: InitLevel::Hyp,
+        run: || {
+            hyper::hyp_init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "el2_stack",
+        level: InitLevel::Hyp,
+        run: el2_stack::paint,
+    },
+    InitCall {
+        name: "alternative",
+        level: InitLevel::Hyp,
+        run: alternative::init,
+    },
+    InitCall {
+        name: "sections",
+        level: InitLevel::Hyp,
+        run: sections::check_layout,
+    },
+    InitCall {
+        name: "vgic",
+        level: InitLevel::Vgic,
+        run: vgic::init,
+    },
+    InitCall {
+        name: "kick",
+        level: InitLevel::CpuIrq,
+        run: || {
+            kick::cpu_init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "vgic_irq",
+        level: InitLevel::CpuIrq,
+        run: || {
+            vgic::cpu_init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "vtimer_irq",
+        level: InitLevel::CpuIrq,
+        run: || {
+            vtimer::cpu_init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "cacheid",
+        level: InitLevel::CpuIrq,
+        run: cacheid::cpu_init,
+    },
+    InitCall {
+        name: "qemu",
+        level: InitLevel::CpuIrq,
+        run: || {
+            qemu::detect();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "workers",
+        level: InitLevel::Services,
+        run: || {
+            workers::init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "console_log",
+        level: InitLevel::Services,
+        run: || {
+            console_log::init();
+            Ok(())
+        },
+    },
+    InitCall {
+        name: "host_pm",
+        level: InitLevel::Services,
+        run: host_pm::init,
+    },
+    InitCall {
+        name: "status",
+        level: InitLevel::Services,
+        run: status::init,
+    },
+    // Last, so the VMs find every service up.
+    InitCall {
+        name: "autostart",
+        level: InitLevel::Services,
+        run: autostart::init,
+    },
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Failure {
+    level: InitLevel,
+    name: &'static str,
+    error: &'static str,
+}
+
+// Bitmask of levels completed on each core.
+#[link_section = ".data"]
+static DONE: [AtomicU32; NUM_CORES] = [const { AtomicU32::new(0) }; NUM_CORES];
+// Levels each core has been through, whether they succeeded or not. Also
+// publishes the core's `FAILURES` entry to the report.
+#[link_section = ".data"]
+static REACHED: [AtomicU32; NUM_CORES] = [const { AtomicU32::new(0) }; NUM_CORES];
+// First failure on each core, only written by that core.
+#[link_section = ".data"]
+static mut FAILURES: [Option<Failure>; NUM_CORES] = [None; NUM_CORES];
+
+fn ready_for(cpu: usize, level: InitLevel) -> bool {
+    let needed = level.bit() - 1;
+    DONE[cpu].load(Ordering::Acquire) & needed == needed
+}
+
+/// Run all init calls of `level` on the calling core.
+pub fn run_level(level: InitLevel) {
+    let cpu = current_cpu_id();
+    if !level.per_cpu() && cpu != 0 {
+        return;
+    }
+    // Nothing more comes up on a core after its first failure.
+    if ready_for(cpu, level) {
+        run_calls(cpu, level);
+    }
+    REACHED[cpu].fetch_or(level.bit(), Ordering::Release);
+    // The logger is up by the last level, say what went wrong anywhere.
+    if level == InitLevel::Services {
+        report();
+    }
+}
+
+fn run_calls(cpu: usize, level: InitLevel) {
+    for call in INITCALLS.iter().filter(|c| c.level == level) {
+        if let Err(error) = (call.run)() {
+            unsafe {
+                (*addr_of_mut!(FAILURES))[cpu] = Some(Failure {
+                    level,
+                    name: call.name,
+                    error,
+                });
+            }
+            return;
+        }
+    }
+    DONE[cpu].fetch_or(level.bit(), Ordering::Release);
+}
+
+/// Whether guests can run on `cpu`.
+pub fn cpu_ready(cpu: usize) -> bool {
+    ready_for(cpu, InitLevel::Services)
+}
+
+// Whether every core has been through all per-core levels.
+fn all_reached() -> bool {
+    let per_cpu = InitLevel::Services.bit() - 1;
+    REACHED
+        .iter()
+        .all(|reached| reached.load(Ordering::Acquire) & per_cpu == per_cpu)
+}
+
+fn report() {
+    // Secondaries may still be running their per-core levels.
+    let deadline = Tick::now().add(Tick(REPORT_WAIT_TICKS));
+    while !all_reached() && Tick::now() < deadline {
+        scheduler::suspend_me_for::<()>(Tick(1), None);
+    }
+    let failures = unsafe { &*addr_of!(FAILURES) };
+    for (cpu, failure) in failures.iter().enumerate() {
+        match failure {
+            Some(f) => log::error!(
+                "[virt] cpu {} init failed at {:?}/{}: {}",
+                cpu,
+                f.level,
+                f.name,
+                f.error
+            ),
+            None if !ready_for(cpu, InitLevel::Services) => {
+                log::error!("[virt] cpu {} did not complete init", cpu)
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fallback decoding of guest loads and stores for data aborts that come
+//! without a valid ISS (ISV=0), such as pre- and post-indexed forms. The
+//! faulting instruction is fetched from guest memory and decoded here.
+//! Single-register LDR/STR and LDP/STP/LDPSW of general purpose registers
+//! are covered; exclusives and SIMD&FP accesses still end the VM.
+
+use super::{
+    exit::MmioAccess,
+    guest_mem::{GuestMemory, VmMemory},
+    hyper,
+    vcpu::Vcpu,
+};
+
+// SPSR_EL2.M[4]: the guest was in AArch32.
+const SPSR_AARCH32: u64 = 1 << 4;
+// SPSR_EL2.M[3:0] for EL1h, the only mode whose SP is SP_EL1.
+const SPSR_MODE_MASK: u64 = 0xf;
+const SPSR_EL1H: u64 = 0x5;
+const SP: u8 = 31;
+
+/// Base register update of an indexed access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Writeback {
+    /// Base register; 31 is SP.
+    pub rn: u8,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadStore {
+    /// The access of `access.reg`, or of the first register of a pair.
+    pub access: MmioAccess,
+    pub writeback: Option<Writeback>,
+    /// Second register of a pair, accessed right after the first.
+    pub pair: Option<u8>,
+}
+
+/// Decode an A64 load/store register instruction: unsigned offset,
+/// unscaled, pre- or post-indexed immediate, or register offset. Also
+/// decodes register pairs, see `decode_pair`.
+pub fn decode_ldst(insn: u32) -> Option<LoadStore> {
+    if insn & 0x3c00_0000 == 0x2800_0000 {
+        return decode_pair(insn);
+    }
+    // Load/store register class with V = 0.
+    if insn & 0x3f00_0000 != 0x3800_0000 && insn & 0x3f00_0000 != 0x3900_0000 {
+        return None;
+    }
+    let size = (insn >> 30) & 0x3;
+    let opc = (insn >> 22) & 0x3;
+    let (write, sign_extend, sf) = match (opc, size) {
+        (0, _) => (true, false, size == 3),
+        (1, _) => (false, false, size == 3),
+        // LDRSB/LDRSH/LDRSW to X; size 3 is PRFM.
+        (2, 0..=2) => (false, true, true),
+        // LDRSB/LDRSH to W.
+        (3, 0..=1) => (false, true, false),
+        _ => return None,
+    };
+    let writeback = if insn & (1 << 24) != 0 {
+        None
+    } else if insn & (1 << 21) != 0 {
+        // Register offset; anything else in this space isn't a plain access.
+        if (insn >> 10) & 0x3 != 0x2 {
+            return None;
+        }
+        None
+    } else {
+        match (insn >> 10) & 0x3 {
+            // Unscaled immediate (LDUR/STUR).
+            0x0 => None,
+            // Post- and pre-indexed.
+            0x1 | 0x3 => Some(Writeback {
+                rn: ((insn >> 5) & 0x1f) as u8,
+                offset: (((insn >> 12) & 0x1ff) as i64) << 55 >> 55,
+            }),
+            // Unprivileged (LDTR/STTR).
+            _ => None,
+        }
+    };
+    Some(LoadStore {
+        access: MmioAccess {
+            write,
+            size: 1 << size,
+            reg: (insn & 0x1f) as u8,
+            sign_extend,
+            sf,
+        },
+        writeback,
+        pair: None,
+    })
+}
+
+/// Decode an A64 load/store pair of general purpose registers: signed
+/// offset, pre- or post-indexed, or non-temporal.
+pub fn decode_pair(insn: u32) -> Option<LoadStore> {
+    // Load/store pair class with V = 0.
+    if insn & 0x3c00_0000 != 0x2800_0000 {
+        return None;
+    }
+    let load = insn & (1 << 22) != 0;
+    let (scale, sign_extend, sf) = match (insn >> 30, load) {
+        (0, _) => (2, false, false),
+        // LDPSW; its store encoding is unallocated.
+        (1, true) => (2, true, true),
+        (2, _) => (3, false, true),
+        _ => return None,
+    };
+    let offset = (((insn >> 15) & 0x7f) as i64) << 57 >> 57 << scale;
+    let rn = ((insn >> 5) & 0x1f) as u8;
+    let writeback = match (insn >> 23) & 0x7 {
+        // Non-temporal and signed offset.
+        0x0 | 0x2 => None,
+        // Post- and pre-indexed.
+        0x1 | 0x3 => Some(Writeback { rn, offset }),
+        _ => return None,
+    };
+    Some(LoadStore {
+        access: MmioAccess {
+            write: !load,
+            size: 1 << scale,
+            reg: (insn & 0x1f) as u8,
+            sign_extend,
+            sf,
+        },
+        writeback,
+        pair: Some(((insn >> 10) & 0x1f) as u8),
+    })
+}
+
+/// Fetch and decode the instruction at the guest's PC.
+pub fn fetch_ldst(vcpu: &Vcpu) -> Option<LoadStore> {
+    if vcpu.regs.spsr & SPSR_AARCH32 != 0 {
+        return None;
+    }
+    let ipa = hyper::translate_el1_va(vcpu.regs.elr)?;
+    let insn = VmMemory(vcpu.vm_id).read_u32(ipa).ok()?;
+    decode_ldst(insn)
+}
+
+/// Update the base register after the access. Fails for an SP base outside
+/// EL1h, whose stack pointer isn't part of the saved state.
+pub fn apply_writeback(vcpu: &mut Vcpu, wb: Writeback) -> Result<(), ()> {
+    let base = if wb.rn == SP {
+        if vcpu.regs.spsr & SPSR_MODE_MASK != SPSR_EL1H {
+            return Err(());
+        }
+        &mut vcpu.regs.sp_el1
+    } else {
+        &mut vcpu.regs.x[wb.rn as usize]
+    };
+    *base = base.wrapping_add(wb.offset as u64);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_decode_ldst() {
+        // str w1, [x0], #4
+        let ls = decode_ldst(0xb800_4401).unwrap();
+        assert!(ls.access.write);
+        assert_eq!(ls.access.size, 4);
+        assert_eq!(ls.access.reg, 1);
+        assert_eq!(ls.writeback, Some(Writeback { rn: 0, offset: 4 }));
+
+        // ldr x2, [x1, #-8]!
+        let ls = decode_ldst(0xf85f_8c22).unwrap();
+        assert!(!ls.access.write);
+        assert_eq!(ls.access.size, 8);
+        assert!(ls.access.sf);
+        assert_eq!(ls.writeback, Some(Writeback { rn: 1, offset: -8 }));
+
+        // ldrsb w3, [x0], #1
+        let ls = decode_ldst(0x38c0_1403).unwrap();
+        assert!(ls.access.sign_extend);
+        assert!(!ls.access.sf);
+        assert_eq!(ls.access.size, 1);
+
+        // ldr w0, [x1, #16]
+        let ls = decode_ldst(0xb940_1020).unwrap();
+        assert_eq!(ls.writeback, None);
+        assert_eq!(ls.access.reg, 0);
+
+        // ldr q0, [x0], #16
+        assert_eq!(decode_ldst(0x3cc1_0400), None);
+        // prfm pldl1keep, [x0]
+        assert_eq!(decode_ldst(0xf980_0000), None);
+    }
+
+    #[test]
+    fn test_decode_pair() {
+        // stp w1, w2, [x0]
+        let ls = decode_ldst(0x2900_0801).unwrap();
+        assert!(ls.access.write);
+        assert_eq!(ls.access.size, 4);
+        assert_eq!(ls.access.reg, 1);
+        assert_eq!(ls.pair, Some(2));
+        assert_eq!(ls.writeback, None);
+
+        // ldp x2, x3, [x1], #16
+        let ls = decode_ldst(0xa8c1_0c22).unwrap();
+        assert!(!ls.access.write);
+        assert_eq!(ls.access.size, 8);
+        assert!(ls.access.sf);
+        assert_eq!(ls.pair, Some(3));
+        assert_eq!(ls.writeback, Some(Writeback { rn: 1, offset: 16 }));
+
+        // stp x0, x1, [sp, #-16]!
+        let ls = decode_ldst(0xa9bf_07e0).unwrap();
+        assert_eq!(
+            ls.writeback,
+            Some(Writeback {
+                rn: 31,
+                offset: -16
+            })
+        );
+
+        // ldpsw x0, x1, [x2]
+        let ls = decode_ldst(0x6940_0440).unwrap();
+        assert!(ls.access.sign_extend);
+        assert_eq!(ls.access.size, 4);
+
+        // ldp q0, q1, [x0]
+        assert_eq!(decode_ldst(0xad40_0400), None);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interrupt lines of emulated devices. A device model holds an `IrqLine`
+//! from `VmBuilder::irq_line` and drives it like the wire it models: a
+//! level line follows `raise`/`lower` and the VGIC keeps it pending for as
+//! long as it is high, re-pending it after the guest's EOI; an edge line
+//! queues one interrupt per `raise`. Lines go to the VM's first vCPU.
+
+use super::{
+    kick,
+    vcpu::{vcpu_manager, VcpuError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Level,
+    Edge,
+}
+
+/// Handle on one SPI of a VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqLine {
+    vm_id: usize,
+    intid: u32,
+    trigger: Trigger,
+}
+
+impl IrqLine {
+    /// A line of VM `vm_id` raising `intid`. `VmBuilder::irq_line` also
+    /// checks that nothing else raises it.
+    pub const fn new(vm_id: usize, intid: u32, trigger: Trigger) -> Self {
+        Self {
+            vm_id,
+            intid,
+            trigger,
+        }
+    }
+
+    pub fn vm_id(&self) -> usize {
+        self.vm_id
+    }
+
+    pub fn intid(&self) -> u32 {
+        self.intid
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+
+    /// Assert the line. For an edge line this is one interrupt.
+    pub fn raise(&self) -> Result<(), VcpuError> {
+        self.set(true)
+    }
+
+    /// Deassert the line. An edge line has nothing to deassert.
+    pub fn lower(&self) -> Result<(), VcpuError> {
+        self.set(false)
+    }
+
+    pub fn set(&self, high: bool) -> Result<(), VcpuError> {
+        let vcpu_id = self.target()?;
+        match self.trigger {
+            Trigger::Level => kick::set_irq_level(self.vm_id, vcpu_id, self.intid, high),
+            Trigger::Edge if high => kick::inject_irq(self.vm_id, vcpu_id, self.intid),
+            Trigger::Edge => Ok(()),
+        }
+    }
+
+    /// Whether a level line is high.
+    pub fn is_high(&self) -> bool {
+        let Ok(vcpu_id) = self.target() else {
+            return false;
+        };
+        vcpu_manager().with_vcpu(vcpu_id, |v| v.vgic.level(self.intid)) == Some(true)
+    }
+
+    fn target(&self) -> Result<usize, VcpuError> {
+        vcpu_manager()
+            .ids_of(self.vm_id)
+            .next()
+            .ok_or(VcpuError::InvalidId)
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host interrupt isolation for latency-sensitive VMs. A VM may claim
+//! physical cores for its vCPUs in `VmConfig::isolated_cores`, and a vCPU
+//! claims the core it is dedicated to; host device interrupts are routed off
+//! every claimed core, so a guest running there isn't thrown out for host
+//! work it doesn't care about.
+
+use super::vcpu::vcpu_manager;
+use crate::arch::aarch64::irq;
+
+/// Cores claimed by the VMs that currently have vCPUs.
+pub fn claimed_cores() -> usize {
+    let mut mask = 0;
+    vcpu_manager().for_each(|vcpu| {
+        mask |= vcpu.config.isolated_cores | vcpu.dedicated_core.map_or(0, |cpu| 1 << cpu);
+    });
+    mask
+}
+
+/// Re-steer host SPIs after the set of claimed cores changed.
+pub fn update() -> Result<(), &'static str> {
+    let mask = claimed_cores();
+    if mask == irq::isolated_cores() {
+        return Ok(());
+    }
+    irq::set_isolated_cores(mask)?;
+    log::info!("[Hyp] host interrupts kept off cores {:#x}", mask);
+    Ok(())
+}
@@ -0,0 +1,315 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-CPU vCPU kicks. A kick records a reason on the target vCPU and, if
+//! the vCPU is in guest mode on another core, raises a dedicated SGI there.
+//! The EL2 IRQ path recognizes that SGI and forces a guest exit carrying the
+//! accumulated reasons back to the vCPU run loop.
+//!
+//! Kicks and injections made while EL2 handles an exit, e.g. by a device
+//! the guest wrote to, are deferred until the exit released its vCPU.
+
+use super::{
+    exit::ExitCode,
+    hal::{gic, GicBackend},
+    ring::Ring,
+    vcpu::{self, vcpu_manager, Vcpu, VcpuError},
+    vector::TrapFrame,
+    vgic::MAINTENANCE_PPI,
+    vlog::vlog_limited,
+    vsgi::{self, Sgi},
+    vtimer::{self, VTIMER_INTID},
+};
+use crate::{
+    arch::aarch64::{
+        current_cpu_id,
+        irq::{self, IrqHandler, IrqNumber, Priority},
+    },
+    sync::SpinLock,
+};
+use alloc::boxed::Box;
+use core::arch::asm;
+
+/// SGI reserved for kicks. SGI 1 is the scheduler IPI.
+pub const KICK_SGI: u32 = 8;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+// Kicks and injections one exit can make. A broadcast SGI takes one.
+const MAX_DEFERRED: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum KickReason {
+    Reschedule = 1 << 0,
+    PendingIrq = 1 << 1,
+    StopRequest = 1 << 2,
+    TlbShootdown = 1 << 3,
+    /// The VM is being reset; the vCPU starts over, see `vcpu::reset_vm`.
+    Reset = 1 << 4,
+    /// The host is suspending; the vCPU waits out of guest mode, see
+    /// `host_pm`.
+    Freeze = 1 << 5,
+}
+
+#[inline]
+pub fn has_reason(reasons: u32, reason: KickReason) -> bool {
+    reasons & reason as u32 != 0
+}
+
+/// Kick vCPU `vcpu_id` of VM `vm_id`. The reason is always recorded; the SGI
+/// is only sent when the vCPU currently runs on another core, otherwise the
+/// reason is picked up on its next entry.
+pub fn vcpu_kick(vm_id: usize, vcpu_id: usize, reason: KickReason) -> Result<(), VcpuError> {
+    if vcpu::in_exit() {
+        return defer(vm_id, vcpu_id, Deferred::Kick(reason));
+    }
+    with_target(vm_id, vcpu_id, |vcpu| kick(vcpu, reason))
+}
+
+/// Queue a virtual interrupt for a vCPU and make sure it notices.
+pub fn inject_irq(vm_id: usize, vcpu_id: usize, intid: u32) -> Result<(), VcpuError> {
+    if vcpu::in_exit() {
+        return defer(vm_id, vcpu_id, Deferred::Irq(intid));
+    }
+    with_target(vm_id, vcpu_id, |vcpu| {
+        vcpu.vgic.inject(intid);
+        notify_irq(vcpu);
+    })
+}
+
+/// Send `sgi` from vCPU `sender` to the vCPUs of its VM whose index is in
+/// `targets`, see `vsgi::deliver`. At EL2 the whole SGI is deferred as one
+/// entry, however many vCPUs it goes to.
+pub fn send_sgi(vm_id: usize, sender: usize, targets: u16, sgi: Sgi) -> Result<(), VcpuError> {
+    if vcpu::in_exit() {
+        return defer(vm_id, sender, Deferred::Sgi(sgi, targets));
+    }
+    vsgi::deliver(vm_id, targets, sgi);
+    Ok(())
+}
+
+/// Queue a virtual FIQ for a vCPU, see `Vgic::inject_fiq`.
+pub fn inject_fiq(vm_id: usize, vcpu_id: usize, intid: u32) -> Result<(), VcpuError> {
+    if vcpu::in_exit() {
+        return defer(vm_id, vcpu_id, Deferred::Fiq(intid));
+    }
+    with_target(vm_id, vcpu_id, |vcpu| {
+        vcpu.vgic.inject_fiq(intid);
+        notify_irq(vcpu);
+    })
+}
+
+/// Drive level-sensitive `intid` of a vCPU, see `Vgic::set_level`. The
+/// vCPU is only kicked when the line goes high.
+pub fn set_irq_level(
+    vm_id: usize,
+    vcpu_id: usize,
+    intid: u32,
+    high: bool,
+) -> Result<(), VcpuError> {
+    if vcpu::in_exit() {
+        return defer(vm_id, vcpu_id, Deferred::Level(intid, high));
+    }
+    with_target(vm_id, vcpu_id, |vcpu| {
+        if vcpu.vgic.set_level(intid, high) {
+            notify_irq(vcpu);
+        }
+    })
+}
+
+fn with_target(vm_id: usize, vcpu_id: usize, f: impl FnOnce(&Vcpu)) -> Result<(), VcpuError> {
+    vcpu_manager()
+        .with_vcpu(vcpu_id, |vcpu| {
+            if vcpu.vm_id != vm_id {
+                return Err(VcpuError::WrongVm);
+            }
+            f(vcpu);
+            Ok(())
+        })
+        .ok_or(VcpuError::InvalidId)?
+}
+
+fn kick(vcpu: &Vcpu, reason: KickReason) {
+    vcpu.raise_kick(reason);
+    match vcpu.running_on() {
+        Some(cpu) if cpu != current_cpu_id() => {
+            irq::send_sgi(IrqNumber::new(KICK_SGI), 1 << cpu);
+        }
+        Some(_) => {}
+        // Its host thread may be blocked waiting for just this.
+        None => vcpu_manager().wake(vcpu.id),
+    }
+}
+
+/// Raise the kick SGI on this core, so EL1 looks at it soon: right after
+/// the eret if EL2 returns to the host, or on an exit it forces if EL2
+/// returns to a guest.
+pub(crate) fn raise_local() {
+    irq::send_sgi(IrqNumber::new(KICK_SGI), 1 << current_cpu_id());
+}
+
+fn notify_irq(vcpu: &Vcpu) {
+    // Out of guest mode the vCPU first needs its host thread scheduled.
+    if vcpu.running_on().is_none() {
+        vcpu.boost.raise();
+    }
+    kick(vcpu, KickReason::PendingIrq);
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Deferred {
+    Kick(KickReason),
+    Irq(u32),
+    Fiq(u32),
+    Level(u32, bool),
+    // An SGI and the indexes of the vCPUs it goes to.
+    Sgi(Sgi, u16),
+}
+
+// Kicks and injections made at EL2 while an exit holds its vCPU, made
+// once it's released: the target may be the held vCPU itself, or one
+// whose own exit is waiting for ours. Per core, so only that core's EL2
+// takes the lock.
+#[link_section = ".hyp.data"]
+static DEFERRED: [SpinLock<Ring<(usize, usize, Deferred), MAX_DEFERRED>>; NUM_CORES] =
+    [const { SpinLock::new(Ring::new()) }; NUM_CORES];
+
+fn defer(vm_id: usize, vcpu_id: usize, op: Deferred) -> Result<(), VcpuError> {
+    DEFERRED[current_cpu_id()]
+        .irqsave_lock()
+        .push_back((vm_id, vcpu_id, op))
+        .map_err(|(vm_id, vcpu_id, op)| {
+            vlog_limited!(
+                Vcpu,
+                Error,
+                "[EL2] deferred queue of cpu {} full, {:?} of vcpu {} in vm {} dropped",
+                current_cpu_id(),
+                op,
+                vcpu_id,
+                vm_id
+            );
+            VcpuError::Busy
+        })
+}
+
+/// Make the kicks and injections deferred on this core. Runs at EL2 once
+/// the exit handled there released its vCPU.
+#[link_section = ".hyp.text"]
+pub(crate) fn run_deferred() {
+    loop {
+        let Some((vm_id, vcpu_id, op)) = DEFERRED[current_cpu_id()].irqsave_lock().pop_front()
+        else {
+            return;
+        };
+        let _ = match op {
+            Deferred::Kick(reason) => vcpu_kick(vm_id, vcpu_id, reason),
+            Deferred::Irq(intid) => inject_irq(vm_id, vcpu_id, intid),
+            Deferred::Fiq(intid) => inject_fiq(vm_id, vcpu_id, intid),
+            Deferred::Level(intid, high) => set_irq_level(vm_id, vcpu_id, intid, high),
+            Deferred::Sgi(sgi, targets) => send_sgi(vm_id, vcpu_id, targets, sgi),
+        };
+    }
+}
+
+/// Handle an IRQ taken at EL2 while `vcpu` owns this core. Always leaves the
+/// guest: either to report kick reasons or to let the host take its IRQ.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of the IRQ.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn handle_el2_irq(frame: *mut TrapFrame, vcpu: &mut Vcpu) {
+    vcpu.regs.save_from_frame(frame);
+    vcpu.shadow.record(&vcpu.regs);
+
+    match gic().highest_pending() {
+        KICK_SGI => {}
+        // The guest made room for interrupts that didn't fit its list
+        // registers, or EOIed a level interrupt; load what is pending and
+        // let it go on.
+        MAINTENANCE_PPI => {
+            let intid = gic().ack();
+            vtimer::update(vcpu);
+            vcpu.vgic.refill();
+            gic().eoi(intid);
+            return;
+        }
+        // The guest's timer fired: mask it and let the guest take it, see
+        // `vtimer`.
+        VTIMER_INTID => {
+            let intid = gic().ack();
+            vtimer::update(vcpu);
+            vcpu.vgic.refill();
+            gic().eoi(intid);
+            return;
+        }
+        // Left pending, so the host takes it at EL1 right after the eret.
+        _ => {
+            vcpu::leave_guest(frame, vcpu, ExitCode::HostIrq);
+            return;
+        }
+    }
+
+    gic().eoi(gic().ack());
+
+    if super::panic::is_panicking() {
+        super::panic::park(vcpu);
+    }
+
+    let reasons = vcpu.take_kicks();
+    if has_reason(reasons, KickReason::TlbShootdown) {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalls12e1is",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        );
+    }
+    vcpu::leave_guest(frame, vcpu, ExitCode::Kick(reasons));
+}
+
+struct KickIrq;
+
+impl IrqHandler for KickIrq {
+    // The exit already happened at EL2. Reaching here means the kick
+    // landed while the target was back in the host, where the reason stays
+    // recorded for the next entry, or that EL2 left wakes for us.
+    fn handle(&mut self) {
+        vcpu_manager().wake_deferred();
+    }
+}
+
+/// Enable the kick SGI on the calling core.
+pub fn cpu_init() {
+    let irq = IrqNumber::new(KICK_SGI);
+    if current_cpu_id() == 0 {
+        let _ = irq::register_handler(irq, Box::new(KickIrq));
+    }
+    irq::enable_irq_with_priority(irq, current_cpu_id(), Priority::High);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_has_reason() {
+        let reasons = KickReason::Reschedule as u32 | KickReason::TlbShootdown as u32;
+        assert!(has_reason(reasons, KickReason::Reschedule));
+        assert!(has_reason(reasons, KickReason::TlbShootdown));
+        assert!(!has_reason(reasons, KickReason::StopRequest));
+        assert!(!has_reason(reasons, KickReason::PendingIrq));
+    }
+}
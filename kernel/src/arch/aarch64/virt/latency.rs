@@ -0,0 +1,210 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! World-switch latency self-measurement. The EL2 vectors stamp the counter
+//! right after the first registers hit the trap frame and right before the
+//! last ones are reloaded; the Rust side stamps around the exit handler and
+//! the VGIC flush/sync. Every segment keeps a ring of recent samples per
+//! core, reported as percentiles through /proc/hypervisor/latency.
+//!
+//! All stamps read CNTPCT_EL0: CNTVOFF_EL2 is rewritten during the switch, so
+//! virtual counter deltas across it would be meaningless.
+
+use super::hyper;
+use crate::arch::aarch64::{current_cpu_id, registers::cntfrq_el0::CNTFRQ_EL0};
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+use tock_registers::interfaces::Readable;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+/// Samples kept per segment and core.
+const RING_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    /// Vector entry until the exit handler starts running.
+    FrameSave,
+    /// The exit handler, minus VGIC work done inside it.
+    Handler,
+    /// One list register flush or sync.
+    Vgic,
+    /// Exit handler return until the final register reload before eret.
+    FrameRestore,
+}
+
+impl Segment {
+    pub const ALL: [Segment; 4] = [
+        Segment::FrameSave,
+        Segment::Handler,
+        Segment::Vgic,
+        Segment::FrameRestore,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Segment::FrameSave => "frame_save",
+            Segment::Handler => "handler",
+            Segment::Vgic => "vgic",
+            Segment::FrameRestore => "frame_restore",
+        }
+    }
+}
+
+struct Ring {
+    samples: [AtomicU32; RING_SIZE],
+    next: AtomicUsize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            samples: [const { AtomicU32::new(0) }; RING_SIZE],
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, cycles: u64) {
+        let n = self.next.load(Ordering::Relaxed);
+        self.samples[n % RING_SIZE].store(cycles.min(u32::MAX as u64) as u32, Ordering::Relaxed);
+        self.next.store(n + 1, Ordering::Release);
+    }
+
+    fn collect(&self, out: &mut Vec<u32>) {
+        let n = self.next.load(Ordering::Acquire).min(RING_SIZE);
+        out.extend(self.samples[..n].iter().map(|s| s.load(Ordering::Relaxed)));
+    }
+}
+
+static RINGS: [[Ring; Segment::ALL.len()]; NUM_CORES] =
+    [const { [const { Ring::new() }; Segment::ALL.len()] }; NUM_CORES];
+
+/// Written by the EL2 vectors, see `latency_stamp!`.
+#[no_mangle]
+static VIRT_LAT_ENTRY: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+#[no_mangle]
+static VIRT_LAT_RESTORE: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+
+// When the running exit handler started, or zero.
+static HANDLER_START: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+// VGIC cycles spent inside the running exit handler.
+static HANDLER_VGIC: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+// When the last exit handler returned, until its frame restore is recorded.
+static HANDLER_END: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(0) }; NUM_CORES];
+
+fn record(cpu: usize, seg: Segment, cycles: u64) {
+    RINGS[cpu][seg as usize].push(cycles);
+}
+
+/// First thing on every trap from a lower EL. The eret that came before it
+/// carried the restore stamp of the previous guest exit, if there was one.
+pub(crate) fn trap_entered() {
+    let cpu = current_cpu_id();
+    let end = HANDLER_END[cpu].swap(0, Ordering::Relaxed);
+    if end != 0 {
+        let restore = VIRT_LAT_RESTORE[cpu].load(Ordering::Relaxed);
+        record(cpu, Segment::FrameRestore, restore.wrapping_sub(end));
+    }
+}
+
+/// The trap is a guest exit and its handler starts now.
+pub(crate) fn exit_begin() {
+    let cpu = current_cpu_id();
+    let now = hyper::read_cntpct();
+    let entry = VIRT_LAT_ENTRY[cpu].load(Ordering::Relaxed);
+    record(cpu, Segment::FrameSave, now.wrapping_sub(entry));
+    HANDLER_VGIC[cpu].store(0, Ordering::Relaxed);
+    HANDLER_START[cpu].store(now, Ordering::Relaxed);
+}
+
+/// The guest exit handler is about to return to the vector.
+pub(crate) fn exit_end() {
+    let cpu = current_cpu_id();
+    let now = hyper::read_cntpct();
+    let start = HANDLER_START[cpu].swap(0, Ordering::Relaxed);
+    let vgic = HANDLER_VGIC[cpu].load(Ordering::Relaxed);
+    record(
+        cpu,
+        Segment::Handler,
+        now.wrapping_sub(start).saturating_sub(vgic),
+    );
+    HANDLER_END[cpu].store(now, Ordering::Relaxed);
+}
+
+/// Time one VGIC flush or sync.
+pub(crate) fn time_vgic<R>(f: impl FnOnce() -> R) -> R {
+    let cpu = current_cpu_id();
+    let start = hyper::read_cntpct();
+    let ret = f();
+    let cycles = hyper::read_cntpct().wrapping_sub(start);
+    record(cpu, Segment::Vgic, cycles);
+    if HANDLER_START[cpu].load(Ordering::Relaxed) != 0 {
+        HANDLER_VGIC[cpu].fetch_add(cycles, Ordering::Relaxed);
+    }
+    ret
+}
+
+/// The `pct`th percentile of `samples` by nearest rank; sorts in place.
+pub fn percentile(samples: &mut [u32], pct: u32) -> u32 {
+    if samples.is_empty() {
+        return 0;
+    }
+    samples.sort_unstable();
+    let rank = (samples.len() * pct as usize).div_ceil(100).max(1);
+    samples[rank.min(samples.len()) - 1]
+}
+
+/// Per-segment percentiles over the recent samples of all cores, in counter
+/// cycles.
+pub fn dump(w: &mut dyn fmt::Write) -> fmt::Result {
+    write!(w, "segment count p50 p90 p99 max\r\n")?;
+    let mut samples = Vec::with_capacity(RING_SIZE * NUM_CORES);
+    for seg in Segment::ALL {
+        samples.clear();
+        for rings in RINGS.iter() {
+            rings[seg as usize].collect(&mut samples);
+        }
+        let p50 = percentile(&mut samples, 50);
+        write!(
+            w,
+            "{} {} {} {} {} {}\r\n",
+            seg.name(),
+            samples.len(),
+            p50,
+            percentile(&mut samples, 90),
+            percentile(&mut samples, 99),
+            samples.last().copied().unwrap_or(0),
+        )?;
+    }
+    write!(w, "cntfrq {}\r\n", CNTFRQ_EL0.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&mut [], 50), 0);
+        let mut samples: Vec<u32> = (1..=100).rev().collect();
+        assert_eq!(percentile(&mut samples, 50), 50);
+        assert_eq!(percentile(&mut samples, 99), 99);
+        assert_eq!(percentile(&mut samples, 100), 100);
+        assert_eq!(percentile(&mut samples, 0), 1);
+        assert_eq!(percentile(&mut [7], 90), 7);
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest RAM allocated on first write. A lazy region starts out with every
+//! page mapped read-only to one shared zero page, so building the VM
+//! allocates nothing and RAM the guest only reads, or never touches, costs
+//! no memory. The first write to a page takes a Stage-2 permission fault.
+//! EL2 can't allocate, so the vCPU exits with `ExitCode::Populate` and its
+//! run loop gives the page memory of its own before the write is retried.
+
+use super::{
+    exit::{ExitAction, ExitCode},
+    stage2::{self, MemType, S2Perms, Stage2, Stage2Error, PAGE_SIZE},
+};
+use crate::sync::SpinLock;
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    boxed::Box,
+    collections::BTreeMap,
+    vec::Vec,
+};
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE as usize]);
+
+// Mapped read-only into every lazy region, so nothing ever writes it.
+static ZERO_PAGE: Page = Page([0; PAGE_SIZE as usize]);
+
+/// Host physical address of the zero page.
+pub fn zero_pa() -> u64 {
+    &ZERO_PAGE as *const Page as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LazyError {
+    /// The IPA isn't in a lazy region of the VM.
+    NotLazy,
+    NoMemory,
+    Stage2(Stage2Error),
+}
+
+struct Region {
+    vm_id: usize,
+    ipa: u64,
+    size: u64,
+    perms: S2Perms,
+    // Pages given memory of their own, by IPA.
+    pages: BTreeMap<u64, Box<Page>>,
+}
+
+impl Region {
+    fn contains(&self, vm_id: usize, ipa: u64) -> bool {
+        self.vm_id == vm_id && (self.ipa..self.ipa + self.size).contains(&ipa)
+    }
+}
+
+// Looked up at EL2 on data aborts, only grown by the host.
+static REGIONS: SpinLock<Vec<Region>> = SpinLock::new(Vec::new());
+
+/// Back `[ipa, ipa + size)` of `s2` with the zero page, readable and
+/// executable as `perms` says but never writable.
+pub fn map_zero(s2: &mut Stage2, ipa: u64, size: u64, perms: S2Perms) -> Result<(), Stage2Error> {
+    let perms = S2Perms::new(perms.read, false, perms.exec);
+    s2.map_repeated(ipa, zero_pa(), size, MemType::Normal, perms)
+}
+
+/// Populate pages of `[ipa, ipa + size)` of VM `vm_id`, mapped by
+/// `map_zero`, with `perms` on their first write.
+pub fn register(vm_id: usize, ipa: u64, size: u64, perms: S2Perms) {
+    REGIONS.irqsave_lock().push(Region {
+        vm_id,
+        ipa,
+        size,
+        perms,
+        pages: BTreeMap::new(),
+    });
+}
+
+/// What to do about a Stage-2 data abort of VM `vm_id` at `ipa`, `None` if
+/// it isn't lazy RAM or the region doesn't allow the access. A write to a
+/// page still on the zero page exits to be populated; a fault on a page
+/// another vCPU populated meanwhile is retried. Runs at EL2.
+pub fn on_abort(vm_id: usize, ipa: u64, write: bool) -> Option<ExitAction> {
+    let page = ipa & !(PAGE_SIZE - 1);
+    // Held over the lookup, so `populate` is never caught between its
+    // unmap and map.
+    let regions = REGIONS.irqsave_lock();
+    let region = regions.iter().find(|r| r.contains(vm_id, page))?;
+    let allowed = if write {
+        region.perms.write
+    } else {
+        region.perms.read
+    };
+    if !allowed {
+        return None;
+    }
+    let (pa, _) = stage2::with_vm(vm_id, |s2| s2.lookup(page))??;
+    if pa == zero_pa() {
+        write.then_some(ExitAction::Exit(ExitCode::Populate(page)))
+    } else {
+        // Populated with `region.perms`, so the access goes through now.
+        Some(ExitAction::Resume)
+    }
+}
+
+fn alloc_page() -> Option<Box<Page>> {
+    let ptr = unsafe { alloc_zeroed(Layout::new::<Page>()) } as *mut Page;
+    // SAFETY: A zeroed `Page` is valid, and the layout is the one `Box`
+    // frees it with.
+    (!ptr.is_null()).then(|| unsafe { Box::from_raw(ptr) })
+}
+
+/// Give the page at `ipa` of VM `vm_id` memory of its own, unless it has
+/// some already. Runs in the vCPU's host thread on `ExitCode::Populate`,
+/// and before the host writes to guest memory.
+pub fn populate(vm_id: usize, ipa: u64) -> Result<(), LazyError> {
+    let page = ipa & !(PAGE_SIZE - 1);
+    let mem = alloc_page().ok_or(LazyError::NoMemory)?;
+    let pa = &*mem as *const Page as u64;
+    let mut regions = REGIONS.irqsave_lock();
+    let region = regions
+        .iter_mut()
+        .find(|r| r.contains(vm_id, page))
+        .ok_or(LazyError::NotLazy)?;
+    if region.pages.contains_key(&page) {
+        return Ok(());
+    }
+    // Unmapping first is the break-before-make the change of output
+    // address needs. Faults in between wait for `REGIONS` and then retry.
+    stage2::with_vm(vm_id, |s2| {
+        s2.unmap(page, PAGE_SIZE)?;
+        s2.map(page, pa, PAGE_SIZE, MemType::Normal, region.perms)
+    })
+    .ok_or(LazyError::NotLazy)?
+    .map_err(LazyError::Stage2)?;
+    region.pages.insert(page, mem);
+    Ok(())
+}
+
+/// Populate every page of `[ipa, ipa + len)` that is lazy RAM, so the host
+/// can write there.
+pub fn populate_range(vm_id: usize, ipa: u64, len: usize) -> Result<(), LazyError> {
+    let end = ipa.saturating_add(len as u64);
+    let mut page = ipa & !(PAGE_SIZE - 1);
+    while page < end {
+        match populate(vm_id, page) {
+            Ok(()) | Err(LazyError::NotLazy) => {}
+            Err(e) => return Err(e),
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// Bytes of lazy RAM of VM `vm_id`, and how many of them have memory.
+pub fn footprint(vm_id: usize) -> (u64, u64) {
+    REGIONS
+        .irqsave_lock()
+        .iter()
+        .filter(|r| r.vm_id == vm_id)
+        .fold((0, 0), |(size, used), r| {
+            (size + r.size, used + r.pages.len() as u64 * PAGE_SIZE)
+        })
+}
+
+/// Unmap and free the memory populated for a VM being torn down.
+pub fn release_vm(vm_id: usize) {
+    let released: Vec<Region> = {
+        let mut regions = REGIONS.irqsave_lock();
+        let (gone, kept): (Vec<_>, Vec<_>) = core::mem::take(&mut *regions)
+            .into_iter()
+            .partition(|r| r.vm_id == vm_id);
+        *regions = kept;
+        gone
+    };
+    for region in &released {
+        for &page in region.pages.keys() {
+            let _ = stage2::with_vm(vm_id, |s2| s2.unmap(page, PAGE_SIZE));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_lazy_populate() {
+        let vm_id = test_vm_id();
+        let base = 0x4000_0000;
+        let mut s2 = Stage2::new();
+        map_zero(&mut s2, base, 4 * PAGE_SIZE, S2Perms::RWX).unwrap();
+        stage2::install(vm_id, s2);
+        register(vm_id, base, 4 * PAGE_SIZE, S2Perms::RWX);
+
+        let page = base + PAGE_SIZE;
+        assert_eq!(on_abort(vm_id, base + 4 * PAGE_SIZE, true), None);
+        assert_eq!(on_abort(vm_id, page + 8, false), None);
+        assert_eq!(
+            on_abort(vm_id, page + 8, true),
+            Some(ExitAction::Exit(ExitCode::Populate(page)))
+        );
+        populate(vm_id, page + 8).unwrap();
+        populate(vm_id, page).unwrap();
+        assert_eq!(on_abort(vm_id, page, true), Some(ExitAction::Resume));
+        let attrs = stage2::with_vm(vm_id, |s2| s2.attrs(page)).flatten();
+        assert_eq!(attrs, Some((MemType::Normal, S2Perms::RWX)));
+        let (pa, _) = stage2::with_vm(vm_id, |s2| s2.lookup(base))
+            .flatten()
+            .unwrap();
+        assert_eq!(pa, zero_pa());
+        assert_eq!(footprint(vm_id), (4 * PAGE_SIZE, PAGE_SIZE));
+        assert_eq!(
+            populate(vm_id, base + 8 * PAGE_SIZE),
+            Err(LazyError::NotLazy)
+        );
+
+        release_vm(vm_id);
+        assert_eq!(footprint(vm_id), (0, 0));
+        stage2::remove(vm_id);
+    }
+
+    #[test]
+    fn test_lazy_read_only_write() {
+        let vm_id = test_vm_id();
+        let base = 0x4000_0000;
+        let mut s2 = Stage2::new();
+        map_zero(&mut s2, base, 2 * PAGE_SIZE, S2Perms::RX).unwrap();
+        stage2::install(vm_id, s2);
+        register(vm_id, base, 2 * PAGE_SIZE, S2Perms::RX);
+
+        // Neither populated nor retried: the guest gets its abort.
+        assert_eq!(on_abort(vm_id, base, true), None);
+        populate(vm_id, base).unwrap();
+        assert_eq!(on_abort(vm_id, base, true), None);
+        assert_eq!(on_abort(vm_id, base, false), Some(ExitAction::Resume));
+
+        release_vm(vm_id);
+        stage2::remove(vm_id);
+    }
+}
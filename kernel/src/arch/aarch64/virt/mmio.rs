@@ -0,0 +1,334 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated MMIO devices. A region registered here must stay unmapped in
+//! the VM's Stage-2 tables; guest accesses to it fault to EL2 and are
+//! replayed against the device model instead of ending the VM. Accesses the
+//! syndrome doesn't describe are decoded from the instruction, see `insn`.
+//! An access the device's backend fails is reported the way the device was
+//! set up for, see `ErrorReport`.
+
+use super::{
+    exit::{decode_mmio, ExitAction, ExitCode, MmioAccess},
+    fault, insn,
+    vcpu::Vcpu,
+    vlog::{vlog, vlog_limited},
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+pub trait MmioDevice: Send + Sync {
+    /// Read `size` bytes at `offset` into the device's region.
+    fn read(&self, offset: u64, size: u8) -> Result<u64, BackendError>;
+    fn write(&self, offset: u64, size: u8, value: u64) -> Result<(), BackendError>;
+
+    /// Like `write`, but the write may also end the guest's run with an
+    /// exit code, for requests only the vCPU's run loop can carry out.
+    /// Runs at EL2.
+    fn write_exit(
+        &self,
+        offset: u64,
+        size: u8,
+        value: u64,
+    ) -> Result<Option<ExitCode>, BackendError> {
+        self.write(offset, size, value).map(|()| None)
+    }
+}
+
+/// The backend behind a device, e.g. a block device's storage, failed the
+/// access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendError {
+    /// What a failed load reads under `ErrorReport::Status`.
+    pub status: u64,
+}
+
+/// How a backend failure reaches the guest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorReport {
+    /// Complete the access, a load reading the error's status. The device
+    /// flags the failure in its own registers, like real hardware would.
+    #[default]
+    Status,
+    /// Synchronous external abort on the faulting access.
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmioError {
+    /// The range overlaps a region already registered for the VM.
+    Overlap,
+    /// No device is registered at the address.
+    NoDevice,
+}
+
+struct Region {
+    base: u64,
+    size: u64,
+    dev: Arc<dyn MmioDevice>,
+    report: ErrorReport,
+}
+
+impl Region {
+    fn contains(&self, ipa: u64) -> bool {
+        (self.base..self.base + self.size).contains(&ipa)
+    }
+
+    // Whether an access of `size` bytes at `ipa` stays within the region.
+    fn fits(&self, ipa: u64, size: u8) -> bool {
+        ipa + size as u64 <= self.base + self.size
+    }
+}
+
+static REGIONS: SpinLock<BTreeMap<usize, Vec<Region>>> = SpinLock::new(BTreeMap::new());
+
+/// Emulate `dev` at IPA range [`base`, `base + size`) of VM `vm_id`.
+pub fn register(
+    vm_id: usize,
+    base: u64,
+    size: u64,
+    dev: Arc<dyn MmioDevice>,
+) -> Result<(), MmioError> {
+    let mut regions = REGIONS.irqsave_lock();
+    let regions = regions.entry(vm_id).or_default();
+    if regions
+        .iter()
+        .any(|r| base < r.base + r.size && r.base < base + size)
+    {
+        return Err(MmioError::Overlap);
+    }
+    regions.push(Region {
+        base,
+        size,
+        dev,
+        report: ErrorReport::default(),
+    });
+    Ok(())
+}
+
+/// Choose how backend failures of the device at `base` of VM `vm_id` are
+/// reported.
+pub fn set_error_report(vm_id: usize, base: u64, report: ErrorReport) -> Result<(), MmioError> {
+    let mut regions = REGIONS.irqsave_lock();
+    let region = regions
+        .get_mut(&vm_id)
+        .and_then(|regions| regions.iter_mut().find(|r| r.base == base))
+        .ok_or(MmioError::NoDevice)?;
+    region.report = report;
+    Ok(())
+}
+
+/// Whether any device of VM `vm_id` sits in [`base`, `base + size`).
+pub fn overlaps(vm_id: usize, base: u64, size: u64) -> bool {
+    REGIONS.irqsave_lock().get(&vm_id).is_some_and(|regions| {
+        regions
+            .iter()
+            .any(|r| base < r.base + r.size && r.base < base + size)
+    })
+}
+
+/// Drop the device registered at `base` of VM `vm_id`.
+pub fn unregister(vm_id: usize, base: u64) -> Option<Arc<dyn MmioDevice>> {
+    let mut regions = REGIONS.irqsave_lock();
+    let regions = regions.get_mut(&vm_id)?;
+    let n = regions.iter().position(|r| r.base == base)?;
+    Some(regions.swap_remove(n).dev)
+}
+
+/// Value a store of register value `reg` puts on the bus: only the
+/// access's bytes, like the hardware drives.
+pub fn store_value(access: MmioAccess, reg: u64) -> u64 {
+    match access.size {
+        8 => reg,
+        size => reg & ((1 << (size as u32 * 8)) - 1),
+    }
+}
+
+/// Register value of a load that read `raw` from a device.
+pub fn load_value(access: MmioAccess, raw: u64) -> u64 {
+    let bits = access.size as u32 * 8;
+    let mut val = if bits == 64 {
+        raw
+    } else {
+        raw & ((1 << bits) - 1)
+    };
+    if access.sign_extend && bits < 64 {
+        let shift = 64 - bits;
+        val = (((val << shift) as i64) >> shift) as u64;
+    }
+    if !access.sf {
+        val &= u32::MAX as u64;
+    }
+    val
+}
+
+/// Emulate the data abort at guest address `far` being handled if its
+/// `ipa` belongs to a device. `None` leaves the abort to the caller.
+pub fn handle(vcpu: &mut Vcpu, far: u64, ipa: u64) -> Option<ExitAction> {
+    // Held across the access, so a concurrent `unregister` can't leave EL2
+    // dropping, and freeing, the last reference to the device.
+    let regions = REGIONS.irqsave_lock();
+    let region = regions.get(&vcpu.vm_id)?.iter().find(|r| r.contains(ipa))?;
+    let offset = ipa - region.base;
+    let (access, writeback, pair) = match decode_mmio(vcpu.exit_esr) {
+        Some(access) => (access, None, None),
+        None => match insn::fetch_ldst(vcpu) {
+            Some(ls) => (ls.access, ls.writeback, ls.pair),
+            None => {
+                vlog_limited!(
+                    Mmio,
+                    Warn,
+                    "[EL2] vcpu {} undecodable device access at {:#x}, pc {:#x}",
+                    vcpu.id,
+                    ipa,
+                    vcpu.regs.elr
+                );
+                return Some(ExitAction::Exit(ExitCode::Fault));
+            }
+        },
+    };
+    // A pair accesses its second register right after the first.
+    let regs = [Some(access.reg as usize), pair.map(usize::from)];
+    let parts = 1 + pair.is_some() as u8;
+    // A store sends the registers as they were before any base update.
+    let store_vals = regs.map(|reg| match reg {
+        None | Some(31) => 0,
+        Some(reg) => store_value(access, vcpu.regs.x[reg]),
+    });
+    // The bus has nothing behind the region's end, so an access running
+    // past it aborts like it would on hardware.
+    if !region.fits(ipa, access.size * parts) {
+        vlog_limited!(
+            Mmio,
+            Warn,
+            "[EL2] vcpu {} device access at {:#x} size {} crosses its end, pc {:#x}",
+            vcpu.id,
+            ipa,
+            access.size * parts,
+            vcpu.regs.elr
+        );
+        fault::inject_data_abort(vcpu, far, access.write);
+        return Some(ExitAction::Resume);
+    }
+    // An aborted access leaves the registers as they were, base included.
+    let saved = vcpu.regs;
+    if let Some(wb) = writeback {
+        if insn::apply_writeback(vcpu, wb).is_err() {
+            vlog_limited!(
+                Mmio,
+                Warn,
+                "[EL2] vcpu {} device access with unsupported base, pc {:#x}",
+                vcpu.id,
+                vcpu.regs.elr
+            );
+            return Some(ExitAction::Exit(ExitCode::Fault));
+        }
+    }
+    vlog!(
+        Mmio,
+        Trace,
+        "[EL2] vcpu {} {} {:#x} size {}",
+        vcpu.id,
+        if access.write { "write" } else { "read" },
+        ipa,
+        access.size * parts
+    );
+    let mut exit = None;
+    let mut loaded = [0; 2];
+    let mut result = Ok(());
+    for n in 0..parts as usize {
+        let at = offset + (n as u64) * access.size as u64;
+        result = if access.write {
+            region
+                .dev
+                .write_exit(at, access.size, store_vals[n])
+                .map(|code| exit = exit.or(code))
+        } else {
+            region
+                .dev
+                .read(at, access.size)
+                .map(|raw| loaded[n] = load_value(access, raw))
+        };
+        if let Err(e) = result {
+            // Under `ErrorReport::Status` the rest of a load reads the status.
+            loaded[n..].fill(load_value(access, e.status));
+            break;
+        }
+    }
+    if result.is_err() {
+        vlog_limited!(
+            Mmio,
+            Debug,
+            "[EL2] vcpu {} backend failed access at {:#x}, pc {:#x}",
+            vcpu.id,
+            ipa,
+            vcpu.regs.elr
+        );
+        if region.report == ErrorReport::Abort {
+            vcpu.regs = saved;
+            fault::inject_data_abort(vcpu, far, access.write);
+            return Some(ExitAction::Resume);
+        }
+    }
+    if !access.write {
+        for (reg, val) in regs.into_iter().zip(loaded) {
+            match reg {
+                None | Some(31) => {}
+                Some(reg) => vcpu.regs.x[reg] = val,
+            }
+        }
+    }
+    vcpu.advance_pc();
+    Some(exit.map_or(ExitAction::Resume, ExitAction::Exit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_load_value() {
+        let mut access = MmioAccess {
+            write: false,
+            size: 1,
+            reg: 0,
+            sign_extend: true,
+            sf: false,
+        };
+        assert_eq!(load_value(access, 0x1ff), 0xffff_ffff);
+        access.sf = true;
+        assert_eq!(load_value(access, 0x80), u64::MAX - 0x7f);
+        access.sign_extend = false;
+        assert_eq!(load_value(access, 0x1ff), 0xff);
+        access.size = 8;
+        assert_eq!(load_value(access, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_store_value() {
+        let mut access = MmioAccess {
+            write: true,
+            size: 2,
+            reg: 0,
+            sign_extend: false,
+            sf: true,
+        };
+        assert_eq!(store_value(access, 0x1234_5678), 0x5678);
+        access.size = 4;
+        assert_eq!(store_value(access, u64::MAX), 0xffff_ffff);
+        access.size = 8;
+        assert_eq!(store_value(access, u64::MAX), u64::MAX);
+    }
+}
@@ -12,21 +12,193 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(virtualization)]
+pub mod abi;
+#[cfg(virtualization)]
+pub mod adaptive;
+pub mod alternative;
+#[cfg(virtualization)]
+pub mod audit;
+#[cfg(virtualization)]
+pub mod autostart;
+#[cfg(virtualization)]
+pub mod boost;
+#[cfg(virt_exit_budget)]
+pub mod budget;
+#[cfg(virtualization)]
+pub mod cacheid;
+#[cfg(virtualization)]
+pub mod clock_probe;
+#[cfg(virtualization)]
+pub mod console_log;
+#[cfg(virtualization)]
+pub mod copy;
+#[cfg(virtualization)]
+pub mod doorbell;
+#[cfg(virtualization)]
+pub mod el2_stack;
+#[cfg(all(test, virtualization))]
+mod esr_corpus;
+#[cfg(virtualization)]
+pub mod exit;
+#[cfg(virtualization)]
+pub mod fault;
+#[cfg(virtualization)]
+pub mod gpio;
+#[cfg(virtualization)]
+pub mod grant;
+#[cfg(virtualization)]
+pub mod guest_mem;
+pub mod hal;
+#[cfg(virtualization)]
+pub mod heartbeat;
+#[cfg(virtualization)]
+pub mod host_pm;
+#[cfg(virtualization)]
+pub mod hotplug;
 pub mod hyper;
+#[cfg(virtualization)]
+pub mod identity;
+#[cfg(virtualization)]
+pub mod initcall;
+#[cfg(virtualization)]
+pub mod insn;
+#[cfg(virtualization)]
+pub mod irq_line;
+#[cfg(virtualization)]
+pub mod isolation;
+#[cfg(virtualization)]
+pub mod kick;
+#[cfg(virt_switch_latency)]
+pub mod latency;
+#[cfg(virtualization)]
+pub mod lazy_ram;
+#[cfg(virtualization)]
+pub mod mmio;
+#[cfg(virtualization)]
+pub mod panic;
+#[cfg(virtualization)]
+pub mod pl011;
+#[cfg(virtualization)]
+pub mod placement;
+#[cfg(virtualization)]
+pub mod policy;
+#[cfg(virtualization)]
+pub mod profile;
+#[cfg(virtualization)]
+pub mod ptimer;
+#[cfg(virtualization)]
+pub mod qemu;
+#[cfg(virtualization)]
+pub mod recovery;
+#[cfg(virtualization)]
+pub mod ring;
+#[cfg(all(virtualization, debug))]
+pub mod sanity;
+#[cfg(virtualization)]
+pub mod sections;
+#[cfg(virtualization)]
+pub mod services;
+#[cfg(virtualization)]
+pub mod shadow;
+#[cfg(virtualization)]
+pub mod shim;
+#[cfg(virtualization)]
+pub mod shmem;
+#[cfg(virtualization)]
+pub mod spi;
+#[cfg(virtualization)]
+pub mod stage2;
+#[cfg(virtualization)]
+pub mod status;
+#[cfg(virtualization)]
+pub mod strict;
+#[cfg(virtualization)]
+pub mod syscon;
+#[cfg(virtualization)]
+pub mod timer_cal;
+#[cfg(virtualization)]
+pub mod trace;
+#[cfg(virtualization)]
+pub mod tvm;
+#[cfg(virtualization)]
+pub mod vconsole;
+#[cfg(virtualization)]
+pub mod vcpu;
 pub mod vector;
-pub use hyper::{get_current_el, hyp_init};
+#[cfg(virtualization)]
+pub mod verify;
+#[cfg(virtualization)]
+pub mod vgic;
+#[cfg(virtualization)]
+pub mod vgicr;
+#[cfg(virtualization)]
+pub mod virtio;
+#[cfg(virtualization)]
+pub mod vlog;
+#[cfg(virtualization)]
+pub mod vm;
+#[cfg(virtualization)]
+pub mod vmid;
+#[cfg(virtualization)]
+pub mod vpsci;
+#[cfg(virtualization)]
+pub mod vsgi;
+#[cfg(virtualization)]
+pub mod vtimer;
+#[cfg(virtualization)]
+pub mod workers;
+pub use hyper::get_current_el;
 
-// Temporary placeholder
+/// A VM id no other test uses, for tests that put a VM in the global
+/// tables. Counts down from below `usize::MAX`, which some tables take for
+/// no VM.
+#[cfg(all(test, virtualization))]
+pub(crate) fn test_vm_id() -> usize {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(usize::MAX - 1);
+    NEXT.fetch_sub(1, Ordering::Relaxed)
+}
+
+/// EL2 is only entered through exceptions, possibly while the interrupted
+/// host holds the heap lock, so nothing reachable from it may allocate or
+/// free. Checked by the global allocator in debug builds.
+#[inline]
+pub fn assert_heap_allowed() {
+    debug_assert!(get_current_el() != 2, "heap allocation at EL2");
+}
+
+/// IRQ taken at EL2 from a lower EL. With IMO set only while a guest runs,
+/// this is always a guest exit.
 #[no_mangle]
-pub extern "C" fn hyper_trap_irq(_context: &mut crate::arch::aarch64::Context) -> usize {
+#[cfg_attr(not(virtualization), allow(unused_variables))]
+#[link_section = ".hyp.text"]
+pub unsafe extern "C" fn hyper_trap_irq(frame: *mut vector::TrapFrame) -> usize {
+    #[cfg(virt_switch_latency)]
+    latency::trap_entered();
+    #[cfg(virtualization)]
+    vcpu::vcpu_manager().with_current_vcpu(|vcpu| {
+        #[cfg(virt_switch_latency)]
+        latency::exit_begin();
+        kick::handle_el2_irq(frame, vcpu);
+        #[cfg(virt_switch_latency)]
+        latency::exit_end();
+    });
     0
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub extern "C" fn hyper_trap_fiq(_context: &mut crate::arch::aarch64::Context) -> usize {
     0
 }
 
 pub fn virt_init() {
-    hyp_init();
+    #[cfg(virtualization)]
+    {
+        initcall::run_level(initcall::InitLevel::Hyp);
+        initcall::run_level(initcall::InitLevel::Vgic);
+    }
+    #[cfg(not(virtualization))]
+    hyper::hyp_init();
 }
@@ -0,0 +1,89 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host panic support. Before the panic dump, every core running a vCPU is
+//! kicked; instead of returning to the host, EL2 parks that core so neither
+//! the guest nor the host thread behind it touch the console or memory again.
+
+use super::{
+    kick::{KickReason, KICK_SGI},
+    vcpu::{self, vcpu_manager, Vcpu},
+};
+use crate::arch::aarch64::{
+    current_cpu_id,
+    irq::{self, IrqNumber},
+};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// Spins to wait for kicked cores to park before dumping anyway.
+const PARK_TIMEOUT_SPINS: usize = 10_000_000;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub fn is_panicking() -> bool {
+    PANICKING.load(Ordering::Acquire)
+}
+
+/// Park the calling core at EL2 for good. Called from the EL2 IRQ path once
+/// a panic is in progress.
+pub(crate) fn park(vcpu: &mut Vcpu) -> ! {
+    vcpu.detach();
+    loop {
+        unsafe { asm!("wfi", options(nostack)) };
+    }
+}
+
+/// Stop every running vCPU and print the state of all of them.
+pub fn stop_all_guests() {
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        // Nested panic, the first one already did this.
+        return;
+    }
+    let me = current_cpu_id();
+    // A vCPU whose holder panicked can't be kicked, but its core is parked
+    // on the SGI all the same.
+    vcpu_manager().for_each_on_panic(|_, vcpu| {
+        if let Some(vcpu) = vcpu.filter(|vcpu| vcpu.running_on().is_some()) {
+            vcpu.raise_kick(KickReason::StopRequest);
+        }
+    });
+    let others = || vcpu::guest_cores() & !(1 << me);
+    let mask = others();
+    if mask != 0 {
+        irq::send_sgi(IrqNumber::new(KICK_SGI), mask as u16);
+        let mut spins = 0;
+        while spins < PARK_TIMEOUT_SPINS && others() != 0 {
+            core::hint::spin_loop();
+            spins += 1;
+        }
+    }
+
+    crate::kearly_println!("---- VM state at panic ----");
+    vcpu_manager().for_each_on_panic(|id, vcpu| match vcpu {
+        Some(vcpu) => crate::kearly_println!(
+            "vm {} vcpu {}: {:?} on {:?} pc {:#x} exits {}",
+            vcpu.vm_id,
+            vcpu.id,
+            vcpu.state,
+            vcpu.running_on(),
+            vcpu.regs.elr,
+            vcpu.stats.exits,
+        ),
+        None => crate::kearly_println!("vcpu {}: held", id),
+    });
+}
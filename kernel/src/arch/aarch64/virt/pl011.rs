@@ -0,0 +1,323 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated PL011 UART, the guest's console. Bytes the guest writes to
+//! UARTDR go where `GUEST_HVC_PUTC` output goes, see `vconsole::putc`;
+//! input from the host console is queued in the receive FIFO and raises
+//! the UART's interrupt through the VGIC. Transmission is instantaneous,
+//! so the transmit FIFO always reads as empty.
+
+use super::{
+    irq_line::{IrqLine, Trigger},
+    mmio::{self, BackendError, MmioDevice, MmioError},
+    ring::Ring,
+    vconsole,
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc};
+
+pub const REGION_SIZE: u64 = 0x1000;
+// Deeper than the PL011's 16 entries so that pasted input isn't overrun.
+const RX_SIZE: usize = 256;
+
+const UARTDR: u64 = 0x000;
+const UARTRSR: u64 = 0x004;
+const UARTFR: u64 = 0x018;
+const UARTIBRD: u64 = 0x024;
+const UARTFBRD: u64 = 0x028;
+const UARTLCR_H: u64 = 0x02c;
+const UARTCR: u64 = 0x030;
+const UARTIFLS: u64 = 0x034;
+const UARTIMSC: u64 = 0x038;
+const UARTRIS: u64 = 0x03c;
+const UARTMIS: u64 = 0x040;
+const UARTICR: u64 = 0x044;
+const UARTDMACR: u64 = 0x048;
+const ID_START: u64 = 0xfe0;
+// UARTPeriphID0-3 then UARTPCellID0-3.
+const ID: [u8; 8] = [0x11, 0x10, 0x14, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
+
+const FR_RXFE: u32 = 1 << 4;
+const FR_RXFF: u32 = 1 << 6;
+const FR_TXFE: u32 = 1 << 7;
+const RSR_OE: u32 = 1 << 3;
+const INT_RX: u32 = 1 << 4;
+const INT_TX: u32 = 1 << 5;
+const INT_RT: u32 = 1 << 6;
+const INT_OE: u32 = 1 << 10;
+const INT_ALL: u32 = 0x7ff;
+// UARTEN, TXE and RXE.
+const CR_RESET: u32 = 0x301;
+const IFLS_RESET: u32 = 0x12;
+
+struct Regs {
+    rx: Ring<u8, RX_SIZE>,
+    rsr: u32,
+    ibrd: u32,
+    fbrd: u32,
+    lcr_h: u32,
+    cr: u32,
+    ifls: u32,
+    imsc: u32,
+    ris: u32,
+    dmacr: u32,
+    overruns: u64,
+}
+
+impl Regs {
+    const fn new() -> Self {
+        Self {
+            rx: Ring::new(),
+            rsr: 0,
+            ibrd: 0,
+            fbrd: 0,
+            lcr_h: 0,
+            cr: CR_RESET,
+            ifls: IFLS_RESET,
+            imsc: 0,
+            ris: 0,
+            dmacr: 0,
+            overruns: 0,
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        let rx = if self.rx.is_empty() {
+            FR_RXFE
+        } else if self.rx.is_full() {
+            FR_RXFF
+        } else {
+            0
+        };
+        rx | FR_TXFE
+    }
+
+    // Receive and receive timeout status follow the FIFO: set while there
+    // is input, whatever UARTICR cleared.
+    fn update_rx_status(&mut self) {
+        if self.rx.is_empty() {
+            self.ris &= !(INT_RX | INT_RT);
+        } else {
+            self.ris |= INT_RX | INT_RT;
+        }
+    }
+
+    fn mis(&self) -> u32 {
+        self.ris & self.imsc
+    }
+}
+
+pub struct Pl011 {
+    line: IrqLine,
+    regs: SpinLock<Regs>,
+}
+
+impl Pl011 {
+    pub fn new(vm_id: usize, intid: u32) -> Self {
+        Self {
+            line: IrqLine::new(vm_id, intid, Trigger::Level),
+            regs: SpinLock::new(Regs::new()),
+        }
+    }
+
+    pub fn vm_id(&self) -> usize {
+        self.line.vm_id()
+    }
+
+    pub fn intid(&self) -> u32 {
+        self.line.intid()
+    }
+
+    /// Input the guest hasn't read yet.
+    pub fn queued(&self) -> usize {
+        self.regs.irqsave_read().rx.len()
+    }
+
+    /// Input dropped because the receive FIFO was full.
+    pub fn overruns(&self) -> u64 {
+        self.regs.irqsave_read().overruns
+    }
+
+    /// Queue `bytes` for the guest to read. Returns how many fit in the
+    /// receive FIFO; the rest is dropped and sets the overrun error.
+    pub fn receive(&self, bytes: &[u8]) -> usize {
+        let taken = {
+            let mut regs = self.regs.irqsave_lock();
+            let mut taken = 0;
+            for &b in bytes {
+                if regs.rx.push_back(b).is_ok() {
+                    taken += 1;
+                } else {
+                    regs.rsr |= RSR_OE;
+                    regs.ris |= INT_OE;
+                    regs.overruns += 1;
+                }
+            }
+            regs.update_rx_status();
+            taken
+        };
+        self.follow_line();
+        taken
+    }
+
+    // Drive the line from the host side. It is set outside the lock: the
+    // guest's accesses take the lock at EL2 while holding the vCPU the line
+    // goes to. Status the guest changed meanwhile is caught by reading it
+    // again once the line moved.
+    fn follow_line(&self) {
+        let mut pending = self.regs.irqsave_read().mis() != 0;
+        loop {
+            if self.line.set(pending).is_err() {
+                // The VM is gone.
+                return;
+            }
+            let now = self.regs.irqsave_read().mis() != 0;
+            if now == pending {
+                return;
+            }
+            pending = now;
+        }
+    }
+
+    // Apply a guest access and follow the change on the interrupt line,
+    // high while UARTMIS is non-zero. At EL2 setting the line is deferred
+    // until the exit is over, so it can be done under the lock.
+    fn update<R>(&self, f: impl FnOnce(&mut Regs) -> R) -> R {
+        let mut regs = self.regs.irqsave_lock();
+        let was_pending = regs.mis() != 0;
+        let ret = f(&mut regs);
+        regs.update_rx_status();
+        let pending = regs.mis() != 0;
+        if pending != was_pending {
+            let _ = self.line.set(pending);
+        }
+        ret
+    }
+
+    fn transmit(&self, regs: &mut Regs, b: u8) {
+        vconsole::putc(self.vm_id(), b);
+        regs.ris |= INT_TX;
+    }
+}
+
+impl MmioDevice for Pl011 {
+    fn read(&self, offset: u64, _size: u8) -> Result<u64, BackendError> {
+        if offset == UARTDR {
+            return Ok(self.update(|regs| regs.rx.pop_front().unwrap_or(0)) as u64);
+        }
+        let regs = self.regs.irqsave_read();
+        let val = match offset {
+            UARTRSR => regs.rsr,
+            UARTFR => regs.flags(),
+            UARTIBRD => regs.ibrd,
+            UARTFBRD => regs.fbrd,
+            UARTLCR_H => regs.lcr_h,
+            UARTCR => regs.cr,
+            UARTIFLS => regs.ifls,
+            UARTIMSC => regs.imsc,
+            UARTRIS => regs.ris,
+            UARTMIS => regs.mis(),
+            UARTDMACR => regs.dmacr,
+            ID_START.. if offset < REGION_SIZE && offset % 4 == 0 => {
+                ID[((offset - ID_START) / 4) as usize] as u32
+            }
+            _ => 0,
+        };
+        Ok(val as u64)
+    }
+
+    fn write(&self, offset: u64, _size: u8, value: u64) -> Result<(), BackendError> {
+        let val = value as u32;
+        self.update(|regs| match offset {
+            UARTDR => self.transmit(regs, val as u8),
+            // Any write to UARTECR clears the errors.
+            UARTRSR => regs.rsr = 0,
+            UARTIBRD => regs.ibrd = val & 0xffff,
+            UARTFBRD => regs.fbrd = val & 0x3f,
+            UARTLCR_H => regs.lcr_h = val & 0xff,
+            UARTCR => regs.cr = val & 0xff87,
+            UARTIFLS => regs.ifls = val & 0x3f,
+            UARTIMSC => regs.imsc = val & INT_ALL,
+            UARTICR => regs.ris &= !val,
+            UARTDMACR => regs.dmacr = val & 0x7,
+            _ => {}
+        });
+        Ok(())
+    }
+}
+
+// UART of each VM with the IPA it sits at.
+static UARTS: SpinLock<BTreeMap<usize, (u64, Arc<Pl011>)>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` a UART at IPA `base` raising `intid`.
+pub fn create(vm_id: usize, base: u64, intid: u32) -> Result<Arc<Pl011>, MmioError> {
+    let uart = Arc::new(Pl011::new(vm_id, intid));
+    mmio::register(vm_id, base, REGION_SIZE, uart.clone())?;
+    UARTS.irqsave_lock().insert(vm_id, (base, uart.clone()));
+    Ok(uart)
+}
+
+/// Remove the UART of VM `vm_id` from the guest's address space.
+pub fn destroy(vm_id: usize) {
+    if let Some((base, _)) = UARTS.irqsave_lock().remove(&vm_id) {
+        mmio::unregister(vm_id, base);
+    }
+}
+
+pub fn get(vm_id: usize) -> Option<Arc<Pl011>> {
+    UARTS
+        .irqsave_lock()
+        .get(&vm_id)
+        .map(|(_, uart)| uart.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_pl011_rx_tx() {
+        vconsole::capture(usize::MAX, false);
+        let uart = Pl011::new(usize::MAX, 33);
+        assert_eq!(uart.read(UARTFR, 4), Ok((FR_TXFE | FR_RXFE) as u64));
+        uart.write(UARTIMSC, 4, (INT_RX | INT_RT) as u64).unwrap();
+
+        assert_eq!(uart.receive(b"ok"), 2);
+        assert_eq!(uart.read(UARTMIS, 4), Ok((INT_RX | INT_RT) as u64));
+        assert_eq!(uart.read(UARTFR, 4), Ok(FR_TXFE as u64));
+        // Clearing doesn't stick while there is input.
+        uart.write(UARTICR, 4, INT_ALL as u64).unwrap();
+        assert_eq!(uart.read(UARTMIS, 4), Ok((INT_RX | INT_RT) as u64));
+        assert_eq!(uart.read(UARTDR, 4), Ok(b'o' as u64));
+        assert_eq!(uart.read(UARTDR, 4), Ok(b'k' as u64));
+        assert_eq!(uart.read(UARTMIS, 4), Ok(0));
+
+        uart.write(UARTDR, 4, 0x100 | b'h' as u64).unwrap();
+        uart.write(UARTDR, 4, b'i' as u64).unwrap();
+        assert_eq!(
+            vconsole::take_output(usize::MAX).as_deref(),
+            Some(&b"hi"[..])
+        );
+        assert_eq!(uart.read(UARTRIS, 4), Ok(INT_TX as u64));
+
+        let overrun = [0; RX_SIZE + 1];
+        assert_eq!(uart.receive(&overrun), RX_SIZE);
+        assert_eq!(uart.overruns(), 1);
+        assert_eq!(uart.read(UARTRSR, 4), Ok(RSR_OE as u64));
+        assert_eq!(uart.read(UARTFR, 4), Ok((FR_TXFE | FR_RXFF) as u64));
+        assert_eq!(uart.read(0xfe0, 4), Ok(0x11));
+        vconsole::release(usize::MAX);
+    }
+}
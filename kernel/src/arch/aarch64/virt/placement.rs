@@ -0,0 +1,127 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where vCPUs run on asymmetric systems. Cores are grouped into classes
+//! by the implementer and part number in MIDR_EL1, e.g. a big and a LITTLE
+//! cluster. A vCPU moving between classes would see MIDR_EL1 and the cache
+//! geometry change under it, so by default all vCPUs of a VM stay on the
+//! class of the core the first of them started on, through the scheduler's
+//! thread affinity. A VM with `Placement::AnyCore` runs anywhere and reads
+//! the same values on every core instead: MIDR_EL1 of the boot core through
+//! VPIDR_EL2, and the cache ID registers `cacheid` folds. Feature ID
+//! registers aren't normalized; cores that differ in features should keep
+//! the default.
+//!
+//! vCPUs with a dedicated core run there whatever their placement.
+
+use crate::{
+    arch::aarch64::{cpuinfo, registers::midr_el1::MIDR_EL1},
+    sync::SpinLock,
+};
+use alloc::collections::BTreeMap;
+use tock_registers::interfaces::Readable;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+/// Every core, one bit each.
+pub const ALL_CORES: usize = usize::MAX >> (usize::BITS as usize - NUM_CORES);
+// MIDR_EL1 implementer, architecture and part number.
+const MIDR_CLASS_MASK: u64 = 0xff0f_fff0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// All vCPUs on cores of one class.
+    #[default]
+    SameClass,
+    /// vCPUs on any core, with ID registers common to all of them.
+    AnyCore,
+}
+
+/// Class of core `cpu`, once it has recorded its ID registers.
+pub fn class_of(cpu: usize) -> Option<u64> {
+    cpuinfo::get(cpu).map(|id| id.midr() & MIDR_CLASS_MASK)
+}
+
+/// Cores of class `class`, one bit each.
+pub fn cores_of(class: u64) -> usize {
+    (0..NUM_CORES)
+        .filter(|&cpu| class_of(cpu) == Some(class))
+        .fold(0, |mask, cpu| mask | (1 << cpu))
+}
+
+/// Whether the cores come in more than one class.
+pub fn is_asymmetric() -> bool {
+    let mut classes = (0..NUM_CORES).filter_map(class_of);
+    classes
+        .next()
+        .is_some_and(|first| classes.any(|class| class != first))
+}
+
+// Class each VM's vCPUs run on, fixed by the first vCPU to start.
+static VM_CLASS: SpinLock<BTreeMap<usize, u64>> = SpinLock::new(BTreeMap::new());
+
+/// Cores a vCPU of VM `vm_id` may run on, when its host thread starts on
+/// `cpu`.
+pub fn cores_for(vm_id: usize, placement: Placement, cpu: usize) -> usize {
+    let Some(own) = class_of(cpu).filter(|_| placement == Placement::SameClass) else {
+        return ALL_CORES;
+    };
+    let class = *VM_CLASS.irqsave_lock().entry(vm_id).or_insert(own);
+    match cores_of(class) {
+        0 => ALL_CORES,
+        cores => cores,
+    }
+}
+
+/// Class of the cores VM `vm_id` runs on, if it's held to one.
+pub fn class_of_vm(vm_id: usize) -> Option<u64> {
+    VM_CLASS.irqsave_lock().get(&vm_id).copied()
+}
+
+/// Forget the class of VM `vm_id`, which is going away.
+pub fn release(vm_id: usize) {
+    VM_CLASS.irqsave_lock().remove(&vm_id);
+}
+
+/// MIDR_EL1 a guest with `placement` reads on `cpu`, for VPIDR_EL2. Runs
+/// at EL2.
+#[link_section = ".hyp.text"]
+pub fn guest_midr(placement: Placement, cpu: usize) -> u64 {
+    let cpu = match placement {
+        Placement::SameClass => cpu,
+        Placement::AnyCore => 0,
+    };
+    cpuinfo::get(cpu).map_or_else(|| MIDR_EL1.get(), |id| id.midr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_placement_cores() {
+        let vm_id = test_vm_id();
+        assert_eq!(cores_for(vm_id, Placement::AnyCore, 0), ALL_CORES);
+        assert_eq!(class_of_vm(vm_id), None);
+        let cores = cores_for(vm_id, Placement::SameClass, 0);
+        assert_ne!(cores & 1, 0);
+        assert_eq!(class_of_vm(vm_id), class_of(0));
+        if !is_asymmetric() {
+            assert_eq!(cores, cores_of(class_of(0).unwrap()));
+        }
+        release(vm_id);
+        assert_eq!(class_of_vm(vm_id), None);
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-VM exit policies. Before an exit is handled its class is looked up in
+//! the VM's policy table, which may let it through, log it, terminate the VM
+//! or pause the vCPU for a debugger.
+
+use super::{exit::ExitReason, vcpu::vcpu_manager};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitClass {
+    Wfx,
+    Hvc,
+    Smc,
+    DataAbort,
+    /// Exception classes without a handler, e.g. system register traps.
+    Unknown,
+}
+
+impl ExitClass {
+    pub const COUNT: usize = 5;
+    pub const ALL: [ExitClass; Self::COUNT] = [
+        ExitClass::Wfx,
+        ExitClass::Hvc,
+        ExitClass::Smc,
+        ExitClass::DataAbort,
+        ExitClass::Unknown,
+    ];
+
+    pub fn of(reason: ExitReason) -> Self {
+        match reason {
+            ExitReason::Wfx => Self::Wfx,
+            ExitReason::Hvc(_) | ExitReason::Hvc32(_) => Self::Hvc,
+            ExitReason::Smc { .. } => Self::Smc,
+            ExitReason::DataAbort { .. } => Self::DataAbort,
+            ExitReason::Unknown(_) => Self::Unknown,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Wfx => "wfx",
+            Self::Hvc => "hvc",
+            Self::Smc => "smc",
+            Self::DataAbort => "data_abort",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Run the normal handler.
+    Handle,
+    /// Log the exit, then run the normal handler.
+    HandleLog,
+    /// Stop the VM as if it had faulted.
+    Terminate,
+    /// Hand the vCPU back to its host thread, which holds it until
+    /// `vcpu::resume_vcpu`.
+    Pause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitPolicy {
+    actions: [PolicyAction; ExitClass::COUNT],
+}
+
+impl ExitPolicy {
+    /// Anything the hypervisor can't make sense of ends the VM. Accesses
+    /// outside the guest's memory and devices abort in the guest, as they
+    /// would on hardware.
+    pub const PRODUCTION: Self = Self {
+        actions: [
+            PolicyAction::Handle,
+            PolicyAction::Handle,
+            PolicyAction::Handle,
+            PolicyAction::Handle,
+            PolicyAction::Terminate,
+        ],
+    };
+
+    /// Surprising exits stop the vCPU where it is, for inspection.
+    pub const DEVELOPMENT: Self = Self {
+        actions: [
+            PolicyAction::Handle,
+            PolicyAction::Handle,
+            PolicyAction::HandleLog,
+            PolicyAction::Pause,
+            PolicyAction::Pause,
+        ],
+    };
+
+    #[cfg(debug)]
+    pub const DEFAULT: Self = Self::DEVELOPMENT;
+    #[cfg(not(debug))]
+    pub const DEFAULT: Self = Self::PRODUCTION;
+
+    #[inline]
+    pub fn action(&self, class: ExitClass) -> PolicyAction {
+        self.actions[class as usize]
+    }
+
+    pub fn set_action(&mut self, class: ExitClass, action: PolicyAction) {
+        self.actions[class as usize] = action;
+    }
+}
+
+impl Default for ExitPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Change how VM `vm_id` treats `class` from its next exit on.
+pub fn set_vm_action(vm_id: usize, class: ExitClass, action: PolicyAction) {
+    vcpu_manager().for_each_of(vm_id, |vcpu| vcpu.config.policy.set_action(class, action));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_policy_lookup() {
+        let mut policy = ExitPolicy::PRODUCTION;
+        let sysreg = ExitClass::of(ExitReason::Unknown(0x18));
+        assert_eq!(sysreg, ExitClass::Unknown);
+        assert_eq!(policy.action(sysreg), PolicyAction::Terminate);
+        assert_eq!(
+            policy.action(ExitClass::of(ExitReason::Hvc32(0))),
+            PolicyAction::Handle
+        );
+        policy.set_action(ExitClass::Unknown, PolicyAction::Pause);
+        assert_eq!(policy.action(sysreg), PolicyAction::Pause);
+        assert_eq!(
+            ExitPolicy::DEVELOPMENT.action(ExitClass::DataAbort),
+            PolicyAction::Pause
+        );
+        assert_eq!(
+            ExitPolicy::PRODUCTION.action(ExitClass::DataAbort),
+            PolicyAction::Handle
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest OS profiles. A profile picks the per-VM settings that usually go
+//! together for one kind of guest, so creating a common VM takes a profile
+//! instead of a hand-filled `VmConfig`.
+
+use super::{
+    audit::SecurityLabel,
+    placement::Placement,
+    policy::ExitPolicy,
+    services::Services,
+    stage2::IPA_BITS,
+    strict,
+    vcpu::{VcpuStateStruct, VirtualCounter, MAX_VCPUS_PER_VM},
+};
+
+// EL1h with DAIF masked.
+const GUEST_INITIAL_SPSR: u64 = 0x3c5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GuestProfile {
+    /// Freestanding test images. Every exit takes the full path and the
+    /// counter only runs in guest mode, so runs are reproducible; surprising
+    /// exits end the run rather than pause it.
+    BareMetalTest,
+    /// Small RTOS images that sit in WFI between ticks.
+    #[default]
+    Rtos,
+    /// Linux booted through the arm64 image protocol.
+    Linux,
+}
+
+/// Guest instructions trapped to EL2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Traps {
+    pub wfi: bool,
+    /// Linux spins on WFE in its locks; trapping those costs far more than
+    /// it saves.
+    pub wfe: bool,
+    /// DC ZVA and the cache ID registers, to give the guest the same cache
+    /// geometry on every core. Always trapped once the cores disagree, see
+    /// `cacheid`.
+    pub cache_id: bool,
+    /// Writes to the EL1 MMU controls, emulated by `tvm`. Only worth it
+    /// to measure what trapping them costs.
+    pub tvm: bool,
+    /// The EL1 physical counter and timer, emulated by `ptimer`, for
+    /// guests that keep time with CNTP.
+    pub ptimer: bool,
+}
+
+impl Traps {
+    /// Each trap by the name of its HCR_EL2 bit, for switching it on a
+    /// live VM; `cache_id` goes by TID2 and `ptimer` by CNTHCTL_EL2.EL1PCEN.
+    pub fn bits(&self) -> [(&'static str, bool); 5] {
+        [
+            ("twi", self.wfi),
+            ("twe", self.wfe),
+            ("tvm", self.tvm),
+            ("tid2", self.cache_id),
+            ("el1pcen", self.ptimer),
+        ]
+    }
+
+    /// The trap named `bit` in `bits`.
+    pub fn bit_mut(&mut self, bit: &str) -> Option<&mut bool> {
+        match bit {
+            "twi" => Some(&mut self.wfi),
+            "twe" => Some(&mut self.wfe),
+            "tvm" => Some(&mut self.tvm),
+            "tid2" => Some(&mut self.cache_id),
+            "el1pcen" => Some(&mut self.ptimer),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootProtocol {
+    /// Jump to the entry point with the boot argument as stack pointer.
+    Bare,
+    /// arm64 Linux: x0 holds the DTB address, x1-x3 are zero, MMU off.
+    LinuxArm64,
+}
+
+impl BootProtocol {
+    /// Register file a vCPU starts from.
+    pub fn initial_regs(self, entry: u64, arg: u64) -> VcpuStateStruct {
+        let mut regs = VcpuStateStruct::new();
+        regs.elr = entry;
+        regs.spsr = GUEST_INITIAL_SPSR;
+        match self {
+            BootProtocol::Bare => regs.sp_el1 = arg,
+            BootProtocol::LinuxArm64 => regs.x[0] = arg,
+        }
+        regs
+    }
+}
+
+/// How far the exit path may cut corners based on observed behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastPath {
+    /// Always the full world switch.
+    Off,
+    /// Let the adaptive exit profile skip VGIC work and batch WFI wakeups.
+    Adaptive,
+}
+
+/// Unmapped pages below the initial stack of bare images, which only know
+/// their stack top. Overflowing into them is reported as a stack overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackGuard {
+    pub stack_size: u64,
+    pub pages: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    pub traps: Traps,
+    /// Paravirtual services the guest may call.
+    pub services: Services,
+    pub boot: BootProtocol,
+    pub fast_path: FastPath,
+    pub counter: VirtualCounter,
+    pub policy: ExitPolicy,
+    /// Needs the VM's Stage-2 tables installed before its vCPUs are created.
+    pub stack_guard: Option<StackGuard>,
+    /// Physical cores, one bit each, reserved for this VM's vCPUs. Host
+    /// device interrupts are routed elsewhere while the VM exists.
+    pub isolated_cores: usize,
+    /// Named in audit records about the VM.
+    pub label: SecurityLabel,
+    /// Start vCPUs through `shim`, for images expecting EL1 as boot
+    /// firmware leaves it.
+    pub reset_shim: bool,
+    /// Size of the guest physical address space. Smaller spaces have
+    /// shallower Stage-2 walks, see `stage2::Geometry`.
+    pub ipa_bits: u32,
+    /// Panic the host on anything unexpected the guest does, see `strict`.
+    pub strict: bool,
+    /// Which cores the vCPUs run on, see `placement`.
+    pub placement: Placement,
+    /// Most vCPUs the VM may have, up to `vcpu::MAX_VCPUS_PER_VM`.
+    pub max_vcpus: usize,
+}
+
+impl GuestProfile {
+    pub const fn config(self) -> VmConfig {
+        match self {
+            GuestProfile::BareMetalTest => VmConfig {
+                traps: Traps {
+                    wfi: true,
+                    wfe: true,
+                    cache_id: false,
+                    tvm: false,
+                    ptimer: false,
+                },
+                services: Services::PROVIDED,
+                boot: BootProtocol::Bare,
+                fast_path: FastPath::Off,
+                counter: VirtualCounter::GuestTime,
+                policy: ExitPolicy::PRODUCTION,
+                stack_guard: None,
+                isolated_cores: 0,
+                label: SecurityLabel::UNLABELED,
+                reset_shim: false,
+                ipa_bits: IPA_BITS,
+                strict: strict::DEFAULT,
+                placement: Placement::SameClass,
+                max_vcpus: MAX_VCPUS_PER_VM,
+            },
+            GuestProfile::Rtos => VmConfig {
+                traps: Traps {
+                    wfi: true,
+                    wfe: false,
+                    cache_id: false,
+                    tvm: false,
+                    ptimer: false,
+                },
+                services: Services::PROVIDED,
+                boot: BootProtocol::Bare,
+                fast_path: FastPath::Adaptive,
+                counter: VirtualCounter::HostTime,
+                policy: ExitPolicy::DEFAULT,
+                stack_guard: None,
+                isolated_cores: 0,
+                label: SecurityLabel::UNLABELED,
+                reset_shim: false,
+                ipa_bits: IPA_BITS,
+                strict: strict::DEFAULT,
+                placement: Placement::SameClass,
+                max_vcpus: MAX_VCPUS_PER_VM,
+            },
+            GuestProfile::Linux => VmConfig {
+                traps: Traps {
+                    wfi: true,
+                    wfe: false,
+                    cache_id: false,
+                    tvm: false,
+                    ptimer: false,
+                },
+                // Linux has its own console drivers.
+                services: Services::PROVIDED.without(Services::CONSOLE),
+                boot: BootProtocol::LinuxArm64,
+                fast_path: FastPath::Adaptive,
+                counter: VirtualCounter::HostTime,
+                policy: ExitPolicy::DEFAULT,
+                stack_guard: None,
+                isolated_cores: 0,
+                label: SecurityLabel::UNLABELED,
+                reset_shim: true,
+                ipa_bits: IPA_BITS,
+                strict: strict::DEFAULT,
+                placement: Placement::SameClass,
+                max_vcpus: MAX_VCPUS_PER_VM,
+            },
+        }
+    }
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        GuestProfile::default().config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_boot_protocol_regs() {
+        let bare = BootProtocol::Bare.initial_regs(0x4000_0000, 0x4800_0000);
+        assert_eq!(bare.elr, 0x4000_0000);
+        assert_eq!(bare.sp_el1, 0x4800_0000);
+        assert_eq!(bare.x[0], 0);
+
+        let linux = BootProtocol::LinuxArm64.initial_regs(0x4008_0000, 0x4400_0000);
+        assert_eq!(linux.elr, 0x4008_0000);
+        assert_eq!(linux.x[0], 0x4400_0000);
+        assert_eq!(&linux.x[1..4], &[0, 0, 0]);
+        assert_eq!(linux.spsr, GUEST_INITIAL_SPSR);
+    }
+
+    #[test]
+    fn test_traps_by_bit() {
+        let mut traps = GuestProfile::Rtos.config().traps;
+        *traps.bit_mut("tvm").unwrap() = true;
+        *traps.bit_mut("twi").unwrap() = false;
+        assert_eq!(
+            traps.bits(),
+            [
+                ("twi", false),
+                ("twe", false),
+                ("tvm", true),
+                ("tid2", false),
+                ("el1pcen", false)
+            ]
+        );
+        assert!(traps.bit_mut("tsc").is_none());
+    }
+
+    #[test]
+    fn test_default_profile() {
+        assert_eq!(VmConfig::default(), GuestProfile::Rtos.config());
+        assert_eq!(
+            GuestProfile::BareMetalTest.config().fast_path,
+            FastPath::Off
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated EL1 physical timer, for guests that use CNTP rather than CNTV.
+//! With `Traps::ptimer` the guest runs with CNTHCTL_EL2.EL1PCTEN and
+//! EL1PCEN clear, so its CNTPCT_EL0 reads and CNTP_* accesses trap to EL2
+//! instead of reaching the host's tick timer. They are emulated against
+//! the VM's time base, the physical count when it was built, which all its
+//! vCPUs share; the timer output drives the guest's PPI 30.
+//!
+//! Nothing interrupts the guest when its emulated timer comes due, so it
+//! fires on the next exit, the next host tick at the latest: a vCPU with
+//! this trap never suppresses the tick of its dedicated core.
+
+use super::{
+    exit::{ExitAction, SysRegAccess},
+    hal::{sysregs, SysReg, SysRegBackend},
+    hyper, kick,
+    vcpu::Vcpu,
+    vgic::TIMER_PRIORITY,
+    vtimer::{self, CTL_ENABLE, CTL_IMASK},
+};
+
+/// The EL1 physical timer's PPI.
+pub const PTIMER_INTID: u32 = 30;
+
+// (op0, op1, CRn, CRm, op2) of the registers EL1PCTEN and EL1PCEN trap.
+const CNTPCT_EL0: (u8, u8, u8, u8, u8) = (3, 3, 14, 0, 1);
+const CNTPCTSS_EL0: (u8, u8, u8, u8, u8) = (3, 3, 14, 0, 5);
+const CNTP_TVAL_EL0: (u8, u8, u8, u8, u8) = (3, 3, 14, 2, 0);
+const CNTP_CTL_EL0: (u8, u8, u8, u8, u8) = (3, 3, 14, 2, 1);
+const CNTP_CVAL_EL0: (u8, u8, u8, u8, u8) = (3, 3, 14, 2, 2);
+
+const CTL_ISTATUS: u64 = 1 << 2;
+// CNTHCTL_EL2.EL1PCTEN and EL1PCEN, with HCR_EL2.E2H clear.
+const CNTHCTL_EL1_ACCESS: u64 = 0b11;
+
+/// The emulated timer of one vCPU.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PTimer {
+    // Physical count at which the guest's count was 0.
+    base: u64,
+    ctl: u64,
+    cval: u64,
+}
+
+impl PTimer {
+    pub const fn new(base: u64) -> Self {
+        Self {
+            base,
+            ctl: 0,
+            cval: 0,
+        }
+    }
+
+    /// Guest physical count at physical count `now`.
+    pub fn count(&self, now: u64) -> u64 {
+        now.wrapping_sub(self.base)
+    }
+
+    /// Whether the timer asserts its interrupt at physical count `now`.
+    pub fn asserted(&self, now: u64) -> bool {
+        vtimer::fires(self.ctl, self.cval, self.count(now))
+    }
+
+    /// Physical count at which the timer comes due, if it is armed.
+    pub fn due(&self) -> Option<u64> {
+        (self.ctl & (CTL_ENABLE | CTL_IMASK) == CTL_ENABLE)
+            .then(|| self.cval.wrapping_add(self.base))
+    }
+
+    /// What a read of the register at `encoding` returns at `now`, or
+    /// `None` if it isn't one of the timer's.
+    fn read(&self, encoding: (u8, u8, u8, u8, u8), now: u64) -> Option<u64> {
+        let count = self.count(now);
+        Some(match encoding {
+            CNTPCT_EL0 | CNTPCTSS_EL0 => count,
+            // The low 32 bits of a signed difference.
+            CNTP_TVAL_EL0 => self.cval.wrapping_sub(count) & 0xffff_ffff,
+            CNTP_CTL_EL0 if self.ctl & CTL_ENABLE != 0 && count >= self.cval => {
+                self.ctl | CTL_ISTATUS
+            }
+            CNTP_CTL_EL0 => self.ctl,
+            CNTP_CVAL_EL0 => self.cval,
+            _ => return None,
+        })
+    }
+
+    /// Write `val` to the register at `encoding` at `now`. Returns whether
+    /// it is one the guest may write.
+    fn write(&mut self, encoding: (u8, u8, u8, u8, u8), val: u64, now: u64) -> bool {
+        match encoding {
+            CNTP_TVAL_EL0 => {
+                let tval = val as u32 as i32 as i64 as u64;
+                self.cval = self.count(now).wrapping_add(tval);
+            }
+            CNTP_CTL_EL0 => self.ctl = val & (CTL_ENABLE | CTL_IMASK),
+            CNTP_CVAL_EL0 => self.cval = val,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Forget what the guest programmed, keeping the VM's time base.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.base);
+    }
+
+    /// Move the time base `delta` counts back, see `Vcpu::rebase_counter`.
+    pub(crate) fn rebase(&mut self, delta: u64) {
+        self.base = self.base.wrapping_sub(delta);
+    }
+}
+
+/// Trap the guest's physical counter and timer accesses, or let them
+/// through as the host has them. Runs at EL2; takes effect after an ISB.
+#[link_section = ".hyp.text"]
+pub(crate) fn trap(on: bool) {
+    let cnthctl = sysregs().read(SysReg::CnthctlEl2);
+    let want = if on {
+        cnthctl & !CNTHCTL_EL1_ACCESS
+    } else {
+        cnthctl | CNTHCTL_EL1_ACCESS
+    };
+    if want != cnthctl {
+        sysregs().write(SysReg::CnthctlEl2, want);
+    }
+}
+
+/// Emulate an access to the physical counter or timer, or `None` if
+/// `access` isn't one or the VM doesn't trap them. Runs at EL2.
+#[link_section = ".hyp.text"]
+pub fn emulate(vcpu: &mut Vcpu, access: SysRegAccess) -> Option<ExitAction> {
+    if !vcpu.config.traps.ptimer {
+        return None;
+    }
+    let encoding = (access.op0, access.op1, access.crn, access.crm, access.op2);
+    let rt = access.rt as usize;
+    let now = hyper::read_cntpct();
+    if access.read {
+        let val = vcpu.ptimer.read(encoding, now)?;
+        if rt != 31 {
+            vcpu.regs.x[rt] = val;
+        }
+    } else {
+        let val = match rt {
+            31 => 0,
+            rt => vcpu.regs.x[rt],
+        };
+        if !vcpu.ptimer.write(encoding, val, now) {
+            return None;
+        }
+        let high = vcpu.ptimer.asserted(now);
+        let _ = kick::set_irq_level(vcpu.vm_id, vcpu.id, PTIMER_INTID, high);
+    }
+    vcpu.advance_pc();
+    Some(ExitAction::Resume)
+}
+
+/// Drive `vcpu`'s PPI 30 with its emulated timer. The vCPU must be out of
+/// guest mode.
+pub fn sync(vcpu: &Vcpu) {
+    if vcpu.config.traps.ptimer {
+        let high = vcpu.ptimer.asserted(hyper::read_cntpct());
+        vcpu.vgic
+            .set_level_with_priority(PTIMER_INTID, high, TIMER_PRIORITY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_ptimer_registers() {
+        let mut timer = PTimer::new(1000);
+        assert_eq!(timer.read(CNTPCT_EL0, 1500), Some(500));
+        assert_eq!(timer.read((3, 3, 14, 3, 1), 1500), None);
+        assert!(!timer.write(CNTPCT_EL0, 0, 1500));
+
+        // Due 100 counts from now, then enabled.
+        assert!(timer.write(CNTP_TVAL_EL0, 100, 1500));
+        assert_eq!(timer.read(CNTP_CVAL_EL0, 1500), Some(600));
+        assert!(timer.write(CNTP_CTL_EL0, CTL_ENABLE | CTL_ISTATUS, 1500));
+        assert_eq!(timer.read(CNTP_CTL_EL0, 1500), Some(CTL_ENABLE));
+        assert!(!timer.asserted(1599));
+        assert!(timer.asserted(1600));
+        assert_eq!(timer.read(CNTP_TVAL_EL0, 1610), Some(0xffff_fff6));
+        assert_eq!(
+            timer.read(CNTP_CTL_EL0, 1610),
+            Some(CTL_ENABLE | CTL_ISTATUS)
+        );
+
+        assert_eq!(timer.due(), Some(1600));
+
+        // Masked, it still says it's due, but asserts nothing.
+        assert!(timer.write(CNTP_CTL_EL0, CTL_ENABLE | CTL_IMASK, 1610));
+        assert!(!timer.asserted(1610));
+        assert_eq!(timer.due(), None);
+        assert_eq!(
+            timer.read(CNTP_CTL_EL0, 1610).unwrap() & CTL_ISTATUS,
+            CTL_ISTATUS
+        );
+
+        // A negative TVAL is a compare value in the past.
+        assert!(timer.write(CNTP_TVAL_EL0, (-10i32) as u32 as u64, 1610));
+        assert_eq!(timer.read(CNTP_CVAL_EL0, 1610), Some(600));
+
+        timer.reset();
+        assert_eq!(timer, PTimer::new(1000));
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running under QEMU's `virt` machine. Selftests end the emulator through
+//! semihosting with a pass/fail code instead of leaving the runner to time
+//! out, and `vm_builder` lays a VM out like the `virt` board so images
+//! built for `qemu-system-aarch64 -M virt` run as guests unmodified.
+
+use super::{
+    profile::VmConfig,
+    spi::Spi,
+    stage2::{MemType, S2Perms},
+    vm::VmBuilder,
+};
+use crate::arch::aarch64::sysreg::read_sysreg;
+use spin::Once;
+
+// Guest physical layout of the `virt` board, the `QEMU_*` values of `abi`.
+pub use super::abi::{
+    QEMU_GPIO_BASE as GPIO_BASE, QEMU_GPIO_INTID as GPIO_INTID, QEMU_RAM_BASE as RAM_BASE,
+    QEMU_UART_BASE as UART_BASE, QEMU_UART_INTID as UART_INTID,
+};
+
+// QEMU puts the device tree at the start of RAM for images loaded with
+// -kernel, and names the machine in the root compatible.
+#[cfg(virtio)]
+const DTB_BASE: u64 = crate::boards::DRAM_BASE;
+#[cfg(virtio)]
+const VIRT_COMPATIBLE: &str = "linux,dummy-virt";
+
+// CPUs QEMU runs `virt` with, by MIDR_EL1 implementer and part number.
+// `max` reports implementer 0, reserved for software use, which silicon
+// never does. `cortex-a57` reports Arm's own, so on real Cortex-A57s
+// without a device tree this takes them for QEMU as well.
+const MIDR_IMPLEMENTER_SHIFT: u64 = 24;
+const MIDR_PARTNUM_SHIFT: u64 = 4;
+const IMPLEMENTER_SOFTWARE: u64 = 0x00;
+const IMPLEMENTER_ARM: u64 = 0x41;
+const PARTNUM_CORTEX_A57: u64 = 0xd07;
+
+static DETECTED: Once<bool> = Once::new();
+
+#[cfg(virtio)]
+fn fdt_says_qemu() -> Option<bool> {
+    // SAFETY: DRAM_BASE is mapped; `from_ptr` checks the header before
+    // trusting anything behind it.
+    let fdt = unsafe { flat_device_tree::Fdt::from_ptr(DTB_BASE as *const u8) }.ok()?;
+    let compatible = fdt.find_node("/")?.compatible()?;
+    Some(compatible.all().any(|c| c == VIRT_COMPATIBLE))
+}
+
+#[cfg(not(virtio))]
+fn fdt_says_qemu() -> Option<bool> {
+    None
+}
+
+fn midr_says_qemu() -> bool {
+    let midr = read_sysreg!("midr_el1");
+    let implementer = (midr >> MIDR_IMPLEMENTER_SHIFT) & 0xff;
+    let part = (midr >> MIDR_PARTNUM_SHIFT) & 0xfff;
+    matches!(
+        (implementer, part),
+        (IMPLEMENTER_SOFTWARE, _) | (IMPLEMENTER_ARM, PARTNUM_CORTEX_A57)
+    )
+}
+
+/// Whether the kernel runs on QEMU's `virt` machine: the device tree if
+/// one is found, otherwise the CPU's implementer code. Decided at the
+/// `CpuIrq` init level, before anything reuses the start of RAM.
+pub fn detect() -> bool {
+    *DETECTED.call_once(|| fdt_says_qemu().unwrap_or_else(midr_says_qemu))
+}
+
+/// Stop the emulator with `code` as its exit status through semihosting.
+/// Returns, and the caller carries on, only when not running on QEMU,
+/// where the semihosting call would be undefined. QEMU itself must have
+/// semihosting enabled, as the test runners do, or the call traps there.
+pub fn exit(code: u32) {
+    if detect() {
+        semihosting::process::exit(code as i32);
+    }
+}
+
+/// End a selftest run: status 0 when everything passed, 1 otherwise.
+pub fn test_exit(passed: bool) {
+    exit(if passed { 0 } else { 1 });
+}
+
+/// A VM laid out like the `virt` board: `ram_size` bytes of RAM at
+/// `RAM_BASE` backed by host memory at `ram_pa`, the PL011 UART and the
+/// PL061 GPIO. Their SPIs are handed out as "uart" and "gpio", the
+/// board's numbers while nothing else added to the builder raises them.
+pub fn vm_builder(vm_id: usize, config: VmConfig, ram_pa: u64, ram_size: u64) -> VmBuilder {
+    VmBuilder::new(vm_id, config)
+        .memory(RAM_BASE, ram_pa, ram_size, MemType::Normal, S2Perms::RWX)
+        .pl011(UART_BASE, Spi::Prefer("uart", UART_INTID))
+        .gpio(GPIO_BASE, Spi::Prefer("gpio", GPIO_INTID))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::{alternative::Features, vm::VirtCaps};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_qemu_virt_layout() {
+        let caps = VirtCaps {
+            ipa_bits: 40,
+            max_vcpus: 1,
+            num_cores: 1,
+            max_intid: 1019,
+            features: Features::empty(),
+        };
+        let builder = vm_builder(usize::MAX, VmConfig::default(), RAM_BASE, 0x100_0000)
+            .boot_vcpu(RAM_BASE + 0x8_0000, RAM_BASE);
+        assert!(builder.validate_against(&caps).is_ok());
+    }
+}
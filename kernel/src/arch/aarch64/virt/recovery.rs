@@ -0,0 +1,268 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Traps EL2 has no handler for. Instead of parking the core, EL2 records
+//! why and gives the core back to the host. A guest trapping with no vCPU
+//! to charge it to gets its VM marked failed, and the host sees
+//! `ExitCode::Fault` from its run HVC. With no room left to mark the VM,
+//! the host sees `ExitCode::VmFailed` and stops the VM itself. An unknown host HVC fails like an
+//! unknown SMCCC call. Any other host trap sends the interrupted thread to
+//! `host_trap_failed`, which prints the record and retires the thread so
+//! the core carries on with the rest.
+
+use super::{exit::ExitCode, hyper, vcpu, vector::TrapFrame, vlog::vlog};
+use crate::{arch::aarch64::current_cpu_id, scheduler};
+use core::{
+    fmt,
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+// HCR_EL2.IMO is only set while a guest runs.
+const HCR_EL2_IMO: u64 = 1 << 4;
+// EL1h with DAIF masked, as on exception entry.
+const SPSR_EL1H_MASKED: u64 = 0x3c5;
+// SMCCC NOT_SUPPORTED.
+const NOT_SUPPORTED: u64 = u64::MAX;
+const NO_VM: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapFailure {
+    /// A guest trapped, but the core has no current vCPU.
+    NoVcpu,
+    /// The host made an HVC EL2 doesn't implement; payload is x0.
+    UnknownHostHvc(u64),
+    /// The host took an exception of a class EL2 doesn't handle.
+    UnhandledHostTrap { ec: u64 },
+}
+
+/// What EL2 saw when a trap failed.
+#[derive(Debug, Clone, Copy)]
+pub struct FailureRecord {
+    pub reason: TrapFailure,
+    pub esr: u64,
+    /// VM of the guest that trapped, if it could be told.
+    pub vm_id: Option<usize>,
+    pub frame: TrapFrame,
+}
+
+impl fmt::Display for FailureRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} esr {:#x} vm {:?}",
+            self.reason, self.esr, self.vm_id
+        )?;
+        writeln!(
+            f,
+            "elr {:#x} spsr {:#x} sp_el1 {:#x}",
+            self.frame.elr, self.frame.spsr, self.frame.sp_el1
+        )?;
+        for (n, pair) in self.frame.x.chunks(2).enumerate() {
+            write!(f, "x{:<2} {:#018x}", n * 2, pair[0])?;
+            if let Some(x) = pair.get(1) {
+                write!(f, "  x{:<2} {:#018x}", n * 2 + 1, x)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// Latest failure of each core. Written at EL2 and read back by the host on
+// the same core.
+#[link_section = ".hyp.data"]
+static mut LAST: [Option<FailureRecord>; NUM_CORES] = [None; NUM_CORES];
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+// VMs marked failed. EL2 marks them, so the list can't grow.
+const MAX_FAILED_VMS: usize = 8;
+static FAILED_VMS: [AtomicUsize; MAX_FAILED_VMS] =
+    [const { AtomicUsize::new(NO_VM) }; MAX_FAILED_VMS];
+// VMs that failed while `FAILED_VMS` was full.
+static UNMARKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the trap being handled came from a guest.
+#[link_section = ".hyp.text"]
+pub(crate) fn in_guest() -> bool {
+    hyper::read_hcr_el2() & HCR_EL2_IMO != 0
+}
+
+/// Record a trap EL2 can't handle and hand the core back to the host.
+/// Returns the value `sync_from_lower_el1` expects, always non-zero.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of the exception.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn trap_failed(frame: *mut TrapFrame, esr: u64, reason: TrapFailure) -> u64 {
+    let cpu = current_cpu_id();
+    let vm_id = match reason {
+        TrapFailure::NoVcpu => vcpu::running_vm(cpu),
+        _ => None,
+    };
+    (*addr_of_mut!(LAST))[cpu] = Some(FailureRecord {
+        reason,
+        esr,
+        vm_id,
+        frame: *frame,
+    });
+    FAILURES.fetch_add(1, Ordering::Relaxed);
+    vlog!(
+        Exit,
+        Error,
+        "[EL2] cpu {} trap failed: {:?} esr {:#x} elr {:#x}",
+        cpu,
+        reason,
+        esr,
+        (*frame).elr
+    );
+    match reason {
+        TrapFailure::NoVcpu => {
+            let code = match vm_id {
+                Some(vm_id) if !mark_failed(vm_id) => ExitCode::VmFailed,
+                _ => ExitCode::Fault,
+            };
+            vcpu::abandon_guest(frame, cpu, code);
+        }
+        TrapFailure::UnknownHostHvc(_) => (*frame).x[0] = NOT_SUPPORTED,
+        TrapFailure::UnhandledHostTrap { .. } => {
+            // Retrying the instruction would trap again.
+            (*frame).x[0] = cpu as u64;
+            (*frame).elr = host_trap_failed as usize as u64;
+            (*frame).spsr = SPSR_EL1H_MASKED;
+        }
+    }
+    1
+}
+
+/// Where a host thread whose trap failed resumes, at EL1 on its own stack.
+extern "C" fn host_trap_failed(cpu: usize) -> ! {
+    if let Some(record) = last(cpu) {
+        crate::kearly_println!("[EL2] host trap failed on cpu {}\n{}", cpu, record);
+    }
+    scheduler::retire_me()
+}
+
+/// Latest failure recorded on `cpu`.
+pub fn last(cpu: usize) -> Option<FailureRecord> {
+    unsafe { (*addr_of!(LAST)).get(cpu).copied().flatten() }
+}
+
+/// Traps that failed since boot.
+pub fn failures() -> usize {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+// Two cores failing the same VM at once may both find it unmarked and
+// take a slot each; `clear_failed` clears both. One that found a slot was
+// just taken by the other sees it there and stops.
+fn mark_failed(vm_id: usize) -> bool {
+    if is_failed(vm_id) {
+        return true;
+    }
+    for slot in &FAILED_VMS {
+        match slot.compare_exchange(NO_VM, vm_id, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return true,
+            Err(current) if current == vm_id => return true,
+            Err(_) => {}
+        }
+    }
+    // The run loop of the vCPU that trapped stops the VM instead.
+    UNMARKED.fetch_add(1, Ordering::Relaxed);
+    vlog!(
+        Exit,
+        Error,
+        "[EL2] vm {} failed, but {} failed VMs are already marked",
+        vm_id,
+        MAX_FAILED_VMS
+    );
+    false
+}
+
+/// VMs that failed while no more could be marked, so the host stopped
+/// them instead.
+pub fn unmarked() -> usize {
+    UNMARKED.load(Ordering::Relaxed)
+}
+
+/// Whether a trap of `vm_id` failed. Its vCPUs no longer enter the guest.
+pub fn is_failed(vm_id: usize) -> bool {
+    FAILED_VMS
+        .iter()
+        .any(|slot| slot.load(Ordering::Acquire) == vm_id)
+}
+
+/// Forget that `vm_id` failed, once the last of its vCPUs is gone.
+pub fn clear_failed(vm_id: usize) {
+    for slot in &FAILED_VMS {
+        let _ = slot.compare_exchange(vm_id, NO_VM, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_recovery_failed_vms() {
+        let vm_id = test_vm_id();
+        assert!(!is_failed(vm_id));
+        assert!(mark_failed(vm_id));
+        assert!(mark_failed(vm_id));
+        assert!(is_failed(vm_id));
+        assert_eq!(
+            FAILED_VMS
+                .iter()
+                .filter(|slot| slot.load(Ordering::Relaxed) == vm_id)
+                .count(),
+            1
+        );
+        clear_failed(vm_id);
+        assert!(!is_failed(vm_id));
+    }
+
+    #[test]
+    fn test_recovery_failed_vms_full() {
+        let ids: [usize; MAX_FAILED_VMS + 1] = core::array::from_fn(|_| test_vm_id());
+        let unmarked_before = unmarked();
+        let marked = ids.iter().filter(|&&vm_id| mark_failed(vm_id)).count();
+        // Other tests may hold slots, so only some of these may fit.
+        assert!(marked < ids.len());
+        assert_eq!(unmarked() - unmarked_before, ids.len() - marked);
+        for &vm_id in &ids {
+            clear_failed(vm_id);
+        }
+    }
+
+    #[test]
+    fn test_recovery_record_dump() {
+        let mut frame = TrapFrame::default();
+        frame.x[30] = 0x1234;
+        frame.elr = 0x4008_0000;
+        let record = FailureRecord {
+            reason: TrapFailure::UnhandledHostTrap { ec: 0x3f },
+            esr: 0xfc00_0000,
+            vm_id: None,
+            frame,
+        };
+        let dump = record.to_string();
+        assert!(dump.starts_with("UnhandledHostTrap { ec: 63 }"));
+        assert!(dump.contains("elr 0x40080000"));
+        assert!(dump.contains("x30 0x0000000000001234"));
+        assert_eq!(dump.lines().count(), 2 + 16);
+    }
+}
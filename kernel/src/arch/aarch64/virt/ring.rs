@@ -0,0 +1,152 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixed-capacity FIFO for state EL2 changes. EL2 runs in exception context
+//! and must not touch the heap, see `assert_heap_allowed`.
+
+pub struct Ring<T, const N: usize> {
+    slots: [Option<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> Ring<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { None }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Append `val`, handing it back if the ring is full.
+    pub fn push_back(&mut self, val: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(val);
+        }
+        self.slots[(self.head + self.len) % N] = Some(val);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let val = self.slots[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        val
+    }
+
+    /// Take out the first entry matching `pred`, keeping the order of the
+    /// others.
+    pub fn remove_first(&mut self, mut pred: impl FnMut(&T) -> bool) -> Option<T> {
+        let pos = self.iter().position(|v| pred(v))?;
+        let val = self.slots[(self.head + pos) % N].take();
+        // Close the gap by moving the entries in front of it back by one.
+        for n in (0..pos).rev() {
+            self.slots[(self.head + n + 1) % N] = self.slots[(self.head + n) % N].take();
+        }
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        val
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).filter_map(move |n| self.slots[(self.head + n) % N].as_ref())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        let (head, len) = (self.head, self.len);
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter(move |(n, _)| (n + N - head) % N < len)
+            .filter_map(|(_, slot)| slot.as_mut())
+    }
+
+    pub fn contains(&self, val: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.iter().any(|v| v == val)
+    }
+}
+
+impl<T, const N: usize> Default for Ring<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_ring_wraps() {
+        let mut ring: Ring<u32, 3> = Ring::new();
+        assert_eq!(ring.pop_front(), None);
+        for n in 0..3 {
+            ring.push_back(n).unwrap();
+        }
+        assert_eq!(ring.push_back(3), Err(3));
+        assert_eq!(ring.pop_front(), Some(0));
+        ring.push_back(3).unwrap();
+        assert!(ring.contains(&3));
+        assert!(!ring.contains(&0));
+        assert_eq!(ring.iter().copied().sum::<u32>(), 6);
+        while ring.pop_front().is_some() {}
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_ring_remove_first() {
+        let mut ring: Ring<u32, 4> = Ring::new();
+        // Start off the beginning of the slots so the entries wrap.
+        ring.push_back(9).unwrap();
+        ring.pop_front();
+        for n in [1, 2, 3, 4] {
+            ring.push_back(n).unwrap();
+        }
+        assert_eq!(ring.remove_first(|&v| v % 2 == 0), Some(2));
+        assert_eq!(ring.remove_first(|&v| v > 10), None);
+        for v in ring.iter_mut() {
+            *v *= 10;
+        }
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [10, 30, 40]);
+        ring.push_back(5).unwrap();
+        assert_eq!(ring.remove_first(|&v| v == 5), Some(5));
+        assert_eq!(ring.pop_front(), Some(10));
+        assert_eq!(ring.len(), 2);
+    }
+}
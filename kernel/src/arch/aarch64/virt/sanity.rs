@@ -0,0 +1,175 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Debug-only validation of guest state captured on exit. Catches world
+//! switch bugs (a corrupted frame, a host context leaking into a guest) close
+//! to where they happen. The module only exists in debug builds.
+//!
+//! `VmBuilder::build` declares the memory each VM may execute from: its
+//! executable RAM and the reset shim page.
+
+use super::{
+    exit,
+    hal::{sysregs, SysReg, SysRegBackend},
+    hyper,
+    vcpu::Vcpu,
+};
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanityError {
+    PcNotExecutable(u64),
+    BadMode(u64),
+    MisalignedSp(u64),
+}
+
+impl fmt::Display for SanityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PcNotExecutable(pc) => write!(f, "pc {:#x} outside executable regions", pc),
+            Self::BadMode(spsr) => write!(f, "invalid guest mode in spsr {:#x}", spsr),
+            Self::MisalignedSp(sp) => write!(f, "sp_el1 {:#x} not 16-byte aligned", sp),
+        }
+    }
+}
+
+struct ExecRegion {
+    vm_id: usize,
+    start: u64,
+    end: u64,
+}
+
+static EXEC_REGIONS: SpinLock<Vec<ExecRegion>> = SpinLock::new(Vec::new());
+
+/// Declare the IPAs `[start, start + size)` as guest executable memory of
+/// `vm_id`. VMs without any declared region skip the PC check.
+pub fn register_exec_region(vm_id: usize, start: u64, size: u64) {
+    EXEC_REGIONS.irqsave_lock().push(ExecRegion {
+        vm_id,
+        start,
+        end: start + size,
+    });
+}
+
+pub fn unregister_vm(vm_id: usize) {
+    EXEC_REGIONS.irqsave_lock().retain(|r| r.vm_id != vm_id);
+}
+
+// IPA the guest fetches `pc` from, with its Stage-1 MMU on or off. `None`
+// if its tables don't map `pc`, which is for the guest's own abort handling
+// to deal with.
+fn pc_ipa(pc: u64) -> Option<u64> {
+    if sysregs().read(SysReg::SctlrEl1) & 1 == 0 {
+        Some(pc)
+    } else {
+        hyper::translate_el1_va(pc)
+    }
+}
+
+fn check_pc(vm_id: usize, pc: u64) -> Result<(), SanityError> {
+    let Some(ipa) = pc_ipa(pc) else {
+        return Ok(());
+    };
+    let regions = EXEC_REGIONS.irqsave_lock();
+    let mut any = false;
+    for r in regions.iter().filter(|r| r.vm_id == vm_id) {
+        if (r.start..r.end).contains(&ipa) {
+            return Ok(());
+        }
+        any = true;
+    }
+    if any {
+        Err(SanityError::PcNotExecutable(pc))
+    } else {
+        Ok(())
+    }
+}
+
+/// A guest may only be in EL0/EL1 (AArch64) or a defined non-hyp AArch32 mode.
+pub fn check_mode(spsr: u64) -> Result<(), SanityError> {
+    let ok = if exit::is_aarch32(spsr) {
+        matches!(spsr & 0x1f, 0x10 | 0x11 | 0x12 | 0x13 | 0x17 | 0x1b | 0x1f)
+    } else {
+        matches!(spsr & 0xf, 0b0000 | 0b0100 | 0b0101)
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(SanityError::BadMode(spsr))
+    }
+}
+
+pub fn check_sp(spsr: u64, sp_el1: u64) -> Result<(), SanityError> {
+    // AArch32 has no SP_EL1 view and EL0t never uses it.
+    if exit::is_aarch32(spsr) || spsr & 0xf != 0b0101 {
+        return Ok(());
+    }
+    if sp_el1 & 0xf != 0 {
+        return Err(SanityError::MisalignedSp(sp_el1));
+    }
+    Ok(())
+}
+
+pub fn check_exit(vcpu: &Vcpu) -> Result<(), SanityError> {
+    let regs = &vcpu.regs;
+    check_mode(regs.spsr)?;
+    check_sp(regs.spsr, regs.sp_el1)?;
+    check_pc(vcpu.vm_id, regs.elr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::{hal::with_mock, test_vm_id};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_check_mode() {
+        assert!(check_mode(0x3c5).is_ok());
+        assert!(check_mode(0x3c4).is_ok());
+        assert!(check_mode(0x0).is_ok());
+        // EL2h
+        assert_eq!(check_mode(0x3c9), Err(SanityError::BadMode(0x3c9)));
+        // AArch32 svc / hyp
+        assert!(check_mode(0x13).is_ok());
+        assert!(check_mode(0x1a).is_err());
+    }
+
+    #[test]
+    fn test_check_sp() {
+        assert!(check_sp(0x3c5, 0x4000_0000).is_ok());
+        assert!(check_sp(0x3c5, 0x4000_0008).is_err());
+        // EL1t runs on SP_EL0.
+        assert!(check_sp(0x3c4, 0x4000_0008).is_ok());
+    }
+
+    #[test]
+    fn test_check_pc() {
+        with_mock(|| {
+            let vm_id = test_vm_id();
+            assert!(check_pc(vm_id, 0x1234).is_ok());
+            register_exec_region(vm_id, 0x4000_0000, 0x10_0000);
+            sysregs().write(SysReg::SctlrEl1, 0);
+            assert!(check_pc(vm_id, 0x4000_1000).is_ok());
+            assert_eq!(
+                check_pc(vm_id, 0x4010_0000),
+                Err(SanityError::PcNotExecutable(0x4010_0000))
+            );
+            unregister_vm(vm_id);
+            assert!(check_pc(vm_id, 0x4010_0000).is_ok());
+        });
+    }
+}
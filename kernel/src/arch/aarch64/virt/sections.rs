@@ -0,0 +1,93 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Linker placement of the hypervisor. The vectors and everything they call
+//! directly live in `.hyp.text`, world-switch state in `.hyp.data`, and
+//! link.x page-aligns both. `check_layout` only checks that placement:
+//! nothing is mapped with the permissions of `regions()` yet. EL2 runs on
+//! the host's identity map in 1GB RW blocks, see `mmu::enable_el2_mmu`, so
+//! `.hyp.text` stays writable, which `alternative` relies on to patch it,
+//! and `.hyp.data` executable. Mapping text RX and data RW and never
+//! executable needs finer Stage-1 tables, installed after the patching.
+//!
+//! Common kernel code the EL2 handlers call (logging, locks) stays in
+//! `.text` and is shared with the host.
+
+use super::{host_pm, vector};
+
+const PAGE_SIZE: usize = 4096;
+
+extern "C" {
+    static __hyp_text_start: u8;
+    static __hyp_text_end: u8;
+    static __hyp_data_start: u8;
+    static __hyp_data_end: u8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HypRegion {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl HypRegion {
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+/// EL2-only image regions and the permissions meant for them, not applied
+/// yet.
+pub fn regions() -> [HypRegion; 2] {
+    unsafe {
+        [
+            HypRegion {
+                name: "hyp.text",
+                start: &__hyp_text_start as *const u8 as usize,
+                end: &__hyp_text_end as *const u8 as usize,
+                writable: false,
+                executable: true,
+            },
+            HypRegion {
+                name: "hyp.data",
+                start: &__hyp_data_start as *const u8 as usize,
+                end: &__hyp_data_end as *const u8 as usize,
+                writable: true,
+                executable: false,
+            },
+        ]
+    }
+}
+
+/// Catch a linker script that lost the EL2 sections: both regions must be
+/// page aligned and the installed vectors must sit in the text region.
+pub fn check_layout() -> Result<(), &'static str> {
+    let [text, data] = regions();
+    for region in [text, data] {
+        if region.start % PAGE_SIZE != 0 || region.end % PAGE_SIZE != 0 {
+            return Err("EL2 section not page aligned");
+        }
+    }
+    if !text.contains(vector::get_vector_table_addr()) {
+        return Err("EL2 vectors outside .hyp.text");
+    }
+    if !text.contains(host_pm::resume_entry as usize) {
+        return Err("EL2 resume entry outside .hyp.text");
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Paravirtual services guests reach through hypercalls. Each VM's
+//! `VmConfig::services` says which ones it may call; the others return
+//! NOT_SUPPORTED. A guest asks with `GUEST_HVC_SERVICES` first and gets
+//! the bitmap below, so it can do without a service instead of finding
+//! out from a failed call.
+//!
+//! Shutdown, PSCI, the discovery call itself and the ABI version query
+//! `GUEST_HVC_ABI_VERSION` are always available.
+
+use super::abi;
+use core::ops::BitOr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Services(u64);
+
+impl Services {
+    pub const NONE: Self = Self(0);
+    /// Character output through `GUEST_HVC_PUTC`.
+    pub const CONSOLE: Self = Self(abi::SERVICE_CONSOLE);
+    /// The guest's own cycle count and timer calibration samples.
+    pub const TIME: Self = Self(abi::SERVICE_TIME);
+    /// Page grants and doorbells.
+    pub const SHMEM: Self = Self(abi::SERVICE_SHMEM);
+    /// Host file access. Reserved: no VM is offered it yet.
+    pub const FS: Self = Self(abi::SERVICE_FS);
+    /// Instrumentation for test images, i.e. guest trace events.
+    pub const TEST_AGENT: Self = Self(abi::SERVICE_TEST_AGENT);
+    /// Device hotplug events.
+    pub const HOTPLUG: Self = Self(abi::SERVICE_HOTPLUG);
+    /// Bulk copies through `GUEST_HVC_COPY`, see `copy`.
+    pub const COPY: Self = Self(abi::SERVICE_COPY);
+    /// Services the hypervisor implements.
+    pub const PROVIDED: Self = Self(
+        Self::CONSOLE.0
+            | Self::TIME.0
+            | Self::SHMEM.0
+            | Self::TEST_AGENT.0
+            | Self::HOTPLUG.0
+            | Self::COPY.0,
+    );
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Bitmap the discovery call returns: the services enabled for the VM
+    /// the hypervisor can actually provide.
+    pub const fn discover(self) -> u64 {
+        self.0 & Self::PROVIDED.0
+    }
+}
+
+impl BitOr for Services {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_services_discover() {
+        let services = Services::CONSOLE | Services::FS | Services::SHMEM;
+        assert!(services.contains(Services::CONSOLE | Services::SHMEM));
+        assert!(!services.contains(Services::TIME));
+        assert_eq!(services.discover(), 0b101);
+        assert_eq!(
+            Services::PROVIDED.without(Services::CONSOLE).discover(),
+            0b111_0110
+        );
+        assert!(Services::NONE.contains(Services::NONE));
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shadow register view. When enabled for a vCPU, every exit mirrors a few
+//! key registers into a snapshot the host can read at any time, so a running
+//! guest can be watched without pausing it. The snapshot is a seqlock: EL2
+//! on the vCPU's core is the only writer and never blocks on readers.
+
+use super::vcpu::VcpuStateStruct;
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+const PC: usize = 0;
+const SP: usize = 1;
+const X0: usize = 2;
+const SPSR: usize = 6;
+const NUM_VALS: usize = 7;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegSnapshot {
+    pub pc: u64,
+    pub sp: u64,
+    pub x: [u64; 4],
+    pub spsr: u64,
+    /// Exits recorded since the view was created.
+    pub exits: usize,
+}
+
+pub struct ShadowRegs {
+    enabled: AtomicBool,
+    // Odd while a record is in progress; twice the number of records.
+    seq: AtomicUsize,
+    vals: [AtomicU64; NUM_VALS],
+}
+
+impl ShadowRegs {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            seq: AtomicUsize::new(0),
+            vals: [const { AtomicU64::new(0) }; NUM_VALS],
+        }
+    }
+
+    pub fn set_enabled(&self, on: bool) {
+        self.enabled.store(on, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Mirror `regs` if the view is on. Only called by the core running the
+    /// vCPU.
+    #[inline]
+    pub fn record(&self, regs: &VcpuStateStruct) {
+        if !self.is_enabled() {
+            return;
+        }
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.vals[PC].store(regs.elr, Ordering::Relaxed);
+        self.vals[SP].store(regs.sp_el1, Ordering::Relaxed);
+        for (n, x) in regs.x[..4].iter().enumerate() {
+            self.vals[X0 + n].store(*x, Ordering::Relaxed);
+        }
+        self.vals[SPSR].store(regs.spsr, Ordering::Relaxed);
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    /// Last recorded registers, or `None` before the first record.
+    pub fn snapshot(&self) -> Option<RegSnapshot> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq == 0 {
+                return None;
+            }
+            if seq & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+            let mut snap = RegSnapshot {
+                pc: self.vals[PC].load(Ordering::Relaxed),
+                sp: self.vals[SP].load(Ordering::Relaxed),
+                x: [0; 4],
+                spsr: self.vals[SPSR].load(Ordering::Relaxed),
+                exits: seq / 2,
+            };
+            for (n, x) in snap.x.iter_mut().enumerate() {
+                *x = self.vals[X0 + n].load(Ordering::Relaxed);
+            }
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return Some(snap);
+            }
+        }
+    }
+}
+
+impl Default for ShadowRegs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_shadow_record() {
+        let shadow = ShadowRegs::new();
+        let mut regs = VcpuStateStruct::new();
+        regs.elr = 0x4000_1000;
+        regs.sp_el1 = 0x4800_0000;
+        regs.x[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+        regs.spsr = 0x3c5;
+
+        shadow.record(&regs);
+        assert_eq!(shadow.snapshot(), None);
+
+        shadow.set_enabled(true);
+        shadow.record(&regs);
+        regs.elr += 4;
+        shadow.record(&regs);
+        let snap = shadow.snapshot().unwrap();
+        assert_eq!(snap.pc, 0x4000_1004);
+        assert_eq!(snap.sp, 0x4800_0000);
+        assert_eq!(snap.x, [1, 2, 3, 4]);
+        assert_eq!(snap.spsr, 0x3c5);
+        assert_eq!(snap.exits, 2);
+    }
+}
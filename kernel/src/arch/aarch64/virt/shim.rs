@@ -0,0 +1,100 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reset shim for guest images that expect firmware to have run. VMs with
+//! `VmConfig::reset_shim` get one page of hypervisor code mapped at the top
+//! of their IPA space, and their vCPUs start there instead of at the image
+//! entry, on boot as well as on PSCI CPU_ON and power-down resume. The shim
+//! puts EL1 in the state boot firmware leaves: interrupts masked, MMU and
+//! data cache off, instruction cache on, no stale TLB or instruction cache
+//! entries. It then jumps to the image with x0-x3 untouched, so the DTB or
+//! context id handed over in x0 arrives where the image looks for it.
+
+use super::{stage2::PAGE_SIZE, vcpu::VcpuStateStruct};
+use core::ptr::addr_of;
+
+/// IPA of the shim page in a VM with `ipa_bits`, the last page the guest
+/// can address.
+pub const fn ipa(ipa_bits: u32) -> u64 {
+    (1 << ipa_bits) - PAGE_SIZE
+}
+/// Register carrying the image entry into the shim.
+const ENTRY_REG: usize = 4;
+
+// SCTLR_EL1: the RES1 bits of Armv8.0 plus I. M and C stay clear.
+core::arch::global_asm!(
+    "
+.pushsection .rodata.guest_shim, \"a\"
+.balign 4096
+.global __guest_reset_shim
+__guest_reset_shim:
+    msr daifset, #0xf
+    movz x5, #0x1800
+    movk x5, #0x30d0, lsl #16
+    msr sctlr_el1, x5
+    isb
+    ic iallu
+    tlbi vmalle1
+    dsb nsh
+    isb
+    mov x5, x4
+    mov x4, xzr
+    br x5
+.balign 4096
+.global __guest_reset_shim_end
+__guest_reset_shim_end:
+.popsection
+"
+);
+
+extern "C" {
+    static __guest_reset_shim: u8;
+    static __guest_reset_shim_end: u8;
+}
+
+/// Host physical address of the shim page.
+pub fn pa() -> u64 {
+    addr_of!(__guest_reset_shim) as u64
+}
+
+/// Make a vCPU of a VM with `ipa_bits`, about to start at `regs.elr`, go
+/// through the shim first.
+pub fn redirect(regs: &mut VcpuStateStruct, ipa_bits: u32) {
+    regs.x[ENTRY_REG] = regs.elr;
+    regs.elr = ipa(ipa_bits);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::profile::BootProtocol;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_shim_page() {
+        let end = addr_of!(__guest_reset_shim_end) as u64;
+        assert_eq!(pa() % PAGE_SIZE, 0);
+        assert_eq!(end - pa(), PAGE_SIZE);
+        assert_eq!(ipa(32), 0xffff_f000);
+    }
+
+    #[test]
+    fn test_shim_redirect() {
+        let mut regs = BootProtocol::LinuxArm64.initial_regs(0x4008_0000, 0x4400_0000);
+        redirect(&mut regs, 39);
+        assert_eq!(regs.elr, ipa(39));
+        assert_eq!(regs.x[ENTRY_REG], 0x4008_0000);
+        assert_eq!(regs.x[0], 0x4400_0000);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Memory model of memory shared between a guest and the host or another
+//! VM, such as granted pages holding a ring.
+//!
+//! Every side maps shared memory Normal, Write-Back cacheable and Inner
+//! Shareable: Stage-2 with `STAGE2_MEM`, the host through its map of RAM,
+//! which `host_coherent` checks, and the guest is expected to do the same
+//! in its own tables. Cores then see the memory coherently whichever one
+//! runs the other side, and no cache maintenance is needed. What is left
+//! is ordering, so rings follow one rule: the producer writes its entries
+//! and then publishes the index with `store_release`, the consumer reads
+//! the index with `load_acquire` and only then the entries it covers.
+//!
+//! Stage-2 can't make a guest mapping cacheable, so a guest that maps the
+//! memory non-cacheable, or a DMA master outside the inner shareable
+//! domain, needs the cache maintenance helpers: clean after writing and
+//! clean and invalidate before reading what the other side wrote.
+
+use super::{hyper, stage2::MemType};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Stage-2 type of shared memory: Normal Write-Back, Inner Shareable.
+pub const STAGE2_MEM: MemType = MemType::Normal;
+
+// Size of the host's RAM and device map entries.
+const HOST_BLOCK_SHIFT: u32 = 30;
+
+// Whether [pa, pa + len) lies in the blocks starting at `bases`.
+fn in_blocks(bases: &[u64], pa: u64, len: u64) -> bool {
+    let Some(last) = pa.checked_add(len.max(1) - 1) else {
+        return false;
+    };
+    (pa >> HOST_BLOCK_SHIFT..=last >> HOST_BLOCK_SHIFT)
+        .all(|block| bases.iter().any(|&b| b >> HOST_BLOCK_SHIFT == block))
+}
+
+/// Whether the host maps [pa, pa + len) with the attributes of shared
+/// memory.
+pub fn host_coherent(pa: u64, len: u64) -> bool {
+    in_blocks(crate::boards::MMU_L1_NORMAL_BASES, pa, len)
+}
+
+/// Read a ring index the other side publishes. Entries it covers may be
+/// read once this returns.
+///
+/// # Safety
+/// `index` must be 4-byte aligned and mapped for reading.
+pub unsafe fn load_acquire(index: *const u32) -> u32 {
+    AtomicU32::from_ptr(index as *mut u32).load(Ordering::Acquire)
+}
+
+/// Publish a ring index. Entries written before are visible to a side
+/// that reads the new value with `load_acquire`.
+///
+/// # Safety
+/// `index` must be 4-byte aligned and mapped for writing.
+pub unsafe fn store_release(index: *mut u32, value: u32) {
+    AtomicU32::from_ptr(index).store(value, Ordering::Release)
+}
+
+fn line_size(ctr: u64) -> usize {
+    // CTR_EL0.DminLine: log2 of the smallest data cache line in words.
+    4 << ((ctr >> 16) & 0xf)
+}
+
+/// Smallest data cache line of any cache of the core, in bytes.
+pub fn dcache_line_size() -> usize {
+    line_size(hyper::read_ctr_el0())
+}
+
+macro_rules! dcache_range {
+    ($op:literal, $addr:expr, $len:expr) => {{
+        let line = dcache_line_size();
+        let mut va = $addr & !(line - 1);
+        let end = $addr + $len;
+        while va < end {
+            asm!(concat!("dc ", $op, ", {0}"), in(reg) va, options(nostack));
+            va += line;
+        }
+        asm!("dsb sy", options(nostack));
+    }};
+}
+
+/// Write dirty lines of [addr, addr + len) back to the point of coherency,
+/// for a reader that doesn't snoop the caches.
+///
+/// # Safety
+/// The range must be mapped.
+pub unsafe fn clean_dcache(addr: usize, len: usize) {
+    dcache_range!("cvac", addr, len);
+}
+
+/// `clean_dcache` and drop the lines, so what a writer that bypasses the
+/// caches put there is read next. Lines are cleaned first, so data sharing
+/// a line with the range isn't lost.
+///
+/// # Safety
+/// The range must be mapped.
+pub unsafe fn clean_invalidate_dcache(addr: usize, len: usize) {
+    dcache_range!("civac", addr, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_shmem_host_blocks() {
+        let bases = [0x4008_0000, 0x8000_0000];
+        assert!(in_blocks(&bases, 0x4000_0000, 0x1000));
+        assert!(in_blocks(&bases, 0x7fff_f000, 0x2000));
+        assert!(!in_blocks(&bases, 0xbfff_f000, 0x2000));
+        assert!(!in_blocks(&bases, 0x1000, 0x1000));
+        assert!(!in_blocks(&bases, u64::MAX, 2));
+        assert_eq!(line_size(0x8444_c004), 64);
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SPI numbers of a VM. Instead of hardcoding an INTID, a device is added
+//! to a `VmBuilder` with its line by name, `Spi::Named`, and gets the
+//! lowest SPI that nothing else of the VM raises and hotplug doesn't keep.
+//! A device whose guest expects a number, e.g. a board layout, names its
+//! line with `Spi::Prefer` and gets that number while it is free. The
+//! names stay with the built VM, so whatever describes it to the guest or
+//! drives it, e.g. /proc/hypervisor/vmN/inject, looks the numbers up with
+//! `lookup` instead of repeating them.
+
+use super::{hotplug, vgic::MAX_INTID};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// Virtual interrupts a device may raise are SPIs.
+pub const FIRST_SPI: u32 = 32;
+
+/// Interrupt of a device added to a `VmBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spi {
+    /// This SPI, whatever else raises it; validation tells.
+    Fixed(u32),
+    /// The SPI handed out under this name.
+    Named(&'static str),
+    /// The SPI handed out under this name, this one if it is free.
+    Prefer(&'static str, u32),
+}
+
+impl From<u32> for Spi {
+    fn from(intid: u32) -> Self {
+        Self::Fixed(intid)
+    }
+}
+
+impl From<&'static str> for Spi {
+    fn from(name: &'static str) -> Self {
+        Self::Named(name)
+    }
+}
+
+impl Spi {
+    /// Name the SPI is handed out under, if it isn't fixed.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Self::Fixed(_) => None,
+            Self::Named(name) | Self::Prefer(name, _) => Some(name),
+        }
+    }
+}
+
+/// SPIs handed out by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SpiMap(Vec<(&'static str, u32)>);
+
+impl SpiMap {
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.0
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, intid)| intid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// The SPI named `name`: the one it already has, or else `prefer` or
+    /// the lowest one, whichever is neither handed out nor `taken` nor kept
+    /// for hotplug.
+    pub fn alloc(
+        &mut self,
+        name: &'static str,
+        prefer: Option<u32>,
+        taken: impl Fn(u32) -> bool,
+    ) -> Option<u32> {
+        if let Some(intid) = self.lookup(name) {
+            return Some(intid);
+        }
+        let free = |intid: u32| {
+            (FIRST_SPI..MAX_INTID).contains(&intid)
+                && !taken(intid)
+                && !hotplug::is_reserved(intid)
+                && self.0.iter().all(|&(_, other)| other != intid)
+        };
+        let intid = prefer
+            .filter(|&intid| free(intid))
+            .or_else(|| (FIRST_SPI..MAX_INTID).find(|&intid| free(intid)))?;
+        self.0.push((name, intid));
+        Some(intid)
+    }
+}
+
+static MAPS: SpinLock<BTreeMap<usize, SpiMap>> = SpinLock::new(BTreeMap::new());
+
+/// Keep the SPIs handed out while building VM `vm_id`.
+pub fn register(vm_id: usize, map: SpiMap) {
+    if !map.0.is_empty() {
+        MAPS.irqsave_lock().insert(vm_id, map);
+    }
+}
+
+/// The SPI named `name` of VM `vm_id`.
+pub fn lookup(vm_id: usize, name: &str) -> Option<u32> {
+    MAPS.irqsave_lock().get(&vm_id)?.lookup(name)
+}
+
+/// All SPIs handed out by name to VM `vm_id`.
+pub fn map_of(vm_id: usize) -> SpiMap {
+    MAPS.irqsave_lock().get(&vm_id).cloned().unwrap_or_default()
+}
+
+/// Forget the names of VM `vm_id`, once it has no vCPUs left.
+pub(crate) fn release_vm(vm_id: usize) {
+    let map = MAPS.irqsave_lock().remove(&vm_id);
+    drop(map);
+}
@@ -0,0 +1,1027 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stage-2 translation tables of a VM: 4KB granule, and level 1 and level 2
+//! entries can map 1GB and 2MB blocks. The IPA space is sized per VM, from
+//! `MIN_IPA_BITS` up to what ID_AA64MMFR0_EL1.PARange allows, and the walk
+//! starts at the deepest level whose table, concatenated up to 16 times,
+//! still resolves all of it: a 32-bit guest takes two lookups, a 40-bit one
+//! three. `vtcr` gives the VTCR_EL2 value to match.
+//! Mappings use the largest block that alignment and size allow. A block is
+//! split into next-level entries when only part of it is unmapped or has its
+//! permissions changed.
+//!
+//! Tables are written by the host at EL1, whose memory is identity mapped, so
+//! a table's address is also its physical address. Stage-2 TLB maintenance is
+//! only possible at EL2 and goes through `HVC_S2_TLB_FLUSH`. Each set of
+//! tables holds a `Vmid` tagging its TLB entries.
+
+use super::{hyper, vlog::vlog, vmid::Vmid};
+use crate::{
+    arch::aarch64::{
+        psci::hvc_call,
+        registers::vtcr_el2::{VTCR_EL2, VTCR_EL2_RES1},
+        sysreg::{read_sysreg, write_sysreg},
+    },
+    sync::SpinLock,
+};
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout},
+    boxed::Box,
+    collections::BTreeMap,
+    vec::Vec,
+};
+use core::{arch::asm, fmt, ptr::NonNull};
+use tock_registers::{interfaces::*, register_bitfields, registers::InMemoryRegister};
+
+pub const PAGE_SIZE: u64 = 4096;
+/// IPA size of VMs that don't ask for another.
+pub const IPA_BITS: u32 = 39;
+/// IPA sizes the tables support; the hardware may allow fewer.
+pub const MIN_IPA_BITS: u32 = 32;
+pub const MAX_IPA_BITS: u32 = 48;
+const ENTRIES: usize = 512;
+const LAST_LEVEL: usize = 3;
+// Root tables VTCR_EL2 lets the walk concatenate.
+const MAX_CONCAT_BITS: u32 = 4;
+// Physical address bits of each ID_AA64MMFR0_EL1.PARange value.
+const PA_RANGE_BITS: [u32; 7] = [32, 36, 40, 42, 44, 48, 52];
+
+/// Host HVC doing Stage-2 TLB maintenance: x1 is the VTTBR_EL2 value of the
+/// tables, x2 the IPA to invalidate or `FLUSH_ALL`.
+pub const HVC_S2_TLB_FLUSH: u64 = 0x02;
+const FLUSH_ALL: u64 = u64::MAX;
+
+register_bitfields! {u64,
+    pub S2_DESCRIPTOR [
+        /// Execute-never for EL1 and EL0.
+        XN OFFSET(53) NUMBITS(2) [
+            Exec = 0b00,
+            NoExec = 0b10
+        ],
+
+        /// Output address, or address of the next-level table.
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [],
+
+        /// Access flag. Always set, the hypervisor doesn't track accesses.
+        AF OFFSET(10) NUMBITS(1) [],
+
+        SH OFFSET(8) NUMBITS(2) [
+            NotShareable = 0b00,
+            InnerShareable = 0b11
+        ],
+
+        /// Stage-2 access permissions.
+        S2AP OFFSET(6) NUMBITS(2) [
+            None = 0b00,
+            ReadOnly = 0b01,
+            WriteOnly = 0b10,
+            ReadWrite = 0b11
+        ],
+
+        /// Stage-2 memory type, with HCR_EL2.FWB clear.
+        MEMATTR OFFSET(2) NUMBITS(4) [
+            DeviceNGnRE = 0b0001,
+            NormalWriteBack = 0b1111
+        ],
+
+        /// 0: block at level 1/2
+        /// 1: table at level 1/2, page at level 3
+        TYPE OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            TableOrPage = 1
+        ],
+
+        VALID OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+type Descriptor = InMemoryRegister<u64, S2_DESCRIPTOR::Register>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemType {
+    Normal,
+    Device,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct S2Perms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl S2Perms {
+    pub const RWX: Self = Self::new(true, true, true);
+    pub const RW: Self = Self::new(true, true, false);
+    pub const RX: Self = Self::new(true, false, true);
+    pub const RO: Self = Self::new(true, false, false);
+
+    pub const fn new(read: bool, write: bool, exec: bool) -> Self {
+        Self { read, write, exec }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage2Error {
+    Misaligned,
+    OutOfRange,
+    AlreadyMapped,
+    /// The range overlaps a guard region.
+    Guarded,
+    /// No table layout covers the IPA size asked for.
+    BadIpaSize,
+    /// Every VMID is taken by another VM.
+    NoVmid,
+}
+
+/// Layout of the tables for one IPA size: the walk starts at `root_level`,
+/// whose table is `root_tables` pages concatenated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub ipa_bits: u32,
+    pub root_level: usize,
+    pub root_tables: usize,
+}
+
+impl Geometry {
+    /// The shortest walk resolving `ipa_bits`, `None` outside
+    /// `MIN_IPA_BITS..=MAX_IPA_BITS`.
+    pub const fn of(ipa_bits: u32) -> Option<Self> {
+        if ipa_bits < MIN_IPA_BITS || ipa_bits > MAX_IPA_BITS {
+            return None;
+        }
+        // A 4KB granule walk starts at level 2 at the latest.
+        let mut level = 2;
+        loop {
+            let span = level_shift(level) as u32 + 9;
+            if ipa_bits <= span + MAX_CONCAT_BITS {
+                let concat = ipa_bits.saturating_sub(span);
+                return Some(Self {
+                    ipa_bits,
+                    root_level: level,
+                    root_tables: 1 << concat,
+                });
+            }
+            if level == 0 {
+                return None;
+            }
+            level -= 1;
+        }
+    }
+
+    /// VTCR_EL2 selecting this layout, for output addresses as wide as
+    /// `pa_range`, an ID_AA64MMFR0_EL1.PARange value.
+    pub fn vtcr(&self, pa_range: u64) -> u64 {
+        // PS has no encoding above 48 bits without FEAT_LPA.
+        let ps = pa_range.min(VTCR_EL2::PS::Bits_48.value);
+        let fields = VTCR_EL2::PS.val(ps)
+            + VTCR_EL2::TG0::KiB_4
+            + VTCR_EL2::SH0::InnerShareable
+            + VTCR_EL2::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + VTCR_EL2::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + VTCR_EL2::SL0.val((2 - self.root_level) as u64)
+            + VTCR_EL2::T0SZ.val((64 - self.ipa_bits) as u64);
+        VTCR_EL2_RES1 | fields.value
+    }
+
+    fn root_layout(&self) -> Layout {
+        // VTTBR_EL2.BADDR must be aligned to the concatenated size.
+        let size = self.root_tables * core::mem::size_of::<Table>();
+        Layout::from_size_align(size, size).unwrap()
+    }
+}
+
+/// IPA bits a PARange value of ID_AA64MMFR0_EL1 allows.
+pub fn pa_range_bits(mmfr0: u64) -> u32 {
+    PA_RANGE_BITS
+        .get((mmfr0 & 0xf) as usize)
+        .copied()
+        .unwrap_or(MIN_IPA_BITS)
+}
+
+/// Largest IPA size this CPU and the tables support.
+pub fn max_ipa_bits() -> u32 {
+    pa_range_bits(hyper::read_id_aa64mmfr0_el1()).min(MAX_IPA_BITS)
+}
+
+/// VTCR_EL2 for a VM with `ipa_bits`, or `None` if this CPU can't honor
+/// that size. Cheap enough for every guest entry.
+#[inline]
+pub fn vtcr(ipa_bits: u32) -> Option<u64> {
+    let pa_range = hyper::read_id_aa64mmfr0_el1() & 0xf;
+    if ipa_bits > pa_range_bits(pa_range) {
+        return None;
+    }
+    Geometry::of(ipa_bits).map(|g| g.vtcr(pa_range))
+}
+
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+enum Entry {
+    Invalid,
+    Table(*mut Table),
+    Leaf,
+}
+
+#[derive(Clone, Copy)]
+enum Update {
+    Unmap,
+    Protect(S2Perms),
+}
+
+#[inline]
+const fn level_shift(level: usize) -> u64 {
+    12 + 9 * (LAST_LEVEL - level) as u64
+}
+
+/// Size mapped by one entry at `level`.
+#[inline]
+pub const fn block_size(level: usize) -> u64 {
+    1 << level_shift(level)
+}
+
+#[inline]
+fn index(ipa: u64, level: usize) -> usize {
+    (ipa >> level_shift(level)) as usize & (ENTRIES - 1)
+}
+
+#[inline]
+fn output_addr(desc: u64) -> u64 {
+    Descriptor::new(desc).read(S2_DESCRIPTOR::OUTPUT_ADDR) << 12
+}
+
+fn classify(desc: u64, level: usize) -> Entry {
+    let d = Descriptor::new(desc);
+    if !d.is_set(S2_DESCRIPTOR::VALID) {
+        Entry::Invalid
+    } else if level < LAST_LEVEL && d.matches_all(S2_DESCRIPTOR::TYPE::TableOrPage) {
+        Entry::Table(output_addr(desc) as *mut Table)
+    } else {
+        Entry::Leaf
+    }
+}
+
+fn with_perms(desc: u64, perms: S2Perms) -> u64 {
+    let d = Descriptor::new(desc);
+    let ap = match (perms.read, perms.write) {
+        (false, false) => S2_DESCRIPTOR::S2AP::None,
+        (true, false) => S2_DESCRIPTOR::S2AP::ReadOnly,
+        (false, true) => S2_DESCRIPTOR::S2AP::WriteOnly,
+        (true, true) => S2_DESCRIPTOR::S2AP::ReadWrite,
+    };
+    let xn = if perms.exec {
+        S2_DESCRIPTOR::XN::Exec
+    } else {
+        S2_DESCRIPTOR::XN::NoExec
+    };
+    d.modify(ap + xn);
+    d.get()
+}
+
+// Leaf attributes without output address and type.
+fn leaf_template(mem: MemType, perms: S2Perms) -> u64 {
+    let d = Descriptor::new(0);
+    match mem {
+        MemType::Normal => d.write(
+            S2_DESCRIPTOR::AF::SET
+                + S2_DESCRIPTOR::MEMATTR::NormalWriteBack
+                + S2_DESCRIPTOR::SH::InnerShareable,
+        ),
+        MemType::Device => d.write(S2_DESCRIPTOR::AF::SET + S2_DESCRIPTOR::MEMATTR::DeviceNGnRE),
+    }
+    with_perms(d.get(), perms)
+}
+
+fn leaf_mem(desc: u64) -> MemType {
+    if Descriptor::new(desc).matches_all(S2_DESCRIPTOR::MEMATTR::DeviceNGnRE) {
+        MemType::Device
+    } else {
+        MemType::Normal
+    }
+}
+
+fn leaf_perms(desc: u64) -> S2Perms {
+    let d = Descriptor::new(desc);
+    let ap = d.read(S2_DESCRIPTOR::S2AP);
+    S2Perms::new(
+        ap & 0b01 != 0,
+        ap & 0b10 != 0,
+        d.matches_all(S2_DESCRIPTOR::XN::Exec),
+    )
+}
+
+fn make_leaf(template: u64, pa: u64, level: usize) -> u64 {
+    let d = Descriptor::new(template);
+    let ty = if level == LAST_LEVEL {
+        S2_DESCRIPTOR::TYPE::TableOrPage
+    } else {
+        S2_DESCRIPTOR::TYPE::Block
+    };
+    d.modify(S2_DESCRIPTOR::VALID::SET + S2_DESCRIPTOR::OUTPUT_ADDR.val(pa >> 12) + ty);
+    d.get()
+}
+
+fn make_table(table: *mut Table) -> u64 {
+    let d = Descriptor::new(0);
+    d.write(
+        S2_DESCRIPTOR::VALID::SET
+            + S2_DESCRIPTOR::TYPE::TableOrPage
+            + S2_DESCRIPTOR::OUTPUT_ADDR.val(table as u64 >> 12),
+    );
+    d.get()
+}
+
+#[inline]
+fn publish() {
+    unsafe { asm!("dsb ishst", options(nostack)) };
+}
+
+/// Stage-2 tables of one VM. The guest must no longer run on them when they
+/// are dropped.
+pub struct Stage2 {
+    geometry: Geometry,
+    // `geometry.root_tables` concatenated tables.
+    root: NonNull<Table>,
+    // Every table below the root; they live as long as the root does.
+    tables: Vec<Box<Table>>,
+    // (ipa, size) of ranges that must stay unmapped, e.g. below stacks.
+    guards: Vec<(u64, u64)>,
+    // Freed after the tables, see `Drop`.
+    vmid: Vmid,
+}
+
+// SAFETY: The root is owned by the `Stage2` like the boxed tables are.
+unsafe impl Send for Stage2 {}
+
+impl Stage2 {
+    /// Tables for an `IPA_BITS` space.
+    pub fn new() -> Self {
+        Self::with_ipa_bits(IPA_BITS).unwrap()
+    }
+
+    /// Tables for a `2^ipa_bits` byte IPA space. Whether the CPU supports
+    /// it is up to the caller, see `max_ipa_bits`.
+    pub fn with_ipa_bits(ipa_bits: u32) -> Result<Self, Stage2Error> {
+        let geometry = Geometry::of(ipa_bits).ok_or(Stage2Error::BadIpaSize)?;
+        let vmid = Vmid::alloc().ok_or(Stage2Error::NoVmid)?;
+        let layout = geometry.root_layout();
+        let root = NonNull::new(unsafe { alloc_zeroed(layout) } as *mut Table)
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        Ok(Self {
+            geometry,
+            root,
+            tables: Vec::new(),
+            guards: Vec::new(),
+            vmid,
+        })
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    pub fn vmid(&self) -> &Vmid {
+        &self.vmid
+    }
+
+    /// VTTBR_EL2 value selecting these tables and their VMID.
+    pub fn vttbr(&self) -> u64 {
+        self.vmid.vttbr() | self.root.as_ptr() as u64
+    }
+
+    fn check_range(&self, ipa: u64, size: u64) -> Result<(), Stage2Error> {
+        if ipa % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+            return Err(Stage2Error::Misaligned);
+        }
+        match ipa.checked_add(size) {
+            Some(end) if end <= 1 << self.geometry.ipa_bits => Ok(()),
+            _ => Err(Stage2Error::OutOfRange),
+        }
+    }
+
+    // Entry of `table` at `level` resolving `ipa`. The root is indexed
+    // across all of its concatenated tables.
+    fn entry(&self, table: *mut Table, ipa: u64, level: usize) -> *mut u64 {
+        let n = if level == self.geometry.root_level {
+            (ipa / block_size(level)) as usize
+        } else {
+            index(ipa, level)
+        };
+        unsafe { (table as *mut u64).add(n) }
+    }
+
+    fn alloc_table(&mut self) -> *mut Table {
+        let mut table: Box<Table> = unsafe { Box::new_zeroed().assume_init() };
+        let ptr = &mut *table as *mut Table;
+        self.tables.push(table);
+        ptr
+    }
+
+    /// Map `[ipa, ipa + size)` to `[pa, pa + size)`. Stops at the first
+    /// entry that is already mapped, keeping what was mapped before it.
+    pub fn map(
+        &mut self,
+        ipa: u64,
+        pa: u64,
+        size: u64,
+        mem: MemType,
+        perms: S2Perms,
+    ) -> Result<(), Stage2Error> {
+        self.check_range(ipa, size)?;
+        if pa % PAGE_SIZE != 0 {
+            return Err(Stage2Error::Misaligned);
+        }
+        if self.is_guarded(ipa, size) {
+            return Err(Stage2Error::Guarded);
+        }
+        let template = leaf_template(mem, perms);
+        let result = self.map_in(
+            self.root.as_ptr(),
+            self.geometry.root_level,
+            ipa,
+            ipa + size,
+            pa.wrapping_sub(ipa),
+            template,
+        );
+        // Invalid to valid needs no TLB maintenance, only ordering against
+        // the guest's walks.
+        publish();
+        vlog!(
+            Stage2,
+            Debug,
+            "[stage2] map ipa {:#x} -> pa {:#x}, size {:#x}: {:?}",
+            ipa,
+            pa,
+            size,
+            result
+        );
+        result
+    }
+
+    /// Map every page of `[ipa, ipa + size)` to the one page at `pa`, e.g.
+    /// a shared zero page. Stops at the first entry that is already mapped.
+    pub fn map_repeated(
+        &mut self,
+        ipa: u64,
+        pa: u64,
+        size: u64,
+        mem: MemType,
+        perms: S2Perms,
+    ) -> Result<(), Stage2Error> {
+        self.check_range(ipa, size)?;
+        if pa % PAGE_SIZE != 0 {
+            return Err(Stage2Error::Misaligned);
+        }
+        if self.is_guarded(ipa, size) {
+            return Err(Stage2Error::Guarded);
+        }
+        let (root, level) = (self.root.as_ptr(), self.geometry.root_level);
+        let template = leaf_template(mem, perms);
+        let result = (ipa..ipa + size)
+            .step_by(PAGE_SIZE as usize)
+            .try_for_each(|page| {
+                self.map_in(
+                    root,
+                    level,
+                    page,
+                    page + PAGE_SIZE,
+                    pa.wrapping_sub(page),
+                    template,
+                )
+            });
+        publish();
+        vlog!(
+            Stage2,
+            Debug,
+            "[stage2] map ipa {:#x} -> pa {:#x} repeated, size {:#x}: {:?}",
+            ipa,
+            pa,
+            size,
+            result
+        );
+        result
+    }
+
+    fn is_guarded(&self, ipa: u64, size: u64) -> bool {
+        self.guards
+            .iter()
+            .any(|&(g, g_size)| ipa < g + g_size && g < ipa + size)
+    }
+
+    fn map_in(
+        &mut self,
+        table: *mut Table,
+        level: usize,
+        start: u64,
+        end: u64,
+        offset: u64,
+        template: u64,
+    ) -> Result<(), Stage2Error> {
+        let size = block_size(level);
+        let mut ipa = start;
+        while ipa < end {
+            let next = ((ipa & !(size - 1)) + size).min(end);
+            let pa = ipa.wrapping_add(offset);
+            let entry = unsafe { &mut *self.entry(table, ipa, level) };
+            match classify(*entry, level) {
+                // Levels 1 to 3 hold leaves, level 0 only tables.
+                Entry::Invalid if level > 0 && next - ipa == size && pa & (size - 1) == 0 => {
+                    *entry = make_leaf(template, pa, level);
+                }
+                Entry::Invalid => {
+                    let sub = self.alloc_table();
+                    *entry = make_table(sub);
+                    self.map_in(sub, level + 1, ipa, next, offset, template)?;
+                }
+                Entry::Table(sub) => self.map_in(sub, level + 1, ipa, next, offset, template)?,
+                Entry::Leaf => return Err(Stage2Error::AlreadyMapped),
+            }
+            ipa = next;
+        }
+        Ok(())
+    }
+
+    /// Remove any mapping in `[ipa, ipa + size)`, splitting blocks that
+    /// straddle the range.
+    pub fn unmap(&mut self, ipa: u64, size: u64) -> Result<(), Stage2Error> {
+        vlog!(
+            Stage2,
+            Debug,
+            "[stage2] unmap ipa {:#x}, size {:#x}",
+            ipa,
+            size
+        );
+        self.update(ipa, size, Update::Unmap)
+    }
+
+    /// Unmap `[ipa, ipa + size)` and keep it unmapped, so a guest access
+    /// there faults and can be told apart from a stray one.
+    pub fn add_guard(&mut self, ipa: u64, size: u64) -> Result<(), Stage2Error> {
+        self.unmap(ipa, size)?;
+        self.guards.push((ipa, size));
+        Ok(())
+    }
+
+    pub fn is_guard(&self, ipa: u64) -> bool {
+        self.guards
+            .iter()
+            .any(|&(g, size)| (g..g + size).contains(&ipa))
+    }
+
+    /// Change the permissions of mapped memory in `[ipa, ipa + size)`,
+    /// splitting blocks that straddle the range. Holes are left alone.
+    pub fn protect(&mut self, ipa: u64, size: u64, perms: S2Perms) -> Result<(), Stage2Error> {
+        self.update(ipa, size, Update::Protect(perms))
+    }
+
+    fn update(&mut self, ipa: u64, size: u64, op: Update) -> Result<(), Stage2Error> {
+        self.check_range(ipa, size)?;
+        let (root, level) = (self.root.as_ptr(), self.geometry.root_level);
+        if self.update_in(root, level, ipa, ipa + size, op) {
+            publish();
+            self.flush_tlb(FLUSH_ALL);
+        }
+        Ok(())
+    }
+
+    // Returns whether any leaf changed.
+    fn update_in(
+        &mut self,
+        table: *mut Table,
+        level: usize,
+        start: u64,
+        end: u64,
+        op: Update,
+    ) -> bool {
+        let size = block_size(level);
+        let mut changed = false;
+        let mut ipa = start;
+        while ipa < end {
+            let base = ipa & !(size - 1);
+            let next = (base + size).min(end);
+            let entry = unsafe { &mut *self.entry(table, ipa, level) };
+            match classify(*entry, level) {
+                Entry::Invalid => {}
+                Entry::Table(sub) => changed |= self.update_in(sub, level + 1, ipa, next, op),
+                Entry::Leaf if next - ipa == size => {
+                    *entry = match op {
+                        Update::Unmap => 0,
+                        Update::Protect(perms) => with_perms(*entry, perms),
+                    };
+                    changed = true;
+                }
+                Entry::Leaf => {
+                    let sub = self.split(entry, base, level);
+                    changed |= self.update_in(sub, level + 1, ipa, next, op);
+                }
+            }
+            ipa = next;
+        }
+        changed
+    }
+
+    // Replace the block in `entry`, which maps `ipa`, by a next-level table
+    // mapping the same memory with the same attributes.
+    fn split(&mut self, entry: &mut u64, ipa: u64, level: usize) -> *mut Table {
+        let sub = self.alloc_table();
+        let block = *entry;
+        let pa = output_addr(block);
+        let child_size = block_size(level + 1);
+        for (i, e) in unsafe { (*sub).0.iter_mut() }.enumerate() {
+            *e = make_leaf(block, pa + i as u64 * child_size, level + 1);
+        }
+        // Break-before-make: the block must be gone from every TLB before
+        // the table takes its place, or the two may be cached at once.
+        *entry = 0;
+        publish();
+        self.flush_tlb(ipa);
+        *entry = make_table(sub);
+        publish();
+        sub
+    }
+
+    // Leaf descriptor mapping `ipa` and its level.
+    fn find_leaf(&self, ipa: u64) -> Option<(u64, usize)> {
+        if ipa >> self.geometry.ipa_bits != 0 {
+            return None;
+        }
+        let mut table = self.root.as_ptr();
+        for level in self.geometry.root_level..=LAST_LEVEL {
+            let desc = unsafe { *self.entry(table, ipa, level) };
+            match classify(desc, level) {
+                Entry::Invalid => return None,
+                Entry::Table(sub) => table = sub,
+                Entry::Leaf => return Some((desc, level)),
+            }
+        }
+        None
+    }
+
+    /// Output address `ipa` translates to and the size of the entry mapping
+    /// it.
+    pub fn lookup(&self, ipa: u64) -> Option<(u64, u64)> {
+        let (desc, level) = self.find_leaf(ipa)?;
+        let size = block_size(level);
+        Some((output_addr(desc) + (ipa & (size - 1)), size))
+    }
+
+    /// Memory type and permissions `ipa` is mapped with.
+    pub fn attrs(&self, ipa: u64) -> Option<(MemType, S2Perms)> {
+        let (desc, _) = self.find_leaf(ipa)?;
+        Some((leaf_mem(desc), leaf_perms(desc)))
+    }
+
+    // Visit every leaf in IPA order as (ipa, descriptor, level).
+    fn walk(&self, f: &mut dyn FnMut(u64, u64, usize)) {
+        fn walk_in(
+            table: *const Table,
+            entries: usize,
+            level: usize,
+            base: u64,
+            f: &mut dyn FnMut(u64, u64, usize),
+        ) {
+            let descs = unsafe { core::slice::from_raw_parts(table as *const u64, entries) };
+            for (i, &desc) in descs.iter().enumerate() {
+                let ipa = base + ((i as u64) << level_shift(level));
+                match classify(desc, level) {
+                    Entry::Invalid => {}
+                    Entry::Table(sub) => walk_in(sub, ENTRIES, level + 1, ipa, f),
+                    Entry::Leaf => f(ipa, desc, level),
+                }
+            }
+        }
+        let g = self.geometry;
+        let entries = ((1 << g.ipa_bits) / block_size(g.root_level)) as usize;
+        walk_in(self.root.as_ptr(), entries, g.root_level, 0, f);
+    }
+
+    /// Write one line per run of leaves that continue each other in both
+    /// IPA and output address with the same block size and attributes.
+    /// Leaves repeating one output address, as `map_repeated` makes, form
+    /// runs too, marked "shared".
+    pub fn dump(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        // (ipa, pa, block size, count, attributes, pa stride)
+        let mut run: Option<(u64, u64, u64, u64, u64, u64)> = None;
+        let mut result = Ok(());
+        let emit =
+            |w: &mut dyn fmt::Write,
+             (ipa, pa, size, count, attrs, stride): (u64, u64, u64, u64, u64, u64)| {
+                let perms = leaf_perms(attrs);
+                write!(
+                    w,
+                    "{:#012x}-{:#012x} {:#012x} {} x{} {} {}{}{}{}\r\n",
+                    ipa,
+                    ipa + size * count,
+                    pa,
+                    match size {
+                        0x4000_0000 => "1G",
+                        0x20_0000 => "2M",
+                        _ => "4K",
+                    },
+                    count,
+                    match leaf_mem(attrs) {
+                        MemType::Normal => "normal",
+                        MemType::Device => "device",
+                    },
+                    if perms.read { 'r' } else { '-' },
+                    if perms.write { 'w' } else { '-' },
+                    if perms.exec { 'x' } else { '-' },
+                    if stride == 0 { " shared" } else { "" },
+                )
+            };
+        write!(w, "ipa_start-ipa_end pa block count type perms\r\n")?;
+        self.walk(&mut |ipa, desc, level| {
+            let size = block_size(level);
+            let attrs = Descriptor::new(desc);
+            attrs.modify(S2_DESCRIPTOR::OUTPUT_ADDR.val(0) + S2_DESCRIPTOR::TYPE::Block);
+            let attrs = attrs.get();
+            let pa = output_addr(desc);
+            if let Some((r_ipa, r_pa, r_size, count, r_attrs, stride)) = run.as_mut() {
+                if *r_size == size && *r_attrs == attrs && *r_ipa + size * *count == ipa {
+                    // The second leaf tells a repeated page from a run.
+                    if *count == 1 && *r_pa == pa {
+                        *stride = 0;
+                    }
+                    if *r_pa + *stride * *count == pa {
+                        *count += 1;
+                        return;
+                    }
+                }
+            }
+            if let Some(prev) = run.replace((ipa, pa, size, 1, attrs, size)) {
+                result = result.and(emit(w, prev));
+            }
+        });
+        if let Some(last) = run {
+            result = result.and(emit(w, last));
+        }
+        for &(ipa, size) in self.guards.iter() {
+            result = result.and(write!(w, "{:#012x}-{:#012x} guard\r\n", ipa, ipa + size));
+        }
+        result
+    }
+
+    fn flush_tlb(&self, ipa: u64) {
+        hvc_call(HVC_S2_TLB_FLUSH, self.vttbr(), ipa);
+    }
+}
+
+impl Drop for Stage2 {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.root.as_ptr() as *mut u8, self.geometry.root_layout()) };
+    }
+}
+
+/// Drop every Stage-2 TLB entry tagged with the VMID of `vttbr`.
+pub(crate) fn flush_all(vttbr: u64) {
+    hvc_call(HVC_S2_TLB_FLUSH, vttbr, FLUSH_ALL);
+}
+
+/// EL2 side of `HVC_S2_TLB_FLUSH`.
+///
+/// # Safety
+/// Must run at EL2 on behalf of the host, with no guest on this core.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn flush_tlb_el2(vttbr: u64, ipa: u64) {
+    let saved = read_sysreg!("vttbr_el2");
+    write_sysreg!("vttbr_el2", vttbr);
+    asm!("isb", options(nostack));
+    if ipa == FLUSH_ALL {
+        asm!("tlbi vmalls12e1is", options(nostack));
+    } else {
+        // Combined stage 1+2 entries may still hold the old block.
+        asm!(
+            "tlbi ipas2e1is, {}",
+            "dsb ish",
+            "tlbi vmalle1is",
+            in(reg) ipa >> 12,
+            options(nostack)
+        );
+    }
+    asm!("dsb ish", options(nostack));
+    write_sysreg!("vttbr_el2", saved);
+    asm!("isb", options(nostack));
+}
+
+static VM_STAGE2: SpinLock<BTreeMap<usize, Stage2>> = SpinLock::new(BTreeMap::new());
+
+/// Make `s2` the Stage-2 tables of `vm_id`, returning the ones it replaces.
+pub fn install(vm_id: usize, s2: Stage2) -> Option<Stage2> {
+    let old = VM_STAGE2.irqsave_lock().insert(vm_id, s2);
+    #[cfg(procfs)]
+    if old.is_none() {
+        let _ = crate::vfs::trace_vm_create(vm_id);
+    }
+    old
+}
+
+/// Make `s2` the Stage-2 tables of `vm_id` unless it already has some.
+pub fn try_install(vm_id: usize, s2: Stage2) -> Result<(), Stage2> {
+    let mut vms = VM_STAGE2.irqsave_lock();
+    if vms.contains_key(&vm_id) {
+        return Err(s2);
+    }
+    vms.insert(vm_id, s2);
+    drop(vms);
+    #[cfg(procfs)]
+    let _ = crate::vfs::trace_vm_create(vm_id);
+    Ok(())
+}
+
+/// Remove the tables of `vm_id`, which ends the VM: its identity and
+/// placement go too.
+pub fn remove(vm_id: usize) -> Option<Stage2> {
+    let old = VM_STAGE2.irqsave_lock().remove(&vm_id);
+    if old.is_some() {
+        #[cfg(procfs)]
+        let _ = crate::vfs::trace_vm_destroy(vm_id);
+        super::identity::release(vm_id);
+        super::placement::release(vm_id);
+    }
+    old
+}
+
+pub fn with_vm<R>(vm_id: usize, f: impl FnOnce(&mut Stage2) -> R) -> Option<R> {
+    VM_STAGE2.irqsave_lock().get_mut(&vm_id).map(f)
+}
+
+/// VTTBR_EL2 of `vm_id`, or `None` if it has no tables and its guest runs
+/// untranslated.
+pub fn vttbr_of(vm_id: usize) -> Option<u64> {
+    VM_STAGE2.irqsave_read().get(&vm_id).map(Stage2::vttbr)
+}
+
+/// Ids of all VMs with Stage-2 tables.
+pub fn vm_ids() -> Vec<usize> {
+    VM_STAGE2.irqsave_lock().keys().copied().collect()
+}
+
+/// Dump the Stage-2 map of `vm_id`, see `Stage2::dump`.
+pub fn dump(vm_id: usize, w: &mut dyn fmt::Write) -> fmt::Result {
+    with_vm(vm_id, |s2| s2.dump(w)).unwrap_or(Err(fmt::Error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    const MB: u64 = 1 << 20;
+    const GB: u64 = 1 << 30;
+
+    #[test]
+    fn test_map_prefers_blocks() {
+        let mut s2 = Stage2::new();
+        s2.map(GB, 0x4000_0000, GB, MemType::Normal, S2Perms::RWX)
+            .unwrap();
+        assert_eq!(s2.lookup(GB + 0x1234), Some((0x4000_1234, GB)));
+        // 2MB aligned but shorter than 1GB.
+        s2.map(4 * GB, 0x8000_0000, 4 * MB, MemType::Normal, S2Perms::RW)
+            .unwrap();
+        assert_eq!(s2.lookup(4 * GB + 3 * MB), Some((0x8030_0000, 2 * MB)));
+        // Output not 2MB aligned, so pages only.
+        s2.map(8 * GB, 0x9000_1000, 2 * MB, MemType::Device, S2Perms::RW)
+            .unwrap();
+        assert_eq!(s2.lookup(8 * GB), Some((0x9000_1000, PAGE_SIZE)));
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let mut s2 = Stage2::new();
+        assert_eq!(
+            s2.map(0x10, 0, PAGE_SIZE, MemType::Normal, S2Perms::RW),
+            Err(Stage2Error::Misaligned)
+        );
+        assert_eq!(
+            s2.map(1 << IPA_BITS, 0, PAGE_SIZE, MemType::Normal, S2Perms::RW),
+            Err(Stage2Error::OutOfRange)
+        );
+        s2.map(0, 0, 2 * MB, MemType::Normal, S2Perms::RW).unwrap();
+        assert_eq!(
+            s2.map(MB, 0, PAGE_SIZE, MemType::Normal, S2Perms::RW),
+            Err(Stage2Error::AlreadyMapped)
+        );
+    }
+
+    #[test]
+    fn test_ipa_geometry() {
+        assert_eq!(Geometry::of(MIN_IPA_BITS - 1), None);
+        assert_eq!(Geometry::of(MAX_IPA_BITS + 1), None);
+        let shape = |bits| Geometry::of(bits).map(|g| (g.root_level, g.root_tables));
+        assert_eq!(shape(32), Some((2, 4)));
+        assert_eq!(shape(36), Some((1, 1)));
+        assert_eq!(shape(39), Some((1, 1)));
+        assert_eq!(shape(40), Some((1, 2)));
+        assert_eq!(shape(44), Some((0, 1)));
+        assert_eq!(shape(48), Some((0, 1)));
+        // T0SZ 24, SL0 level 1, PS 40 bits.
+        let vtcr = Geometry::of(40).unwrap().vtcr(2);
+        assert_eq!(vtcr & 0x3f, 24);
+        assert_eq!((vtcr >> 6) & 0b11, 1);
+        assert_eq!((vtcr >> 16) & 0b111, 2);
+        assert_eq!((Geometry::of(48).unwrap().vtcr(6) >> 16) & 0b111, 5);
+        assert_eq!(pa_range_bits(0x1122_0002), 40);
+        assert_eq!(pa_range_bits(0xf), MIN_IPA_BITS);
+    }
+
+    #[test]
+    fn test_small_ipa_space() {
+        let mut s2 = Stage2::with_ipa_bits(32).unwrap();
+        assert_eq!(s2.vttbr() % (4 * PAGE_SIZE), 0);
+        // The last of the four concatenated root tables.
+        s2.map(3 * GB, 0x8000_0000, 4 * MB, MemType::Normal, S2Perms::RW)
+            .unwrap();
+        assert_eq!(s2.lookup(3 * GB + MB), Some((0x8010_0000, 2 * MB)));
+        assert_eq!(
+            s2.map(4 * GB, 0, PAGE_SIZE, MemType::Normal, S2Perms::RW),
+            Err(Stage2Error::OutOfRange)
+        );
+        assert_eq!(s2.lookup(4 * GB + MB), None);
+        assert!(matches!(
+            Stage2::with_ipa_bits(MAX_IPA_BITS + 1),
+            Err(Stage2Error::BadIpaSize)
+        ));
+    }
+
+    #[test]
+    fn test_unmap_splits_block() {
+        let mut s2 = Stage2::new();
+        s2.map(0, 0x4000_0000, 2 * MB, MemType::Normal, S2Perms::RWX)
+            .unwrap();
+        s2.unmap(MB, PAGE_SIZE).unwrap();
+        assert_eq!(s2.lookup(MB), None);
+        assert_eq!(s2.lookup(MB - PAGE_SIZE), Some((0x400f_f000, PAGE_SIZE)));
+        assert_eq!(s2.lookup(MB + PAGE_SIZE), Some((0x4010_1000, PAGE_SIZE)));
+        // The hole can be mapped again.
+        s2.map(MB, 0x5000_0000, PAGE_SIZE, MemType::Normal, S2Perms::RW)
+            .unwrap();
+        assert_eq!(s2.lookup(MB), Some((0x5000_0000, PAGE_SIZE)));
+    }
+
+    #[test]
+    fn test_guard_stays_unmapped() {
+        let mut s2 = Stage2::new();
+        s2.map(0, 0x4000_0000, 2 * MB, MemType::Normal, S2Perms::RW)
+            .unwrap();
+        s2.add_guard(MB, PAGE_SIZE).unwrap();
+        assert_eq!(s2.lookup(MB), None);
+        assert!(s2.is_guard(MB + 0x10));
+        assert!(!s2.is_guard(MB + PAGE_SIZE));
+        assert_eq!(
+            s2.map(MB, 0x5000_0000, PAGE_SIZE, MemType::Normal, S2Perms::RW),
+            Err(Stage2Error::Guarded)
+        );
+    }
+
+    #[test]
+    fn test_dump_merges_runs() {
+        let mut s2 = Stage2::new();
+        s2.map(0, 0x4000_0000, 4 * MB, MemType::Normal, S2Perms::RWX)
+            .unwrap();
+        s2.map(GB, 0x0900_0000, 2 * PAGE_SIZE, MemType::Device, S2Perms::RW)
+            .unwrap();
+        let mut out = alloc::string::String::new();
+        s2.dump(&mut out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "0x0000000000-0x0000400000 0x0040000000 2M x2 normal rwx"
+        );
+        assert_eq!(
+            lines[2],
+            "0x0040000000-0x0040002000 0x0009000000 4K x2 device rw-"
+        );
+    }
+
+    #[test]
+    fn test_map_repeated() {
+        let mut s2 = Stage2::new();
+        s2.map_repeated(2 * GB, 0x7000_0000, 3 * MB, MemType::Normal, S2Perms::RO)
+            .unwrap();
+        assert_eq!(s2.lookup(2 * GB), Some((0x7000_0000, PAGE_SIZE)));
+        assert_eq!(
+            s2.lookup(2 * GB + 2 * MB + 8),
+            Some((0x7000_0008, PAGE_SIZE))
+        );
+        let mut out = alloc::string::String::new();
+        s2.dump(&mut out).unwrap();
+        assert_eq!(
+            out.lines().nth(1),
+            Some("0x0080000000-0x0080300000 0x0070000000 4K x768 normal r-- shared")
+        );
+    }
+}
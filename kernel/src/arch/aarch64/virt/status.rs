@@ -0,0 +1,148 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How VMs ended. A guest shutting down with `GUEST_HVC_EXIT` reports a
+//! 32-bit status, by convention `SUCCESS` or a failure code of its own,
+//! like a process exit status. PSCI SYSTEM_OFF, the shutdown hypercall and
+//! the syscon power-off report `SUCCESS`. A VM a trap ended is recorded as
+//! `VmExit::Fault`.
+//!
+//! The first vCPU to stop gives the VM its status, which stays after the
+//! VM is gone until a VM with its id gets its first vCPU. The host reads it
+//! with `of` or from /proc/hypervisor/vmN/status. A run-state hook is told
+//! when a VM ended: by the time a vCPU of it moves to `RunState::Stopped`,
+//! `of` has how. The status of a selftest guest ends the run that way, see
+//! `set_selftest`.
+
+use super::{abi, exit::ExitCode, identity, qemu};
+use crate::{
+    sync::SpinLock,
+    virt::run_state::{self, RunState},
+};
+use alloc::collections::BTreeMap;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const SUCCESS: u32 = abi::STATUS_SUCCESS;
+
+const NO_VM: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmExit {
+    /// The guest shut down and reported this status.
+    Status(u32),
+    /// A trap EL2 couldn't handle ended the VM.
+    Fault,
+}
+
+impl VmExit {
+    /// How the VM ended if its vCPU left the run loop with `code`.
+    pub fn of_code(code: ExitCode) -> Option<Self> {
+        match code {
+            ExitCode::Shutdown(status) => Some(Self::Status(status)),
+            ExitCode::Fault => Some(Self::Fault),
+            _ => None,
+        }
+    }
+
+    pub fn passed(self) -> bool {
+        self == Self::Status(SUCCESS)
+    }
+}
+
+impl fmt::Display for VmExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "exit {}", status),
+            Self::Fault => write!(f, "fault"),
+        }
+    }
+}
+
+static STATUS: SpinLock<BTreeMap<usize, VmExit>> = SpinLock::new(BTreeMap::new());
+static SELFTEST: AtomicUsize = AtomicUsize::new(NO_VM);
+
+/// The `Services` init call: hook the end of selftest guests.
+pub(crate) fn init() -> Result<(), &'static str> {
+    run_state::register_hook(end_selftest)
+        .map(|_| ())
+        .map_err(|_| "no run state hook free")
+}
+
+/// End the run, under QEMU, with the result of VM `vm_id` once it ends:
+/// passed if its status is `SUCCESS`. `None` stops doing so.
+pub fn set_selftest(vm_id: Option<usize>) {
+    SELFTEST.store(vm_id.unwrap_or(NO_VM), Ordering::Release);
+}
+
+fn end_selftest(vm_id: usize, _vcpu_id: usize, _from: RunState, to: RunState) {
+    if to != RunState::Stopped || SELFTEST.load(Ordering::Acquire) != vm_id {
+        return;
+    }
+    if let Some(exit) = of(vm_id) {
+        qemu::test_exit(exit.passed());
+    }
+}
+
+/// How VM `vm_id` ended, or `None` while it hasn't.
+pub fn of(vm_id: usize) -> Option<VmExit> {
+    STATUS.irqsave_lock().get(&vm_id).copied()
+}
+
+/// Give VM `vm_id` its status unless it has one already.
+pub(crate) fn record(vm_id: usize, exit: VmExit) {
+    {
+        let mut status = STATUS.irqsave_lock();
+        if status.contains_key(&vm_id) {
+            return;
+        }
+        status.insert(vm_id, exit);
+    }
+    log::info!("[virt] {} ended: {}", identity::tag(vm_id), exit);
+}
+
+/// Forget how the last VM with id `vm_id` ended.
+pub(crate) fn clear(vm_id: usize) {
+    STATUS.irqsave_lock().remove(&vm_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_vm_exit_status() {
+        assert_eq!(
+            VmExit::of_code(ExitCode::Shutdown(3)),
+            Some(VmExit::Status(3))
+        );
+        assert_eq!(VmExit::of_code(ExitCode::Fault), Some(VmExit::Fault));
+        assert_eq!(VmExit::of_code(ExitCode::Reset), None);
+        assert!(VmExit::Status(SUCCESS).passed());
+        assert!(!VmExit::Status(1).passed());
+        assert!(!VmExit::Fault.passed());
+
+        let vm_id = test_vm_id();
+        record(vm_id, VmExit::Status(7));
+        // The first vCPU to stop decides.
+        record(vm_id, VmExit::Fault);
+        assert_eq!(of(vm_id), Some(VmExit::Status(7)));
+        clear(vm_id);
+        assert_eq!(of(vm_id), None);
+    }
+}
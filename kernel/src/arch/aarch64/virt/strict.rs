@@ -0,0 +1,181 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Strict mode for hypervisor bring-up. A VM with `VmConfig::strict` set
+//! doesn't log and carry on when its guest does something the hypervisor
+//! doesn't expect: an exit that would fault the VM, a system register
+//! trap without a handler, an unknown hypercall, a dropped virtual
+//! interrupt, a list register in a state the guest can't have left it in,
+//! a failed exit check or an exit over its latency budget all
+//! dump the vCPU and panic the host. EL2 can't panic, so it exits with
+//! `ExitCode::Anomaly` and the vCPU's run loop does. `CONFIG_VIRT_STRICT`
+//! makes it the default of every profile.
+
+use super::{
+    exit::{ExitAction, ExitCode, ExitReason, EC_SYSREG},
+    identity,
+    vcpu::Vcpu,
+};
+
+/// Whether profiles start VMs in strict mode.
+pub const DEFAULT: bool = cfg!(virt_strict);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Anomaly {
+    /// The exit would have stopped the VM as faulted.
+    Fault = 1,
+    /// A system register trap nothing handles.
+    Sysreg = 2,
+    /// An exception class nothing handles.
+    UnknownExit = 3,
+    /// A guest hypercall with an unknown immediate.
+    UnknownHvc = 4,
+    /// The VGIC dropped an interrupt its pending queue had no room for.
+    VgicOverflow = 5,
+    /// Guest state captured on exit failed `sanity::check_exit`.
+    ExitCheck = 6,
+    /// An exit handler ran over the budget of its class, see `budget`.
+    ExitBudget = 7,
+    /// A list register held something the guest can't have left in it,
+    /// see `Vgic::sync`.
+    LrMismatch = 8,
+}
+
+impl Anomaly {
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            1 => Self::Fault,
+            2 => Self::Sysreg,
+            3 => Self::UnknownExit,
+            4 => Self::UnknownHvc,
+            5 => Self::VgicOverflow,
+            6 => Self::ExitCheck,
+            7 => Self::ExitBudget,
+            8 => Self::LrMismatch,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fault => "fault",
+            Self::Sysreg => "unhandled sysreg trap",
+            Self::UnknownExit => "unhandled exit",
+            Self::UnknownHvc => "unknown hypercall",
+            Self::VgicOverflow => "vgic pending queue overflow",
+            Self::ExitCheck => "exit check failed",
+            Self::ExitBudget => "exit over budget",
+            Self::LrMismatch => "vgic list register mismatch",
+        }
+    }
+}
+
+/// The action to take on an exit of a strict vCPU handled as `action`:
+/// exits that would fault the VM become anomalies. Runs at EL2.
+pub fn check_exit(reason: ExitReason, action: ExitAction) -> ExitAction {
+    if action != ExitAction::Exit(ExitCode::Fault) {
+        return action;
+    }
+    let anomaly = match reason {
+        ExitReason::Unknown(EC_SYSREG) => Anomaly::Sysreg,
+        ExitReason::Unknown(_) => Anomaly::UnknownExit,
+        _ => Anomaly::Fault,
+    };
+    ExitAction::Exit(ExitCode::Anomaly(anomaly as u32))
+}
+
+/// Anomaly of a strict vCPU noticed outside of its exits.
+pub fn check_vcpu(vcpu: &Vcpu) -> Option<Anomaly> {
+    if !vcpu.config.strict {
+        return None;
+    }
+    if vcpu.vgic.dropped() != 0 {
+        Some(Anomaly::VgicOverflow)
+    } else if vcpu.vgic.lr_mismatches != 0 {
+        Some(Anomaly::LrMismatch)
+    } else {
+        None
+    }
+}
+
+/// Dump `vcpu` and panic the host. Called by the vCPU's run loop.
+pub fn fail(vcpu: &Vcpu, anomaly: Option<Anomaly>) -> ! {
+    let name = anomaly.map_or("unknown anomaly", Anomaly::name);
+    let regs = &vcpu.regs;
+    crate::kearly_println!(
+        "---- strict: {} vcpu {}: {} ----",
+        identity::tag(vcpu.vm_id),
+        vcpu.id,
+        name
+    );
+    crate::kearly_println!(
+        "pc {:#018x} spsr {:#x} sp_el1 {:#018x} vbar_el1 {:#018x}",
+        regs.elr,
+        regs.spsr,
+        regs.sp_el1,
+        regs.el1.vbar_el1
+    );
+    for (n, pair) in regs.x.chunks(2).enumerate() {
+        match pair {
+            [a, b] => {
+                crate::kearly_println!("x{:<2} {:#018x} x{:<2} {:#018x}", 2 * n, a, 2 * n + 1, b)
+            }
+            [a] => crate::kearly_println!("x{:<2} {:#018x}", 2 * n, a),
+            _ => {}
+        }
+    }
+    crate::kearly_println!(
+        "esr {:#x} last exit {:?} exits {} vgic dropped {} lr mismatches {}",
+        vcpu.exit_esr,
+        vcpu.last_exit,
+        vcpu.stats.exits,
+        vcpu.vgic.dropped(),
+        vcpu.vgic.lr_mismatches
+    );
+    panic!(
+        "strict mode: {} vcpu {}: {} at pc {:#x}",
+        identity::tag(vcpu.vm_id),
+        vcpu.id,
+        name,
+        regs.elr
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_strict_check_exit() {
+        let fault = ExitAction::Exit(ExitCode::Fault);
+        assert_eq!(
+            check_exit(ExitReason::Unknown(EC_SYSREG), fault),
+            ExitAction::Exit(ExitCode::Anomaly(Anomaly::Sysreg as u32))
+        );
+        assert_eq!(
+            check_exit(ExitReason::Unknown(0x3f), fault),
+            ExitAction::Exit(ExitCode::Anomaly(Anomaly::UnknownExit as u32))
+        );
+        assert_eq!(
+            check_exit(ExitReason::Wfx, ExitAction::Exit(ExitCode::Wfi)),
+            ExitAction::Exit(ExitCode::Wfi)
+        );
+        for raw in 1..=8 {
+            assert_eq!(Anomaly::from_raw(raw).map(|a| a as u32), Some(raw));
+        }
+        assert_eq!(Anomaly::from_raw(0), None);
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated system controller for guests that reboot or power off by
+//! writing a magic value to a register instead of calling PSCI, like
+//! Linux's `syscon-reboot` and `syscon-poweroff` drivers do. A matching
+//! write ends the guest's run: power off stops the VM, reboot resets it
+//! through `vcpu::reset_vm`. The registers are write-only and read as 0.
+
+use super::{
+    exit::ExitCode,
+    identity,
+    mmio::{self, BackendError, MmioDevice, MmioError},
+    status,
+    vlog::vlog,
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc};
+
+pub const REGION_SIZE: u64 = 0x1000;
+
+/// Register write the controller acts on, as the `offset`, `mask` and
+/// `value` properties of a `syscon-reboot` node describe it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub offset: u64,
+    pub mask: u32,
+    pub value: u32,
+}
+
+impl Command {
+    fn matches(&self, offset: u64, value: u32) -> bool {
+        offset == self.offset && value & self.mask == self.value & self.mask
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysconConfig {
+    pub poweroff: Option<Command>,
+    pub reboot: Option<Command>,
+}
+
+impl Default for SysconConfig {
+    /// The values of the SiFive test finisher, which QEMU boards use.
+    fn default() -> Self {
+        Self {
+            poweroff: Some(Command {
+                offset: 0,
+                mask: 0xffff,
+                value: 0x5555,
+            }),
+            reboot: Some(Command {
+                offset: 0,
+                mask: 0xffff,
+                value: 0x7777,
+            }),
+        }
+    }
+}
+
+pub struct Syscon {
+    vm_id: usize,
+    config: SysconConfig,
+}
+
+impl Syscon {
+    pub fn new(vm_id: usize, config: SysconConfig) -> Self {
+        Self { vm_id, config }
+    }
+}
+
+impl MmioDevice for Syscon {
+    fn read(&self, _offset: u64, _size: u8) -> Result<u64, BackendError> {
+        Ok(0)
+    }
+
+    fn write(&self, offset: u64, size: u8, value: u64) -> Result<(), BackendError> {
+        self.write_exit(offset, size, value).map(|_| ())
+    }
+
+    fn write_exit(
+        &self,
+        offset: u64,
+        _size: u8,
+        value: u64,
+    ) -> Result<Option<ExitCode>, BackendError> {
+        let value = value as u32;
+        let code = if self
+            .config
+            .poweroff
+            .is_some_and(|c| c.matches(offset, value))
+        {
+            ExitCode::Shutdown(status::SUCCESS)
+        } else if self.config.reboot.is_some_and(|c| c.matches(offset, value)) {
+            ExitCode::Reset
+        } else {
+            return Ok(None);
+        };
+        vlog!(
+            Mmio,
+            Info,
+            "[EL2] {} syscon {:?}",
+            identity::tag(self.vm_id),
+            code
+        );
+        Ok(Some(code))
+    }
+}
+
+// IPA of each VM's controller.
+static SYSCONS: SpinLock<BTreeMap<usize, u64>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` a system controller at IPA `base`.
+pub fn create(vm_id: usize, base: u64, config: SysconConfig) -> Result<(), MmioError> {
+    mmio::register(
+        vm_id,
+        base,
+        REGION_SIZE,
+        Arc::new(Syscon::new(vm_id, config)),
+    )?;
+    SYSCONS.irqsave_lock().insert(vm_id, base);
+    Ok(())
+}
+
+/// Remove the controller of VM `vm_id` from the guest's address space.
+pub fn destroy(vm_id: usize) {
+    if let Some(base) = SYSCONS.irqsave_lock().remove(&vm_id) {
+        mmio::unregister(vm_id, base);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_syscon_commands() {
+        let syscon = Syscon::new(usize::MAX, SysconConfig::default());
+        assert_eq!(
+            syscon.write_exit(0, 4, 0x5555),
+            Ok(Some(ExitCode::Shutdown(0)))
+        );
+        assert_eq!(syscon.write_exit(0, 4, 0x1_7777), Ok(Some(ExitCode::Reset)));
+        assert_eq!(syscon.write_exit(0, 4, 0x3333), Ok(None));
+        assert_eq!(syscon.write_exit(4, 4, 0x5555), Ok(None));
+        assert_eq!(syscon.read(0, 4), Ok(0));
+
+        let reboot_only = SysconConfig {
+            poweroff: None,
+            reboot: Some(Command {
+                offset: 0x10,
+                mask: 0x1,
+                value: 0x1,
+            }),
+        };
+        let syscon = Syscon::new(usize::MAX, reboot_only);
+        assert_eq!(syscon.write_exit(0, 4, 0x5555), Ok(None));
+        assert_eq!(syscon.write_exit(0x10, 4, 0xff), Ok(Some(ExitCode::Reset)));
+    }
+}
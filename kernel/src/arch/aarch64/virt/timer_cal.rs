@@ -0,0 +1,215 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Virtual timer delivery accuracy. A cooperating test guest arms its
+//! virtual timer, reads CNTVCT_EL0 first thing in the handler of the timer
+//! PPI and reports both through `GUEST_HVC_TIMER_SAMPLE`; the difference
+//! is how late the guest saw the deadline. `calibrate` turns the recent
+//! samples into an offset that `compensate` subtracts from a vCPU's timer
+//! deadline when its host thread waits for it in WFI, and `selftest` fails
+//! when the spread is over a bound.
+//!
+//! The offset is the smallest latency seen, not the median: a timer armed
+//! early by it still never fires before its deadline. All values are in
+//! counter cycles; the guest virtual counter ticks at CNTFRQ like the
+//! physical one, so the offset applies to either.
+
+use super::ring::Ring;
+use crate::sync::SpinLock;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Samples kept before the oldest is dropped.
+pub const MAX_SAMPLES: usize = 64;
+/// Samples `calibrate` wants before it trusts them.
+pub const MIN_SAMPLES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalError {
+    /// Not enough samples reported yet; payload is how many there are.
+    TooFewSamples(usize),
+    /// The 99th percentile latency is over the bound the selftest was given.
+    OverBound { p99: u64, bound: u64 },
+}
+
+/// Latency of the samples a calibration was made from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    pub samples: usize,
+    pub min: u64,
+    pub p50: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Calibration {
+    /// Summary of `samples`, which get sorted. `None` if there are none.
+    pub fn of(samples: &mut [u64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let rank = |pct: usize| samples[(samples.len() * pct).div_ceil(100).max(1) - 1];
+        Some(Self {
+            samples: samples.len(),
+            min: samples[0],
+            p50: rank(50),
+            p99: rank(99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+struct Samples {
+    latencies: Ring<u64, MAX_SAMPLES>,
+    /// Reports whose handler ran before the deadline, a guest bug.
+    rejected: u64,
+}
+
+// Taken at EL2 by the guest hypercall, so nothing is allocated under it.
+static SAMPLES: SpinLock<Samples> = SpinLock::new(Samples {
+    latencies: Ring::new(),
+    rejected: 0,
+});
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Take one report of a guest whose timer armed at `deadline` was seen at
+/// `observed`, both guest virtual counts. Runs at EL2 for the guest's HVC
+/// and returns the offset in use, so the guest can compensate its own
+/// deadlines too.
+pub fn record_sample(deadline: u64, observed: u64) -> u64 {
+    let mut samples = SAMPLES.irqsave_lock();
+    match observed.checked_sub(deadline) {
+        Some(latency) => {
+            if samples.latencies.is_full() {
+                samples.latencies.pop_front();
+            }
+            let _ = samples.latencies.push_back(latency);
+        }
+        None => samples.rejected += 1,
+    }
+    offset()
+}
+
+/// Summary of the samples kept, without changing the offset.
+pub fn measure() -> Option<Calibration> {
+    let mut copy = [0; MAX_SAMPLES];
+    let n = {
+        let samples = SAMPLES.irqsave_lock();
+        for (slot, &latency) in copy.iter_mut().zip(samples.latencies.iter()) {
+            *slot = latency;
+        }
+        samples.latencies.len()
+    };
+    Calibration::of(&mut copy[..n])
+}
+
+/// Reports whose handler ran before the deadline.
+pub fn rejected() -> u64 {
+    SAMPLES.irqsave_lock().rejected
+}
+
+/// Set the offset from the samples kept.
+pub fn calibrate() -> Result<Calibration, CalError> {
+    let cal = measure().unwrap_or_default();
+    if cal.samples < MIN_SAMPLES {
+        return Err(CalError::TooFewSamples(cal.samples));
+    }
+    set_offset(cal.min);
+    Ok(cal)
+}
+
+/// Calibrate, then check that injection is no later than `bound` cycles
+/// for 99% of the samples.
+pub fn selftest(bound: u64) -> Result<Calibration, CalError> {
+    let cal = calibrate()?;
+    if cal.p99 > bound {
+        return Err(CalError::OverBound {
+            p99: cal.p99,
+            bound,
+        });
+    }
+    Ok(cal)
+}
+
+/// Cycles deadlines are armed early by.
+pub fn offset() -> u64 {
+    OFFSET.load(Ordering::Relaxed)
+}
+
+/// Use a known offset, e.g. one measured on an earlier boot.
+pub fn set_offset(cycles: u64) {
+    OFFSET.store(cycles, Ordering::Relaxed);
+}
+
+/// When to arm a timer for it to be seen at `deadline`.
+pub fn compensate(deadline: u64) -> u64 {
+    deadline.saturating_sub(offset())
+}
+
+/// Drop the samples kept, e.g. before a new calibration run. The offset
+/// stays.
+pub fn reset() {
+    let mut samples = SAMPLES.irqsave_lock();
+    while samples.latencies.pop_front().is_some() {}
+    samples.rejected = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_timer_cal_summary() {
+        assert_eq!(Calibration::of(&mut []), None);
+        let mut samples: [u64; 100] = core::array::from_fn(|n| 100 - n as u64);
+        let cal = Calibration::of(&mut samples).unwrap();
+        assert_eq!(
+            cal,
+            Calibration {
+                samples: 100,
+                min: 1,
+                p50: 50,
+                p99: 99,
+                max: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn test_timer_cal_calibrate() {
+        reset();
+        let saved = offset();
+        assert_eq!(calibrate(), Err(CalError::TooFewSamples(0)));
+        record_sample(1000, 900);
+        assert_eq!(rejected(), 1);
+        for n in 0..MIN_SAMPLES as u64 {
+            record_sample(1000, 1040 + n);
+        }
+        let cal = calibrate().unwrap();
+        assert_eq!((cal.samples, cal.min), (MIN_SAMPLES, 40));
+        assert_eq!(compensate(1000), 960);
+        assert_eq!(compensate(10), 0);
+        assert_eq!(
+            selftest(10),
+            Err(CalError::OverBound {
+                p99: 40 + MIN_SAMPLES as u64 - 1,
+                bound: 10
+            })
+        );
+        assert!(selftest(100).is_ok());
+        reset();
+        set_offset(saved);
+    }
+}
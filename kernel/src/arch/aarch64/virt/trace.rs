@@ -0,0 +1,195 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hypervisor trace buffer: vCPU exits as their run loops see them, merged
+//! with events an instrumented guest emits through `GUEST_HVC_TRACE`. The
+//! guest stamps its events with its virtual counter; they are converted to
+//! the physical counter the exits are stamped with, so one timeline shows
+//! both. Tracing is off until enabled. Like `audit`, events are kept in a
+//! fixed ring, and reading doesn't consume them.
+
+use super::{
+    exit::ExitCode,
+    hyper,
+    identity::{self, Uuid},
+    ring::Ring,
+    vcpu::Vcpu,
+};
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Events kept before the oldest is overwritten.
+pub const TRACE_ENTRIES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// The vCPU came back to its run loop.
+    Exit(ExitCode),
+    /// An event of the guest, with its id and argument.
+    Guest { id: u64, arg: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub seq: u64,
+    /// Physical counter.
+    pub time: u64,
+    pub vm_id: usize,
+    pub uuid: Option<Uuid>,
+    pub vcpu_id: usize,
+    pub kind: TraceKind,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} vm{} ", self.seq, self.time, self.vm_id)?;
+        if let Some(uuid) = self.uuid {
+            write!(f, "{} ", uuid)?;
+        }
+        write!(f, "vcpu:{} ", self.vcpu_id)?;
+        match self.kind {
+            TraceKind::Exit(code) => write!(f, "exit {:?}", code),
+            TraceKind::Guest { id, arg } => write!(f, "guest {id:#x} arg {arg:#x}"),
+        }
+    }
+}
+
+struct TraceLog {
+    events: Ring<TraceEvent, TRACE_ENTRIES>,
+    next_seq: u64,
+}
+
+impl TraceLog {
+    const fn new() -> Self {
+        Self {
+            events: Ring::new(),
+            next_seq: 0,
+        }
+    }
+
+    fn push(&mut self, mut event: TraceEvent) {
+        event.seq = self.next_seq;
+        self.next_seq += 1;
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let _ = self.events.push_back(event);
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Also taken at EL2 by guest hypercalls, so nothing is allocated under it.
+static LOG: SpinLock<TraceLog> = SpinLock::new(TraceLog::new());
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Drop all events kept; sequence numbers go on.
+pub fn clear() {
+    let mut log = LOG.irqsave_lock();
+    while log.events.pop_front().is_some() {}
+}
+
+fn record(time: u64, vm_id: usize, vcpu_id: usize, kind: TraceKind) {
+    let uuid = identity::uuid_of(vm_id);
+    LOG.irqsave_lock().push(TraceEvent {
+        seq: 0,
+        time,
+        vm_id,
+        uuid,
+        vcpu_id,
+        kind,
+    });
+}
+
+/// Trace an exit of `vcpu` that left guest mode at physical count `time`.
+pub fn record_exit(vcpu: &Vcpu, time: u64, code: ExitCode) {
+    if is_enabled() {
+        record(time, vcpu.vm_id, vcpu.id, TraceKind::Exit(code));
+    }
+}
+
+/// Trace guest event `id` of `vcpu`, taken at virtual count `stamp`, or
+/// now if `stamp` is 0. Returns false while tracing is off. Runs at EL2.
+pub fn record_guest(vcpu: &Vcpu, id: u64, arg: u64, stamp: u64) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let time = match stamp {
+        0 => hyper::read_cntpct(),
+        stamp => vcpu.host_cycles(stamp),
+    };
+    record(time, vcpu.vm_id, vcpu.id, TraceKind::Guest { id, arg });
+    true
+}
+
+/// Events kept, in time order. Each vCPU records its own, so the ring
+/// itself is only ordered per vCPU.
+pub fn events() -> Vec<TraceEvent> {
+    let mut events: Vec<_> = LOG.irqsave_lock().events.iter().copied().collect();
+    events.sort_unstable_by_key(|e| (e.time, e.seq));
+    events
+}
+
+/// Events traced since boot, including overwritten ones.
+pub fn total() -> u64 {
+    LOG.irqsave_lock().next_seq
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    fn guest(time: u64, id: u64) -> TraceEvent {
+        TraceEvent {
+            seq: 0,
+            time,
+            vm_id: 2,
+            uuid: None,
+            vcpu_id: 1,
+            kind: TraceKind::Guest { id, arg: 0x10 },
+        }
+    }
+
+    #[test]
+    fn test_trace_ring_keeps_latest() {
+        let mut log = TraceLog::new();
+        for n in 0..TRACE_ENTRIES as u64 + 3 {
+            log.push(guest(n, n));
+        }
+        assert_eq!(log.next_seq, TRACE_ENTRIES as u64 + 3);
+        assert_eq!(log.events.len(), TRACE_ENTRIES);
+        assert_eq!(log.events.iter().next().unwrap().seq, 3);
+    }
+
+    #[test]
+    fn test_trace_event_format() {
+        let mut event = guest(42, 0x7);
+        event.seq = 5;
+        assert_eq!(event.to_string(), "5 42 vm2 vcpu:1 guest 0x7 arg 0x10");
+        event.kind = TraceKind::Exit(ExitCode::Wfi);
+        assert_eq!(event.to_string(), "5 42 vm2 vcpu:1 exit Wfi");
+    }
+}
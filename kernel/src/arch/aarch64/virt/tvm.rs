@@ -0,0 +1,94 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writes to the EL1 MMU controls, trapped while `Traps::tvm` sets
+//! HCR_EL2.TVM. The guest's EL1 registers are live while EL2 handles its
+//! exit, so each write goes straight to the register it names; the trap
+//! only adds the exit, which is what turning it on measures.
+
+use super::{
+    exit::{ExitAction, SysRegAccess},
+    hal::{sysregs, SysReg, SysRegBackend},
+    vcpu::Vcpu,
+};
+
+// (op0, op1, CRn, CRm, op2) of the registers HCR_EL2.TVM traps writes to.
+const TRAPPED: [((u8, u8, u8, u8, u8), SysReg); 11] = [
+    ((3, 0, 1, 0, 0), SysReg::SctlrEl1),
+    ((3, 0, 2, 0, 0), SysReg::Ttbr0El1),
+    ((3, 0, 2, 0, 1), SysReg::Ttbr1El1),
+    ((3, 0, 2, 0, 2), SysReg::TcrEl1),
+    ((3, 0, 5, 1, 0), SysReg::Afsr0El1),
+    ((3, 0, 5, 1, 1), SysReg::Afsr1El1),
+    ((3, 0, 5, 2, 0), SysReg::EsrEl1),
+    ((3, 0, 6, 0, 0), SysReg::FarEl1),
+    ((3, 0, 10, 2, 0), SysReg::MairEl1),
+    ((3, 0, 10, 3, 0), SysReg::AmairEl1),
+    ((3, 0, 13, 0, 1), SysReg::ContextidrEl1),
+];
+
+/// Register a trapped MSR of `access` writes, or `None` if TVM doesn't
+/// trap it.
+pub fn trapped(access: SysRegAccess) -> Option<SysReg> {
+    if access.read {
+        return None;
+    }
+    let encoding = (access.op0, access.op1, access.crn, access.crm, access.op2);
+    TRAPPED
+        .iter()
+        .find(|(e, _)| *e == encoding)
+        .map(|&(_, reg)| reg)
+}
+
+/// Emulate a write trapped by TVM, or `None` if `access` isn't one. Runs
+/// at EL2.
+#[link_section = ".hyp.text"]
+pub fn emulate(vcpu: &mut Vcpu, access: SysRegAccess) -> Option<ExitAction> {
+    let reg = trapped(access)?;
+    let val = match access.rt {
+        31 => 0,
+        rt => vcpu.regs.x[rt as usize],
+    };
+    sysregs().write(reg, val);
+    vcpu.advance_pc();
+    Some(ExitAction::Resume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_tvm_trapped() {
+        let msr = |op0, op1, crn, crm, op2| SysRegAccess {
+            op0,
+            op1,
+            crn,
+            crm,
+            op2,
+            rt: 0,
+            read: false,
+        };
+        assert_eq!(trapped(msr(3, 0, 1, 0, 0)), Some(SysReg::SctlrEl1));
+        assert_eq!(trapped(msr(3, 0, 13, 0, 1)), Some(SysReg::ContextidrEl1));
+        // TPIDR_EL1 isn't an MMU control.
+        assert_eq!(trapped(msr(3, 0, 13, 0, 4)), None);
+        let mrs = SysRegAccess {
+            read: true,
+            ..msr(3, 0, 2, 0, 0)
+        };
+        assert_eq!(trapped(mrs), None);
+    }
+}
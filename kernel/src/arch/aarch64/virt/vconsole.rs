@@ -0,0 +1,343 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host console input for one VM at a time. While a VM is attached, what
+//! arrives on the host UART goes to it instead of the host's readers: the
+//! RX interrupt handler queues the bytes and raises the VM's console SPI,
+//! edge triggered, and the guest takes them with `GUEST_HVC_GETC`. A guest
+//! should read until GETC has nothing left, a byte arriving after that
+//! raises the SPI again. Nothing is polled, so a shell in the guest sees
+//! each key as it is typed.
+//!
+//! A VM with an emulated PL011 can be attached to it instead, see
+//! `attach_uart`: input then goes into the UART's receive FIFO and the
+//! guest reads it through UARTDR.
+//!
+//! Input that arrives while the queue is full is dropped and counted.
+//!
+//! Console output, `GUEST_HVC_PUTC` or the PL011's, goes to the host
+//! console unless the VM's console is captured, see `capture`. Then the
+//! last `OUTPUT_SIZE` bytes are kept for the host to take, e.g. from
+//! /proc/hypervisor/vmN/console or by `console_log`, and the host can
+//! `inject` input without attaching the VM.
+
+use super::{
+    identity,
+    irq_line::{IrqLine, Trigger},
+    pl011::{self, Pl011},
+    ring::Ring,
+    spi::FIRST_SPI,
+    vcpu::vcpu_manager,
+    vgic::MAX_INTID,
+};
+use crate::sync::SpinLock;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Bytes queued for the attached VM.
+pub const RX_SIZE: usize = 256;
+/// Output a captured console keeps before the oldest is dropped.
+pub const OUTPUT_SIZE: usize = 4096;
+
+const NO_VM: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachError {
+    NoSuchVm,
+    BadIntid,
+    /// The VM has no PL011.
+    NoUart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attachment {
+    pub vm_id: usize,
+    pub intid: u32,
+    /// Input the VM hasn't taken yet.
+    pub queued: usize,
+    /// Input dropped since the VM was attached because the queue was full.
+    pub dropped: u64,
+}
+
+struct Console {
+    line: Option<IrqLine>,
+    uart: Option<Arc<Pl011>>,
+    rx: Ring<u8, RX_SIZE>,
+    dropped: u64,
+}
+
+static CONSOLE: SpinLock<Console> = SpinLock::new(Console {
+    line: None,
+    uart: None,
+    rx: Ring::new(),
+    dropped: 0,
+});
+// Read by the RX interrupt handler before it takes the lock.
+static ATTACHED: AtomicUsize = AtomicUsize::new(NO_VM);
+
+struct Captured {
+    output: Ring<u8, OUTPUT_SIZE>,
+    // Also written to the host console.
+    tee: bool,
+    // Input injected by the host, taken with GETC.
+    input: Ring<u8, RX_SIZE>,
+}
+
+// Consoles being captured. Created on the host; EL2 only looks them up.
+static CAPTURED: SpinLock<BTreeMap<usize, Box<Captured>>> = SpinLock::new(BTreeMap::new());
+
+/// Send host console input to VM `vm_id`, raising SPI `intid` when there
+/// is some. Detaches any VM attached before and drops its unread input.
+pub fn attach(vm_id: usize, intid: u32) -> Result<(), AttachError> {
+    if !(FIRST_SPI..MAX_INTID).contains(&intid) {
+        return Err(AttachError::BadIntid);
+    }
+    if vcpu_manager().ids_of(vm_id).next().is_none() {
+        return Err(AttachError::NoSuchVm);
+    }
+    let mut console = CONSOLE.irqsave_lock();
+    console.line = Some(IrqLine::new(vm_id, intid, Trigger::Edge));
+    console.uart = None;
+    console.rx = Ring::new();
+    console.dropped = 0;
+    ATTACHED.store(vm_id, Ordering::Release);
+    log::info!("[virt] console attached to {}", identity::tag(vm_id));
+    Ok(())
+}
+
+/// Send host console input to the PL011 of VM `vm_id`. Detaches any VM
+/// attached before.
+pub fn attach_uart(vm_id: usize) -> Result<(), AttachError> {
+    let uart = pl011::get(vm_id).ok_or(AttachError::NoUart)?;
+    let mut console = CONSOLE.irqsave_lock();
+    console.line = None;
+    console.uart = Some(uart);
+    console.rx = Ring::new();
+    console.dropped = 0;
+    ATTACHED.store(vm_id, Ordering::Release);
+    log::info!(
+        "[virt] console attached to the UART of {}",
+        identity::tag(vm_id)
+    );
+    Ok(())
+}
+
+/// Give host console input back to the host.
+pub fn detach() {
+    let mut console = CONSOLE.irqsave_lock();
+    console.line = None;
+    console.uart = None;
+    console.rx = Ring::new();
+    ATTACHED.store(NO_VM, Ordering::Release);
+}
+
+/// The VM host console input goes to.
+pub fn attached() -> Option<usize> {
+    match ATTACHED.load(Ordering::Acquire) {
+        NO_VM => None,
+        vm_id => Some(vm_id),
+    }
+}
+
+/// The attached VM and how its input fares.
+pub fn attachment() -> Option<Attachment> {
+    let console = CONSOLE.irqsave_read();
+    if let Some(uart) = &console.uart {
+        return Some(Attachment {
+            vm_id: uart.vm_id(),
+            intid: uart.intid(),
+            queued: uart.queued(),
+            dropped: console.dropped,
+        });
+    }
+    console.line.map(|line| Attachment {
+        vm_id: line.vm_id(),
+        intid: line.intid(),
+        queued: console.rx.len(),
+        dropped: console.dropped,
+    })
+}
+
+/// Queue host console input for the attached VM and interrupt it. Called
+/// from the host UART's RX interrupt handler.
+pub fn receive(bytes: &[u8]) {
+    let line = {
+        let mut console = CONSOLE.irqsave_lock();
+        if let Some(uart) = console.uart.clone() {
+            drop(console);
+            receive_uart(&uart, bytes);
+            return;
+        }
+        let Some(line) = console.line else {
+            return;
+        };
+        for &b in bytes {
+            if console.rx.push_back(b).is_err() {
+                console.dropped += 1;
+            }
+        }
+        line
+    };
+    // Raised outside the lock: GETC takes it at EL2 while holding the
+    // vCPU the line goes to.
+    if line.raise().is_err() {
+        // The VM is gone.
+        detach();
+    }
+}
+
+// Like the queue, the UART is fed outside the lock: it drives its line
+// from the host.
+fn receive_uart(uart: &Pl011, bytes: &[u8]) {
+    let dropped = bytes.len() - uart.receive(bytes);
+    if dropped != 0 {
+        CONSOLE.irqsave_lock().dropped += dropped as u64;
+    }
+}
+
+/// The next byte of input for VM `vm_id`: from the host console if it is
+/// attached, then what the host injected.
+pub(crate) fn getc(vm_id: usize) -> Option<u8> {
+    {
+        let mut console = CONSOLE.irqsave_lock();
+        if console.line.map(|line| line.vm_id()) == Some(vm_id) {
+            if let Some(b) = console.rx.pop_front() {
+                return Some(b);
+            }
+        }
+    }
+    CAPTURED
+        .irqsave_lock()
+        .get_mut(&vm_id)
+        .and_then(|c| c.input.pop_front())
+}
+
+/// Keep the console output of VM `vm_id` from now on, writing it to the
+/// host console as well with `tee`. Output kept already stays. It is kept
+/// after the VM is gone, until `release`.
+pub fn capture(vm_id: usize, tee: bool) {
+    // Allocated outside the lock, and dropped outside it if unused.
+    let captured = Box::new(Captured {
+        output: Ring::new(),
+        tee,
+        input: Ring::new(),
+    });
+    let mut consoles = CAPTURED.irqsave_lock();
+    match consoles.get_mut(&vm_id) {
+        Some(c) => c.tee = tee,
+        None => {
+            consoles.insert(vm_id, captured);
+        }
+    }
+}
+
+/// Send the console output of VM `vm_id` to the host console again.
+pub fn release(vm_id: usize) {
+    // Dropped outside the lock.
+    let captured = CAPTURED.irqsave_lock().remove(&vm_id);
+    drop(captured);
+}
+
+pub fn is_captured(vm_id: usize) -> bool {
+    CAPTURED.irqsave_read().contains_key(&vm_id)
+}
+
+/// Console output of VM `vm_id`. Runs at EL2 for `GUEST_HVC_PUTC` and
+/// PL011 writes.
+pub(crate) fn putc(vm_id: usize, b: u8) {
+    if let Some(captured) = CAPTURED.irqsave_lock().get_mut(&vm_id) {
+        if captured.output.is_full() {
+            captured.output.pop_front();
+        }
+        let _ = captured.output.push_back(b);
+        if !captured.tee {
+            return;
+        }
+    }
+    let _ = crate::console::EarlyConsole {}.write_char(b as char);
+}
+
+/// Take the output kept for VM `vm_id`, oldest first. `None` if its
+/// console isn't captured.
+pub fn take_output(vm_id: usize) -> Option<Vec<u8>> {
+    let mut captured = CAPTURED.irqsave_lock();
+    let captured = captured.get_mut(&vm_id)?;
+    let mut output = Vec::with_capacity(captured.output.len());
+    while let Some(b) = captured.output.pop_front() {
+        output.push(b);
+    }
+    Some(output)
+}
+
+/// Give VM `vm_id` console input: into its PL011 if it has one, otherwise
+/// for GETC if its console is captured. Returns how many bytes it took,
+/// or `None` if it has neither.
+pub fn inject(vm_id: usize, bytes: &[u8]) -> Option<usize> {
+    if let Some(uart) = pl011::get(vm_id) {
+        return Some(uart.receive(bytes));
+    }
+    let mut captured = CAPTURED.irqsave_lock();
+    let captured = captured.get_mut(&vm_id)?;
+    Some(
+        bytes
+            .iter()
+            .take_while(|&&b| captured.input.push_back(b).is_ok())
+            .count(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_attach_checks() {
+        assert_eq!(attach(0, 16), Err(AttachError::BadIntid));
+        assert_eq!(attach(0, MAX_INTID), Err(AttachError::BadIntid));
+        assert_eq!(attach(test_vm_id(), 40), Err(AttachError::NoSuchVm));
+        assert_eq!(attach_uart(test_vm_id()), Err(AttachError::NoUart));
+        assert_eq!(attached(), None);
+        assert_eq!(attachment(), None);
+    }
+
+    #[test]
+    fn test_captured_console() {
+        let vm_id = test_vm_id();
+        assert_eq!(take_output(vm_id), None);
+        assert_eq!(inject(vm_id, b"x"), None);
+        capture(vm_id, false);
+        putc(vm_id, b'o');
+        putc(vm_id, b'k');
+        assert_eq!(take_output(vm_id).as_deref(), Some(&b"ok"[..]));
+        assert_eq!(take_output(vm_id).as_deref(), Some(&b""[..]));
+        assert_eq!(inject(vm_id, b"ls"), Some(2));
+        assert_eq!(getc(vm_id), Some(b'l'));
+        assert_eq!(getc(vm_id), Some(b's'));
+        assert_eq!(getc(vm_id), None);
+        release(vm_id);
+        assert!(!is_captured(vm_id));
+    }
+
+    #[test]
+    fn test_detached_input_is_not_queued() {
+        detach();
+        receive(b"ls\n");
+        assert_eq!(getc(0), None);
+    }
+}
@@ -0,0 +1,1653 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    adaptive::ExitStats,
+    alternative::alternative,
+    boost::Boost,
+    cacheid, copy, doorbell, el2_stack,
+    exit::{self, ExitCode},
+    grant,
+    hal::{sysregs, SysReg, SysRegBackend},
+    heartbeat::Heartbeat,
+    host_pm, hyper, identity, isolation,
+    kick::{self, KickReason},
+    lazy_ram,
+    placement::{self, Placement},
+    profile::{BootProtocol, FastPath, StackGuard, Traps, VmConfig},
+    ptimer::{self, PTimer},
+    shadow::ShadowRegs,
+    shim, stage2,
+    status::{self, VmExit},
+    strict::{self, Anomaly},
+    timer_cal, trace,
+    vector::TrapFrame,
+    vgic::Vgic,
+    vlog::vlog,
+    vpsci, vtimer,
+};
+use crate::{
+    arch::aarch64::{
+        current_cpu_id,
+        psci::hvc_call,
+        registers::{cntp_ctl_el0::CNTP_CTL_EL0, hcr_el2::HCR_EL2, midr_el1::MIDR_EL1},
+    },
+    scheduler::{self, InsertToEnd},
+    sync::{
+        event_flags::{EventFlags, EventFlagsMode},
+        SpinLock,
+    },
+    time::{self, Tick},
+    virt::run_state::{self, RunState},
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    arch::asm,
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{fence, AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+use tock_registers::interfaces::{Readable, Writeable};
+
+/// Most vCPUs one VM may have. The guest tells them apart by
+/// MPIDR_EL1.Aff0, and a GICv3 SGI target list covers 16 of those.
+pub const MAX_VCPUS_PER_VM: usize = 16;
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+// Host HVC asking EL2 to enter the vCPU whose id is in x1.
+pub const HVC_VCPU_RUN: u64 = 0x01;
+
+const NOT_RUNNING: usize = usize::MAX;
+// VMPIDR_EL2 bit 31 is RES1.
+const VMPIDR_RES1: u64 = 1 << 31;
+// SCTLR_EL1 bits 29, 28, 23, 22, 20 and 11 are RES1.
+const SCTLR_EL1_RES1: u64 = 0x30d0_0800;
+
+macro_rules! el1_context {
+    ($($field:ident: $reg:ident,)*) => {
+        /// EL1 system registers of a vCPU besides SP_EL1. Host and guest
+        /// both run at EL1 under nVHE, so a world switch swaps all of them.
+        #[repr(C)]
+        #[derive(Debug, Default, Clone, Copy)]
+        pub struct El1Context {
+            $(pub $field: u64,)*
+        }
+
+        impl El1Context {
+            /// Reset state: MMU and caches off, everything else zero.
+            pub const fn new() -> Self {
+                let mut ctx = Self { $($field: 0,)* };
+                ctx.sctlr_el1 = SCTLR_EL1_RES1;
+                ctx
+            }
+
+            /// Store the live registers.
+            #[link_section = ".hyp.text"]
+            pub(crate) fn save(&mut self) {
+                let regs = sysregs();
+                $(self.$field = regs.read(SysReg::$reg);)*
+            }
+
+            /// Load the registers; takes effect after an ISB.
+            #[link_section = ".hyp.text"]
+            pub(crate) fn restore(&self) {
+                let regs = sysregs();
+                $(regs.write(SysReg::$reg, self.$field);)*
+            }
+        }
+    };
+}
+
+el1_context! {
+    sctlr_el1: SctlrEl1,
+    cpacr_el1: CpacrEl1,
+    ttbr0_el1: Ttbr0El1,
+    ttbr1_el1: Ttbr1El1,
+    tcr_el1: TcrEl1,
+    mair_el1: MairEl1,
+    amair_el1: AmairEl1,
+    vbar_el1: VbarEl1,
+    contextidr_el1: ContextidrEl1,
+    cntkctl_el1: CntkctlEl1,
+    // Compare value first, so a restored timer never fires on a stale one.
+    cntv_cval_el0: CntvCvalEl0,
+    cntv_ctl_el0: CntvCtlEl0,
+    esr_el1: EsrEl1,
+    far_el1: FarEl1,
+    afsr0_el1: Afsr0El1,
+    afsr1_el1: Afsr1El1,
+    par_el1: ParEl1,
+    elr_el1: ElrEl1,
+    spsr_el1: SpsrEl1,
+    tpidr_el1: TpidrEl1,
+    sp_el0: SpEl0,
+    tpidr_el0: TpidrEl0,
+    tpidrro_el0: TpidrroEl0,
+    csselr_el1: CsselrEl1,
+}
+
+/// Register file of a vCPU: what the EL2 vectors keep in a `TrapFrame`,
+/// plus the EL1 state a world switch moves by hand.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VcpuStateStruct {
+    pub x: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    pub sp_el1: u64,
+    pub el1: El1Context,
+}
+
+impl VcpuStateStruct {
+    pub const fn new() -> Self {
+        Self {
+            x: [0; 31],
+            elr: 0,
+            spsr: 0,
+            sp_el1: 0,
+            el1: El1Context::new(),
+        }
+    }
+
+    /// # Safety
+    /// `frame` must point to an EL2 trap frame.
+    pub(crate) unsafe fn save_from_frame(&mut self, frame: *const TrapFrame) {
+        let frame = &*frame;
+        self.x = frame.x;
+        self.elr = frame.elr;
+        self.spsr = frame.spsr;
+        self.sp_el1 = frame.sp_el1;
+    }
+
+    /// # Safety
+    /// `frame` must point to an EL2 trap frame.
+    pub(crate) unsafe fn restore_to_frame(&self, frame: *mut TrapFrame) {
+        let frame = &mut *frame;
+        frame.x = self.x;
+        frame.elr = self.elr;
+        frame.spsr = self.spsr;
+        frame.sp_el1 = self.sp_el1;
+    }
+}
+
+/// Pointer authentication keys: APIA, APIB, APDA, APDB and APGA, low half
+/// first.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PauthKeys([u64; 10]);
+
+impl PauthKeys {
+    pub const fn new() -> Self {
+        Self([0; 10])
+    }
+}
+
+/// Store the live keys to `save` and load them from `load`. On a CPU
+/// without pointer authentication the first instruction stays a `ret`.
+#[naked]
+#[link_section = ".hyp.text"]
+unsafe extern "C" fn switch_pauth_keys(save: *mut PauthKeys, load: *const PauthKeys) {
+    // Key registers by encoding, for assemblers before Armv8.3.
+    core::arch::naked_asm!(
+        alternative!("ret", "nop", PAuth),
+        "mrs x2, s3_0_c2_c1_0\n",
+        "mrs x3, s3_0_c2_c1_1\n",
+        "stp x2, x3, [x0, #0]\n",
+        "mrs x2, s3_0_c2_c1_2\n",
+        "mrs x3, s3_0_c2_c1_3\n",
+        "stp x2, x3, [x0, #16]\n",
+        "mrs x2, s3_0_c2_c2_0\n",
+        "mrs x3, s3_0_c2_c2_1\n",
+        "stp x2, x3, [x0, #32]\n",
+        "mrs x2, s3_0_c2_c2_2\n",
+        "mrs x3, s3_0_c2_c2_3\n",
+        "stp x2, x3, [x0, #48]\n",
+        "mrs x2, s3_0_c2_c3_0\n",
+        "mrs x3, s3_0_c2_c3_1\n",
+        "stp x2, x3, [x0, #64]\n",
+        "ldp x2, x3, [x1, #0]\n",
+        "msr s3_0_c2_c1_0, x2\n",
+        "msr s3_0_c2_c1_1, x3\n",
+        "ldp x2, x3, [x1, #16]\n",
+        "msr s3_0_c2_c1_2, x2\n",
+        "msr s3_0_c2_c1_3, x3\n",
+        "ldp x2, x3, [x1, #32]\n",
+        "msr s3_0_c2_c2_0, x2\n",
+        "msr s3_0_c2_c2_1, x3\n",
+        "ldp x2, x3, [x1, #48]\n",
+        "msr s3_0_c2_c2_2, x2\n",
+        "msr s3_0_c2_c2_3, x3\n",
+        "ldp x2, x3, [x1, #64]\n",
+        "msr s3_0_c2_c3_0, x2\n",
+        "msr s3_0_c2_c3_1, x3\n",
+        "ret\n",
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcpuState {
+    Created,
+    Running,
+    Blocked,
+    Stopped,
+    /// Powered off through PSCI, waiting for CPU_ON.
+    Off,
+}
+
+/// What the guest's virtual counter (CNTVCT_EL0) counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualCounter {
+    /// Wall time since the vCPU was created.
+    HostTime,
+    /// Only time the vCPU spent in guest mode, so benchmarks don't see host
+    /// preemption.
+    GuestTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcpuError {
+    InvalidId,
+    WrongVm,
+    Stopped,
+    NotPaused,
+    AlreadyStarted,
+    AlreadyOn,
+    NoSuchCore,
+    /// The core is another vCPU's, or the last one left to the host.
+    CoreTaken,
+    /// EL2 has no room left to defer the kick, see `kick::run_deferred`.
+    Busy,
+    /// The VM already has `VmConfig::max_vcpus` vCPUs.
+    TooMany,
+    /// The stack guard falls outside the guest address space, or the VM has
+    /// no Stage-2 tables to put it in yet.
+    BadStackGuard,
+}
+
+pub struct Vcpu {
+    pub id: usize,
+    pub vm_id: usize,
+    /// Position within the VM, which the guest sees as MPIDR_EL1.Aff0.
+    pub index: usize,
+    pub state: VcpuState,
+    pub regs: VcpuStateStruct,
+    pub vgic: Vgic,
+    pub stats: ExitStats,
+    pub config: VmConfig,
+    /// Registers mirrored on every exit while enabled.
+    pub shadow: ShadowRegs,
+    /// Physical core the vCPU owns while its run loop is active, see
+    /// `set_dedicated_core`.
+    pub dedicated_core: Option<usize>,
+    /// Id of the host thread in `run_vcpu` for this vCPU.
+    pub host_thread: Option<usize>,
+    /// Why the vCPU last came back to its host thread.
+    pub last_exit: Option<ExitCode>,
+    /// Priority boost of the host thread on interrupt injection.
+    pub boost: Boost,
+    // ESR_EL2 of the exit being handled.
+    pub(crate) exit_esr: u64,
+    // Heartbeat page of the VM, beaten on every entry.
+    pub(crate) heartbeat: Option<Heartbeat>,
+    // Emulated physical timer, used with `Traps::ptimer`.
+    pub(crate) ptimer: PTimer,
+    // Whether EL2 masked the virtual timer until the guest is done with
+    // its interrupt, see `vtimer`.
+    pub(crate) vtimer_masked: bool,
+    // Guest pointer authentication keys while switched out.
+    pauth_keys: PauthKeys,
+    // Whether the vCPU entered guest mode since it was powered on.
+    started: bool,
+    // x0-x3 for the first entry.
+    boot_args: Option<[u64; 4]>,
+    // Registers and boot arguments a VM reset starts over with.
+    reset_regs: VcpuStateStruct,
+    reset_args: Option<[u64; 4]>,
+    // Created powered off, so back off after a VM reset.
+    secondary: bool,
+    // CNTVOFF_EL2 of this vCPU: physical count minus guest count.
+    cntvoff: u64,
+    // Physical count when the vCPU last left guest mode, 0 before first run.
+    exit_cycles: u64,
+    // Physical count when the vCPU last entered guest mode.
+    enter_cycles: u64,
+    // Cycles spent in guest mode, up to the last exit.
+    guest_total: u64,
+    // Physical count when the current run loop started, 0 outside of one.
+    run_start: u64,
+    // Cycles spent in finished run loops.
+    run_total: u64,
+    // What the run-state hooks were last told.
+    run_state: RunState,
+    // Bitmask of KickReason raised since the last time EL2 looked.
+    pending_kicks: AtomicU32,
+    // Physical core currently executing this vCPU in guest mode.
+    running_on: AtomicUsize,
+}
+
+impl Vcpu {
+    /// `arg` is passed according to `config.boot`.
+    pub fn new(
+        id: usize,
+        vm_id: usize,
+        index: usize,
+        config: VmConfig,
+        entry: u64,
+        arg: u64,
+    ) -> Self {
+        let mut regs = config.boot.initial_regs(entry, arg);
+        if config.reset_shim {
+            shim::redirect(&mut regs, config.ipa_bits);
+        }
+        Self {
+            id,
+            vm_id,
+            index,
+            state: VcpuState::Created,
+            regs,
+            vgic: Vgic::new(),
+            stats: ExitStats::new(),
+            config,
+            shadow: ShadowRegs::new(),
+            dedicated_core: None,
+            host_thread: None,
+            last_exit: None,
+            boost: Boost::new(),
+            exit_esr: 0,
+            heartbeat: None,
+            ptimer: PTimer::new(hyper::read_cntpct()),
+            vtimer_masked: false,
+            pauth_keys: PauthKeys::new(),
+            started: false,
+            boot_args: None,
+            reset_regs: regs,
+            reset_args: None,
+            secondary: false,
+            cntvoff: hyper::read_cntpct(),
+            exit_cycles: 0,
+            enter_cycles: 0,
+            guest_total: 0,
+            run_start: 0,
+            run_state: RunState::Stopped,
+            run_total: 0,
+            pending_kicks: AtomicU32::new(0),
+            running_on: AtomicUsize::new(NOT_RUNNING),
+        }
+    }
+
+    pub fn running_on(&self) -> Option<usize> {
+        match self.running_on.load(Ordering::Acquire) {
+            NOT_RUNNING => None,
+            cpu => Some(cpu),
+        }
+    }
+
+    pub(crate) fn raise_kick(&self, reason: KickReason) {
+        self.pending_kicks.fetch_or(reason as u32, Ordering::AcqRel);
+    }
+
+    /// Take the vCPU off its core for good without going back to the host.
+    pub(crate) fn detach(&mut self) {
+        self.state = VcpuState::Stopped;
+        self.running_on.store(NOT_RUNNING, Ordering::Release);
+        CURRENT_VCPU[current_cpu_id()].store(NOT_RUNNING, Ordering::Release);
+    }
+
+    /// Load x0-x3 on the first entry into the guest, e.g. a DTB address
+    /// or a PSCI context id. Rejected once the vCPU has run.
+    pub fn set_boot_args(&mut self, args: &[u64; 4]) -> Result<(), VcpuError> {
+        if self.started {
+            return Err(VcpuError::AlreadyStarted);
+        }
+        self.boot_args = Some(*args);
+        self.reset_args = Some(*args);
+        Ok(())
+    }
+
+    /// Bring a powered off vCPU up at `entry`, as PSCI CPU_ON does.
+    pub fn power_on(&mut self, entry: u64, context_id: u64) -> Result<(), VcpuError> {
+        if self.state != VcpuState::Off {
+            return Err(VcpuError::AlreadyOn);
+        }
+        self.regs = BootProtocol::Bare.initial_regs(entry, 0);
+        if self.config.reset_shim {
+            shim::redirect(&mut self.regs, self.config.ipa_bits);
+        }
+        self.started = false;
+        self.set_boot_args(&[context_id, 0, 0, 0])?;
+        self.state = VcpuState::Created;
+        vcpu_manager().wake(self.id);
+        Ok(())
+    }
+
+    /// Put the vCPU back the way it was created, as a VM reset does: the
+    /// boot vCPU starts over at its entry with its boot arguments,
+    /// secondaries wait for CPU_ON again. Interrupts already queued stay,
+    /// an emulated redistributor goes back to sleep with them disabled.
+    pub fn reset(&mut self) {
+        self.regs = self.reset_regs;
+        self.ptimer.reset();
+        self.vtimer_masked = false;
+        self.vgic.reset_lrs();
+        if let Some(redist) = &self.vgic.redist {
+            redist.reset();
+        }
+        self.boot_args = self.reset_args;
+        self.started = false;
+        self.state = if self.secondary {
+            VcpuState::Off
+        } else {
+            VcpuState::Created
+        };
+    }
+
+    /// The physical counter started over, `delta` counts behind where it
+    /// stood: move what the vCPU keeps in physical counts back with it, so
+    /// the guest's counter goes on from where it was.
+    pub(crate) fn rebase_counter(&mut self, delta: u64) {
+        self.cntvoff = self.cntvoff.wrapping_sub(delta);
+        self.ptimer.rebase(delta);
+        for count in [
+            &mut self.exit_cycles,
+            &mut self.enter_cycles,
+            &mut self.run_start,
+        ] {
+            // 0 stands for never.
+            if *count != 0 {
+                *count = count.wrapping_sub(delta);
+            }
+        }
+    }
+
+    /// Whether anything would wake a suspended vCPU.
+    pub fn has_wakeup(&self) -> bool {
+        self.vgic.has_pending()
+            || self.pending_kicks.load(Ordering::Acquire) != 0
+            || vtimer::asserted(self, hyper::read_cntpct())
+            || (self.config.traps.ptimer && self.ptimer.asserted(hyper::read_cntpct()))
+    }
+
+    /// Physical count at which the vCPU's next timer comes due, if one is
+    /// armed.
+    pub fn timer_due(&self) -> Option<u64> {
+        let ptimer = self.ptimer.due().filter(|_| self.config.traps.ptimer);
+        match (vtimer::due(self), ptimer) {
+            (Some(v), Some(p)) => Some(v.min(p)),
+            (v, p) => v.or(p),
+        }
+    }
+
+    pub(crate) fn take_kicks(&self) -> u32 {
+        self.pending_kicks.swap(0, Ordering::AcqRel)
+    }
+
+    /// Guest view of the counter at physical count `now`.
+    #[inline]
+    pub fn guest_cycles(&self, now: u64) -> u64 {
+        now.wrapping_sub(self.cntvoff)
+    }
+
+    /// Physical count at guest virtual count `guest`, the inverse of
+    /// `guest_cycles` as long as the offset hasn't moved since.
+    #[inline]
+    pub fn host_cycles(&self, guest: u64) -> u64 {
+        guest.wrapping_add(self.cntvoff)
+    }
+
+    /// Cycles, up to physical count `now`, the vCPU's run loops spent in
+    /// guest mode and in the host.
+    pub fn time_split(&self, now: u64) -> (u64, u64) {
+        let mut guest = self.guest_total;
+        if self.running_on().is_some() {
+            guest += now.wrapping_sub(self.enter_cycles);
+        }
+        let mut run = self.run_total;
+        if self.run_start != 0 {
+            run += now.wrapping_sub(self.run_start);
+        }
+        (guest, run.saturating_sub(guest))
+    }
+
+    /// Start the vCPU's statistics over at physical count `now`: exit and
+    /// boost counters and the time split. The exit profile and the guest's
+    /// counter are left alone.
+    pub fn reset_stats(&mut self, now: u64) {
+        self.stats.reset();
+        self.boost.reset_stats();
+        self.guest_total = 0;
+        self.run_total = 0;
+        if self.running_on().is_some() {
+            self.enter_cycles = now;
+        }
+        if self.run_start != 0 {
+            self.run_start = now;
+        }
+    }
+
+    /// Step over the instruction that caused the current exit, which is 2
+    /// bytes for 16-bit T32 encodings, and on in its IT block.
+    #[inline]
+    pub fn advance_pc(&mut self) {
+        self.regs.elr += exit::instr_len(self.exit_esr);
+        self.regs.spsr = exit::advance_itstate(self.regs.spsr);
+    }
+}
+
+/// The vCPU slots, allocated by the host as VMs ask for more vCPUs. Each
+/// slot has a lock of its own, so vCPUs of different cores don't wait for
+/// each other; `with_vcpu` shares a slot, `with_vcpu_mut` and
+/// `with_current_vcpu` hold it alone. Host code holds one slot at a time.
+/// EL2 holds the slot of the vCPU whose exit it handles, and kicks and
+/// injections it makes meanwhile wait until the slot is released, see
+/// `kick`; it only tries other slots, see `try_with_peer`.
+///
+/// A freed slot is reused by the next vCPU and never dropped, so the table
+/// always holds a reference and EL2 letting go of its own never frees.
+pub struct VcpuManager {
+    slots: SpinLock<Vec<Arc<Slot>>>,
+}
+
+struct Slot {
+    vcpu: SpinLock<Option<Vcpu>>,
+    // VM of the slot, `NOT_RUNNING` while it is free. It and the index are
+    // kept outside the vCPU, so finding a vCPU takes no slot lock.
+    owner: AtomicUsize,
+    index: AtomicUsize,
+    // What the vCPU's host thread blocks on out of guest mode, see
+    // `VcpuManager::wait_wake`.
+    wake: EventFlags,
+    // A wake asked for at EL2, left for EL1 to make.
+    wake_deferred: AtomicBool,
+}
+
+// SAFETY: `EventFlags` keeps its state behind its own lock and is only
+// `!Send` to stay out of thread-local use; the slot is shared by design.
+unsafe impl Send for Slot {}
+
+// The only token of `Slot::wake`.
+const WAKE: u32 = 1;
+// Whether any slot has `wake_deferred` set.
+static WAKES_DEFERRED: AtomicBool = AtomicBool::new(false);
+
+#[link_section = ".hyp.data"]
+static VCPU_MANAGER: VcpuManager = VcpuManager::new();
+
+impl VcpuManager {
+    pub const fn new() -> Self {
+        Self {
+            slots: SpinLock::new(Vec::new()),
+        }
+    }
+
+    pub fn create_vcpu(&self, vm_id: usize, entry: u64, sp: u64) -> Result<usize, VcpuError> {
+        self.create_vcpu_with(vm_id, VmConfig::default(), entry, sp)
+    }
+
+    /// Create a secondary vCPU that stays powered off until the guest starts
+    /// it with PSCI CPU_ON.
+    pub fn create_secondary_vcpu(
+        &self,
+        vm_id: usize,
+        config: VmConfig,
+    ) -> Result<usize, VcpuError> {
+        let id = self.create_vcpu_with(vm_id, config, 0, 0)?;
+        self.with_vcpu_mut(id, |vcpu| {
+            vcpu.state = VcpuState::Off;
+            vcpu.secondary = true;
+        });
+        Ok(id)
+    }
+
+    /// Create a vCPU set up by `config`, usually a `GuestProfile`'s. `arg`
+    /// is the boot protocol's argument: the stack pointer for bare images,
+    /// the DTB address for Linux.
+    pub fn create_vcpu_with(
+        &self,
+        vm_id: usize,
+        config: VmConfig,
+        entry: u64,
+        arg: u64,
+    ) -> Result<usize, VcpuError> {
+        let (id, index, slot) = self.claim(vm_id, config.max_vcpus.min(MAX_VCPUS_PER_VM))?;
+        if index == 0 {
+            // A new VM with the id of one that ended.
+            status::clear(vm_id);
+        }
+        let guarded = match (config.boot, config.stack_guard) {
+            (BootProtocol::Bare, Some(guard)) => add_stack_guard(vm_id, arg, guard),
+            _ => Ok(()),
+        };
+        if let Err(e) = guarded {
+            slot.owner.store(NOT_RUNNING, Ordering::Release);
+            return Err(e);
+        }
+        *slot.vcpu.irqsave_write() = Some(Vcpu::new(id, vm_id, index, config, entry, arg));
+        if config.isolated_cores != 0 && isolation::update().is_err() {
+            let mut vcpu = slot.vcpu.irqsave_write();
+            slot.owner.store(NOT_RUNNING, Ordering::Release);
+            *vcpu = None;
+            return Err(VcpuError::CoreTaken);
+        }
+        super::vm::attach_vcpu(vm_id, id);
+        vlog!(
+            Vcpu,
+            Debug,
+            "[vcpu] vcpu {} created for {}, entry {:#x}",
+            id,
+            identity::tag(vm_id),
+            entry
+        );
+        Ok(id)
+    }
+
+    // Take the first free slot, or a new one, for the next vCPU of VM
+    // `vm_id`, and give it the lowest index none of the VM's vCPUs has,
+    // so one destroyed early leaves no duplicate behind. Both happen under
+    // the table lock, so creates racing for one VM get distinct indices
+    // and can't go past `max` together. The caller puts the vCPU in,
+    // without the table held, so no slot lock is waited for with it.
+    fn claim(&self, vm_id: usize, max: usize) -> Result<(usize, usize, Arc<Slot>), VcpuError> {
+        let mut slots = self.slots.irqsave_write();
+        let taken = slots
+            .iter()
+            .filter(|slot| slot.owner.load(Ordering::Acquire) == vm_id)
+            .fold(0u32, |taken, slot| {
+                taken | 1 << slot.index.load(Ordering::Acquire)
+            });
+        let index = taken.trailing_ones() as usize;
+        if index >= max {
+            return Err(VcpuError::TooMany);
+        }
+        let free = slots
+            .iter()
+            .position(|slot| slot.owner.load(Ordering::Acquire) == NOT_RUNNING);
+        let id = free.unwrap_or_else(|| {
+            let slot = Arc::new(Slot {
+                owner: AtomicUsize::new(NOT_RUNNING),
+                index: AtomicUsize::new(0),
+                vcpu: SpinLock::new(None),
+                wake: EventFlags::new(),
+                wake_deferred: AtomicBool::new(false),
+            });
+            slot.wake.init(0);
+            slots.push(slot);
+            slots.len() - 1
+        });
+        let slot = slots[id].clone();
+        slot.index.store(index, Ordering::Release);
+        slot.owner.store(vm_id, Ordering::Release);
+        Ok((id, index, slot))
+    }
+
+    // The slot of vCPU `id`. The table lock is only held to find it.
+    fn slot(&self, id: usize) -> Option<Arc<Slot>> {
+        self.slots.irqsave_read().get(id).cloned()
+    }
+
+    /// Free the slot of a vCPU that isn't in guest mode.
+    pub fn destroy_vcpu(&self, id: usize) -> Result<(), VcpuError> {
+        let slot = self.slot(id).ok_or(VcpuError::InvalidId)?;
+        let (vm_id, claimed) = {
+            let mut vcpu = slot.vcpu.irqsave_write();
+            let v = vcpu.as_ref().ok_or(VcpuError::InvalidId)?;
+            if v.running_on().is_some() {
+                return Err(VcpuError::AlreadyStarted);
+            }
+            let claimed = v.config.isolated_cores != 0 || v.dedicated_core.is_some();
+            let vm_id = v.vm_id;
+            slot.owner.store(NOT_RUNNING, Ordering::Release);
+            *vcpu = None;
+            (vm_id, claimed)
+        };
+        if self.ids_of(vm_id).next().is_none() {
+            super::vm::vm_manager().release(vm_id);
+        }
+        if claimed {
+            // Fewer claimed cores can't leave the host without one.
+            let _ = isolation::update();
+        }
+        Ok(())
+    }
+
+    /// Run `f` on vCPU `id`, sharing it with other readers.
+    pub fn with_vcpu<R>(&self, id: usize, f: impl FnOnce(&Vcpu) -> R) -> Option<R> {
+        self.slot(id)?.vcpu.irqsave_read().as_ref().map(f)
+    }
+
+    /// Run `f` on vCPU `id`, holding it alone.
+    pub fn with_vcpu_mut<R>(&self, id: usize, f: impl FnOnce(&mut Vcpu) -> R) -> Option<R> {
+        self.slot(id)?.vcpu.irqsave_write().as_mut().map(f)
+    }
+
+    /// Like `with_vcpu`, but `None` rather than waiting if the slot is held.
+    pub fn try_with_vcpu<R>(&self, id: usize, f: impl FnOnce(&Vcpu) -> R) -> Option<R> {
+        let slot = self.slots.try_irqsave_read()?.get(id).cloned()?;
+        let vcpu = slot.vcpu.try_irqsave_read()?;
+        vcpu.as_ref().map(f)
+    }
+
+    /// Run `f` on vCPU `id` from the exit handler of another vCPU at EL2.
+    /// Gives up with `None` once `id` is seen in guest mode, where its own
+    /// exit may hold it; a slot held by the host is waited for.
+    pub fn try_with_peer<R>(&self, id: usize, f: impl FnOnce(&mut Vcpu) -> R) -> Option<R> {
+        let slot = self.slot(id)?;
+        loop {
+            if let Some(mut vcpu) = slot.vcpu.try_irqsave_write() {
+                return vcpu.as_mut().map(f);
+            }
+            if in_guest(id) {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Run `f` on the vCPU in guest mode on this core, holding it alone.
+    /// Runs at EL2. Kicks and injections deferred meanwhile are made once
+    /// it's released.
+    #[link_section = ".hyp.text"]
+    pub fn with_current_vcpu<R>(&self, f: impl FnOnce(&mut Vcpu) -> R) -> Option<R> {
+        let cpu = current_cpu_id();
+        let id = CURRENT_VCPU[cpu].load(Ordering::Acquire);
+        let slot = self.slot(id)?;
+        let ret = {
+            let mut vcpu = slot.vcpu.irqsave_write();
+            let vcpu = vcpu.as_mut()?;
+            HANDLING[cpu].store(id, Ordering::Release);
+            let ret = f(vcpu);
+            HANDLING[cpu].store(NOT_RUNNING, Ordering::Release);
+            ret
+        };
+        kick::run_deferred();
+        Some(ret)
+    }
+
+    /// VM of vCPU `id`.
+    pub fn vm_of(&self, id: usize) -> Option<usize> {
+        match self.slot(id)?.owner.load(Ordering::Acquire) {
+            NOT_RUNNING => None,
+            vm_id => Some(vm_id),
+        }
+    }
+
+    /// Run `f` on every vCPU id and the vCPU, or `None` where its slot is
+    /// held. For the panic path: a table held by a core that won't let go
+    /// is walked without its lock.
+    pub(crate) fn for_each_on_panic(&self, mut f: impl FnMut(usize, Option<&Vcpu>)) {
+        let guard = self.slots.try_irqsave_read();
+        let slots = match &guard {
+            Some(slots) => &**slots,
+            // SAFETY: the other cores are stopped or parking; at worst a
+            // slot being added is missed.
+            None => unsafe { &*self.slots.as_mut_ptr() },
+        };
+        for (id, slot) in slots.iter().enumerate() {
+            if slot.owner.load(Ordering::Acquire) == NOT_RUNNING {
+                continue;
+            }
+            let vcpu = slot.vcpu.try_irqsave_read();
+            f(id, vcpu.as_deref().and_then(Option::as_ref));
+        }
+    }
+
+    /// Ids of all vCPUs.
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.capacity()).filter(|&id| self.vm_of(id).is_some())
+    }
+
+    /// Ids of the vCPUs of VM `vm_id`, in slot order.
+    pub fn ids_of(&self, vm_id: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.capacity()).filter(move |&id| self.vm_of(id) == Some(vm_id))
+    }
+
+    // Slots allocated so far, free ones included.
+    fn capacity(&self) -> usize {
+        self.slots.irqsave_read().len()
+    }
+
+    /// Id of the vCPU at position `index` of VM `vm_id`.
+    pub fn find(&self, vm_id: usize, index: usize) -> Option<usize> {
+        self.ids_of(vm_id).find(|&id| {
+            self.slot(id)
+                .is_some_and(|slot| slot.index.load(Ordering::Acquire) == index)
+        })
+    }
+
+    /// Run `f` on each vCPU in turn.
+    pub fn for_each(&self, mut f: impl FnMut(&Vcpu)) {
+        for id in self.ids() {
+            self.with_vcpu(id, &mut f);
+        }
+    }
+
+    /// Block the calling thread, the host thread of vCPU `id`, until `wake`
+    /// is called for it, at most `timeout`. A wake that came since the last
+    /// wait returns at once, so the caller checks its condition first and
+    /// waits again if it doesn't hold yet.
+    pub(crate) fn wait_wake(&self, id: usize, timeout: Tick) {
+        if let Some(slot) = self.slot(id) {
+            let _ = slot
+                .wake
+                .wait::<InsertToEnd>(WAKE, EventFlagsMode::ANY, timeout);
+        }
+    }
+
+    /// Wake the host thread of vCPU `id` out of `wait_wake`. EL2 can't wake
+    /// threads: there the wake is left for `wake_deferred` on EL1, and the
+    /// kick SGI raised on this core makes sure EL1 gets to it.
+    pub(crate) fn wake(&self, id: usize) {
+        let Some(slot) = self.slot(id) else {
+            return;
+        };
+        if hyper::get_current_el() != 2 {
+            let _ = slot.wake.set(WAKE);
+            return;
+        }
+        slot.wake_deferred.store(true, Ordering::Release);
+        WAKES_DEFERRED.store(true, Ordering::Release);
+        kick::raise_local();
+    }
+
+    /// Make the wakes EL2 left for EL1.
+    pub(crate) fn wake_deferred(&self) {
+        if !WAKES_DEFERRED.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        for id in 0..self.capacity() {
+            if let Some(slot) = self.slot(id) {
+                if slot.wake_deferred.swap(false, Ordering::AcqRel) {
+                    let _ = slot.wake.set(WAKE);
+                }
+            }
+        }
+    }
+
+    /// Run `f` on each vCPU of VM `vm_id` in turn, holding it alone.
+    pub fn for_each_of(&self, vm_id: usize, mut f: impl FnMut(&mut Vcpu)) {
+        for id in self.ids_of(vm_id) {
+            self.with_vcpu_mut(id, &mut f);
+        }
+    }
+}
+
+pub fn vcpu_manager() -> &'static VcpuManager {
+    &VCPU_MANAGER
+}
+
+/// Whether vCPU `id` is in guest mode on some core, or in an exit there.
+pub(crate) fn in_guest(id: usize) -> bool {
+    CURRENT_VCPU.iter().any(|c| c.load(Ordering::Acquire) == id)
+}
+
+/// Whether this core is handling a vCPU exit at EL2, with the vCPU held.
+pub(crate) fn in_exit() -> bool {
+    HANDLING[current_cpu_id()].load(Ordering::Acquire) != NOT_RUNNING
+}
+
+/// Cores with a vCPU in guest mode.
+pub(crate) fn guest_cores() -> usize {
+    (0..NUM_CORES)
+        .filter(|&cpu| CURRENT_VCPU[cpu].load(Ordering::Acquire) != NOT_RUNNING)
+        .fold(0, |mask, cpu| mask | (1 << cpu))
+}
+
+// vCPU id currently in guest mode on each core.
+#[link_section = ".hyp.data"]
+static CURRENT_VCPU: [AtomicUsize; NUM_CORES] =
+    [const { AtomicUsize::new(NOT_RUNNING) }; NUM_CORES];
+// vCPU whose exit each core handles at EL2, see `with_current_vcpu`.
+#[link_section = ".hyp.data"]
+static HANDLING: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(NOT_RUNNING) }; NUM_CORES];
+// VM of the guest on each core, kept apart from the vCPU so a failed trap
+// can still be charged to it.
+static RUNNING_VM: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(NOT_RUNNING) }; NUM_CORES];
+
+// Host EL1 state parked at EL2 while a guest owns the core.
+#[link_section = ".hyp.data"]
+static mut HOST_CONTEXT: [VcpuStateStruct; NUM_CORES] = [VcpuStateStruct::new(); NUM_CORES];
+
+// Host pointer authentication keys parked the same way.
+#[link_section = ".hyp.data"]
+static mut HOST_PAUTH: [PauthKeys; NUM_CORES] = [PauthKeys::new(); NUM_CORES];
+
+// Host CNTP_CTL_EL0 of each core whose tick is masked for a dedicated vCPU.
+#[link_section = ".hyp.data"]
+static mut SUPPRESSED_TICK: [Option<u64>; NUM_CORES] = [None; NUM_CORES];
+
+// HCR_EL2.API and APK, so guests use pointer authentication without
+// trapping; zero on CPUs without it.
+#[inline(always)]
+fn pauth_hcr() -> u64 {
+    let v: u64;
+    unsafe {
+        asm!(
+            alternative!("mov {0}, xzr", "movz {0}, #0x300, lsl #32", PAuth),
+            out(reg) v,
+            options(pure, nomem, nostack)
+        )
+    };
+    v
+}
+
+// Stage-2 translation is on for VMs with tables; the others see physical
+// memory as it is. SMCs always trap, so a guest reaches the firmware only
+// through `vpsci`.
+fn guest_hcr(traps: Traps, translate: bool) -> u64 {
+    let mut hcr = (HCR_EL2::RW::EL1AArch64
+        + HCR_EL2::IMO::EL2Handled
+        + HCR_EL2::FMO::EL2Handled
+        + HCR_EL2::AMO::EL2Handled
+        + HCR_EL2::TSC::Trap)
+        .value
+        | pauth_hcr();
+    if traps.wfi {
+        hcr |= HCR_EL2::TWI::Trap.value;
+    }
+    if traps.wfe {
+        hcr |= HCR_EL2::TWE::Trap.value;
+    }
+    if traps.cache_id || cacheid::mismatched() {
+        hcr |= (HCR_EL2::TID2::Trap + HCR_EL2::TDZ::Trap).value;
+    }
+    if traps.tvm {
+        hcr |= HCR_EL2::TVM::Trap.value;
+    }
+    if translate {
+        hcr |= HCR_EL2::VM::Enable.value;
+    }
+    hcr
+}
+
+/// Switch this core from the host into vCPU `id`. Runs at EL2 on the host's
+/// trap frame; the host sees the eventual exit code in x0 of its HVC.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of a host HVC.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn enter_guest(frame: *mut TrapFrame, id: usize) {
+    let cpu = current_cpu_id();
+    if super::panic::is_panicking() || !super::initcall::cpu_ready(cpu) {
+        (*frame).x[0] = ExitCode::Invalid.encode();
+        return;
+    }
+    let entered = vcpu_manager().with_vcpu_mut(id, |vcpu| enter(frame, cpu, id, vcpu));
+    if entered.is_none() {
+        (*frame).x[0] = ExitCode::Invalid.encode();
+    }
+}
+
+#[link_section = ".hyp.text"]
+unsafe fn enter(frame: *mut TrapFrame, cpu: usize, id: usize, vcpu: &mut Vcpu) {
+    if super::recovery::is_failed(vcpu.vm_id) {
+        (*frame).x[0] = ExitCode::Fault.encode();
+        return;
+    }
+    // Validated at creation, but the core may have a smaller PARange than
+    // the one the VM was checked on.
+    let Some(vtcr) = stage2::vtcr(vcpu.config.ipa_bits) else {
+        (*frame).x[0] = ExitCode::Invalid.encode();
+        return;
+    };
+    let vttbr = stage2::vttbr_of(vcpu.vm_id);
+    match vcpu.state {
+        VcpuState::Stopped => {
+            (*frame).x[0] = ExitCode::Invalid.encode();
+            return;
+        }
+        VcpuState::Off => {
+            (*frame).x[0] = ExitCode::PowerOff.encode();
+            return;
+        }
+        VcpuState::Blocked => {
+            (*frame).x[0] = ExitCode::Paused.encode();
+            return;
+        }
+        _ => {}
+    }
+    // A kick raised while the vCPU was out of guest mode must not be lost.
+    // Running first, so a `host_pm::freeze` either waits for this entry or
+    // is seen here, even by a vCPU it didn't kick.
+    vcpu.running_on.store(cpu, Ordering::Release);
+    fence(Ordering::SeqCst);
+    let mut kicks = vcpu.take_kicks();
+    if host_pm::is_frozen() {
+        kicks |= KickReason::Freeze as u32;
+    }
+    if kicks != 0 {
+        vcpu.running_on.store(NOT_RUNNING, Ordering::Release);
+        (*frame).x[0] = ExitCode::Kick(kicks).encode();
+        return;
+    }
+
+    let host = &mut *addr_of_mut!(HOST_CONTEXT[cpu]);
+    host.save_from_frame(frame);
+    host.el1.save();
+    switch_pauth_keys(addr_of_mut!(HOST_PAUTH[cpu]), &vcpu.pauth_keys);
+
+    let now = hyper::read_cntpct();
+    if vcpu.config.counter == VirtualCounter::GuestTime && vcpu.exit_cycles != 0 {
+        vcpu.cntvoff = vcpu
+            .cntvoff
+            .wrapping_add(now.wrapping_sub(vcpu.exit_cycles));
+    }
+    hyper::write_cntvoff_el2(vcpu.cntvoff);
+    vcpu.enter_cycles = now;
+    if let Some(heartbeat) = vcpu.heartbeat {
+        heartbeat.beat(now, vcpu.index);
+    }
+
+    if !vcpu.started {
+        if let Some(args) = vcpu.boot_args.take() {
+            vcpu.regs.x[..4].copy_from_slice(&args);
+        }
+        vcpu.started = true;
+    }
+    vcpu.regs.restore_to_frame(frame);
+    vcpu.regs.el1.restore();
+    vtimer::update(vcpu);
+    hyper::write_vmpidr_el2(VMPIDR_RES1 | vcpu.index as u64);
+    hyper::write_vpidr_el2(placement::guest_midr(vcpu.config.placement, cpu));
+    hyper::write_vtcr_el2(vtcr);
+    if let Some(vttbr) = vttbr {
+        hyper::write_vttbr_el2(vttbr);
+    }
+    #[cfg(virt_switch_latency)]
+    super::latency::time_vgic(|| vcpu.vgic.flush());
+    #[cfg(not(virt_switch_latency))]
+    vcpu.vgic.flush();
+    vcpu.state = VcpuState::Running;
+    let mut traps = vcpu.config.traps;
+    // The cache geometry must not change under a guest that moves between
+    // core classes.
+    traps.cache_id |= vcpu.config.placement == Placement::AnyCore;
+    if vcpu.dedicated_core == Some(cpu) && !traps.ptimer {
+        // Nothing else wants this core, so the host tick stays quiet and an
+        // idle guest waits in its own WFI.
+        let ctl = CNTP_CTL_EL0.get();
+        (*addr_of_mut!(SUPPRESSED_TICK))[cpu] = Some(ctl);
+        CNTP_CTL_EL0.set(ctl | CNTP_CTL_EL0::IMASK::Masked.value);
+        traps.wfi = false;
+        traps.wfe = false;
+    }
+    hyper::write_hcr_el2(guest_hcr(traps, vttbr.is_some()));
+    ptimer::trap(traps.ptimer);
+    sysregs().isb();
+    RUNNING_VM[cpu].store(vcpu.vm_id, Ordering::Release);
+    CURRENT_VCPU[cpu].store(id, Ordering::Release);
+}
+
+/// Hand the core back to the host. `vcpu.regs` must already hold the guest
+/// state captured on exit.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of the exit being handled.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn leave_guest(frame: *mut TrapFrame, vcpu: &mut Vcpu, code: ExitCode) {
+    let cpu = current_cpu_id();
+    vcpu.exit_cycles = hyper::read_cntpct();
+    vcpu.guest_total += vcpu.exit_cycles.wrapping_sub(vcpu.enter_cycles);
+    vcpu.regs.el1.save();
+    switch_pauth_keys(&mut vcpu.pauth_keys, addr_of!(HOST_PAUTH[cpu]));
+    #[cfg(virt_switch_latency)]
+    super::latency::time_vgic(|| vcpu.vgic.sync());
+    #[cfg(not(virt_switch_latency))]
+    vcpu.vgic.sync();
+    vcpu.running_on.store(NOT_RUNNING, Ordering::Release);
+    vcpu.state = match code {
+        ExitCode::Shutdown(_) | ExitCode::Fault | ExitCode::Anomaly(_) => VcpuState::Stopped,
+        ExitCode::Paused => VcpuState::Blocked,
+        ExitCode::PowerOff => VcpuState::Off,
+        _ => VcpuState::Created,
+    };
+    restore_host(frame, cpu, code);
+}
+
+/// Hand the core back to the host after a guest trapped with no vCPU to
+/// save its state to. The host's run HVC returns `code`.
+///
+/// # Safety
+/// `frame` must point to the EL2 trap frame of the trap.
+#[link_section = ".hyp.text"]
+pub(crate) unsafe fn abandon_guest(frame: *mut TrapFrame, cpu: usize, code: ExitCode) {
+    let mut lost = PauthKeys::new();
+    switch_pauth_keys(&mut lost, addr_of!(HOST_PAUTH[cpu]));
+    Vgic::clear_lrs();
+    restore_host(frame, cpu, code);
+}
+
+// Host half of a switch back from a guest.
+#[link_section = ".hyp.text"]
+unsafe fn restore_host(frame: *mut TrapFrame, cpu: usize, code: ExitCode) {
+    CURRENT_VCPU[cpu].store(NOT_RUNNING, Ordering::Release);
+    RUNNING_VM[cpu].store(NOT_RUNNING, Ordering::Release);
+    // A tick that came due meanwhile is taken as soon as the host runs.
+    if let Some(ctl) = (*addr_of_mut!(SUPPRESSED_TICK))[cpu].take() {
+        CNTP_CTL_EL0.set(ctl);
+    }
+
+    let host = &*addr_of_mut!(HOST_CONTEXT[cpu]);
+    hyper::write_hcr_el2(HCR_EL2::RW::EL1AArch64.value);
+    ptimer::trap(false);
+    hyper::write_vpidr_el2(MIDR_EL1.get());
+    host.el1.restore();
+    sysregs().isb();
+    host.restore_to_frame(frame);
+    (*frame).x[0] = code.encode();
+}
+
+/// VM of the guest running on `cpu`.
+pub(crate) fn running_vm(cpu: usize) -> Option<usize> {
+    match RUNNING_VM.get(cpu)?.load(Ordering::Acquire) {
+        NOT_RUNNING => None,
+        vm_id => Some(vm_id),
+    }
+}
+
+/// Let a vCPU paused by its exit policy run again. With `skip` it resumes
+/// after the instruction that paused it instead of retrying it.
+pub fn resume_vcpu(id: usize, skip: bool) -> Result<(), VcpuError> {
+    vcpu_manager()
+        .with_vcpu_mut(id, |vcpu| {
+            if vcpu.state != VcpuState::Blocked {
+                return Err(VcpuError::NotPaused);
+            }
+            if skip {
+                vcpu.advance_pc();
+            }
+            vcpu.state = VcpuState::Created;
+            Ok(())
+        })
+        .ok_or(VcpuError::InvalidId)??;
+    vcpu_manager().wake(id);
+    Ok(())
+}
+
+/// Reset VM `vm_id` in place: each vCPU starts over as `Vcpu::reset`
+/// says, memory and devices stay as they are. Powered off vCPUs are reset
+/// right away; the others are kicked, so a vCPU in guest mode is reset by
+/// its run loop once it is out. Stopped vCPUs stay stopped.
+pub fn reset_vm(vm_id: usize) {
+    let mut kicked = Vec::new();
+    vcpu_manager().for_each_of(vm_id, |vcpu| match vcpu.state {
+        VcpuState::Stopped => {}
+        VcpuState::Off => vcpu.reset(),
+        _ => kicked.push(vcpu.id),
+    });
+    for id in kicked {
+        let _ = kick::vcpu_kick(vm_id, id, KickReason::Reset);
+    }
+}
+
+/// Stop every vCPU of VM `vm_id` that still runs or may run again.
+pub fn stop_vm(vm_id: usize) {
+    let mut kicked = Vec::new();
+    vcpu_manager().for_each_of(vm_id, |vcpu| {
+        if vcpu.state != VcpuState::Stopped {
+            kicked.push(vcpu.id);
+        }
+    });
+    for id in kicked {
+        let _ = kick::vcpu_kick(vm_id, id, KickReason::StopRequest);
+    }
+}
+
+// Tell the run-state hooks if vCPU `id` moved to `to`.
+fn set_run_state(id: usize, to: RunState) {
+    let moved = vcpu_manager().with_vcpu_mut(id, |vcpu| {
+        let from = core::mem::replace(&mut vcpu.run_state, to);
+        (from != to).then_some((vcpu.vm_id, from))
+    });
+    // The hooks may look at the vCPU themselves.
+    if let Some(Some((vm_id, from))) = moved {
+        run_state::notify(vm_id, id, from, to);
+    }
+}
+
+// Unmap `guard` below the stack of a bare image whose stack top is `sp`.
+fn add_stack_guard(vm_id: usize, sp: u64, guard: StackGuard) -> Result<(), VcpuError> {
+    let size = guard.pages * stage2::PAGE_SIZE;
+    let base = sp
+        .checked_sub(guard.stack_size + size)
+        .ok_or(VcpuError::BadStackGuard)?;
+    stage2::with_vm(vm_id, |s2| s2.add_guard(base, size))
+        .ok_or(VcpuError::BadStackGuard)?
+        .map_err(|_| VcpuError::BadStackGuard)
+}
+
+// Hold the vCPU's host thread while another party has to change `state`.
+// Whoever changes it wakes the thread.
+fn wait_while(id: usize, state: VcpuState) {
+    set_run_state(id, RunState::Blocked);
+    while vcpu_manager().with_vcpu(id, |v| v.state) == Some(state) {
+        vcpu_manager().wait_wake(id, Tick::MAX);
+    }
+}
+
+// Held by `set_dedicated_core` from its check of the core to the update
+// of the isolated cores, so two callers can't both take one core.
+static DEDICATING: SpinLock<()> = SpinLock::new(());
+
+/// Let vCPU `id` own physical core `cpu`, or give it up with `None`, from
+/// its next `run_vcpu` on. The host scheduler moves every other thread off
+/// the core, host device interrupts are routed elsewhere and the host tick
+/// is masked while the guest runs, so only kicks and interrupts of devices
+/// assigned to the guest make it exit.
+pub fn set_dedicated_core(id: usize, cpu: Option<usize>) -> Result<(), VcpuError> {
+    if cpu.is_some_and(|cpu| cpu >= NUM_CORES) {
+        return Err(VcpuError::NoSuchCore);
+    }
+    let _dedicating = DEDICATING.irqsave_lock();
+    if cpu.is_some() {
+        let mut taken = false;
+        vcpu_manager().for_each(|v| taken |= v.id != id && v.dedicated_core == cpu);
+        if taken {
+            return Err(VcpuError::CoreTaken);
+        }
+    }
+    let old = vcpu_manager()
+        .with_vcpu_mut(id, |vcpu| core::mem::replace(&mut vcpu.dedicated_core, cpu))
+        .ok_or(VcpuError::InvalidId)?;
+    if isolation::update().is_err() {
+        vcpu_manager().with_vcpu_mut(id, |vcpu| vcpu.dedicated_core = old);
+        return Err(VcpuError::CoreTaken);
+    }
+    Ok(())
+}
+
+/// Start the statistics of VM `vm_id`'s vCPUs over, e.g. before a benchmark.
+pub fn reset_vm_stats(vm_id: usize) {
+    let now = hyper::read_cntpct();
+    vcpu_manager().for_each_of(vm_id, |vcpu| vcpu.reset_stats(now));
+}
+
+/// Start the statistics of every vCPU over.
+pub fn reset_all_stats() {
+    let now = hyper::read_cntpct();
+    for id in vcpu_manager().ids() {
+        vcpu_manager().with_vcpu_mut(id, |vcpu| vcpu.reset_stats(now));
+    }
+}
+
+/// Run loop of a vCPU's backing host thread. Returns once the guest has
+/// stopped, either on its own or because it was asked to.
+pub fn run_vcpu(id: usize) -> Result<ExitCode, VcpuError> {
+    vcpu_manager()
+        .with_vcpu_mut(id, |vcpu| {
+            vcpu.host_thread = Some(scheduler::current_thread_id());
+            vcpu.boost.attach(scheduler::current_thread());
+            vcpu.run_start = hyper::read_cntpct();
+        })
+        .ok_or(VcpuError::InvalidId)?;
+    let ret = run_on_core(id);
+    if let (Ok(code), Some(vm_id)) = (ret, vcpu_manager().vm_of(id)) {
+        if let Some(exit) = VmExit::of_code(code) {
+            status::record(vm_id, exit);
+        }
+    }
+    vcpu_manager().with_vcpu_mut(id, |vcpu| {
+        vcpu.run_total += hyper::read_cntpct().wrapping_sub(vcpu.run_start);
+        vcpu.run_start = 0;
+        vcpu.host_thread = None;
+        vcpu.boost.detach();
+    });
+    set_run_state(id, RunState::Stopped);
+    ret
+}
+
+fn run_on_core(id: usize) -> Result<ExitCode, VcpuError> {
+    #[cfg(smp)]
+    if let Some(cpu) = vcpu_manager().with_vcpu(id, |v| v.dedicated_core).flatten() {
+        scheduler::dedicate_core(cpu, &scheduler::current_thread())
+            .map_err(|_| VcpuError::CoreTaken)?;
+        // Only `cpu` picks this thread up from now on, so a yield moves it.
+        while current_cpu_id() != cpu {
+            scheduler::yield_me();
+        }
+        let ret = run_loop(id);
+        scheduler::release_core(cpu);
+        return ret;
+    }
+    #[cfg(smp)]
+    if let Some((vm_id, placement)) =
+        vcpu_manager().with_vcpu(id, |v| (v.vm_id, v.config.placement))
+    {
+        let cores = placement::cores_for(vm_id, placement, current_cpu_id());
+        if cores != placement::ALL_CORES {
+            let me = scheduler::current_thread();
+            // `cores_for` never leaves a vCPU without a core.
+            let _ = scheduler::set_affinity(&me, cores);
+            while cores & (1 << current_cpu_id()) == 0 {
+                scheduler::yield_me();
+            }
+            let ret = run_loop(id);
+            let _ = scheduler::set_affinity(&me, usize::MAX);
+            return ret;
+        }
+    }
+    run_loop(id)
+}
+
+// Hold the vCPU's host thread until something would wake its guest: an
+// interrupt or kick, which wakes the thread, or a timer coming due, which
+// a host timeout stands in for.
+fn wait_for_wakeup(id: usize) {
+    loop {
+        let Some((wakeup, due)) = vcpu_manager().with_vcpu(id, |v| (v.has_wakeup(), v.timer_due()))
+        else {
+            return;
+        };
+        // Back into the guest early by the calibrated delivery latency, so
+        // it sees its timer fire on time; a guest that waits again meanwhile
+        // comes straight back.
+        let due = due.map(timer_cal::compensate);
+        let now = hyper::read_cntpct();
+        if wakeup || due.is_some_and(|due| now >= due) {
+            return;
+        }
+        let timeout = due.map_or(Tick::MAX, |due| {
+            let cycles = due - now;
+            // Rounded up, so the timer is due by the time the thread wakes.
+            Tick::from_nanos(time::from_clock_cycles(cycles).as_nanos() as u64).add(Tick(1))
+        });
+        set_run_state(id, RunState::Blocked);
+        vcpu_manager().wait_wake(id, timeout);
+    }
+}
+
+// Run a wait that needn't end soon with the run loop's watch paused, so the
+// watchdog doesn't take a parked vCPU for a stuck one.
+macro_rules! unwatched {
+    ($watch:ident, $wait:expr) => {{
+        #[cfg(soft_watchdog)]
+        $watch.pause();
+        let ret = $wait;
+        #[cfg(soft_watchdog)]
+        $watch.resume();
+        ret
+    }};
+}
+
+fn run_loop(id: usize) -> Result<ExitCode, VcpuError> {
+    // A guest exits at least on host timer interrupts, so a run loop that
+    // stops coming back means EL2 is stuck. Not on a dedicated core, where
+    // the host tick is masked and a busy guest may never exit.
+    #[cfg(soft_watchdog)]
+    let watch = crate::watchdog::track("vcpu run loop");
+    loop {
+        set_run_state(id, RunState::Running);
+        vcpu_manager().with_vcpu(id, ptimer::sync);
+        #[cfg(smp)]
+        let dedicated = scheduler::dedicated_cores() & (1 << current_cpu_id()) != 0;
+        #[cfg(not(smp))]
+        let dedicated = false;
+        let ret = if dedicated {
+            unwatched!(watch, hvc_call(HVC_VCPU_RUN, id as u64, 0))
+        } else {
+            hvc_call(HVC_VCPU_RUN, id as u64, 0)
+        };
+        let code = ExitCode::decode(ret);
+        vcpu_manager().wake_deferred();
+        #[cfg(soft_watchdog)]
+        watch.pet();
+        el2_stack::check(current_cpu_id());
+        let anomaly = match code {
+            ExitCode::Invalid => None,
+            code => vcpu_manager()
+                .with_vcpu_mut(id, |vcpu| {
+                    vcpu.last_exit = Some(code);
+                    trace::record_exit(vcpu, vcpu.exit_cycles, code);
+                    vcpu.boost.decay();
+                    let changed = vcpu.stats.record_exit(code);
+                    if let (FastPath::Adaptive, Some(profile)) = (vcpu.config.fast_path, changed) {
+                        vcpu.vgic.profile = profile;
+                    }
+                    strict::check_vcpu(vcpu)
+                })
+                .flatten(),
+        };
+        if let Some(anomaly) = anomaly {
+            // Shared, so the panic can still show the vCPU.
+            vcpu_manager().with_vcpu(id, |vcpu| strict::fail(vcpu, Some(anomaly)));
+        }
+        match code {
+            // The IRQ that forced the exit is taken as soon as EL2 returns to us.
+            ExitCode::HostIrq => {}
+            ExitCode::Wfi | ExitCode::Suspended => unwatched!(watch, wait_for_wakeup(id)),
+            ExitCode::Kick(reasons) => {
+                if kick::has_reason(reasons, KickReason::StopRequest) {
+                    vcpu_manager().with_vcpu_mut(id, |vcpu| vcpu.state = VcpuState::Stopped);
+                    return Ok(code);
+                }
+                if kick::has_reason(reasons, KickReason::Freeze) {
+                    set_run_state(id, RunState::Blocked);
+                    host_pm::left_guest();
+                    unwatched!(watch, {
+                        while host_pm::is_frozen() {
+                            vcpu_manager().wait_wake(id, Tick::MAX);
+                        }
+                    });
+                }
+                if kick::has_reason(reasons, KickReason::Reset) {
+                    vcpu_manager().with_vcpu_mut(id, Vcpu::reset);
+                    unwatched!(watch, wait_while(id, VcpuState::Off));
+                    continue;
+                }
+                if kick::has_reason(reasons, KickReason::Reschedule) {
+                    scheduler::yield_me();
+                }
+            }
+            ExitCode::Shutdown(_) | ExitCode::Fault => return Ok(code),
+            ExitCode::VmFailed => {
+                if let Some(vm_id) = vcpu_manager().vm_of(id) {
+                    stop_vm(vm_id);
+                }
+                return Ok(ExitCode::Fault);
+            }
+            ExitCode::Anomaly(kind) => {
+                vcpu_manager().with_vcpu(id, |vcpu| strict::fail(vcpu, Anomaly::from_raw(kind)));
+                return Ok(code);
+            }
+            ExitCode::Reset => {
+                if let Some(vm_id) = vcpu_manager().vm_of(id) {
+                    reset_vm(vm_id);
+                }
+            }
+            ExitCode::Paused => {
+                vcpu_manager().with_vcpu(id, |vcpu| {
+                    vlog!(
+                        Vcpu,
+                        Warn,
+                        "vcpu {} of {} paused at pc {:#x}, esr {:#x}",
+                        id,
+                        identity::tag(vcpu.vm_id),
+                        vcpu.regs.elr,
+                        vcpu.exit_esr
+                    );
+                });
+                unwatched!(watch, wait_while(id, VcpuState::Blocked));
+            }
+            // Another vCPU's CPU_ON brings it back.
+            ExitCode::PowerOff => unwatched!(watch, wait_while(id, VcpuState::Off)),
+            ExitCode::Doorbell(n) => {
+                if let Some(vm_id) = vcpu_manager().vm_of(id) {
+                    doorbell::ring(vm_id, n);
+                }
+            }
+            ExitCode::Copy => {
+                vcpu_manager().with_vcpu_mut(id, copy::submit);
+            }
+            ExitCode::CpuOn(index) => vpsci::start_secondary(id, index as usize),
+            ExitCode::GrantMap(imm) => {
+                vcpu_manager().with_vcpu_mut(id, |vcpu| grant::handle_map(vcpu, imm));
+            }
+            // The write that faulted runs again on the next entry.
+            ExitCode::Populate(ipa) => {
+                let Some(vm_id) = vcpu_manager().vm_of(id) else {
+                    continue;
+                };
+                if let Err(e) = lazy_ram::populate(vm_id, ipa) {
+                    vlog!(
+                        Vcpu,
+                        Error,
+                        "vcpu {} of {}: no memory for ipa {:#x}: {:?}",
+                        id,
+                        identity::tag(vm_id),
+                        ipa,
+                        e
+                    );
+                    return Ok(ExitCode::Fault);
+                }
+            }
+            ExitCode::Invalid => return Err(VcpuError::InvalidId),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::{hal::with_mock, profile::GuestProfile, test_vm_id};
+    use blueos_test_macro::test;
+
+    // Spins for x0 ms of the virtual counter, then shuts down. Takes no
+    // trap before the shutdown call.
+    #[cfg(all(smp, soft_watchdog))]
+    core::arch::global_asm!(
+        "
+.pushsection .rodata.guest_busy_loop, \"a\"
+.balign 4096
+.global __guest_busy_loop
+__guest_busy_loop:
+    mrs x1, cntfrq_el0
+    mul x1, x1, x0
+    mov x2, #1000
+    udiv x1, x1, x2
+    isb
+    mrs x2, cntvct_el0
+1:
+    mrs x3, cntvct_el0
+    sub x3, x3, x2
+    cmp x3, x1
+    b.lo 1b
+    hvc #{shutdown}
+    b .
+.balign 4096
+.popsection
+",
+        shutdown = const crate::arch::aarch64::virt::abi::GUEST_HVC_SHUTDOWN,
+    );
+
+    #[cfg(all(smp, soft_watchdog))]
+    extern "C" {
+        static __guest_busy_loop: u8;
+    }
+
+    #[test]
+    fn test_el1_context_switch() {
+        with_mock(|| {
+            let mut guest = El1Context::new();
+            assert_eq!(guest.sctlr_el1 & 1, 0);
+            guest.ttbr0_el1 = 0x4100_0000;
+            guest.tcr_el1 = 0x19;
+            guest.contextidr_el1 = 7;
+
+            let mut host = El1Context::new();
+            host.save();
+            guest.restore();
+            let mut saved = El1Context::new();
+            saved.save();
+            assert_eq!(saved.ttbr0_el1, 0x4100_0000);
+            assert_eq!(saved.tcr_el1, 0x19);
+            assert_eq!(saved.contextidr_el1, 7);
+            host.restore();
+            saved.save();
+            assert_eq!(saved.ttbr0_el1, host.ttbr0_el1);
+        });
+    }
+    #[test]
+    fn test_guest_hcr_traps_smc() {
+        for profile in [
+            GuestProfile::BareMetalTest,
+            GuestProfile::Rtos,
+            GuestProfile::Linux,
+        ] {
+            let hcr = guest_hcr(profile.config().traps, false);
+            assert_ne!(hcr & (1 << 19), 0);
+        }
+    }
+
+    #[test]
+    fn test_boot_args() {
+        let vm_id = test_vm_id();
+        let id = vcpu_manager()
+            .create_secondary_vcpu(vm_id, VmConfig::default())
+            .unwrap();
+        vcpu_manager().with_vcpu_mut(id, |vcpu| {
+            assert!(vcpu.power_on(0x4000_0000, 7).is_ok());
+            assert_eq!(vcpu.boot_args, Some([7, 0, 0, 0]));
+            vcpu.started = true;
+            assert!(matches!(
+                vcpu.set_boot_args(&[1, 2, 3, 4]),
+                Err(VcpuError::AlreadyStarted)
+            ));
+        });
+        assert!(vcpu_manager().destroy_vcpu(id).is_ok());
+    }
+
+    #[cfg(all(smp, soft_watchdog))]
+    #[test]
+    fn test_dedicated_core_not_stalled() {
+        use crate::arch::aarch64::virt::{
+            stage2::PAGE_SIZE,
+            vm::{vm_manager, VmBuilder},
+        };
+        const TIMEOUT_MS: u64 = blueos_kconfig::CONFIG_SOFT_WATCHDOG_TIMEOUT_MS as u64;
+        const IPA: u64 = 0x4000_0000;
+
+        let vm_id = test_vm_id();
+        let id = VmBuilder::new(vm_id, GuestProfile::BareMetalTest.config())
+            .rom(IPA, addr_of!(__guest_busy_loop) as u64, PAGE_SIZE, true)
+            // Long enough for the watchdog to report twice over.
+            .boot_vcpu(IPA, 3 * TIMEOUT_MS)
+            .build()
+            .unwrap()[0];
+        assert!(set_dedicated_core(id, Some(NUM_CORES - 1)).is_ok());
+        let reports = crate::watchdog::reports();
+        let ret = run_vcpu(id);
+        let exits = vcpu_manager().with_vcpu(id, |v| v.stats.exits).unwrap();
+        assert!(vm_manager().destroy(vm_id).is_ok());
+        status::clear(vm_id);
+        assert_eq!(ret, Ok(ExitCode::Shutdown(status::SUCCESS)));
+        // Only the shutdown.
+        assert_eq!(exits, 1);
+        assert_eq!(crate::watchdog::reports(), reports);
+    }
+
+    #[test]
+    fn test_vcpu_index_reused() {
+        let vm_id = test_vm_id();
+        let create = || vcpu_manager().create_vcpu(vm_id, 0, 0).unwrap();
+        let index = |id| vcpu_manager().with_vcpu(id, |vcpu| vcpu.index).unwrap();
+        let ids = [create(), create(), create()];
+        assert!(vcpu_manager().destroy_vcpu(ids[0]).is_ok());
+
+        // The freed index, not one the other vCPUs still have.
+        let new = create();
+        assert_eq!(index(new), 0);
+        assert_eq!((index(ids[1]), index(ids[2])), (1, 2));
+
+        for id in [new, ids[1], ids[2]] {
+            assert!(vcpu_manager().destroy_vcpu(id).is_ok());
+        }
+    }
+}
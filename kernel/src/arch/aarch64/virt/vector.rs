@@ -13,15 +13,72 @@
 // limitations under the License.
 
 use super::hyper;
-use core::arch::asm;
+#[cfg(virt_switch_latency)]
+use super::latency;
+#[cfg(virtualization)]
+use super::{
+    exit,
+    recovery::{self, TrapFailure},
+    stage2, vcpu,
+};
+use crate::arch::aarch64::sysreg::{read_sysreg, write_sysreg};
+use core::{arch::asm, mem::offset_of};
 
 static mut PRINTED_ALIGN: bool = false;
 const VECTOR_TABLE_SIZE: usize = 2048;
 const SYNC_EXCEPTION_OFFSET: usize = 0x400;
 
+// Store CNTPCT_EL0 into this core's slot of the per-core u64 array `$sym`,
+// clobbering x0 and x1. Expands to nothing unless switch latency is measured.
+#[cfg(virt_switch_latency)]
+macro_rules! latency_stamp {
+    ($sym:literal) => {
+        concat!(
+            "mrs x0, mpidr_el1\n",
+            "and x0, x0, #0xff\n",
+            "adrp x1, ",
+            $sym,
+            "\n",
+            "add x1, x1, :lo12:",
+            $sym,
+            "\n",
+            "add x1, x1, x0, lsl #3\n",
+            "isb\n",
+            "mrs x0, cntpct_el0\n",
+            "str x0, [x1]\n",
+        )
+    };
+}
+
+#[cfg(not(virt_switch_latency))]
+macro_rules! latency_stamp {
+    ($sym:literal) => {
+        ""
+    };
+}
+
+/// Registers the vectors push on an exception from a lower EL, at the EL2
+/// stack pointer. Handlers get a pointer to it; whatever they leave in it is
+/// restored on the eret. The other EL1 registers stay live across a trap and
+/// are only swapped on a world switch, see `vcpu::El1Context`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TrapFrame {
+    pub x: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    pub sp_el1: u64,
+}
+
+// The stack pointer has to stay 16-byte aligned.
+const FRAME_SIZE: usize = (core::mem::size_of::<TrapFrame>() + 15) & !15;
+const ELR_OFFSET: usize = offset_of!(TrapFrame, elr);
+const SPSR_OFFSET: usize = offset_of!(TrapFrame, spsr);
+const SP_EL1_OFFSET: usize = offset_of!(TrapFrame, sp_el1);
+
 core::arch::global_asm!(
     "
-.section .text.hyper_vector_table
+.section .hyp.text.vectors, \"ax\"
 .align 11
 .global hyper_vector_table
 hyper_vector_table:
@@ -78,10 +135,12 @@ pub fn get_vector_table_addr() -> usize {
 
 #[naked]
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn sync_from_lower_el1() {
     core::arch::naked_asm!(
-        "sub sp, sp, #272\n",
+        "sub sp, sp, #{size}\n",
         "stp x0, x1, [sp, #0]\n",
+        latency_stamp!("VIRT_LAT_ENTRY"),
         "stp x2, x3, [sp, #16]\n",
         "stp x4, x5, [sp, #32]\n",
         "stp x6, x7, [sp, #48]\n",
@@ -100,20 +159,19 @@ pub unsafe extern "C" fn sync_from_lower_el1() {
         "mrs x1, elr_el2\n",
         "mrs x2, spsr_el2\n",
         "mrs x3, sp_el1\n",
-        "str x1, [sp, #248]\n",
-        "str x2, [sp, #256]\n",
-        "str x3, [sp, #264]\n",
+        "str x1, [sp, #{elr}]\n",
+        "str x2, [sp, #{spsr}]\n",
+        "str x3, [sp, #{sp_el1}]\n",
         "mov x0, sp\n",
         "bl sync_from_lower_el1_rust\n",
         "cbz x0, 1f\n",
-        "ldr x1, [sp, #248]\n",
-        "ldr x2, [sp, #256]\n",
-        "ldr x3, [sp, #264]\n",
+        "ldr x1, [sp, #{elr}]\n",
+        "ldr x2, [sp, #{spsr}]\n",
+        "ldr x3, [sp, #{sp_el1}]\n",
         "msr elr_el2, x1\n",
         "msr spsr_el2, x2\n",
         "msr sp_el1, x3\n",
         "isb\n",
-        "ldp x0, x1, [sp, #0]\n",
         "ldp x2, x3, [sp, #16]\n",
         "ldp x4, x5, [sp, #32]\n",
         "ldp x6, x7, [sp, #48]\n",
@@ -129,33 +187,70 @@ pub unsafe extern "C" fn sync_from_lower_el1() {
         "ldp x26, x27, [sp, #208]\n",
         "ldp x28, x29, [sp, #224]\n",
         "ldr x30, [sp, #240]\n",
-        "add sp, sp, #272\n",
+        latency_stamp!("VIRT_LAT_RESTORE"),
+        "ldp x0, x1, [sp, #0]\n",
+        "add sp, sp, #{size}\n",
         "eret\n",
         "1:\n",
         "wfi\n",
-        "b 1b\n"
+        "b 1b\n",
+        size = const FRAME_SIZE,
+        elr = const ELR_OFFSET,
+        spsr = const SPSR_OFFSET,
+        sp_el1 = const SP_EL1_OFFSET,
     );
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sync_from_lower_el1_rust(frame: *mut u64) -> u64 {
-    let esr: u64;
-    let elr: u64;
-    asm!("mrs {}, esr_el2", out(reg) esr, options(nostack));
-    asm!("mrs {}, elr_el2", out(reg) elr, options(nostack));
+#[link_section = ".hyp.text"]
+pub unsafe extern "C" fn sync_from_lower_el1_rust(frame: *mut TrapFrame) -> u64 {
+    let esr = read_sysreg!("esr_el2");
     let ec = (esr >> 26) & 0x3F;
 
+    #[cfg(virt_switch_latency)]
+    latency::trap_entered();
+    #[cfg(virtualization)]
+    if let Some(ret) = vcpu::vcpu_manager().with_current_vcpu(|vcpu| {
+        #[cfg(virt_switch_latency)]
+        latency::exit_begin();
+        let ret = exit::handle_guest_sync(frame, vcpu);
+        #[cfg(virt_switch_latency)]
+        latency::exit_end();
+        ret
+    }) {
+        return ret;
+    }
+    #[cfg(virtualization)]
+    if recovery::in_guest() {
+        return recovery::trap_failed(frame, esr, TrapFailure::NoVcpu);
+    }
+
     // EC = 0x16 (HVC64)
     if ec == 0x16 {
-        let func_id = *frame.add(0);
+        let func_id = (*frame).x[0];
 
         match func_id {
             0x00 => {
                 let el = hyper::get_current_el();
-                core::ptr::write_volatile(frame.add(0), 0u64);
+                (*frame).x[0] = 0;
+            }
+            #[cfg(virtualization)]
+            vcpu::HVC_VCPU_RUN => {
+                vcpu::enter_guest(frame, (*frame).x[1] as usize);
             }
+            #[cfg(virtualization)]
+            stage2::HVC_S2_TLB_FLUSH => {
+                stage2::flush_tlb_el2((*frame).x[1], (*frame).x[2]);
+                (*frame).x[0] = 0;
+            }
+            #[cfg(virtualization)]
+            _ => {
+                return recovery::trap_failed(frame, esr, TrapFailure::UnknownHostHvc(func_id));
+            }
+            // Fails like an unknown SMCCC call, as `recovery` answers it.
+            #[cfg(not(virtualization))]
             _ => {
-                panic!("[EL2] Unknown Host HVC:{} ", func_id);
+                (*frame).x[0] = u64::MAX;
             }
         }
         return 1; // Resume
@@ -163,10 +258,13 @@ pub unsafe extern "C" fn sync_from_lower_el1_rust(frame: *mut u64) -> u64 {
 
     // EC = 0x07 (Access to SIMD/FP)
     if ec == 0x07 {
-        asm!("msr cptr_el2, xzr");
+        write_sysreg!("cptr_el2", 0);
         return 1;
     }
 
+    #[cfg(virtualization)]
+    return recovery::trap_failed(frame, esr, TrapFailure::UnhandledHostTrap { ec });
+    #[cfg(not(virtualization))]
     0
 }
 
@@ -177,10 +275,12 @@ const HCR_EL2_VF: u64 = 1 << 6;
 /// Solve irq from lower el1.
 #[naked]
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn irq_from_lower_el1() {
     core::arch::naked_asm!(
-        "sub sp, sp, #272\n",
+        "sub sp, sp, #{size}\n",
         "stp x0, x1, [sp, #0]\n",
+        latency_stamp!("VIRT_LAT_ENTRY"),
         "stp x2, x3, [sp, #16]\n",
         "stp x4, x5, [sp, #32]\n",
         "stp x6, x7, [sp, #48]\n",
@@ -198,17 +298,21 @@ pub unsafe extern "C" fn irq_from_lower_el1() {
         "str x30, [sp, #240]\n",
         "mrs x1, elr_el2\n",
         "mrs x2, spsr_el2\n",
-        "str x1, [sp, #248]\n",
-        "str x2, [sp, #256]\n",
+        "mrs x3, sp_el1\n",
+        "str x1, [sp, #{elr}]\n",
+        "str x2, [sp, #{spsr}]\n",
+        "str x3, [sp, #{sp_el1}]\n",
         "mov x0, sp\n",
         "mov x19, sp\n",
         "bl hyper_trap_irq\n",
         "mov sp, x19\n",
-        "ldr x1, [sp, #248]\n",
-        "ldr x2, [sp, #256]\n",
+        "ldr x1, [sp, #{elr}]\n",
+        "ldr x2, [sp, #{spsr}]\n",
+        "ldr x3, [sp, #{sp_el1}]\n",
         "msr elr_el2, x1\n",
         "msr spsr_el2, x2\n",
-        "ldp x0, x1, [sp, #0]\n",
+        "msr sp_el1, x3\n",
+        "isb\n",
         "ldp x2, x3, [sp, #16]\n",
         "ldp x4, x5, [sp, #32]\n",
         "ldp x6, x7, [sp, #48]\n",
@@ -224,17 +328,24 @@ pub unsafe extern "C" fn irq_from_lower_el1() {
         "ldp x26, x27, [sp, #208]\n",
         "ldp x28, x29, [sp, #224]\n",
         "ldr x30, [sp, #240]\n",
-        "add sp, sp, #272\n",
+        latency_stamp!("VIRT_LAT_RESTORE"),
+        "ldp x0, x1, [sp, #0]\n",
+        "add sp, sp, #{size}\n",
         "eret\n",
+        size = const FRAME_SIZE,
+        elr = const ELR_OFFSET,
+        spsr = const SPSR_OFFSET,
+        sp_el1 = const SP_EL1_OFFSET,
     );
 }
 
 /// Solve fiq from lower el1.
 #[naked]
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn fiq_from_lower_el1() {
     core::arch::naked_asm!(
-        "sub sp, sp, #272\n",
+        "sub sp, sp, #{size}\n",
         "stp x0, x1, [sp, #0]\n",
         "stp x2, x3, [sp, #16]\n",
         "stp x4, x5, [sp, #32]\n",
@@ -253,11 +364,11 @@ pub unsafe extern "C" fn fiq_from_lower_el1() {
         "str x30, [sp, #240]\n",
         "mrs x1, elr_el2\n",
         "mrs x2, spsr_el2\n",
-        "str x1, [sp, #248]\n",
-        "str x2, [sp, #256]\n",
+        "str x1, [sp, #{elr}]\n",
+        "str x2, [sp, #{spsr}]\n",
         "mov x0, sp\n",
         "bl hyper_trap_fiq\n",
-        "ldr x1, [sp, #248]\n",
+        "ldr x1, [sp, #{elr}]\n",
         "msr elr_el2, x1\n",
         "ldp x0, x1, [sp, #0]\n",
         "ldp x2, x3, [sp, #16]\n",
@@ -275,19 +386,24 @@ pub unsafe extern "C" fn fiq_from_lower_el1() {
         "ldp x26, x27, [sp, #208]\n",
         "ldp x28, x29, [sp, #224]\n",
         "ldr x30, [sp, #240]\n",
-        "add sp, sp, #272\n",
+        "add sp, sp, #{size}\n",
         "eret\n",
+        size = const FRAME_SIZE,
+        elr = const ELR_OFFSET,
+        spsr = const SPSR_OFFSET,
     );
 }
 
 /// Solve serror from lower el1.
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn serror_from_lower_el1() {
     asm!("eret", options(noreturn));
 }
 
 /// Solve sync exception from lower el2 sp0.
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn sync_current_sp0() {
     loop {
         asm!("wfi");
@@ -295,13 +411,12 @@ pub unsafe extern "C" fn sync_current_sp0() {
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn sync_current_spx() {
-    let esr: u64;
-    let elr: u64;
-    let far: u64;
-    asm!("mrs {}, esr_el2", out(reg) esr, options(nostack));
-    asm!("mrs {}, elr_el2", out(reg) elr, options(nostack));
-    asm!("mrs {}, far_el2", out(reg) far, options(nostack));
+    let esr = read_sysreg!("esr_el2");
+    // Left in registers for a debugger.
+    let _elr = read_sysreg!("elr_el2");
+    let _far = read_sysreg!("far_el2");
 
     // Attempt to decode syndrome
     let ec = (esr >> 26) & 0x3F;
@@ -317,21 +432,20 @@ pub unsafe extern "C" fn sync_current_spx() {
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn sync_current_el1() {
-    let esr: u64;
-    let elr: u64;
-    let far: u64;
-    let spsr: u64;
-    asm!("mrs {}, esr_el2", out(reg) esr, options(nostack));
-    asm!("mrs {}, elr_el2", out(reg) elr, options(nostack));
-    asm!("mrs {}, far_el2", out(reg) far, options(nostack));
-    asm!("mrs {}, spsr_el2", out(reg) spsr, options(nostack));
+    // Left in registers for a debugger.
+    let _esr = read_sysreg!("esr_el2");
+    let _elr = read_sysreg!("elr_el2");
+    let _far = read_sysreg!("far_el2");
+    let _spsr = read_sysreg!("spsr_el2");
     loop {
         asm!("wfi");
     }
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn sync_current_el0() {
     loop {
         asm!("wfi");
@@ -339,6 +453,7 @@ pub unsafe extern "C" fn sync_current_el0() {
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn irq_current() {
     loop {
         asm!("wfi");
@@ -346,6 +461,7 @@ pub unsafe extern "C" fn irq_current() {
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn fiq_current() {
     loop {
         asm!("wfi");
@@ -353,8 +469,31 @@ pub unsafe extern "C" fn fiq_current() {
 }
 
 #[no_mangle]
+#[link_section = ".hyp.text"]
 pub unsafe extern "C" fn serror_current() {
     loop {
         asm!("wfi");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::mem::{align_of, size_of};
+
+    #[test]
+    fn test_trap_frame_layout() {
+        // The stp/ldp sequences in the vectors store x0-x30 at fixed
+        // offsets; only the fields after them come from the struct.
+        assert_eq!(offset_of!(TrapFrame, x), 0);
+        assert_eq!(size_of::<[u64; 31]>(), 248);
+        assert_eq!(ELR_OFFSET, 248);
+        assert_eq!(SPSR_OFFSET, 256);
+        assert_eq!(SP_EL1_OFFSET, 264);
+        assert_eq!(align_of::<TrapFrame>(), 8);
+        assert!(size_of::<TrapFrame>() <= FRAME_SIZE);
+        assert_eq!(FRAME_SIZE % 16, 0);
+        assert_eq!(FRAME_SIZE, 272);
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest image verification before a VM is built. A VM described with
+//! `VmBuilder::verify_image` has the SHA-256 digest of its image computed
+//! from the memory it is about to get and compared with the expected one,
+//! or handed with a signature to the check a crypto module registered with
+//! `set_signature_check`, e.g. against a key embedded in the kernel. The
+//! `Policy` decides whether a guest that didn't verify is started.
+
+use super::guest_mem::GuestMemory;
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// SHA-256 digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Digest(pub [u8; 32]);
+
+impl Digest {
+    /// Parse 64 hex digits.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 64 {
+            return None;
+        }
+        let mut digest = [0; 32];
+        for (n, pair) in hex.chunks(2).enumerate() {
+            let pair = core::str::from_utf8(pair).ok()?;
+            digest[n] = u8::from_str_radix(pair, 16).ok()?;
+        }
+        Some(Self(digest))
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256, FIPS 180-4.
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    // Bytes in `block`.
+    used: usize,
+    // Bytes hashed in total.
+    len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: H0,
+            block: [0; 64],
+            used: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.used).min(data.len());
+            self.block[self.used..self.used + n].copy_from_slice(&data[..n]);
+            self.used += n;
+            data = &data[n..];
+            if self.used == 64 {
+                self.compress();
+                self.used = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Digest {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.used != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (out, word) in digest.chunks_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        Digest(digest)
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (n, word) in self.block.chunks(4).enumerate() {
+            w[n] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for n in 16..64 {
+            let s0 = w[n - 15].rotate_right(7) ^ w[n - 15].rotate_right(18) ^ (w[n - 15] >> 3);
+            let s1 = w[n - 2].rotate_right(17) ^ w[n - 2].rotate_right(19) ^ (w[n - 2] >> 10);
+            w[n] = w[n - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[n - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for n in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[n])
+                .wrapping_add(w[n]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a guest image must match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    Sha256(Digest),
+    /// A signature over the image's digest, for the registered check.
+    Signature(&'static [u8]),
+}
+
+/// Checks `signature` over an image with `digest`.
+pub type SignatureCheck = fn(digest: &Digest, signature: &[u8]) -> bool;
+
+/// What verifying a VM's image found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The image matched.
+    Verified,
+    /// The VM has no expected value.
+    Unverified,
+    /// The image didn't match; its digest.
+    Mismatch(Digest),
+    /// A signature is expected but no check is registered.
+    NoCheck,
+    /// Part of the image isn't backed by memory yet, e.g. lazy RAM.
+    Unreadable(u64),
+}
+
+/// Whether guests that don't verify are started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Policy {
+    /// Images aren't looked at.
+    Off,
+    /// Start them anyway, logging why they didn't verify.
+    Warn,
+    /// Only start verified guests.
+    Enforce,
+}
+
+impl Policy {
+    pub const ALL: [Policy; 3] = [Policy::Off, Policy::Warn, Policy::Enforce];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::Off => "off",
+            Policy::Warn => "warn",
+            Policy::Enforce => "enforce",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Warn as u8);
+static SIGNATURE_CHECK: SpinLock<Option<SignatureCheck>> = SpinLock::new(None);
+// Outcome of the last build of each VM.
+static OUTCOMES: SpinLock<BTreeMap<usize, Outcome>> = SpinLock::new(BTreeMap::new());
+
+pub fn policy() -> Policy {
+    Policy::ALL[POLICY.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Verify `Expected::Signature` images with `check` from now on.
+pub fn set_signature_check(check: SignatureCheck) {
+    *SIGNATURE_CHECK.irqsave_lock() = Some(check);
+}
+
+/// SHA-256 of the `size` bytes at `ipa` of `mem`.
+pub fn digest(mem: &impl GuestMemory, ipa: u64, size: u64) -> Result<Digest, Outcome> {
+    let mut sha = Sha256::new();
+    let mut buf = [0; 256];
+    let mut at = ipa;
+    while at < ipa + size {
+        let n = (ipa + size - at).min(buf.len() as u64) as usize;
+        mem.read(at, &mut buf[..n])
+            .map_err(|_| Outcome::Unreadable(at))?;
+        sha.update(&buf[..n]);
+        at += n as u64;
+    }
+    Ok(sha.finish())
+}
+
+/// Check the image of `size` bytes at `ipa` of `mem` against `expected`.
+pub fn check(mem: &impl GuestMemory, ipa: u64, size: u64, expected: Expected) -> Outcome {
+    let digest = match digest(mem, ipa, size) {
+        Ok(digest) => digest,
+        Err(outcome) => return outcome,
+    };
+    let verified = match expected {
+        Expected::Sha256(want) => want == digest,
+        Expected::Signature(signature) => {
+            // Copied out, so the check runs without the lock.
+            let check = *SIGNATURE_CHECK.irqsave_lock();
+            match check {
+                Some(check) => check(&digest, signature),
+                None => return Outcome::NoCheck,
+            }
+        }
+    };
+    if verified {
+        Outcome::Verified
+    } else {
+        Outcome::Mismatch(digest)
+    }
+}
+
+/// Record `outcome` for VM `vm_id` and tell whether the policy lets the VM
+/// start.
+pub fn admit(vm_id: usize, outcome: Outcome) -> bool {
+    let policy = policy();
+    OUTCOMES.irqsave_lock().insert(vm_id, outcome);
+    match outcome {
+        Outcome::Verified => log::info!("[virt] vm {} image verified", vm_id),
+        Outcome::Unverified => {}
+        Outcome::Mismatch(digest) => {
+            log::warn!("[virt] vm {} image mismatch, digest {}", vm_id, digest)
+        }
+        Outcome::NoCheck => log::warn!("[virt] vm {} image signed but no check", vm_id),
+        Outcome::Unreadable(ipa) => {
+            log::warn!("[virt] vm {} image unreadable at {:#x}", vm_id, ipa)
+        }
+    }
+    policy != Policy::Enforce || outcome == Outcome::Verified
+}
+
+/// Outcome of the last build of VM `vm_id`.
+pub fn outcome(vm_id: usize) -> Option<Outcome> {
+    OUTCOMES.irqsave_lock().get(&vm_id).copied()
+}
+
+/// Outcomes by VM.
+pub fn outcomes() -> BTreeMap<usize, Outcome> {
+    OUTCOMES.irqsave_lock().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use alloc::string::ToString;
+    use blueos_test_macro::test;
+
+    fn sha256(data: &[u8]) -> Digest {
+        let mut sha = Sha256::new();
+        sha.update(data);
+        sha.finish()
+    }
+
+    #[test]
+    fn test_sha256_vectors() {
+        assert_eq!(
+            sha256(b""),
+            Digest::from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                .unwrap()
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            Digest::from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                .unwrap()
+        );
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(
+            sha256(long).to_string(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        // Split updates hash like one.
+        let mut sha = Sha256::new();
+        sha.update(&long[..7]);
+        sha.update(&long[7..]);
+        assert_eq!(sha.finish(), sha256(long));
+        assert_eq!(Digest::from_hex("00"), None);
+    }
+
+    #[test]
+    fn test_verify_policy() {
+        let vm_id = test_vm_id();
+        set_policy(Policy::Enforce);
+        assert!(admit(vm_id, Outcome::Verified));
+        assert!(!admit(vm_id, Outcome::Unverified));
+        assert_eq!(outcome(vm_id), Some(Outcome::Unverified));
+        set_policy(Policy::Warn);
+        assert!(admit(vm_id, Outcome::NoCheck));
+        OUTCOMES.irqsave_lock().remove(&vm_id);
+    }
+}
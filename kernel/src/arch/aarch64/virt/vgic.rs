@@ -0,0 +1,961 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    adaptive::ExitProfile,
+    alternative::{self, Feature},
+    hal::{gic, sysregs, GicBackend, SysRegBackend},
+    ring::Ring,
+    vgicr::Redistributor,
+    vlog::{vlog, vlog_limited},
+};
+use crate::{
+    arch::aarch64::{
+        current_cpu_id,
+        irq::{self, IrqHandler, IrqNumber, Priority},
+    },
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+// Only the list registers every GICv3 implementation provides are used.
+pub const NUM_LRS: usize = 4;
+/// SGIs, PPIs and SPIs. INTIDs from here on are special or LPIs.
+pub const MAX_INTID: u32 = 1020;
+/// Interrupts a vCPU can have waiting for a list register.
+pub const MAX_PENDING: usize = blueos_kconfig::CONFIG_VIRT_VGIC_PENDING as usize;
+
+const LR_STATE_SHIFT: u64 = 62;
+const LR_STATE_MASK: u64 = 0b11 << LR_STATE_SHIFT;
+const LR_STATE_PENDING: u64 = 0b01 << LR_STATE_SHIFT;
+const LR_GROUP1: u64 = 1 << 60;
+// Maintenance interrupt once the guest deactivates the interrupt.
+const LR_EOI: u64 = 1 << 41;
+const LR_PRIORITY_SHIFT: u64 = 48;
+const LR_INTID_MASK: u64 = 0xffff_ffff;
+
+/// Priority of interrupts injected without one. Lower is more urgent.
+pub const DEFAULT_PRIORITY: u8 = 0xa0;
+/// Priority of virtual FIQs, see `Vgic::inject_fiq`.
+pub const FIQ_PRIORITY: u8 = 0x40;
+/// Interrupts with a priority value below this are urgent. One list
+/// register is kept for them, so a burst of other interrupts can't hold
+/// e.g. a timer back until the guest EOIs one of the burst.
+pub const URGENT_PRIORITY: u8 = blueos_kconfig::CONFIG_VIRT_VGIC_URGENT_PRIORITY as u8;
+/// Priority of the guest's timer PPIs, urgent so they get the reserved
+/// list register.
+pub const TIMER_PRIORITY: u8 = URGENT_PRIORITY.saturating_sub(0x10);
+// List registers only urgent interrupts may take.
+const RESERVED_LRS: usize = 1;
+// Words of a bitmap with one bit per INTID.
+const INTID_WORDS: usize = (MAX_INTID as usize).div_ceil(32);
+
+const ICH_HCR_EN: u64 = 1;
+// Maintenance interrupt once at most one list register holds an interrupt.
+const ICH_HCR_UIE: u64 = 1 << 1;
+// Maintenance interrupt while the guest EOIed interrupts no list register
+// held, counted in ICH_HCR_EL2.EOIcount until the next write clears it.
+const ICH_HCR_LRENPIE: u64 = 1 << 2;
+// What `flush` arms while interrupts wait for a list register.
+const ICH_HCR_REFILL: u64 = ICH_HCR_EN | ICH_HCR_UIE | ICH_HCR_LRENPIE;
+/// PPI the virtual CPU interface raises maintenance interrupts on.
+pub const MAINTENANCE_PPI: u32 = 25;
+// VPMR = 0xff, VENG1 = 1.
+const ICH_VMCR_DEFAULT: u64 = (0xff << 24) | (1 << 1);
+// ICH_AP0R<n>_EL2 and ICH_AP1R<n>_EL2 an implementation can have.
+const MAX_APRS: usize = 4;
+// Those this GIC has, one per 32 preemption levels, see `init`.
+static APRS: AtomicUsize = AtomicUsize::new(1);
+
+/// Enable the virtual CPU interface of this core. Runs at EL2.
+pub fn init() -> Result<(), &'static str> {
+    if !alternative::has(Feature::GicV3) {
+        return Err("no GIC system register interface");
+    }
+    let vtr = gic().vtr();
+    // ICH_VTR_EL2.ListRegs is the number of list registers minus one.
+    if (vtr & 0x1f) as usize + 1 < NUM_LRS {
+        return Err("too few list registers");
+    }
+    // ICH_VTR_EL2.PREbits is the number of preemption bits minus one, at
+    // least five of them.
+    let pre_bits = ((vtr >> 26) & 0x7) as usize + 1;
+    APRS.store(
+        (1 << pre_bits.saturating_sub(5)).min(MAX_APRS),
+        Ordering::Relaxed,
+    );
+    gic().write_vmcr(ICH_VMCR_DEFAULT);
+    gic().write_hcr(ICH_HCR_EN);
+    for n in 0..NUM_LRS {
+        gic().write_lr(n, 0);
+    }
+    sysregs().isb();
+    Ok(())
+}
+
+// State field of a list register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LrState {
+    Invalid = 0,
+    Pending = 1,
+    Active = 2,
+    PendingActive = 3,
+}
+
+impl LrState {
+    fn of(lr: u64) -> Self {
+        match (lr & LR_STATE_MASK) >> LR_STATE_SHIFT {
+            0 => Self::Invalid,
+            1 => Self::Pending,
+            2 => Self::Active,
+            _ => Self::PendingActive,
+        }
+    }
+
+    fn set(self, lr: u64) -> u64 {
+        (lr & !LR_STATE_MASK) | ((self as u64) << LR_STATE_SHIFT)
+    }
+
+    // Whether the guest can take a list register from this state to `to`
+    // by acknowledging and EOIing. Only the hypervisor makes an interrupt
+    // pending.
+    fn can_become(self, to: Self) -> bool {
+        match self {
+            Self::Invalid => to == Self::Invalid,
+            Self::Pending => to != Self::PendingActive,
+            Self::Active => matches!(to, Self::Active | Self::Invalid),
+            Self::PendingActive => true,
+        }
+    }
+}
+
+// Whether the guest could have turned list register contents `loaded`
+// into `lr`: a legal state change, the rest unchanged.
+fn lr_consistent(loaded: u64, lr: u64) -> bool {
+    let (from, to) = (LrState::of(loaded), LrState::of(lr));
+    from.can_become(to) && (to == LrState::Invalid || (lr ^ loaded) & !LR_STATE_MASK == 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    intid: u32,
+    priority: u8,
+    // Group 0, which the guest takes as an FIQ.
+    fiq: bool,
+}
+
+impl Pending {
+    // Whether the redistributor, if any, lets the interrupt through.
+    fn is_enabled(&self, redist: Option<&Redistributor>) -> bool {
+        redist.map_or(true, |r| r.is_enabled(self.intid))
+    }
+
+    // The interrupt with the group and priority the guest gave it, if it
+    // did. For SGIs and PPIs the guest's GICR_IGROUPR0 decides whether it
+    // takes them as FIQs, as on hardware.
+    fn prioritized(self, redist: Option<&Redistributor>) -> Self {
+        let Some(r) = redist else {
+            return self;
+        };
+        Self {
+            priority: r.priority(self.intid).unwrap_or(self.priority),
+            fiq: r.is_group0(self.intid).unwrap_or(self.fiq),
+            ..self
+        }
+    }
+
+    fn is_urgent(&self) -> bool {
+        self.priority < URGENT_PRIORITY
+    }
+
+    fn to_lr(self) -> u64 {
+        let group = if self.fiq { 0 } else { LR_GROUP1 };
+        LR_STATE_PENDING | group | ((self.priority as u64) << LR_PRIORITY_SHIFT) | self.intid as u64
+    }
+
+    fn from_lr(lr: u64) -> Self {
+        Self {
+            intid: (lr & LR_INTID_MASK) as u32,
+            priority: (lr >> LR_PRIORITY_SHIFT) as u8,
+            fiq: lr & LR_GROUP1 == 0,
+        }
+    }
+}
+
+// Interrupts waiting for a list register, taken most urgent first and in
+// arrival order among equals. The priority is looked up as they are taken,
+// since the guest may reprogram IPRIORITYR while one waits.
+struct PendingQueue(Ring<Pending, MAX_PENDING>);
+
+impl PendingQueue {
+    const fn new() -> Self {
+        Self(Ring::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Pending> {
+        self.0.iter()
+    }
+
+    // Queue `p`, merging it into a queued one with the same INTID, which
+    // keeps the more urgent priority. Hands `p` back if there's no room.
+    fn insert(&mut self, p: Pending) -> Result<(), Pending> {
+        if let Some(queued) = self.0.iter_mut().find(|q| q.intid == p.intid) {
+            queued.priority = queued.priority.min(p.priority);
+            return Ok(());
+        }
+        self.0.push_back(p)
+    }
+
+    fn remove(&mut self, intid: u32) -> Option<Pending> {
+        self.0.remove_first(|p| p.intid == intid)
+    }
+
+    // The most urgent interrupt matching `pred`, with the priority the
+    // guest gave it.
+    fn highest(
+        &self,
+        redist: Option<&Redistributor>,
+        pred: impl Fn(&Pending) -> bool,
+    ) -> Option<Pending> {
+        self.0
+            .iter()
+            .filter(|p| pred(p))
+            .map(|p| p.prioritized(redist))
+            .min_by_key(|p| p.priority)
+    }
+}
+
+// One bit per INTID, changed by the host while EL2 reads it.
+struct IntidSet([AtomicU32; INTID_WORDS]);
+
+impl IntidSet {
+    const fn new() -> Self {
+        Self([const { AtomicU32::new(0) }; INTID_WORDS])
+    }
+
+    fn contains(&self, intid: u32) -> bool {
+        self.0[intid as usize / 32].load(Ordering::Acquire) & (1 << (intid % 32)) != 0
+    }
+
+    // Returns whether `intid` was in the set.
+    fn insert(&self, intid: u32) -> bool {
+        let bit = 1 << (intid % 32);
+        self.0[intid as usize / 32].fetch_or(bit, Ordering::AcqRel) & bit != 0
+    }
+
+    fn remove(&self, intid: u32) -> bool {
+        let bit = 1 << (intid % 32);
+        self.0[intid as usize / 32].fetch_and(!bit, Ordering::AcqRel) & bit != 0
+    }
+}
+
+/// Enable the maintenance interrupt on the calling core. It only fires at
+/// EL2 while a guest runs, see `Vgic::refill`.
+pub fn cpu_init() {
+    let irq = IrqNumber::new(MAINTENANCE_PPI);
+    if current_cpu_id() == 0 {
+        let _ = irq::register_handler(irq, Box::new(MaintenanceIrq));
+    }
+    irq::enable_irq_with_priority(irq, current_cpu_id(), Priority::High);
+}
+
+struct MaintenanceIrq;
+
+impl IrqHandler for MaintenanceIrq {
+    // Raised for a guest that has left by now; its next entry refills the
+    // list registers anyway.
+    fn handle(&mut self) {}
+}
+
+/// Per-vCPU virtual interrupt state: interrupts waiting for a list register
+/// plus the list register contents while the vCPU is switched out.
+///
+/// When more interrupts are pending than list registers are free, `flush`
+/// asks for an underflow maintenance interrupt. It fires once the guest
+/// has EOIed all but one of the loaded interrupts, or EOIed one no list
+/// register held, and `refill` loads the
+/// next ones without the vCPU leaving the guest, so a burst drains at the
+/// guest's pace rather than at the next unrelated exit.
+///
+/// `sync` checks each list register against what `flush` loaded into it.
+/// One in a state the guest couldn't have brought it to, or naming
+/// another interrupt, is counted in `lr_mismatches` and taken to hold what
+/// was loaded, so a corrupted list register delays an interrupt rather
+/// than losing or inventing one. An interrupt the guest took keeps its
+/// list register until the guest EOIs it, across switches; raised again
+/// meanwhile it becomes pending and active there instead of taking a
+/// second one.
+///
+/// Interrupts are edge-triggered unless driven with `set_level`. A level
+/// interrupt lowered before the guest took it is withdrawn on the next
+/// switch. Its list register asks for a maintenance interrupt when the
+/// guest EOIs it, and `refill` makes it pending again there if it is still
+/// high, without waiting for the vCPU to leave the guest.
+///
+/// Waiting interrupts go out most urgent first. One more urgent than an
+/// interrupt loaded but not yet taken by the guest preempts it: the two
+/// swap places, as a GIC would signal the more urgent one first. Equal
+/// priorities never preempt each other.
+///
+/// With a `Redistributor`, SGIs and PPIs the guest disabled stay queued
+/// until it enables them, and go out with the priority it gave them.
+///
+/// The rest of the virtual CPU interface, ICH_VMCR_EL2 with the guest's
+/// priority mask and group enables, and the active priorities of the
+/// interrupts it is handling, is saved by `sync` and restored by `flush`
+/// with the list registers, so vCPUs sharing a core don't see each other's.
+pub struct Vgic {
+    pending: SpinLock<PendingQueue>,
+    lrs: [u64; NUM_LRS],
+    vmcr: u64,
+    // ICH_AP0R<n>_EL2 and ICH_AP1R<n>_EL2.
+    aprs: [[u64; MAX_APRS]; 2],
+    // Interrupts driven with `set_level`, and those of them that are high.
+    level: IntidSet,
+    asserted: IntidSet,
+    // Interrupts the pending queue had no room for.
+    dropped: AtomicU32,
+    pub(crate) profile: ExitProfile,
+    /// Emulated redistributor of the vCPU, see `vgicr`.
+    pub(crate) redist: Option<Arc<Redistributor>>,
+    // Whether `flush` asked for an underflow maintenance interrupt.
+    underflow: bool,
+    pub flushes_skipped: u64,
+    pub syncs_skipped: u64,
+    /// List register refills on maintenance interrupts, see `refill`.
+    pub refills: u64,
+    /// List registers `sync` found in a state the guest can't have left
+    /// them in.
+    pub lr_mismatches: u64,
+}
+
+impl Vgic {
+    pub const fn new() -> Self {
+        Self {
+            pending: SpinLock::new(PendingQueue::new()),
+            lrs: [0; NUM_LRS],
+            vmcr: ICH_VMCR_DEFAULT,
+            aprs: [[0; MAX_APRS]; 2],
+            level: IntidSet::new(),
+            asserted: IntidSet::new(),
+            dropped: AtomicU32::new(0),
+            profile: ExitProfile::Normal,
+            redist: None,
+            underflow: false,
+            flushes_skipped: 0,
+            syncs_skipped: 0,
+            refills: 0,
+            lr_mismatches: 0,
+        }
+    }
+
+    /// Queue a virtual interrupt at `DEFAULT_PRIORITY`.
+    pub fn inject(&self, intid: u32) {
+        self.inject_with_priority(intid, DEFAULT_PRIORITY);
+    }
+
+    /// Queue a virtual interrupt. Duplicates of an already pending
+    /// interrupt are merged, keeping the more urgent priority. Safe at EL2:
+    /// the queue never allocates, and an interrupt that doesn't fit is
+    /// dropped.
+    pub fn inject_with_priority(&self, intid: u32, priority: u8) {
+        self.queue(Pending {
+            intid,
+            priority,
+            fiq: false,
+        });
+    }
+
+    /// Queue a virtual FIQ: `intid` as a Group 0 interrupt at
+    /// `FIQ_PRIORITY`, which the guest takes as an FIQ once it enabled
+    /// Group 0 with ICC_IGRPEN0_EL1, and acknowledges with ICC_IAR0_EL1.
+    /// Merged like `inject_with_priority`.
+    pub fn inject_fiq(&self, intid: u32) {
+        self.queue(Pending {
+            intid,
+            priority: FIQ_PRIORITY,
+            fiq: true,
+        });
+    }
+
+    fn queue(&self, p: Pending) {
+        vlog!(
+            Vgic,
+            Trace,
+            "[vgic] inject intid {} priority {:#x}{}",
+            p.intid,
+            p.priority,
+            if p.fiq { " as fiq" } else { "" }
+        );
+        let intid = p.intid;
+        let mut pending = self.pending.irqsave_lock();
+        if pending.insert(p).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            vlog_limited!(
+                Vgic,
+                Warn,
+                "[vgic] pending queue full, dropped intid {}",
+                intid
+            );
+        }
+    }
+
+    /// Interrupts dropped because the pending queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Drop `intid` if it still waits for a list register, e.g. because
+    /// the device raising it went away.
+    pub fn retract(&self, intid: u32) {
+        self.pending.irqsave_lock().remove(intid);
+    }
+
+    /// Drive level-sensitive `intid` high or low. Returns whether it went
+    /// from low to high, and so was queued at `DEFAULT_PRIORITY`.
+    pub fn set_level(&self, intid: u32, high: bool) -> bool {
+        self.set_level_with_priority(intid, high, DEFAULT_PRIORITY)
+    }
+
+    /// `set_level`, queueing at `priority`.
+    pub fn set_level_with_priority(&self, intid: u32, high: bool, priority: u8) -> bool {
+        if intid >= MAX_INTID {
+            return false;
+        }
+        self.level.insert(intid);
+        if !high {
+            if self.asserted.remove(intid) {
+                self.retract(intid);
+            }
+            return false;
+        }
+        if self.asserted.insert(intid) {
+            return false;
+        }
+        self.inject_with_priority(intid, priority);
+        true
+    }
+
+    /// Whether level-sensitive `intid` is high.
+    pub fn level(&self, intid: u32) -> bool {
+        intid < MAX_INTID && self.asserted.contains(intid)
+    }
+
+    // A list register holding a level interrupt that was lowered before
+    // the guest took it, or took it again.
+    fn is_withdrawn(&self, lr: u64) -> bool {
+        let intid = Pending::from_lr(lr).intid;
+        lr & LR_STATE_PENDING != 0
+            && intid < MAX_INTID
+            && self.level.contains(intid)
+            && !self.asserted.contains(intid)
+    }
+
+    // Whether the interrupt of a list register the guest is done with
+    // must be pending again.
+    fn needs_resample(&self, lr: u64) -> bool {
+        let intid = Pending::from_lr(lr).intid;
+        lr != 0 && intid < MAX_INTID && self.asserted.contains(intid)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        let redist = self.redist.as_deref();
+        self.pending
+            .irqsave_lock()
+            .iter()
+            .any(|p| p.is_enabled(redist))
+            || self.lrs.iter().any(|lr| lr & LR_STATE_PENDING != 0)
+    }
+
+    fn lrs_empty(&self) -> bool {
+        self.lrs.iter().all(|lr| lr & LR_STATE_MASK == 0)
+    }
+
+    /// Load list registers before entering the guest, filling free slots
+    /// from the pending queue most urgent first. Only urgent interrupts may
+    /// take the reserved list register. Then each waiting interrupt more
+    /// urgent than one loaded but not yet taken swaps places with the least
+    /// urgent of those. Interrupts the redistributor holds back are
+    /// skipped.
+    ///
+    /// # Safety
+    /// Must run at EL2 on the core about to enter this vCPU.
+    #[link_section = ".hyp.text"]
+    pub(crate) unsafe fn flush(&mut self) {
+        self.restore_cpu_if();
+        let mut pending = self.pending.irqsave_lock();
+        // List registers are left zeroed between runs, so an idle vCPU with
+        // nothing to deliver needs no writes at all.
+        if self.profile == ExitProfile::Idle && pending.is_empty() && self.lrs_empty() {
+            self.flushes_skipped += 1;
+            return;
+        }
+        for n in 0..NUM_LRS {
+            if self.is_withdrawn(self.lrs[n]) {
+                self.lrs[n] = match LrState::of(self.lrs[n]) {
+                    LrState::PendingActive => LrState::Active.set(self.lrs[n]),
+                    _ => 0,
+                };
+            }
+            // Raised again while loaded: never two list registers with the
+            // same INTID. One the guest is still handling is pending again
+            // once it's done.
+            let loaded = Pending::from_lr(self.lrs[n]);
+            let state = LrState::of(self.lrs[n]);
+            if state == LrState::Invalid {
+                continue;
+            }
+            if let Some(p) = pending.remove(loaded.intid) {
+                self.lrs[n] = match state {
+                    LrState::Pending => {
+                        let p = p.prioritized(self.redist.as_deref());
+                        Pending {
+                            priority: loaded.priority.min(p.priority),
+                            ..loaded
+                        }
+                        .to_lr()
+                    }
+                    _ => LrState::PendingActive.set(self.lrs[n]),
+                };
+            }
+        }
+        let redist = self.redist.as_deref();
+        let ready = |p: &Pending| p.is_enabled(redist);
+        let mut free = self
+            .lrs
+            .iter()
+            .filter(|&&lr| lr & LR_STATE_MASK == 0)
+            .count();
+        for n in 0..NUM_LRS {
+            if self.lrs[n] & LR_STATE_MASK != 0 {
+                continue;
+            }
+            match pending.highest(redist, ready) {
+                Some(p) if p.is_urgent() || free > RESERVED_LRS => {
+                    pending.remove(p.intid);
+                    self.lrs[n] = p.to_lr();
+                    free -= 1;
+                }
+                _ => break,
+            }
+        }
+        // Each swap lowers the priority values loaded, so this ends.
+        while let Some(p) = pending.highest(redist, ready) {
+            // The least urgent interrupt not taken yet, the first of equals.
+            let victim = (0..NUM_LRS)
+                .filter(|&n| self.lrs[n] & LR_STATE_MASK == LR_STATE_PENDING)
+                .map(|n| (n, Pending::from_lr(self.lrs[n])))
+                .filter(|(_, q)| q.priority > p.priority)
+                .max_by_key(|&(n, q)| (q.priority, NUM_LRS - n));
+            let Some((n, q)) = victim else {
+                break;
+            };
+            pending.remove(p.intid);
+            // Can't fail, the queue just made room.
+            let _ = pending.insert(q);
+            self.lrs[n] = p.to_lr();
+        }
+        for n in 0..NUM_LRS {
+            if self.lrs[n] & LR_STATE_MASK != 0
+                && self.level.contains(Pending::from_lr(self.lrs[n]).intid)
+            {
+                self.lrs[n] |= LR_EOI;
+            }
+            gic().write_lr(n, self.lrs[n]);
+        }
+        // Whatever didn't fit is loaded once the guest made room; what the
+        // redistributor holds back waits for the guest to enable it.
+        self.underflow = pending.iter().any(ready);
+        if self.underflow {
+            gic().write_hcr(ICH_HCR_REFILL);
+        }
+    }
+
+    /// Load the interrupts still pending into the list registers the guest
+    /// is done with, on the maintenance interrupt `flush` asked for or the
+    /// guest's EOI of a level interrupt, which is pending again if still
+    /// high. The vCPU stays in the guest.
+    ///
+    /// # Safety
+    /// Must run at EL2 on the core running this vCPU.
+    #[link_section = ".hyp.text"]
+    pub(crate) unsafe fn refill(&mut self) {
+        self.sync();
+        self.flush();
+        self.refills += 1;
+    }
+
+    /// Forget what the list registers held, e.g. active interrupts a
+    /// guest being reset will never EOI, and the virtual CPU interface
+    /// state that went with them.
+    pub fn reset_lrs(&mut self) {
+        self.lrs = [0; NUM_LRS];
+        self.vmcr = ICH_VMCR_DEFAULT;
+        self.aprs = [[0; MAX_APRS]; 2];
+    }
+
+    // The guest's ICH_VMCR_EL2 and active priorities, as `sync` saved them.
+    #[link_section = ".hyp.text"]
+    unsafe fn restore_cpu_if(&self) {
+        gic().write_vmcr(self.vmcr);
+        for n in 0..APRS.load(Ordering::Relaxed) {
+            gic().write_apr(false, n, self.aprs[0][n]);
+            gic().write_apr(true, n, self.aprs[1][n]);
+        }
+    }
+
+    #[link_section = ".hyp.text"]
+    unsafe fn save_cpu_if(&mut self) {
+        self.vmcr = gic().read_vmcr();
+        for n in 0..APRS.load(Ordering::Relaxed) {
+            self.aprs[0][n] = gic().read_apr(false, n);
+            self.aprs[1][n] = gic().read_apr(true, n);
+        }
+    }
+
+    /// Zero the list registers of a guest whose state is being dropped, as
+    /// `sync` leaves them.
+    ///
+    /// # Safety
+    /// Must run at EL2.
+    #[link_section = ".hyp.text"]
+    pub(crate) unsafe fn clear_lrs() {
+        for n in 0..NUM_LRS {
+            gic().write_lr(n, 0);
+        }
+    }
+
+    /// Capture list registers after leaving the guest.
+    ///
+    /// # Safety
+    /// Must run at EL2 on the core that just left this vCPU.
+    #[link_section = ".hyp.text"]
+    pub(crate) unsafe fn sync(&mut self) {
+        self.save_cpu_if();
+        if self.underflow {
+            self.underflow = false;
+            // Also zeroes EOIcount, which keeps the interrupt asserted.
+            gic().write_hcr(ICH_HCR_EN);
+        }
+        // The guest cannot populate a list register by itself.
+        if self.profile == ExitProfile::Busy && self.lrs_empty() {
+            self.syncs_skipped += 1;
+            return;
+        }
+        let empty = gic().elrsr();
+        for n in 0..NUM_LRS {
+            let loaded = self.lrs[n];
+            let mut lr = gic().read_lr(n);
+            if empty & (1 << n) != 0 {
+                lr &= !LR_STATE_MASK;
+            }
+            if !lr_consistent(loaded, lr) {
+                self.lr_mismatches += 1;
+                lr = loaded;
+            }
+            if LrState::of(lr) == LrState::Invalid {
+                if self.needs_resample(loaded) {
+                    // The list register as loaded still names the interrupt.
+                    let _ = self.pending.irqsave_lock().insert(Pending::from_lr(loaded));
+                }
+                lr = 0;
+            }
+            self.lrs[n] = lr;
+            gic().write_lr(n, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::hal::with_mock;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_vgic_flush_sync() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            vgic.inject(40);
+            vgic.inject(40);
+            vgic.inject(41);
+            unsafe { vgic.flush() };
+            let lr0 = gic().read_lr(0);
+            assert_eq!(lr0 & 0x3ff, 40);
+            assert_eq!(lr0 & LR_STATE_MASK, LR_STATE_PENDING);
+            assert_eq!(gic().read_lr(1) & 0x3ff, 41);
+            assert_eq!(gic().read_lr(2), 0);
+
+            // The guest took and EOIed 40 while 41 stays pending.
+            gic().write_lr(0, 0);
+            unsafe { vgic.sync() };
+            assert_eq!(gic().elrsr(), 0b1111);
+            assert!(vgic.has_pending());
+            unsafe { vgic.flush() };
+            assert_eq!(gic().read_lr(0), 0);
+            assert_eq!(gic().read_lr(1) & 0x3ff, 41);
+        });
+    }
+
+    #[test]
+    fn test_vgic_level() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            assert!(vgic.set_level(50, true));
+            assert!(!vgic.set_level(50, true));
+            unsafe { vgic.flush() };
+            assert_eq!(gic().read_lr(0) & 0x3ff, 50);
+            assert_ne!(gic().read_lr(0) & LR_EOI, 0);
+
+            // EOIed while still high: pending again from the maintenance
+            // interrupt the EOI raised.
+            gic().write_lr(0, LR_EOI | 50);
+            unsafe { vgic.refill() };
+            assert_eq!(gic().read_lr(0) & 0x3ff, 50);
+            assert_eq!(gic().read_lr(0) & LR_STATE_MASK, LR_STATE_PENDING);
+
+            // Lowered before the guest took it: withdrawn.
+            unsafe { vgic.sync() };
+            assert!(!vgic.set_level(50, false));
+            assert!(!vgic.level(50));
+            unsafe { vgic.flush() };
+            assert_eq!(gic().read_lr(0), 0);
+            assert!(!vgic.has_pending());
+
+            // Edge interrupts are never resampled, nor ask to be.
+            vgic.inject(51);
+            unsafe { vgic.flush() };
+            assert_eq!(gic().read_lr(0) & LR_EOI, 0);
+            gic().write_lr(0, 0);
+            unsafe { vgic.sync() };
+            assert!(!vgic.has_pending());
+        });
+    }
+
+    #[test]
+    fn test_vgic_lr_states() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            vgic.inject(40);
+            vgic.inject(41);
+            vgic.inject(42);
+            unsafe { vgic.flush() };
+
+            // The guest took 40 and EOIed 41; 42 turned into another interrupt
+            // by nothing the guest did.
+            gic().write_lr(0, LrState::Active.set(gic().read_lr(0)));
+            gic().write_lr(1, 0);
+            gic().write_lr(2, gic().read_lr(2) + 1);
+            unsafe { vgic.sync() };
+            assert_eq!(LrState::of(vgic.lrs[0]), LrState::Active);
+            assert_eq!(vgic.lrs[1], 0);
+            assert_eq!(Pending::from_lr(vgic.lrs[2]).intid, 42);
+            assert_eq!(LrState::of(vgic.lrs[2]), LrState::Pending);
+            assert_eq!(vgic.lr_mismatches, 1);
+            assert!(vgic.has_pending());
+
+            // Raised again while loaded: still one list register each.
+            vgic.inject(40);
+            vgic.inject_with_priority(42, 0x90);
+            unsafe { vgic.flush() };
+            assert_eq!(LrState::of(gic().read_lr(0)), LrState::PendingActive);
+            assert_eq!(
+                Pending::from_lr(gic().read_lr(2)),
+                Pending {
+                    intid: 42,
+                    priority: 0x90,
+                    fiq: false,
+                }
+            );
+            assert_eq!((gic().read_lr(1), gic().read_lr(3)), (0, 0));
+            assert!(vgic.pending.irqsave_lock().is_empty());
+
+            // The guest EOIs 40 and takes it again.
+            gic().write_lr(0, LrState::Active.set(gic().read_lr(0)));
+            unsafe { vgic.sync() };
+            assert_eq!(LrState::of(vgic.lrs[0]), LrState::Active);
+            assert_eq!(vgic.lr_mismatches, 1);
+
+            // An empty list register the guest can't fill.
+            gic().write_lr(
+                3,
+                Pending {
+                    intid: 50,
+                    priority: 0,
+                    fiq: false,
+                }
+                .to_lr(),
+            );
+            unsafe { vgic.sync() };
+            assert_eq!(vgic.lrs[3], 0);
+            assert_eq!(vgic.lr_mismatches, 2);
+        });
+    }
+
+    #[test]
+    fn test_vgic_fiq() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            vgic.inject(40);
+            vgic.inject_fiq(41);
+            unsafe { vgic.flush() };
+            // More urgent, so it goes first; Group 0.
+            let lr = gic().read_lr(0);
+            assert_eq!(
+                Pending::from_lr(lr),
+                Pending {
+                    intid: 41,
+                    priority: FIQ_PRIORITY,
+                    fiq: true,
+                }
+            );
+            assert_eq!(lr & LR_GROUP1, 0);
+            assert_ne!(gic().read_lr(1) & LR_GROUP1, 0);
+            gic().write_lr(0, 0);
+            gic().write_lr(1, 0);
+            unsafe { vgic.sync() };
+        });
+    }
+
+    #[test]
+    fn test_vgic_reserved_lr() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            for intid in 32..38 {
+                vgic.inject(intid);
+            }
+            unsafe { vgic.flush() };
+            // The burst leaves the last list register to urgent interrupts.
+            assert_eq!(gic().read_lr(NUM_LRS - 1), 0);
+
+            // The timer PPI raised while the burst waits.
+            vgic.set_level_with_priority(27, true, TIMER_PRIORITY);
+            unsafe { vgic.flush() };
+            let lr = gic().read_lr(NUM_LRS - 1);
+            assert_eq!(Pending::from_lr(lr).intid, 27);
+            assert_eq!(lr & LR_STATE_MASK, LR_STATE_PENDING);
+
+            // With every list register taken, an urgent one evicts a burst
+            // interrupt the guest hasn't taken yet.
+            vgic.inject_with_priority(26, 0);
+            unsafe { vgic.flush() };
+            let intids: [u32; NUM_LRS] =
+                core::array::from_fn(|n| Pending::from_lr(gic().read_lr(n)).intid);
+            assert_eq!(intids, [26, 33, 34, 27]);
+            assert!(vgic.pending.irqsave_lock().iter().any(|p| p.intid == 32));
+        });
+    }
+
+    #[test]
+    fn test_vgic_priority_order() {
+        with_mock(|| {
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            for intid in 40..46 {
+                vgic.inject_with_priority(intid, 0xc0);
+            }
+            vgic.inject_with_priority(46, 0xb0);
+            unsafe { vgic.flush() };
+            let intids: [u32; NUM_LRS] =
+                core::array::from_fn(|n| Pending::from_lr(gic().read_lr(n)).intid);
+            assert_eq!(intids, [46, 40, 41, 0]);
+
+            // A more urgent interrupt preempts the least urgent one not taken
+            // yet; an equally urgent one doesn't.
+            vgic.inject_with_priority(47, 0x90);
+            vgic.inject_with_priority(48, 0xb0);
+            unsafe { vgic.flush() };
+            let intids: [u32; NUM_LRS] =
+                core::array::from_fn(|n| Pending::from_lr(gic().read_lr(n)).intid);
+            assert_eq!(intids, [46, 47, 48, 0]);
+
+            // Interrupts the guest took stay put, even if more urgent ones come.
+            gic().write_lr(1, LrState::Active.set(gic().read_lr(1)));
+            unsafe { vgic.sync() };
+            vgic.inject_with_priority(49, 0x88);
+            unsafe { vgic.flush() };
+            let intids: [u32; NUM_LRS] =
+                core::array::from_fn(|n| Pending::from_lr(gic().read_lr(n)).intid);
+            assert_eq!(intids, [49, 47, 48, 0]);
+            let pending = vgic.pending.irqsave_lock();
+            let queued: alloc::vec::Vec<u32> = pending.iter().map(|p| p.intid).collect();
+            assert_eq!(queued, [42, 43, 44, 45, 40, 41, 46]);
+        });
+    }
+
+    #[test]
+    fn test_vgic_underflow_refill() {
+        with_mock(|| {
+            use super::super::hal::mock;
+            init().unwrap();
+            let mut vgic = Vgic::new();
+            for intid in 40..46 {
+                vgic.inject(intid);
+            }
+            unsafe { vgic.flush() };
+            // Three fit, the rest wait for the guest to make room.
+            assert_eq!(mock().vgic_ctrl().1, ICH_HCR_REFILL);
+
+            // The guest EOIed two of them.
+            gic().write_lr(0, 0);
+            gic().write_lr(1, 0);
+            unsafe { vgic.refill() };
+            let intids: [u32; NUM_LRS] =
+                core::array::from_fn(|n| Pending::from_lr(gic().read_lr(n)).intid);
+            assert_eq!(intids, [43, 44, 42, 0]);
+            assert_eq!(vgic.refills, 1);
+
+            // One left: the interrupt is still wanted.
+            assert_eq!(mock().vgic_ctrl().1, ICH_HCR_REFILL);
+            unsafe { vgic.sync() };
+            assert_eq!(mock().vgic_ctrl().1, ICH_HCR_EN);
+        });
+    }
+
+    #[test]
+    fn test_vgic_cpu_if_switch() {
+        with_mock(|| {
+            init().unwrap();
+            let (mut a, mut b) = (Vgic::new(), Vgic::new());
+            unsafe { a.flush() };
+            // Guest A masks below 0x80 and is handling a Group 1 interrupt.
+            gic().write_vmcr(0x80 << 24 | 1 << 1);
+            gic().write_apr(true, 0, 1 << 10);
+            unsafe { a.sync() };
+
+            unsafe { b.flush() };
+            assert_eq!(gic().read_vmcr(), ICH_VMCR_DEFAULT);
+            assert_eq!(gic().read_apr(true, 0), 0);
+            unsafe { b.sync() };
+
+            unsafe { a.flush() };
+            assert_eq!(gic().read_vmcr(), 0x80 << 24 | 1 << 1);
+            assert_eq!(gic().read_apr(true, 0), 1 << 10);
+            unsafe { a.sync() };
+            a.reset_lrs();
+            unsafe { a.flush() };
+            assert_eq!(gic().read_apr(true, 0), 0);
+        });
+    }
+}
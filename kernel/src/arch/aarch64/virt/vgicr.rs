@@ -0,0 +1,404 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulated GICv3 redistributors, one frame pair per vCPU, so that a guest
+//! kernel written for GICv3 can wake its redistributor and set up its SGIs
+//! and PPIs. Only the per-CPU state such a guest touches is modelled:
+//! GICR_WAKER, the SGI/PPI groups, enables and priorities. Everything else
+//! reads as zero, or as the one value a virtual CPU interface allows, and
+//! ignores writes.
+//!
+//! The vCPU's `Vgic` holds its `Redistributor`. While the guest keeps an
+//! SGI or PPI disabled, or the redistributor asleep, `Vgic::flush` leaves
+//! the interrupt queued, and it loads the interrupt with the group and
+//! priority the guest gave it.
+
+use super::{
+    mmio::{self, BackendError, MmioDevice, MmioError},
+    vcpu::{vcpu_manager, MAX_VCPUS_PER_VM},
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+/// RD_base and SGI_base frames of one vCPU.
+pub const FRAME_SIZE: u64 = 0x2_0000;
+/// Frames for as many vCPUs as a VM can have.
+pub const REGION_SIZE: u64 = FRAME_SIZE * MAX_VCPUS_PER_VM as u64;
+/// SGIs and PPIs, the interrupts a redistributor owns.
+pub const PRIVATE_INTIDS: u32 = 32;
+
+const SGI_BASE: u64 = 0x1_0000;
+
+const GICR_CTLR: u64 = 0x0000;
+const GICR_IIDR: u64 = 0x0004;
+const GICR_TYPER: u64 = 0x0008;
+const GICR_WAKER: u64 = 0x0014;
+const GICR_PIDR2: u64 = 0xffe8;
+const GICR_IGROUPR0: u64 = SGI_BASE + 0x0080;
+const GICR_ISENABLER0: u64 = SGI_BASE + 0x0100;
+const GICR_ICENABLER0: u64 = SGI_BASE + 0x0180;
+const GICR_IPRIORITYR: u64 = SGI_BASE + 0x0400;
+const GICR_ICFGR0: u64 = SGI_BASE + 0x0c00;
+
+// Arm as implementer.
+const IIDR: u32 = 0x43b;
+// GICv3, with the Arm JEP106 bits.
+const PIDR2: u32 = 0x3b;
+const TYPER_LAST: u64 = 1 << 4;
+const TYPER_PROCESSOR_SHIFT: u64 = 8;
+const TYPER_AFFINITY_SHIFT: u64 = 32;
+const WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+// Every SGI and PPI starts in Group 1, as a guest not using FIQs wants.
+const IGROUPR0_RESET: u32 = u32::MAX;
+// SGIs are edge-triggered, two bits each.
+const ICFGR0_SGI_EDGE: u32 = 0xaaaa_aaaa;
+// Frames past the last vCPU have nothing behind them; a load reads 0.
+const UNBACKED: BackendError = BackendError { status: 0 };
+
+/// SGI/PPI state of one vCPU, as the guest programmed it.
+pub struct Redistributor {
+    waker: AtomicU32,
+    group: AtomicU32,
+    enabled: AtomicU32,
+    priority: [AtomicU8; PRIVATE_INTIDS as usize],
+}
+
+impl Redistributor {
+    /// Asleep with every SGI and PPI disabled and in Group 1, as out of
+    /// reset.
+    pub fn new() -> Self {
+        Self {
+            waker: AtomicU32::new(WAKER_PROCESSOR_SLEEP | WAKER_CHILDREN_ASLEEP),
+            group: AtomicU32::new(IGROUPR0_RESET),
+            enabled: AtomicU32::new(0),
+            priority: [const { AtomicU8::new(0) }; PRIVATE_INTIDS as usize],
+        }
+    }
+
+    /// Back to the state of `new`, for a VM reset.
+    pub fn reset(&self) {
+        self.waker.store(
+            WAKER_PROCESSOR_SLEEP | WAKER_CHILDREN_ASLEEP,
+            Ordering::Relaxed,
+        );
+        self.group.store(IGROUPR0_RESET, Ordering::Relaxed);
+        self.enabled.store(0, Ordering::Relaxed);
+        for p in &self.priority {
+            p.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_awake(&self) -> bool {
+        self.waker.load(Ordering::Relaxed) & WAKER_PROCESSOR_SLEEP == 0
+    }
+
+    /// Whether `intid` may be delivered. Only SGIs and PPIs are up to the
+    /// redistributor.
+    pub fn is_enabled(&self, intid: u32) -> bool {
+        intid >= PRIVATE_INTIDS
+            || (self.is_awake() && self.enabled.load(Ordering::Relaxed) & (1 << intid) != 0)
+    }
+
+    /// Whether the guest put SGI or PPI `intid` in Group 0, to take it as
+    /// an FIQ.
+    pub fn is_group0(&self, intid: u32) -> Option<bool> {
+        (intid < PRIVATE_INTIDS).then(|| self.group.load(Ordering::Relaxed) & (1 << intid) == 0)
+    }
+
+    /// Priority the guest gave SGI or PPI `intid`.
+    pub fn priority(&self, intid: u32) -> Option<u8> {
+        self.priority
+            .get(intid as usize)
+            .map(|p| p.load(Ordering::Relaxed))
+    }
+
+    fn read(&self, offset: u64, size: u8) -> u64 {
+        match offset {
+            GICR_WAKER => self.waker.load(Ordering::Relaxed) as u64,
+            GICR_IGROUPR0 => self.group.load(Ordering::Relaxed) as u64,
+            GICR_ISENABLER0 | GICR_ICENABLER0 => self.enabled.load(Ordering::Relaxed) as u64,
+            o if (GICR_IPRIORITYR..GICR_IPRIORITYR + PRIVATE_INTIDS as u64).contains(&o) => {
+                let first = (o - GICR_IPRIORITYR) as usize;
+                (0..size as usize)
+                    .filter_map(|n| self.priority.get(first + n))
+                    .enumerate()
+                    .fold(0, |val, (n, p)| {
+                        val | (p.load(Ordering::Relaxed) as u64) << (n * 8)
+                    })
+            }
+            GICR_ICFGR0 => ICFGR0_SGI_EDGE as u64,
+            _ => 0,
+        }
+    }
+
+    fn write(&self, offset: u64, size: u8, value: u64) {
+        match offset {
+            GICR_WAKER => {
+                // The virtual redistributor has nothing to quiesce, so
+                // ChildrenAsleep follows ProcessorSleep right away.
+                let waker = match value as u32 & WAKER_PROCESSOR_SLEEP {
+                    0 => 0,
+                    _ => WAKER_PROCESSOR_SLEEP | WAKER_CHILDREN_ASLEEP,
+                };
+                self.waker.store(waker, Ordering::Relaxed);
+            }
+            GICR_IGROUPR0 => {
+                self.group.store(value as u32, Ordering::Relaxed);
+            }
+            GICR_ISENABLER0 => {
+                self.enabled.fetch_or(value as u32, Ordering::Relaxed);
+            }
+            GICR_ICENABLER0 => {
+                self.enabled.fetch_and(!(value as u32), Ordering::Relaxed);
+            }
+            o if (GICR_IPRIORITYR..GICR_IPRIORITYR + PRIVATE_INTIDS as u64).contains(&o) => {
+                let first = (o - GICR_IPRIORITYR) as usize;
+                for n in 0..size as usize {
+                    if let Some(p) = self.priority.get(first + n) {
+                        p.store((value >> (n * 8)) as u8, Ordering::Relaxed);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for Redistributor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The redistributor region of a VM: frame n belongs to the vCPU with
+/// index n. Frames of vCPUs the VM doesn't have read as zero.
+pub struct Gicr {
+    frames: Vec<Arc<Redistributor>>,
+    // Frames with a vCPU behind them; the last one says so in GICR_TYPER.
+    used: AtomicUsize,
+}
+
+impl Gicr {
+    pub fn new() -> Self {
+        Self {
+            frames: (0..MAX_VCPUS_PER_VM)
+                .map(|_| Arc::new(Redistributor::new()))
+                .collect(),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Redistributor of the vCPU with index `index`.
+    pub fn frame(&self, index: usize) -> Option<&Arc<Redistributor>> {
+        self.frames.get(index)
+    }
+
+    fn typer(&self, index: usize) -> u64 {
+        let last = if index + 1 == self.used.load(Ordering::Relaxed) {
+            TYPER_LAST
+        } else {
+            0
+        };
+        // The vCPU's MPIDR_EL1.Aff0 is its index.
+        (index as u64) << TYPER_AFFINITY_SHIFT | (index as u64) << TYPER_PROCESSOR_SHIFT | last
+    }
+}
+
+impl Default for Gicr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmioDevice for Gicr {
+    fn read(&self, offset: u64, size: u8) -> Result<u64, BackendError> {
+        let index = (offset / FRAME_SIZE) as usize;
+        if index >= self.used.load(Ordering::Relaxed) {
+            return Err(UNBACKED);
+        }
+        let offset = offset % FRAME_SIZE;
+        Ok(match offset {
+            GICR_CTLR => 0,
+            GICR_IIDR => IIDR as u64,
+            GICR_TYPER => match size {
+                8 => self.typer(index),
+                _ => self.typer(index) & u32::MAX as u64,
+            },
+            o if o == GICR_TYPER + 4 => self.typer(index) >> 32,
+            GICR_PIDR2 => PIDR2 as u64,
+            _ => self.frames[index].read(offset, size),
+        })
+    }
+
+    fn write(&self, offset: u64, size: u8, value: u64) -> Result<(), BackendError> {
+        let index = (offset / FRAME_SIZE) as usize;
+        if index >= self.used.load(Ordering::Relaxed) {
+            return Err(UNBACKED);
+        }
+        self.frames[index].write(offset % FRAME_SIZE, size, value);
+        Ok(())
+    }
+}
+
+static GICRS: SpinLock<BTreeMap<usize, (u64, Arc<Gicr>)>> = SpinLock::new(BTreeMap::new());
+
+/// Give VM `vm_id` redistributor frames at IPA `base`, `REGION_SIZE` of
+/// them. Its vCPUs use them once `attach`ed.
+pub fn create(vm_id: usize, base: u64) -> Result<Arc<Gicr>, MmioError> {
+    let gicr = Arc::new(Gicr::new());
+    mmio::register(vm_id, base, REGION_SIZE, gicr.clone())?;
+    GICRS.irqsave_lock().insert(vm_id, (base, gicr.clone()));
+    Ok(gicr)
+}
+
+/// Hand vCPU `vcpu_id` the frame of its index, if its VM has
+/// redistributors.
+pub fn attach(vm_id: usize, vcpu_id: usize) {
+    let Some(gicr) = get(vm_id) else {
+        return;
+    };
+    vcpu_manager().with_vcpu_mut(vcpu_id, |vcpu| {
+        if let Some(frame) = gicr.frame(vcpu.index) {
+            vcpu.vgic.redist = Some(frame.clone());
+            gicr.used.fetch_max(vcpu.index + 1, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Remove the redistributors of VM `vm_id` from the guest's address space.
+pub fn destroy(vm_id: usize) {
+    if let Some((base, _)) = GICRS.irqsave_lock().remove(&vm_id) {
+        mmio::unregister(vm_id, base);
+    }
+}
+
+pub fn get(vm_id: usize) -> Option<Arc<Gicr>> {
+    GICRS
+        .irqsave_lock()
+        .get(&vm_id)
+        .map(|(_, gicr)| gicr.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::{
+        hal::{gic, with_mock, GicBackend},
+        vgic::{self, Vgic},
+    };
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_gicr_wake_and_enable() {
+        with_mock(|| {
+            let gicr = Gicr::new();
+            gicr.used.store(2, Ordering::Relaxed);
+            let rd = gicr.frame(1).unwrap().clone();
+            assert_eq!(gicr.read(FRAME_SIZE + GICR_PIDR2, 4), Ok(PIDR2 as u64));
+            assert_eq!(
+                gicr.read(FRAME_SIZE + GICR_TYPER, 8),
+                Ok(1 << 32 | 1 << 8 | TYPER_LAST)
+            );
+            assert_eq!(gicr.read(GICR_TYPER, 8), Ok(0));
+            assert_eq!(gicr.read(2 * FRAME_SIZE + GICR_PIDR2, 4), Err(UNBACKED));
+
+            let waker = FRAME_SIZE + GICR_WAKER;
+            assert_eq!(
+                gicr.read(waker, 4),
+                Ok((WAKER_PROCESSOR_SLEEP | WAKER_CHILDREN_ASLEEP) as u64)
+            );
+            gicr.write(FRAME_SIZE + GICR_ISENABLER0, 4, 1 << 27)
+                .unwrap();
+            assert!(!rd.is_enabled(27));
+            gicr.write(waker, 4, 0).unwrap();
+            assert_eq!(gicr.read(waker, 4), Ok(0));
+            assert!(rd.is_enabled(27));
+            assert!(!rd.is_enabled(26));
+            assert!(rd.is_enabled(PRIVATE_INTIDS));
+            gicr.write(FRAME_SIZE + GICR_ICENABLER0, 4, 1 << 27)
+                .unwrap();
+            assert!(!rd.is_enabled(27));
+
+            gicr.write(FRAME_SIZE + GICR_IPRIORITYR + 24, 4, 0x8070_6050)
+                .unwrap();
+            gicr.write(FRAME_SIZE + GICR_IPRIORITYR + 1, 1, 0x20)
+                .unwrap();
+            assert_eq!(rd.priority(27), Some(0x80));
+            assert_eq!(rd.priority(1), Some(0x20));
+            assert_eq!(rd.priority(PRIVATE_INTIDS), None);
+            assert_eq!(
+                gicr.read(FRAME_SIZE + GICR_IPRIORITYR + 24, 4),
+                Ok(0x8070_6050)
+            );
+            assert_eq!(rd.is_group0(27), Some(false));
+            gicr.write(FRAME_SIZE + GICR_IGROUPR0, 4, !(1 << 27))
+                .unwrap();
+            assert_eq!(
+                gicr.read(FRAME_SIZE + GICR_IGROUPR0, 4),
+                Ok(!(1u32 << 27) as u64)
+            );
+            assert_eq!(rd.is_group0(27), Some(true));
+            assert_eq!(rd.is_group0(26), Some(false));
+            assert_eq!(rd.is_group0(PRIVATE_INTIDS), None);
+            rd.reset();
+            assert!(!rd.is_awake());
+            assert_eq!(rd.is_group0(27), Some(false));
+            assert_eq!(rd.priority(27), Some(0));
+        });
+    }
+
+    #[test]
+    fn test_gicr_holds_back_private_irqs() {
+        with_mock(|| {
+            vgic::init().unwrap();
+            let rd = Arc::new(Redistributor::new());
+            let mut vgic = Vgic::new();
+            vgic.redist = Some(rd.clone());
+            vgic.inject(27);
+            vgic.inject(40);
+            assert!(vgic.has_pending());
+            unsafe { vgic.flush() };
+            assert_eq!(gic().read_lr(0) & 0x3ff, 40);
+            assert_eq!(gic().read_lr(1), 0);
+            gic().write_lr(0, 0);
+            unsafe { vgic.sync() };
+            // 27 is still disabled, so nothing is left to wake the vCPU.
+            assert!(!vgic.has_pending());
+
+            rd.write(GICR_WAKER, 4, 0);
+            rd.write(GICR_ISENABLER0, 4, 1 << 27);
+            rd.write(GICR_IPRIORITYR + 27, 1, 0x60);
+            assert!(vgic.has_pending());
+            unsafe { vgic.flush() };
+            let lr0 = gic().read_lr(0);
+            assert_eq!(lr0 & 0x3ff, 27);
+            assert_eq!((lr0 >> 48) & 0xff, 0x60);
+            assert_ne!(lr0 & 1 << 60, 0);
+            gic().write_lr(0, 0);
+            unsafe { vgic.sync() };
+
+            // Moved to Group 0, 27 goes to the guest as an FIQ.
+            rd.write(GICR_IGROUPR0, 4, !(1 << 27));
+            vgic.inject(27);
+            unsafe { vgic.flush() };
+            let lr0 = gic().read_lr(0);
+            assert_eq!(lr0 & 0x3ff, 27);
+            assert_eq!(lr0 & 1 << 60, 0);
+            gic().write_lr(0, 0);
+            unsafe { vgic.sync() };
+        });
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Split virtqueue, device side. The descriptor table and both rings live in
+//! guest memory and are only reached through a `GuestMemory`. Nothing read
+//! from them is trusted: indices, chain lengths and buffer ranges are all
+//! checked before a chain is handed to the device.
+
+use crate::arch::aarch64::virt::guest_mem::{GuestMemError, GuestMemory};
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+pub const MAX_QUEUE_SIZE: u16 = 32768;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+const VIRTQ_DESC_F_INDIRECT: u16 = 4;
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+const DESC_SIZE: u64 = 16;
+const USED_ELEM_SIZE: u64 = 8;
+// flags and idx in front of both rings.
+const RING_HEADER: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    NotReady,
+    /// The driver configured an invalid size or a misaligned ring.
+    BadConfig,
+    Memory(GuestMemError),
+    /// The driver claims more new buffers than the queue holds.
+    BadAvailIdx(u16),
+    BadDescIndex(u16),
+    /// The chain loops or is longer than its descriptor table.
+    ChainTooLong,
+    BadIndirect,
+    /// A device-readable descriptor follows a device-writable one.
+    ReadAfterWrite,
+    /// A buffer wraps the address space or the chain exceeds 4GB.
+    LengthOverflow,
+}
+
+impl From<GuestMemError> for QueueError {
+    fn from(e: GuestMemError) -> Self {
+        Self::Memory(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// One guest buffer of a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescBuf {
+    pub addr: u64,
+    pub len: u32,
+    pub writable: bool,
+}
+
+/// A request from the driver: device-readable buffers followed by
+/// device-writable ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescChain {
+    pub head: u16,
+    pub bufs: Vec<DescBuf>,
+}
+
+impl DescChain {
+    pub fn readable(&self) -> impl Iterator<Item = &DescBuf> {
+        self.bufs.iter().filter(|b| !b.writable)
+    }
+
+    pub fn writable(&self) -> impl Iterator<Item = &DescBuf> {
+        self.bufs.iter().filter(|b| b.writable)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Virtqueue {
+    pub size: u16,
+    pub desc_table: u64,
+    pub avail_ring: u64,
+    pub used_ring: u64,
+    ready: bool,
+    last_avail_idx: u16,
+    used_idx: u16,
+}
+
+impl Virtqueue {
+    pub const fn new() -> Self {
+        Self {
+            size: 0,
+            desc_table: 0,
+            avail_ring: 0,
+            used_ring: 0,
+            ready: false,
+            last_avail_idx: 0,
+            used_idx: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Start or stop processing. The layout set up by the driver is checked
+    /// when the queue is made ready.
+    pub fn set_ready(&mut self, ready: bool) -> Result<(), QueueError> {
+        if ready {
+            self.check_layout()?;
+        }
+        self.ready = ready;
+        self.last_avail_idx = 0;
+        self.used_idx = 0;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn check_layout(&self) -> Result<(), QueueError> {
+        let size = self.size as u64;
+        if size == 0 || !self.size.is_power_of_two() || self.size > MAX_QUEUE_SIZE {
+            return Err(QueueError::BadConfig);
+        }
+        if self.desc_table % 16 != 0 || self.avail_ring % 2 != 0 || self.used_ring % 4 != 0 {
+            return Err(QueueError::BadConfig);
+        }
+        // Each area with its trailing event field.
+        let areas = [
+            (self.desc_table, DESC_SIZE * size),
+            (self.avail_ring, RING_HEADER + 2 * size + 2),
+            (self.used_ring, RING_HEADER + USED_ELEM_SIZE * size + 2),
+        ];
+        if areas
+            .iter()
+            .any(|&(start, len)| start.checked_add(len).is_none())
+        {
+            return Err(QueueError::BadConfig);
+        }
+        Ok(())
+    }
+
+    fn read_desc<M: GuestMemory>(mem: &M, table: u64, index: u16) -> Result<Desc, QueueError> {
+        let base = table + index as u64 * DESC_SIZE;
+        Ok(Desc {
+            addr: mem.read_u64(base)?,
+            len: mem.read_u32(base + 8)?,
+            flags: mem.read_u16(base + 12)?,
+            next: mem.read_u16(base + 14)?,
+        })
+    }
+
+    /// Take the next available chain. On error nothing is consumed; the
+    /// device should stop using the queue until the driver resets it.
+    pub fn pop<M: GuestMemory>(&mut self, mem: &M) -> Result<Option<DescChain>, QueueError> {
+        if !self.ready {
+            return Err(QueueError::NotReady);
+        }
+        let avail_idx = mem.read_u16(self.avail_ring + 2)?;
+        let pending = avail_idx.wrapping_sub(self.last_avail_idx);
+        if pending == 0 {
+            return Ok(None);
+        }
+        if pending > self.size {
+            return Err(QueueError::BadAvailIdx(avail_idx));
+        }
+        // Ring entries are only valid once the index covering them is seen.
+        fence(Ordering::Acquire);
+        let slot = (self.last_avail_idx % self.size) as u64;
+        let head = mem.read_u16(self.avail_ring + RING_HEADER + 2 * slot)?;
+        let chain = self.read_chain(mem, head)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+        Ok(Some(chain))
+    }
+
+    fn read_chain<M: GuestMemory>(&self, mem: &M, head: u16) -> Result<DescChain, QueueError> {
+        let mut bufs: Vec<DescBuf> = Vec::new();
+        let mut total: u64 = 0;
+        let mut table = self.desc_table;
+        let mut table_size = self.size;
+        let mut indirect = false;
+        let mut index = head;
+        let mut seen: u16 = 0;
+        loop {
+            if index >= table_size {
+                return Err(QueueError::BadDescIndex(index));
+            }
+            // Visiting more descriptors than the table has means a loop.
+            if seen == table_size {
+                return Err(QueueError::ChainTooLong);
+            }
+            seen += 1;
+            let desc = Self::read_desc(mem, table, index)?;
+            if desc.flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                // An indirect table ends the chain and can't nest.
+                let count = desc.len as u64 / DESC_SIZE;
+                if indirect
+                    || desc.flags & VIRTQ_DESC_F_NEXT != 0
+                    || desc.len as u64 % DESC_SIZE != 0
+                    || count == 0
+                    || count > MAX_QUEUE_SIZE as u64
+                    || desc.addr.checked_add(desc.len as u64).is_none()
+                {
+                    return Err(QueueError::BadIndirect);
+                }
+                table = desc.addr;
+                table_size = count as u16;
+                indirect = true;
+                index = 0;
+                seen = 0;
+                continue;
+            }
+            let writable = desc.flags & VIRTQ_DESC_F_WRITE != 0;
+            if !writable && bufs.last().is_some_and(|b| b.writable) {
+                return Err(QueueError::ReadAfterWrite);
+            }
+            total += desc.len as u64;
+            if total > u32::MAX as u64 || desc.addr.checked_add(desc.len as u64).is_none() {
+                return Err(QueueError::LengthOverflow);
+            }
+            bufs.push(DescBuf {
+                addr: desc.addr,
+                len: desc.len,
+                writable,
+            });
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(DescChain { head, bufs });
+            }
+            index = desc.next;
+        }
+    }
+
+    /// Return chain `head` to the driver with `len` bytes written into its
+    /// writable buffers.
+    pub fn add_used<M: GuestMemory>(
+        &mut self,
+        mem: &M,
+        head: u16,
+        len: u32,
+    ) -> Result<(), QueueError> {
+        if !self.ready {
+            return Err(QueueError::NotReady);
+        }
+        if head >= self.size {
+            return Err(QueueError::BadDescIndex(head));
+        }
+        let slot = (self.used_idx % self.size) as u64;
+        let elem = self.used_ring + RING_HEADER + USED_ELEM_SIZE * slot;
+        mem.write_u32(elem, head as u32)?;
+        mem.write_u32(elem + 4, len)?;
+        // The element must be visible before the index publishing it.
+        fence(Ordering::Release);
+        self.used_idx = self.used_idx.wrapping_add(1);
+        mem.write_u16(self.used_ring + 2, self.used_idx)?;
+        Ok(())
+    }
+
+    /// Whether the driver wants an interrupt for the buffers used so far.
+    pub fn needs_notification<M: GuestMemory>(&self, mem: &M) -> Result<bool, QueueError> {
+        // Order the used index update before reading the driver's flags.
+        fence(Ordering::SeqCst);
+        Ok(mem.read_u16(self.avail_ring)? & VIRTQ_AVAIL_F_NO_INTERRUPT == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::cell::RefCell;
+
+    const DESC: u64 = 0x0;
+    const AVAIL: u64 = 0x1000;
+    const USED: u64 = 0x2000;
+    const DATA: u64 = 0x3000;
+
+    struct TestMem(RefCell<Vec<u8>>);
+
+    impl TestMem {
+        fn new() -> Self {
+            Self(RefCell::new(alloc::vec![0; 0x4000]))
+        }
+    }
+
+    impl GuestMemory for TestMem {
+        fn read(&self, ipa: u64, buf: &mut [u8]) -> Result<(), GuestMemError> {
+            let mem = self.0.borrow();
+            let start = ipa as usize;
+            let src = mem
+                .get(start..start + buf.len())
+                .ok_or(GuestMemError::Unmapped(ipa))?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write(&self, ipa: u64, buf: &[u8]) -> Result<(), GuestMemError> {
+            let mut mem = self.0.borrow_mut();
+            let start = ipa as usize;
+            let dst = mem
+                .get_mut(start..start + buf.len())
+                .ok_or(GuestMemError::Unmapped(ipa))?;
+            dst.copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    fn set_desc(mem: &TestMem, table: u64, i: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let base = table + i as u64 * DESC_SIZE;
+        mem.write_u64(base, addr).unwrap();
+        mem.write_u32(base + 8, len).unwrap();
+        mem.write_u16(base + 12, flags).unwrap();
+        mem.write_u16(base + 14, next).unwrap();
+    }
+
+    fn push_avail(mem: &TestMem, head: u16) {
+        let idx = mem.read_u16(AVAIL + 2).unwrap();
+        mem.write_u16(AVAIL + RING_HEADER + 2 * (idx % 8) as u64, head)
+            .unwrap();
+        mem.write_u16(AVAIL + 2, idx.wrapping_add(1)).unwrap();
+    }
+
+    fn ready_queue() -> Virtqueue {
+        let mut q = Virtqueue::new();
+        q.size = 8;
+        q.desc_table = DESC;
+        q.avail_ring = AVAIL;
+        q.used_ring = USED;
+        q.set_ready(true).unwrap();
+        q
+    }
+
+    #[test]
+    fn test_pop_and_add_used() {
+        let mem = TestMem::new();
+        let mut q = ready_queue();
+        assert_eq!(q.pop(&mem), Ok(None));
+        set_desc(&mem, DESC, 0, DATA, 16, VIRTQ_DESC_F_NEXT, 1);
+        set_desc(&mem, DESC, 1, DATA + 0x100, 32, VIRTQ_DESC_F_WRITE, 0);
+        push_avail(&mem, 0);
+        let chain = q.pop(&mem).unwrap().unwrap();
+        assert_eq!(chain.head, 0);
+        assert_eq!(chain.readable().count(), 1);
+        assert_eq!(chain.writable().next().unwrap().len, 32);
+        assert_eq!(q.pop(&mem), Ok(None));
+
+        q.add_used(&mem, chain.head, 32).unwrap();
+        assert_eq!(mem.read_u16(USED + 2), Ok(1));
+        assert_eq!(mem.read_u32(USED + RING_HEADER), Ok(0));
+        assert_eq!(mem.read_u32(USED + RING_HEADER + 4), Ok(32));
+        assert_eq!(q.needs_notification(&mem), Ok(true));
+    }
+
+    #[test]
+    fn test_malicious_chains() {
+        let mem = TestMem::new();
+        let mut q = ready_queue();
+        // Loop.
+        set_desc(&mem, DESC, 0, DATA, 16, VIRTQ_DESC_F_NEXT, 1);
+        set_desc(&mem, DESC, 1, DATA, 16, VIRTQ_DESC_F_NEXT, 0);
+        push_avail(&mem, 0);
+        assert_eq!(q.pop(&mem), Err(QueueError::ChainTooLong));
+        // Nothing was consumed.
+        assert_eq!(q.pop(&mem), Err(QueueError::ChainTooLong));
+
+        let mut q = ready_queue();
+        set_desc(&mem, DESC, 1, DATA, 16, VIRTQ_DESC_F_NEXT, 9);
+        mem.write_u16(AVAIL + RING_HEADER, 1).unwrap();
+        assert_eq!(q.pop(&mem), Err(QueueError::BadDescIndex(9)));
+
+        let mut q = ready_queue();
+        set_desc(
+            &mem,
+            DESC,
+            1,
+            DATA,
+            16,
+            VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+            2,
+        );
+        set_desc(&mem, DESC, 2, DATA, 16, 0, 0);
+        assert_eq!(q.pop(&mem), Err(QueueError::ReadAfterWrite));
+
+        let mut q = ready_queue();
+        set_desc(&mem, DESC, 1, u64::MAX - 4, 16, 0, 0);
+        assert_eq!(q.pop(&mem), Err(QueueError::LengthOverflow));
+
+        let mut q = ready_queue();
+        mem.write_u16(AVAIL + 2, 9).unwrap();
+        assert_eq!(q.pop(&mem), Err(QueueError::BadAvailIdx(9)));
+    }
+
+    #[test]
+    fn test_indirect() {
+        let mem = TestMem::new();
+        let mut q = ready_queue();
+        let table = DATA + 0x800;
+        set_desc(&mem, DESC, 0, table, 32, VIRTQ_DESC_F_INDIRECT, 0);
+        set_desc(&mem, table, 0, DATA, 8, VIRTQ_DESC_F_NEXT, 1);
+        set_desc(&mem, table, 1, DATA + 8, 8, VIRTQ_DESC_F_WRITE, 0);
+        push_avail(&mem, 0);
+        let chain = q.pop(&mem).unwrap().unwrap();
+        assert_eq!(chain.bufs.len(), 2);
+
+        // Nested indirect table.
+        let mut q = ready_queue();
+        set_desc(&mem, table, 1, table, 32, VIRTQ_DESC_F_INDIRECT, 0);
+        assert_eq!(q.pop(&mem), Err(QueueError::BadIndirect));
+    }
+
+    #[test]
+    fn test_set_ready_checks_layout() {
+        let mut q = Virtqueue::new();
+        q.size = 6;
+        assert_eq!(q.set_ready(true), Err(QueueError::BadConfig));
+        q.size = 8;
+        q.desc_table = 0x8;
+        assert_eq!(q.set_ready(true), Err(QueueError::BadConfig));
+        q.desc_table = 0;
+        q.used_ring = u64::MAX - 8;
+        assert_eq!(q.set_ready(true), Err(QueueError::BadConfig));
+        q.used_ring = USED;
+        assert!(q.set_ready(true).is_ok());
+    }
+}
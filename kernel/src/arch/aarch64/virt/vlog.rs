@@ -0,0 +1,214 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-component log levels for the hypervisor. `vlog!` checks its
+//! component's level instead of the kernel-wide one, so e.g. Stage-2 can be
+//! traced without flooding the console with everything else. A component
+//! left at its default follows the kernel-wide level.
+//!
+//! Call sites on paths taken at exit rate use `vlog_limited!`, which lets
+//! each of them through at most `BURST` times a second, so a guest stuck
+//! in a loop of failing exits can't flood the console.
+
+use crate::arch::aarch64::registers::cntfrq_el0::CNTFRQ_EL0;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+use log::{Level, LevelFilter, Record};
+use tock_registers::interfaces::Readable;
+
+/// Messages a `vlog_limited!` call site logs per window.
+pub const BURST: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Vcpu,
+    Vgic,
+    Exit,
+    Mmio,
+    Stage2,
+}
+
+impl Component {
+    pub const ALL: [Component; 5] = [
+        Component::Vcpu,
+        Component::Vgic,
+        Component::Exit,
+        Component::Mmio,
+        Component::Stage2,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Component::Vcpu => "vcpu",
+            Component::Vgic => "vgic",
+            Component::Exit => "exit",
+            Component::Mmio => "mmio",
+            Component::Stage2 => "stage2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+// A `LevelFilter` as usize, or `DEFAULT`.
+const DEFAULT: usize = usize::MAX;
+static LEVELS: [AtomicUsize; Component::ALL.len()] =
+    [const { AtomicUsize::new(DEFAULT) }; Component::ALL.len()];
+
+/// Level `comp` logs at, `None` if it follows the kernel-wide level.
+pub fn level(comp: Component) -> Option<LevelFilter> {
+    match LEVELS[comp as usize].load(Ordering::Relaxed) {
+        DEFAULT => None,
+        n => LevelFilter::iter().nth(n),
+    }
+}
+
+/// Set the level of `comp`; `None` makes it follow the kernel-wide level.
+pub fn set_level(comp: Component, level: Option<LevelFilter>) {
+    let n = level.map_or(DEFAULT, |l| l as usize);
+    LEVELS[comp as usize].store(n, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn enabled(comp: Component, level: Level) -> bool {
+    level <= self::level(comp).unwrap_or_else(log::max_level)
+}
+
+#[doc(hidden)]
+pub fn emit(comp: Component, level: Level, args: fmt::Arguments) {
+    crate::logger::log_unfiltered(
+        &Record::builder()
+            .args(args)
+            .level(level)
+            .target(comp.name())
+            .build(),
+    );
+}
+
+/// Budget of one `vlog_limited!` call site. Lock-free, so it works at
+/// EL2; concurrent callers may overshoot the burst by a message or two.
+pub struct RateLimit {
+    // Physical count the current window started at.
+    start: AtomicU64,
+    logged: AtomicU32,
+    suppressed: AtomicU32,
+}
+
+impl RateLimit {
+    pub const fn new() -> Self {
+        Self {
+            start: AtomicU64::new(0),
+            logged: AtomicU32::new(0),
+            suppressed: AtomicU32::new(0),
+        }
+    }
+
+    /// Whether a message may go out at physical count `now` with windows
+    /// `window` cycles long. If so, returns how many were suppressed since
+    /// the last one that went out.
+    pub fn check(&self, now: u64, window: u64) -> Option<u32> {
+        let start = self.start.load(Ordering::Relaxed);
+        if now.wrapping_sub(start) >= window
+            && self
+                .start
+                .compare_exchange(start, now, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.logged.store(0, Ordering::Relaxed);
+        }
+        if self.logged.fetch_add(1, Ordering::Relaxed) < BURST {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Window length of `vlog_limited!`: one second of the physical counter.
+#[doc(hidden)]
+pub fn window() -> u64 {
+    CNTFRQ_EL0.get()
+}
+
+/// `vlog!(Stage2, Debug, "...", args)` logs through the kernel logger if
+/// the component's level lets it through.
+macro_rules! vlog {
+    ($comp:ident, $level:ident, $($arg:tt)+) => {{
+        use $crate::arch::virt::vlog::{emit, enabled, Component};
+        if enabled(Component::$comp, log::Level::$level) {
+            emit(Component::$comp, log::Level::$level, format_args!($($arg)+));
+        }
+    }};
+}
+pub(crate) use vlog;
+
+/// `vlog!` for hot paths: the call site logs at most `BURST` messages a
+/// second, and the first message after suppressed ones says how many.
+macro_rules! vlog_limited {
+    ($comp:ident, $level:ident, $($arg:tt)+) => {{
+        use $crate::arch::virt::vlog::{emit, enabled, window, Component, RateLimit};
+        static LIMIT: RateLimit = RateLimit::new();
+        if enabled(Component::$comp, log::Level::$level) {
+            let now = $crate::arch::virt::hyper::read_cntpct();
+            match LIMIT.check(now, window()) {
+                Some(0) => emit(Component::$comp, log::Level::$level, format_args!($($arg)+)),
+                Some(n) => emit(
+                    Component::$comp,
+                    log::Level::$level,
+                    format_args!("{} ({} suppressed)", format_args!($($arg)+), n),
+                ),
+                None => {}
+            }
+        }
+    }};
+}
+pub(crate) use vlog_limited;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_component_levels() {
+        assert_eq!(Component::from_name("stage2"), Some(Component::Stage2));
+        assert_eq!(Component::from_name("gic"), None);
+        set_level(Component::Mmio, Some(LevelFilter::Trace));
+        assert_eq!(level(Component::Mmio), Some(LevelFilter::Trace));
+        assert!(enabled(Component::Mmio, Level::Trace));
+        set_level(Component::Mmio, Some(LevelFilter::Off));
+        assert!(!enabled(Component::Mmio, Level::Error));
+        set_level(Component::Mmio, None);
+        assert_eq!(level(Component::Mmio), None);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new();
+        let window = 1000;
+        for _ in 0..BURST {
+            assert_eq!(limit.check(window, window), Some(0));
+        }
+        assert_eq!(limit.check(window + 1, window), None);
+        assert_eq!(limit.check(window + 2, window), None);
+        // A new window lets the next message through with the count.
+        assert_eq!(limit.check(2 * window, window), Some(2));
+        assert_eq!(limit.check(2 * window + 1, window), Some(0));
+    }
+}
@@ -0,0 +1,1298 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Two-phase VM creation. A `VmBuilder` collects the whole VM description;
+//! `validate` checks it against what the platform offers without touching
+//! anything, and `build` either creates all of it or leaves no trace.
+//!
+//! A built VM is a `Vm` kept by the `VmManager`. Its vCPUs hold it up: it
+//! is dropped with the last of them, and dropping it releases everything
+//! `build` set up for it.
+
+use super::{
+    alternative::{self, Features},
+    audit::{self, Initiator, Operation},
+    console_log, copy, doorbell, gpio, grant,
+    heartbeat::{self, HeartbeatPage},
+    hotplug, hyper,
+    identity::{self, Identity, IdentityError, Uuid},
+    irq_line::{IrqLine, Trigger},
+    lazy_ram,
+    mmio::{self, ErrorReport},
+    pl011,
+    profile::VmConfig,
+    ptimer::PTimer,
+    recovery, shim,
+    spi::{self, Spi, SpiMap, FIRST_SPI},
+    stage2::{self, Geometry, MemType, S2Perms, Stage2, PAGE_SIZE},
+    status,
+    syscon::{self, SysconConfig},
+    vconsole,
+    vcpu::{vcpu_manager, VcpuError, MAX_VCPUS_PER_VM},
+    verify::{self, Expected, Outcome, Policy},
+    vgic::MAX_INTID,
+    vgicr, workers,
+};
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+/// What the platform can give a new VM right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtCaps {
+    /// Largest IPA size a VM may ask for, bounded by the CPU's PARange.
+    pub ipa_bits: u32,
+    /// Most vCPUs one VM may have.
+    pub max_vcpus: usize,
+    pub num_cores: usize,
+    pub max_intid: u32,
+    /// CPU features the hypervisor was patched for.
+    pub features: Features,
+}
+
+impl VirtCaps {
+    pub fn probe() -> Self {
+        Self {
+            ipa_bits: stage2::max_ipa_bits(),
+            max_vcpus: MAX_VCPUS_PER_VM,
+            num_cores: NUM_CORES,
+            max_intid: MAX_INTID,
+            features: alternative::features(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// The VM id already has Stage-2 tables.
+    VmExists,
+    NoVcpus,
+    /// `VmConfig::ipa_bits` is more than the hardware allows or no table
+    /// layout fits it.
+    IpaSize {
+        bits: u32,
+        max: u32,
+    },
+    /// More vCPUs than `VmConfig::max_vcpus` or the platform allow.
+    TooManyVcpus {
+        wanted: usize,
+        max: usize,
+    },
+    MemMisaligned {
+        ipa: u64,
+    },
+    MemOutOfRange {
+        ipa: u64,
+    },
+    MemOverlap {
+        ipa: u64,
+        other: u64,
+    },
+    /// A device region overlaps memory or another device.
+    DeviceOverlap {
+        base: u64,
+        other: u64,
+    },
+    /// The boot vCPU would start outside executable memory.
+    EntryNotExecutable {
+        entry: u64,
+    },
+    BadIntid {
+        intid: u32,
+    },
+    IntidShared {
+        intid: u32,
+    },
+    /// The SPI is kept for hotplugged devices.
+    IntidReserved {
+        intid: u32,
+    },
+    /// No SPI was left to hand out under the name.
+    NoFreeSpi {
+        name: &'static str,
+    },
+    /// Memory covers the page `VmConfig::reset_shim` needs.
+    ShimOverlap {
+        ipa: u64,
+    },
+    /// `VmConfig::isolated_cores` names cores the platform doesn't have.
+    NoSuchCore {
+        mask: usize,
+    },
+    /// `VmConfig::isolated_cores` leaves the host without a core.
+    NoHostCore,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::VmExists => write!(f, "vm already exists"),
+            Self::NoVcpus => write!(f, "no vcpus"),
+            Self::IpaSize { bits, max } => {
+                write!(
+                    f,
+                    "{}-bit ipa space unsupported, {} bits at most",
+                    bits, max
+                )
+            }
+            Self::TooManyVcpus { wanted, max } => {
+                write!(f, "{} vcpus wanted, {} at most", wanted, max)
+            }
+            Self::MemMisaligned { ipa } => write!(f, "memory at {:#x} not page aligned", ipa),
+            Self::MemOutOfRange { ipa } => write!(f, "memory at {:#x} outside the ipa space", ipa),
+            Self::MemOverlap { ipa, other } => {
+                write!(f, "memory at {:#x} overlaps memory at {:#x}", ipa, other)
+            }
+            Self::DeviceOverlap { base, other } => {
+                write!(f, "device at {:#x} overlaps region at {:#x}", base, other)
+            }
+            Self::EntryNotExecutable { entry } => {
+                write!(f, "entry {:#x} not in executable memory", entry)
+            }
+            Self::BadIntid { intid } => write!(f, "intid {} is not an spi", intid),
+            Self::IntidShared { intid } => write!(f, "intid {} used by two devices", intid),
+            Self::IntidReserved { intid } => write!(f, "intid {} reserved for hotplug", intid),
+            Self::NoFreeSpi { name } => write!(f, "no spi left for {}", name),
+            Self::ShimOverlap { ipa } => {
+                write!(f, "memory at {:#x} covers the reset shim", ipa)
+            }
+            Self::NoSuchCore { mask } => write!(f, "isolated cores {:#x} don't exist", mask),
+            Self::NoHostCore => write!(f, "isolated cores leave none to the host"),
+        }
+    }
+}
+
+/// Everything wrong with a VM description; empty if it can be built.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub conflicts: Vec<Conflict>,
+}
+
+impl ValidationReport {
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for conflict in &self.conflicts {
+            writeln!(f, "{}", conflict)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    Invalid(ValidationReport),
+    /// A step failed although the description validated, e.g. because
+    /// another VM claimed the isolated cores meanwhile. Nothing was kept.
+    Failed(&'static str),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MemRegion {
+    ipa: u64,
+    pa: u64,
+    size: u64,
+    mem: MemType,
+    perms: S2Perms,
+    // Host ROM, flash or an embedded payload; other VMs may map it too.
+    rom: bool,
+    // Allocated on first write, see `lazy_ram`; `pa` is unused.
+    lazy: bool,
+}
+
+/// Read-only payload embedded in the kernel, laid out so guests can map it
+/// where it is: page aligned, and padded to whole pages so nothing else of
+/// the kernel shares its last page.
+///
+/// ```ignore
+/// static FIRMWARE: RomImage<{ include_bytes!("fw.bin").len() }> =
+///     RomImage(*include_bytes!("fw.bin"));
+/// ```
+#[repr(C, align(4096))]
+pub struct RomImage<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> RomImage<N> {
+    /// Host physical address; the kernel runs identity mapped.
+    pub fn pa(&'static self) -> u64 {
+        self as *const Self as u64
+    }
+
+    /// Bytes a guest mapping takes, padding included.
+    pub const fn size(&self) -> u64 {
+        core::mem::size_of::<Self>() as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Device {
+    Gpio { base: u64, intid: u32 },
+    Pl011 { base: u64, intid: u32 },
+    Syscon { base: u64, config: SysconConfig },
+    Gicr { base: u64 },
+}
+
+impl Device {
+    fn range(&self) -> (u64, u64) {
+        match *self {
+            Device::Gpio { base, .. } => (base, gpio::REGION_SIZE),
+            Device::Pl011 { base, .. } => (base, pl011::REGION_SIZE),
+            Device::Syscon { base, .. } => (base, syscon::REGION_SIZE),
+            Device::Gicr { base } => (base, vgicr::REGION_SIZE),
+        }
+    }
+
+    fn intid(&self) -> Option<u32> {
+        match *self {
+            Device::Gpio { intid, .. } | Device::Pl011 { intid, .. } => Some(intid),
+            Device::Syscon { .. } | Device::Gicr { .. } => None,
+        }
+    }
+}
+
+/// A VM `VmBuilder::build` created. Dropping it tears down what the VM
+/// still holds: devices, hotplugged devices, pages handed out on demand,
+/// doorbells, grants, the copy buffer and finally its Stage-2 tables,
+/// identity and placement. Its
+/// vCPUs must be gone by then.
+pub struct Vm {
+    id: usize,
+    config: VmConfig,
+    // One time base for the emulated physical timers of all vCPUs.
+    ptimer_base: u64,
+}
+
+impl Vm {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        let vm_id = self.id;
+        // First, so no job of the VM runs against what goes next.
+        workers::cancel_vm(vm_id);
+        hotplug::release_vm(vm_id);
+        gpio::destroy(vm_id);
+        pl011::destroy(vm_id);
+        syscon::destroy(vm_id);
+        vgicr::destroy(vm_id);
+        doorbell::release_vm(vm_id);
+        // Before its tables go, so no peer keeps its pages mapped.
+        grant::release_vm(vm_id);
+        copy::clear_host_buffer(vm_id);
+        heartbeat::release_vm(vm_id);
+        spi::release_vm(vm_id);
+        lazy_ram::release_vm(vm_id);
+        recovery::clear_failed(vm_id);
+        #[cfg(debug)]
+        super::sanity::unregister_vm(vm_id);
+        stage2::remove(vm_id);
+    }
+}
+
+/// The VMs that are built and still have vCPUs.
+pub struct VmManager {
+    vms: SpinLock<BTreeMap<usize, Vm>>,
+}
+
+static VM_MANAGER: VmManager = VmManager::new();
+
+pub fn vm_manager() -> &'static VmManager {
+    &VM_MANAGER
+}
+
+impl VmManager {
+    pub const fn new() -> Self {
+        Self {
+            vms: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn insert(&self, vm: Vm) {
+        let old = self.vms.irqsave_lock().insert(vm.id, vm);
+        debug_assert!(old.is_none());
+    }
+
+    pub fn contains(&self, vm_id: usize) -> bool {
+        self.vms.irqsave_read().contains_key(&vm_id)
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.vms.irqsave_read().keys().copied().collect()
+    }
+
+    pub fn with_vm<R>(&self, vm_id: usize, f: impl FnOnce(&Vm) -> R) -> Option<R> {
+        self.vms.irqsave_read().get(&vm_id).map(f)
+    }
+
+    /// Destroy every vCPU of VM `vm_id`, and with the last the VM. The
+    /// vCPUs in guest mode are kept, and with them the VM; the others still
+    /// go, and the first error is returned once all were tried.
+    pub fn destroy(&self, vm_id: usize) -> Result<(), VcpuError> {
+        let ids: Vec<usize> = vcpu_manager().ids_of(vm_id).collect();
+        let mut result = Ok(());
+        let mut kept = 0;
+        for id in ids {
+            if let Err(e) = vcpu_manager().destroy_vcpu(id) {
+                result = result.and(Err(e));
+                kept += 1;
+            }
+        }
+        if kept != 0 {
+            log::warn!(
+                "[virt] {} keeps {} vcpus it couldn't destroy",
+                identity::tag(vm_id),
+                kept
+            );
+            return result;
+        }
+        // A VM whose build failed before its first vCPU has none.
+        self.release(vm_id);
+        Ok(())
+    }
+
+    /// Drop VM `vm_id`, whose last vCPU is gone.
+    pub(crate) fn release(&self, vm_id: usize) {
+        let vm = self.vms.irqsave_lock().remove(&vm_id);
+        drop(vm);
+    }
+}
+
+pub struct VmBuilder {
+    vm_id: usize,
+    config: VmConfig,
+    identity: Identity,
+    memory: Vec<MemRegion>,
+    devices: Vec<Device>,
+    // SPIs handed out with `irq_line`.
+    lines: Vec<u32>,
+    // SPIs handed out by name, see `Spi`.
+    spis: SpiMap,
+    // Names of `spis` a device or line raises; the others are only kept.
+    bound_spis: Vec<&'static str>,
+    // Names no SPI was left for.
+    no_spi: Vec<&'static str>,
+    boot: Option<(u64, u64)>,
+    boot_args: Option<[u64; 4]>,
+    secondaries: usize,
+    capture_console: bool,
+    // Image to check before the VM starts: ipa, size and what it must match.
+    image: Option<(u64, u64, Expected)>,
+    // IPA of the heartbeat page.
+    heartbeat: Option<u64>,
+    // Bytes of the host buffer for `copy`.
+    copy_buffer: Option<usize>,
+    // How backend failures of the device at a base reach the guest, for
+    // those not left at the default.
+    error_reports: Vec<(u64, ErrorReport)>,
+    selftest: bool,
+}
+
+fn overlaps((a, a_size): (u64, u64), (b, b_size): (u64, u64)) -> bool {
+    a < b.saturating_add(b_size) && b < a.saturating_add(a_size)
+}
+
+impl VmBuilder {
+    /// Start describing VM `vm_id`, whose vCPUs all use `config`.
+    pub fn new(vm_id: usize, config: VmConfig) -> Self {
+        Self {
+            vm_id,
+            config,
+            identity: Identity::default(),
+            memory: Vec::new(),
+            devices: Vec::new(),
+            lines: Vec::new(),
+            spis: SpiMap::new(),
+            bound_spis: Vec::new(),
+            no_spi: Vec::new(),
+            boot: None,
+            boot_args: None,
+            secondaries: 0,
+            capture_console: false,
+            image: None,
+            heartbeat: None,
+            copy_buffer: None,
+            error_reports: Vec::new(),
+            selftest: false,
+        }
+    }
+
+    /// Identify the VM by `uuid` instead of a generated one, e.g. to keep
+    /// it across boots.
+    pub fn uuid(mut self, uuid: Uuid) -> Self {
+        self.identity.uuid = uuid;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.identity.name = Some(String::from(name));
+        self
+    }
+
+    /// Attach `key` = `value` to the VM's identity.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.identity
+            .metadata
+            .push((String::from(key), String::from(value)));
+        self
+    }
+
+    /// Map `[ipa, ipa + size)` to host physical `pa`.
+    pub fn memory(mut self, ipa: u64, pa: u64, size: u64, mem: MemType, perms: S2Perms) -> Self {
+        self.memory.push(MemRegion {
+            ipa,
+            pa,
+            size,
+            mem,
+            perms,
+            rom: false,
+            lazy: false,
+        });
+        self
+    }
+
+    /// RAM at `[ipa, ipa + size)` that reads as zeros and gets host memory
+    /// page by page as the guest first writes to it.
+    pub fn lazy_memory(mut self, ipa: u64, size: u64, perms: S2Perms) -> Self {
+        self.memory.push(MemRegion {
+            ipa,
+            pa: 0,
+            size,
+            mem: MemType::Normal,
+            perms,
+            rom: false,
+            lazy: true,
+        });
+        self
+    }
+
+    /// Map `[ipa, ipa + size)` read-only to host ROM or flash at `pa`,
+    /// executable with `exec`. Guest writes fault; no copy is made, so VMs
+    /// mapping the same payload share its pages.
+    pub fn rom(mut self, ipa: u64, pa: u64, size: u64, exec: bool) -> Self {
+        self.memory.push(MemRegion {
+            ipa,
+            pa,
+            size,
+            mem: MemType::Normal,
+            perms: if exec { S2Perms::RX } else { S2Perms::RO },
+            rom: true,
+            lazy: false,
+        });
+        self
+    }
+
+    /// Map a payload embedded in the kernel read-only at `ipa`.
+    pub fn rom_image<const N: usize>(
+        self,
+        ipa: u64,
+        image: &'static RomImage<N>,
+        exec: bool,
+    ) -> Self {
+        self.rom(ipa, image.pa(), image.size(), exec)
+    }
+
+    /// The boot vCPU, started at `entry` with the boot protocol's `arg`.
+    pub fn boot_vcpu(mut self, entry: u64, arg: u64) -> Self {
+        self.boot = Some((entry, arg));
+        self
+    }
+
+    /// x0-x3 of the boot vCPU on its first entry and after each reset, in
+    /// place of what the boot protocol puts there.
+    pub fn boot_args(mut self, args: [u64; 4]) -> Self {
+        self.boot_args = Some(args);
+        self
+    }
+
+    /// vCPUs powered off until the guest starts them with PSCI CPU_ON.
+    pub fn secondary_vcpus(mut self, count: usize) -> Self {
+        self.secondaries = count;
+        self
+    }
+
+    pub fn gpio(mut self, base: u64, spi: Spi) -> Self {
+        if let Some(intid) = self.take_spi(spi) {
+            self.devices.push(Device::Gpio { base, intid });
+        }
+        self
+    }
+
+    /// PL011 UART for the guest's console, see `pl011`.
+    pub fn pl011(mut self, base: u64, spi: Spi) -> Self {
+        if let Some(intid) = self.take_spi(spi) {
+            self.devices.push(Device::Pl011 { base, intid });
+        }
+        self
+    }
+
+    /// Verify the `size` bytes of guest memory at `ipa` against `expected`
+    /// when the VM is built; the `verify` policy decides whether a guest
+    /// that doesn't match starts.
+    pub fn verify_image(mut self, ipa: u64, size: u64, expected: Expected) -> Self {
+        self.image = Some((ipa, size, expected));
+        self
+    }
+
+    /// Read-only page at `ipa` the hypervisor updates on every guest entry,
+    /// see `heartbeat`.
+    pub fn heartbeat(mut self, ipa: u64) -> Self {
+        self.heartbeat = Some(ipa);
+        self
+    }
+
+    /// Host buffer of `len` bytes the guest copies to and from with
+    /// `GUEST_HVC_COPY`, see `copy`.
+    pub fn copy_buffer(mut self, len: usize) -> Self {
+        self.copy_buffer = Some(len);
+        self
+    }
+
+    /// The guest is a selftest: how it ends ends the run, see
+    /// `status::set_selftest`.
+    pub fn selftest(mut self) -> Self {
+        self.selftest = true;
+        self
+    }
+
+    /// GICv3 redistributor frames for the guest's vCPUs, see `vgicr`.
+    pub fn gicr(mut self, base: u64) -> Self {
+        self.devices.push(Device::Gicr { base });
+        self
+    }
+
+    /// Report backend failures of the device at `base` as `report` says
+    /// instead of as `ErrorReport::Status`.
+    pub fn error_report(mut self, base: u64, report: ErrorReport) -> Self {
+        self.error_reports.push((base, report));
+        self
+    }
+
+    /// Keep the guest's console output for the host to read instead of
+    /// writing it to the host console, see `vconsole::capture`.
+    pub fn capture_console(mut self) -> Self {
+        self.capture_console = true;
+        self
+    }
+
+    /// System controller the guest reboots or powers off through, see
+    /// `syscon`.
+    pub fn syscon(mut self, base: u64, config: SysconConfig) -> Self {
+        self.devices.push(Device::Syscon { base, config });
+        self
+    }
+
+    /// Line for a device model of the VM to raise SPI `intid` with.
+    /// Validation checks that nothing else raises it.
+    pub fn irq_line(&mut self, intid: u32, trigger: Trigger) -> IrqLine {
+        self.lines.push(intid);
+        IrqLine::new(self.vm_id, intid, trigger)
+    }
+
+    /// Keep an SPI under `name` for a device model that isn't added yet,
+    /// e.g. one the host sets up once the VM runs and finds with
+    /// `spi::lookup`.
+    pub fn spi(mut self, name: &'static str) -> Self {
+        let _ = self.alloc_spi(name, None);
+        self
+    }
+
+    /// `irq_line` on the SPI named `name`. `None` once all are taken.
+    pub fn named_irq_line(&mut self, name: &'static str, trigger: Trigger) -> Option<IrqLine> {
+        let intid = self.take_spi(Spi::Named(name))?;
+        Some(self.irq_line(intid, trigger))
+    }
+
+    // The SPI named `name`, see `SpiMap::alloc`: one no device or line
+    // added so far raises. Validation reports it if none is left.
+    fn alloc_spi(&mut self, name: &'static str, prefer: Option<u32>) -> Option<u32> {
+        let taken: Vec<u32> = self
+            .devices
+            .iter()
+            .filter_map(Device::intid)
+            .chain(self.lines.iter().copied())
+            .collect();
+        let intid = self
+            .spis
+            .alloc(name, prefer, |intid| taken.contains(&intid));
+        if intid.is_none() {
+            self.no_spi.push(name);
+        }
+        intid
+    }
+
+    // The SPI a device or line about to be added raises.
+    fn take_spi(&mut self, spi: Spi) -> Option<u32> {
+        let (name, prefer) = match spi {
+            Spi::Fixed(intid) => return Some(intid),
+            Spi::Named(name) => (name, None),
+            Spi::Prefer(name, intid) => (name, Some(intid)),
+        };
+        let intid = self.alloc_spi(name, prefer)?;
+        self.bound_spis.push(name);
+        Some(intid)
+    }
+
+    fn num_vcpus(&self) -> usize {
+        self.boot.is_some() as usize + self.secondaries
+    }
+
+    /// Check the description against the running platform. Nothing is
+    /// allocated or registered.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = self.validate_against(&VirtCaps::probe());
+        if stage2::with_vm(self.vm_id, |_| ()).is_some() {
+            report.conflicts.insert(0, Conflict::VmExists);
+        }
+        report
+    }
+
+    /// The checks of `validate` that only depend on `caps`.
+    pub fn validate_against(&self, caps: &VirtCaps) -> ValidationReport {
+        let mut conflicts = Vec::new();
+
+        let ipa_bits = self.config.ipa_bits;
+        if ipa_bits > caps.ipa_bits || Geometry::of(ipa_bits).is_none() {
+            conflicts.push(Conflict::IpaSize {
+                bits: ipa_bits,
+                max: caps.ipa_bits,
+            });
+        }
+
+        let max_vcpus = self.config.max_vcpus.min(caps.max_vcpus);
+        match self.num_vcpus() {
+            0 => conflicts.push(Conflict::NoVcpus),
+            n if n > max_vcpus => conflicts.push(Conflict::TooManyVcpus {
+                wanted: n,
+                max: max_vcpus,
+            }),
+            _ => {}
+        }
+
+        for (n, m) in self.memory.iter().enumerate() {
+            if m.ipa % PAGE_SIZE != 0 || m.pa % PAGE_SIZE != 0 || m.size % PAGE_SIZE != 0 {
+                conflicts.push(Conflict::MemMisaligned { ipa: m.ipa });
+            }
+            if m.ipa
+                .checked_add(m.size)
+                .map_or(true, |end| end > 1 << ipa_bits.min(caps.ipa_bits))
+            {
+                conflicts.push(Conflict::MemOutOfRange { ipa: m.ipa });
+            }
+            if let Some(other) = self.memory[..n]
+                .iter()
+                .find(|o| overlaps((m.ipa, m.size), (o.ipa, o.size)))
+            {
+                conflicts.push(Conflict::MemOverlap {
+                    ipa: m.ipa,
+                    other: other.ipa,
+                });
+            }
+        }
+
+        if self.config.reset_shim {
+            let shim_ipa = shim::ipa(ipa_bits.min(caps.ipa_bits));
+            let shim = self
+                .memory
+                .iter()
+                .find(|m| overlaps((m.ipa, m.size), (shim_ipa, PAGE_SIZE)));
+            if let Some(m) = shim {
+                conflicts.push(Conflict::ShimOverlap { ipa: m.ipa });
+            }
+        }
+
+        if let Some(ipa) = self.heartbeat {
+            if ipa % PAGE_SIZE != 0 {
+                conflicts.push(Conflict::MemMisaligned { ipa });
+            }
+            if ipa >= 1 << ipa_bits.min(caps.ipa_bits) {
+                conflicts.push(Conflict::MemOutOfRange { ipa });
+            }
+            let other = self
+                .memory
+                .iter()
+                .find(|m| overlaps((m.ipa, m.size), (ipa, PAGE_SIZE)));
+            if let Some(m) = other {
+                conflicts.push(Conflict::MemOverlap { ipa, other: m.ipa });
+            }
+        }
+
+        if let Some((entry, _)) = self.boot {
+            let executable = self.memory.iter().any(|m| {
+                m.mem == MemType::Normal && m.perms.exec && overlaps((entry, 4), (m.ipa, m.size))
+            });
+            if !executable {
+                conflicts.push(Conflict::EntryNotExecutable { entry });
+            }
+        }
+
+        for (n, dev) in self.devices.iter().enumerate() {
+            let range = dev.range();
+            let other = self
+                .memory
+                .iter()
+                .map(|m| (m.ipa, m.size))
+                .chain(self.devices[..n].iter().map(Device::range))
+                .find(|&other| overlaps(range, other));
+            if let Some((other, _)) = other {
+                conflicts.push(Conflict::DeviceOverlap {
+                    base: range.0,
+                    other,
+                });
+            }
+        }
+
+        // Device interrupts first, then lines, then SPIs only kept by name,
+        // each checked against those before it.
+        let kept = self
+            .spis
+            .iter()
+            .filter(|(name, _)| !self.bound_spis.contains(name))
+            .map(|(_, intid)| intid);
+        let intids: Vec<u32> = self
+            .devices
+            .iter()
+            .filter_map(Device::intid)
+            .chain(self.lines.iter().copied())
+            .chain(kept)
+            .collect();
+        for (n, &intid) in intids.iter().enumerate() {
+            if !(FIRST_SPI..caps.max_intid).contains(&intid) {
+                conflicts.push(Conflict::BadIntid { intid });
+            } else if hotplug::is_reserved(intid) {
+                conflicts.push(Conflict::IntidReserved { intid });
+            } else if intids[..n].contains(&intid) {
+                conflicts.push(Conflict::IntidShared { intid });
+            }
+        }
+        for &name in &self.no_spi {
+            conflicts.push(Conflict::NoFreeSpi { name });
+        }
+
+        let all_cores = (1 << caps.num_cores) - 1;
+        let isolated = self.config.isolated_cores;
+        if isolated & !all_cores != 0 {
+            conflicts.push(Conflict::NoSuchCore {
+                mask: isolated & !all_cores,
+            });
+        } else if isolated != 0 && isolated == all_cores {
+            conflicts.push(Conflict::NoHostCore);
+        }
+
+        ValidationReport { conflicts }
+    }
+
+    /// Validate, then create the VM and hand it to the `VmManager`. On any
+    /// failure everything created so far is torn down again. Returns the
+    /// ids of the vCPUs, boot vCPU first.
+    pub fn build(mut self) -> Result<Vec<usize>, BuildError> {
+        let report = self.validate();
+        if !report.is_ok() {
+            return Err(BuildError::Invalid(report));
+        }
+
+        let mut s2 = Stage2::with_ipa_bits(self.config.ipa_bits)
+            .map_err(|_| BuildError::Failed("stage-2 tables failed"))?;
+        for m in &self.memory {
+            if m.lazy {
+                lazy_ram::map_zero(&mut s2, m.ipa, m.size, m.perms)
+            } else {
+                s2.map(m.ipa, m.pa, m.size, m.mem, m.perms)
+            }
+            .map_err(|_| BuildError::Failed("stage-2 map failed"))?;
+        }
+        // Checked before anything of the VM is installed, so a refused
+        // guest leaves nothing behind.
+        let outcome = match (verify::policy(), self.image) {
+            (Policy::Off, _) | (_, None) => Outcome::Unverified,
+            (_, Some((ipa, size, expected))) => verify::check(&s2, ipa, size, expected),
+        };
+        if !verify::admit(self.vm_id, outcome) {
+            return Err(BuildError::Failed("image not verified"));
+        }
+        if self.config.reset_shim {
+            s2.map(
+                shim::ipa(self.config.ipa_bits),
+                shim::pa(),
+                PAGE_SIZE,
+                MemType::Normal,
+                S2Perms::RX,
+            )
+            .map_err(|_| BuildError::Failed("stage-2 map failed"))?;
+        }
+        let heartbeat = match self.heartbeat {
+            Some(ipa) => {
+                let page = HeartbeatPage::new(hyper::read_cntpct());
+                s2.map(ipa, page.pa(), PAGE_SIZE, MemType::Normal, S2Perms::RO)
+                    .map_err(|_| BuildError::Failed("stage-2 map failed"))?;
+                Some(page)
+            }
+            None => None,
+        };
+        let mut identity = core::mem::take(&mut self.identity);
+        if identity.uuid == Uuid::NIL {
+            identity.uuid = Uuid::generate();
+        }
+        identity::register(self.vm_id, identity).map_err(|e| match e {
+            IdentityError::VmExists => BuildError::Failed("vm already exists"),
+            IdentityError::UuidTaken => BuildError::Failed("uuid taken"),
+        })?;
+        // Lost a race with another creation of the same VM.
+        if stage2::try_install(self.vm_id, s2).is_err() {
+            identity::release(self.vm_id);
+            return Err(BuildError::Failed("vm already exists"));
+        }
+        for m in self.memory.iter().filter(|m| m.lazy) {
+            lazy_ram::register(self.vm_id, m.ipa, m.size, m.perms);
+        }
+        if let Some(page) = heartbeat {
+            heartbeat::register(self.vm_id, page);
+        }
+        spi::register(self.vm_id, core::mem::take(&mut self.spis));
+        if let Some(len) = self.copy_buffer {
+            copy::set_host_buffer(self.vm_id, len);
+        }
+        #[cfg(debug)]
+        {
+            for m in self
+                .memory
+                .iter()
+                .filter(|m| m.mem == MemType::Normal && m.perms.exec)
+            {
+                super::sanity::register_exec_region(self.vm_id, m.ipa, m.size);
+            }
+            if self.config.reset_shim {
+                super::sanity::register_exec_region(
+                    self.vm_id,
+                    shim::ipa(self.config.ipa_bits),
+                    PAGE_SIZE,
+                );
+            }
+        }
+        // From here on the VM is torn down like any other.
+        vm_manager().insert(Vm {
+            id: self.vm_id,
+            config: self.config,
+            ptimer_base: hyper::read_cntpct(),
+        });
+
+        let mut vcpus = Vec::with_capacity(self.num_vcpus());
+        let result = self.create_parts(&mut vcpus);
+        if let Err(e) = result {
+            // None of its vCPUs ran yet.
+            let _ = vm_manager().destroy(self.vm_id);
+            vconsole::release(self.vm_id);
+            return Err(BuildError::Failed(e));
+        }
+        for m in &self.memory {
+            let (ipa, pa, size) = (m.ipa, m.pa, m.size);
+            let op = match m.mem {
+                MemType::Device => Operation::Passthrough { ipa, pa, size },
+                MemType::Normal if m.rom => Operation::Rom { ipa, pa, size },
+                MemType::Normal => continue,
+            };
+            audit::record(self.vm_id, self.config.label, Initiator::host(), op);
+        }
+        if self.selftest {
+            status::set_selftest(Some(self.vm_id));
+        }
+        Ok(vcpus)
+    }
+
+    fn create_parts(&self, vcpus: &mut Vec<usize>) -> Result<(), &'static str> {
+        // Output a previous VM with this id left isn't this one's.
+        vconsole::release(self.vm_id);
+        if self.capture_console {
+            vconsole::capture(self.vm_id, false);
+        }
+        console_log::vm_created(self.vm_id);
+        for dev in &self.devices {
+            match *dev {
+                Device::Gpio { base, intid } => {
+                    gpio::create(self.vm_id, base, intid).map_err(|_| "device overlap")?;
+                }
+                Device::Pl011 { base, intid } => {
+                    pl011::create(self.vm_id, base, intid).map_err(|_| "device overlap")?;
+                }
+                Device::Syscon { base, config } => {
+                    syscon::create(self.vm_id, base, config).map_err(|_| "device overlap")?;
+                }
+                Device::Gicr { base } => {
+                    vgicr::create(self.vm_id, base).map_err(|_| "device overlap")?;
+                }
+            }
+        }
+        for &(base, report) in &self.error_reports {
+            mmio::set_error_report(self.vm_id, base, report)
+                .map_err(|_| "error report for no device")?;
+        }
+        if let Some((entry, arg)) = self.boot {
+            let id = vcpu_manager()
+                .create_vcpu_with(self.vm_id, self.config, entry, arg)
+                .map_err(vcpu_failed)?;
+            vcpus.push(id);
+            if let Some(args) = self.boot_args {
+                vcpu_manager()
+                    .with_vcpu_mut(id, |vcpu| vcpu.set_boot_args(&args))
+                    .unwrap_or(Err(VcpuError::InvalidId))
+                    .map_err(vcpu_failed)?;
+            }
+        }
+        for _ in 0..self.secondaries {
+            vcpus.push(
+                vcpu_manager()
+                    .create_secondary_vcpu(self.vm_id, self.config)
+                    .map_err(vcpu_failed)?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Hook vCPU `vcpu_id`, just created for VM `vm_id`, up to what the VM
+/// shares between its vCPUs: redistributor frames, heartbeat page and the
+/// time base of the physical timer. Every vCPU goes through here, those
+/// the guest brings up with PSCI CPU_ON included.
+pub(crate) fn attach_vcpu(vm_id: usize, vcpu_id: usize) {
+    vgicr::attach(vm_id, vcpu_id);
+    heartbeat::attach(vm_id, vcpu_id);
+    if let Some(base) = vm_manager().with_vm(vm_id, |vm| vm.ptimer_base) {
+        vcpu_manager().with_vcpu_mut(vcpu_id, |vcpu| vcpu.ptimer = PTimer::new(base));
+    }
+}
+
+fn vcpu_failed(e: VcpuError) -> &'static str {
+    match e {
+        VcpuError::TooMany => "too many vcpus",
+        VcpuError::BadStackGuard => "bad stack guard range",
+        VcpuError::CoreTaken => "isolated cores taken",
+        _ => "vcpu creation failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    const CAPS: VirtCaps = VirtCaps {
+        ipa_bits: 39,
+        max_vcpus: 2,
+        num_cores: 4,
+        max_intid: 1020,
+        features: Features::empty(),
+    };
+
+    #[test]
+    fn test_validate_report() {
+        let ok = VmBuilder::new(usize::MAX, VmConfig::default())
+            .memory(
+                0x4000_0000,
+                0x8000_0000,
+                0x10_0000,
+                MemType::Normal,
+                S2Perms::RWX,
+            )
+            .gpio(0x0903_0000, Spi::Fixed(40))
+            .boot_vcpu(0x4000_0000, 0x4010_0000)
+            .secondary_vcpus(1);
+        assert!(ok.validate_against(&CAPS).is_ok());
+
+        let config = VmConfig {
+            isolated_cores: 0xf,
+            ..VmConfig::default()
+        };
+        let bad = VmBuilder::new(usize::MAX, config)
+            .memory(
+                0x4000_0000,
+                0x8000_0000,
+                0x10_0000,
+                MemType::Normal,
+                S2Perms::RW,
+            )
+            .memory(
+                0x4008_0000,
+                0x9000_0000,
+                0x1000,
+                MemType::Normal,
+                S2Perms::RWX,
+            )
+            .gpio(0x4000_0000, Spi::Fixed(40))
+            .gpio(0x0903_0000, Spi::Fixed(40))
+            .boot_vcpu(0x4000_0000, 0x4010_0000)
+            .secondary_vcpus(2);
+        assert_eq!(
+            bad.validate_against(&CAPS).conflicts,
+            [
+                Conflict::TooManyVcpus { wanted: 3, max: 2 },
+                Conflict::MemOverlap {
+                    ipa: 0x4008_0000,
+                    other: 0x4000_0000
+                },
+                Conflict::EntryNotExecutable { entry: 0x4000_0000 },
+                Conflict::DeviceOverlap {
+                    base: 0x4000_0000,
+                    other: 0x4000_0000
+                },
+                Conflict::IntidShared { intid: 40 },
+                Conflict::NoHostCore,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_ipa_size() {
+        let small = VmConfig {
+            ipa_bits: 32,
+            reset_shim: true,
+            ..VmConfig::default()
+        };
+        let builder = VmBuilder::new(usize::MAX, small)
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .memory(
+                0xffff_0000,
+                0x9000_0000,
+                0x1_0000,
+                MemType::Normal,
+                S2Perms::RW,
+            )
+            .memory(
+                0x1_0000_0000,
+                0xa000_0000,
+                0x1000,
+                MemType::Normal,
+                S2Perms::RW,
+            )
+            .boot_vcpu(0, 0);
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [
+                Conflict::MemOutOfRange { ipa: 0x1_0000_0000 },
+                Conflict::ShimOverlap { ipa: 0xffff_0000 },
+            ]
+        );
+
+        let large = VmConfig {
+            ipa_bits: 44,
+            ..VmConfig::default()
+        };
+        let builder = VmBuilder::new(usize::MAX, large)
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .boot_vcpu(0, 0);
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [Conflict::IpaSize { bits: 44, max: 39 }]
+        );
+        let caps = VirtCaps {
+            ipa_bits: 44,
+            ..CAPS
+        };
+        assert!(builder.validate_against(&caps).is_ok());
+    }
+
+    #[test]
+    fn test_validate_irq_lines() {
+        let mut builder = VmBuilder::new(usize::MAX, VmConfig::default())
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .gpio(0x0903_0000, Spi::Fixed(40))
+            .boot_vcpu(0, 0);
+        let line = builder.irq_line(41, Trigger::Level);
+        assert_eq!((line.intid(), line.trigger()), (41, Trigger::Level));
+        assert!(builder.validate_against(&CAPS).is_ok());
+
+        builder.irq_line(40, Trigger::Edge);
+        builder.irq_line(16, Trigger::Edge);
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [
+                Conflict::IntidShared { intid: 40 },
+                Conflict::BadIntid { intid: 16 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_named_spis() {
+        let mut builder = VmBuilder::new(usize::MAX, VmConfig::default())
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .gpio(0x0903_0000, Spi::Fixed(32))
+            .boot_vcpu(0, 0);
+        builder.irq_line(34, Trigger::Edge);
+        let mut builder = builder.spi("rtc").spi("rtc");
+        assert_eq!(builder.spis.lookup("rtc"), Some(33));
+        let line = builder.named_irq_line("net", Trigger::Level).unwrap();
+        assert_eq!(line.intid(), 35);
+        let builder = builder
+            .pl011(0x0900_0000, Spi::Prefer("uart", 34))
+            .pl011(0x0901_0000, Spi::Prefer("uart2", 40));
+        assert_eq!(builder.spis.lookup("uart"), Some(36));
+        assert_eq!(builder.spis.lookup("uart2"), Some(40));
+        assert!(builder.validate_against(&CAPS).is_ok());
+
+        // A fixed number is checked against the ones only kept by name.
+        let builder = builder.gpio(0x0904_0000, Spi::Fixed(33));
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [Conflict::IntidShared { intid: 33 }]
+        );
+
+        // Lines kept for hotplug are never handed out.
+        let mut spis = SpiMap::new();
+        let taken = |intid| intid < hotplug::FIRST_HOTPLUG_SPI;
+        assert_eq!(
+            spis.alloc("net", None, taken),
+            Some(hotplug::FIRST_HOTPLUG_SPI + hotplug::MAX_HOTPLUG as u32)
+        );
+        assert_eq!(spis.alloc("net2", Some(0), |_| false), Some(FIRST_SPI));
+    }
+
+    #[test]
+    fn test_validate_heartbeat() {
+        let builder = VmBuilder::new(usize::MAX, VmConfig::default())
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .boot_vcpu(0, 0);
+        assert!(builder.heartbeat(0x10_0000).validate_against(&CAPS).is_ok());
+
+        let builder = VmBuilder::new(usize::MAX, VmConfig::default())
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .heartbeat(0xf_f800)
+            .boot_vcpu(0, 0);
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [
+                Conflict::MemMisaligned { ipa: 0xf_f800 },
+                Conflict::MemOverlap {
+                    ipa: 0xf_f800,
+                    other: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rom_image() {
+        static ROM: RomImage<5> = RomImage(*b"fw v1");
+        assert_eq!(ROM.pa() % PAGE_SIZE, 0);
+        assert_eq!(ROM.size(), PAGE_SIZE);
+
+        let builder = VmBuilder::new(usize::MAX, VmConfig::default())
+            .rom_image(0, &ROM, true)
+            .rom(0x1000, 0x1_0000_0800, PAGE_SIZE, false)
+            .boot_vcpu(0, 0);
+        assert_eq!(
+            builder.validate_against(&CAPS).conflicts,
+            [Conflict::MemMisaligned { ipa: 0x1000 }]
+        );
+        assert!(builder.memory.iter().all(|m| m.rom && !m.perms.write));
+    }
+
+    #[test]
+    fn test_verify_image_refused() {
+        static ROM: RomImage<5> = RomImage(*b"fw v2");
+        let vm_id = test_vm_id();
+        verify::set_policy(Policy::Enforce);
+        let result = VmBuilder::new(vm_id, VmConfig::default())
+            .rom_image(0, &ROM, true)
+            .verify_image(0, 5, Expected::Sha256(verify::Digest([0; 32])))
+            .boot_vcpu(0, 0)
+            .build();
+        verify::set_policy(Policy::Warn);
+        assert!(matches!(
+            result,
+            Err(BuildError::Failed("image not verified"))
+        ));
+        assert!(matches!(verify::outcome(vm_id), Some(Outcome::Mismatch(_))));
+        assert!(stage2::with_vm(vm_id, |_| ()).is_none());
+    }
+
+    #[test]
+    fn test_destroy_releases_vm() {
+        let vm_id = test_vm_id();
+        let vcpus = VmBuilder::new(vm_id, VmConfig::default())
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .gpio(0x0903_0000, Spi::Fixed(40))
+            .syscon(0x0904_0000, SysconConfig::default())
+            .copy_buffer(64)
+            .boot_vcpu(0, 0)
+            .build()
+            .unwrap();
+        assert!(vm_manager().contains(vm_id));
+        assert!(gpio::get(vm_id).is_some());
+
+        assert!(vm_manager().destroy(vm_id).is_ok());
+        assert!(!vm_manager().contains(vm_id));
+        assert!(vcpu_manager().with_vcpu(vcpus[0], |_| ()).is_none());
+        assert!(gpio::get(vm_id).is_none());
+        assert!(copy::host_buffer(vm_id).is_none());
+        assert!(stage2::with_vm(vm_id, |_| ()).is_none());
+        assert!(identity::uuid_of(vm_id).is_none());
+    }
+
+    #[test]
+    fn test_error_report_needs_device() {
+        let vm_id = test_vm_id();
+        let result = VmBuilder::new(vm_id, VmConfig::default())
+            .gicr(0x080a_0000)
+            .error_report(0x0900_0000, ErrorReport::Abort)
+            .boot_vcpu(0, 0)
+            .build();
+        assert!(matches!(
+            result,
+            Err(BuildError::Failed("error report for no device"))
+        ));
+        assert!(!vm_manager().contains(vm_id));
+
+        let vcpus = VmBuilder::new(vm_id, VmConfig::default())
+            .gicr(0x080a_0000)
+            .error_report(0x080a_0000, ErrorReport::Abort)
+            .boot_vcpu(0, 0)
+            .build()
+            .unwrap();
+        assert_eq!(vcpus.len(), 1);
+        assert!(vm_manager().destroy(vm_id).is_ok());
+    }
+
+    #[test]
+    fn test_late_vcpus_attached() {
+        let vm_id = test_vm_id();
+        let config = VmConfig::default();
+        VmBuilder::new(vm_id, config)
+            .memory(0, 0x8000_0000, 0x10_0000, MemType::Normal, S2Perms::RWX)
+            .gicr(0x080a_0000)
+            .heartbeat(0x10_0000)
+            .boot_vcpu(0, 0)
+            .build()
+            .unwrap();
+        // As PSCI CPU_ON creates them.
+        let id = vcpu_manager().create_secondary_vcpu(vm_id, config).unwrap();
+        let attached = vcpu_manager().with_vcpu(id, |vcpu| {
+            vcpu.vgic.redist.is_some() && vcpu.heartbeat.is_some()
+        });
+        assert_eq!(attached, Some(true));
+        assert!(vm_manager().destroy(vm_id).is_ok());
+    }
+}
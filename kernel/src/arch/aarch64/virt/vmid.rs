@@ -0,0 +1,128 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! VMIDs. The Stage-2 tables of each VM carry one in VTTBR_EL2, so the TLB
+//! keeps the translations of different VMs apart and switching between
+//! them flushes nothing. VMID 0 is never handed out, and only 8-bit VMIDs
+//! are used, which every CPU has: at most 255 VMs have tables at a time.
+//!
+//! A freed VMID may still tag TLB entries of the VM that had it, so the
+//! next VM to get it flushes them first with TLBI VMALLS12E1IS, see
+//! `stage2::flush_all`.
+
+use super::stage2;
+use crate::sync::SpinLock;
+
+const VMIDS: usize = 256;
+const WORDS: usize = VMIDS / 64;
+const VTTBR_VMID_SHIFT: u64 = 48;
+
+// One bit per VMID in each map.
+struct Pool {
+    used: [u64; WORDS],
+    // Freed since they were last flushed.
+    stale: [u64; WORDS],
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            used: [0; WORDS],
+            stale: [0; WORDS],
+        }
+    }
+
+    // Take the lowest free VMID, and whether it needs a flush.
+    fn take(&mut self) -> Option<(u16, bool)> {
+        let vmid = (1..VMIDS).find(|&vmid| self.used[vmid / 64] & bit(vmid) == 0)?;
+        self.used[vmid / 64] |= bit(vmid);
+        let stale = self.stale[vmid / 64] & bit(vmid) != 0;
+        self.stale[vmid / 64] &= !bit(vmid);
+        Some((vmid as u16, stale))
+    }
+
+    fn give(&mut self, vmid: u16) {
+        let vmid = vmid as usize;
+        self.used[vmid / 64] &= !bit(vmid);
+        self.stale[vmid / 64] |= bit(vmid);
+    }
+
+    fn in_use(&self) -> usize {
+        self.used.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+fn bit(vmid: usize) -> u64 {
+    1 << (vmid % 64)
+}
+
+static POOL: SpinLock<Pool> = SpinLock::new(Pool::new());
+
+/// A VMID, free for another VM once dropped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Vmid(u16);
+
+impl Vmid {
+    /// Take a free VMID, or `None` if all are taken.
+    pub fn alloc() -> Option<Self> {
+        let (vmid, stale) = POOL.irqsave_lock().take()?;
+        let vmid = Self(vmid);
+        if stale {
+            stage2::flush_all(vmid.vttbr());
+        }
+        Some(vmid)
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+
+    /// VTTBR_EL2 with this VMID and no tables.
+    pub fn vttbr(&self) -> u64 {
+        (self.0 as u64) << VTTBR_VMID_SHIFT
+    }
+}
+
+impl Drop for Vmid {
+    fn drop(&mut self) {
+        POOL.irqsave_lock().give(self.0);
+    }
+}
+
+/// VMIDs taken.
+pub fn in_use() -> usize {
+    POOL.irqsave_lock().in_use()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_vmid_pool_reuse() {
+        let mut pool = Pool::new();
+        assert_eq!(pool.take(), Some((1, false)));
+        assert_eq!(pool.take(), Some((2, false)));
+        pool.give(1);
+        // The lowest free one comes back, with the old VM's entries to flush.
+        assert_eq!(pool.take(), Some((1, true)));
+        pool.give(1);
+        assert_eq!(pool.in_use(), 1);
+        for _ in 1..VMIDS - 1 {
+            assert!(pool.take().is_some());
+        }
+        assert_eq!(pool.take(), None);
+    }
+}
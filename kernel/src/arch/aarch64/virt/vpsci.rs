@@ -0,0 +1,261 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PSCI 0.2 for guests, called through HVC #0 or SMC #0 with the function id
+//! in x0. Target CPUs are vCPU indices within the calling VM.
+//!
+//! CPU_ON of a vCPU the VM doesn't have yet, up to its `max_vcpus`, or of
+//! one no host thread runs, is finished by the caller's host thread: it
+//! creates the vCPU and starts a thread for it, see `start_secondary`.
+
+use super::{
+    exit::{ExitAction, ExitCode},
+    identity,
+    profile::{BootProtocol, VmConfig},
+    shim, status,
+    vcpu::{self, vcpu_manager, Vcpu, VcpuError, VcpuState, MAX_VCPUS_PER_VM},
+};
+use crate::{
+    arch::aarch64::psci::PsciFuncName,
+    thread::{self, Entry},
+};
+use alloc::boxed::Box;
+
+const PSCI_32: u64 = 0x8400_0000;
+const PSCI_64: u64 = 0xc400_0000;
+const PSCI_FUNC_MASK: u64 = 0x1f;
+
+const PSCI_VERSION_0_2: u64 = 2;
+
+const PSCI_SUCCESS: i64 = 0;
+const PSCI_NOT_SUPPORTED: i64 = -1;
+const PSCI_INVALID_PARAMS: i64 = -2;
+const PSCI_ALREADY_ON: i64 = -4;
+
+const AFFINITY_ON: u64 = 0;
+const AFFINITY_OFF: u64 = 1;
+
+// CPU_SUSPEND power_state, original format.
+const POWER_STATE_TYPE: u64 = 1 << 16;
+const POWER_STATE_LEVEL_SHIFT: u64 = 24;
+const POWER_STATE_RESERVED: u64 = 0xfcfe_0000;
+
+// MIGRATE_INFO_TYPE: no Trusted OS, nothing to migrate.
+const MIGRATE_NOT_REQUIRED: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    /// Registers survive; the call returns like WFI.
+    Standby,
+    /// The core loses its context and wakes at the given entry point.
+    PowerDown,
+}
+
+/// Validate a CPU_SUSPEND power_state. Only the core power level exists for
+/// a vCPU.
+pub fn parse_power_state(power_state: u64) -> Result<PowerState, i64> {
+    if power_state & POWER_STATE_RESERVED != 0
+        || (power_state >> POWER_STATE_LEVEL_SHIFT) & 0x3 != 0
+    {
+        return Err(PSCI_INVALID_PARAMS);
+    }
+    if power_state & POWER_STATE_TYPE != 0 {
+        Ok(PowerState::PowerDown)
+    } else {
+        Ok(PowerState::Standby)
+    }
+}
+
+pub fn is_psci_call(x0: u64) -> bool {
+    matches!(x0 & !PSCI_FUNC_MASK, PSCI_32 | PSCI_64)
+}
+
+/// Handle the PSCI call in the vCPU's registers; the PC must already point
+/// past the calling instruction.
+pub fn handle(vcpu: &mut Vcpu) -> ExitAction {
+    let func = vcpu.regs.x[0];
+    let (a1, a2, a3) = args(vcpu);
+
+    let ret = match (func & PSCI_FUNC_MASK) as u32 {
+        f if f == PsciFuncName::Version as u32 => PSCI_VERSION_0_2 as i64,
+        f if f == PsciFuncName::CpuSuspend as u32 => match parse_power_state(a1) {
+            Ok(state) => return cpu_suspend(vcpu, state, a2, a3),
+            Err(e) => e,
+        },
+        f if f == PsciFuncName::CpuOff as u32 => {
+            return ExitAction::Exit(ExitCode::PowerOff);
+        }
+        f if f == PsciFuncName::CpuOn as u32 => {
+            if needs_host(vcpu, a1) {
+                return ExitAction::Exit(ExitCode::CpuOn(a1 as u32));
+            }
+            cpu_on(vcpu, a1, a2, a3)
+        }
+        f if f == PsciFuncName::AffinityInfo as u32 => affinity_info(vcpu, a1, a2),
+        f if f == PsciFuncName::MigrateInfoType as u32 => MIGRATE_NOT_REQUIRED as i64,
+        f if f == PsciFuncName::SystemOff as u32 => {
+            return ExitAction::Exit(ExitCode::Shutdown(status::SUCCESS));
+        }
+        f if f == PsciFuncName::SystemReset as u32 => {
+            return ExitAction::Exit(ExitCode::Reset);
+        }
+        _ => PSCI_NOT_SUPPORTED,
+    };
+    vcpu.regs.x[0] = ret as u64;
+    ExitAction::Resume
+}
+
+// Arguments x1-x3 of the call, truncated for the SMC32 calling convention.
+fn args(vcpu: &Vcpu) -> (u64, u64, u64) {
+    if vcpu.regs.x[0] & !PSCI_FUNC_MASK == PSCI_32 {
+        (
+            vcpu.regs.x[1] as u32 as u64,
+            vcpu.regs.x[2] as u32 as u64,
+            vcpu.regs.x[3] as u32 as u64,
+        )
+    } else {
+        (vcpu.regs.x[1], vcpu.regs.x[2], vcpu.regs.x[3])
+    }
+}
+
+// The host thread holds the vCPU until it has an interrupt to take, much
+// like a WFI, but a power-down state comes back through the entry point.
+fn cpu_suspend(vcpu: &mut Vcpu, state: PowerState, entry: u64, context_id: u64) -> ExitAction {
+    match state {
+        PowerState::Standby => vcpu.regs.x[0] = PSCI_SUCCESS as u64,
+        PowerState::PowerDown => {
+            vcpu.regs = BootProtocol::Bare.initial_regs(entry, 0);
+            vcpu.regs.x[0] = context_id;
+            if vcpu.config.reset_shim {
+                shim::redirect(&mut vcpu.regs, vcpu.config.ipa_bits);
+            }
+        }
+    }
+    ExitAction::Exit(ExitCode::Suspended)
+}
+
+// Another vCPU of the VM is looked at from this one's exit, so one in
+// guest mode, or the caller itself, is taken as on without waiting for it.
+fn cpu_on(vcpu: &Vcpu, target: u64, entry: u64, context_id: u64) -> i64 {
+    let Some(target) = vcpu_manager().find(vcpu.vm_id, target as usize) else {
+        return PSCI_INVALID_PARAMS;
+    };
+    match vcpu_manager().try_with_peer(target, |t| t.power_on(entry, context_id)) {
+        Some(Ok(())) => PSCI_SUCCESS,
+        Some(Err(VcpuError::AlreadyOn)) | None => PSCI_ALREADY_ON,
+        Some(Err(_)) => PSCI_INVALID_PARAMS,
+    }
+}
+
+// Whether CPU_ON of `target` has to be finished by the host: the vCPU
+// doesn't exist yet, or is off with no thread to run it.
+fn needs_host(vcpu: &Vcpu, target: u64) -> bool {
+    if target >= vcpu.config.max_vcpus.min(MAX_VCPUS_PER_VM) as u64 {
+        return false;
+    }
+    match vcpu_manager().find(vcpu.vm_id, target as usize) {
+        None => true,
+        Some(id) => {
+            vcpu_manager()
+                .try_with_peer(id, |t| t.state == VcpuState::Off && t.host_thread.is_none())
+                == Some(true)
+        }
+    }
+}
+
+/// Finish the CPU_ON vCPU `id` exited with `ExitCode::CpuOn`: create vCPU
+/// `index` of its VM if it doesn't exist yet, power it on and start a host
+/// thread running it. The PSCI result goes to the caller's x0. Runs in the
+/// caller's host thread.
+pub(crate) fn start_secondary(id: usize, index: usize) {
+    let Some((vm_id, config, (_, entry, context_id))) =
+        vcpu_manager().with_vcpu(id, |v| (v.vm_id, v.config, args(v)))
+    else {
+        return;
+    };
+    let ret = start(vm_id, config, index, entry, context_id);
+    vcpu_manager().with_vcpu_mut(id, |v| v.regs.x[0] = ret as u64);
+}
+
+fn start(vm_id: usize, config: VmConfig, index: usize, entry: u64, context_id: u64) -> i64 {
+    let manager = vcpu_manager();
+    // vCPUs are created in index order; the ones below `index` the guest
+    // hasn't started yet stay off.
+    let target = loop {
+        if let Some(target) = manager.find(vm_id, index) {
+            break target;
+        }
+        if manager.create_secondary_vcpu(vm_id, config).is_err() {
+            return PSCI_INVALID_PARAMS;
+        }
+    };
+    let spawn = manager.with_vcpu_mut(target, |t| {
+        t.power_on(entry, context_id)?;
+        Ok(t.host_thread.is_none())
+    });
+    match spawn {
+        Some(Ok(true)) => {}
+        Some(Ok(false)) => return PSCI_SUCCESS,
+        Some(Err(VcpuError::AlreadyOn)) => return PSCI_ALREADY_ON,
+        Some(Err(_)) | None => return PSCI_INVALID_PARAMS,
+    }
+    thread::Builder::new(Entry::Closure(Box::new(move || {
+        if let Err(e) = vcpu::run_vcpu(target) {
+            log::warn!(
+                "[virt] {} vcpu {} failed: {:?}",
+                identity::tag(vm_id),
+                index,
+                e
+            );
+        }
+    })))
+    .start();
+    PSCI_SUCCESS
+}
+
+fn affinity_info(vcpu: &Vcpu, target: u64, level: u64) -> i64 {
+    if level != 0 {
+        return PSCI_INVALID_PARAMS;
+    }
+    let Some(target) = vcpu_manager().find(vcpu.vm_id, target as usize) else {
+        return PSCI_INVALID_PARAMS;
+    };
+    match vcpu_manager().try_with_peer(target, |t| t.state) {
+        Some(VcpuState::Off) => AFFINITY_OFF as i64,
+        _ => AFFINITY_ON as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_is_psci_call() {
+        assert!(is_psci_call(0x8400_0000));
+        assert!(is_psci_call(0xc400_0003));
+        assert!(!is_psci_call(b'a' as u64));
+        assert!(!is_psci_call(0x8400_0100));
+    }
+
+    #[test]
+    fn test_parse_power_state() {
+        assert_eq!(parse_power_state(0), Ok(PowerState::Standby));
+        assert_eq!(parse_power_state(0x1_0003), Ok(PowerState::PowerDown));
+        assert_eq!(parse_power_state(1 << 24), Err(PSCI_INVALID_PARAMS));
+        assert_eq!(parse_power_state(1 << 20), Err(PSCI_INVALID_PARAMS));
+        assert_eq!(parse_power_state(1 << 30), Err(PSCI_INVALID_PARAMS));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Virtual SGIs. While a guest runs with HCR_EL2.IMO set, its writes to
+//! ICC_SGI1R_EL1, ICC_SGI0R_EL1 and ICC_ASGI1R_EL1 trap to EL2, which
+//! queues the SGI on the Vgic of each target vCPU, so the guest's IPIs
+//! reach its other vCPUs. A vCPU's affinity is 0.0.0.index, see
+//! `MAX_VCPUS_PER_VM`: a target list with Aff3, Aff2, Aff1 or the range
+//! selector set names no vCPU. The broadcast mode targets every vCPU of
+//! the VM but the sender.
+//!
+//! The guest has a single Security state, so like ICC_SGI0R_EL1, an
+//! ICC_ASGI1R_EL1 write only reaches targets that put the SGI in Group 0.
+//! An ICC_SGI1R_EL1 write reaches them in whichever group they chose.
+
+use super::{
+    exit::{ExitAction, SysRegAccess},
+    kick,
+    vcpu::{vcpu_manager, Vcpu, MAX_VCPUS_PER_VM},
+};
+
+// (op0, op1, CRn, CRm, op2) of the SGI generation registers.
+const ICC_SGI1R_EL1: (u8, u8, u8, u8, u8) = (3, 0, 12, 11, 5);
+const ICC_ASGI1R_EL1: (u8, u8, u8, u8, u8) = (3, 0, 12, 11, 6);
+const ICC_SGI0R_EL1: (u8, u8, u8, u8, u8) = (3, 0, 12, 11, 7);
+
+const SGI1R_TARGET_LIST: u64 = 0xffff;
+const SGI1R_INTID_SHIFT: u64 = 24;
+const SGI1R_INTID_MASK: u64 = 0xf;
+// Aff1, Aff2, RS and Aff3.
+const SGI1R_AFFINITY: u64 = (0xff << 16) | (0xff << 32) | (0xf << 44) | (0xff << 48);
+// Interrupt Routing Mode: every PE but the sender.
+const SGI1R_IRM: u64 = 1 << 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Targets {
+    /// Indexes of vCPUs as a bitmask.
+    List(u16),
+    /// All vCPUs of the VM but the sender.
+    Others,
+}
+
+/// An SGI a guest sent by writing one of the SGI registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sgi {
+    pub intid: u32,
+    pub targets: Targets,
+    /// Only for targets that put the SGI in Group 0.
+    pub group0: bool,
+}
+
+impl Sgi {
+    pub fn decode(val: u64) -> Self {
+        let intid = ((val >> SGI1R_INTID_SHIFT) & SGI1R_INTID_MASK) as u32;
+        let targets = if val & SGI1R_IRM != 0 {
+            Targets::Others
+        } else if val & SGI1R_AFFINITY != 0 {
+            Targets::List(0)
+        } else {
+            Targets::List((val & SGI1R_TARGET_LIST) as u16)
+        };
+        Self {
+            intid,
+            targets,
+            group0: false,
+        }
+    }
+
+    /// Indexes of the vCPUs the SGI goes to when vCPU `sender` sends it,
+    /// as a bitmask.
+    pub fn targets(&self, sender: usize) -> u16 {
+        match self.targets {
+            Targets::List(list) => list,
+            Targets::Others => !(1 << sender),
+        }
+    }
+}
+
+/// Queue `sgi` on the vCPUs of VM `vm_id` whose index is in `targets`, a
+/// bitmask. Returns how many it was queued on.
+pub fn deliver(vm_id: usize, targets: u16, sgi: Sgi) -> usize {
+    (0..MAX_VCPUS_PER_VM)
+        .filter(|&index| targets & (1 << index) != 0)
+        .filter_map(|index| vcpu_manager().find(vm_id, index))
+        .filter(|&id| !sgi.group0 || in_group0(id, sgi.intid))
+        .filter(|&id| kick::inject_irq(vm_id, id, sgi.intid).is_ok())
+        .count()
+}
+
+// Whether vCPU `id` put SGI `intid` in Group 0. Without a redistributor
+// every SGI is in Group 1.
+fn in_group0(id: usize, intid: u32) -> bool {
+    vcpu_manager()
+        .with_vcpu(id, |vcpu| {
+            vcpu.vgic
+                .redist
+                .as_ref()
+                .and_then(|redist| redist.is_group0(intid))
+        })
+        .flatten()
+        .unwrap_or(false)
+}
+
+/// Emulate a write to an SGI register, or `None` if `access` isn't one.
+/// Runs at EL2.
+#[link_section = ".hyp.text"]
+pub fn emulate(vcpu: &mut Vcpu, access: SysRegAccess) -> Option<ExitAction> {
+    let encoding = (access.op0, access.op1, access.crn, access.crm, access.op2);
+    let group0 = match encoding {
+        ICC_SGI1R_EL1 => false,
+        ICC_SGI0R_EL1 | ICC_ASGI1R_EL1 => true,
+        _ => return None,
+    };
+    if access.read {
+        return None;
+    }
+    let val = match access.rt {
+        31 => 0,
+        rt => vcpu.regs.x[rt as usize],
+    };
+    let sgi = Sgi {
+        group0,
+        ..Sgi::decode(val)
+    };
+    // A full deferred queue is logged there; the guest sees a lost IPI.
+    let _ = kick::send_sgi(vcpu.vm_id, vcpu.id, sgi.targets(vcpu.index), sgi);
+    vcpu.advance_pc();
+    Some(ExitAction::Resume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::aarch64::virt::test_vm_id;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_sgi_decode() {
+        let sgi = Sgi::decode((3 << SGI1R_INTID_SHIFT) | 0b1010);
+        assert_eq!(sgi.intid, 3);
+        assert_eq!(sgi.targets, Targets::List(0b1010));
+        assert_eq!(sgi.targets(1), 0b1010);
+
+        // Aff1 = 1 names no vCPU.
+        let sgi = Sgi::decode((1 << 16) | 1);
+        assert_eq!(sgi.targets, Targets::List(0));
+
+        let sgi = Sgi::decode(SGI1R_IRM | (15 << SGI1R_INTID_SHIFT) | 0xffff);
+        assert_eq!(sgi.intid, 15);
+        assert_eq!(sgi.targets, Targets::Others);
+        assert_eq!(sgi.targets(2), !0b100);
+    }
+
+    #[test]
+    fn test_sgi_delivery() {
+        let vm_id = test_vm_id();
+        let ids: alloc::vec::Vec<usize> = (0..3)
+            .map(|_| vcpu_manager().create_vcpu(vm_id, 0, 0).unwrap())
+            .collect();
+        let pending = |id| vcpu_manager().with_vcpu(id, |v| v.vgic.has_pending()) == Some(true);
+
+        // vCPU 0 sends to all others.
+        let sgi = Sgi::decode(SGI1R_IRM | (3 << SGI1R_INTID_SHIFT));
+        assert!(kick::send_sgi(vm_id, ids[0], sgi.targets(0), sgi).is_ok());
+        assert!(!pending(ids[0]));
+        assert!(pending(ids[1]));
+        assert!(pending(ids[2]));
+
+        // Without redistributors no vCPU has SGIs in Group 0.
+        let sgi = Sgi {
+            group0: true,
+            ..Sgi::decode((5 << SGI1R_INTID_SHIFT) | 0b1)
+        };
+        assert_eq!(deliver(vm_id, sgi.targets(1), sgi), 0);
+        assert!(!pending(ids[0]));
+
+        for id in ids {
+            assert!(vcpu_manager().destroy_vcpu(id).is_ok());
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The guest's virtual timer. CNTV_CTL_EL0 and CNTV_CVAL_EL0 move with the
+//! rest of the EL1 context on a world switch, and CNTVOFF_EL2 is the
+//! vCPU's own, so each guest compares against its own counter.
+//!
+//! The host enables the timer's PPI on every core but keeps its own
+//! virtual timer off, so the PPI only fires while a guest's timer is
+//! loaded and it is taken at EL2. There `update` masks the timer with
+//! CNTV_CTL_EL0.IMASK, which lowers the physical line, and drives the
+//! guest's PPI 27 high, as the wire from the timer to the GIC would; the
+//! guest goes on running and takes it. The list register of the level
+//! interrupt raises a maintenance interrupt when the guest deactivates it,
+//! and `update` runs again: once the guest stopped the timer firing, the
+//! line goes low and the mask comes off. A guest that sets IMASK itself
+//! while handling the interrupt finds it cleared after its EOI.
+//!
+//! `update` also runs on every entry, for a timer that came due, or was
+//! reprogrammed, while the vCPU was out of guest mode. A vCPU waiting in
+//! the host for an interrupt uses `due` to sleep until its timer fires.
+
+use super::{
+    hal::{sysregs, SysReg, SysRegBackend},
+    hyper,
+    vcpu::Vcpu,
+    vgic::TIMER_PRIORITY,
+};
+use crate::arch::aarch64::{
+    current_cpu_id,
+    irq::{self, IrqHandler, IrqNumber, Priority},
+};
+use alloc::boxed::Box;
+
+/// The virtual timer's PPI.
+pub const VTIMER_INTID: u32 = 27;
+
+pub(crate) const CTL_ENABLE: u64 = 1 << 0;
+pub(crate) const CTL_IMASK: u64 = 1 << 1;
+
+/// Whether a timer with control `ctl` and compare value `cval` asserts its
+/// interrupt at guest count `now`.
+pub fn fires(ctl: u64, cval: u64, now: u64) -> bool {
+    ctl & (CTL_ENABLE | CTL_IMASK) == CTL_ENABLE && now >= cval
+}
+
+/// Whether `vcpu`'s timer, as saved on its last exit, asserts its
+/// interrupt at physical count `now`. A timer masked until the guest is
+/// done with its interrupt doesn't.
+pub fn asserted(vcpu: &Vcpu, now: u64) -> bool {
+    let el1 = &vcpu.regs.el1;
+    fires(el1.cntv_ctl_el0, el1.cntv_cval_el0, vcpu.guest_cycles(now))
+}
+
+/// Physical count at which `vcpu`'s timer, as saved on its last exit,
+/// comes due, if it is armed.
+pub fn due(vcpu: &Vcpu) -> Option<u64> {
+    let el1 = &vcpu.regs.el1;
+    (el1.cntv_ctl_el0 & (CTL_ENABLE | CTL_IMASK) == CTL_ENABLE)
+        .then(|| vcpu.host_cycles(el1.cntv_cval_el0))
+}
+
+// Control register and mask state for a timer with control `ctl`, which
+// has IMASK set by EL2 if `masked`, and compare value `cval` at guest
+// count `now`, and whether the line to the guest is high. The timer is
+// masked while it fires.
+fn next_state(ctl: u64, masked: bool, cval: u64, now: u64) -> (u64, bool, bool) {
+    let guest_ctl = if masked { ctl & !CTL_IMASK } else { ctl };
+    let firing = fires(guest_ctl, cval, now);
+    match (firing, masked) {
+        (true, false) => (ctl | CTL_IMASK, true, true),
+        (false, true) => (guest_ctl, false, false),
+        _ => (ctl, masked, firing),
+    }
+}
+
+/// Drive `vcpu`'s PPI 27 with its timer, masking the timer while it fires
+/// and unmasking it once it stopped, see the module doc. Runs at EL2 with
+/// the vCPU's timer loaded, before its list registers are.
+#[link_section = ".hyp.text"]
+pub(crate) fn update(vcpu: &mut Vcpu) {
+    let ctl = sysregs().read(SysReg::CntvCtlEl0);
+    let cval = sysregs().read(SysReg::CntvCvalEl0);
+    let now = vcpu.guest_cycles(hyper::read_cntpct());
+    let (new_ctl, masked, high) = next_state(ctl, vcpu.vtimer_masked, cval, now);
+    if new_ctl != ctl {
+        sysregs().write(SysReg::CntvCtlEl0, new_ctl);
+        sysregs().isb();
+    }
+    vcpu.vtimer_masked = masked;
+    vcpu.vgic
+        .set_level_with_priority(VTIMER_INTID, high, TIMER_PRIORITY);
+}
+
+struct VtimerIrq;
+
+impl IrqHandler for VtimerIrq {
+    // Taken at EL2 while a guest runs. Reaching here means a guest's timer
+    // fired as its vCPU left, and the host's restored timer lowered the
+    // line already.
+    fn handle(&mut self) {}
+}
+
+/// Enable the virtual timer's PPI on the calling core.
+pub fn cpu_init() {
+    let irq = IrqNumber::new(VTIMER_INTID);
+    if current_cpu_id() == 0 {
+        let _ = irq::register_handler(irq, Box::new(VtimerIrq));
+    }
+    irq::enable_irq_with_priority(irq, current_cpu_id(), Priority::High);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_vtimer_fires() {
+        assert!(!fires(0, 100, 200));
+        assert!(!fires(CTL_ENABLE, 100, 99));
+        assert!(fires(CTL_ENABLE, 100, 100));
+        assert!(!fires(CTL_ENABLE | CTL_IMASK, 100, 200));
+        // ISTATUS is the hardware's to set, and says nothing here.
+        assert!(fires(CTL_ENABLE | (1 << 2), 0, 0));
+    }
+
+    #[test]
+    fn test_vtimer_mask_while_firing() {
+        // Not due: left alone, line low.
+        assert_eq!(
+            next_state(CTL_ENABLE, false, 100, 99),
+            (CTL_ENABLE, false, false)
+        );
+        // Fires: masked, line high.
+        let (ctl, masked, high) = next_state(CTL_ENABLE, false, 100, 100);
+        assert_eq!(ctl, CTL_ENABLE | CTL_IMASK);
+        assert!(masked && high);
+        // Still firing after the guest's EOI: stays masked and high.
+        assert_eq!(next_state(ctl, true, 100, 150), (ctl, true, true));
+        // The guest moved the compare value on: unmasked, line low.
+        assert_eq!(next_state(ctl, true, 200, 150), (CTL_ENABLE, false, false));
+        // Masked by the guest itself: never touched.
+        let guest = CTL_ENABLE | CTL_IMASK;
+        assert_eq!(next_state(guest, false, 100, 150), (guest, false, false));
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker threads for device emulation. A device model handling an exit only
+//! queues the slow part of a request (file IO, draining a console) and lets
+//! the vCPU go back to the guest. A worker runs the job later and raises the
+//! completion interrupt in the owning VM.
+
+use super::{identity, kick};
+use crate::{
+    config,
+    scheduler::{self, InsertToEnd},
+    sync::{semaphore::Semaphore, SpinLock},
+    thread::{self, Entry},
+};
+use alloc::{boxed::Box, collections::VecDeque};
+
+const NUM_WORKERS: usize = 2;
+// A guest can't make the host queue more than this many jobs.
+const MAX_QUEUED_JOBS: usize = 64;
+
+/// Virtual interrupt to raise once a job is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub vcpu_id: usize,
+    pub intid: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerError {
+    QueueFull,
+}
+
+struct Job {
+    vm_id: usize,
+    work: Box<dyn FnOnce() -> Option<Completion> + Send>,
+}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    // Thread and VM of the job each worker runs. Set as the job is taken,
+    // under the same lock, so `cancel_vm` sees every job of a VM either
+    // queued or running.
+    running: [Option<(usize, usize)>; NUM_WORKERS],
+}
+
+static JOBS: SpinLock<Queue> = SpinLock::new(Queue {
+    jobs: VecDeque::new(),
+    running: [None; NUM_WORKERS],
+});
+// Counts queued jobs; may run ahead of JOBS after `cancel_vm`.
+static PENDING: Semaphore = Semaphore::new();
+
+/// Queue `work` on behalf of VM `vm_id`. Must be called from thread context.
+pub fn submit(
+    vm_id: usize,
+    work: impl FnOnce() -> Option<Completion> + Send + 'static,
+) -> Result<(), WorkerError> {
+    {
+        let mut queue = JOBS.irqsave_lock();
+        if queue.jobs.len() >= MAX_QUEUED_JOBS {
+            return Err(WorkerError::QueueFull);
+        }
+        queue.jobs.push_back(Job {
+            vm_id,
+            work: Box::new(work),
+        });
+    }
+    PENDING.release();
+    Ok(())
+}
+
+/// Drop the jobs of a VM that is going away and wait for those already
+/// running, so none touches its memory once this returns. Their interrupts
+/// are rejected since the vCPUs are gone. A job tearing down its own VM
+/// isn't waited for. Must be called from thread context.
+pub fn cancel_vm(vm_id: usize) {
+    let me = scheduler::current_thread_id();
+    let mut queue = JOBS.irqsave_lock();
+    queue.jobs.retain(|job| job.vm_id != vm_id);
+    while queue
+        .running
+        .iter()
+        .flatten()
+        .any(|&(thread, vm)| vm == vm_id && thread != me)
+    {
+        drop(queue);
+        scheduler::yield_me();
+        queue = JOBS.irqsave_lock();
+    }
+}
+
+fn run_worker(worker: usize) {
+    let me = scheduler::current_thread_id();
+    loop {
+        PENDING.acquire_notimeout::<InsertToEnd>();
+        let job = {
+            let mut queue = JOBS.irqsave_lock();
+            let Some(job) = queue.jobs.pop_front() else {
+                continue;
+            };
+            queue.running[worker] = Some((me, job.vm_id));
+            job
+        };
+        let done = (job.work)();
+        JOBS.irqsave_lock().running[worker] = None;
+        let Some(done) = done else {
+            continue;
+        };
+        if let Err(e) = kick::inject_irq(job.vm_id, done.vcpu_id, done.intid) {
+            log::warn!(
+                "[virt] {} completion irq {} not delivered: {:?}",
+                identity::tag(job.vm_id),
+                done.intid,
+                e
+            );
+        }
+    }
+}
+
+pub(crate) fn init() {
+    PENDING.init(0);
+    for worker in 0..NUM_WORKERS {
+        thread::Builder::new(Entry::Closure(Box::new(move || run_worker(worker))))
+            .set_priority(config::VIRT_WORKER_THREAD_PRIORITY)
+            .start();
+    }
+}
@@ -16,13 +16,14 @@ use super::{xpsr, IsrContext};
 use core::fmt;
 use cortex_m::peripheral::SCB;
 
-#[derive(Debug, Default)]
-struct HardFaultRegs {
-    cfsr: u32,  // Configurable Fault Status Register
-    hfsr: u32,  // Hard Fault Status Register
-    mmfar: u32, // Memory Management Fault Address Register
-    bfar: u32,  // Bus Fault Address Register
-    afsr: u32,  // Auxiliary Fault Status Register (ARMv8-M)
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HardFaultRegs {
+    pub cfsr: u32,  // Configurable Fault Status Register
+    pub hfsr: u32,  // Hard Fault Status Register
+    pub mmfar: u32, // Memory Management Fault Address Register
+    pub bfar: u32,  // Bus Fault Address Register
+    pub afsr: u32,  // Auxiliary Fault Status Register (ARMv8-M)
 }
 
 impl HardFaultRegs {
@@ -213,18 +214,140 @@ impl fmt::Display for HardFaultRegs {
     }
 }
 
-pub extern "C" fn panic_on_hardfault(ctx: &IsrContext) {
+/// EXC_RETURN value held in LR on exception entry.
+#[derive(Debug, Clone, Copy)]
+pub struct ExcReturn(pub(crate) u32);
+
+impl ExcReturn {
+    /// The frame was pushed on PSP rather than MSP.
+    pub fn uses_psp(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// The exception was taken from thread mode rather than handler mode.
+    pub fn from_thread_mode(self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    /// FType is clear when the extended frame with FP state was pushed.
+    pub fn has_fp_frame(self) -> bool {
+        self.0 & (1 << 4) == 0
+    }
+
+    /// The exception was taken from secure state.
+    pub fn is_secure(self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+}
+
+impl fmt::Display for ExcReturn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08x} ({} mode, {}, {} frame",
+            self.0,
+            if self.from_thread_mode() {
+                "thread"
+            } else {
+                "handler"
+            },
+            if self.uses_psp() { "PSP" } else { "MSP" },
+            if self.has_fp_frame() {
+                "extended"
+            } else {
+                "basic"
+            },
+        )?;
+        #[cfg(armv8m)]
+        write!(
+            f,
+            ", {}",
+            if self.is_secure() {
+                "secure"
+            } else {
+                "non-secure"
+            }
+        )?;
+        write!(f, ")")
+    }
+}
+
+// The FP part of IsrContext only exists on the stack when EXC_RETURN says an
+// extended frame was pushed, so it must not be read otherwise.
+struct FaultFrame<'a> {
+    ctx: &'a IsrContext,
+    #[cfg_attr(not(target_abi = "eabihf"), allow(dead_code))]
+    exc_return: ExcReturn,
+}
+
+impl fmt::Display for FaultFrame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ctx = self.ctx;
+        writeln!(
+            f,
+            "r0: 0x{:08x} r1: 0x{:08x} r2: 0x{:08x} r3: 0x{:08x}",
+            ctx.r0, ctx.r1, ctx.r2, ctx.r3
+        )?;
+        write!(
+            f,
+            "r12: 0x{:08x} lr: 0x{:08x} pc: 0x{:08x} xpsr: 0x{:08x}",
+            ctx.r12, ctx.lr, ctx.pc, ctx.xpsr
+        )?;
+        #[cfg(target_abi = "eabihf")]
+        if self.exc_return.has_fp_frame() {
+            let s = [
+                ctx.s0, ctx.s1, ctx.s2, ctx.s3, ctx.s4, ctx.s5, ctx.s6, ctx.s7, ctx.s8, ctx.s9,
+                ctx.s10, ctx.s11, ctx.s12, ctx.s13, ctx.s14, ctx.s15,
+            ];
+            for (i, chunk) in s.chunks(4).enumerate() {
+                writeln!(f)?;
+                for (j, val) in chunk.iter().enumerate() {
+                    write!(f, "s{}: 0x{:08x} ", i * 4 + j, val)?;
+                }
+            }
+            write!(f, "\nfpscr: 0x{:08x} vpr: 0x{:08x}", ctx.fpscr, ctx.vpr)?;
+        }
+        Ok(())
+    }
+}
+
+pub extern "C" fn panic_on_hardfault(ctx: &IsrContext, exc_return: u32) {
     super::disable_local_irq();
     let fault_regs: HardFaultRegs = HardFaultRegs::from_scb();
     let xpsr = xpsr::read();
+    let exc_return = ExcReturn(exc_return);
+    #[cfg(hardfault_recovery)]
+    {
+        crate::kearly_println!(
+            "\n==== HARD FAULT ====\nEXC_RETURN: {}\nFRAME:\n{}\nFAULT REGS: {}\nXPSR: {}\nResetting into recovery mode.",
+            exc_return,
+            FaultFrame { ctx, exc_return },
+            fault_regs,
+            xpsr,
+        );
+        let crumb = super::recovery::Breadcrumb::new(
+            fault_regs,
+            ctx.pc as u32,
+            ctx.lr as u32,
+            ctx.xpsr as u32,
+            exc_return.0,
+        );
+        super::recovery::reset_with(crumb);
+    }
+    #[cfg(not(hardfault_recovery))]
     panic!(
         "
         ==== HARD FAULT ====
-        FRAME: {:?}
+        EXC_RETURN: {}
+        FRAME:
+{}
         FAULT REGS: {}
         XPSR: {}
         ",
-        ctx, fault_regs, xpsr,
+        exc_return,
+        FaultFrame { ctx, exc_return },
+        fault_regs,
+        xpsr,
     );
 }
 
@@ -238,6 +361,7 @@ pub(crate) unsafe extern "C" fn handle_hardfault() {
         beq 1f
         mrs r0, psp
         1:
+        mov r1, lr
         bl {panic}
         ",
         panic = sym panic_on_hardfault
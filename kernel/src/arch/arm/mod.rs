@@ -14,6 +14,8 @@
 
 pub(crate) mod hardfault;
 pub mod irq;
+#[cfg(hardfault_recovery)]
+pub mod recovery;
 pub(crate) mod xpsr;
 use crate::{
     arch::irq::Vector,
@@ -23,7 +25,7 @@ use crate::{
     syscalls::{dispatch_syscall, Context as ScContext},
 };
 pub(crate) use hardfault::handle_hardfault;
-pub use hardfault::panic_on_hardfault;
+pub use hardfault::{panic_on_hardfault, ExcReturn};
 #[cfg(use_mpu)]
 pub mod mpu;
 
@@ -0,0 +1,152 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reboot into recovery after a hard fault. With `HARDFAULT_RECOVERY` set,
+//! `panic_on_hardfault` leaves a breadcrumb in `.noinit` RAM, which the
+//! startup code neither copies nor zeroes, and resets the system instead
+//! of spinning. The next boot finds the breadcrumb, prints the crash log
+//! on the console and stays in a minimal mode without the applications, so
+//! a device without a debug probe still tells what went wrong. The
+//! breadcrumb is consumed by that boot; the reset after it boots normally.
+
+use super::hardfault::{ExcReturn, HardFaultRegs};
+use core::{fmt, mem::MaybeUninit, ptr::addr_of_mut};
+use cortex_m::peripheral::SCB;
+
+const MAGIC: u32 = 0x4846_5243;
+
+/// What a hard fault leaves for the next boot.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Breadcrumb {
+    magic: u32,
+    pub regs: HardFaultRegs,
+    pub pc: u32,
+    pub lr: u32,
+    pub xpsr: u32,
+    pub exc_return: u32,
+    check: u32,
+}
+
+impl Breadcrumb {
+    pub(crate) fn new(regs: HardFaultRegs, pc: u32, lr: u32, xpsr: u32, exc_return: u32) -> Self {
+        let mut crumb = Self {
+            magic: MAGIC,
+            regs,
+            pc,
+            lr,
+            xpsr,
+            exc_return,
+            check: 0,
+        };
+        crumb.check = crumb.checksum();
+        crumb
+    }
+
+    fn checksum(&self) -> u32 {
+        let r = &self.regs;
+        [
+            self.magic,
+            r.cfsr,
+            r.hfsr,
+            r.mmfar,
+            r.bfar,
+            r.afsr,
+            self.pc,
+            self.lr,
+            self.xpsr,
+            self.exc_return,
+        ]
+        .iter()
+        .fold(!MAGIC, |sum, &word| sum.rotate_left(5) ^ word)
+    }
+
+    /// Whether this is a breadcrumb and not what RAM held at power-on.
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.check == self.checksum()
+    }
+}
+
+impl fmt::Display for Breadcrumb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "pc: 0x{:08x} lr: 0x{:08x} xpsr: 0x{:08x}",
+            self.pc, self.lr, self.xpsr
+        )?;
+        write!(f, "EXC_RETURN: {}{}", ExcReturn(self.exc_return), self.regs)
+    }
+}
+
+#[link_section = ".noinit"]
+static mut BREADCRUMB: MaybeUninit<Breadcrumb> = MaybeUninit::uninit();
+// The breadcrumb found at boot, once `boot_after_fault` took it.
+static mut LAST_FAULT: Option<Breadcrumb> = None;
+
+/// Leave `crumb` for the next boot and reset the system.
+pub(crate) fn reset_with(crumb: Breadcrumb) -> ! {
+    // SAFETY: Only written here with interrupts off, and read back at boot
+    // before anything else runs.
+    unsafe {
+        core::ptr::write_volatile(addr_of_mut!(BREADCRUMB), MaybeUninit::new(crumb));
+    }
+    cortex_m::asm::dsb();
+    SCB::sys_reset()
+}
+
+/// Take the breadcrumb of the previous boot, if it ended in a hard fault,
+/// and print it. Returns whether this boot should stay in recovery mode.
+/// Called once, before the applications start.
+pub(crate) fn boot_after_fault() -> bool {
+    // SAFETY: Any bit pattern is a valid `Breadcrumb`, and nothing else
+    // runs yet.
+    let crumb = unsafe {
+        let slot = addr_of_mut!(BREADCRUMB);
+        let crumb = core::ptr::read_volatile(slot).assume_init();
+        core::ptr::write_volatile(slot, MaybeUninit::zeroed());
+        crumb
+    };
+    if !crumb.is_valid() {
+        return false;
+    }
+    // SAFETY: See above.
+    unsafe { *addr_of_mut!(LAST_FAULT) = Some(crumb) };
+    crate::kprintln!(
+        "\n==== RECOVERY MODE ====\nThe last boot ended in a hard fault:\n{}\nApplications are not started. Reset to boot normally.",
+        crumb
+    );
+    true
+}
+
+/// The hard fault that ended the previous boot, in recovery mode.
+pub fn last_fault() -> Option<Breadcrumb> {
+    // SAFETY: Only written by `boot_after_fault`, before the scheduler runs.
+    unsafe { *addr_of_mut!(LAST_FAULT) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_breadcrumb_checksum() {
+        let mut crumb = Breadcrumb::new(HardFaultRegs::default(), 0x1000, 0x2001, 1 << 24, !2);
+        assert!(crumb.is_valid());
+        crumb.pc ^= 4;
+        assert!(!crumb.is_valid());
+        let zeroed: Breadcrumb = unsafe { MaybeUninit::zeroed().assume_init() };
+        assert!(!zeroed.is_valid());
+    }
+}
@@ -130,6 +130,8 @@ fn poll_inner() {
     let mut ctx = Context::from_waker(Waker::noop());
     let mut w = ASYNC_WORK_QUEUE.advance_active_queue();
     for task in w.iter() {
+        #[cfg(procfs)]
+        crate::irq::deferred_trace::record(crate::irq::deferred_trace::DeferredWork::Tasklet);
         let mut l = task.lock();
         if let Poll::Ready(()) = l.future.as_mut().poll(&mut ctx) {
             if let Some(t) = l.blocked.take() {
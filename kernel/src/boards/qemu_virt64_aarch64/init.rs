@@ -36,7 +36,10 @@ const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
 pub(crate) fn init() {
     STAGING.run(0, true, crate::boot::init_runtime);
     STAGING.run(1, true, crate::boot::init_heap);
-    STAGING.run(2, false, arch::vector::init);
+    STAGING.run(2, false, || {
+        arch::vector::init();
+        arch::cpuinfo::record();
+    });
     STAGING.run(3, true, || unsafe {
         arch::irq::init(config::GICD as u64, config::GICR as u64, NUM_CORES, false)
     });
@@ -55,7 +58,13 @@ pub(crate) fn init() {
             irq::Priority::Normal,
         );
     });
-    STAGING.run(7, true, || arch::secondary_cpu_setup(config::PSCI_BASE));
+    #[cfg(virtualization)]
+    STAGING.run(7, false, || {
+        arch::virt::initcall::run_level(arch::virt::initcall::InitLevel::CpuIrq)
+    });
+    // Stages count up, so without the hypervisor's stage this one is 7.
+    let stage = if cfg!(virtualization) { 8 } else { 7 };
+    STAGING.run(stage, true, || arch::secondary_cpu_setup(config::PSCI_BASE));
     if arch::current_cpu_id() != 0 {
         scheduler::wait_and_then_start_schedule();
         unreachable!("Secondary cores should have jumped to the scheduler");
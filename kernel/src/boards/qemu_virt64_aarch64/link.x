@@ -28,8 +28,14 @@ SECTIONS
         KEEP(*(.text._startup_el1))
         KEEP(*(.text.vector_table))
         KEEP(*(.text._exception))
-        KEEP(*(.text.hyper_vector_table))
         *(.text*)
+        /* EL2 code, page aligned so EL2 can map it RX on its own */
+        . = ALIGN(4096);
+        __hyp_text_start = .;
+        KEEP(*(.hyp.text.vectors))
+        *(.hyp.text*)
+        . = ALIGN(4096);
+        __hyp_text_end = .;
         __text_end = .;
     } > DRAM :text
 
@@ -37,9 +43,23 @@ SECTIONS
     {
         __rodata_start = .;
         *(.rodata*)
+        /* Boot-time patch sites of EL2 code, see virt/alternative.rs */
+        . = ALIGN(4);
+        __hyp_alt_start = .;
+        KEEP(*(.hyp.alternatives))
+        __hyp_alt_end = .;
         __rodata_end = .;
     } > DRAM :rodata
 
+    /* EL2 state, loaded with the image rather than cleared with .bss */
+    .hyp.data : ALIGN(4096)
+    {
+        __hyp_data_start = .;
+        *(.hyp.data*)
+        . = ALIGN(4096);
+        __hyp_data_end = .;
+    } > DRAM :data
+
     .data : ALIGN(4096)
     {
         __data_start = .;
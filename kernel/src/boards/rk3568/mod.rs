@@ -26,6 +26,7 @@ pub(crate) fn init() {
     crate::boot::init_runtime();
     crate::boot::init_heap();
     arch::vector::init();
+    arch::cpuinfo::record();
     unsafe {
         arch::irq::init(
             config::GICD as u64,
@@ -160,10 +160,20 @@ extern "C" fn init() {
     time::timer::init();
     #[cfg(kernel_async)]
     asynk::init();
+    #[cfg(soft_watchdog)]
+    crate::watchdog::init();
+    #[cfg(virtualization)]
+    crate::arch::virt::initcall::run_level(crate::arch::virt::initcall::InitLevel::Services);
     #[cfg(enable_net)]
     net::net_manager::init();
     #[cfg(enable_vfs)]
     init_vfs();
+    // After a hard fault the applications stay down, see arch::recovery.
+    #[cfg(hardfault_recovery)]
+    if !arch::recovery::boot_after_fault() {
+        init_apps();
+    }
+    #[cfg(not(hardfault_recovery))]
     init_apps();
     arch::start_schedule(scheduler::schedule);
     unreachable!("We should have jumped to the schedule loop!");
@@ -42,3 +42,7 @@ pub const DEFAULT_STACK_SIZE: usize = 16 << 10;
 pub const DEFAULT_STACK_SIZE: usize = 8 << 10;
 
 pub const SOFT_TIMER_THREAD_PRIORITY: ThreadPriority = 0;
+pub const WATCHDOG_THREAD_PRIORITY: ThreadPriority = 1;
+pub const VIRT_WORKER_THREAD_PRIORITY: ThreadPriority = 2;
+pub const VIRT_INIT_THREAD_PRIORITY: ThreadPriority = 3;
+pub const VIRT_LOG_THREAD_PRIORITY: ThreadPriority = 4;
@@ -485,6 +485,28 @@ impl Serial {
 
         Ok(nbytes)
     }
+
+    /// Like `recvchars`, but hands what arrived to `sink` instead of the
+    /// device's readers. `sink` runs without the device locked.
+    pub fn divert_rx(&self, mut sink: impl FnMut(&[u8])) -> Result<usize, SerialError> {
+        let mut nbytes: usize = 0;
+        let mut buf = [0u8; 32];
+        loop {
+            let n = {
+                let mut uart_ops = self.uart_ops.irqsave_lock();
+                if !uart_ops.read_ready()? {
+                    break;
+                }
+                uart_ops.read(&mut buf)?
+            };
+            if n == 0 {
+                break;
+            }
+            sink(&buf[..n]);
+            nbytes += n;
+        }
+        Ok(nbytes)
+    }
 }
 
 impl Device for Serial {
@@ -309,6 +309,15 @@ pub fn uart_handler() {
     match intr {
         blueos_driver::uart::InterruptType::Rx => {
             let t_uart = crate::boot::get_serial(0);
+            // Console input belongs to the guest attached to it.
+            #[cfg(virtualization)]
+            if crate::arch::virt::vconsole::attached().is_some() {
+                if let Err(e) = t_uart.divert_rx(crate::arch::virt::vconsole::receive) {
+                    log::warn!("uart divert_rx error: {:?}", e);
+                }
+                uart.clear_interrupt(intr);
+                return;
+            }
             if let Err(e) = t_uart.recvchars() {
                 log::warn!("uart recvchars error: {:?}", e);
             }
@@ -22,11 +22,17 @@ pub(crate) static mut IRQ_NESTING_COUNT: [Uint; NUM_CORES as usize] =
 
 pub struct IrqTrace {
     irq_number: arch::irq::IrqNumber,
+    #[cfg(irq_handler_stats)]
+    enter_cycles: u64,
 }
 
 impl IrqTrace {
     pub fn new(irq_number: arch::irq::IrqNumber) -> Self {
-        let irq_trace = Self { irq_number };
+        let irq_trace = Self {
+            irq_number,
+            #[cfg(irq_handler_stats)]
+            enter_cycles: time::current_clock_cycles(),
+        };
         irq_trace.enter();
         irq_trace
     }
@@ -42,6 +48,13 @@ impl IrqTrace {
 
     #[inline]
     fn leave(&self) {
+        #[cfg(irq_handler_stats)]
+        {
+            let _dig = DisableInterruptGuard::new();
+            let cycles = time::current_clock_cycles().saturating_sub(self.enter_cycles);
+            let mut stats = irq_trace::IRQ_HANDLER_STATS[arch::current_cpu_id()].lock();
+            stats[usize::from(self.irq_number)].record(cycles);
+        }
         leave_irq();
     }
 }
@@ -94,6 +107,8 @@ pub extern "C" fn leave_irq() -> usize {
 
 #[cfg(procfs)]
 pub mod irq_trace {
+    #[cfg(irq_handler_stats)]
+    use crate::sync::SpinLock;
     use crate::{arch::irq::INTERRUPT_TABLE_LEN, time};
     use blueos_kconfig::CONFIG_NUM_CORES as NUM_CORES;
     use core::sync::atomic::AtomicUsize;
@@ -112,6 +127,8 @@ pub mod irq_trace {
 
     pub struct IrqTraceInfo {
         pub last_irq_enter_cycles: u64,
+        // Cycles the last IRQ on this core took, what /proc/stat reports as
+        // irq time. Per-IRQ totals are in `IRQ_HANDLER_STATS`, if kept.
         pub total_irq_process_cycles: u64,
     }
 
@@ -128,4 +145,99 @@ pub mod irq_trace {
                 current_cycles.saturating_sub(self.last_irq_enter_cycles);
         }
     }
+
+    /// Upper bounds of the handler duration histogram buckets, in
+    /// microseconds. The last bucket takes everything above.
+    #[cfg(irq_handler_stats)]
+    pub const HISTOGRAM_BOUNDS_US: [u64; HISTOGRAM_BUCKETS - 1] = [1, 4, 16, 64, 256, 1000, 4000];
+    #[cfg(irq_handler_stats)]
+    pub const HISTOGRAM_BUCKETS: usize = 8;
+
+    // Per core: a core only records into its own entry, so the lock is
+    // contended by readers alone.
+    #[cfg(irq_handler_stats)]
+    pub static IRQ_HANDLER_STATS: [SpinLock<[IrqHandlerStats; INTERRUPT_TABLE_LEN]>;
+        NUM_CORES as usize] =
+        [const { SpinLock::new([const { IrqHandlerStats::new() }; INTERRUPT_TABLE_LEN]) };
+            NUM_CORES as usize];
+
+    #[cfg(irq_handler_stats)]
+    #[derive(Clone, Copy)]
+    pub struct IrqHandlerStats {
+        pub count: u64,
+        pub min_cycles: u64,
+        pub max_cycles: u64,
+        pub total_cycles: u64,
+        pub histogram: [u32; HISTOGRAM_BUCKETS],
+    }
+
+    #[cfg(irq_handler_stats)]
+    impl IrqHandlerStats {
+        pub const fn new() -> Self {
+            Self {
+                count: 0,
+                min_cycles: u64::MAX,
+                max_cycles: 0,
+                total_cycles: 0,
+                histogram: [0; HISTOGRAM_BUCKETS],
+            }
+        }
+
+        /// Fold `other`, the stats of the same IRQ on another core, into
+        /// these.
+        pub fn merge(&mut self, other: &Self) {
+            self.count += other.count;
+            self.min_cycles = self.min_cycles.min(other.min_cycles);
+            self.max_cycles = self.max_cycles.max(other.max_cycles);
+            self.total_cycles = self.total_cycles.saturating_add(other.total_cycles);
+            for (n, m) in self.histogram.iter_mut().zip(other.histogram) {
+                *n = n.saturating_add(m);
+            }
+        }
+
+        pub fn record(&mut self, cycles: u64) {
+            self.count += 1;
+            self.min_cycles = self.min_cycles.min(cycles);
+            self.max_cycles = self.max_cycles.max(cycles);
+            self.total_cycles = self.total_cycles.saturating_add(cycles);
+            let us = time::from_clock_cycles(cycles).as_micros() as u64;
+            let bucket = HISTOGRAM_BOUNDS_US
+                .iter()
+                .position(|&bound| us < bound)
+                .unwrap_or(HISTOGRAM_BUCKETS - 1);
+            self.histogram[bucket] = self.histogram[bucket].saturating_add(1);
+        }
+
+        #[inline]
+        pub fn avg_cycles(&self) -> u64 {
+            if self.count == 0 {
+                0
+            } else {
+                self.total_cycles / self.count
+            }
+        }
+    }
+}
+
+#[cfg(procfs)]
+pub mod deferred_trace {
+    use crate::arch;
+    use blueos_kconfig::CONFIG_NUM_CORES as NUM_CORES;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Copy, Clone)]
+    pub enum DeferredWork {
+        Timer,
+        Tasklet,
+    }
+
+    pub const DEFERRED_WORK_NAMES: [&str; 2] = ["TIMER", "TASKLET"];
+
+    pub static DEFERRED_COUNTERS: [[AtomicUsize; NUM_CORES as usize]; DEFERRED_WORK_NAMES.len()] =
+        [const { [const { AtomicUsize::new(0) }; NUM_CORES as usize] }; DEFERRED_WORK_NAMES.len()];
+
+    #[inline]
+    pub fn record(work: DeferredWork) {
+        DEFERRED_COUNTERS[work as usize][arch::current_cpu_id()].fetch_add(1, Ordering::Relaxed);
+    }
 }
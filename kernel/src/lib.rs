@@ -83,6 +83,10 @@ pub mod time;
 pub mod types;
 #[cfg(enable_vfs)]
 pub mod vfs;
+#[cfg(virtualization)]
+pub mod virt;
+#[cfg(soft_watchdog)]
+pub mod watchdog;
 
 pub use syscall_handlers as syscalls;
 pub(crate) mod signal;
@@ -239,6 +243,8 @@ mod tests {
     #[panic_handler]
     fn oops(info: &PanicInfo) -> ! {
         let _guard = DisableInterruptGuard::new();
+        #[cfg(target_arch = "aarch64")]
+        arch::stop_guests_on_panic();
         #[cfg(not(use_defmt))]
         {
             semihosting::println!("{}", info);
@@ -250,6 +256,8 @@ mod tests {
             defmt::error!("{}", defmt::Display2Format(info));
             defmt::error!("Oops: {}", defmt::Display2Format(&info.message()));
         }
+        #[cfg(target_arch = "aarch64")]
+        arch::test_exit(false);
         loop {}
     }
 
@@ -781,6 +789,8 @@ mod tests {
         println!("---- Done kernel unittests.");
         #[cfg(coverage)]
         crate::coverage::write_coverage_data();
+        #[cfg(target_arch = "aarch64")]
+        arch::test_exit(true);
         #[cfg(use_defmt)]
         cortex_m_semihosting::debug::exit(cortex_m_semihosting::debug::EXIT_SUCCESS);
     }
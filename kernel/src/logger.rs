@@ -58,19 +58,25 @@ impl log::Log for Logger {
         if !self.enabled(record.metadata()) {
             return;
         }
-        let timestamp = time::now().as_millis();
-        let tid = scheduler::current_thread_id();
-        let cpu = arch::current_cpu_id();
-        let _guard = LOGGER_MUTEX.irqsave_lock();
-        kprintln!(
-            "[T:{:09} C:{} TH:0x{:x}][{}] {} ",
-            timestamp,
-            cpu,
-            tid,
-            record.level(),
-            record.args()
-        );
+        log_unfiltered(record);
     }
 
     fn flush(&self) {}
 }
+
+/// Print `record` whatever the max level, for callers doing their own
+/// filtering.
+pub fn log_unfiltered(record: &Record) {
+    let timestamp = time::now().as_millis();
+    let tid = scheduler::current_thread_id();
+    let cpu = arch::current_cpu_id();
+    let _guard = LOGGER_MUTEX.irqsave_lock();
+    kprintln!(
+        "[T:{:09} C:{} TH:0x{:x}][{}] {} ",
+        timestamp,
+        cpu,
+        tid,
+        record.level(),
+        record.args()
+    );
+}
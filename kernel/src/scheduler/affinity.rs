@@ -0,0 +1,71 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Cores a thread may run on, one bit each. Threads start with all of them.
+// The ready table only hands a thread to a core in its mask, and a thread
+// running on a core it lost leaves at that core's next reschedule.
+
+use super::NUM_CORES;
+use crate::{
+    arch,
+    thread::{Thread, ThreadNode},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const ALL_CORES: usize = usize::MAX >> (usize::BITS as usize - NUM_CORES);
+
+// Set once any thread is held to fewer cores, so the ready table can skip
+// all of this until then.
+static RESTRICTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityError {
+    /// The mask names none of the cores.
+    NoCore,
+}
+
+/// Let `t` run only on the cores in `mask`. If it's running elsewhere, its
+/// core is asked to reschedule right away.
+pub fn set_affinity(t: &ThreadNode, mask: usize) -> Result<(), AffinityError> {
+    let mask = mask & ALL_CORES;
+    if mask == 0 {
+        return Err(AffinityError::NoCore);
+    }
+    if mask != ALL_CORES {
+        RESTRICTED.store(true, Ordering::Release);
+    }
+    t.set_affinity(mask);
+    let this = arch::current_cpu_id();
+    for cpu in (0..NUM_CORES).filter(|&cpu| mask & (1 << cpu) == 0 && cpu != this) {
+        if Thread::id(&super::running_thread_of(cpu)) == Thread::id(t) {
+            arch::send_ipi(cpu);
+        }
+    }
+    Ok(())
+}
+
+/// Cores `t` may run on.
+pub fn affinity_of(t: &Thread) -> usize {
+    t.affinity() & ALL_CORES
+}
+
+pub(super) fn restricted() -> bool {
+    RESTRICTED.load(Ordering::Acquire)
+}
+
+/// Whether `cpu` is in the mask of `t`.
+#[inline]
+pub(super) fn allows(t: &Thread, cpu: usize) -> bool {
+    t.affinity() & (1 << cpu) != 0
+}
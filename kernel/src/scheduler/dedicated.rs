@@ -0,0 +1,99 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Cores owned by a single thread. The ready table hands an owner only to its
+// own core and never gives that core anything else, so everything else runs
+// on the remaining cores while the owner has its core to itself.
+
+use super::NUM_CORES;
+use crate::{
+    arch,
+    thread::{Thread, ThreadNode},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const NO_OWNER: usize = 0;
+
+static OWNERS: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(NO_OWNER) }; NUM_CORES];
+// One bit per dedicated core, so the ready table can skip all of this when
+// nothing is dedicated.
+static DEDICATED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedicateError {
+    NoSuchCore,
+    /// The core belongs to another thread.
+    Taken,
+    /// At least one core has to stay shared.
+    LastCore,
+}
+
+/// Reserve core `cpu` for `owner`. Whatever else runs there is moved off at
+/// its next reschedule, which is asked for right away.
+pub fn dedicate_core(cpu: usize, owner: &ThreadNode) -> Result<(), DedicateError> {
+    if cpu >= NUM_CORES {
+        return Err(DedicateError::NoSuchCore);
+    }
+    let id = Thread::id(owner);
+    if let Err(current) =
+        OWNERS[cpu].compare_exchange(NO_OWNER, id, Ordering::AcqRel, Ordering::Acquire)
+    {
+        if current != id {
+            return Err(DedicateError::Taken);
+        }
+        return Ok(());
+    }
+    let all = (1 << NUM_CORES) - 1;
+    if DEDICATED.fetch_or(1 << cpu, Ordering::AcqRel) | (1 << cpu) == all {
+        release_core(cpu);
+        return Err(DedicateError::LastCore);
+    }
+    if cpu != arch::current_cpu_id() {
+        arch::send_ipi(cpu);
+    }
+    Ok(())
+}
+
+/// Give core `cpu` back to the shared pool.
+pub fn release_core(cpu: usize) {
+    if cpu >= NUM_CORES {
+        return;
+    }
+    DEDICATED.fetch_and(!(1 << cpu), Ordering::AcqRel);
+    OWNERS[cpu].store(NO_OWNER, Ordering::Release);
+}
+
+pub fn dedicated_cores() -> usize {
+    DEDICATED.load(Ordering::Acquire)
+}
+
+/// Core owned by thread `id`, if any.
+pub fn core_of(id: usize) -> Option<usize> {
+    if dedicated_cores() == 0 {
+        return None;
+    }
+    OWNERS
+        .iter()
+        .position(|owner| owner.load(Ordering::Acquire) == id)
+}
+
+/// Whether `cpu` may pick up `t` from the ready table.
+#[inline]
+pub(super) fn runnable_on(t: &Thread, cpu: usize) -> bool {
+    let id = Thread::id(t);
+    match OWNERS[cpu].load(Ordering::Acquire) {
+        NO_OWNER => core_of(id).is_none(),
+        owner => owner == id,
+    }
+}
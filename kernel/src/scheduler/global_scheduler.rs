@@ -79,8 +79,34 @@ fn inner_next_thread(mut tbl: SpinLockGuard<'_, ReadyTable>, index: usize) -> Op
     Some(next)
 }
 
+// Highest priority thread up to `limit` that this core may run, skipping
+// threads that belong to other dedicated cores or to other cores' affinity.
+#[cfg(smp)]
+fn next_runnable_here(mut tbl: SpinLockGuard<'_, ReadyTable>, limit: u32) -> Option<ThreadNode> {
+    let cpu = arch::current_cpu_id();
+    for prio in tbl.highest_active() as usize..=limit as usize {
+        if tbl.active_tables & (1 << prio) == 0 {
+            continue;
+        }
+        let q = &mut tbl.tables[prio];
+        let Some(next) = q.remove_if(|t| super::runnable_on(t, cpu)) else {
+            continue;
+        };
+        debug_assert_eq!(next.state(), thread::READY);
+        if q.is_empty() {
+            tbl.clear_active_queue(prio as u32);
+        }
+        return Some(next);
+    }
+    None
+}
+
 pub fn next_preferred_thread(prio: ThreadPriority) -> Option<ThreadNode> {
     let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
+    #[cfg(smp)]
+    if super::is_filtered() {
+        return next_runnable_here(tbl, prio as u32);
+    }
     let highest_active = tbl.highest_active();
     if highest_active > prio as u32 {
         return None;
@@ -90,6 +116,10 @@ pub fn next_preferred_thread(prio: ThreadPriority) -> Option<ThreadNode> {
 
 pub fn next_ready_thread() -> Option<ThreadNode> {
     let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
+    #[cfg(smp)]
+    if super::is_filtered() {
+        return next_runnable_here(tbl, MAX_THREAD_PRIORITY as u32);
+    }
     let highest_active = tbl.highest_active();
 
     #[cfg(debugging_scheduler)]
@@ -150,13 +180,24 @@ pub fn queue_ready_thread(old_state: Uint, t: ThreadNode) -> Result<(), Uint> {
     if old_state == thread::READY {
         return Err(thread::READY);
     }
+    #[cfg(smp)]
+    let (owned_core, allowed) = (
+        super::dedicated::core_of(Thread::id(&t)),
+        super::affinity_of(&t),
+    );
     let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
     t.transfer_state(old_state, thread::READY)?;
     let ok = queue_ready_thread_inner(&mut tbl, t);
     debug_assert!(ok);
     drop(tbl);
     #[cfg(smp)]
-    super::notify_idle_cores(1);
+    match owned_core {
+        Some(cpu) if cpu != arch::current_cpu_id() && super::is_idle_core(cpu) => {
+            arch::send_ipi(cpu)
+        }
+        Some(_) => {}
+        None => super::notify_idle_cores(1, allowed),
+    }
     Ok(())
 }
 
@@ -199,3 +240,28 @@ pub fn update_ready_thread_priority(
     }
     Err(thread::RUNNING)
 }
+
+/// Change the origin priority of `t` whatever its state, the one a mutex
+/// owner falls back to once it holds no mutex. While `t` holds one, a
+/// priority inherited from its waiters is kept if more urgent; releasing
+/// the mutex recovers the new origin. A ready thread moves to the queue of
+/// its new priority; holding the table lock keeps it from being queued
+/// under the old one meanwhile.
+pub fn set_thread_origin_priority(t: &ThreadNode, new_priority: ThreadPriority) {
+    let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
+    let was_ready = remove_from_ready_queue_inner(&mut tbl, t);
+    let holds_mutex = t.has_acquired_mutex();
+    {
+        let mut w = t.lock();
+        w.set_origin_priority(new_priority);
+        if holds_mutex {
+            let _ = w.promote_priority_to(new_priority);
+        } else {
+            w.recover_priority();
+        }
+    }
+    if was_ready {
+        let ok = queue_ready_thread_inner(&mut tbl, t.clone());
+        debug_assert!(ok);
+    }
+}
@@ -30,15 +30,23 @@ use crate::{
     types::{Arc, IlistHead, Uint},
     with_iou,
 };
+#[cfg(smp)]
+pub use affinity::{affinity_of, set_affinity, AffinityError};
 use alloc::boxed::Box;
 use core::{
     intrinsics::unlikely,
     mem::MaybeUninit,
     sync::atomic::{compiler_fence, AtomicBool, AtomicU8, Ordering},
 };
+#[cfg(smp)]
+pub use dedicated::{dedicate_core, dedicated_cores, release_core, DedicateError};
 pub use global_scheduler::*;
 pub(crate) use wait_queue::*;
 
+#[cfg(smp)]
+mod affinity;
+#[cfg(smp)]
+mod dedicated;
 mod global_scheduler;
 mod idle;
 pub use idle::{
@@ -192,6 +200,8 @@ pub(crate) extern "C" fn save_context_finish_hook(
 
 fn switch_current_thread(next: ThreadNode, old_sp: usize) -> usize {
     let now = Tick::now();
+    #[cfg(soft_watchdog)]
+    crate::watchdog::cpu_switched();
     #[cfg(round_robin)]
     {
         next.set_this_round_start_at(now);
@@ -314,12 +324,29 @@ fn inner_yield(next: ThreadNode) {
     }
 }
 
+// The idle thread, if the current thread sits on a core dedicated to some
+// other thread and has to make way even with nothing else ready.
+#[cfg(smp)]
+fn evicted_to_idle() -> Option<ThreadNode> {
+    let old = current_thread_ref();
+    if Thread::id(old) == Thread::id(idle::current_idle_thread_ref())
+        || runnable_on(old, arch::current_cpu_id())
+    {
+        return None;
+    }
+    Some(idle::current_idle_thread())
+}
+
 pub fn yield_me() {
     // We don't allow thread yielding with irq disabled.
     // The scheduler assumes every thread should be resumed with local
     // irq enabled.
     debug_assert!(arch::local_irq_enabled());
     let Some(next) = next_ready_thread() else {
+        #[cfg(smp)]
+        if let Some(idle) = evicted_to_idle() {
+            inner_yield(idle);
+        }
         return;
     };
     debug_assert_eq!(next.state(), thread::READY);
@@ -330,6 +357,10 @@ pub fn relinquish_me() {
     debug_assert!(arch::local_irq_enabled());
     let old = current_thread_ref();
     let Some(next) = next_preferred_thread(old.priority()) else {
+        #[cfg(smp)]
+        if let Some(idle) = evicted_to_idle() {
+            inner_yield(idle);
+        }
         return;
     };
     debug_assert_eq!(next.state(), thread::READY);
@@ -504,11 +535,38 @@ pub(crate) fn is_idle_core(id: usize) -> bool {
     Thread::id(running) == Thread::id(idle)
 }
 
-pub(crate) fn notify_idle_cores(how_many: usize) {
+pub(crate) fn running_thread_of(id: usize) -> ThreadNode {
+    let _guard = DisableInterruptGuard::new();
+    unsafe { RUNNING_THREADS[id].assume_init_ref().clone() }
+}
+
+// Whether the ready table has to check each thread against the core picking
+// it up.
+#[cfg(smp)]
+#[inline]
+fn is_filtered() -> bool {
+    dedicated::dedicated_cores() != 0 || affinity::restricted()
+}
+
+/// Whether `cpu` may pick up `t` from the ready table.
+#[cfg(smp)]
+#[inline]
+fn runnable_on(t: &Thread, cpu: usize) -> bool {
+    dedicated::runnable_on(t, cpu) && affinity::allows(t, cpu)
+}
+
+/// Wake up to `how_many` idle cores out of `allowed`, one bit each.
+pub(crate) fn notify_idle_cores(how_many: usize, allowed: usize) {
     let this = arch::current_cpu_id();
     let mut notified = 0;
+    #[cfg(smp)]
+    let dedicated = dedicated::dedicated_cores();
+    #[cfg(not(smp))]
+    let dedicated = 0;
     for i in 0..NUM_CORES {
-        if this == i || !is_idle_core(i) {
+        // A dedicated core has nothing to take from the ready table but its
+        // owner, which is woken directly.
+        if this == i || allowed & (1 << i) == 0 || dedicated & (1 << i) != 0 || !is_idle_core(i) {
             continue;
         }
         arch::send_ipi(i);
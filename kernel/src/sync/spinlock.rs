@@ -200,6 +200,13 @@ impl<T: ?Sized> SpinLock<T> {
         }
     }
 
+    /// The protected data, without taking the lock. Only for paths that
+    /// can't wait for it, like a panic.
+    #[inline]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.lock.as_mut_ptr()
+    }
+
     pub fn reader_count(&self) -> usize {
         self.lock.reader_count() as usize
     }
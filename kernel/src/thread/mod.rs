@@ -200,6 +200,9 @@ pub struct Thread {
     origin_priority: ThreadPriority,
     state: AtomicUint,
     preempt_count: AtomicUint,
+    // Cores this thread may run on, one bit each.
+    #[cfg(smp)]
+    affinity: AtomicUsize,
     // FIXME: Using a rusty lock looks not flexible. Now we are using
     // a C-style intrusive lock. It's conventional to declare which
     // fields this lock is protecting. lock is protecting the
@@ -434,6 +437,8 @@ impl Thread {
             priority: 0,
             origin_priority: 0,
             preempt_count: AtomicUint::new(0),
+            #[cfg(smp)]
+            affinity: AtomicUsize::new(usize::MAX),
             #[cfg(thread_stats)]
             stats: ThreadStats::new(),
             kind,
@@ -636,6 +641,19 @@ impl Thread {
         self.priority
     }
 
+    /// Cores the thread may run on, see `scheduler::set_affinity`.
+    #[cfg(smp)]
+    #[inline]
+    pub fn affinity(&self) -> usize {
+        self.affinity.load(Ordering::Acquire)
+    }
+
+    #[cfg(smp)]
+    #[inline]
+    pub(crate) fn set_affinity(&self, mask: usize) {
+        self.affinity.store(mask, Ordering::Release);
+    }
+
     #[inline]
     pub fn disable_preempt(&self) -> bool {
         self.preempt_count.fetch_add(1, Ordering::Release) == 0
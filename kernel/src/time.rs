@@ -87,6 +87,8 @@ impl Tick {
 
 pub(crate) extern "C" fn handle_clock_interrupt() {
     let _guard = DisableInterruptGuard::new();
+    #[cfg(soft_watchdog)]
+    crate::watchdog::cpu_heartbeat();
     let now = Tick::now();
     if let Some(next_deadline) = timer::expire_timers(now) {
         Tick::interrupt_at(next_deadline);
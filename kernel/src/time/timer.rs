@@ -68,6 +68,8 @@ extern "C" fn run_soft_timer() {
             let mut w = unsafe { &SW_TIMER_WORKER.timers }.irqsave_lock();
             w.post_expire();
         }
+        #[cfg(procfs)]
+        crate::irq::deferred_trace::record(crate::irq::deferred_trace::DeferredWork::Timer);
         update_clock_interrupt();
         atomic_wait(unsafe { &SW_TIMER_WORKER.waker }, n, Tick::MAX);
     }
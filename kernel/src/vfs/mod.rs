@@ -41,6 +41,8 @@ mod path;
 mod procfs;
 #[cfg(procfs)]
 pub use procfs::{trace_thread_close, trace_thread_create};
+#[cfg(all(procfs, virtualization))]
+pub use procfs::{trace_vm_create, trace_vm_destroy};
 mod root;
 #[cfg(enable_net)]
 mod sockfs;
@@ -0,0 +1,83 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ProcFileOps;
+use crate::{
+    arch::{cpuinfo, registers::cntfrq_el0::CNTFRQ_EL0},
+    error::Error,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use tock_registers::interfaces::Readable;
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+/// Identification and virtualization support of each core that came up,
+/// /proc/cpuinfo.
+pub(crate) struct CpuInfo;
+
+impl ProcFileOps for CpuInfo {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(256 * NUM_CORES);
+        let freq = CNTFRQ_EL0.get();
+        for cpu in 0..NUM_CORES {
+            let Some(id) = cpuinfo::get(cpu) else {
+                continue;
+            };
+            let _ = write!(result, "processor\t: {}\r\n", cpu);
+            let _ = write!(
+                result,
+                "implementer\t: {:#04x} ({})\r\n",
+                id.implementer(),
+                id.implementer_name()
+            );
+            let _ = write!(result, "variant\t\t: {:#x}\r\n", id.variant());
+            let _ = write!(result, "part\t\t: {:#05x}\r\n", id.part());
+            let _ = write!(result, "revision\t: {}\r\n", id.revision());
+            let _ = write!(result, "counter\t\t: {} Hz\r\n", freq);
+            result.push_str("flags\t\t:");
+            for flag in id.flags() {
+                let _ = write!(result, " {}", flag);
+            }
+            result.push_str("\r\n");
+            #[cfg(virtualization)]
+            write_hypervisor(&mut result, cpu);
+            result.push_str("\r\n");
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Whether guests can run on `cpu` and the features EL2 was patched for,
+/// which may be fewer than the core's flags.
+#[cfg(virtualization)]
+fn write_hypervisor(result: &mut String, cpu: usize) {
+    use crate::arch::virt::{alternative, initcall};
+
+    let state = if initcall::cpu_ready(cpu) {
+        "ready"
+    } else {
+        "unavailable"
+    };
+    let _ = write!(result, "hypervisor\t: {}\r\n", state);
+    result.push_str("hyp features\t:");
+    for feature in alternative::features().iter() {
+        let _ = write!(result, " {}", feature.name());
+    }
+    result.push_str("\r\n");
+}
@@ -0,0 +1,973 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ProcFileOps;
+#[cfg(virt_exit_budget)]
+use crate::arch::virt::budget;
+use crate::{
+    arch::{
+        irq::{self, IrqNumber, IRQ_MANAGER},
+        virt::{
+            adaptive::{self, Window},
+            audit,
+            boost::{self, BoostConfig},
+            clock_probe::{self, ProbeError},
+            console_log::{self, LogConfig},
+            el2_stack,
+            exit::GuestEl,
+            gpio, identity, kick,
+            policy::ExitClass,
+            spi, stage2, status,
+            timer_cal::{self, CalError},
+            trace,
+            vconsole::{self, AttachError},
+            vcpu::{self, vcpu_manager},
+            verify::{self, Outcome, Policy},
+            vgic::MAX_INTID,
+            vlog::{self, Component},
+        },
+    },
+    config::MAX_THREAD_PRIORITY,
+    error::{code, Error},
+    time::Tick,
+    types::ThreadPriority,
+};
+use alloc::{string::String, vec::Vec};
+use core::{fmt::Write, time::Duration};
+
+/// Stage-2 map of one VM, /proc/hypervisor/vmN/mappings.
+pub(crate) struct VmMappings {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmMappings {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(1024);
+        stage2::dump(self.vm_id, &mut result).map_err(|_| code::ENOENT)?;
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Identity of one VM, /proc/hypervisor/vmN/identity: "uuid", "name" if
+/// it has one and a line per metadata entry, each followed by its value.
+pub(crate) struct VmIdentity {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmIdentity {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let identity = identity::identity_of(self.vm_id).ok_or(code::ENOENT)?;
+        let mut result = String::with_capacity(128);
+        write!(result, "uuid {}\r\n", identity.uuid).unwrap();
+        if let Some(name) = &identity.name {
+            write!(result, "name {}\r\n", name).unwrap();
+        }
+        for (key, value) in &identity.metadata {
+            write!(result, "{} {}\r\n", key, value).unwrap();
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Guest traps of a VM's vCPUs by the exception level they came from,
+/// /proc/hypervisor/vmN/exits: one line per vCPU and level, then the count
+/// of each exit class. Writing "reset" starts the VM's statistics over.
+pub(crate) struct VmExits {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmExits {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(256);
+        result.push_str("vcpu el");
+        for class in ExitClass::ALL {
+            let _ = write!(result, " {}", class.name());
+        }
+        result.push_str("\r\n");
+        vcpu_manager().for_each_of(self.vm_id, |vcpu| {
+            for el in GuestEl::ALL {
+                let _ = write!(result, "{} {}", vcpu.id, el.name());
+                for count in vcpu.stats.traps[el as usize] {
+                    let _ = write!(result, " {}", count);
+                }
+                result.push_str("\r\n");
+            }
+        });
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        match core::str::from_utf8(&content).map(str::trim) {
+            Ok("reset") => vcpu::reset_vm_stats(self.vm_id),
+            _ => return Err(code::EINVAL),
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Shadow registers of a VM's vCPUs, /proc/hypervisor/vmN/regs. Writing
+/// "on" or "off" switches mirroring on exits for all of them.
+pub(crate) struct VmRegs {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmRegs {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(256);
+        vcpu_manager().for_each_of(self.vm_id, |vcpu| {
+            let _ = write!(result, "vcpu{}", vcpu.id);
+            let _ = match vcpu.shadow.snapshot() {
+                _ if !vcpu.shadow.is_enabled() => write!(result, " off"),
+                None => write!(result, " no exits yet"),
+                Some(s) => write!(
+                    result,
+                    " pc {:#x} sp {:#x} x0 {:#x} x1 {:#x} x2 {:#x} x3 {:#x} spsr {:#x} exits {}",
+                    s.pc, s.sp, s.x[0], s.x[1], s.x[2], s.x[3], s.spsr, s.exits
+                ),
+            };
+            result.push_str("\r\n");
+        });
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let on = match core::str::from_utf8(&content).map(str::trim) {
+            Ok("on") => true,
+            Ok("off") => false,
+            _ => return Err(code::EINVAL),
+        };
+        vcpu_manager().for_each_of(self.vm_id, |vcpu| vcpu.shadow.set_enabled(on));
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// How a VM ended, /proc/hypervisor/vmN/status: "running" until then,
+/// see `status`.
+pub(crate) struct VmStatus {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmStatus {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(16);
+        let _ = match status::of(self.vm_id) {
+            Some(exit) => write!(result, "{}\r\n", exit),
+            None => write!(result, "running\r\n"),
+        };
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Traps of a VM's vCPUs by HCR_EL2 bit, /proc/hypervisor/vmN/traps.
+/// Writing "<bit> <on|off>" switches one for all of them from their next
+/// entry on, to measure its cost in the exits file without a rebuild.
+pub(crate) struct VmTraps {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmTraps {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(64);
+        let first = vcpu_manager().ids_of(self.vm_id).next();
+        let Some(traps) = first.and_then(|id| vcpu_manager().with_vcpu(id, |v| v.config.traps))
+        else {
+            return Ok(result.into_bytes());
+        };
+        for (bit, on) in traps.bits() {
+            let _ = write!(result, "{} {}\r\n", bit, if on { "on" } else { "off" });
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        let (Some(bit), Some(on), None) = (words.next(), words.next(), words.next()) else {
+            return Err(code::EINVAL);
+        };
+        let on = match on {
+            "on" => true,
+            "off" => false,
+            _ => return Err(code::EINVAL),
+        };
+        // Whether the bit is known, once there is a vCPU to ask.
+        let mut known = None;
+        vcpu_manager().for_each_of(self.vm_id, |vcpu| {
+            let trap = vcpu.config.traps.bit_mut(bit);
+            known = Some(trap.is_some());
+            if let Some(trap) = trap {
+                *trap = on;
+            }
+        });
+        match known {
+            Some(true) => Ok(content.len()),
+            Some(false) => Err(code::EINVAL),
+            None => Err(code::ENOENT),
+        }
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Developer control raising a virtual interrupt in a VM, written as
+/// "irq <intid|name> [vcpu]", /proc/hypervisor/vmN/inject, where a name is
+/// one the VM's SPIs were handed out under. Without a vCPU the VM's first
+/// one gets it. Reads list the names.
+pub(crate) struct VmInject {
+    pub vm_id: usize,
+}
+
+impl VmInject {
+    fn parse(&self, cmd: &str) -> Result<(u32, usize), Error> {
+        let mut words = cmd.split_whitespace();
+        if words.next() != Some("irq") {
+            return Err(code::EINVAL);
+        }
+        let intid = words
+            .next()
+            .and_then(|w| w.parse::<u32>().ok().or_else(|| spi::lookup(self.vm_id, w)))
+            .filter(|&intid| intid < MAX_INTID)
+            .ok_or(code::EINVAL)?;
+        let vcpu_id = match words.next() {
+            Some(w) => w.parse().map_err(|_| code::EINVAL)?,
+            None => vcpu_manager()
+                .ids_of(self.vm_id)
+                .next()
+                .ok_or(code::ENOENT)?,
+        };
+        if words.next().is_some() {
+            return Err(code::EINVAL);
+        }
+        Ok((intid, vcpu_id))
+    }
+}
+
+impl ProcFileOps for VmInject {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::from("irq <intid|name> [vcpu]\r\n");
+        for (name, intid) in spi::map_of(self.vm_id).iter() {
+            write!(result, "{} {}\r\n", name, intid).unwrap();
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let (intid, vcpu_id) = self.parse(cmd)?;
+        kick::inject_irq(self.vm_id, vcpu_id, intid).map_err(|_| code::ENOENT)?;
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Pins of a VM's emulated GPIO controller, /proc/hypervisor/vmN/gpio.
+/// Writing "<pin> <0|1>" drives an input pin.
+pub(crate) struct VmGpio {
+    pub vm_id: usize,
+}
+
+impl VmGpio {
+    fn parse(cmd: &str) -> Result<(usize, bool), Error> {
+        let mut words = cmd.split_whitespace();
+        let pin = words
+            .next()
+            .and_then(|w| w.parse().ok())
+            .ok_or(code::EINVAL)?;
+        let high = match words.next() {
+            Some("0") => false,
+            Some("1") => true,
+            _ => return Err(code::EINVAL),
+        };
+        if words.next().is_some() {
+            return Err(code::EINVAL);
+        }
+        Ok((pin, high))
+    }
+}
+
+impl ProcFileOps for VmGpio {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let gpio = gpio::get(self.vm_id).ok_or(code::ENOENT)?;
+        let (levels, outputs) = (gpio.levels(), gpio.outputs());
+        let mut result = String::with_capacity(128);
+        write!(result, "pin dir level\r\n").unwrap();
+        for pin in 0..gpio::NUM_PINS {
+            let dir = if outputs & (1 << pin) != 0 {
+                "out"
+            } else {
+                "in"
+            };
+            write!(result, "{} {} {}\r\n", pin, dir, (levels >> pin) & 1).unwrap();
+        }
+        write!(result, "ris {:#04x}\r\n", gpio.raw_status()).unwrap();
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let (pin, high) = Self::parse(cmd)?;
+        let gpio = gpio::get(self.vm_id).ok_or(code::ENOENT)?;
+        gpio.set_input(pin, high).map_err(|_| code::EINVAL)?;
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Console of a VM, /proc/hypervisor/vmN/console. Reading takes the
+/// output kept since the last read, if the console is captured; writing
+/// gives the guest the bytes as input, see `vconsole::inject`.
+pub(crate) struct VmConsole {
+    pub vm_id: usize,
+}
+
+impl ProcFileOps for VmConsole {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        vconsole::take_output(self.vm_id).ok_or(code::ENOENT)
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        vconsole::inject(self.vm_id, &content).ok_or(code::ENOENT)
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// World-switch segment latency percentiles, /proc/hypervisor/latency.
+#[cfg(virt_switch_latency)]
+pub(crate) struct SwitchLatency;
+
+#[cfg(virt_switch_latency)]
+impl ProcFileOps for SwitchLatency {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(256);
+        crate::arch::virt::latency::dump(&mut result).map_err(|_| code::EINVAL)?;
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Routing of the host's registered SPIs, /proc/hypervisor/irq_affinity.
+/// "any" means the GIC picks a core per interrupt.
+pub(crate) struct IrqAffinity;
+
+impl ProcFileOps for IrqAffinity {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let routes: Vec<(IrqNumber, Option<usize>)> = IRQ_MANAGER
+            .irqsave_lock()
+            .contexts
+            .iter()
+            .flatten()
+            .map(|context| context.irq)
+            .filter(|irq| irq.is_spi())
+            .map(|irq| (irq, irq::irq_affinity(irq)))
+            .collect();
+        let mut result = String::with_capacity(256);
+        write!(
+            result,
+            "isolated {:#x}\r\nirq cpu\r\n",
+            irq::isolated_cores()
+        )
+        .unwrap();
+        for (irq, cpu) in routes {
+            match cpu {
+                Some(cpu) => write!(result, "{} {}\r\n", u32::from(irq), cpu).unwrap(),
+                None => write!(result, "{} any\r\n", u32::from(irq)).unwrap(),
+            }
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Interrupt boost of vCPU host threads, /proc/hypervisor/irq_boost: the
+/// settings, then per vCPU how often it was boosted, how often it didn't
+/// need to be and the ticks spent boosted. Writing "priority <n>" or
+/// "decay <ticks>" changes the settings for later boosts.
+pub(crate) struct IrqBoost;
+
+impl ProcFileOps for IrqBoost {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let config = boost::config();
+        let mut result = String::with_capacity(256);
+        write!(
+            result,
+            "priority {}\r\ndecay {}\r\nvcpu boosts skipped ticks active\r\n",
+            config.priority, config.decay.0
+        )
+        .unwrap();
+        vcpu_manager().for_each(|vcpu| {
+            let stats = vcpu.boost.stats();
+            write!(
+                result,
+                "{} {} {} {} {}\r\n",
+                vcpu.id,
+                stats.boosts,
+                stats.skipped,
+                stats.boosted_ticks,
+                vcpu.boost.is_active() as u8
+            )
+            .unwrap();
+        });
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        let (Some(key), Some(value), None) = (words.next(), words.next(), words.next()) else {
+            return Err(code::EINVAL);
+        };
+        let value: usize = value.parse().map_err(|_| code::EINVAL)?;
+        let config = match key {
+            "priority" if value <= MAX_THREAD_PRIORITY as usize => BoostConfig {
+                priority: value as ThreadPriority,
+                ..boost::config()
+            },
+            "decay" => BoostConfig {
+                decay: Tick(value),
+                ..boost::config()
+            },
+            _ => return Err(code::EINVAL),
+        };
+        boost::set_config(config);
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Per-component hypervisor log levels, /proc/hypervisor/log_levels.
+/// Writing "<component|all> <level|default>" changes them, e.g.
+/// "stage2 debug"; a component at default follows the kernel log level.
+pub(crate) struct LogLevels;
+
+impl LogLevels {
+    fn parse(cmd: &str) -> Result<(Option<Component>, Option<log::LevelFilter>), Error> {
+        let mut words = cmd.split_whitespace();
+        let comp = match words.next().ok_or(code::EINVAL)? {
+            "all" => None,
+            name => Some(Component::from_name(name).ok_or(code::EINVAL)?),
+        };
+        let level = match words.next().ok_or(code::EINVAL)? {
+            "default" => None,
+            level => Some(level.parse().map_err(|_| code::EINVAL)?),
+        };
+        if words.next().is_some() {
+            return Err(code::EINVAL);
+        }
+        Ok((comp, level))
+    }
+}
+
+impl ProcFileOps for LogLevels {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(128);
+        for comp in Component::ALL {
+            match vlog::level(comp) {
+                Some(level) => write!(result, "{} {}\r\n", comp.name(), level).unwrap(),
+                None => {
+                    write!(result, "{} default ({})\r\n", comp.name(), log::max_level()).unwrap()
+                }
+            }
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        match Self::parse(cmd)? {
+            (Some(comp), level) => vlog::set_level(comp, level),
+            (None, level) => {
+                for comp in Component::ALL {
+                    vlog::set_level(comp, level);
+                }
+            }
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Audit log of privileged VM operations, /proc/hypervisor/audit: how many
+/// were logged since boot, then one per line, oldest first, as "seq time
+/// vmN [label] initiator operation".
+pub(crate) struct Audit;
+
+impl ProcFileOps for Audit {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(4096);
+        write!(result, "total {}\r\n", audit::total()).unwrap();
+        audit::for_each(|record| write!(result, "{}\r\n", record).unwrap());
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Hypervisor trace buffer, /proc/hypervisor/trace: whether tracing is on
+/// and how many events were traced since boot, then one per line in time
+/// order, as "seq time vmN vcpu:M event". Writing "on" or "off" switches
+/// tracing, "clear" drops the events kept.
+pub(crate) struct Trace;
+
+impl ProcFileOps for Trace {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(8192);
+        write!(
+            result,
+            "{} total {}\r\n",
+            if trace::is_enabled() { "on" } else { "off" },
+            trace::total()
+        )
+        .unwrap();
+        for event in trace::events() {
+            write!(result, "{}\r\n", event).unwrap();
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        match cmd.trim() {
+            "on" => trace::set_enabled(true),
+            "off" => trace::set_enabled(false),
+            "clear" => trace::clear(),
+            _ => return Err(code::EINVAL),
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Exit handler latency budgets, /proc/hypervisor/exit_budget: one
+/// "class budget exits violations worst" line per exit class, in counter
+/// cycles, then the violations kept. Writing "<class> <cycles>" sets the
+/// budget of a class, 0 removing it; "reset" clears the counts.
+#[cfg(virt_exit_budget)]
+pub(crate) struct ExitBudget;
+
+#[cfg(virt_exit_budget)]
+impl ProcFileOps for ExitBudget {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(2048);
+        result.push_str("class budget exits violations worst\r\n");
+        for class in ExitClass::ALL {
+            let stats = budget::stats(class);
+            write!(
+                result,
+                "{} {} {} {} {}\r\n",
+                class.name(),
+                stats.budget,
+                stats.exits,
+                stats.violations,
+                stats.worst
+            )
+            .unwrap();
+        }
+        for violation in budget::violations() {
+            write!(result, "{}\r\n", violation).unwrap();
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("reset"), None, None) => budget::reset(),
+            (Some(class), Some(cycles), None) => {
+                let class = ExitClass::from_name(class).ok_or(code::EINVAL)?;
+                let cycles = cycles.parse().map_err(|_| code::EINVAL)?;
+                budget::set_budget(class, cycles);
+            }
+            _ => return Err(code::EINVAL),
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Per-VM console logs, /proc/hypervisor/console_log: a line per logged
+/// VM with its file, size and rotations, then the last VFS error if any.
+/// Writing "<vm>" logs VM vm with the default config, "<vm> <max_size>
+/// <keep> [tee]" with that one, and "<vm> off" stops it; a max_size under
+/// `console_log::MIN_SIZE` fails with EINVAL.
+pub(crate) struct ConsoleLog;
+
+impl ProcFileOps for ConsoleLog {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(128);
+        for (vm_id, st) in console_log::status() {
+            write!(
+                result,
+                "vm{} {} size {} rotations {}",
+                vm_id,
+                console_log::path(vm_id, 0),
+                st.size,
+                st.rotations
+            )
+            .unwrap();
+            if let Some(errno) = st.error {
+                write!(result, " error {}", errno).unwrap();
+            }
+            result.push_str("\r\n");
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let words: Vec<&str> = cmd.split_whitespace().collect();
+        let Some((vm_id, args)) = words.split_first() else {
+            return Err(code::EINVAL);
+        };
+        let vm_id = vm_id.parse().map_err(|_| code::EINVAL)?;
+        let config = match args {
+            ["off"] => {
+                console_log::disable(vm_id);
+                return Ok(content.len());
+            }
+            [] => LogConfig::default(),
+            [max_size, keep, tee @ ..] => LogConfig {
+                max_size: max_size.parse().map_err(|_| code::EINVAL)?,
+                keep: keep.parse().map_err(|_| code::EINVAL)?,
+                tee: match tee {
+                    [] => false,
+                    ["tee"] => true,
+                    _ => return Err(code::EINVAL),
+                },
+            },
+            _ => return Err(code::EINVAL),
+        };
+        console_log::enable(vm_id, config).map_err(|_| code::EINVAL)?;
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Guest image verification, /proc/hypervisor/verify: the policy, then
+/// what the last build of each VM found. Writing "off", "warn" or
+/// "enforce" sets the policy for VMs built from then on.
+pub(crate) struct Verify;
+
+impl ProcFileOps for Verify {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(128);
+        write!(result, "policy {}\r\n", verify::policy().name()).unwrap();
+        for (vm_id, outcome) in verify::outcomes() {
+            write!(result, "vm{} ", vm_id).unwrap();
+            match outcome {
+                Outcome::Verified => result.push_str("verified"),
+                Outcome::Unverified => result.push_str("unverified"),
+                Outcome::Mismatch(digest) => write!(result, "mismatch {}", digest).unwrap(),
+                Outcome::NoCheck => result.push_str("no-check"),
+                Outcome::Unreadable(ipa) => write!(result, "unreadable {:#x}", ipa).unwrap(),
+            }
+            result.push_str("\r\n");
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let policy = Policy::from_name(cmd.trim()).ok_or(code::EINVAL)?;
+        verify::set_policy(policy);
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Counter read cost, /proc/hypervisor/clock_probe: the reads a probe run
+/// makes, then the cycles and traps of the last run. Writing "<vm> <bound>"
+/// runs the probe as VM vm, which must not exist; a trapped read fails
+/// with EIO and a loop over bound cycles with ETIMEDOUT.
+pub(crate) struct ClockProbe;
+
+impl ProcFileOps for ClockProbe {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(64);
+        write!(result, "reads {}\r\n", clock_probe::READS).unwrap();
+        match clock_probe::last() {
+            Some(cost) => {
+                write!(result, "cycles {} traps {}\r\n", cost.cycles, cost.traps).unwrap()
+            }
+            None => result.push_str("not run\r\n"),
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        let (Some(vm_id), Some(bound), None) = (words.next(), words.next(), words.next()) else {
+            return Err(code::EINVAL);
+        };
+        let vm_id = vm_id.parse().map_err(|_| code::EINVAL)?;
+        let bound = bound.parse().map_err(|_| code::EINVAL)?;
+        clock_probe::run(vm_id, bound).map_err(|e| match e {
+            ProbeError::Build(_) => code::EEXIST,
+            ProbeError::Run(_) | ProbeError::Ended(_) | ProbeError::Trapped(_) => code::EIO,
+            ProbeError::OverBound { .. } => code::ETIMEDOUT,
+        })?;
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Virtual timer delivery latency, /proc/hypervisor/timer_calibration: the
+/// offset deadlines are armed early by, how many guest samples were kept
+/// and rejected, then "min p50 p99 max" in counter cycles. Writing
+/// "calibrate", "selftest <bound>", "offset <cycles>" or "reset" runs the
+/// `timer_cal` operation of that name; too few samples fail with ENODATA
+/// and a selftest over its bound with ETIMEDOUT.
+pub(crate) struct TimerCalibration;
+
+impl ProcFileOps for TimerCalibration {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let cal = timer_cal::measure().unwrap_or_default();
+        let mut result = String::with_capacity(128);
+        write!(
+            result,
+            "offset {}\r\nsamples {} rejected {}\r\nmin p50 p99 max\r\n{} {} {} {}\r\n",
+            timer_cal::offset(),
+            cal.samples,
+            timer_cal::rejected(),
+            cal.min,
+            cal.p50,
+            cal.p99,
+            cal.max
+        )
+        .unwrap();
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        let value = |word: Option<&str>| -> Result<u64, Error> {
+            word.ok_or(code::EINVAL)?.parse().map_err(|_| code::EINVAL)
+        };
+        let result = match words.next() {
+            Some("calibrate") => timer_cal::calibrate().map(|_| ()),
+            Some("selftest") => timer_cal::selftest(value(words.next())?).map(|_| ()),
+            Some("offset") => {
+                timer_cal::set_offset(value(words.next())?);
+                Ok(())
+            }
+            Some("reset") => {
+                timer_cal::reset();
+                Ok(())
+            }
+            _ => return Err(code::EINVAL),
+        };
+        if words.next().is_some() {
+            return Err(code::EINVAL);
+        }
+        match result {
+            Ok(()) => Ok(content.len()),
+            Err(CalError::TooFewSamples(_)) => Err(code::ENODATA),
+            Err(CalError::OverBound { .. }) => Err(code::ETIMEDOUT),
+        }
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// /proc/hypervisor/console: the VM host console input goes to. Writing
+/// "<vm> <intid>" attaches VM vm, interrupting it with SPI intid when
+/// there is input; "<vm> uart" attaches it through its PL011; "detach"
+/// gives the input back to the host.
+pub(crate) struct Console;
+
+impl ProcFileOps for Console {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(64);
+        match vconsole::attachment() {
+            Some(a) => write!(
+                result,
+                "{} intid {} queued {} dropped {}\r\n",
+                identity::tag(a.vm_id),
+                a.intid,
+                a.queued,
+                a.dropped
+            )
+            .unwrap(),
+            None => result.push_str("host\r\n"),
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("detach"), None, None) => vconsole::detach(),
+            (Some(vm_id), Some("uart"), None) => {
+                let vm_id = vm_id.parse().map_err(|_| code::EINVAL)?;
+                vconsole::attach_uart(vm_id).map_err(|_| code::ENOENT)?;
+            }
+            (Some(vm_id), Some(intid), None) => {
+                let vm_id = vm_id.parse().map_err(|_| code::EINVAL)?;
+                let intid = intid.parse().map_err(|_| code::EINVAL)?;
+                vconsole::attach(vm_id, intid).map_err(|e| match e {
+                    AttachError::NoSuchVm | AttachError::NoUart => code::ENOENT,
+                    AttachError::BadIntid => code::EINVAL,
+                })?;
+            }
+            _ => return Err(code::EINVAL),
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// /proc/hypervisor/stats: the exit classification window, see `adaptive`.
+/// Writing "window_exits <n>" or "busy_us <us>" resizes it, "reset" starts
+/// the statistics of every vCPU over.
+pub(crate) struct Stats;
+
+impl ProcFileOps for Stats {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let window = adaptive::window();
+        let mut result = String::with_capacity(64);
+        write!(
+            result,
+            "window_exits {}\r\nbusy_us {}\r\n",
+            window.exits,
+            window.busy.as_micros()
+        )
+        .unwrap();
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        let cmd = core::str::from_utf8(&content).map_err(|_| code::EINVAL)?;
+        let mut words = cmd.split_whitespace();
+        let value = |word: Option<&str>| -> Result<u64, Error> {
+            word.ok_or(code::EINVAL)?.parse().map_err(|_| code::EINVAL)
+        };
+        let window = adaptive::window();
+        let window = match words.next() {
+            Some("reset") => None,
+            Some("window_exits") => Some(Window {
+                exits: u32::try_from(value(words.next())?).map_err(|_| code::EINVAL)?,
+                ..window
+            }),
+            Some("busy_us") => Some(Window {
+                busy: Duration::from_micros(value(words.next())?),
+                ..window
+            }),
+            _ => return Err(code::EINVAL),
+        };
+        if words.next().is_some() {
+            return Err(code::EINVAL);
+        }
+        match window {
+            Some(window) => adaptive::set_window(window).map_err(|_| code::EINVAL)?,
+            None => vcpu::reset_all_stats(),
+        }
+        Ok(content.len())
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// /proc/hypervisor/el2_stack: how deep each core's EL2 stack got, see
+/// `el2_stack`.
+pub(crate) struct El2Stack;
+
+impl ProcFileOps for El2Stack {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(128);
+        result.push_str("cpu used size percent\r\n");
+        for cpu in 0..blueos_kconfig::CONFIG_NUM_CORES as usize {
+            match el2_stack::usage(cpu) {
+                Some(u) => {
+                    write!(result, "{} {} {} {}\r\n", cpu, u.used, u.size, u.percent()).unwrap()
+                }
+                None => write!(result, "{} unpainted\r\n", cpu).unwrap(),
+            }
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
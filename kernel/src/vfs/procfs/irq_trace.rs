@@ -0,0 +1,79 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ProcFileOps;
+use crate::{
+    error::Error,
+    irq::irq_trace::{IrqHandlerStats, HISTOGRAM_BOUNDS_US, IRQ_COUNTERS, IRQ_HANDLER_STATS},
+    time,
+};
+use alloc::{string::String, vec::Vec};
+use core::{fmt::Write, sync::atomic::Ordering};
+
+/// Per-IRQ handler execution time: count, min/max/avg in microseconds and a
+/// histogram over HISTOGRAM_BOUNDS_US.
+pub(crate) struct IrqTraceStat;
+
+fn cycles_to_us(cycles: u64) -> u128 {
+    time::from_clock_cycles(cycles).as_micros()
+}
+
+impl ProcFileOps for IrqTraceStat {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(1024);
+        write!(result, "irq count min_us max_us avg_us |").unwrap();
+        for bound in HISTOGRAM_BOUNDS_US {
+            write!(result, " <{}", bound).unwrap();
+        }
+        write!(
+            result,
+            " >={}\r\n",
+            HISTOGRAM_BOUNDS_US[HISTOGRAM_BOUNDS_US.len() - 1]
+        )
+        .unwrap();
+
+        // IRQs never taken have nothing recorded on any core.
+        for (irq, counter) in IRQ_COUNTERS.iter().enumerate() {
+            if counter.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            let mut stats = IrqHandlerStats::new();
+            for per_cpu in IRQ_HANDLER_STATS.iter() {
+                stats.merge(&per_cpu.irqsave_lock()[irq]);
+            }
+            if stats.count == 0 {
+                continue;
+            }
+            write!(
+                result,
+                "{} {} {} {} {} |",
+                irq,
+                stats.count,
+                cycles_to_us(stats.min_cycles),
+                cycles_to_us(stats.max_cycles),
+                cycles_to_us(stats.avg_cycles())
+            )
+            .unwrap();
+            for n in stats.histogram {
+                write!(result, " {}", n).unwrap();
+            }
+            result.push_str("\r\n");
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
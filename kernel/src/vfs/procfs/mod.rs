@@ -12,11 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(target_arch = "aarch64")]
+mod cpuinfo;
+#[cfg(virtualization)]
+mod hypervisor;
+#[cfg(irq_handler_stats)]
+mod irq_trace;
 mod memory_info;
+mod softirqs;
 mod stat;
 mod task;
 
+#[cfg(target_arch = "aarch64")]
+use cpuinfo::CpuInfo;
+#[cfg(virt_exit_budget)]
+use hypervisor::ExitBudget;
+#[cfg(virt_switch_latency)]
+use hypervisor::SwitchLatency;
+#[cfg(virtualization)]
+use hypervisor::{
+    Audit, ClockProbe, Console, ConsoleLog, El2Stack, IrqAffinity, IrqBoost, LogLevels, Stats,
+    TimerCalibration, Trace, Verify, VmConsole, VmExits, VmGpio, VmIdentity, VmInject, VmMappings,
+    VmRegs, VmStatus, VmTraps,
+};
+#[cfg(irq_handler_stats)]
+use irq_trace::IrqTraceStat;
 use memory_info::MemoryInfo;
+use softirqs::SoftIrqs;
 use stat::SystemStat;
 use task::ProcTaskFile;
 
@@ -53,6 +75,10 @@ pub trait ProcFileOps: Send + Sync {
     fn get_content(&self) -> Result<Vec<u8>, Error>;
     // Set the file content when a write operation is performed on a proc inode.
     fn set_content(&self, content: Vec<u8>) -> Result<usize, Error>;
+    // Whether writes reach set_content; other files reject them.
+    fn is_writable(&self) -> bool {
+        false
+    }
 }
 
 struct DefaultProcFileOps;
@@ -136,6 +162,35 @@ impl ProcFileSystem {
 
         self.root.create_meminfo_file("meminfo")?;
         self.root.create_stat_file("stat")?;
+        self.root.create_softirqs_file("softirqs")?;
+        #[cfg(irq_handler_stats)]
+        self.root.create_irqtrace_file("irqtrace")?;
+        #[cfg(target_arch = "aarch64")]
+        self.root.create_cpuinfo_file("cpuinfo")?;
+        #[cfg(virtualization)]
+        {
+            let hyp_dir = self.root.create_dir("hypervisor", false)?;
+            #[cfg(virt_switch_latency)]
+            hyp_dir.create_switch_latency_file("latency")?;
+            #[cfg(virt_exit_budget)]
+            hyp_dir.create_exit_budget_file("exit_budget")?;
+            hyp_dir.create_irq_affinity_file("irq_affinity")?;
+            hyp_dir.create_log_levels_file("log_levels")?;
+            hyp_dir.create_irq_boost_file("irq_boost")?;
+            hyp_dir.create_console_file("console")?;
+            hyp_dir.create_stats_file("stats")?;
+            hyp_dir.create_el2_stack_file("el2_stack")?;
+            hyp_dir.create_audit_file("audit")?;
+            hyp_dir.create_timer_calibration_file("timer_calibration")?;
+            hyp_dir.create_clock_probe_file("clock_probe")?;
+            hyp_dir.create_console_log_file("console_log")?;
+            hyp_dir.create_verify_file("verify")?;
+            hyp_dir.create_trace_file("trace")?;
+            hyp_dir.create_dir("by-uuid", false)?;
+            for vm_id in crate::arch::virt::stage2::vm_ids() {
+                hyp_dir.create_vm_dir(vm_id)?;
+            }
+        }
 
         // not support process yet, use thread info instead. and put all threads in /proc
         let mut global_queue_visitor = GlobalQueueVisitor::new();
@@ -274,6 +329,254 @@ impl ProcDir {
         Ok(inode)
     }
 
+    pub fn create_softirqs_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(SoftIrqs {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(irq_handler_stats)]
+    pub fn create_irqtrace_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(IrqTraceStat {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn create_cpuinfo_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(CpuInfo, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virt_switch_latency)]
+    pub fn create_switch_latency_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(SwitchLatency, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virt_exit_budget)]
+    pub fn create_exit_budget_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(ExitBudget, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_irq_affinity_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(IrqAffinity, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_log_levels_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(LogLevels, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_irq_boost_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(IrqBoost, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_console_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Console, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_stats_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Stats, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_el2_stack_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(El2Stack, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_audit_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Audit, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_trace_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Trace, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_timer_calibration_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(TimerCalibration, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_clock_probe_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(ClockProbe, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_console_log_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(ConsoleLog, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(virtualization)]
+    pub fn create_verify_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Verify, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    /// /proc/hypervisor/vmN of VM `vm_id`, and the same files under
+    /// /proc/hypervisor/by-uuid/<uuid> once the VM has an identity, which
+    /// doesn't go to the next VM with its id.
+    #[cfg(virtualization)]
+    pub fn create_vm_dir(&self, vm_id: usize) -> Result<Arc<Self>, Error> {
+        let vm_dir = self.create_dir(alloc::format!("vm{}", vm_id).as_str(), false)?;
+        vm_dir.create_vm_files(vm_id);
+        if let Some(uuid) = crate::arch::virt::identity::uuid_of(vm_id) {
+            let by_uuid = self.lookup("by-uuid")?;
+            let by_uuid = by_uuid.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
+            by_uuid
+                .create_dir(alloc::format!("{}", uuid).as_str(), false)?
+                .create_vm_files(vm_id);
+        }
+        Ok(vm_dir)
+    }
+
+    #[cfg(virtualization)]
+    fn create_vm_files(&self, vm_id: usize) {
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(VmMappings { vm_id }, ino, self.base.fs.clone(), false)
+            as Arc<dyn InodeOps>;
+        self.insert("mappings", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(VmInject { vm_id }, ino, self.base.fs.clone(), false)
+            as Arc<dyn InodeOps>;
+        self.insert("inject", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(VmRegs { vm_id }, ino, self.base.fs.clone(), false) as Arc<dyn InodeOps>;
+        self.insert("regs", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(VmTraps { vm_id }, ino, self.base.fs.clone(), false) as Arc<dyn InodeOps>;
+        self.insert("traps", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(VmGpio { vm_id }, ino, self.base.fs.clone(), false) as Arc<dyn InodeOps>;
+        self.insert("gpio", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(VmConsole { vm_id }, ino, self.base.fs.clone(), false)
+            as Arc<dyn InodeOps>;
+        self.insert("console", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(VmExits { vm_id }, ino, self.base.fs.clone(), false) as Arc<dyn InodeOps>;
+        self.insert("exits", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(VmIdentity { vm_id }, ino, self.base.fs.clone(), false)
+            as Arc<dyn InodeOps>;
+        self.insert("identity", inode);
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(VmStatus { vm_id }, ino, self.base.fs.clone(), false)
+            as Arc<dyn InodeOps>;
+        self.insert("status", inode);
+    }
+
     pub fn create_dir(&self, name: &str, is_dcacheable: bool) -> Result<Arc<Self>, Error> {
         if name.len() > NAME_MAX {
             return Err(code::ENAMETOOLONG);
@@ -433,12 +736,13 @@ impl<T: ProcFileOps> ProcFile<T> {
         fs: Weak<ProcFileSystem>,
         is_dcacheable: bool,
     ) -> Arc<Self> {
+        let mode = if file.is_writable() { 0o644 } else { 0o444 };
         Arc::new(Self {
             base: BaseNode {
                 attr: RwLock::new(InodeAttr::new(
                     inode_no,
                     InodeFileType::Regular,
-                    InodeMode::from(0o444),
+                    InodeMode::from(mode),
                     0,
                     0,
                     BLOCK_SIZE,
@@ -471,8 +775,12 @@ impl<T: ProcFileOps + 'static> InodeOps for ProcFile<T> {
         Ok(len)
     }
 
-    fn write_at(&self, _offset: usize, _buf: &[u8], _nonblock: bool) -> Result<usize, Error> {
-        Err(code::EPERM)
+    fn write_at(&self, _offset: usize, buf: &[u8], _nonblock: bool) -> Result<usize, Error> {
+        if !self.inner.is_writable() {
+            return Err(code::EPERM);
+        }
+        // Every write is taken as one complete command.
+        self.inner.set_content(buf.to_vec())
     }
 
     fn resize(&self, _new_size: usize) -> Result<(), Error> {
@@ -524,3 +832,32 @@ pub fn trace_thread_close(thread: ThreadNode) -> Result<(), Error> {
     task_dir.remove(Thread::id(&thread).to_string().as_str());
     Ok(())
 }
+
+#[cfg(virtualization)]
+pub fn trace_vm_create(vm_id: usize) -> Result<(), Error> {
+    let procfs = get_procfs();
+    if !procfs.is_mounted() {
+        return Err(code::EINVAL);
+    }
+    let hyp_dir = procfs.root.lookup("hypervisor")?;
+    let hyp_dir = hyp_dir.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
+    hyp_dir.create_vm_dir(vm_id)?;
+    Ok(())
+}
+
+#[cfg(virtualization)]
+pub fn trace_vm_destroy(vm_id: usize) -> Result<(), Error> {
+    let procfs = get_procfs();
+    if !procfs.is_mounted() {
+        return Err(code::EINVAL);
+    }
+    let hyp_dir = procfs.root.lookup("hypervisor")?;
+    let hyp_dir = hyp_dir.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
+    hyp_dir.remove(alloc::format!("vm{}", vm_id).as_str());
+    if let Some(uuid) = crate::arch::virt::identity::uuid_of(vm_id) {
+        let by_uuid = hyp_dir.lookup("by-uuid")?;
+        let by_uuid = by_uuid.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
+        by_uuid.remove(alloc::format!("{}", uuid).as_str());
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ProcFileOps;
+use crate::{
+    error::Error,
+    irq::deferred_trace::{DEFERRED_COUNTERS, DEFERRED_WORK_NAMES},
+};
+use alloc::{format, string::String, vec::Vec};
+use core::{fmt::Write, sync::atomic::Ordering::Relaxed};
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+
+/// Per-CPU counts of deferred work runs, laid out like Linux /proc/softirqs.
+pub(crate) struct SoftIrqs;
+
+impl ProcFileOps for SoftIrqs {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result =
+            String::with_capacity(16 * (NUM_CORES + 1) * (DEFERRED_WORK_NAMES.len() + 1));
+        write!(result, "{:>12}", "").unwrap();
+        for cpu_id in 0..NUM_CORES {
+            write!(result, " {:>10}", format!("CPU{}", cpu_id)).unwrap();
+        }
+        result.push_str("\r\n");
+        for (name, counters) in DEFERRED_WORK_NAMES.iter().zip(DEFERRED_COUNTERS.iter()) {
+            write!(result, "{:>11}:", name).unwrap();
+            for counter in counters {
+                write!(result, " {:>10}", counter.load(Relaxed)).unwrap();
+            }
+            result.push_str("\r\n");
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
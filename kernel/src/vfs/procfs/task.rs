@@ -37,6 +37,8 @@ impl ProcFileOps for ProcTaskFile {
         writeln!(result, "{:<9} {}", "State:", self.thread.state_to_str()).unwrap();
         writeln!(result, "{:<9} {}", "Tid:", Thread::id(&self.thread)).unwrap();
         writeln!(result, "{:<9} {}", "Priority:", self.thread.priority()).unwrap();
+        #[cfg(virtualization)]
+        write_vcpu_info(&mut result, Thread::id(&self.thread));
         Ok(result.as_bytes().to_vec())
     }
 
@@ -44,3 +46,36 @@ impl ProcFileOps for ProcTaskFile {
         Ok(0)
     }
 }
+
+/// What the thread does if it backs a vCPU: the vCPU it runs, how its time
+/// splits between guest and host, and why the guest last exited.
+#[cfg(virtualization)]
+fn write_vcpu_info(result: &mut String, tid: usize) {
+    use crate::arch::aarch64::{
+        registers::cntfrq_el0::CNTFRQ_EL0,
+        virt::{hyper, vcpu::vcpu_manager},
+    };
+    use tock_registers::interfaces::Readable;
+
+    let manager = vcpu_manager();
+    for id in manager.ids() {
+        let found = manager.with_vcpu(id, |vcpu| {
+            if vcpu.host_thread != Some(tid) {
+                return false;
+            }
+            let (guest, host) = vcpu.time_split(hyper::read_cntpct());
+            let per_ms = (CNTFRQ_EL0.get() / 1000).max(1);
+            writeln!(result, "{:<9} vm{} vcpu{}", "Vcpu:", vcpu.vm_id, vcpu.id).unwrap();
+            writeln!(result, "{:<9} {} ms", "Guest:", guest / per_ms).unwrap();
+            writeln!(result, "{:<9} {} ms", "Host:", host / per_ms).unwrap();
+            match vcpu.last_exit {
+                Some(code) => writeln!(result, "{:<9} {:?}", "Exit:", code).unwrap(),
+                None => writeln!(result, "{:<9} -", "Exit:").unwrap(),
+            }
+            true
+        });
+        if found == Some(true) {
+            return;
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! vCPU run-state hooks for power management. A vCPU is running while its
+//! host thread drives the guest, blocked while the thread sleeps until the
+//! guest has something to do again (an idle WFI, a pause, PSCI power off
+//! or suspend), and stopped outside of its run loop. Platform code hooks
+//! the transitions to e.g. raise the frequency of a core while a
+//! latency-critical guest runs, or allow deep idle once every vCPU is
+//! blocked.
+//!
+//! Hooks are called on the vCPU's host thread right before it enters or
+//! after it leaves the state, without locks held. They run on every
+//! blocking wait of the guest, so they should be quick.
+
+use crate::sync::SpinLock;
+
+/// Hooks that can be registered at the same time.
+pub const MAX_HOOKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Blocked,
+    Stopped,
+}
+
+/// Called with the VM, the vCPU and its old and new state.
+pub type RunStateHook = fn(vm_id: usize, vcpu_id: usize, from: RunState, to: RunState);
+
+/// Handle of a registered hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookError {
+    /// All `MAX_HOOKS` slots are taken.
+    Full,
+    NotRegistered,
+}
+
+struct Hooks {
+    slots: [Option<RunStateHook>; MAX_HOOKS],
+}
+
+impl Hooks {
+    const fn new() -> Self {
+        Self {
+            slots: [None; MAX_HOOKS],
+        }
+    }
+
+    fn register(&mut self, hook: RunStateHook) -> Result<HookId, HookError> {
+        let n = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(HookError::Full)?;
+        self.slots[n] = Some(hook);
+        Ok(HookId(n))
+    }
+
+    fn unregister(&mut self, id: HookId) -> Result<(), HookError> {
+        self.slots
+            .get_mut(id.0)
+            .and_then(Option::take)
+            .map(|_| ())
+            .ok_or(HookError::NotRegistered)
+    }
+}
+
+static HOOKS: SpinLock<Hooks> = SpinLock::new(Hooks::new());
+
+/// Call `hook` on every run-state transition of every vCPU from now on.
+pub fn register_hook(hook: RunStateHook) -> Result<HookId, HookError> {
+    HOOKS.irqsave_lock().register(hook)
+}
+
+pub fn unregister_hook(id: HookId) -> Result<(), HookError> {
+    HOOKS.irqsave_lock().unregister(id)
+}
+
+/// Tell the hooks vCPU `vcpu_id` of VM `vm_id` went from `from` to `to`.
+/// Called by the hypervisor, only on an actual change.
+pub fn notify(vm_id: usize, vcpu_id: usize, from: RunState, to: RunState) {
+    // Copied out so a hook may (un)register hooks itself.
+    let slots = HOOKS.irqsave_lock().slots;
+    for hook in slots.into_iter().flatten() {
+        hook(vm_id, vcpu_id, from, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn hook(_: usize, _: usize, _: RunState, _: RunState) {}
+
+    #[test]
+    fn test_run_state_hooks() {
+        let mut hooks = Hooks::new();
+        let first = hooks.register(hook).unwrap();
+        let second = hooks.register(hook).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(hooks.unregister(first), Ok(()));
+        assert_eq!(hooks.unregister(first), Err(HookError::NotRegistered));
+        // The freed slot is taken again.
+        assert_eq!(hooks.register(hook), Ok(first));
+        for _ in 2..MAX_HOOKS {
+            assert!(hooks.register(hook).is_ok());
+        }
+        assert_eq!(hooks.register(hook), Err(HookError::Full));
+        assert_eq!(
+            hooks.unregister(HookId(MAX_HOOKS)),
+            Err(HookError::NotRegistered)
+        );
+    }
+}
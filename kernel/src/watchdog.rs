@@ -0,0 +1,301 @@
+// Copyright (c) 2026 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Soft lockup detector. Every CPU bumps a heartbeat on clock interrupts and
+// context switches, and a progress count on context switches only; threads
+// that want to be watched register a `Watch` and pet it as they make
+// progress. A high priority checker thread periodically compares all of
+// them against what it saw last time. A CPU whose heartbeat stopped spins
+// with IRQs masked; one whose heartbeat moves but whose progress doesn't
+// spins with IRQs enabled, and is asked to log its own backtrace from the
+// next interrupt it takes. A core dedicated to one thread isn't checked:
+// its owner may run a guest there with the host tick masked.
+
+use crate::{
+    arch, config, scheduler,
+    sync::SpinLock,
+    thread::{self, Entry, Thread, ThreadNode},
+    time::Tick,
+};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const NUM_CORES: usize = blueos_kconfig::CONFIG_NUM_CORES as usize;
+const TIMEOUT_MS: u64 = blueos_kconfig::CONFIG_SOFT_WATCHDOG_TIMEOUT_MS as u64;
+// The checker wakes up a few times per timeout so a stall is reported no
+// later than 1.25 timeouts after it began.
+const CHECK_PERIOD_MS: u64 = TIMEOUT_MS / 4;
+const STALL_CHECKS: usize = 4;
+
+// Frame records followed when a stalled CPU dumps its stack.
+const MAX_FRAMES: usize = 16;
+
+static CPU_HEARTBEAT: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+static CPU_PROGRESS: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+static DUMP_REQUESTED: [AtomicBool; NUM_CORES] = [const { AtomicBool::new(false) }; NUM_CORES];
+static WATCHES: SpinLock<Vec<Weak<Watch>>> = SpinLock::new(Vec::new());
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+pub(crate) fn cpu_heartbeat() {
+    CPU_HEARTBEAT[arch::current_cpu_id()].fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn cpu_switched() {
+    let cpu = arch::current_cpu_id();
+    CPU_HEARTBEAT[cpu].fetch_add(1, Ordering::Relaxed);
+    CPU_PROGRESS[cpu].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called on interrupt entry with the interrupted PC, LR and frame pointer.
+/// Logs the backtrace of the interrupted code if the checker found this CPU
+/// spinning with IRQs enabled.
+pub(crate) fn irq_taken(pc: usize, lr: usize, fp: usize) {
+    let cpu = arch::current_cpu_id();
+    if !DUMP_REQUESTED[cpu].swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let thread = scheduler::current_thread_ref();
+    log::error!(
+        "[watchdog] CPU {} backtrace of thread 0x{:x}:",
+        cpu,
+        Thread::id(thread)
+    );
+    log::error!("  pc 0x{:x}", pc);
+    log::error!("  lr 0x{:x}", lr);
+    // AAPCS64 frame records: [fp] holds the caller's fp, [fp + 8] the
+    // return address. Only follow records inside the thread's stack, each
+    // older than the last.
+    let stack = thread.stack_base()..thread.stack_base() + thread.stack_size();
+    let mut fp = fp;
+    for _ in 0..MAX_FRAMES {
+        if fp % 16 != 0 || !stack.contains(&fp) || !stack.contains(&(fp + 15)) {
+            break;
+        }
+        let (next, ret) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+        log::error!("  0x{:x}", ret);
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+}
+
+/// Progress marker of a watchdog-tracked thread. Tracking stops when the
+/// last reference is dropped.
+pub struct Watch {
+    name: &'static str,
+    thread: ThreadNode,
+    progress: AtomicUsize,
+    paused: AtomicBool,
+}
+
+impl Watch {
+    /// Also counts as progress of the current CPU, so a watched thread that
+    /// keeps its CPU without switching isn't reported as a stalled CPU.
+    #[inline]
+    pub fn pet(&self) {
+        self.progress.fetch_add(1, Ordering::Relaxed);
+        CPU_PROGRESS[arch::current_cpu_id()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stop checking until `resume`, e.g. around an intentionally unbounded
+    /// wait.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.pet();
+        self.paused.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Start tracking the calling thread under `name`.
+pub fn track(name: &'static str) -> Arc<Watch> {
+    let watch = Arc::new(Watch {
+        name,
+        thread: scheduler::current_thread(),
+        progress: AtomicUsize::new(0),
+        paused: AtomicBool::new(false),
+    });
+    WATCHES.irqsave_lock().push(Arc::downgrade(&watch));
+    watch
+}
+
+#[derive(Default, Clone, Copy)]
+struct Sample {
+    seen: usize,
+    thread_id: usize,
+    unchanged: usize,
+    reported: bool,
+}
+
+impl Sample {
+    // Returns true exactly once per stall episode, when it crosses the limit.
+    fn update(&mut self, seen: usize, thread_id: usize) -> bool {
+        if seen != self.seen || thread_id != self.thread_id {
+            *self = Self {
+                seen,
+                thread_id,
+                ..Self::default()
+            };
+            return false;
+        }
+        self.unchanged += 1;
+        if self.unchanged < STALL_CHECKS || self.reported {
+            return false;
+        }
+        self.reported = true;
+        true
+    }
+}
+
+fn dump_thread(t: &Thread) {
+    log::error!(
+        "  thread 0x{:x} kind {} state {} priority {} saved sp 0x{:x} stack {}/{}",
+        Thread::id(t),
+        t.kind_to_str(),
+        t.state_to_str(),
+        t.priority(),
+        t.saved_sp(),
+        t.saved_stack_usage(),
+        t.stack_size(),
+    );
+}
+
+fn dump_irq_counters(cpu: usize) {
+    log::error!("  irq nesting {}", unsafe {
+        crate::irq::IRQ_NESTING_COUNT[cpu]
+    });
+    #[cfg(procfs)]
+    {
+        use crate::irq::irq_trace::IRQ_COUNTERS;
+        for (irq, counter) in IRQ_COUNTERS.iter().enumerate() {
+            let count = counter.load(Ordering::Relaxed);
+            if count != 0 {
+                log::error!("  irq {}: {}", irq, count);
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct CpuSample {
+    heartbeat: Sample,
+    progress: Sample,
+}
+
+fn check_cpus(samples: &mut [CpuSample; NUM_CORES]) {
+    #[cfg(smp)]
+    let dedicated = scheduler::dedicated_cores();
+    #[cfg(not(smp))]
+    let dedicated = 0;
+    for (cpu, sample) in samples.iter_mut().enumerate() {
+        if scheduler::is_idle_core(cpu) || dedicated & (1 << cpu) != 0 {
+            *sample = CpuSample::default();
+            continue;
+        }
+        let running = scheduler::running_thread_of(cpu);
+        let id = Thread::id(&running);
+        let beat = CPU_HEARTBEAT[cpu].load(Ordering::Relaxed);
+        let progress = CPU_PROGRESS[cpu].load(Ordering::Relaxed);
+        // Both cross the limit on the same check when nothing moves at all;
+        // that is the IRQs masked case.
+        let masked = sample.heartbeat.update(beat, id);
+        let spinning = sample.progress.update(progress, id);
+        if masked || spinning {
+            REPORTS.fetch_add(1, Ordering::Relaxed);
+        }
+        if masked {
+            log::error!(
+                "[watchdog] CPU {} stalled with IRQs masked for over {}ms, no backtrace",
+                cpu,
+                TIMEOUT_MS
+            );
+            dump_thread(&running);
+            dump_irq_counters(cpu);
+        } else if spinning {
+            log::error!(
+                "[watchdog] CPU {} took interrupts but didn't switch for over {}ms",
+                cpu,
+                TIMEOUT_MS
+            );
+            dump_thread(&running);
+            dump_irq_counters(cpu);
+            DUMP_REQUESTED[cpu].store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn check_watches(samples: &mut Vec<(Weak<Watch>, Sample)>) {
+    {
+        let mut watches = WATCHES.irqsave_lock();
+        watches.retain(|w| w.strong_count() != 0);
+        samples.retain(|(w, _)| w.strong_count() != 0);
+        for w in watches.iter() {
+            if !samples.iter().any(|(s, _)| Weak::ptr_eq(s, w)) {
+                samples.push((w.clone(), Sample::default()));
+            }
+        }
+    }
+    for (w, sample) in samples.iter_mut() {
+        let Some(watch) = w.upgrade() else {
+            continue;
+        };
+        if watch.paused.load(Ordering::Relaxed) {
+            *sample = Sample::default();
+            continue;
+        }
+        let progress = watch.progress.load(Ordering::Relaxed);
+        if sample.update(progress, Thread::id(&watch.thread)) {
+            REPORTS.fetch_add(1, Ordering::Relaxed);
+            log::error!(
+                "[watchdog] {} made no progress for over {}ms",
+                watch.name,
+                TIMEOUT_MS
+            );
+            dump_thread(&watch.thread);
+        }
+    }
+}
+
+/// Stalls reported since boot, of CPUs and of watched threads.
+pub fn reports() -> usize {
+    REPORTS.load(Ordering::Relaxed)
+}
+
+fn run_checker() {
+    let mut cpu_samples = [CpuSample::default(); NUM_CORES];
+    let mut watch_samples = Vec::new();
+    loop {
+        scheduler::suspend_me_for::<()>(Tick::from_millis(CHECK_PERIOD_MS), None);
+        check_cpus(&mut cpu_samples);
+        check_watches(&mut watch_samples);
+    }
+}
+
+pub(crate) fn init() {
+    thread::Builder::new(Entry::Closure(Box::new(run_checker)))
+        .set_priority(config::WATCHDOG_THREAD_PRIORITY)
+        .start();
+}
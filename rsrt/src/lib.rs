@@ -68,6 +68,8 @@ fn trace_stack() {
 #[cfg(not(feature = "std"))]
 #[panic_handler]
 fn oops(info: &core::panic::PanicInfo) -> ! {
+    #[cfg(target_arch = "aarch64")]
+    blueos::arch::stop_guests_on_panic();
     // Enable semihosting on qemu boards.
     #[cfg(any(
         target_board = "qemu_mps2_an385",